@@ -0,0 +1,77 @@
+//! Per-provider cost of the request and response transforms, without HTTP.
+//!
+//! Run with `cargo bench --bench transform_roundtrip`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use serde_json::json;
+use webrana_backend::routes::proxy::{transform_roundtrip, ChatCompletionRequest};
+use webrana_backend::services::transformers::Provider;
+
+/// Model and a synthetic upstream response for each provider
+fn fixture(provider: Provider) -> (&'static str, String) {
+    let (model, body) = match provider {
+        Provider::OpenAI => ("gpt-4o-mini", json!({
+            "id": "chatcmpl-abc",
+            "object": "chat.completion",
+            "created": 1733900000,
+            "model": "gpt-4o-mini",
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": "Halo!" }, "finish_reason": "stop" }],
+            "usage": { "prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15 }
+        })),
+        Provider::Anthropic => ("claude-3-5-sonnet-20241022", json!({
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "content": [{ "type": "text", "text": "Halo!" }],
+            "model": "claude-3-5-sonnet-20241022",
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": { "input_tokens": 12, "output_tokens": 3 }
+        })),
+        Provider::Google => ("gemini-1.5-flash", json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [{ "text": "Halo!" }] },
+                "finishReason": "STOP",
+                "index": 0
+            }],
+            "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 3, "totalTokenCount": 15 }
+        })),
+        Provider::Qwen => ("qwen-turbo", json!({
+            "output": {
+                "choices": [{ "finish_reason": "stop", "message": { "role": "assistant", "content": "Halo!" } }]
+            },
+            "usage": { "input_tokens": 12, "output_tokens": 3, "total_tokens": 15 },
+            "request_id": "req-1"
+        })),
+    };
+    (model, body.to_string())
+}
+
+fn request(model: &str) -> ChatCompletionRequest {
+    serde_json::from_value(json!({
+        "model": model,
+        "messages": [
+            { "role": "system", "content": "Jawab singkat." },
+            { "role": "user", "content": "Halo" }
+        ],
+        "temperature": 0.7,
+        "max_tokens": 256,
+        "stop": ["\n\n"]
+    }))
+    .expect("valid request")
+}
+
+fn bench_transform_roundtrip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transform_roundtrip");
+    for provider in Provider::ALL {
+        let (model, raw) = fixture(provider);
+        let request = request(model);
+        group.bench_function(provider.name(), |b| {
+            b.iter(|| transform_roundtrip(provider, black_box(&request), black_box(&raw)).expect("roundtrip"))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_transform_roundtrip);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+-- Migration: Add OpenAI organization/project scoping to api_keys
+-- Only used for provider = 'openai' (sent as OpenAI-Organization / OpenAI-Project headers)
+
+ALTER TABLE api_keys
+    ADD COLUMN openai_organization VARCHAR(100),
+    ADD COLUMN openai_project VARCHAR(100);
@@ -0,0 +1,20 @@
+-- Migration: Create debug_captures table
+-- Sampled request/upstream/response bodies for debugging transformation bugs.
+-- Only written when content logging is enabled; rows expire after a TTL.
+
+CREATE TABLE IF NOT EXISTS debug_captures (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    provider VARCHAR(20) NOT NULL,
+    model VARCHAR(100) NOT NULL,
+    request_body JSONB NOT NULL,
+    upstream_body JSONB,
+    response_body JSONB,
+    status_code INTEGER NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Index for TTL cleanup and recent listing
+CREATE INDEX IF NOT EXISTS idx_debug_captures_created ON debug_captures(created_at);
+
+COMMENT ON TABLE debug_captures IS 'Sampled full request/response captures for debugging (TTL-cleaned)';
@@ -0,0 +1,19 @@
+-- Migration: Create model_routes table
+-- Admin-managed routing overrides consulted before model-prefix routing.
+-- key_name optionally pins which of the user's provider keys is used.
+
+CREATE TABLE IF NOT EXISTS model_routes (
+    model VARCHAR(100) PRIMARY KEY,
+    provider ai_provider NOT NULL,
+    key_name VARCHAR(100),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Trigger for updated_at
+CREATE TRIGGER update_model_routes_updated_at
+    BEFORE UPDATE ON model_routes
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+COMMENT ON TABLE model_routes IS 'Per-model provider routing overrides (take precedence over prefix routing)';
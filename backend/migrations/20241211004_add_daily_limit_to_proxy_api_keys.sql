@@ -0,0 +1,5 @@
+-- Migration: Add optional per-key daily request limit
+-- Enforced alongside the plan's monthly limit; NULL means no daily cap.
+
+ALTER TABLE proxy_api_keys
+    ADD COLUMN IF NOT EXISTS daily_request_limit INTEGER CHECK (daily_request_limit > 0);
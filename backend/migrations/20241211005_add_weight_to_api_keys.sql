@@ -0,0 +1,6 @@
+-- Migration: Add load-balancing weight to provider API keys
+-- Requests are spread across a user's active keys for a provider in
+-- proportion to weight; a weight of 0 takes the key out of rotation.
+
+ALTER TABLE api_keys
+    ADD COLUMN IF NOT EXISTS weight INTEGER NOT NULL DEFAULT 1 CHECK (weight >= 0);
@@ -0,0 +1,18 @@
+-- Migration: Create usage_monthly_aggregates table
+-- Per-user monthly usage totals that survive retention cleanup and
+-- user-initiated purges of proxy_requests, so billing history is kept.
+
+CREATE TABLE IF NOT EXISTS usage_monthly_aggregates (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    month DATE NOT NULL,  -- First day of the month (UTC)
+    provider ai_provider NOT NULL,
+    request_count BIGINT NOT NULL DEFAULT 0,
+    prompt_tokens BIGINT NOT NULL DEFAULT 0,
+    completion_tokens BIGINT NOT NULL DEFAULT 0,
+    total_tokens BIGINT NOT NULL DEFAULT 0,
+    estimated_cost_idr BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, month, provider)
+);
+
+COMMENT ON TABLE usage_monthly_aggregates IS 'Rolled-up usage from purged proxy_requests rows (billing aggregates)';
@@ -0,0 +1,5 @@
+-- Migration: Add per-key anthropic-beta flags to api_keys
+-- Only used for provider = 'anthropic' (comma-separated, validated against an allowlist)
+
+ALTER TABLE api_keys
+    ADD COLUMN anthropic_beta VARCHAR(255);
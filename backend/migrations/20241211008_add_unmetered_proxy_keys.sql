@@ -0,0 +1,9 @@
+-- Migration: Add unmetered (internal) proxy keys
+-- Unmetered keys skip quota/rate checks; only admins can set the flag.
+-- Their requests are still logged, marked is_internal.
+
+ALTER TABLE proxy_api_keys
+    ADD COLUMN IF NOT EXISTS is_unmetered BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE proxy_requests
+    ADD COLUMN IF NOT EXISTS is_internal BOOLEAN NOT NULL DEFAULT false;
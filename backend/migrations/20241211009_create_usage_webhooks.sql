@@ -0,0 +1,27 @@
+-- Migration: Create usage threshold webhooks
+-- Per-user outbound webhook notified when monthly usage crosses 50/80/100%.
+-- usage_webhook_deliveries dedupes notifications per user, month and threshold.
+
+CREATE TABLE IF NOT EXISTS usage_webhooks (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    secret VARCHAR(100) NOT NULL,  -- HMAC-SHA256 signing secret
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TRIGGER update_usage_webhooks_updated_at
+    BEFORE UPDATE ON usage_webhooks
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+CREATE TABLE IF NOT EXISTS usage_webhook_deliveries (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    period DATE NOT NULL,  -- First day of the month (UTC)
+    threshold_percent SMALLINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, period, threshold_percent)
+);
+
+COMMENT ON TABLE usage_webhooks IS 'Outbound usage threshold webhook per user';
+COMMENT ON TABLE usage_webhook_deliveries IS 'Threshold notifications already sent (one per user, month and threshold)';
@@ -0,0 +1,18 @@
+-- Migration: Create feature_flags table
+-- Admin-toggled switches for optional proxy features. A feature with no row
+-- is enabled; its own configuration (env vars) still decides whether it runs.
+
+CREATE TABLE IF NOT EXISTS feature_flags (
+    name VARCHAR(100) PRIMARY KEY,
+    enabled BOOLEAN NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Trigger for updated_at
+CREATE TRIGGER update_feature_flags_updated_at
+    BEFORE UPDATE ON feature_flags
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+COMMENT ON TABLE feature_flags IS 'Runtime feature switches (missing row = enabled)';
@@ -0,0 +1,6 @@
+-- Migration: Add session revocation cutoff to users
+-- Access and refresh tokens issued at or before tokens_valid_after are
+-- rejected, so revoking sessions logs a user out everywhere.
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS tokens_valid_after TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,6 @@
+-- Migration: Allow proxy keys to supply their own provider key
+-- Keys with the flag may send X-Provider-Key; it is used for that request
+-- only and never stored.
+
+ALTER TABLE proxy_api_keys
+    ADD COLUMN IF NOT EXISTS allow_provider_key_override BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,26 @@
+-- Migration: Opt-in conversation transcripts for audit
+-- Proxy keys with store_transcripts keep each request's messages and the
+-- response, encrypted with the master key. Only written when content logging
+-- is enabled; rows are deleted after the retention window.
+
+ALTER TABLE proxy_api_keys
+    ADD COLUMN IF NOT EXISTS store_transcripts BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS conversation_transcripts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    proxy_key_id UUID REFERENCES proxy_api_keys(id) ON DELETE SET NULL,
+    provider VARCHAR(20) NOT NULL,
+    model VARCHAR(100) NOT NULL,
+    status_code INTEGER NOT NULL,
+    content_ciphertext BYTEA NOT NULL,
+    content_iv BYTEA NOT NULL,
+    content_auth_tag BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Index for owner listing and retention cleanup
+CREATE INDEX IF NOT EXISTS idx_conversation_transcripts_user ON conversation_transcripts(user_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_conversation_transcripts_created ON conversation_transcripts(created_at);
+
+COMMENT ON TABLE conversation_transcripts IS 'Encrypted prompt/response transcripts for keys that opted in (retention-cleaned)';
@@ -0,0 +1,9 @@
+-- Migration: Add tax exemption for B2B customers
+-- Tax-exempt users are charged no PPN. Subscriptions record the exemption
+-- at order time so the invoice split matches what was charged.
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS tax_exempt BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE subscriptions
+    ADD COLUMN IF NOT EXISTS tax_exempt BOOLEAN NOT NULL DEFAULT false;
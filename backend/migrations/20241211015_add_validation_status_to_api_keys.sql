@@ -0,0 +1,7 @@
+-- Migration: Track provider key health
+-- Updated from upstream responses: 'valid' when the provider accepted the
+-- key, 'invalid' when it rejected it (401/403). NULL until first used.
+
+ALTER TABLE api_keys
+    ADD COLUMN IF NOT EXISTS validation_status VARCHAR(20),
+    ADD COLUMN IF NOT EXISTS last_validated_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,9 @@
+-- Migration: Credit notes for refunded subscriptions
+-- A credit note is an invoice row with negative amounts that reverses a paid invoice
+
+ALTER TABLE invoices
+    ADD COLUMN kind VARCHAR(20) NOT NULL DEFAULT 'invoice',  -- 'invoice' or 'credit_note'
+    ADD COLUMN credited_invoice_id UUID REFERENCES invoices(id);
+
+ALTER TABLE subscriptions
+    ADD COLUMN refunded_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,8 @@
+-- Migration: Client-supplied request metadata for analytics tagging
+-- String key/value pairs from the request's `metadata` field
+
+ALTER TABLE proxy_requests
+    ADD COLUMN metadata JSONB;
+
+-- Supports filtering by metadata key
+CREATE INDEX idx_proxy_requests_metadata ON proxy_requests USING GIN (metadata);
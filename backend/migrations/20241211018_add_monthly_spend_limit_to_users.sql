@@ -0,0 +1,6 @@
+-- Migration: Add an optional monthly spend limit per user
+-- Requests whose estimated worst-case cost exceeds the remaining spend for
+-- the month are rejected before reaching the provider. NULL means no limit.
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS monthly_spend_limit_idr BIGINT;
@@ -0,0 +1,20 @@
+-- Migration: Create provider_errors table
+-- Redacted upstream error responses per user, for support lookups.
+-- Capped per user; older rows are pruned when new errors are recorded.
+
+CREATE TABLE IF NOT EXISTS provider_errors (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    proxy_key_id UUID REFERENCES proxy_api_keys(id) ON DELETE SET NULL,
+    provider VARCHAR(20) NOT NULL,
+    model VARCHAR(100) NOT NULL,
+    status_code INTEGER NOT NULL,
+    error_code VARCHAR(100),
+    message TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Index for per-user recent listing and pruning
+CREATE INDEX IF NOT EXISTS idx_provider_errors_user_created ON provider_errors(user_id, created_at DESC);
+
+COMMENT ON TABLE provider_errors IS 'Recent redacted upstream provider errors per user (capped per user)';
@@ -0,0 +1,11 @@
+-- Migration: Provider-assigned request id on usage logs
+-- Quoted to the provider in support escalations (OpenAI x-request-id,
+-- Anthropic request-id, DashScope request_id)
+
+ALTER TABLE proxy_requests
+    ADD COLUMN provider_request_id VARCHAR(255);
+
+-- Supports looking up a log from the id a provider quotes back
+CREATE INDEX idx_proxy_requests_provider_request_id
+    ON proxy_requests (provider_request_id)
+    WHERE provider_request_id IS NOT NULL;
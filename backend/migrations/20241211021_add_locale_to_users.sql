@@ -0,0 +1,7 @@
+-- Migration: Add the language a user's emails are sent in
+-- Detected from the Accept-Language header at registration; existing users
+-- keep the previous Indonesian default.
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS locale VARCHAR(5) NOT NULL DEFAULT 'id'
+        CHECK (locale IN ('id', 'en'));
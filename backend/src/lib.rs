@@ -0,0 +1,38 @@
+//! Webrana AI Proxy backend: the API server's routes, middleware and
+//! services. The server binary is `main.rs`; benches use this crate too.
+
+use std::sync::Arc;
+
+pub mod middleware;
+pub mod models;
+pub mod routes;
+pub mod services;
+pub mod utils;
+
+#[cfg(test)]
+mod test_support;
+
+/// Application state shared across handlers
+#[derive(Clone)]
+pub struct AppState {
+    pub db: sqlx::PgPool,
+    pub redis: redis::Client,
+    /// Shared client for provider requests (one connection pool)
+    pub http_client: reqwest::Client,
+    pub provider_limiter: services::provider_limiter::ProviderLimiter,
+    pub abuse_detector: services::abuse_detector::AbuseDetector,
+    pub model_router: services::model_routing::ModelRouter,
+    pub region_selector: services::provider_regions::RegionSelector,
+    pub provider_headers: services::provider_headers::ProviderHeaders,
+    pub feature_flags: services::feature_flags::FeatureFlags,
+    /// Completion request settings, read from the environment at startup
+    pub proxy_config: services::proxy_config::ProxyConfig,
+    /// Background queue for transactional emails; `None` when email is not configured
+    pub email_queue: Option<services::email_service::EmailQueue>,
+    /// Subscription billing; `None` (and no billing routes) when Midtrans is not configured
+    pub billing_service: Option<Arc<services::billing_service::BillingService>>,
+    pub tasks: services::task_manager::TaskManager,
+    /// Cancelled when shutdown starts so open streams end cleanly
+    pub shutdown: tokio_util::sync::CancellationToken,
+}
+
@@ -1,25 +1,21 @@
-use axum::{routing::get, Router, Extension, Json, middleware as axum_middleware};
+use axum::{
+    http::{header, HeaderValue, Method, StatusCode, Uri},
+    middleware as axum_middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
 use serde::Serialize;
 use sqlx::postgres::PgPoolOptions;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
+use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-mod routes;
-mod services;
-mod models;
-mod middleware;
-mod utils;
+use webrana_backend::{middleware, routes, services, utils, AppState};
 
-use middleware::auth::{jwt_auth, api_key_auth};
-
-/// Application state shared across handlers
-#[derive(Clone)]
-pub struct AppState {
-    pub db: sqlx::PgPool,
-    pub redis: redis::Client,
-}
+use middleware::auth::jwt_auth;
 
 #[tokio::main]
 async fn main() {
@@ -45,13 +41,27 @@ async fn main() {
     tracing::info!("✅ Connected to PostgreSQL");
 
     // Run migrations
-    sqlx::migrate!("./migrations")
+    services::migration_status::MIGRATOR
         .run(&db_pool)
         .await
         .expect("Failed to run migrations");
 
     tracing::info!("✅ Database migrations completed");
 
+    // Sandbox keys must never meet production, nor production keys the sandbox
+    let midtrans_environment = services::billing_service::midtrans_environment_from_env();
+    let mismatches = services::billing_service::midtrans_key_mismatches(
+        midtrans_environment,
+        &std::env::var("MIDTRANS_SERVER_KEY").unwrap_or_default(),
+        &std::env::var("MIDTRANS_CLIENT_KEY").unwrap_or_default(),
+    );
+    for mismatch in &mismatches {
+        tracing::error!("🚨 Midtrans misconfigured: {}", mismatch);
+    }
+    if mismatches.is_empty() {
+        tracing::info!("✅ Midtrans configured for {}", midtrans_environment);
+    }
+
     // Redis connection
     let redis_url = std::env::var("REDIS_URL")
         .unwrap_or_else(|_| "redis://localhost:6379".to_string());
@@ -61,41 +71,309 @@ async fn main() {
 
     tracing::info!("✅ Connected to Redis");
 
+    // Replay usage logs that failed to persist
+    services::usage_dlq::spawn_retry_worker(
+        db_pool.clone(),
+        services::usage_dlq::RedisDeadLetterQueue::new(redis_client.clone()),
+    );
+
+    // Delete expired debug captures
+    services::debug_capture::spawn_cleanup_worker(
+        db_pool.clone(),
+        services::debug_capture::DebugCaptureConfig::from_env().ttl,
+    );
+
+    // Delete audit transcripts past their retention window
+    services::transcripts::spawn_cleanup_worker(
+        db_pool.clone(),
+        services::transcripts::TranscriptConfig::from_env().retention,
+    );
+
+    // Roll up and delete usage rows past the retention window
+    if let Some(days) = services::usage_retention::retention_days_from_env() {
+        services::usage_retention::spawn_retention_worker(db_pool.clone(), days);
+    }
+
+    // Load model routing overrides and keep them fresh
+    let model_router = services::model_routing::ModelRouter::default();
+    match model_router.refresh(&db_pool).await {
+        Ok(count) => tracing::info!("✅ Loaded {} model routing overrides", count),
+        Err(e) => tracing::error!("Failed to load model routing overrides: {}", e),
+    }
+    model_router.spawn_refresh_worker(
+        db_pool.clone(),
+        services::model_routing::refresh_interval_from_env(),
+    );
+
+    // Load feature flags and keep them fresh
+    let feature_flags = services::feature_flags::FeatureFlags::default();
+    match feature_flags.refresh(&db_pool).await {
+        Ok(count) => tracing::info!("✅ Loaded {} feature flags", count),
+        Err(e) => tracing::error!("Failed to load feature flags: {}", e),
+    }
+    feature_flags.spawn_refresh_worker(
+        db_pool.clone(),
+        services::feature_flags::refresh_interval_from_env(),
+    );
+
+    // One upstream client, and connection pool, for every provider request
+    let http_client = services::upstream_client::UpstreamClientConfig::from_env().build();
+
+    // Probe regional endpoints when a provider has more than one enabled
+    let region_selector = services::provider_regions::RegionSelector::from_env();
+    if region_selector.has_choices() {
+        region_selector.spawn_probe_worker(
+            http_client.clone(),
+            services::provider_regions::probe_interval_from_env(),
+        );
+    }
+
+    // Extra static headers per provider; a bad entry is a startup error
+    let provider_headers = services::provider_headers::ProviderHeaders::from_env()
+        .unwrap_or_else(|e| panic!("Invalid provider headers: {}", e));
+    if !provider_headers.is_empty() {
+        tracing::info!("✅ Loaded {} static provider headers", provider_headers.len());
+    }
+
+    // Send transactional emails in the background when an email API key is set
+    let email_queue = match std::env::var("RESEND_API_KEY") {
+        Ok(api_key) if !api_key.trim().is_empty() => {
+            let (queue, receiver) = services::email_service::email_queue();
+            let email_service = Arc::new(services::email_service::EmailService::new(db_pool.clone(), api_key));
+            services::email_service::spawn_email_worker(email_service.clone(), receiver);
+            tracing::info!("✅ Email worker started");
+
+            // Onboarding, expiry, and grace period reminders
+            Arc::new(services::scheduler_service::SchedulerService::new(db_pool.clone(), email_service))
+                .start_all_jobs()
+                .await;
+            Some(queue)
+        }
+        _ => {
+            tracing::info!("RESEND_API_KEY not set, transactional emails disabled");
+            None
+        }
+    };
+
+    // Billing is mounted only when Midtrans keys are set
+    let billing_service = match std::env::var("MIDTRANS_SERVER_KEY") {
+        Ok(server_key) if !server_key.trim().is_empty() => {
+            let service = services::billing_service::BillingService::new(
+                db_pool.clone(),
+                server_key,
+                std::env::var("MIDTRANS_CLIENT_KEY").unwrap_or_default(),
+                midtrans_environment == services::billing_service::MidtransEnvironment::Sandbox,
+            );
+            let service = match &email_queue {
+                Some(queue) => service.with_email_queue(queue.clone()),
+                None => service,
+            };
+            Some(Arc::new(service))
+        }
+        _ => {
+            tracing::info!("MIDTRANS_SERVER_KEY not set, billing routes disabled");
+            None
+        }
+    };
+
     // Create shared state
     let state = Arc::new(AppState {
         db: db_pool,
         redis: redis_client,
+        http_client,
+        provider_limiter: services::provider_limiter::ProviderLimiter::from_env(),
+        abuse_detector: services::abuse_detector::AbuseDetector::from_env(),
+        model_router,
+        region_selector,
+        provider_headers,
+        feature_flags,
+        proxy_config: services::proxy_config::ProxyConfig::from_env(),
+        email_queue,
+        billing_service,
+        tasks: services::task_manager::TaskManager::default(),
+        shutdown: tokio_util::sync::CancellationToken::new(),
     });
+    let tasks = state.tasks.clone();
+    let shutdown = state.shutdown.clone();
+
+    let app = public_router(state.clone());
+
+    // Internal listener for metrics, admin, and health (optional)
+    match internal_bind_addr() {
+        Some(internal_addr) => {
+            let internal_app = internal_router(state);
+            let internal_listener = TcpListener::bind(internal_addr)
+                .await
+                .expect("Failed to bind internal listener");
+            tracing::info!("🔒 Internal admin listener starting on {}", internal_addr);
 
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(internal_listener, internal_app).await {
+                    tracing::error!("Internal listener failed: {}", e);
+                }
+            });
+        }
+        None => {
+            tracing::info!("INTERNAL_BIND_ADDR not set, admin and metrics endpoints disabled");
+        }
+    }
+
+    // Start server
+    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    tracing::info!("🚀 Webrana AI Proxy starting on {}", addr);
+    
+    let listener = TcpListener::bind(addr).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            // End open streams so the connection drain doesn't wait on them
+            shutdown.cancel();
+        })
+        .await
+        .unwrap();
+
+    // Let in-flight usage logs and other background writes finish
+    tasks
+        .shutdown(services::task_manager::shutdown_timeout_from_env())
+        .await;
+    tracing::info!("👋 Shutdown complete");
+}
+
+/// Resolve on Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("Shutdown signal received, draining connections");
+}
+
+/// Public router: auth, API key management, usage, and the proxy
+fn public_router(state: Arc<AppState>) -> Router {
     // API keys routes with JWT authentication middleware
     let api_keys_routes = routes::api_keys::router()
         .layer(axum_middleware::from_fn_with_state(state.clone(), jwt_auth));
 
-    // Proxy routes with API key authentication middleware
+    // Proxy routes (API key auth is applied inside the router)
     let proxy_routes = routes::proxy::router()
-        .layer(axum_middleware::from_fn(api_key_auth));
+        .method_not_allowed_fallback(method_not_allowed);
+
+    // Session management routes with JWT authentication
+    let session_routes = routes::auth::session_router()
+        .layer(axum_middleware::from_fn_with_state(state.clone(), jwt_auth));
 
     // Usage routes with JWT authentication
     let usage_routes = routes::usage::usage_routes()
         .with_state(state.db.clone())
         .layer(axum_middleware::from_fn_with_state(state.clone(), jwt_auth));
 
-    // Build application router
-    let app = Router::new()
+    let mut router = Router::new()
         .route("/health", get(health_check))
-        .route("/health/db", get(health_check_db))
-        .nest("/auth", routes::auth::router())
+        .nest("/auth", routes::auth::router().merge(session_routes))
         .nest("/api-keys", api_keys_routes)
         .nest("/usage", usage_routes)
-        .nest("/v1", proxy_routes)  // Uses API key auth (wbr_* keys)
-        .layer(Extension(state));
+        .nest("/v1", proxy_routes);  // Uses API key auth (wbr_* keys)
 
-    // Start server
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    tracing::info!("🚀 Webrana AI Proxy starting on {}", addr);
-    
-    let listener = TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // Billing routes with JWT authentication; the Midtrans webhook is signed instead
+    if let Some(billing_service) = &state.billing_service {
+        let billing_routes = routes::billing::billing_routes(billing_service.clone())
+            .layer(axum_middleware::from_fn_with_state(state.clone(), jwt_auth));
+        router = router.nest(
+            "/billing",
+            billing_routes.merge(routes::billing::webhook_routes(billing_service.clone())),
+        );
+    }
+
+    router
+        .fallback(not_found)
+        .layer(cors_layer())
+        .layer(TraceLayer::new_for_http())
+        .layer(Extension(state))
+}
+
+/// Internal router: metrics, admin, and health checks.
+/// Bound to a private address only, so it has no CORS layer.
+fn internal_router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/health", get(health_check))
+        .route("/health/db", get(health_check_db))
+        .route("/health/version", get(health_check_version))
+        .route("/metrics", get(metrics))
+        .nest("/admin", routes::admin::admin_routes().with_state(state.db.clone()))
+        .layer(TraceLayer::new_for_http())
+        .layer(Extension(state))
+}
+
+/// Internal listener address from `INTERNAL_BIND_ADDR` (e.g. `127.0.0.1:9090`)
+fn internal_bind_addr() -> Option<SocketAddr> {
+    let value = std::env::var("INTERNAL_BIND_ADDR").ok()?;
+    match value.trim().parse() {
+        Ok(addr) => Some(addr),
+        Err(e) => {
+            tracing::warn!(value = %value, "Invalid INTERNAL_BIND_ADDR: {}", e);
+            None
+        }
+    }
+}
+
+/// CORS policy for public routes from `CORS_ALLOWED_ORIGINS` (comma-separated).
+/// Cross-origin requests are rejected when unset.
+fn cors_layer() -> CorsLayer {
+    let origins: Vec<HeaderValue> = std::env::var("CORS_ALLOWED_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty())
+        .filter_map(|o| o.parse().ok())
+        .collect();
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+}
+
+/// OpenAI-shaped 404 for unmatched routes
+async fn not_found(method: Method, uri: Uri) -> Response {
+    utils::api_error::proxy_error(
+        StatusCode::NOT_FOUND,
+        &format!("Unknown request URL: {} {}", method, uri.path()),
+        "invalid_request_error",
+        "NOT_FOUND",
+    )
+}
+
+/// OpenAI-shaped 405 for a known route called with the wrong method
+async fn method_not_allowed(method: Method, uri: Uri) -> Response {
+    utils::api_error::proxy_error(
+        StatusCode::METHOD_NOT_ALLOWED,
+        &format!("Method {} is not allowed for {}", method, uri.path()),
+        "invalid_request_error",
+        "METHOD_NOT_ALLOWED",
+    )
 }
 
 async fn health_check() -> &'static str {
@@ -121,3 +399,153 @@ async fn health_check_db(
         database: db_status,
     })
 }
+
+/// Build version and applied migrations; 503 when migrations are pending
+async fn health_check_version(Extension(state): Extension<Arc<AppState>>) -> Response {
+    use services::migration_status::{version_report, VersionReport};
+
+    match version_report(&state.db).await {
+        Ok(report) if report.is_healthy() => Json(report).into_response(),
+        Ok(report) => (StatusCode::SERVICE_UNAVAILABLE, Json(report)).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to read applied migrations: {}", e);
+            let report = VersionReport::new(&services::migration_status::expected_versions(), &[]);
+            (StatusCode::SERVICE_UNAVAILABLE, Json(report)).into_response()
+        }
+    }
+}
+
+/// Prometheus-style metrics
+async fn metrics(Extension(state): Extension<Arc<AppState>>) -> String {
+    format!(
+        "# TYPE webrana_up gauge\nwebrana_up 1\n\
+         # TYPE webrana_db_pool_connections gauge\nwebrana_db_pool_connections {}\n\
+         # TYPE webrana_db_pool_idle_connections gauge\nwebrana_db_pool_idle_connections {}\n",
+        state.db.size(),
+        state.db.num_idle()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::Service;
+
+    fn test_state() -> Arc<AppState> {
+        let db = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/webrana_test")
+            .unwrap();
+        let redis = redis::Client::open("redis://localhost:6379").unwrap();
+        let http_client = reqwest::Client::new();
+        let provider_limiter = services::provider_limiter::ProviderLimiter::from_env();
+        let abuse_detector = services::abuse_detector::AbuseDetector::default();
+        let model_router = services::model_routing::ModelRouter::default();
+        let region_selector = services::provider_regions::RegionSelector::default();
+        let provider_headers = services::provider_headers::ProviderHeaders::default();
+        let feature_flags = services::feature_flags::FeatureFlags::default();
+        let tasks = services::task_manager::TaskManager::default();
+        let shutdown = tokio_util::sync::CancellationToken::new();
+        Arc::new(AppState { db, redis, http_client, provider_limiter, abuse_detector, model_router, region_selector, provider_headers, feature_flags, proxy_config: Default::default(), email_queue: None, billing_service: None, tasks, shutdown })
+    }
+
+    async fn status_of(mut router: Router, method: Method, uri: &str) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        router.call(request).await.unwrap().status()
+    }
+
+    async fn error_of(mut router: Router, method: Method, uri: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_path_returns_openai_shaped_404() {
+        let (status, body) = error_of(public_router(test_state()), Method::GET, "/v1/does-not-exist").await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "NOT_FOUND");
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        assert_eq!(body["error"]["message"], "Unknown request URL: GET /v1/does-not-exist");
+    }
+
+    #[tokio::test]
+    async fn test_wrong_method_on_chat_completions_returns_405() {
+        let (status, body) = error_of(public_router(test_state()), Method::GET, "/v1/chat/completions").await;
+
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body["error"]["code"], "METHOD_NOT_ALLOWED");
+
+        // The right method still goes through API key auth
+        let status = status_of(public_router(test_state()), Method::POST, "/v1/chat/completions").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Health check is unchanged
+        let status = status_of(public_router(test_state()), Method::GET, "/health").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_models_route_requires_api_key() {
+        let status = status_of(public_router(test_state()), Method::GET, "/v1/models").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_billing_routes_mounted_when_configured() {
+        let unconfigured = status_of(public_router(test_state()), Method::GET, "/billing/subscription").await;
+        assert_eq!(unconfigured, StatusCode::NOT_FOUND);
+
+        let billing_service = services::billing_service::BillingService::new(
+            test_state().db.clone(),
+            "server-key".to_string(),
+            "client-key".to_string(),
+            true,
+        );
+        let state = Arc::new(AppState {
+            billing_service: Some(Arc::new(billing_service)),
+            ..Arc::unwrap_or_clone(test_state())
+        });
+
+        // User routes need a JWT; the webhook is outside JWT auth
+        let subscription = status_of(public_router(state.clone()), Method::GET, "/billing/subscription").await;
+        assert_eq!(subscription, StatusCode::UNAUTHORIZED);
+        let invoice = status_of(public_router(state.clone()), Method::GET, &format!("/billing/invoices/{}", uuid::Uuid::nil())).await;
+        assert_eq!(invoice, StatusCode::UNAUTHORIZED);
+        let webhook = status_of(public_router(state), Method::POST, "/billing/webhook/midtrans").await;
+        assert_ne!(webhook, StatusCode::UNAUTHORIZED);
+        assert_ne!(webhook, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_only_on_internal_router() {
+        // POST to a GET-only admin route: 405 means the route is mounted,
+        // without touching the database
+        let internal = status_of(internal_router(test_state()), Method::POST, "/admin/stats").await;
+        let public = status_of(public_router(test_state()), Method::POST, "/admin/stats").await;
+
+        assert_eq!(internal, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(public, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_only_on_internal_router() {
+        let internal = status_of(internal_router(test_state()), Method::GET, "/metrics").await;
+        let public = status_of(public_router(test_state()), Method::GET, "/metrics").await;
+
+        assert_eq!(internal, StatusCode::OK);
+        assert_eq!(public, StatusCode::NOT_FOUND);
+    }
+}
@@ -1,17 +1,24 @@
 //! Authentication middleware for JWT and API key validation.
 
 use axum::{
-    extract::{Extension, Request},
-    http::{header::AUTHORIZATION, StatusCode},
+    extract::{Extension, Request, State},
+    http::{header::AUTHORIZATION, HeaderValue, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::Serialize;
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::services::auth_service::Claims;
+use crate::models::PlanTier;
+use crate::services::provider_key_override::ProviderKeyOverride;
+use crate::services::auth_service::{self, Claims};
+use crate::services::rate_limiter::{QuotaLimits, RateLimitResult};
+use crate::services::session_revocation;
+use crate::services::usage_webhook;
+use crate::utils::api_error::proxy_error;
 
 /// Error response for authentication failures
 #[derive(Debug, Serialize)]
@@ -31,15 +38,18 @@ pub struct AuthUser {
 /// JWT authentication middleware
 /// 
 /// Extracts and validates Bearer token from Authorization header.
+/// Tokens issued before the user's sessions were revoked are rejected.
 /// On success, attaches AuthUser to request extensions.
 /// 
 /// # Arguments
+/// * `state` - Application state containing database pool
 /// * `request` - The incoming HTTP request
 /// * `next` - The next middleware/handler in the chain
 /// 
 /// # Returns
 /// Response from the next handler or an authentication error
 pub async fn jwt_auth(
+    State(state): State<Arc<crate::AppState>>,
     mut request: Request,
     next: Next,
 ) -> Response {
@@ -134,6 +144,20 @@ pub async fn jwt_auth(
         }
     };
 
+    // Reject tokens issued before the user's sessions were revoked
+    match session_revocation::tokens_valid_after(&state.db, &state.redis, user_id).await {
+        Ok(cutoff) if auth_service::issued_before_cutoff(claims.iat, cutoff) => {
+            return auth_error(
+                StatusCode::UNAUTHORIZED,
+                "Token has been revoked",
+                "TOKEN_REVOKED",
+            );
+        }
+        Ok(_) => {}
+        // Fail open if the database is unavailable
+        Err(e) => tracing::warn!("Session cutoff check failed for user {}: {}", user_id, e),
+    }
+
     // Create AuthUser and attach to request extensions
     let auth_user = AuthUser {
         user_id,
@@ -152,6 +176,32 @@ pub async fn jwt_auth(
 pub struct ApiKeyUser {
     pub key_id: Uuid,
     pub user_id: Uuid,
+    pub plan: PlanTier,
+    /// Internal key exempt from quotas; its usage is logged as internal
+    pub unmetered: bool,
+    /// Key may supply its own provider key per request
+    pub allow_provider_key: bool,
+    /// Provider key supplied with this request, set by the proxy handler
+    pub provider_key: Option<ProviderKeyOverride>,
+    /// Id for correlating this request with provider logs
+    pub request_id: String,
+    /// Key opted in to encrypted audit transcripts
+    pub store_transcripts: bool,
+    /// Spend left for this request's output under the monthly spend limit,
+    /// set by the proxy handler (`None` when unlimited)
+    pub output_spend_limit_idr: Option<i64>,
+}
+
+/// Quota limits to enforce for a proxy key; unmetered keys have none
+fn quota_limits(
+    plan: PlanTier,
+    daily_request_limit: Option<i64>,
+    unmetered: bool,
+) -> Option<QuotaLimits> {
+    (!unmetered).then(|| QuotaLimits {
+        monthly: plan.request_limit() as i64,
+        daily: daily_request_limit,
+    })
 }
 
 /// Proxy API key authentication middleware
@@ -171,8 +221,14 @@ pub async fn api_key_auth(
     mut request: Request,
     next: Next,
 ) -> Response {
-    use crate::services::proxy_key_service::ProxyKeyService;
+    use crate::services::proxy_key_service::{ProxyKeyService, ValidatedProxyKey};
     use crate::models::proxy_api_key::PROXY_KEY_PREFIX;
+    use crate::services::request_timing::RequestStart;
+    use crate::services::request_id::{insert_header, request_id_from_headers, REQUEST_ID_HEADER};
+
+    // Mark request start for per-phase timing
+    request.extensions_mut().insert(RequestStart(std::time::Instant::now()));
+    let request_id = request_id_from_headers(request.headers());
 
     // Extract Authorization header
     let auth_header = request
@@ -221,11 +277,38 @@ pub async fn api_key_auth(
 
     // Validate the API key (Requirement 7.1, 7.2)
     match ProxyKeyService::validate_key(&state.db, api_key).await {
-        Ok((key_id, user_id)) => {
+        Ok(validated) => {
+            let ValidatedProxyKey {
+                key_id,
+                user_id,
+                daily_request_limit,
+                unmetered,
+                allow_provider_key_override,
+                store_transcripts,
+                plan,
+            } = validated;
+
+            // Enforced by `enforce_quota` on routes that consume quota
+            if let Some(limits) = quota_limits(plan, daily_request_limit, unmetered) {
+                request.extensions_mut().insert(limits);
+            }
+
             // Requirement 7.5: Associate request with user account
-            let api_key_user = ApiKeyUser { key_id, user_id };
+            let api_key_user = ApiKeyUser {
+                key_id,
+                user_id,
+                plan,
+                unmetered,
+                allow_provider_key: allow_provider_key_override,
+                provider_key: None,
+                request_id: request_id.clone(),
+                store_transcripts,
+                output_spend_limit_idr: None,
+            };
             request.extensions_mut().insert(api_key_user);
-            next.run(request).await
+            let mut response = next.run(request).await;
+            insert_header(response.headers_mut(), REQUEST_ID_HEADER, &request_id);
+            response
         }
         Err(_) => {
             // Requirement 7.3: Invalid or revoked key
@@ -238,6 +321,96 @@ pub async fn api_key_auth(
     }
 }
 
+/// Quota middleware for routes that consume quota, layered inside
+/// `api_key_auth`.
+///
+/// Enforces the plan's monthly limit and the key's daily limit. A slot is
+/// reserved before the request runs, so concurrent requests can't overshoot,
+/// and given back if the response isn't a success: only successful requests
+/// count toward quota.
+pub async fn enforce_quota(
+    Extension(state): Extension<Arc<crate::AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    use crate::services::rate_limiter::RateLimiter;
+
+    let limits = request.extensions().get::<QuotaLimits>().copied();
+    let key = request
+        .extensions()
+        .get::<ApiKeyUser>()
+        .map(|user| (user.user_id, user.key_id));
+    let (Some(limits), Some((user_id, key_id))) = (limits, key) else {
+        // Unmetered key
+        return next.run(request).await;
+    };
+
+    let limiter = RateLimiter::from_client(state.redis.clone());
+    let monthly_used = match limiter
+        .check_and_increment_limits(user_id, Some(key_id), limits)
+        .await
+    {
+        Ok(result) if !result.allowed => return quota_exceeded(&result),
+        Ok(result) => Some(result.monthly_used),
+        // Fail open if Redis is unavailable
+        Err(e) => {
+            tracing::warn!("Rate limit check failed for key {}: {}", key_id, e);
+            None
+        }
+    };
+
+    let response = next.run(request).await;
+
+    if let Some(monthly_used) = monthly_used {
+        if response.status().is_success() {
+            usage_webhook::spawn_threshold_check(
+                &state.tasks,
+                state.db.clone(),
+                state.http_client.clone(),
+                user_id,
+                monthly_used,
+                limits.monthly,
+            );
+        } else if let Err(e) = limiter.release_limits(user_id, Some(key_id), limits).await {
+            tracing::warn!("Failed to release quota for key {}: {}", key_id, e);
+        }
+    }
+    response
+}
+
+/// 429 response naming the quota window that was exhausted
+fn quota_exceeded(result: &RateLimitResult) -> Response {
+    let window = result.window.as_str();
+    let mut response = proxy_error(
+        StatusCode::TOO_MANY_REQUESTS,
+        &format!("{} request limit of {} reached", capitalize(window), result.limit),
+        "insufficient_quota",
+        &format!("{}_LIMIT_EXCEEDED", window.to_uppercase()),
+    );
+    let headers = response.headers_mut();
+    let values = [
+        ("Retry-After", result.retry_after_secs.unwrap_or(1).to_string()),
+        ("X-RateLimit-Limit", result.limit.to_string()),
+        ("X-RateLimit-Remaining", result.remaining.to_string()),
+        ("X-RateLimit-Reset", result.reset_at.timestamp().to_string()),
+        ("X-RateLimit-Scope", window.to_string()),
+    ];
+    for (name, value) in values {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+    response
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
 /// Helper function to create authentication error responses
 fn auth_error(status: StatusCode, message: &str, code: &str) -> Response {
     let body = Json(AuthErrorResponse {
@@ -447,6 +620,13 @@ mod tests {
         let api_key_user = ApiKeyUser {
             key_id: Uuid::parse_str("550e8400-e29b-41d4-a716-446655440000").unwrap(),
             user_id: Uuid::parse_str("660e8400-e29b-41d4-a716-446655440001").unwrap(),
+            plan: PlanTier::Free,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
         };
         
         assert_eq!(api_key_user.key_id.to_string(), "550e8400-e29b-41d4-a716-446655440000");
@@ -515,6 +695,46 @@ mod tests {
         assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
 
+    #[tokio::test]
+    async fn test_daily_quota_exceeded_response() {
+        use crate::services::rate_limiter::{QuotaUsage, QuotaWindow, RateLimiter};
+
+        let usage = QuotaUsage {
+            monthly_used: 40,
+            daily_used: 20,
+            minute_used: 0,
+        };
+        let limits = QuotaLimits {
+            monthly: 1_000,
+            daily: Some(20),
+        };
+        let result = RateLimiter::evaluate(usage, limits, Utc::now());
+        assert_eq!(result.window, QuotaWindow::Daily);
+
+        let response = quota_exceeded(&result);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["X-RateLimit-Scope"], "daily");
+        assert_eq!(response.headers()["X-RateLimit-Limit"], "20");
+        assert_eq!(response.headers()["X-RateLimit-Remaining"], "0");
+        assert!(response.headers().contains_key("Retry-After"));
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["message"], "Daily request limit of 20 reached");
+        assert_eq!(json["error"]["type"], "insufficient_quota");
+        assert_eq!(json["error"]["code"], "DAILY_LIMIT_EXCEEDED");
+    }
+
+    #[test]
+    fn test_unmetered_key_is_never_rate_limited() {
+        assert!(quota_limits(PlanTier::Free, Some(1), true).is_none());
+        assert!(quota_limits(PlanTier::Team, None, true).is_none());
+
+        let limits = quota_limits(PlanTier::Free, Some(5), false).unwrap();
+        assert_eq!(limits.monthly, PlanTier::Free.request_limit() as i64);
+        assert_eq!(limits.daily, Some(5));
+    }
+
     #[test]
     fn test_auth_error_invalid_api_key() {
         let response = auth_error(
@@ -525,4 +745,112 @@ mod tests {
         
         assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
+
+    // Redis-backed tests; run with REDIS_URL set and `--ignored`
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_URL"]
+    async fn test_failed_requests_do_not_count_toward_quota() {
+        use axum::{body::Body, http::Request as HttpRequest, routing::post, Router};
+        use tower::Service;
+        use crate::services::rate_limiter::RateLimiter;
+
+        let redis = redis::Client::open(std::env::var("REDIS_URL").expect("REDIS_URL")).unwrap();
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://postgres@127.0.0.1:1/webrana")
+            .unwrap();
+        let state = Arc::new(crate::AppState {
+            redis: redis.clone(),
+            ..Arc::unwrap_or_clone(crate::test_support::app_state(pool))
+        });
+        let user = ApiKeyUser {
+            key_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            plan: PlanTier::Free,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req_test".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+        let limits = quota_limits(PlanTier::Free, Some(10), false).unwrap();
+        let mut app = Router::new()
+            .route(
+                "/:status",
+                post(|axum::extract::Path(status): axum::extract::Path<u16>| async move {
+                    StatusCode::from_u16(status).unwrap()
+                })
+                .route_layer(axum::middleware::from_fn(enforce_quota)),
+            )
+            .layer(Extension(limits))
+            .layer(Extension(user.clone()))
+            .layer(Extension(state));
+        let mut status_of = |path: &'static str| {
+            let request = HttpRequest::post(path).body(Body::empty()).unwrap();
+            app.call(request)
+        };
+
+        assert_eq!(status_of("/502").await.unwrap().status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(status_of("/400").await.unwrap().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(status_of("/200").await.unwrap().status(), StatusCode::OK);
+
+        let usage = RateLimiter::from_client(redis)
+            .get_usage(user.user_id, crate::services::billing_service::PlanTier::Free)
+            .await
+            .unwrap();
+        assert_eq!(usage.monthly_used, 1);
+    }
+
+    // Database-backed tests (see test_support for how to run them)
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_revoked_sessions_reject_older_access_tokens(pool: sqlx::PgPool) {
+        use axum::{body::Body, http::Request as HttpRequest, routing::get, Router};
+        use tower::Service;
+        use crate::test_support::{app_state, insert_user};
+
+        const SECRET: &str = "test-secret";
+        std::env::set_var("JWT_SECRET", SECRET);
+
+        let state = app_state(pool.clone());
+        let user_id = insert_user(&pool, "rina@example.com", PlanTier::Free).await;
+        let token_issued_at = |iat: chrono::DateTime<Utc>| {
+            let claims = Claims {
+                sub: user_id.to_string(),
+                email: "rina@example.com".to_string(),
+                plan: "free".to_string(),
+                exp: (iat + Duration::hours(24)).timestamp(),
+                iat: iat.timestamp(),
+                token_type: "access".to_string(),
+            };
+            encode(&Header::default(), &claims, &EncodingKey::from_secret(SECRET.as_bytes())).unwrap()
+        };
+        let app = Router::new()
+            .route("/me", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), jwt_auth));
+        let status_with = |token: String| {
+            let mut app = app.clone();
+            async move {
+                let request = HttpRequest::get("/me")
+                    .header(AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())
+                    .unwrap();
+                app.call(request).await.unwrap().status()
+            }
+        };
+
+        let old_token = token_issued_at(Utc::now() - Duration::minutes(5));
+        assert_eq!(status_with(old_token.clone()).await, StatusCode::OK);
+
+        let cutoff = session_revocation::revoke_all_sessions(&state.db, &state.redis, user_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(status_with(old_token).await, StatusCode::UNAUTHORIZED);
+        let fresh_token = token_issued_at(cutoff + Duration::seconds(1));
+        assert_eq!(status_with(fresh_token).await, StatusCode::OK);
+    }
 }
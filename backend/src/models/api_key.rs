@@ -5,8 +5,11 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
+use crate::services::anthropic_headers::parse_beta_flags;
+use crate::services::transformers::Provider;
+
 /// Supported AI providers matching PostgreSQL enum
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "ai_provider", rename_all = "lowercase")]
 pub enum AiProvider {
     #[serde(rename = "openai")]
@@ -41,6 +44,31 @@ impl AiProvider {
     }
 }
 
+// Both directions match exhaustively, so a provider added to one enum
+// doesn't compile until it's added to the other.
+
+impl From<Provider> for AiProvider {
+    fn from(provider: Provider) -> Self {
+        match provider {
+            Provider::OpenAI => AiProvider::Openai,
+            Provider::Anthropic => AiProvider::Anthropic,
+            Provider::Google => AiProvider::Google,
+            Provider::Qwen => AiProvider::Qwen,
+        }
+    }
+}
+
+impl From<AiProvider> for Provider {
+    fn from(provider: AiProvider) -> Self {
+        match provider {
+            AiProvider::Openai => Provider::OpenAI,
+            AiProvider::Anthropic => Provider::Anthropic,
+            AiProvider::Google => Provider::Google,
+            AiProvider::Qwen => Provider::Qwen,
+        }
+    }
+}
+
 /// Provider API key entity (encrypted)
 #[derive(Debug, FromRow)]
 pub struct ApiKey {
@@ -55,14 +83,97 @@ pub struct ApiKey {
     pub last_used_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub openai_organization: Option<String>,
+    pub openai_project: Option<String>,
+    pub weight: i32,
+    pub anthropic_beta: Option<String>,
+    pub validation_status: Option<String>,
+    pub last_validated_at: Option<DateTime<Utc>>,
 }
 
+/// Default load-balancing weight for a provider key
+pub const DEFAULT_KEY_WEIGHT: i32 = 1;
+
 /// Create API key DTO
 #[derive(Debug, Deserialize)]
 pub struct CreateApiKey {
     pub provider: AiProvider,
     pub key: String,
     pub name: String,
+    /// OpenAI organization ID (OpenAI keys only)
+    #[serde(default)]
+    pub openai_organization: Option<String>,
+    /// OpenAI project ID (OpenAI keys only)
+    #[serde(default)]
+    pub openai_project: Option<String>,
+    /// Load-balancing weight among the user's keys for this provider (0 = excluded)
+    #[serde(default)]
+    pub weight: Option<i32>,
+    /// Comma-separated `anthropic-beta` flags (Anthropic keys only)
+    #[serde(default)]
+    pub anthropic_beta: Option<String>,
+}
+
+impl CreateApiKey {
+    /// Validate provider-specific options
+    pub fn validate_options(&self) -> Result<(), String> {
+        if self.weight.is_some_and(|weight| weight < 0) {
+            return Err("weight must not be negative".to_string());
+        }
+        let has_openai_scope = self.openai_organization.is_some() || self.openai_project.is_some();
+        if has_openai_scope && self.provider != AiProvider::Openai {
+            return Err("openai_organization and openai_project are only supported for OpenAI keys".to_string());
+        }
+        if let Some(beta) = &self.anthropic_beta {
+            if self.provider != AiProvider::Anthropic {
+                return Err("anthropic_beta is only supported for Anthropic keys".to_string());
+            }
+            parse_beta_flags(beta)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a provider key worked the last time it was used upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyHealth {
+    /// Not used upstream since it was stored
+    Unverified,
+    /// The provider accepted the key
+    Valid,
+    /// The provider rejected the key (401/403)
+    Invalid,
+}
+
+impl KeyHealth {
+    /// Value stored in `api_keys.validation_status`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyHealth::Unverified => "unverified",
+            KeyHealth::Valid => "valid",
+            KeyHealth::Invalid => "invalid",
+        }
+    }
+
+    /// Parse a stored `validation_status`
+    pub fn from_status(status: Option<&str>) -> Self {
+        match status {
+            Some("valid") => KeyHealth::Valid,
+            Some("invalid") => KeyHealth::Invalid,
+            _ => KeyHealth::Unverified,
+        }
+    }
+
+    /// What an upstream response status says about the key; `None` when it
+    /// says nothing (rate limits, server errors, bad requests)
+    pub fn from_upstream_status(status: u16) -> Option<Self> {
+        match status {
+            200..=299 => Some(KeyHealth::Valid),
+            401 | 403 => Some(KeyHealth::Invalid),
+            _ => None,
+        }
+    }
 }
 
 /// API key info for listing (masked, no sensitive data)
@@ -72,7 +183,16 @@ pub struct ApiKeyInfo {
     pub provider: AiProvider,
     pub name: String,
     pub masked_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub openai_organization: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub openai_project: Option<String>,
+    pub weight: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anthropic_beta: Option<String>,
     pub is_active: bool,
+    pub status: KeyHealth,
+    pub last_validated_at: Option<DateTime<Utc>>,
     pub last_used_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
 }
@@ -88,3 +208,79 @@ impl ApiKeyInfo {
         format!("{}...{}", prefix, suffix)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_provider_round_trips_through_stored_provider() {
+        for provider in Provider::ALL {
+            let stored = AiProvider::from(provider);
+            assert_eq!(Provider::from(stored), provider);
+            // Both enums serialize to the same name
+            assert_eq!(serde_json::to_value(stored).unwrap(), serde_json::to_value(provider).unwrap());
+        }
+    }
+
+    fn create_key(provider: AiProvider, org: Option<&str>) -> CreateApiKey {
+        CreateApiKey {
+            provider,
+            key: "sk-test".to_string(),
+            name: "test".to_string(),
+            openai_organization: org.map(|s| s.to_string()),
+            openai_project: None,
+            weight: None,
+            anthropic_beta: None,
+        }
+    }
+
+    #[test]
+    fn test_key_health_from_upstream_status() {
+        assert_eq!(KeyHealth::from_upstream_status(200), Some(KeyHealth::Valid));
+        assert_eq!(KeyHealth::from_upstream_status(401), Some(KeyHealth::Invalid));
+        assert_eq!(KeyHealth::from_upstream_status(403), Some(KeyHealth::Invalid));
+        assert_eq!(KeyHealth::from_upstream_status(429), None);
+        assert_eq!(KeyHealth::from_upstream_status(500), None);
+
+        for health in [KeyHealth::Unverified, KeyHealth::Valid, KeyHealth::Invalid] {
+            assert_eq!(KeyHealth::from_status(Some(health.as_str())), health);
+        }
+        assert_eq!(KeyHealth::from_status(None), KeyHealth::Unverified);
+    }
+
+    #[test]
+    fn test_openai_scope_allowed_for_openai() {
+        assert!(create_key(AiProvider::Openai, Some("org-123")).validate_options().is_ok());
+        assert!(create_key(AiProvider::Anthropic, None).validate_options().is_ok());
+    }
+
+    #[test]
+    fn test_negative_weight_rejected() {
+        let mut key = create_key(AiProvider::Openai, None);
+        key.weight = Some(0);
+        assert!(key.validate_options().is_ok());
+        key.weight = Some(-1);
+        assert!(key.validate_options().is_err());
+    }
+
+    #[test]
+    fn test_anthropic_beta_validated() {
+        let mut key = create_key(AiProvider::Anthropic, None);
+        key.anthropic_beta = Some("prompt-caching-2024-07-31".to_string());
+        assert!(key.validate_options().is_ok());
+
+        key.anthropic_beta = Some("not-a-real-beta".to_string());
+        assert!(key.validate_options().is_err());
+
+        let mut openai = create_key(AiProvider::Openai, None);
+        openai.anthropic_beta = Some("prompt-caching-2024-07-31".to_string());
+        assert!(openai.validate_options().is_err());
+    }
+
+    #[test]
+    fn test_openai_scope_rejected_for_other_providers() {
+        assert!(create_key(AiProvider::Anthropic, Some("org-123")).validate_options().is_err());
+        assert!(create_key(AiProvider::Qwen, Some("org-123")).validate_options().is_err());
+    }
+}
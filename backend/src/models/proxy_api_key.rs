@@ -19,6 +19,13 @@ pub struct ProxyApiKey {
     pub is_active: bool,
     pub last_used_at: Option<DateTime<Utc>>,
     pub request_count: i64,
+    pub daily_request_limit: Option<i32>,
+    /// Internal key exempt from quotas (admin-only)
+    pub is_unmetered: bool,
+    /// May send its own provider key per request (X-Provider-Key)
+    pub allow_provider_key_override: bool,
+    /// Keep encrypted transcripts of this key's completions for audit
+    pub store_transcripts: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -27,6 +34,15 @@ pub struct ProxyApiKey {
 #[derive(Debug, Deserialize)]
 pub struct CreateProxyApiKey {
     pub name: String,
+    /// Optional cap on requests per UTC day
+    #[serde(default)]
+    pub daily_request_limit: Option<i32>,
+    /// Allow requests to supply their own provider key
+    #[serde(default)]
+    pub allow_provider_key_override: bool,
+    /// Store encrypted transcripts of completions for audit
+    #[serde(default)]
+    pub store_transcripts: bool,
 }
 
 /// Proxy API key info for listing (no sensitive data)
@@ -37,6 +53,11 @@ pub struct ProxyApiKeyInfo {
     pub name: String,
     pub is_active: bool,
     pub request_count: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_request_limit: Option<i32>,
+    pub is_unmetered: bool,
+    pub allow_provider_key_override: bool,
+    pub store_transcripts: bool,
     pub created_at: DateTime<Utc>,
     pub last_used_at: Option<DateTime<Utc>>,
 }
@@ -49,6 +70,10 @@ impl From<ProxyApiKey> for ProxyApiKeyInfo {
             name: key.name,
             is_active: key.is_active,
             request_count: key.request_count,
+            daily_request_limit: key.daily_request_limit,
+            is_unmetered: key.is_unmetered,
+            allow_provider_key_override: key.allow_provider_key_override,
+            store_transcripts: key.store_transcripts,
             created_at: key.created_at,
             last_used_at: key.last_used_at,
         }
@@ -49,6 +49,26 @@ impl PlanTier {
             PlanTier::Pro | PlanTier::Team => None, // All providers
         }
     }
+
+    /// Get default max messages per chat completion request for this plan
+    pub fn max_messages_per_request(&self) -> usize {
+        match self {
+            PlanTier::Free => 50,
+            PlanTier::Starter => 100,
+            PlanTier::Pro => 200,
+            PlanTier::Team => 500,
+        }
+    }
+
+    /// Lowercase plan name (matches PostgreSQL enum value)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanTier::Free => "free",
+            PlanTier::Starter => "starter",
+            PlanTier::Pro => "pro",
+            PlanTier::Team => "team",
+        }
+    }
 }
 
 /// User entity
@@ -70,6 +90,9 @@ pub struct User {
 pub struct CreateUser {
     pub email: String,
     pub password: String,
+    /// Email language (`id`/`en`); the configured default when unset
+    #[serde(default)]
+    pub locale: Option<String>,
 }
 
 /// User login DTO
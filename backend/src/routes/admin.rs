@@ -1,13 +1,23 @@
 use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
-    routing::{get, post},
-    Json, Router,
+    routing::{get, post, put},
+    Extension, Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::{PgPool, Row};
+use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::services::feature_flags::{self, FeatureFlag, FeatureFlagState, FeatureFlags};
+use crate::services::model_routing::{self, ModelRoute, ModelRouteOverride};
+use crate::services::provider_errors::{self, ProviderErrorEntry};
+use crate::services::proxy_key_service::{ProxyKeyError, ProxyKeyService};
+use crate::services::session_revocation;
+use crate::services::transcripts::{self, StoredTranscript};
+use crate::utils::encryption::EncryptionUtils;
+use crate::AppState;
+
 /// Admin stats response
 #[derive(Debug, Serialize)]
 pub struct AdminStats {
@@ -47,6 +57,12 @@ pub struct UserListQuery {
     pub search: Option<String>,
 }
 
+/// Provider error history query
+#[derive(Debug, Deserialize)]
+pub struct ProviderErrorQuery {
+    pub limit: Option<i64>,
+}
+
 /// User detail response
 #[derive(Debug, Serialize)]
 pub struct UserDetailResponse {
@@ -73,6 +89,18 @@ pub struct ChangePlanRequest {
     pub plan_tier: String,
 }
 
+/// Set unmetered flag request
+#[derive(Debug, Deserialize)]
+pub struct SetUnmeteredRequest {
+    pub unmetered: bool,
+}
+
+/// Request to flip a feature flag
+#[derive(Debug, Deserialize)]
+pub struct SetFeatureFlagRequest {
+    pub enabled: bool,
+}
+
 /// Admin action response
 #[derive(Debug, Serialize)]
 pub struct AdminActionResponse {
@@ -92,6 +120,31 @@ pub struct SystemHealthResponse {
     pub database_status: String,
 }
 
+/// Debug capture list item (bodies omitted)
+#[derive(Debug, Serialize)]
+pub struct DebugCaptureListItem {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub provider: String,
+    pub model: String,
+    pub status_code: i32,
+    pub created_at: String,
+}
+
+/// Debug capture detail with full bodies
+#[derive(Debug, Serialize)]
+pub struct DebugCaptureDetail {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub provider: String,
+    pub model: String,
+    pub status_code: i32,
+    pub request_body: serde_json::Value,
+    pub upstream_body: Option<serde_json::Value>,
+    pub response_body: Option<serde_json::Value>,
+    pub created_at: String,
+}
+
 /// Admin routes
 /// Requirements: 6.1, 6.2, 6.3, 6.4, 6.5, 6.6
 pub fn admin_routes() -> Router<PgPool> {
@@ -102,7 +155,17 @@ pub fn admin_routes() -> Router<PgPool> {
         .route("/users/{id}/suspend", post(suspend_user))
         .route("/users/{id}/unsuspend", post(unsuspend_user))
         .route("/users/{id}/plan", post(change_user_plan))
+        .route("/users/:id/revoke-sessions", post(revoke_user_sessions))
+        .route("/users/:id/provider-errors", get(get_user_provider_errors))
         .route("/health", get(get_system_health))
+        .route("/debug-captures", get(get_debug_captures))
+        .route("/debug-captures/:id", get(get_debug_capture))
+        .route("/transcripts/:id", get(get_transcript))
+        .route("/model-routes", get(get_model_routes))
+        .route("/model-routes/:model", put(put_model_route).delete(delete_model_route))
+        .route("/proxy-keys/:id/unmetered", put(set_proxy_key_unmetered))
+        .route("/feature-flags", get(get_feature_flags))
+        .route("/feature-flags/:name", put(put_feature_flag))
 }
 
 
@@ -375,6 +438,38 @@ async fn change_user_plan(
     }))
 }
 
+/// Log a user out everywhere by revoking all of their tokens
+/// POST /admin/users/:id/revoke-sessions
+async fn revoke_user_sessions(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<AdminActionResponse>, StatusCode> {
+    let cutoff = session_revocation::revoke_all_sessions(&state.db, &state.redis, user_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    tracing::info!(user_id = %user_id, "User sessions revoked by admin");
+
+    Ok(Json(AdminActionResponse {
+        success: true,
+        message: format!("Tokens issued before {} are revoked", cutoff.to_rfc3339()),
+    }))
+}
+
+/// List a user's recent upstream provider errors, newest first
+/// GET /admin/users/:id/provider-errors?limit=50
+async fn get_user_provider_errors(
+    State(pool): State<PgPool>,
+    Path(user_id): Path<Uuid>,
+    Query(query): Query<ProviderErrorQuery>,
+) -> Result<Json<Vec<ProviderErrorEntry>>, StatusCode> {
+    let limit = query.limit.unwrap_or(50).clamp(1, provider_errors::MAX_ERRORS_PER_USER);
+    let errors = provider_errors::list_recent(&pool, user_id, limit)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(errors))
+}
 
 /// Get system health metrics
 /// GET /admin/health
@@ -440,3 +535,216 @@ async fn get_system_health(
         database_status: db_status,
     }))
 }
+
+/// List recent debug captures
+/// GET /admin/debug-captures
+async fn get_debug_captures(
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<DebugCaptureListItem>>, StatusCode> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, user_id, provider, model, status_code, created_at
+        FROM debug_captures
+        ORDER BY created_at DESC
+        LIMIT 100
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let captures = rows
+        .iter()
+        .map(|row| DebugCaptureListItem {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            provider: row.get("provider"),
+            model: row.get("model"),
+            status_code: row.get("status_code"),
+            created_at: row
+                .get::<chrono::DateTime<chrono::Utc>, _>("created_at")
+                .to_rfc3339(),
+        })
+        .collect();
+
+    Ok(Json(captures))
+}
+
+/// Get a debug capture with full bodies
+/// GET /admin/debug-captures/:id
+async fn get_debug_capture(
+    State(pool): State<PgPool>,
+    Path(capture_id): Path<Uuid>,
+) -> Result<Json<DebugCaptureDetail>, StatusCode> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, user_id, provider, model, status_code, created_at,
+            request_body::text as request_body,
+            upstream_body::text as upstream_body,
+            response_body::text as response_body
+        FROM debug_captures
+        WHERE id = $1
+        "#,
+    )
+    .bind(capture_id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let row = row.ok_or(StatusCode::NOT_FOUND)?;
+    let parse = |column: &str| -> Option<serde_json::Value> {
+        row.get::<Option<String>, _>(column)
+            .and_then(|text| serde_json::from_str(&text).ok())
+    };
+
+    Ok(Json(DebugCaptureDetail {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        provider: row.get("provider"),
+        model: row.get("model"),
+        status_code: row.get("status_code"),
+        request_body: parse("request_body").unwrap_or_default(),
+        upstream_body: parse("upstream_body"),
+        response_body: parse("response_body"),
+        created_at: row
+            .get::<chrono::DateTime<chrono::Utc>, _>("created_at")
+            .to_rfc3339(),
+    }))
+}
+
+/// Get any user's audit transcript, decrypted
+/// GET /admin/transcripts/:id
+async fn get_transcript(
+    State(pool): State<PgPool>,
+    Path(transcript_id): Path<Uuid>,
+) -> Result<Json<StoredTranscript>, StatusCode> {
+    let encryption = EncryptionUtils::from_env().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    match transcripts::get(&pool, &encryption, transcript_id, None).await {
+        Ok(Some(transcript)) => Ok(Json(transcript)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!(%transcript_id, "Failed to load transcript: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// List model routing overrides
+/// GET /admin/model-routes
+async fn get_model_routes(
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<ModelRouteOverride>>, StatusCode> {
+    model_routing::list_overrides(&pool)
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Create or replace the routing override for a model.
+/// Takes effect on the next cache refresh.
+/// PUT /admin/model-routes/:model
+async fn put_model_route(
+    State(pool): State<PgPool>,
+    Path(model): Path<String>,
+    Json(route): Json<ModelRoute>,
+) -> Result<Json<AdminActionResponse>, StatusCode> {
+    if model.trim().is_empty() || route.key_name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    model_routing::upsert_override(&pool, &model, &route)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    tracing::info!(model = %model, provider = route.provider.name(), "Model route override saved");
+
+    Ok(Json(AdminActionResponse {
+        success: true,
+        message: format!("Model {} now routes to {}", model, route.provider.name()),
+    }))
+}
+
+/// Remove the routing override for a model
+/// DELETE /admin/model-routes/:model
+async fn delete_model_route(
+    State(pool): State<PgPool>,
+    Path(model): Path<String>,
+) -> Result<Json<AdminActionResponse>, StatusCode> {
+    let deleted = model_routing::delete_override(&pool, &model)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(AdminActionResponse {
+        success: true,
+        message: format!("Model route override for {} removed", model),
+    }))
+}
+
+/// Mark a proxy key as unmetered (exempt from quotas, usage logged as internal)
+/// PUT /admin/proxy-keys/:id/unmetered
+async fn set_proxy_key_unmetered(
+    State(pool): State<PgPool>,
+    Path(key_id): Path<Uuid>,
+    Json(body): Json<SetUnmeteredRequest>,
+) -> Result<Json<AdminActionResponse>, StatusCode> {
+    match ProxyKeyService::set_unmetered(&pool, key_id, body.unmetered).await {
+        Ok(()) => {}
+        Err(ProxyKeyError::NotFound) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+
+    tracing::info!(key_id = %key_id, unmetered = body.unmetered, "Proxy key metering changed");
+
+    Ok(Json(AdminActionResponse {
+        success: true,
+        message: if body.unmetered {
+            "Proxy key is now unmetered".to_string()
+        } else {
+            "Proxy key is now metered".to_string()
+        },
+    }))
+}
+
+/// List every feature flag with its stored (or default) state
+/// GET /admin/feature-flags
+async fn get_feature_flags(
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<FeatureFlagState>>, StatusCode> {
+    let flags = FeatureFlags::default();
+    flags
+        .refresh(&pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(flags.snapshot()))
+}
+
+/// Enable or disable a feature. Takes effect on the next cache refresh.
+/// PUT /admin/feature-flags/:name
+async fn put_feature_flag(
+    State(pool): State<PgPool>,
+    Path(name): Path<String>,
+    Json(body): Json<SetFeatureFlagRequest>,
+) -> Result<Json<AdminActionResponse>, StatusCode> {
+    let flag = FeatureFlag::from_name(&name).ok_or(StatusCode::NOT_FOUND)?;
+
+    feature_flags::set_flag(&pool, flag, body.enabled)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    tracing::info!(flag = flag.name(), enabled = body.enabled, "Feature flag changed");
+
+    Ok(Json(AdminActionResponse {
+        success: true,
+        message: format!(
+            "Feature {} {}",
+            flag.name(),
+            if body.enabled { "enabled" } else { "disabled" }
+        ),
+    }))
+}
@@ -17,15 +17,17 @@ use crate::middleware::auth::AuthUser;
 use crate::models::api_key::{AiProvider, CreateApiKey};
 use crate::models::proxy_api_key::CreateProxyApiKey;
 use crate::models::user::PlanTier;
-use crate::services::api_key_service::{ApiKeyError, ApiKeyServiceImpl};
+use crate::services::api_key_service::{ApiKeyError, ApiKeyServiceImpl, BulkImportResult};
 use crate::services::proxy_key_service::{ProxyKeyError, ProxyKeyService};
 use crate::AppState;
 
 pub fn router() -> Router {
     Router::new()
         // Provider API keys
+        .route("/", get(list_provider_keys))
         .route("/provider", post(store_provider_key))
         .route("/provider", get(list_provider_keys))
+        .route("/provider/bulk", post(import_provider_keys))
         .route("/provider/{id}", delete(delete_provider_key))
         // Proxy API keys (TODO: Task 11)
         .route("/proxy", post(generate_proxy_key))
@@ -39,6 +41,14 @@ pub struct StoreProviderKeyRequest {
     pub provider: AiProvider,
     pub key: String,
     pub name: String,
+    #[serde(default)]
+    pub openai_organization: Option<String>,
+    #[serde(default)]
+    pub openai_project: Option<String>,
+    #[serde(default)]
+    pub weight: Option<i32>,
+    #[serde(default)]
+    pub anthropic_beta: Option<String>,
 }
 
 /// Response for stored provider API key
@@ -51,6 +61,17 @@ pub struct StoreProviderKeyResponse {
     pub created_at: String,
 }
 
+/// Maximum keys accepted in a single bulk import
+pub const MAX_BULK_IMPORT_KEYS: usize = 100;
+
+/// Response for bulk provider key import
+#[derive(Debug, Serialize)]
+pub struct BulkImportResponse {
+    pub imported: usize,
+    pub failed: usize,
+    pub results: Vec<BulkImportResult>,
+}
+
 /// Error response
 #[derive(Debug, Serialize)]
 pub struct ApiKeyErrorResponse {
@@ -86,10 +107,14 @@ async fn store_provider_key(
         provider: body.provider,
         key: body.key,
         name: body.name,
+        openai_organization: body.openai_organization,
+        openai_project: body.openai_project,
+        weight: body.weight,
+        anthropic_beta: body.anthropic_beta,
     };
 
-    // Store the key
-    match service.store_provider_key(&state.db, auth_user.user_id, input).await {
+    // Store the key if the plan allows another one
+    match service.add_provider_key(&state.db, auth_user.user_id, input).await {
         Ok(stored) => (
             StatusCode::CREATED,
             Json(StoreProviderKeyResponse {
@@ -109,6 +134,22 @@ async fn store_provider_key(
             }),
         )
             .into_response(),
+        Err(ApiKeyError::InvalidOptions(msg)) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiKeyErrorResponse {
+                error: msg,
+                code: "INVALID_KEY_OPTIONS".to_string(),
+            }),
+        )
+            .into_response(),
+        Err(ApiKeyError::PlanLimitReached(msg)) => (
+            StatusCode::FORBIDDEN,
+            Json(ApiKeyErrorResponse {
+                error: msg,
+                code: "PLAN_LIMIT_REACHED".to_string(),
+            }),
+        )
+            .into_response(),
         Err(e) => {
             tracing::error!("Failed to store provider key: {}", e);
             (
@@ -123,8 +164,86 @@ async fn store_provider_key(
     }
 }
 
+/// POST /api-keys/provider/bulk - Import multiple provider API keys
+/// Each key is validated and stored independently; one bad key doesn't abort the batch
+async fn import_provider_keys(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(body): Json<Vec<StoreProviderKeyRequest>>,
+) -> impl IntoResponse {
+    if body.is_empty() || body.len() > MAX_BULK_IMPORT_KEYS {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiKeyErrorResponse {
+                error: format!("Provide between 1 and {} keys", MAX_BULK_IMPORT_KEYS),
+                code: "INVALID_BATCH_SIZE".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    // Initialize service
+    let service = match ApiKeyServiceImpl::from_env() {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("Failed to initialize encryption: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiKeyErrorResponse {
+                    error: "Server configuration error".to_string(),
+                    code: "ENCRYPTION_CONFIG_ERROR".to_string(),
+                }),
+            )
+                .into_response();
+        }
+    };
+
+    let items = body
+        .into_iter()
+        .map(|item| CreateApiKey {
+            provider: item.provider,
+            key: item.key,
+            name: item.name,
+            openai_organization: item.openai_organization,
+            openai_project: item.openai_project,
+            weight: item.weight,
+            anthropic_beta: item.anthropic_beta,
+        })
+        .collect();
+
+    match service
+        .import_provider_keys(&state.db, auth_user.user_id, items)
+        .await
+    {
+        Ok(results) => {
+            let imported = results.iter().filter(|r| r.success).count();
+            let failed = results.len() - imported;
+            (
+                StatusCode::OK,
+                Json(BulkImportResponse {
+                    imported,
+                    failed,
+                    results,
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to import provider keys: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiKeyErrorResponse {
+                    error: "Failed to import API keys".to_string(),
+                    code: "STORAGE_ERROR".to_string(),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
 
-/// GET /api-keys/provider - List provider API keys (masked)
+/// GET /api-keys, GET /api-keys/provider - List provider API keys (masked,
+/// with health status)
 /// Requirement: 3.4
 async fn list_provider_keys(
     Extension(state): Extension<Arc<AppState>>,
@@ -217,6 +336,14 @@ async fn delete_provider_key(
 #[derive(Debug, Deserialize)]
 pub struct GenerateProxyKeyRequest {
     pub name: String,
+    #[serde(default)]
+    pub daily_request_limit: Option<i32>,
+    /// Let requests with this key send their own provider key (X-Provider-Key)
+    #[serde(default)]
+    pub allow_provider_key_override: bool,
+    /// Keep encrypted transcripts of this key's completions for audit
+    #[serde(default)]
+    pub store_transcripts: bool,
 }
 
 /// POST /api-keys/proxy - Generate a new proxy API key
@@ -227,15 +354,25 @@ async fn generate_proxy_key(
     Json(body): Json<GenerateProxyKeyRequest>,
 ) -> impl IntoResponse {
     // Parse plan tier from auth user
-    let plan: PlanTier = match auth_user.plan.as_str() {
-        "free" => PlanTier::Free,
-        "starter" => PlanTier::Starter,
-        "pro" => PlanTier::Pro,
-        "team" => PlanTier::Team,
-        _ => PlanTier::Free,
-    };
+    let plan = parse_plan(&auth_user.plan);
+
+    if body.daily_request_limit.is_some_and(|limit| limit <= 0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiKeyErrorResponse {
+                error: "daily_request_limit must be positive".to_string(),
+                code: "INVALID_DAILY_LIMIT".to_string(),
+            }),
+        )
+            .into_response();
+    }
 
-    let input = CreateProxyApiKey { name: body.name };
+    let input = CreateProxyApiKey {
+        name: body.name,
+        daily_request_limit: body.daily_request_limit,
+        allow_provider_key_override: body.allow_provider_key_override,
+        store_transcripts: body.store_transcripts,
+    };
 
     match ProxyKeyService::generate_key(&state.db, auth_user.user_id, plan, input).await {
         Ok(created) => (StatusCode::CREATED, Json(created)).into_response(),
@@ -313,3 +450,14 @@ async fn revoke_proxy_key(
         }
     }
 }
+
+/// Parse plan tier from JWT claim (defaults to Free)
+fn parse_plan(plan: &str) -> PlanTier {
+    match plan {
+        "free" => PlanTier::Free,
+        "starter" => PlanTier::Starter,
+        "pro" => PlanTier::Pro,
+        "team" => PlanTier::Team,
+        _ => PlanTier::Free,
+    }
+}
@@ -3,10 +3,11 @@
 use axum::{
     routing::post,
     Router, Extension, Json,
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
     extract::ConnectInfo,
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::net::SocketAddr;
@@ -15,6 +16,9 @@ use tokio::time::{sleep, Duration};
 use crate::AppState;
 use crate::models::CreateUser;
 use crate::services::auth_service::{AuthService, AuthError};
+use crate::services::email_service;
+use crate::services::session_revocation;
+use crate::middleware::auth::AuthUser;
 use crate::middleware::rate_limit::{LoginRateLimiter, rate_limit_response};
 
 pub fn router() -> Router {
@@ -24,6 +28,12 @@ pub fn router() -> Router {
         .route("/refresh", post(refresh_token))
 }
 
+/// Session management routes (require JWT authentication)
+pub fn session_router() -> Router {
+    Router::new()
+        .route("/sessions/revoke", post(revoke_sessions))
+}
+
 /// Registration request body
 #[derive(Debug, Deserialize)]
 pub struct RegisterRequest {
@@ -44,6 +54,12 @@ pub struct RefreshRequest {
     pub refresh_token: String,
 }
 
+/// Response after revoking all sessions
+#[derive(Debug, Serialize)]
+pub struct RevokeSessionsResponse {
+    pub tokens_valid_after: DateTime<Utc>,
+}
+
 /// Error response
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
@@ -87,6 +103,10 @@ fn auth_error_response(err: AuthError) -> (StatusCode, Json<ErrorResponse>) {
             StatusCode::UNAUTHORIZED,
             Json(ErrorResponse::new("token_expired", "Token has expired")),
         ),
+        AuthError::TokenRevoked => (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse::new("token_revoked", "Token has been revoked")),
+        ),
         AuthError::DatabaseError(_) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse::new("server_error", "An internal error occurred")),
@@ -101,16 +121,25 @@ fn auth_error_response(err: AuthError) -> (StatusCode, Json<ErrorResponse>) {
 /// POST /auth/register - Register a new user
 async fn register(
     Extension(state): Extension<Arc<AppState>>,
+    headers: HeaderMap,
     Json(body): Json<RegisterRequest>,
 ) -> impl IntoResponse {
     let jwt_secret = std::env::var("JWT_SECRET")
         .unwrap_or_else(|_| "default-secret-change-in-production".to_string());
 
-    let auth_service = AuthService::new(state.db.clone(), jwt_secret);
+    let mut auth_service = AuthService::new(state.db.clone(), jwt_secret);
+    if let Some(queue) = &state.email_queue {
+        auth_service = auth_service.with_email_queue(queue.clone());
+    }
+
+    // Best-effort email language from the browser's preference
+    let accept_language = headers.get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok());
+    let locale = email_service::locale_from_accept_language(accept_language, email_service::default_locale_from_env());
 
     let input = CreateUser {
         email: body.email,
         password: body.password,
+        locale: Some(locale.to_string()),
     };
 
     match auth_service.register(input).await {
@@ -177,3 +206,24 @@ async fn refresh_token(
         }
     }
 }
+
+/// POST /auth/sessions/revoke - Log out everywhere, including this session
+async fn revoke_sessions(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> impl IntoResponse {
+    match session_revocation::revoke_all_sessions(&state.db, &state.redis, auth_user.user_id).await {
+        Ok(Some(tokens_valid_after)) => {
+            tracing::info!(user_id = %auth_user.user_id, "User revoked all sessions");
+            (StatusCode::OK, Json(serde_json::to_value(RevokeSessionsResponse { tokens_valid_after }).unwrap())).into_response()
+        }
+        Ok(None) => {
+            let (status, json) = auth_error_response(AuthError::InvalidToken);
+            (status, Json(serde_json::to_value(json.0).unwrap())).into_response()
+        }
+        Err(e) => {
+            let (status, json) = auth_error_response(AuthError::DatabaseError(e.to_string()));
+            (status, Json(serde_json::to_value(json.0).unwrap())).into_response()
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode},
     response::{Html, IntoResponse, Response},
     routing::{get, post},
     Json, Router,
@@ -9,8 +9,10 @@ use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::middleware::auth::AuthUser;
 use crate::services::billing_service::{
-    BillingError, BillingService, MidtransSnapToken, MidtransWebhook, PlanTier, Subscription,
+    BillingError, BillingService, MidtransSnapToken, MidtransWebhook, PlanTier, ProrationPreview,
+    Subscription,
 };
 use crate::services::invoice_service::{Invoice, InvoiceService};
 
@@ -35,15 +37,28 @@ pub struct CreateSubscriptionResponse {
     pub order_id: String,
 }
 
-/// Billing routes
-pub fn billing_routes(billing_service: std::sync::Arc<BillingService>) -> Router<PgPool> {
+/// Upgrade preview query
+#[derive(Debug, Deserialize)]
+pub struct UpgradePreviewQuery {
+    pub plan: String,
+}
+
+/// Billing routes for signed-in users (requires JWT auth middleware)
+pub fn billing_routes(billing_service: std::sync::Arc<BillingService>) -> Router {
     Router::new()
         .route("/subscribe", post(create_subscription))
         .route("/subscription", get(get_subscription))
         .route("/subscription/cancel", post(cancel_subscription))
+        .route("/upgrade/preview", get(preview_upgrade))
         .route("/invoices", get(get_invoices))
-        .route("/invoices/{id}", get(get_invoice_html))
-        .route("/invoices/{id}/download", get(download_invoice))
+        .route("/invoices/:id", get(get_invoice_html))
+        .route("/invoices/:id/download", get(download_invoice))
+        .with_state(billing_service)
+}
+
+/// Midtrans payment notifications, authenticated by signature instead of JWT
+pub fn webhook_routes(billing_service: std::sync::Arc<BillingService>) -> Router {
+    Router::new()
         .route("/webhook/midtrans", post(handle_midtrans_webhook))
         .with_state(billing_service)
 }
@@ -53,11 +68,9 @@ pub fn billing_routes(billing_service: std::sync::Arc<BillingService>) -> Router
 /// Requirements: 2.1, 2.3
 async fn create_subscription(
     State(billing_service): State<std::sync::Arc<BillingService>>,
+    Extension(auth_user): Extension<AuthUser>,
     Json(req): Json<CreateSubscriptionRequest>,
 ) -> Result<Json<CreateSubscriptionResponse>, (StatusCode, String)> {
-    // TODO: Get user_id and email from auth middleware
-    let user_id = Uuid::nil();
-    let user_email = "user@example.com";
 
     let plan = match req.plan.to_lowercase().as_str() {
         "starter" => PlanTier::Starter,
@@ -67,7 +80,7 @@ async fn create_subscription(
     };
 
     let snap_token = billing_service
-        .create_subscription(user_id, plan, user_email)
+        .create_subscription(auth_user.user_id, plan, &auth_user.email)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
@@ -83,29 +96,47 @@ async fn create_subscription(
 /// GET /billing/subscription
 async fn get_subscription(
     State(billing_service): State<std::sync::Arc<BillingService>>,
+    Extension(auth_user): Extension<AuthUser>,
 ) -> Result<Json<Option<Subscription>>, (StatusCode, String)> {
-    // TODO: Get user_id from auth middleware
-    let user_id = Uuid::nil();
-
     let subscription = billing_service
-        .get_subscription(user_id)
+        .get_subscription(auth_user.user_id)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
     Ok(Json(subscription))
 }
 
+/// Preview the prorated charge for upgrading, without creating an order
+/// GET /billing/upgrade/preview?plan=pro
+async fn preview_upgrade(
+    State(billing_service): State<std::sync::Arc<BillingService>>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<UpgradePreviewQuery>,
+) -> Result<Json<ProrationPreview>, (StatusCode, String)> {
+    let plan = match PlanTier::parse(&query.plan) {
+        Some(plan) if plan != PlanTier::Free => plan,
+        _ => return Err((StatusCode::BAD_REQUEST, "Invalid plan tier".to_string())),
+    };
+
+    billing_service
+        .preview_upgrade(auth_user.user_id, plan)
+        .await
+        .map(Json)
+        .map_err(|e| match e {
+            BillingError::InvalidPlanTier => (StatusCode::BAD_REQUEST, e.to_string()),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        })
+}
+
 /// Cancel subscription
 /// POST /billing/subscription/cancel
 /// Requirements: 3.5
 async fn cancel_subscription(
     State(billing_service): State<std::sync::Arc<BillingService>>,
+    Extension(auth_user): Extension<AuthUser>,
 ) -> Result<StatusCode, (StatusCode, String)> {
-    // TODO: Get user_id from auth middleware
-    let user_id = Uuid::nil();
-
     billing_service
-        .cancel_subscription(user_id)
+        .cancel_subscription(auth_user.user_id)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
@@ -117,6 +148,7 @@ async fn cancel_subscription(
 /// Requirements: 2.4, 2.5, 2.6
 async fn handle_midtrans_webhook(
     State(billing_service): State<std::sync::Arc<BillingService>>,
+    headers: HeaderMap,
     Json(webhook): Json<MidtransWebhook>,
 ) -> Result<StatusCode, (StatusCode, String)> {
     tracing::info!(
@@ -125,7 +157,13 @@ async fn handle_midtrans_webhook(
         "Received Midtrans webhook"
     );
 
-    match billing_service.handle_webhook(webhook).await {
+    // Midtrans signs in the body; a header is also accepted when configured
+    let header_signature = billing_service
+        .signature_header()
+        .and_then(|name| headers.get(name))
+        .and_then(|v| v.to_str().ok());
+
+    match billing_service.handle_webhook(webhook, header_signature).await {
         Ok(_) => Ok(StatusCode::OK),
         Err(BillingError::InvalidSignature) => {
             tracing::warn!("Invalid webhook signature");
@@ -143,15 +181,13 @@ async fn handle_midtrans_webhook(
 /// Requirements: 4.6
 async fn get_invoices(
     State(billing_service): State<std::sync::Arc<BillingService>>,
+    Extension(auth_user): Extension<AuthUser>,
 ) -> Result<Json<Vec<Invoice>>, (StatusCode, String)> {
-    // TODO: Get user_id from auth middleware
-    let user_id = Uuid::nil();
-    
     let pool = billing_service.pool();
     let invoice_service = InvoiceService::new(pool.clone());
     
     let invoices = invoice_service
-        .get_user_invoices(user_id)
+        .get_user_invoices(auth_user.user_id)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
@@ -163,17 +199,19 @@ async fn get_invoices(
 /// Requirements: 4.1, 4.6
 async fn get_invoice_html(
     State(billing_service): State<std::sync::Arc<BillingService>>,
+    Extension(auth_user): Extension<AuthUser>,
     Path(invoice_id): Path<Uuid>,
 ) -> Response {
     let pool = billing_service.pool();
     let invoice_service = InvoiceService::new(pool.clone());
     
+    // Other users' invoices are reported as missing
     match invoice_service.get_invoice(invoice_id).await {
-        Ok(invoice) => {
+        Ok(invoice) if invoice.invoice.user_id == auth_user.user_id => {
             let html = InvoiceService::generate_html_invoice(&invoice);
             Html(html).into_response()
         }
-        Err(_) => (StatusCode::NOT_FOUND, "Invoice not found").into_response(),
+        _ => (StatusCode::NOT_FOUND, "Invoice not found").into_response(),
     }
 }
 
@@ -182,13 +220,15 @@ async fn get_invoice_html(
 /// Requirements: 4.1, 4.6
 async fn download_invoice(
     State(billing_service): State<std::sync::Arc<BillingService>>,
+    Extension(auth_user): Extension<AuthUser>,
     Path(invoice_id): Path<Uuid>,
 ) -> Response {
     let pool = billing_service.pool();
     let invoice_service = InvoiceService::new(pool.clone());
     
+    // Other users' invoices are reported as missing
     match invoice_service.get_invoice(invoice_id).await {
-        Ok(invoice) => {
+        Ok(invoice) if invoice.invoice.user_id == auth_user.user_id => {
             let html = InvoiceService::generate_html_invoice(&invoice);
             let filename = format!("invoice-{}.html", invoice.invoice.invoice_number);
             
@@ -202,6 +242,6 @@ async fn download_invoice(
             
             (headers, html).into_response()
         }
-        Err(_) => (StatusCode::NOT_FOUND, "Invoice not found").into_response(),
+        _ => (StatusCode::NOT_FOUND, "Invoice not found").into_response(),
     }
 }
@@ -5,48 +5,127 @@
 use axum::{
     body::Body,
     extract::Extension,
-    http::{header, StatusCode},
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response, Sse},
-    routing::post,
+    routing::{get, post},
     Json, Router,
 };
 use futures::StreamExt;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::convert::Infallible;
 use async_stream::stream;
 use axum::response::sse::Event;
-
-use crate::middleware::auth::ApiKeyUser;
-use crate::models::api_key::AiProvider;
-use crate::services::api_key_service::ApiKeyServiceImpl;
+use tracing::Instrument;
+
+use crate::middleware::auth::{api_key_auth, enforce_quota, ApiKeyUser};
+use crate::services::abuse_detector::KeyCoolingDown;
+use crate::models::api_key::KeyHealth;
+use crate::services::anthropic_overload::{send_with_overload_retry, OverloadOutcome, OverloadRetryPolicy};
+use crate::services::upstream_retry::send_with_retry;
+use crate::services::api_key_service::{ApiKeyError, ApiKeyServiceImpl, ProviderCredentials};
+use crate::services::debug_capture::{self, DebugCapture};
+use crate::services::feature_flags::{FeatureFlag, FeatureFlags};
+use crate::services::provider_errors::{self, ProviderErrorRecord};
+use crate::services::provider_key_override::{ProviderKeyOverride, ProviderKeyOverrideError};
+use crate::services::request_metadata::{self, RequestMetadata};
+use crate::services::request_id::{insert_header, upstream_request_id, UPSTREAM_REQUEST_ID_HEADER};
+use crate::services::safety_fallback::{retry_once_if_blocked, ServedBy};
+use crate::services::request_timing::{Phase, RequestStart, RequestTimings};
+use crate::services::model_access::{DisallowedStreaming, ModelAccess, StreamingNotInPlan};
+use crate::services::model_routing::ModelRoute;
+use crate::services::shadow_mirror::{spawn_mirror, MirrorConfig, MirrorResult};
+use crate::services::spend_guard;
+use crate::services::transcripts::{self, Transcript, TranscriptConfig};
+use crate::services::stream_coalesce::coalesce;
 use crate::services::stream_handler::{
-    StreamHandler, StreamChunk, AnthropicStreamEvent, GoogleStreamChunk, QwenStreamChunk,
+    StreamHandler, StreamChunk, StreamUsage,
 };
+use crate::services::usage_logger::{TokenCounter, UsageLog, UsageLogger};
+use crate::services::usage_receipt::{UsageReceipt, USAGE_RECEIPT_HEADER};
 use crate::services::transformers::{
     anthropic::AnthropicTransformer,
     google::GoogleTransformer,
     qwen::QwenTransformer,
-    Provider,
+    deserialize_stop, reconcile_usage, BaseModel, ChatCompletionResponse, ContentPart, FunctionDefinition, MessageContent, ModelMetadata, Provider,
+    ResponseFormat, Tool, Usage,
 };
+use crate::utils::api_error::proxy_error;
+use crate::utils::encryption::EncryptionUtils;
 use crate::AppState;
 
+/// Create the proxy router.
+///
+/// API key auth is a route layer on the method router, so a wrong method
+/// gets a 405 instead of an auth error. Only completions consume quota.
 pub fn router() -> Router {
-    Router::new().route("/chat/completions", post(chat_completions))
+    Router::new()
+        .route(
+            "/chat/completions",
+            post(chat_completions)
+                .route_layer(axum::middleware::from_fn(enforce_quota))
+                .route_layer(axum::middleware::from_fn(api_key_auth)),
+        )
+        .route(
+            "/models",
+            get(list_models).route_layer(axum::middleware::from_fn(api_key_auth)),
+        )
 }
 
-/// Error response
+/// OpenAI-compatible model entry
 #[derive(Debug, Serialize)]
-pub struct ProxyErrorResponse {
-    pub error: ProxyError,
+pub struct ModelObject {
+    pub id: &'static str,
+    pub object: &'static str,
+    /// Catalog models carry no release date; always `0`
+    pub created: i64,
+    pub owned_by: &'static str,
 }
 
+/// OpenAI-compatible model list
 #[derive(Debug, Serialize)]
-pub struct ProxyError {
-    pub message: String,
-    pub r#type: String,
-    pub code: String,
+pub struct ModelList {
+    pub object: &'static str,
+    pub data: Vec<ModelObject>,
+}
+
+/// Catalog models of `providers`, listed in `Provider::ALL` order
+fn model_list(providers: &[Provider]) -> ModelList {
+    let data = Provider::ALL
+        .into_iter()
+        .filter(|provider| providers.contains(provider))
+        .flat_map(|provider| {
+            provider.base_models().iter().map(move |model| ModelObject {
+                id: model.id,
+                object: "model",
+                created: 0,
+                owned_by: provider.name(),
+            })
+        })
+        .collect();
+    ModelList { object: "list", data }
+}
+
+/// GET /v1/models - Models of the providers the user has keys for
+async fn list_models(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(api_key_user): Extension<ApiKeyUser>,
+) -> Response {
+    match ApiKeyServiceImpl::configured_providers(&state.db, api_key_user.user_id).await {
+        Ok(providers) => {
+            let providers: Vec<Provider> = providers.into_iter().map(Provider::from).collect();
+            Json(model_list(&providers)).into_response()
+        }
+        Err(e) => {
+            tracing::error!(user_id = %api_key_user.user_id, "Failed to load configured providers: {}", e);
+            proxy_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to list models",
+                "server_error",
+                "KEY_LOOKUP_FAILED",
+            )
+        }
+    }
 }
 
 /// Chat completion request (OpenAI-compatible format)
@@ -66,16 +145,120 @@ pub struct ChatCompletionRequest {
     pub frequency_penalty: Option<f32>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub presence_penalty: Option<f32>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "deserialize_stop", skip_serializing_if = "Option::is_none")]
     pub stop: Option<Vec<String>>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+    /// OpenAI stream options (e.g. `include_usage`), passed through as-is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<serde_json::Value>,
+    /// Structured output format (`json_object` / `json_schema`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// Number of choices to generate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// Functions the model may call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    /// How the model may use `tools` (OpenAI format)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    /// Deprecated OpenAI function calling; converted into `tools`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub functions: Option<Vec<FunctionDefinition>>,
+    /// Deprecated OpenAI `function_call`; converted into `tool_choice`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<serde_json::Value>,
+    /// Whether `tools`/`tool_choice` were converted from the legacy fields
+    #[serde(skip)]
+    pub legacy_functions: bool,
+    /// Return token log probabilities (OpenAI only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// Number of most likely alternatives per token (OpenAI only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+    /// OpenAI service tier (`auto`, `default` or `flex`); ignored for other providers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
+    /// Stream reasoning deltas as `delta.reasoning_content` for models that
+    /// support it; a proxy-only flag, never sent upstream
+    #[serde(default, skip_serializing)]
+    pub include_reasoning: bool,
+    /// Client tags stored with the usage log for analytics; never sent upstream
+    #[serde(default, skip_serializing)]
+    pub metadata: Option<RequestMetadata>,
+    /// Upper bound on upstream retries for this request, capped by the
+    /// server's retry budget; ignored for streaming, never sent upstream
+    #[serde(default, skip_serializing)]
+    pub max_retries: Option<u32>,
+}
+
+impl ChatCompletionRequest {
+    /// Whether the client asked for log probabilities
+    fn logprobs_requested(&self) -> bool {
+        self.logprobs == Some(true) || self.top_logprobs.is_some()
+    }
+
+    /// Whether reasoning deltas should reach the client
+    fn reasoning_included(&self) -> bool {
+        self.include_reasoning && ModelMetadata::for_model(&self.model).supports_reasoning
+    }
+
+    /// Convert legacy `functions`/`function_call` into `tools`/`tool_choice`
+    /// so every provider handles one representation; explicit `tools` win
+    fn convert_legacy_functions(&mut self) {
+        if self.tools.is_some() {
+            return;
+        }
+        let Some(functions) = &self.functions else {
+            return;
+        };
+        self.tools = Some(functions.iter().cloned().map(Tool::from).collect());
+        self.tool_choice = self.function_call.as_ref().map(|call| match call {
+            serde_json::Value::String(_) => call.clone(),
+            _ => serde_json::json!({ "type": "function", "function": { "name": call["name"] } }),
+        });
+        self.legacy_functions = true;
+    }
+
+    /// Send OpenAI the legacy fields the client used instead of the tools
+    /// converted from them
+    fn restore_legacy_functions(&mut self) {
+        if self.legacy_functions {
+            self.tools = None;
+            self.tool_choice = None;
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    /// `null` on assistant turns that only call tools
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    /// Function calls made by the assistant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<crate::services::transformers::ToolCall>>,
+    /// Call a `role: "tool"` message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl Message {
+    /// Every text segment, for in-place edits
+    fn texts_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        self.content.iter_mut().flat_map(MessageContent::texts_mut)
+    }
+
+    /// The message's text (empty when it has no content)
+    fn text(&self) -> std::borrow::Cow<'_, str> {
+        self.content.as_ref().map(MessageContent::text).unwrap_or_default()
+    }
 }
 
 /// Convert route Message to transformer Message
@@ -83,7 +266,10 @@ impl From<Message> for crate::services::transformers::Message {
     fn from(msg: Message) -> Self {
         crate::services::transformers::Message {
             role: msg.role,
-            content: msg.content,
+            content: msg.content.unwrap_or_else(|| MessageContent::Text(String::new())),
+            tool_calls: msg.tool_calls,
+            tool_call_id: msg.tool_call_id,
+            name: msg.name,
         }
     }
 }
@@ -102,6 +288,10 @@ impl From<ChatCompletionRequest> for crate::services::transformers::ChatCompleti
             presence_penalty: req.presence_penalty,
             stop: req.stop,
             user: req.user,
+            response_format: req.response_format,
+            n: req.n,
+            tools: req.tools,
+            tool_choice: req.tool_choice,
         }
     }
 }
@@ -110,386 +300,1680 @@ impl From<ChatCompletionRequest> for crate::services::transformers::ChatCompleti
 /// Requirements: 1.1, 2.1, 3.1, 5.1 - Multi-provider routing
 async fn chat_completions(
     Extension(state): Extension<Arc<AppState>>,
-    Extension(api_key_user): Extension<ApiKeyUser>,
-    Json(body): Json<ChatCompletionRequest>,
+    Extension(mut api_key_user): Extension<ApiKeyUser>,
+    request_start: Option<Extension<RequestStart>>,
+    headers: HeaderMap,
+    Json(mut body): Json<ChatCompletionRequest>,
 ) -> impl IntoResponse {
-    // Determine provider from model name
-    let provider = match Provider::from_model(&body.model) {
-        Some(p) => p,
+    let start = request_start
+        .map(|Extension(RequestStart(at))| at)
+        .unwrap_or_else(std::time::Instant::now);
+    let mut timings = RequestTimings::new(start);
+    let span = tracing::info_span!(
+        "chat_completions",
+        model = %body.model,
+        auth_ms = tracing::field::Empty,
+        upstream_ms = tracing::field::Empty,
+        transform_ms = tracing::field::Empty,
+        total_ms = tracing::field::Empty,
+    );
+
+    // Legacy function calling is handled as tools from here on
+    body.convert_legacy_functions();
+
+    // Refuse keys replaying the same request in a loop
+    if let Err(e) = state.abuse_detector.check(api_key_user.key_id, &serde_json::to_vec(&body).unwrap_or_default()) {
+        tracing::warn!(user_id = %api_key_user.user_id, key_id = %api_key_user.key_id, "Request refused during abuse cooldown");
+        return key_cooling_down(&e);
+    }
+
+    // Determine provider from routing overrides, then model name
+    let route = match state.model_router.resolve(&body.model) {
+        Some(route) => route,
         None => {
             return proxy_error(
                 StatusCode::BAD_REQUEST,
-                &format!("Unknown model: {}. Supported prefixes: gpt-*, claude-*, gemini-*, qwen-*", body.model),
+                &format!(
+                    "Unknown model: {}. Supported prefixes: gpt-*, claude-*, gemini-*, qwen-*, or provider/model",
+                    body.model
+                ),
                 "invalid_model",
                 "UNKNOWN_MODEL",
             );
         }
     };
-
-    // Initialize API key service
-    let service = match ApiKeyServiceImpl::from_env() {
-        Ok(s) => s,
-        Err(e) => {
-            tracing::error!("Failed to initialize encryption: {}", e);
-            return proxy_error(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Server configuration error",
-                "server_error",
-                "CONFIG_ERROR",
-            );
+    let provider = route.provider;
+    // `provider/model` names go upstream as the bare model name
+    if let Some((_, model)) = Provider::split_prefixed(&body.model) {
+        body.model = model.to_string();
+    }
+    let catalog_model = provider.catalog_model(&body.model);
+    match catalog_model {
+        Some(model) if model.deprecated => {
+            tracing::warn!(model = %body.model, "Request uses a deprecated model");
         }
-    };
+        Some(_) => {}
+        None => tracing::debug!(model = %body.model, "Model not in the {} catalog", provider.name()),
+    }
 
-    // Route to appropriate provider
-    match provider {
-        Provider::OpenAI => forward_to_openai(&state, &service, api_key_user.user_id, body).await,
-        Provider::Anthropic => forward_to_anthropic(&state, &service, api_key_user.user_id, body).await,
-        Provider::Google => forward_to_google(&state, &service, api_key_user.user_id, body).await,
-        Provider::Qwen => forward_to_qwen(&state, &service, api_key_user.user_id, body).await,
+    // Only models included in the user's plan
+    let access = state.proxy_config.model_access.get(api_key_user.plan);
+    if let Err(e) = access.check(provider, &body.model) {
+        return proxy_error(
+            StatusCode::FORBIDDEN,
+            &e.to_string(),
+            "permission_error",
+            "MODEL_NOT_IN_PLAN",
+        );
     }
-}
 
-/// Forward request to OpenAI
-/// Requirements: 4.1-4.5, 5.1-5.6
-async fn forward_to_openai(
-    state: &Arc<AppState>,
-    service: &ApiKeyServiceImpl,
-    user_id: uuid::Uuid,
-    body: ChatCompletionRequest,
-) -> Response {
-    // Get user's OpenAI API key
-    let api_key = match service
-        .get_decrypted_key(&state.db, user_id, AiProvider::Openai)
-        .await
-    {
-        Ok(key) => key,
-        Err(_) => {
+    // Streaming only on plans that include it
+    let stream_downgraded = match apply_streaming_access(access, state.proxy_config.disallowed_streaming, &mut body) {
+        Ok(downgraded) => downgraded,
+        Err(e) => return streaming_not_in_plan(&e),
+    };
+
+    // Use the caller's own provider key if sent and permitted for this key
+    match ProviderKeyOverride::from_headers(&headers, provider, api_key_user.allow_provider_key) {
+        Ok(provider_key) => api_key_user.provider_key = provider_key,
+        Err(e @ ProviderKeyOverrideError::NotAllowed) => {
+            return proxy_error(
+                StatusCode::FORBIDDEN,
+                &e.to_string(),
+                "permission_error",
+                "PROVIDER_KEY_OVERRIDE_FORBIDDEN",
+            );
+        }
+        Err(e @ ProviderKeyOverrideError::InvalidFormat(_)) => {
             return proxy_error(
                 StatusCode::BAD_REQUEST,
-                "OpenAI API key not configured",
-                "api_key_missing",
-                "OPENAI_KEY_NOT_CONFIGURED",
+                &e.to_string(),
+                "invalid_request_error",
+                "INVALID_PROVIDER_KEY",
             );
         }
-    };
-
-    let client = Client::new();
-    let url = "https://api.openai.com/v1/chat/completions";
-    let is_streaming = body.stream;
+    }
 
-    let response = match client
-        .post(url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .header("Content-Type", "application/json")
-        .json(&body)
-        .send()
-        .await
-    {
-        Ok(resp) => resp,
+    // Strip or reject control characters some providers refuse
+    match state.proxy_config.content_sanitizer.sanitize(body.messages.iter_mut().flat_map(Message::texts_mut)) {
+        Ok(0) => {}
+        Ok(stripped) => tracing::debug!(stripped, "Stripped control characters from messages"),
         Err(e) => {
-            tracing::error!("Failed to forward request to OpenAI: {}", e);
             return proxy_error(
-                StatusCode::BAD_GATEWAY,
-                "Failed to connect to OpenAI",
-                "upstream_error",
-                "OPENAI_CONNECTION_ERROR",
+                StatusCode::BAD_REQUEST,
+                &e.to_string(),
+                "invalid_request_error",
+                "INVALID_CONTROL_CHARACTER",
             );
         }
-    };
+    }
 
-    // For streaming, passthrough OpenAI's SSE directly
-    if is_streaming && response.status().is_success() {
-        return forward_stream_response(response).await;
+    // Models flagged as mishandling system messages get them as user text
+    if !ModelMetadata::for_model(&body.model).supports_system_prompt && fold_system_prompt(&mut body.messages) {
+        tracing::debug!(model = %body.model, "Folded system prompt into the first user message");
     }
 
-    forward_response(response).await
-}
+    // Enforce conversation length limits for the user's plan
+    let limits = state.proxy_config.conversation_limits.get(api_key_user.plan);
+    let total_chars: usize = body.messages.iter().map(|m| m.text().chars().count()).sum();
+    if let Err(e) = limits.check(body.messages.len(), total_chars) {
+        return proxy_error(
+            StatusCode::BAD_REQUEST,
+            &e.to_string(),
+            "invalid_request_error",
+            "TOO_MANY_MESSAGES",
+        );
+    }
 
-/// Forward request to Anthropic
-/// Requirements: 1.1-1.5, 4.1-4.5
-async fn forward_to_anthropic(
-    state: &Arc<AppState>,
-    service: &ApiKeyServiceImpl,
-    user_id: uuid::Uuid,
-    body: ChatCompletionRequest,
-) -> Response {
-    // Get user's Anthropic API key
-    let api_key = match service
-        .get_decrypted_key(&state.db, user_id, AiProvider::Anthropic)
-        .await
-    {
-        Ok(key) => key,
-        Err(_) => {
+    // Reject output formats the provider can't honor
+    if let Some(format) = &body.response_format {
+        if let Err(message) = format.check_supported(provider, body.stream) {
             return proxy_error(
                 StatusCode::BAD_REQUEST,
-                "Anthropic API key not configured",
-                "api_key_missing",
-                "ANTHROPIC_KEY_NOT_CONFIGURED",
+                &message,
+                "invalid_request_error",
+                "UNSUPPORTED_RESPONSE_FORMAT",
             );
         }
-    };
-
-    // Transform request to Anthropic format
-    let transformer_request: crate::services::transformers::ChatCompletionRequest = body.clone().into();
-    let anthropic_request = AnthropicTransformer::transform_request(&transformer_request);
-    let is_streaming = body.stream;
-    let model = body.model.clone();
+    }
 
-    let client = Client::new();
-    let url = "https://api.anthropic.com/v1/messages";
+    // DashScope's text generation API doesn't take images
+    if provider == Provider::Qwen && body.messages.iter().any(|m| m.content.as_ref().is_some_and(MessageContent::has_images)) {
+        return proxy_error(
+            StatusCode::BAD_REQUEST,
+            "Image content is not supported for Qwen models",
+            "invalid_request_error",
+            "UNSUPPORTED_CONTENT_TYPE",
+        );
+    }
 
-    let response = match client
-        .post(url)
-        .header("x-api-key", &api_key)
-        .header("anthropic-version", "2023-06-01")
-        .header("Content-Type", "application/json")
-        .json(&anthropic_request)
-        .send()
-        .await
-    {
-        Ok(resp) => resp,
-        Err(e) => {
-            tracing::error!("Failed to forward request to Anthropic: {}", e);
+    // Keep analytics tags within size limits
+    if let Some(metadata) = &body.metadata {
+        if let Err(e) = request_metadata::validate(metadata) {
             return proxy_error(
-                StatusCode::BAD_GATEWAY,
-                "Failed to connect to Anthropic",
-                "upstream_error",
-                "ANTHROPIC_CONNECTION_ERROR",
+                StatusCode::BAD_REQUEST,
+                &e.to_string(),
+                "invalid_request_error",
+                "INVALID_METADATA",
             );
         }
-    };
-
-    // Handle streaming response
-    let status = response.status();
-    if is_streaming && status.is_success() {
-        return forward_anthropic_stream(response, model).await;
     }
 
-    // Transform response back to OpenAI format
-    if status.is_success() {
-        match response.json::<crate::services::transformers::anthropic::AnthropicResponse>().await {
-            Ok(anthropic_resp) => {
-                let openai_resp = AnthropicTransformer::transform_response(anthropic_resp);
-                (StatusCode::OK, Json(openai_resp)).into_response()
-            }
-            Err(e) => {
-                tracing::error!("Failed to parse Anthropic response: {}", e);
-                proxy_error(
-                    StatusCode::BAD_GATEWAY,
-                    "Failed to parse Anthropic response",
-                    "upstream_error",
-                    "ANTHROPIC_PARSE_ERROR",
-                )
+    // Pre-authorize the worst-case cost against the user's monthly spend limit
+    if !api_key_user.unmetered {
+        let messages: Vec<crate::services::transformers::Message> =
+            body.messages.iter().cloned().map(Into::into).collect();
+        let estimate = spend_guard::estimate(provider, &body.model, &messages, body.max_tokens);
+        match spend_guard::load_budget(&state.db, api_key_user.user_id).await {
+            Ok(budget) => {
+                if let Err(e) = budget.check(&estimate) {
+                    return proxy_error(
+                        StatusCode::PAYMENT_REQUIRED,
+                        &e.to_string(),
+                        "insufficient_quota",
+                        "INSUFFICIENT_QUOTA",
+                    );
+                }
+                api_key_user.output_spend_limit_idr = budget.output_limit_idr(&estimate);
             }
+            // Fail open if the budget can't be read
+            Err(e) => tracing::warn!("Spend pre-authorization failed for user {}: {}", api_key_user.user_id, e),
         }
-    } else {
-        // Forward error response as-is
-        forward_response_with_status(response, status).await
     }
-}
 
-/// Forward request to Google AI
-/// Requirements: 2.1-2.5, 4.1-4.5
-async fn forward_to_google(
-    state: &Arc<AppState>,
-    service: &ApiKeyServiceImpl,
-    user_id: uuid::Uuid,
-    body: ChatCompletionRequest,
-) -> Response {
-    // Get user's Google AI API key
-    let api_key = match service
-        .get_decrypted_key(&state.db, user_id, AiProvider::Google)
-        .await
-    {
-        Ok(key) => key,
-        Err(_) => {
+    // Initialize API key service
+    let service = match ApiKeyServiceImpl::from_env() {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("Failed to initialize encryption: {}", e);
             return proxy_error(
-                StatusCode::BAD_REQUEST,
-                "Google AI API key not configured",
-                "api_key_missing",
-                "GOOGLE_KEY_NOT_CONFIGURED",
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Server configuration error",
+                "server_error",
+                "CONFIG_ERROR",
             );
         }
     };
 
-    // Transform request to Google format
-    let transformer_request: crate::services::transformers::ChatCompletionRequest = body.clone().into();
-    let google_request = GoogleTransformer::transform_request(&transformer_request);
+    // Shape outbound concurrency per provider (global across users)
+    let upstream_permit = match state.provider_limiter.acquire(provider).await {
+        Ok(permit) => permit,
+        Err(e) => {
+            let mut response = proxy_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                &e.to_string(),
+                "server_error",
+                "PROVIDER_SATURATED",
+            );
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from_static("1"));
+            return response;
+        }
+    };
+
+    // Sample for shadow mirroring before the body is consumed
+    let mirror = mirror_request(
+        &state.feature_flags,
+        state.proxy_config.mirror.clone(),
+        &body,
+        rand::random::<f64>(),
+    );
+
+    // Retry safety-blocked completions once on the fallback model
+    let fallback_body = state
+        .feature_flags
+        .gate(FeatureFlag::SafetyFallback, state.proxy_config.safety_fallback.clone())
+        .filter(|config| config.applies_to(&body.model, body.stream))
+        .map(|config| {
+            let mut fallback_body = body.clone();
+            fallback_body.model = config.model;
+            fallback_body
+        });
+
+    // Only OpenAI returns logprobs; other providers drop the fields
+    let logprobs_requested = body.logprobs_requested();
+
+    // Cost annotation is opt-in so strict OpenAI clients never see it
+    let include_cost = !body.stream && cost_requested(&headers);
+
+    // Sample for debug capture (never when content logging is disabled)
     let is_streaming = body.stream;
+    let capture_enabled = state.feature_flags.is_enabled(FeatureFlag::DebugCapture);
+    let debug_capture = (capture_enabled && state.proxy_config.debug_capture.should_capture(rand::random::<f64>()))
+        .then(|| DebugCapture {
+            user_id: api_key_user.user_id,
+            provider,
+            model: body.model.clone(),
+            request_body: serde_json::to_value(&body).unwrap_or_default(),
+            upstream_body: Some(upstream_request_body(provider, &body).unwrap_or_default()),
+            response_body: None,
+            status_code: 0,
+        });
+
+    // Audit transcript for keys that opted in (never when content logging is disabled)
+    let transcript = transcript_for(&state.proxy_config.transcripts, &api_key_user, provider, &body);
     let model = body.model.clone();
 
-    let client = Client::new();
-    // Use streaming endpoint if streaming is requested
-    let url = if is_streaming {
-        format!(
-            "https://generativelanguage.googleapis.com/v1beta/models/{}:streamGenerateContent?key={}&alt=sse",
-            model, api_key
-        )
+    // Route to appropriate provider
+    let response = dispatch_to_provider(&state, &service, &api_key_user, &route, body, &mut timings, UsageLogging::Log)
+        .instrument(span.clone())
+        .await;
+
+    // Complete responses are buffered once and shared by the stages below;
+    // streams are passed on as they arrive
+    let response = if is_streaming {
+        ClientResponse::Passthrough(response)
     } else {
-        GoogleTransformer::api_url(&model, &api_key)
+        match BufferedResponse::read(response, "post-processing").await {
+            Ok(buffered) => ClientResponse::Complete(buffered),
+            Err(error_response) => ClientResponse::Passthrough(error_response),
+        }
     };
 
-    let response = match client
-        .post(&url)
-        .header("Content-Type", "application/json")
-        .json(&google_request)
-        .send()
-        .await
-    {
-        Ok(resp) => resp,
-        Err(e) => {
-            tracing::error!("Failed to forward request to Google AI: {}", e);
-            return proxy_error(
-                StatusCode::BAD_GATEWAY,
-                "Failed to connect to Google AI",
-                "upstream_error",
-                "GOOGLE_CONNECTION_ERROR",
-            );
+    let (mut response, provider) = match (response, fallback_body) {
+        (ClientResponse::Complete(primary), Some(fallback_body)) if primary.status().is_success() => {
+            let (response, provider) =
+                fallback_if_blocked(&state, &service, &api_key_user, fallback_body, primary, provider, &mut timings)
+                    .instrument(span.clone())
+                    .await;
+            (ClientResponse::Complete(response), provider)
         }
+        (response, _) => (response, provider),
     };
 
-    // Handle streaming response
-    let status = response.status();
-    if is_streaming && status.is_success() {
-        return forward_google_stream(response, model).await;
+    timings.record_to_span(&span);
+    if let Some(config) = state.proxy_config.slow_request {
+        timings.warn_if_slow(config, provider, &model);
     }
 
-    // Transform response back to OpenAI format
-    if status.is_success() {
-        match response.json::<crate::services::transformers::google::GoogleResponse>().await {
-            Ok(google_resp) => {
-                let openai_resp = GoogleTransformer::transform_response(google_resp, &body.model);
-                (StatusCode::OK, Json(openai_resp)).into_response()
-            }
-            Err(e) => {
-                tracing::error!("Failed to parse Google AI response: {}", e);
-                proxy_error(
-                    StatusCode::BAD_GATEWAY,
-                    "Failed to parse Google AI response",
-                    "upstream_error",
-                    "GOOGLE_PARSE_ERROR",
-                )
-            }
+    if let (Some((primary_model, mirror_body)), ClientResponse::Complete(primary)) = (mirror, &response) {
+        if primary.status().is_success() {
+            let latency_ms = timings.total().as_millis() as u64;
+            mirror_after_response(state.clone(), api_key_user, primary_model, mirror_body, primary, latency_ms);
         }
-    } else {
-        forward_response_with_status(response, status).await
     }
-}
-
-/// Forward request to Qwen (DashScope)
-/// Requirements: 3.1-3.5, 4.1-4.5
-async fn forward_to_qwen(
-    state: &Arc<AppState>,
-    service: &ApiKeyServiceImpl,
-    user_id: uuid::Uuid,
-    body: ChatCompletionRequest,
-) -> Response {
-    // Get user's Qwen API key
-    let api_key = match service
-        .get_decrypted_key(&state.db, user_id, AiProvider::Qwen)
-        .await
-    {
-        Ok(key) => key,
-        Err(_) => {
-            return proxy_error(
-                StatusCode::BAD_REQUEST,
-                "Qwen API key not configured",
-                "api_key_missing",
-                "QWEN_KEY_NOT_CONFIGURED",
-            );
-        }
-    };
 
-    // Transform request to Qwen format
-    let transformer_request: crate::services::transformers::ChatCompletionRequest = body.clone().into();
-    let qwen_request = QwenTransformer::transform_request(&transformer_request);
-    let is_streaming = body.stream;
-    let model = body.model.clone();
+    if let Some(capture) = debug_capture {
+        capture_response(&state, capture, response.status(), response.body());
+    }
+    if let Some(transcript) = transcript {
+        record_transcript(&state, service.encryption(), transcript, response.status(), response.body());
+    }
 
-    let client = Client::new();
-    let url = "https://dashscope.aliyuncs.com/api/v1/services/aigc/text-generation/generation";
+    if let ClientResponse::Complete(complete) = &mut response {
+        attach_cost_if_requested(complete, provider, include_cost);
+        attach_context_headers(complete);
+        if state.proxy_config.usage_receipt.enabled {
+            attach_usage_receipt(complete, provider, timings.total().as_millis() as u64);
+        }
+    }
 
-    // Add SSE header for streaming
-    let mut request_builder = client
-        .post(url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .header("Content-Type", "application/json");
-    
+    let mut response = response.into_response();
+    // A stream's upstream request lasts as long as its body, and so does its slot
     if is_streaming {
-        request_builder = request_builder.header("X-DashScope-SSE", "enable");
+        response = hold_until_body_ends(response, upstream_permit);
     }
+    let response = mark_logprobs_unavailable(response, provider, logprobs_requested);
+    let response = mark_stream_downgraded(response, stream_downgraded);
+    warn_if_deprecated(response, catalog_model)
+}
 
-    let response = match request_builder.json(&qwen_request).send().await {
-        Ok(resp) => resp,
-        Err(e) => {
-            tracing::error!("Failed to forward request to Qwen: {}", e);
-            return proxy_error(
-                StatusCode::BAD_GATEWAY,
-                "Failed to connect to Qwen",
-                "upstream_error",
-                "QWEN_CONNECTION_ERROR",
-            );
-        }
-    };
+/// Keep `guard` alive until the response body has been sent, or dropped
+/// when the client disconnects
+fn hold_until_body_ends<G: Send + 'static>(response: Response, guard: G) -> Response {
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _guard = &guard;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
 
-    // Handle streaming response
-    let status = response.status();
-    if is_streaming && status.is_success() {
-        return forward_qwen_stream(response, model).await;
+/// Merge all system messages into the start of the first user message
+/// (or a new leading user message when there is none).
+/// Returns whether anything was folded.
+fn fold_system_prompt(messages: &mut Vec<Message>) -> bool {
+    let system: Vec<String> = messages
+        .iter()
+        .filter(|m| m.role == "system")
+        .map(|m| m.text().into_owned())
+        .collect();
+    if system.is_empty() {
+        return false;
     }
-
-    // Transform response back to OpenAI format
-    if status.is_success() {
-        match response.json::<crate::services::transformers::qwen::QwenResponse>().await {
-            Ok(qwen_resp) => {
-                let openai_resp = QwenTransformer::transform_response(qwen_resp, &body.model);
-                (StatusCode::OK, Json(openai_resp)).into_response()
-            }
+    messages.retain(|m| m.role != "system");
+
+    let prompt = system.join("\n\n");
+    match messages.iter_mut().find(|m| m.role == "user") {
+        Some(user) => match &mut user.content {
+            Some(MessageContent::Text(text)) => *text = format!("{}\n\n{}", prompt, text),
+            Some(MessageContent::Parts(parts)) => parts.insert(0, ContentPart::Text { text: prompt }),
+            None => user.content = Some(prompt.into()),
+        },
+        None => messages.insert(
+            0,
+            Message { role: "user".to_string(), content: Some(prompt.into()), tool_calls: None, tool_call_id: None, name: None },
+        ),
+    }
+    true
+}
+
+/// Reject or downgrade a streaming request the plan doesn't allow.
+/// Returns whether the request was downgraded to non-streaming.
+fn apply_streaming_access(
+    access: &ModelAccess,
+    mode: DisallowedStreaming,
+    body: &mut ChatCompletionRequest,
+) -> Result<bool, StreamingNotInPlan> {
+    let Err(e) = access.check_streaming(body.stream) else {
+        return Ok(false);
+    };
+    match mode {
+        DisallowedStreaming::Reject => Err(e),
+        DisallowedStreaming::Downgrade => {
+            tracing::debug!(plan = access.plan.as_str(), "Serving streaming request as non-streaming");
+            body.stream = false;
+            body.stream_options = None;
+            Ok(true)
+        }
+    }
+}
+
+fn streaming_not_in_plan(e: &StreamingNotInPlan) -> Response {
+    proxy_error(
+        StatusCode::FORBIDDEN,
+        &e.to_string(),
+        "permission_error",
+        "STREAMING_NOT_IN_PLAN",
+    )
+}
+
+fn key_cooling_down(e: &KeyCoolingDown) -> Response {
+    let mut response = proxy_error(
+        StatusCode::TOO_MANY_REQUESTS,
+        &e.to_string(),
+        "rate_limit_error",
+        "IDENTICAL_REQUEST_COOLDOWN",
+    );
+    if let Ok(value) = header::HeaderValue::from_str(&e.retry_after_secs.to_string()) {
+        response.headers_mut().insert(header::RETRY_AFTER, value);
+    }
+    response
+}
+
+/// Retry budget for an upstream request. Streams always use the server's
+/// budget; only non-streaming requests may lower it with `max_retries`.
+fn upstream_retry_policy(policy: OverloadRetryPolicy, body: &ChatCompletionRequest) -> OverloadRetryPolicy {
+    if body.stream {
+        policy
+    } else {
+        policy.with_max_retries(body.max_retries)
+    }
+}
+
+/// Header set when a streaming request was served as a single response
+const STREAM_HEADER: &str = "x-webrana-stream";
+
+/// Tell clients their `stream: true` was ignored for their plan
+fn mark_stream_downgraded(mut response: Response, downgraded: bool) -> Response {
+    if downgraded {
+        response
+            .headers_mut()
+            .insert(STREAM_HEADER, header::HeaderValue::from_static("downgraded"));
+    }
+    response
+}
+
+/// Warn clients of a deprecated model with an RFC 7234 `Warning` header,
+/// still serving the request
+fn warn_if_deprecated(mut response: Response, model: Option<&BaseModel>) -> Response {
+    if let Some(notice) = model.and_then(BaseModel::deprecation_notice) {
+        let value = format!("299 - \"{}\"", notice.replace('"', "'"));
+        if let Ok(value) = header::HeaderValue::from_str(&value) {
+            response.headers_mut().insert(header::WARNING, value);
+        }
+    }
+    response
+}
+
+/// Header set when logprobs were requested from a provider that can't return them
+const LOGPROBS_HEADER: &str = "x-webrana-logprobs";
+
+/// Flag responses whose provider ignored a logprobs request
+fn mark_logprobs_unavailable(mut response: Response, provider: Provider, requested: bool) -> Response {
+    if requested && provider != Provider::OpenAI {
+        response
+            .headers_mut()
+            .insert(LOGPROBS_HEADER, header::HeaderValue::from_static("unavailable"));
+    }
+    response
+}
+
+/// Primary model and mirror body when this request is sampled for shadow
+/// mirroring and mirror mode is enabled
+fn mirror_request(
+    flags: &FeatureFlags,
+    config: Option<MirrorConfig>,
+    body: &ChatCompletionRequest,
+    roll: f64,
+) -> Option<(String, ChatCompletionRequest)> {
+    flags
+        .gate(FeatureFlag::MirrorMode, config)
+        .filter(|config| config.should_mirror(&body.model, body.stream, roll))
+        .map(|config| {
+            let mut mirror_body = body.clone();
+            mirror_body.model = config.model;
+            (body.model.clone(), mirror_body)
+        })
+}
+
+/// Provider request body as it is sent upstream (for debug captures and
+/// transform roundtrips)
+fn upstream_request_body(provider: Provider, body: &ChatCompletionRequest) -> serde_json::Result<serde_json::Value> {
+    let request: crate::services::transformers::ChatCompletionRequest = body.clone().into();
+    match provider {
+        Provider::OpenAI => {
+            let mut openai_body = body.clone();
+            let metadata = ModelMetadata::for_model(&openai_body.model);
+            openai_body.temperature = metadata.resolve_temperature(openai_body.temperature);
+            openai_body.max_tokens = metadata.resolve_max_tokens(&openai_body.model, openai_body.max_tokens);
+            serde_json::to_value(openai_body)
+        }
+        Provider::Anthropic => serde_json::to_value(AnthropicTransformer::transform_request(&request)),
+        Provider::Google => serde_json::to_value(GoogleTransformer::transform_request(&request)),
+        Provider::Qwen => serde_json::to_value(QwenTransformer::transform_request(&request)),
+    }
+}
+
+/// A request taken through a provider's transformers
+#[derive(Debug)]
+pub struct TransformRoundtrip {
+    /// Body that would be sent upstream
+    pub upstream_body: serde_json::Value,
+    /// The provider's response mapped back to the unified format
+    pub response: ChatCompletionResponse,
+}
+
+/// Run a request through a provider's transformers without any HTTP:
+/// build the upstream body as it would be sent, then parse `raw_response`
+/// and map it back to the unified format. For tests, fuzzing and the
+/// transform benchmark.
+pub fn transform_roundtrip(
+    provider: Provider,
+    body: &ChatCompletionRequest,
+    raw_response: &str,
+) -> serde_json::Result<TransformRoundtrip> {
+    let upstream_body = upstream_request_body(provider, body)?;
+    let response = match provider {
+        Provider::OpenAI => serde_json::from_str(raw_response)?,
+        Provider::Anthropic => AnthropicTransformer::transform_response(serde_json::from_str(raw_response)?),
+        Provider::Google => GoogleTransformer::transform_response(serde_json::from_str(raw_response)?, &body.model),
+        Provider::Qwen => QwenTransformer::transform_response(serde_json::from_str(raw_response)?, &body.model),
+    };
+    Ok(TransformRoundtrip { upstream_body, response })
+}
+
+/// Record the response in a debug capture. Streaming bodies are not
+/// buffered (`body` is `None`); only their status is captured.
+fn capture_response(
+    state: &Arc<AppState>,
+    mut capture: DebugCapture,
+    status: StatusCode,
+    body: Option<&serde_json::Value>,
+) {
+    capture.status_code = status.as_u16();
+    capture.response_body = body.cloned();
+    debug_capture::save_async(&state.tasks, state.db.clone(), capture);
+}
+
+/// Transcript to record for this request, if the key opted in and content
+/// logging is enabled
+fn transcript_for(
+    config: &TranscriptConfig,
+    api_key_user: &ApiKeyUser,
+    provider: Provider,
+    body: &ChatCompletionRequest,
+) -> Option<Transcript> {
+    config.should_store(api_key_user.store_transcripts).then(|| Transcript {
+        user_id: api_key_user.user_id,
+        proxy_key_id: api_key_user.key_id,
+        provider,
+        model: body.model.clone(),
+        messages: serde_json::to_value(&body.messages).unwrap_or_default(),
+        response: None,
+        status_code: 0,
+    })
+}
+
+/// Store an encrypted transcript of the response. Streaming bodies are not
+/// buffered (`body` is `None`); only the request and status are kept.
+fn record_transcript(
+    state: &Arc<AppState>,
+    encryption: &EncryptionUtils,
+    mut transcript: Transcript,
+    status: StatusCode,
+    body: Option<&serde_json::Value>,
+) {
+    transcript.status_code = status.as_u16();
+    transcript.response = body.cloned();
+    transcripts::save_async(&state.tasks, state.db.clone(), encryption.clone(), transcript);
+}
+
+/// Largest non-streaming response body buffered for post-processing
+const MAX_BUFFERED_RESPONSE_BYTES: usize = 32 * 1024 * 1024;
+
+/// A complete response buffered once, with its body parsed as JSON (or a
+/// JSON string when it isn't JSON), shared by the post-processing stages
+struct BufferedResponse {
+    parts: axum::http::response::Parts,
+    bytes: axum::body::Bytes,
+    json: serde_json::Value,
+}
+
+/// Parsed body kept on a response rebuilt from a [`BufferedResponse`], so
+/// buffering it again doesn't parse it again
+#[derive(Clone)]
+struct ParsedBody(Arc<serde_json::Value>);
+
+impl BufferedResponse {
+    /// Buffer a response body of up to `MAX_BUFFERED_RESPONSE_BYTES`
+    async fn read(response: Response, purpose: &str) -> Result<Self, Response> {
+        let (mut parts, body) = response.into_parts();
+        let bytes = match axum::body::to_bytes(body, MAX_BUFFERED_RESPONSE_BYTES).await {
+            Ok(bytes) => bytes,
             Err(e) => {
-                tracing::error!("Failed to parse Qwen response: {}", e);
-                proxy_error(
+                tracing::error!("Failed to buffer response for {}: {}", purpose, e);
+                return Err(proxy_error(
                     StatusCode::BAD_GATEWAY,
-                    "Failed to parse Qwen response",
+                    "Failed to read response from provider",
                     "upstream_error",
-                    "QWEN_PARSE_ERROR",
-                )
+                    "RESPONSE_READ_ERROR",
+                ));
+            }
+        };
+
+        let json = match parts.extensions.remove::<ParsedBody>() {
+            Some(ParsedBody(json)) => Arc::unwrap_or_clone(json),
+            None => serde_json::from_slice(&bytes)
+                .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&bytes).into_owned())),
+        };
+        Ok(Self { parts, bytes, json })
+    }
+
+    fn status(&self) -> StatusCode {
+        self.parts.status
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.parts.headers
+    }
+
+    /// Replace the body with `json`
+    fn set_json(&mut self, json: serde_json::Value) {
+        self.bytes = json.to_string().into();
+        self.json = json;
+        self.parts.headers.remove(header::CONTENT_LENGTH);
+    }
+
+    fn into_response(self) -> Response {
+        let mut parts = self.parts;
+        parts.extensions.insert(ParsedBody(Arc::new(self.json)));
+        Response::from_parts(parts, Body::from(self.bytes))
+    }
+}
+
+/// A provider response on its way to the client: complete and buffered, or
+/// passed on as it is (streams, and responses that couldn't be buffered)
+enum ClientResponse {
+    Complete(BufferedResponse),
+    Passthrough(Response),
+}
+
+impl ClientResponse {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Complete(response) => response.status(),
+            Self::Passthrough(response) => response.status(),
+        }
+    }
+
+    /// Parsed body, when the response was buffered
+    fn body(&self) -> Option<&serde_json::Value> {
+        match self {
+            Self::Complete(response) => Some(&response.json),
+            Self::Passthrough(_) => None,
+        }
+    }
+
+    fn into_response(self) -> Response {
+        match self {
+            Self::Complete(response) => response.into_response(),
+            Self::Passthrough(response) => response,
+        }
+    }
+}
+
+/// Header naming the provider that served a response retried after a safety block
+const SERVED_BY_HEADER: &str = "x-webrana-provider";
+
+/// If a successful primary response was safety-blocked, retry once on the
+/// fallback model. Returns the final response and its provider.
+async fn fallback_if_blocked(
+    state: &Arc<AppState>,
+    service: &ApiKeyServiceImpl,
+    api_key_user: &ApiKeyUser,
+    fallback_body: ChatCompletionRequest,
+    primary: BufferedResponse,
+    provider: Provider,
+    timings: &mut RequestTimings,
+) -> (BufferedResponse, Provider) {
+    let fallback_route = state.model_router.resolve(&fallback_body.model);
+    let fallback_provider = fallback_route.as_ref().map(|route| route.provider);
+    let primary_bytes = primary.bytes.clone();
+
+    let (mut response, served_by) = retry_once_if_blocked(primary, &primary_bytes, || async move {
+        let route = fallback_route?;
+        tracing::warn!(
+            primary = provider.name(),
+            fallback_model = %fallback_body.model,
+            "Response was safety-blocked; retrying on fallback model"
+        );
+        let _upstream_permit = state.provider_limiter.acquire(route.provider).await.ok()?;
+        let response =
+            dispatch_to_provider(state, service, api_key_user, &route, fallback_body, timings, UsageLogging::Log).await;
+        if !response.status().is_success() {
+            return None;
+        }
+        BufferedResponse::read(response, "safety fallback").await.ok()
+    })
+    .await;
+
+    let served_provider = match (served_by, fallback_provider) {
+        (ServedBy::Fallback, Some(fallback_provider)) => fallback_provider,
+        _ => provider,
+    };
+    if let Ok(value) = header::HeaderValue::from_str(&served_provider.name().to_lowercase()) {
+        response.headers_mut().insert(SERVED_BY_HEADER, value);
+    }
+
+    (response, served_provider)
+}
+
+/// Header requesting `x_webrana_cost_idr` in non-streaming responses
+const INCLUDE_COST_HEADER: &str = "x-webrana-include-cost";
+
+/// Whether the client asked for the estimated cost in the response
+fn cost_requested(headers: &HeaderMap) -> bool {
+    headers
+        .get(INCLUDE_COST_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// Add `x_webrana_cost_idr` (estimated IDR cost from the response usage) to a
+/// successful completion response when requested. Responses that aren't a
+/// JSON object are left as they are.
+fn attach_cost_if_requested(response: &mut BufferedResponse, provider: Provider, requested: bool) {
+    if !requested || !response.status().is_success() || !response.json.is_object() {
+        return;
+    }
+
+    let json = &response.json;
+    let model = json["model"].as_str().unwrap_or_default();
+    let prompt_tokens = json["usage"]["prompt_tokens"].as_i64().unwrap_or(0) as i32;
+    let completion_tokens = json["usage"]["completion_tokens"].as_i64().unwrap_or(0) as i32;
+    let service_tier = json["service_tier"].as_str();
+    let cost = UsageLogger::calculate_tier_cost(provider, model, prompt_tokens, completion_tokens, service_tier);
+
+    let mut json = response.json.clone();
+    json["x_webrana_cost_idr"] = serde_json::json!(cost);
+    response.set_json(json);
+}
+
+/// Response header with the tokens a completion used of the model's context
+const CONTEXT_USED_HEADER: &str = "x-context-used-tokens";
+
+/// Response header with the model's context window, when known
+const CONTEXT_LIMIT_HEADER: &str = "x-context-limit-tokens";
+
+/// Report context window usage on successful non-streaming responses
+fn attach_context_headers(response: &mut BufferedResponse) {
+    if response.status().is_success() {
+        set_context_headers(&mut response.parts.headers, &response.json);
+    }
+}
+
+/// Set the context headers from a completion's usage and model; each header
+/// is omitted when its value isn't known
+fn set_context_headers(headers: &mut HeaderMap, completion: &serde_json::Value) {
+    if let Some(used) = completion["usage"]["total_tokens"].as_u64() {
+        insert_header(headers, CONTEXT_USED_HEADER, &used.to_string());
+    }
+    let limit = completion["model"]
+        .as_str()
+        .and_then(|model| ModelMetadata::for_model(model).context_window);
+    if let Some(limit) = limit {
+        insert_header(headers, CONTEXT_LIMIT_HEADER, &limit.to_string());
+    }
+}
+
+/// Add the `X-Webrana-Usage` receipt to a successful non-streaming response
+fn attach_usage_receipt(response: &mut BufferedResponse, provider: Provider, latency_ms: u64) {
+    if !response.status().is_success() {
+        return;
+    }
+    if let Some(receipt) = UsageReceipt::from_completion(provider, &response.json, latency_ms) {
+        insert_header(response.headers_mut(), USAGE_RECEIPT_HEADER, &receipt.encode());
+    }
+}
+
+/// Whether a dispatched request's usage is logged for the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UsageLogging {
+    Log,
+    /// Shadow mirror requests, which the user didn't make
+    Skip,
+}
+
+/// Forward a request to the provider's forwarder
+async fn dispatch_to_provider(
+    state: &Arc<AppState>,
+    service: &ApiKeyServiceImpl,
+    api_key_user: &ApiKeyUser,
+    route: &ModelRoute,
+    body: ChatCompletionRequest,
+    timings: &mut RequestTimings,
+    logging: UsageLogging,
+) -> Response {
+    let key_name = route.key_name.as_deref();
+    match route.provider {
+        Provider::OpenAI => forward_to_openai(state, service, api_key_user, key_name, body, timings, logging).await,
+        Provider::Anthropic => forward_to_anthropic(state, service, api_key_user, key_name, body, timings, logging).await,
+        Provider::Google => forward_to_google(state, service, api_key_user, key_name, body, timings, logging).await,
+        Provider::Qwen => forward_to_qwen(state, service, api_key_user, key_name, body, timings, logging).await,
+    }
+}
+
+/// Start the mirror request in the background, to be compared with the
+/// primary response
+fn mirror_after_response(
+    state: Arc<AppState>,
+    api_key_user: ApiKeyUser,
+    primary_model: String,
+    mirror_body: ChatCompletionRequest,
+    primary: &BufferedResponse,
+    latency_ms: u64,
+) {
+    let primary = MirrorResult::from_response_body(&primary_model, &primary.bytes, latency_ms);
+    if let (Some(primary), Some(route)) = (primary, state.model_router.resolve(&mirror_body.model)) {
+        let tasks = state.tasks.clone();
+        spawn_mirror(&tasks, primary, run_mirror(state, api_key_user, route, mirror_body));
+    }
+}
+
+/// Issue the mirror request and summarize its result
+async fn run_mirror(
+    state: Arc<AppState>,
+    api_key_user: ApiKeyUser,
+    route: ModelRoute,
+    body: ChatCompletionRequest,
+) -> Option<MirrorResult> {
+    let _upstream_permit = state.provider_limiter.acquire(route.provider).await.ok()?;
+    let service = ApiKeyServiceImpl::from_env().ok()?;
+    let model = body.model.clone();
+    let mut timings = RequestTimings::new(std::time::Instant::now());
+
+    // Mirror traffic is ours, not the user's: it isn't logged as their usage
+    let response =
+        dispatch_to_provider(&state, &service, &api_key_user, &route, body, &mut timings, UsageLogging::Skip).await;
+    if !response.status().is_success() {
+        return None;
+    }
+
+    let bytes = axum::body::to_bytes(response.into_body(), MAX_BUFFERED_RESPONSE_BYTES).await.ok()?;
+    MirrorResult::from_response_body(&model, &bytes, timings.total().as_millis() as u64)
+}
+
+/// Credentials for `provider`: the key supplied with the request when it
+/// applies to this provider, otherwise one of the user's stored keys
+async fn provider_credentials(
+    state: &Arc<AppState>,
+    service: &ApiKeyServiceImpl,
+    api_key_user: &ApiKeyUser,
+    provider: Provider,
+    key_name: Option<&str>,
+) -> Result<ProviderCredentials, ApiKeyError> {
+    if let Some(credentials) = api_key_user
+        .provider_key
+        .as_ref()
+        .and_then(|key| key.credentials_for(provider))
+    {
+        return Ok(credentials);
+    }
+
+    service
+        .get_decrypted_credentials(&state.db, api_key_user.user_id, provider.into(), key_name)
+        .await
+}
+
+/// Error response when provider credentials can't be loaded: a missing key
+/// is the user's to fix, anything else is a server fault
+fn credentials_error(provider: Provider, error: ApiKeyError) -> Response {
+    let (label, code) = match provider {
+        Provider::OpenAI => ("OpenAI", "OPENAI_KEY_NOT_CONFIGURED"),
+        Provider::Anthropic => ("Anthropic", "ANTHROPIC_KEY_NOT_CONFIGURED"),
+        Provider::Google => ("Google AI", "GOOGLE_KEY_NOT_CONFIGURED"),
+        Provider::Qwen => ("Qwen", "QWEN_KEY_NOT_CONFIGURED"),
+    };
+    match error {
+        ApiKeyError::NotFound => proxy_error(
+            StatusCode::BAD_REQUEST,
+            &format!("{} API key not configured", label),
+            "api_key_missing",
+            code,
+        ),
+        ApiKeyError::DecryptionFailed(key_id, e) => {
+            tracing::error!(key_id = %key_id, provider = provider.name(), "Failed to decrypt stored provider key: {}", e);
+            proxy_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Stored {} API key could not be decrypted", label),
+                "server_error",
+                "KEY_DECRYPTION_FAILED",
+            )
+        }
+        e => {
+            tracing::error!(provider = provider.name(), "Failed to load provider key: {}", e);
+            proxy_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Failed to load {} API key", label),
+                "server_error",
+                "KEY_LOOKUP_FAILED",
+            )
+        }
+    }
+}
+
+/// Forward request to OpenAI
+/// Requirements: 4.1-4.5, 5.1-5.6
+async fn forward_to_openai(
+    state: &Arc<AppState>,
+    service: &ApiKeyServiceImpl,
+    api_key_user: &ApiKeyUser,
+    key_name: Option<&str>,
+    mut body: ChatCompletionRequest,
+    timings: &mut RequestTimings,
+    logging: UsageLogging,
+) -> Response {
+    // Get the request's own OpenAI API key or the user's stored one
+    let credentials = match provider_credentials(state, service, api_key_user, Provider::OpenAI, key_name).await {
+        Ok(credentials) => credentials,
+        Err(e) => return credentials_error(Provider::OpenAI, e),
+    };
+    timings.record_since_start(Phase::Auth);
+
+    // Apply per-model temperature handling (e.g. o1 rejects temperature)
+    // and keep max_tokens within the model's output cap
+    let metadata = ModelMetadata::for_model(&body.model);
+    body.temperature = metadata.resolve_temperature(body.temperature);
+    body.max_tokens = metadata.resolve_max_tokens(&body.model, body.max_tokens);
+
+    let client = &state.http_client;
+    let url = format!("{}/v1/chat/completions", state.region_selector.base_url(Provider::OpenAI));
+    let is_streaming = body.stream;
+
+    let mut request_builder = client
+        .post(url)
+        .header("Authorization", format!("Bearer {}", credentials.api_key))
+        .header("Content-Type", "application/json");
+
+    for (name, value) in openai_scope_headers(&credentials) {
+        request_builder = request_builder.header(name, value);
+    }
+    request_builder = state.provider_headers.apply(Provider::OpenAI, request_builder);
+    if let Some(name) = state.proxy_config.request_id.outbound_header(Provider::OpenAI) {
+        request_builder = request_builder.header(name, &api_key_user.request_id);
+    }
+
+    body.restore_legacy_functions();
+    let request_builder = request_builder.json(&body);
+    let request = send_with_retry(upstream_retry_policy(state.proxy_config.upstream_retry, &body), || {
+        request_builder
+            .try_clone()
+            .expect("JSON request bodies are cloneable")
+            .send()
+    });
+
+    let response = match timings.measure(Phase::Upstream, request).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            tracing::error!("Failed to forward request to OpenAI: {}", e);
+            return proxy_error(
+                StatusCode::BAD_GATEWAY,
+                "Failed to connect to OpenAI",
+                "upstream_error",
+                "OPENAI_CONNECTION_ERROR",
+            );
+        }
+    };
+
+    let upstream_id = log_upstream_request_id(Provider::OpenAI, api_key_user, &response);
+    record_key_health(state, credentials.key_id, &response);
+
+    // For streaming, passthrough OpenAI's SSE directly
+    if is_streaming && response.status().is_success() {
+        let usage = stream_usage(state, api_key_user, Provider::OpenAI, &body, upstream_id.as_deref(), timings.start());
+        let response = forward_stream_response(
+            response,
+            usage,
+            body.model.clone(),
+            body.reasoning_included(),
+            api_key_user.output_spend_limit_idr,
+            state,
+        )
+        .await;
+        return with_upstream_request_id(response, upstream_id);
+    }
+
+    let response = if response.status().is_success() {
+        timings.measure(Phase::Transform, forward_response(response)).await
+    } else {
+        forward_upstream_error(state, api_key_user, Provider::OpenAI, &body.model, response).await
+    };
+    let response = with_upstream_request_id(response, upstream_id);
+    log_completion_usage(state, api_key_user, Provider::OpenAI, &body, timings.start(), response, logging).await
+}
+
+/// Forward request to Anthropic
+/// Requirements: 1.1-1.5, 4.1-4.5
+async fn forward_to_anthropic(
+    state: &Arc<AppState>,
+    service: &ApiKeyServiceImpl,
+    api_key_user: &ApiKeyUser,
+    key_name: Option<&str>,
+    body: ChatCompletionRequest,
+    timings: &mut RequestTimings,
+    logging: UsageLogging,
+) -> Response {
+    // Get the request's own Anthropic API key or the user's stored one
+    let credentials = match provider_credentials(state, service, api_key_user, Provider::Anthropic, key_name).await {
+        Ok(credentials) => credentials,
+        Err(e) => return credentials_error(Provider::Anthropic, e),
+    };
+    timings.record_since_start(Phase::Auth);
+
+    // Transform request to Anthropic format
+    let transformer_request: crate::services::transformers::ChatCompletionRequest = body.clone().into();
+    let anthropic_request = AnthropicTransformer::transform_request(&transformer_request);
+    let is_streaming = body.stream;
+    let include_reasoning = body.reasoning_included();
+
+    let client = &state.http_client;
+    let url = format!("{}/v1/messages", state.region_selector.base_url(Provider::Anthropic));
+
+    let mut request_builder = client
+        .post(url)
+        .header("x-api-key", &credentials.api_key)
+        .header("Content-Type", "application/json");
+
+    let header_config = &state.proxy_config.anthropic_headers;
+    for (name, value) in header_config.headers(credentials.anthropic_beta.as_deref()) {
+        request_builder = request_builder.header(name, value);
+    }
+    request_builder = state.provider_headers.apply(Provider::Anthropic, request_builder);
+    if let Some(name) = state.proxy_config.request_id.outbound_header(Provider::Anthropic) {
+        request_builder = request_builder.header(name, &api_key_user.request_id);
+    }
+
+    let request_builder = request_builder.json(&anthropic_request);
+    // Overloaded (529) responses are retried like other transient failures
+    let request = send_with_overload_retry(upstream_retry_policy(state.proxy_config.upstream_retry, &body), || {
+        request_builder
+            .try_clone()
+            .expect("JSON request bodies are cloneable")
+            .send()
+    });
+
+    let response = match timings.measure(Phase::Upstream, request).await {
+        Ok(OverloadOutcome::Response(resp)) => resp,
+        Ok(OverloadOutcome::Overloaded { retry_after_secs }) => {
+            let mut response = proxy_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Anthropic is temporarily overloaded, please retry later",
+                "overloaded",
+                "ANTHROPIC_OVERLOADED",
+            );
+            if let Ok(value) = header::HeaderValue::from_str(&retry_after_secs.to_string()) {
+                response.headers_mut().insert(header::RETRY_AFTER, value);
             }
+            return response;
+        }
+        Err(e) => {
+            tracing::error!("Failed to forward request to Anthropic: {}", e);
+            return proxy_error(
+                StatusCode::BAD_GATEWAY,
+                "Failed to connect to Anthropic",
+                "upstream_error",
+                "ANTHROPIC_CONNECTION_ERROR",
+            );
+        }
+    };
+
+    let upstream_id = log_upstream_request_id(Provider::Anthropic, api_key_user, &response);
+    record_key_health(state, credentials.key_id, &response);
+
+    // Handle streaming response
+    let status = response.status();
+    if is_streaming && status.is_success() {
+        let usage = stream_usage(state, api_key_user, Provider::Anthropic, &body, upstream_id.as_deref(), timings.start());
+        let response = forward_anthropic_stream(
+            response,
+            usage,
+            body.model.clone(),
+            include_reasoning,
+            api_key_user.output_spend_limit_idr,
+            state,
+        )
+        .await;
+        return with_upstream_request_id(response, upstream_id);
+    }
+
+    // Transform response back to OpenAI format
+    let transform = async {
+        if status.is_success() {
+            let upstream_headers = response.headers().clone();
+            let bytes = match read_json_body(response, "Anthropic").await {
+                Ok(bytes) => bytes,
+                Err(error_response) => return error_response,
+            };
+            match serde_json::from_slice::<crate::services::transformers::anthropic::AnthropicResponse>(&bytes) {
+                Ok(anthropic_resp) => {
+                    let openai_resp = if matches!(body.response_format, Some(ResponseFormat::JsonSchema { .. })) {
+                        AnthropicTransformer::transform_structured_response(anthropic_resp)
+                    } else {
+                        AnthropicTransformer::transform_response(anthropic_resp)
+                    };
+                    tracing::debug!(
+                        id = %openai_resp.id,
+                        provider_request_id = ?openai_resp.provider_request_id,
+                        "Transformed Anthropic response"
+                    );
+                    (StatusCode::OK, Json(openai_resp)).into_response()
+                }
+                Err(e) => {
+                    tracing::error!("Failed to parse Anthropic response: {}", e);
+                    let usage = unparsed_response_usage(Provider::Anthropic, &body, &upstream_headers, &bytes);
+                    parse_error("Anthropic", "ANTHROPIC_PARSE_ERROR", usage)
+                }
+            }
+        } else {
+            // Forward error response as-is
+            forward_upstream_error(state, api_key_user, Provider::Anthropic, &body.model, response).await
+        }
+    };
+
+    let response = timings.measure(Phase::Transform, transform).await;
+    let response = with_upstream_request_id(response, upstream_id);
+    log_completion_usage(state, api_key_user, Provider::Anthropic, &body, timings.start(), response, logging).await
+}
+
+/// Forward request to Google AI
+/// Requirements: 2.1-2.5, 4.1-4.5
+async fn forward_to_google(
+    state: &Arc<AppState>,
+    service: &ApiKeyServiceImpl,
+    api_key_user: &ApiKeyUser,
+    key_name: Option<&str>,
+    body: ChatCompletionRequest,
+    timings: &mut RequestTimings,
+    logging: UsageLogging,
+) -> Response {
+    // Get the request's own Google AI API key or the user's stored one
+    let (api_key, key_id) = match provider_credentials(state, service, api_key_user, Provider::Google, key_name).await {
+        Ok(credentials) => (credentials.api_key, credentials.key_id),
+        Err(e) => return credentials_error(Provider::Google, e),
+    };
+    timings.record_since_start(Phase::Auth);
+
+    // Transform request to Google format
+    let transformer_request: crate::services::transformers::ChatCompletionRequest = body.clone().into();
+    let google_request = GoogleTransformer::transform_request(&transformer_request);
+    let is_streaming = body.stream;
+    let model = body.model.clone();
+
+    let client = &state.http_client;
+    let base_url = state.region_selector.base_url(Provider::Google);
+    // Use streaming endpoint if streaming is requested
+    let url = if is_streaming {
+        format!(
+            "{}/v1beta/models/{}:streamGenerateContent?key={}&alt=sse",
+            base_url, model, api_key
+        )
+    } else {
+        GoogleTransformer::api_url(base_url, &model, &api_key)
+    };
+
+    let mut request_builder = client
+        .post(&url)
+        .header("Content-Type", "application/json");
+    request_builder = state.provider_headers.apply(Provider::Google, request_builder);
+    if let Some(name) = state.proxy_config.request_id.outbound_header(Provider::Google) {
+        request_builder = request_builder.header(name, &api_key_user.request_id);
+    }
+
+    let request_builder = request_builder.json(&google_request);
+    let request = send_with_retry(upstream_retry_policy(state.proxy_config.upstream_retry, &body), || {
+        request_builder
+            .try_clone()
+            .expect("JSON request bodies are cloneable")
+            .send()
+    });
+
+    let response = match timings.measure(Phase::Upstream, request).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            // The key is part of the URL, so log the error without it
+            tracing::error!("Failed to forward request to Google AI: {}", e.without_url());
+            return proxy_error(
+                StatusCode::BAD_GATEWAY,
+                "Failed to connect to Google AI",
+                "upstream_error",
+                "GOOGLE_CONNECTION_ERROR",
+            );
+        }
+    };
+
+    let upstream_id = log_upstream_request_id(Provider::Google, api_key_user, &response);
+    record_key_health(state, key_id, &response);
+
+    // Handle streaming response
+    let status = response.status();
+    if is_streaming && status.is_success() {
+        let usage = stream_usage(state, api_key_user, Provider::Google, &body, upstream_id.as_deref(), timings.start());
+        let response = forward_google_stream(
+            response,
+            usage,
+            model,
+            api_key_user.output_spend_limit_idr,
+            state,
+        )
+        .await;
+        return with_upstream_request_id(response, upstream_id);
+    }
+
+    // Transform response back to OpenAI format
+    let transform = async {
+        if status.is_success() {
+            let upstream_headers = response.headers().clone();
+            let bytes = match read_json_body(response, "Google AI").await {
+                Ok(bytes) => bytes,
+                Err(error_response) => return error_response,
+            };
+            match serde_json::from_slice::<crate::services::transformers::google::GoogleResponse>(&bytes) {
+                Ok(google_resp) => {
+                    let openai_resp = GoogleTransformer::transform_response(google_resp, &body.model);
+                    tracing::debug!(
+                        id = %openai_resp.id,
+                        provider_request_id = ?openai_resp.provider_request_id,
+                        "Transformed Google response"
+                    );
+                    (StatusCode::OK, Json(openai_resp)).into_response()
+                }
+                Err(e) => {
+                    tracing::error!("Failed to parse Google AI response: {}", e);
+                    let usage = unparsed_response_usage(Provider::Google, &body, &upstream_headers, &bytes);
+                    parse_error("Google AI", "GOOGLE_PARSE_ERROR", usage)
+                }
+            }
+        } else {
+            forward_upstream_error(state, api_key_user, Provider::Google, &body.model, response).await
+        }
+    };
+
+    let response = timings.measure(Phase::Transform, transform).await;
+    let response = with_upstream_request_id(response, upstream_id);
+    log_completion_usage(state, api_key_user, Provider::Google, &body, timings.start(), response, logging).await
+}
+
+/// Forward request to Qwen (DashScope)
+/// Requirements: 3.1-3.5, 4.1-4.5
+async fn forward_to_qwen(
+    state: &Arc<AppState>,
+    service: &ApiKeyServiceImpl,
+    api_key_user: &ApiKeyUser,
+    key_name: Option<&str>,
+    body: ChatCompletionRequest,
+    timings: &mut RequestTimings,
+    logging: UsageLogging,
+) -> Response {
+    // Get the request's own Qwen API key or the user's stored one
+    let (api_key, key_id) = match provider_credentials(state, service, api_key_user, Provider::Qwen, key_name).await {
+        Ok(credentials) => (credentials.api_key, credentials.key_id),
+        Err(e) => return credentials_error(Provider::Qwen, e),
+    };
+    timings.record_since_start(Phase::Auth);
+
+    // Transform request to Qwen format
+    let transformer_request: crate::services::transformers::ChatCompletionRequest = body.clone().into();
+    let qwen_request = QwenTransformer::transform_request(&transformer_request);
+    let is_streaming = body.stream;
+    let model = body.model.clone();
+
+    let client = &state.http_client;
+    let url = format!(
+        "{}/api/v1/services/aigc/text-generation/generation",
+        state.region_selector.base_url(Provider::Qwen)
+    );
+
+    // Add SSE header for streaming
+    let mut request_builder = client
+        .post(url)
+        .header("Authorization", format!("Bearer {}", api_key))
+        .header("Content-Type", "application/json");
+    
+    if is_streaming {
+        request_builder = request_builder.header("X-DashScope-SSE", "enable");
+    }
+    request_builder = state.provider_headers.apply(Provider::Qwen, request_builder);
+    if let Some(name) = state.proxy_config.request_id.outbound_header(Provider::Qwen) {
+        request_builder = request_builder.header(name, &api_key_user.request_id);
+    }
+
+    let request_builder = request_builder.json(&qwen_request);
+    let request = send_with_retry(upstream_retry_policy(state.proxy_config.upstream_retry, &body), || {
+        request_builder
+            .try_clone()
+            .expect("JSON request bodies are cloneable")
+            .send()
+    });
+
+    let response = match timings.measure(Phase::Upstream, request).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            tracing::error!("Failed to forward request to Qwen: {}", e);
+            return proxy_error(
+                StatusCode::BAD_GATEWAY,
+                "Failed to connect to Qwen",
+                "upstream_error",
+                "QWEN_CONNECTION_ERROR",
+            );
+        }
+    };
+
+    let upstream_id = log_upstream_request_id(Provider::Qwen, api_key_user, &response);
+    record_key_health(state, key_id, &response);
+
+    // Handle streaming response
+    let status = response.status();
+    if is_streaming && status.is_success() {
+        let usage = stream_usage(state, api_key_user, Provider::Qwen, &body, upstream_id.as_deref(), timings.start());
+        let response = forward_qwen_stream(
+            response,
+            usage,
+            model,
+            api_key_user.output_spend_limit_idr,
+            state,
+        )
+        .await;
+        return with_upstream_request_id(response, upstream_id);
+    }
+
+    // Transform response back to OpenAI format
+    let transform = async {
+        if status.is_success() {
+            let upstream_headers = response.headers().clone();
+            let bytes = match read_json_body(response, "Qwen").await {
+                Ok(bytes) => bytes,
+                Err(error_response) => return error_response,
+            };
+            match serde_json::from_slice::<crate::services::transformers::qwen::QwenResponse>(&bytes) {
+                Ok(qwen_resp) => qwen_completion_response(qwen_resp, &body.model),
+                Err(e) => {
+                    tracing::error!("Failed to parse Qwen response: {}", e);
+                    let usage = unparsed_response_usage(Provider::Qwen, &body, &upstream_headers, &bytes);
+                    parse_error("Qwen", "QWEN_PARSE_ERROR", usage)
+                }
+            }
+        } else {
+            forward_upstream_error(state, api_key_user, Provider::Qwen, &body.model, response).await
+        }
+    };
+
+    let response = timings.measure(Phase::Transform, transform).await;
+    let response = with_upstream_request_id(response, upstream_id);
+    log_completion_usage(state, api_key_user, Provider::Qwen, &body, timings.start(), response, logging).await
+}
+
+/// Transform a Qwen response, surfacing the `request_id` from its body in
+/// `X-Upstream-Request-Id` (DashScope doesn't always send it as a header)
+fn qwen_completion_response(qwen_resp: crate::services::transformers::qwen::QwenResponse, model: &str) -> Response {
+    let openai_resp = QwenTransformer::transform_response(qwen_resp, model);
+    tracing::debug!(
+        id = %openai_resp.id,
+        provider_request_id = ?openai_resp.provider_request_id,
+        "Transformed Qwen response"
+    );
+    let provider_request_id = openai_resp.provider_request_id.clone();
+    with_upstream_request_id((StatusCode::OK, Json(openai_resp)).into_response(), provider_request_id)
+}
+
+/// Log the provider's request id next to ours and return it
+fn log_upstream_request_id(
+    provider: Provider,
+    api_key_user: &ApiKeyUser,
+    response: &reqwest::Response,
+) -> Option<String> {
+    let upstream_id = upstream_request_id(response.headers());
+    tracing::info!(
+        provider = provider.name(),
+        request_id = %api_key_user.request_id,
+        upstream_request_id = upstream_id.as_deref().unwrap_or("-"),
+        status = response.status().as_u16(),
+        "Upstream response received"
+    );
+    upstream_id
+}
+
+/// Record in the background whether the provider accepted the user's stored
+/// key, for the key listing's health status
+fn record_key_health(state: &Arc<AppState>, key_id: Option<uuid::Uuid>, response: &reqwest::Response) {
+    let (Some(key_id), Some(health)) = (key_id, KeyHealth::from_upstream_status(response.status().as_u16())) else {
+        return;
+    };
+    let pool = state.db.clone();
+    state.tasks.spawn(async move {
+        if let Err(e) = ApiKeyServiceImpl::record_key_health(&pool, key_id, health).await {
+            tracing::warn!(key_id = %key_id, error = %e, "Failed to record provider key health");
         }
+    });
+}
+
+/// Return the provider's request id to the client in `X-Upstream-Request-Id`
+fn with_upstream_request_id(mut response: Response, upstream_id: Option<String>) -> Response {
+    if let Some(upstream_id) = upstream_id {
+        insert_header(response.headers_mut(), UPSTREAM_REQUEST_ID_HEADER, &upstream_id);
+    }
+    response
+}
+
+/// OpenAI organization/project scoping headers for the stored key
+fn openai_scope_headers(credentials: &ProviderCredentials) -> Vec<(&'static str, String)> {
+    let mut headers = Vec::new();
+    if let Some(org) = credentials.openai_organization.as_deref().filter(|v| !v.is_empty()) {
+        headers.push(("OpenAI-Organization", org.to_string()));
+    }
+    if let Some(project) = credentials.openai_project.as_deref().filter(|v| !v.is_empty()) {
+        headers.push(("OpenAI-Project", project.to_string()));
+    }
+    headers
+}
+
+/// Log a non-streaming completion's usage without delaying the response.
+/// Tokens come from the completion's `usage`, estimated from the messages
+/// and choices when the provider didn't report any
+async fn log_completion_usage(
+    state: &Arc<AppState>,
+    api_key_user: &ApiKeyUser,
+    provider: Provider,
+    body: &ChatCompletionRequest,
+    started: std::time::Instant,
+    response: Response,
+    logging: UsageLogging,
+) -> Response {
+    if logging == UsageLogging::Skip {
+        return response;
+    }
+    let response = match BufferedResponse::read(response, "usage logging").await {
+        Ok(buffered) => buffered,
+        Err(response) => return response,
+    };
+    let completion = &response.json;
+
+    let provider_request_id = response
+        .parts
+        .headers
+        .get(UPSTREAM_REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let mut usage_log = usage_log_for(api_key_user, provider, body, provider_request_id);
+    usage_log.status_code = response.status().as_u16() as i16;
+    usage_log.latency_ms = started.elapsed().as_millis() as i32;
+
+    if response.status().is_success() {
+        let (prompt_tokens, completion_tokens) = match completion.get("usage").filter(|usage| usage.is_object()) {
+            Some(usage) => (
+                usage["prompt_tokens"].as_i64().unwrap_or(0) as i32,
+                usage["completion_tokens"].as_i64().unwrap_or(0) as i32,
+            ),
+            None => estimate_completion_tokens(body, completion),
+        };
+        usage_log.prompt_tokens = prompt_tokens;
+        usage_log.completion_tokens = completion_tokens;
+        usage_log.total_tokens = prompt_tokens + completion_tokens;
+        usage_log.estimated_cost_idr = UsageLogger::calculate_tier_cost(
+            provider,
+            &body.model,
+            prompt_tokens,
+            completion_tokens,
+            completion["service_tier"].as_str(),
+        );
     } else {
-        forward_response_with_status(response, status).await
+        usage_log.error_message = completion["error"]["message"]
+            .as_str()
+            .or_else(|| completion.as_str())
+            .map(provider_errors::redact);
+        // The provider served (and bills for) a completion we couldn't parse
+        if let Some(PartialUsage(usage)) = response.parts.extensions.get::<PartialUsage>() {
+            usage_log.prompt_tokens = usage.prompt_tokens;
+            usage_log.completion_tokens = usage.completion_tokens;
+            usage_log.total_tokens = usage.total_tokens;
+            usage_log.estimated_cost_idr = UsageLogger::calculate_tier_cost(
+                provider,
+                &body.model,
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                body.service_tier.as_deref(),
+            );
+        }
+    }
+
+    UsageLogger::log_async(&state.tasks, state.db.clone(), state.redis.clone(), usage_log);
+    response.into_response()
+}
+
+/// Headers carrying token usage, read when a successful response body can't
+/// be parsed. Providers don't report usage in headers themselves; gateways
+/// in front of them may.
+const PROMPT_TOKENS_HEADERS: &[&str] = &["x-usage-prompt-tokens", "x-usage-input-tokens"];
+const COMPLETION_TOKENS_HEADERS: &[&str] = &["x-usage-completion-tokens", "x-usage-output-tokens"];
+
+/// Usage of a successful upstream response whose body couldn't be parsed,
+/// carried on the error response sent in its place so it is still logged
+#[derive(Debug, Clone)]
+struct PartialUsage(Usage);
+
+fn header_tokens(headers: &reqwest::header::HeaderMap, names: &[&str]) -> Option<i32> {
+    names
+        .iter()
+        .filter_map(|name| headers.get(*name))
+        .find_map(|value| value.to_str().ok()?.trim().parse::<i32>().ok())
+}
+
+/// Usage for an unparseable successful response: token counts from the
+/// response headers where present, otherwise estimated from the request and
+/// the raw body
+fn unparsed_response_usage(
+    provider: Provider,
+    body: &ChatCompletionRequest,
+    headers: &reqwest::header::HeaderMap,
+    raw: &[u8],
+) -> PartialUsage {
+    let prompt_tokens = header_tokens(headers, PROMPT_TOKENS_HEADERS).unwrap_or_else(|| estimated_prompt_tokens(body));
+    let completion_tokens = header_tokens(headers, COMPLETION_TOKENS_HEADERS)
+        .unwrap_or_else(|| TokenCounter::estimate_tokens(&String::from_utf8_lossy(raw)));
+    PartialUsage(reconcile_usage(provider, prompt_tokens, completion_tokens, None))
+}
+
+/// Error response for a successful upstream body that couldn't be parsed,
+/// keeping its usage for logging
+fn parse_error(provider_label: &str, code: &str, usage: PartialUsage) -> Response {
+    let mut response = proxy_error(
+        StatusCode::BAD_GATEWAY,
+        &format!("Failed to parse {} response", provider_label),
+        "upstream_error",
+        code,
+    );
+    response.extensions_mut().insert(usage);
+    response
+}
+
+/// Estimated prompt tokens of a request
+fn estimated_prompt_tokens(body: &ChatCompletionRequest) -> i32 {
+    let messages: Vec<crate::services::transformers::Message> = body.messages.iter().cloned().map(Into::into).collect();
+    TokenCounter::count_message_tokens(&messages)
+}
+
+/// Estimated prompt and completion tokens for a completion without `usage`
+fn estimate_completion_tokens(body: &ChatCompletionRequest, completion: &serde_json::Value) -> (i32, i32) {
+    let completion_tokens = completion["choices"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|choice| choice["message"]["content"].as_str())
+        .map(TokenCounter::estimate_tokens)
+        .sum();
+    (estimated_prompt_tokens(body), completion_tokens)
+}
+
+/// Usage log for a request; token counts are filled in once they are known
+fn usage_log_for(
+    api_key_user: &ApiKeyUser,
+    provider: Provider,
+    body: &ChatCompletionRequest,
+    provider_request_id: Option<&str>,
+) -> UsageLog {
+    UsageLog {
+        user_id: api_key_user.user_id,
+        proxy_key_id: Some(api_key_user.key_id),
+        provider,
+        model: body.model.clone(),
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        total_tokens: 0,
+        latency_ms: 0,
+        estimated_cost_idr: 0,
+        status_code: 200,
+        error_message: None,
+        is_internal: api_key_user.unmetered,
+        metadata: body.metadata.clone(),
+        provider_request_id: provider_request_id.map(str::to_string),
     }
 }
 
+/// Usage accounting for a stream: logs its tokens and cost once the stream
+/// ends, including when the client disconnects partway
+fn stream_usage(
+    state: &Arc<AppState>,
+    api_key_user: &ApiKeyUser,
+    provider: Provider,
+    body: &ChatCompletionRequest,
+    provider_request_id: Option<&str>,
+    started: std::time::Instant,
+) -> StreamUsage<impl FnOnce(Usage) + Send + 'static> {
+    let tasks = state.tasks.clone();
+    let pool = state.db.clone();
+    let redis = state.redis.clone();
+    let service_tier = body.service_tier.clone();
+    let mut usage_log = usage_log_for(api_key_user, provider, body, provider_request_id);
+
+    StreamUsage::new(estimated_prompt_tokens(body), move |usage| {
+        usage_log.prompt_tokens = usage.prompt_tokens;
+        usage_log.completion_tokens = usage.completion_tokens;
+        usage_log.total_tokens = usage.total_tokens;
+        usage_log.latency_ms = started.elapsed().as_millis() as i32;
+        usage_log.estimated_cost_idr = UsageLogger::calculate_tier_cost(
+            usage_log.provider,
+            &usage_log.model,
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            service_tier.as_deref(),
+        );
+        UsageLogger::log_async(&tasks, pool, redis, usage_log);
+    })
+}
+
 /// Forward streaming response (passthrough for OpenAI)
 /// Requirements: 4.1-4.3
-async fn forward_stream_response(response: reqwest::Response) -> Response {
+async fn forward_stream_response(
+    response: reqwest::Response,
+    usage: StreamUsage<impl FnOnce(Usage) + Send + 'static>,
+    model: String,
+    include_reasoning: bool,
+    output_spend_limit_idr: Option<i64>,
+    state: &AppState,
+) -> Response {
+    let payloads = StreamHandler::openai_passthrough(response.bytes_stream(), include_reasoning, usage);
+    sse_response(spend_guard::limit_stream(payloads, Provider::OpenAI, model, output_spend_limit_idr), state)
+}
+
+/// Forward Anthropic streaming response with transformation
+/// Requirements: 4.1-4.5
+async fn forward_anthropic_stream(
+    response: reqwest::Response,
+    usage: StreamUsage<impl FnOnce(Usage) + Send + 'static>,
+    model: String,
+    include_reasoning: bool,
+    output_spend_limit_idr: Option<i64>,
+    state: &AppState,
+) -> Response {
+    let payloads = StreamHandler::anthropic_stream(response.bytes_stream(), model.clone(), include_reasoning, usage);
+    sse_response(spend_guard::limit_stream(payloads, Provider::Anthropic, model, output_spend_limit_idr), state)
+}
+
+/// Forward Google streaming response with transformation
+/// Requirements: 4.1-4.5
+async fn forward_google_stream(
+    response: reqwest::Response,
+    usage: StreamUsage<impl FnOnce(Usage) + Send + 'static>,
+    model: String,
+    output_spend_limit_idr: Option<i64>,
+    state: &AppState,
+) -> Response {
+    let payloads = StreamHandler::google_stream(response.bytes_stream(), model.clone(), usage);
+    sse_response(spend_guard::limit_stream(payloads, Provider::Google, model, output_spend_limit_idr), state)
+}
+
+/// Forward Qwen streaming response with transformation
+/// Requirements: 4.1-4.5
+async fn forward_qwen_stream(
+    response: reqwest::Response,
+    usage: StreamUsage<impl FnOnce(Usage) + Send + 'static>,
+    model: String,
+    output_spend_limit_idr: Option<i64>,
+    state: &AppState,
+) -> Response {
+    let payloads = StreamHandler::qwen_stream(response.bytes_stream(), model.clone(), usage);
+    sse_response(spend_guard::limit_stream(payloads, Provider::Qwen, model, output_spend_limit_idr), state)
+}
+
+/// Send chunk payloads as SSE, coalescing content deltas when
+/// `STREAM_COALESCE_MS` is set, and finish with `[DONE]`; a shutdown
+/// ends the stream early with an error event
+fn sse_response<S>(payloads: S, state: &AppState) -> Response
+where
+    S: futures::Stream<Item = String> + Send + 'static,
+{
+    let payloads = match state.proxy_config.stream_coalesce {
+        Some(config) => coalesce(payloads, config.window).boxed(),
+        None => payloads.boxed(),
+    };
+    let payloads = StreamHandler::until_shutdown(payloads, state.shutdown.clone());
+
     let stream = stream! {
-        let mut byte_stream = response.bytes_stream();
-        let mut buffer = String::new();
-
-        while let Some(chunk_result) = byte_stream.next().await {
-            match chunk_result {
-                Ok(bytes) => {
-                    buffer.push_str(&String::from_utf8_lossy(&bytes));
-                    
-                    // Process complete lines
-                    while let Some(pos) = buffer.find("\n\n") {
-                        let line = buffer[..pos].to_string();
-                        buffer = buffer[pos + 2..].to_string();
-                        
-                        if line.starts_with("data: ") {
-                            yield Ok::<_, Infallible>(Event::default().data(&line[6..]));
-                        }
-                    }
-                }
-                Err(e) => {
-                    tracing::error!("Stream error: {}", e);
-                    break;
-                }
-            }
+        for await data in payloads {
+            yield Ok::<_, Infallible>(Event::default().data(data));
         }
-        
-        // Send [DONE] at the end
+
         yield Ok::<_, Infallible>(Event::default().data("[DONE]"));
     };
 
@@ -498,301 +1982,1059 @@ async fn forward_stream_response(response: reqwest::Response) -> Response {
         .into_response()
 }
 
-/// Forward Anthropic streaming response with transformation
-/// Requirements: 4.1-4.5
-async fn forward_anthropic_stream(response: reqwest::Response, model: String) -> Response {
-    let stream = stream! {
-        let mut byte_stream = response.bytes_stream();
-        let mut buffer = String::new();
-        let mut message_id = String::new();
-
-        while let Some(chunk_result) = byte_stream.next().await {
-            match chunk_result {
-                Ok(bytes) => {
-                    buffer.push_str(&String::from_utf8_lossy(&bytes));
-                    
-                    // Process complete SSE events
-                    while let Some(pos) = buffer.find("\n\n") {
-                        let event_block = buffer[..pos].to_string();
-                        buffer = buffer[pos + 2..].to_string();
-                        
-                        // Parse event type and data
-                        let mut event_type = String::new();
-                        let mut data = String::new();
-                        
-                        for line in event_block.lines() {
-                            if line.starts_with("event: ") {
-                                event_type = line[7..].to_string();
-                            } else if line.starts_with("data: ") {
-                                data = line[6..].to_string();
-                            }
-                        }
-                        
-                        if data.is_empty() {
-                            continue;
-                        }
-                        
-                        // Parse and transform Anthropic event
-                        if let Ok(event) = serde_json::from_str::<AnthropicStreamEvent>(&data) {
-                            // Extract message ID from message_start
-                            if let AnthropicStreamEvent::MessageStart { ref message } = event {
-                                message_id = message.id.clone();
-                            }
-                            
-                            if let Some(chunk) = StreamHandler::transform_anthropic_chunk(&event, &message_id, &model) {
-                                let sse_data = serde_json::to_string(&chunk).unwrap_or_default();
-                                yield Ok::<_, Infallible>(Event::default().data(sse_data));
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    tracing::error!("Anthropic stream error: {}", e);
-                    break;
+/// Maximum characters of a non-JSON upstream body included in errors
+const NON_JSON_SNIPPET_CHARS: usize = 200;
+
+/// Content type of an upstream response
+fn upstream_content_type(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string())
+}
+
+/// Build a `502 UPSTREAM_NON_JSON` error if a successful upstream body isn't JSON
+/// (e.g. an HTML maintenance page served with 200)
+fn non_json_error(provider: &str, content_type: Option<&str>, body: &[u8]) -> Option<Response> {
+    let is_json = content_type
+        .map(|ct| ct.to_ascii_lowercase().contains("json"))
+        .unwrap_or(false);
+    if is_json {
+        return None;
+    }
+
+    let text = String::from_utf8_lossy(body);
+    let mut snippet: String = text.chars().take(NON_JSON_SNIPPET_CHARS).collect();
+    if text.chars().count() > NON_JSON_SNIPPET_CHARS {
+        snippet.push_str("...");
+    }
+    let content_type = content_type.unwrap_or("none");
+
+    tracing::error!(
+        provider = %provider,
+        content_type = %content_type,
+        snippet = %snippet,
+        "Upstream returned non-JSON response"
+    );
+
+    Some(proxy_error(
+        StatusCode::BAD_GATEWAY,
+        &format!(
+            "{} returned a non-JSON response (content-type: {}): {}",
+            provider, content_type, snippet
+        ),
+        "upstream_error",
+        "UPSTREAM_NON_JSON",
+    ))
+}
+
+/// Read a successful upstream body, rejecting non-JSON content
+async fn read_json_body(response: reqwest::Response, provider: &str) -> Result<axum::body::Bytes, Response> {
+    let content_type = upstream_content_type(&response);
+
+    let bytes = response.bytes().await.map_err(|e| {
+        tracing::error!("Failed to read upstream response: {}", e);
+        proxy_error(
+            StatusCode::BAD_GATEWAY,
+            "Failed to read response from provider",
+            "upstream_error",
+            "RESPONSE_READ_ERROR",
+        )
+    })?;
+
+    match non_json_error(provider, content_type.as_deref(), &bytes) {
+        Some(error_response) => Err(error_response),
+        None => Ok(bytes),
+    }
+}
+
+/// Forward response from upstream provider.
+/// The client has already decoded any gzip/brotli body, so only the content
+/// type is carried over; the upstream `Content-Encoding` and `Content-Length`
+/// describe the encoded body and must not reach the client.
+async fn forward_response(response: reqwest::Response) -> Response {
+    let status_code = response.status().as_u16();
+    let content_type = upstream_content_type(&response);
+
+    match response.bytes().await {
+        Ok(bytes) => {
+            if (200..300).contains(&status_code) {
+                if let Some(error_response) = non_json_error("Upstream provider", content_type.as_deref(), &bytes) {
+                    return error_response;
                 }
             }
+
+            forwarded_body(status_code, content_type, bytes)
         }
-        
-        yield Ok::<_, Infallible>(Event::default().data("[DONE]"));
-    };
+        Err(e) => upstream_read_error(e),
+    }
+}
 
-    Sse::new(stream)
-        .keep_alive(axum::response::sse::KeepAlive::default())
-        .into_response()
+/// Forward an upstream error response as-is, recording a redacted copy
+/// in the user's provider error history
+async fn forward_upstream_error(
+    state: &Arc<AppState>,
+    api_key_user: &ApiKeyUser,
+    provider: Provider,
+    model: &str,
+    response: reqwest::Response,
+) -> Response {
+    let status_code = response.status().as_u16();
+    let content_type = upstream_content_type(&response);
+
+    match response.bytes().await {
+        Ok(bytes) => {
+            let error = ProviderErrorRecord::from_upstream(
+                api_key_user.user_id,
+                Some(api_key_user.key_id),
+                provider,
+                model,
+                status_code,
+                &bytes,
+            );
+            provider_errors::record_async(&state.tasks, state.db.clone(), error);
+            forwarded_body(status_code, content_type, bytes)
+        }
+        Err(e) => upstream_read_error(e),
+    }
 }
 
-/// Forward Google streaming response with transformation
-/// Requirements: 4.1-4.5
-async fn forward_google_stream(response: reqwest::Response, model: String) -> Response {
-    let stream = stream! {
-        let mut byte_stream = response.bytes_stream();
-        let mut buffer = String::new();
-
-        while let Some(chunk_result) = byte_stream.next().await {
-            match chunk_result {
-                Ok(bytes) => {
-                    buffer.push_str(&String::from_utf8_lossy(&bytes));
-                    
-                    // Process complete lines
-                    while let Some(pos) = buffer.find("\n") {
-                        let line = buffer[..pos].to_string();
-                        buffer = buffer[pos + 1..].to_string();
-                        
-                        if let Some(data) = StreamHandler::parse_sse_line(&line) {
-                            if let Ok(google_chunk) = serde_json::from_str::<GoogleStreamChunk>(&data) {
-                                if let Some(chunk) = StreamHandler::transform_google_chunk(&google_chunk, &model) {
-                                    let sse_data = serde_json::to_string(&chunk).unwrap_or_default();
-                                    yield Ok::<_, Infallible>(Event::default().data(sse_data));
-                                }
-                            }
-                        }
-                    }
+/// Rebuild an upstream body with its status and content type
+fn forwarded_body(status_code: u16, content_type: Option<String>, bytes: axum::body::Bytes) -> Response {
+    let axum_status = StatusCode::from_u16(status_code).unwrap_or(StatusCode::OK);
+    let mut builder = Response::builder().status(axum_status);
+
+    if let Some(ct) = content_type {
+        builder = builder.header("Content-Type", ct);
+    }
+
+    builder.body(Body::from(bytes)).unwrap_or_else(|_| {
+        Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::empty())
+            .unwrap()
+    })
+}
+
+fn upstream_read_error(e: reqwest::Error) -> Response {
+    tracing::error!("Failed to read upstream response: {}", e);
+    proxy_error(
+        StatusCode::BAD_GATEWAY,
+        "Failed to read response from provider",
+        "upstream_error",
+        "RESPONSE_READ_ERROR",
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::transformers::Provider;
+    use crate::models::api_key::AiProvider;
+
+    // ============================================================
+    // Unit Tests for Multi-Provider Proxy (Tasks 1-4)
+    // **Validates: Requirements 1.1, 2.1, 3.1, 5.1, 5.2**
+    // ============================================================
+
+    #[test]
+    fn test_provider_routing_openai() {
+        assert_eq!(Provider::from_model("gpt-4"), Some(Provider::OpenAI));
+        assert_eq!(Provider::from_model("gpt-4-turbo"), Some(Provider::OpenAI));
+        assert_eq!(Provider::from_model("gpt-3.5-turbo"), Some(Provider::OpenAI));
+        assert_eq!(Provider::from_model("o1-preview"), Some(Provider::OpenAI));
+    }
+
+    #[test]
+    fn test_provider_routing_anthropic() {
+        assert_eq!(Provider::from_model("claude-3-opus"), Some(Provider::Anthropic));
+        assert_eq!(Provider::from_model("claude-3-sonnet"), Some(Provider::Anthropic));
+        assert_eq!(Provider::from_model("claude-3-haiku"), Some(Provider::Anthropic));
+    }
+
+    #[test]
+    fn test_provider_routing_google() {
+        assert_eq!(Provider::from_model("gemini-pro"), Some(Provider::Google));
+        assert_eq!(Provider::from_model("gemini-1.5-pro"), Some(Provider::Google));
+        assert_eq!(Provider::from_model("gemini-1.5-flash"), Some(Provider::Google));
+    }
+
+    #[test]
+    fn test_provider_routing_qwen() {
+        assert_eq!(Provider::from_model("qwen-turbo"), Some(Provider::Qwen));
+        assert_eq!(Provider::from_model("qwen-plus"), Some(Provider::Qwen));
+        assert_eq!(Provider::from_model("qwen-max"), Some(Provider::Qwen));
+    }
+
+    #[test]
+    fn test_provider_routing_unknown() {
+        assert_eq!(Provider::from_model("llama-2"), None);
+        assert_eq!(Provider::from_model("mistral-7b"), None);
+        assert_eq!(Provider::from_model("unknown"), None);
+    }
+
+    #[test]
+    fn test_chat_completion_request_serialization() {
+        let request = ChatCompletionRequest {
+            model: "gpt-4".to_string(),
+            messages: vec![message("user", "Hello")],
+            temperature: Some(0.7),
+            max_tokens: Some(100),
+            stream: false,
+            top_p: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            stop: None,
+            user: None,
+            response_format: None,
+            n: None,
+            stream_options: None,
+            logprobs: None,
+            top_logprobs: None,
+            service_tier: None,
+            max_retries: None,
+            include_reasoning: false,
+            metadata: None,
+            tools: None,
+            tool_choice: None,
+            function_call: None,
+            functions: None,
+            legacy_functions: false,
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains("gpt-4"));
+        assert!(json.contains("Hello"));
+    }
+
+    #[test]
+    fn test_message_conversion() {
+        let msg = message("user", "Test");
+
+        let transformer_msg: crate::services::transformers::Message = msg.into();
+        assert_eq!(transformer_msg.role, "user");
+        assert_eq!(transformer_msg.content, "Test");
+    }
+
+    #[test]
+    fn test_proxy_error_struct() {
+        use crate::utils::api_error::ProxyError;
+
+        let error = ProxyError {
+            message: "Test message".to_string(),
+            r#type: "test_type".to_string(),
+            code: "TEST_CODE".to_string(),
+        };
+
+        assert_eq!(error.message, "Test message");
+        assert_eq!(error.r#type, "test_type");
+        assert_eq!(error.code, "TEST_CODE");
+    }
+
+    #[test]
+    fn test_include_reasoning_defaults_off_and_stays_local() {
+        let request = |model: &str, extra: serde_json::Value| -> ChatCompletionRequest {
+            let mut json = serde_json::json!({
+                "model": model,
+                "messages": [{ "role": "user", "content": "Hello" }],
+                "stream": true
+            });
+            json.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            serde_json::from_value(json).unwrap()
+        };
+
+        assert!(!request("o1-mini", serde_json::json!({})).reasoning_included());
+        let body = request("o1-mini", serde_json::json!({ "include_reasoning": true }));
+        assert!(body.reasoning_included());
+        // Never forwarded to the provider
+        assert!(serde_json::to_value(&body).unwrap().get("include_reasoning").is_none());
+        // Models without reasoning ignore the flag
+        assert!(!request("gpt-4o", serde_json::json!({ "include_reasoning": true })).reasoning_included());
+    }
+
+    #[test]
+    fn test_max_retries_ignored_for_streaming() {
+        let request = |stream: bool| -> ChatCompletionRequest {
+            serde_json::from_value(serde_json::json!({
+                "model": "gpt-4o",
+                "messages": [{ "role": "user", "content": "Hello" }],
+                "stream": stream,
+                "max_retries": 0
+            }))
+            .unwrap()
+        };
+
+        let policy = OverloadRetryPolicy::from_lookup(|_| None);
+        assert_eq!(upstream_retry_policy(policy, &request(false)).attempts, 1);
+        assert_eq!(upstream_retry_policy(policy, &request(true)).attempts, policy.attempts);
+    }
+
+    #[test]
+    fn test_logprobs_pass_through_to_openai_only() {
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Hello" }],
+            "logprobs": true,
+            "top_logprobs": 3
+        }))
+        .unwrap();
+        assert!(body.logprobs_requested());
+
+        // OpenAI receives the route request as-is
+        let openai = serde_json::to_value(&body).unwrap();
+        assert_eq!(openai["logprobs"], true);
+        assert_eq!(openai["top_logprobs"], 3);
+
+        // Other providers are built from the transformer request, which drops them
+        let mut claude = body.clone();
+        claude.model = "claude-3-haiku-20240307".to_string();
+        let upstream = upstream_request_body(Provider::Anthropic, &claude).unwrap();
+        assert!(upstream.get("logprobs").is_none());
+        assert!(upstream.get("top_logprobs").is_none());
+    }
+
+    /// Model and a synthetic upstream response for each provider
+    fn roundtrip_fixture(provider: Provider) -> (&'static str, String) {
+        let (model, body) = match provider {
+            Provider::OpenAI => ("gpt-4o-mini", serde_json::json!({
+                "id": "chatcmpl-abc",
+                "object": "chat.completion",
+                "created": 1733900000,
+                "model": "gpt-4o-mini",
+                "choices": [{ "index": 0, "message": { "role": "assistant", "content": "Halo!" }, "finish_reason": "stop" }],
+                "usage": { "prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15 }
+            })),
+            Provider::Anthropic => ("claude-3-5-sonnet-20241022", serde_json::json!({
+                "id": "msg_01",
+                "type": "message",
+                "role": "assistant",
+                "content": [{ "type": "text", "text": "Halo!" }],
+                "model": "claude-3-5-sonnet-20241022",
+                "stop_reason": "end_turn",
+                "stop_sequence": null,
+                "usage": { "input_tokens": 12, "output_tokens": 3 }
+            })),
+            Provider::Google => ("gemini-1.5-flash", serde_json::json!({
+                "candidates": [{
+                    "content": { "role": "model", "parts": [{ "text": "Halo!" }] },
+                    "finishReason": "STOP",
+                    "index": 0
+                }],
+                "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 3, "totalTokenCount": 15 }
+            })),
+            Provider::Qwen => ("qwen-turbo", serde_json::json!({
+                "output": {
+                    "choices": [{ "finish_reason": "stop", "message": { "role": "assistant", "content": "Halo!" } }]
+                },
+                "usage": { "input_tokens": 12, "output_tokens": 3, "total_tokens": 15 },
+                "request_id": "req-1"
+            })),
+        };
+        (model, body.to_string())
+    }
+
+    fn roundtrip_request(model: &str) -> ChatCompletionRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": model,
+            "messages": [
+                { "role": "system", "content": "Jawab singkat." },
+                { "role": "user", "content": "Halo" }
+            ],
+            "temperature": 0.7,
+            "max_tokens": 256,
+            "stop": ["\n\n"]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_transform_roundtrip_all_providers() {
+        for provider in Provider::ALL {
+            let (model, raw) = roundtrip_fixture(provider);
+            let TransformRoundtrip { upstream_body, response } =
+                transform_roundtrip(provider, &roundtrip_request(model), &raw).unwrap();
+
+            // The conversation and stop sequences reach the provider in its own shape
+            let system = serde_json::json!({ "role": "system", "content": "Jawab singkat." });
+            let user = serde_json::json!({ "role": "user", "content": "Halo" });
+            let (conversation, expected, stop) = match provider {
+                Provider::OpenAI => (&upstream_body["messages"], serde_json::json!([system, user]), &upstream_body["stop"]),
+                Provider::Anthropic => {
+                    assert_eq!(upstream_body["system"], "Jawab singkat.");
+                    (&upstream_body["messages"], serde_json::json!([user]), &upstream_body["stop_sequences"])
                 }
-                Err(e) => {
-                    tracing::error!("Google stream error: {}", e);
-                    break;
+                Provider::Google => {
+                    assert_eq!(upstream_body["systemInstruction"]["parts"][0]["text"], "Jawab singkat.");
+                    (
+                        &upstream_body["contents"],
+                        serde_json::json!([{ "role": "user", "parts": [{ "text": "Halo" }] }]),
+                        &upstream_body["generationConfig"]["stopSequences"],
+                    )
                 }
-            }
+                Provider::Qwen => (
+                    &upstream_body["input"]["messages"],
+                    serde_json::json!([system, user]),
+                    &upstream_body["parameters"]["stop"],
+                ),
+            };
+            assert_eq!(conversation, &expected, "{}", provider.name());
+            assert_eq!(stop, &serde_json::json!(["\n\n"]), "{}", provider.name());
+
+            assert_eq!(response.choices.len(), 1, "{}", provider.name());
+            assert_eq!(response.choices[0].message.content, "Halo!", "{}", provider.name());
+            assert_eq!(response.choices[0].finish_reason.as_deref(), Some("stop"), "{}", provider.name());
+            assert_eq!(response.usage.total_tokens, 15, "{}", provider.name());
         }
-        
-        yield Ok::<_, Infallible>(Event::default().data("[DONE]"));
-    };
+    }
+
+    #[test]
+    fn test_openai_service_tier_surfaced() {
+        let (model, raw) = roundtrip_fixture(Provider::OpenAI);
+        let mut body: serde_json::Value = serde_json::from_str(&raw).unwrap();
+        body["service_tier"] = "flex".into();
+
+        let mut request = roundtrip_request(model);
+        request.service_tier = Some("flex".to_string());
+        let roundtrip = transform_roundtrip(Provider::OpenAI, &request, &body.to_string()).unwrap();
+        assert_eq!(roundtrip.upstream_body["service_tier"], "flex");
+        assert_eq!(roundtrip.response.service_tier.as_deref(), Some("flex"));
+        assert_eq!(serde_json::to_value(&roundtrip.response).unwrap()["service_tier"], "flex");
+
+        // Other providers never report a tier
+        let (model, raw) = roundtrip_fixture(Provider::Anthropic);
+        let roundtrip = transform_roundtrip(Provider::Anthropic, &roundtrip_request(model), &raw).unwrap();
+        assert!(serde_json::to_value(&roundtrip.response).unwrap().get("service_tier").is_none());
+    }
+
+    #[test]
+    fn test_transform_roundtrip_rejects_malformed_response() {
+        for provider in Provider::ALL {
+            let (model, _) = roundtrip_fixture(provider);
+            assert!(transform_roundtrip(provider, &roundtrip_request(model), "<html>502 Bad Gateway</html>").is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_stream_holds_provider_permit() {
+        use crate::services::provider_limiter::ProviderLimiter;
+
+        let limiter = ProviderLimiter::new(1, std::time::Duration::from_millis(20));
+        let permit = limiter.acquire(Provider::OpenAI).await.unwrap();
+        let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, Infallible>>(1);
+        let response =
+            Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)).into_response();
+        let mut body = hold_until_body_ends(response, permit)
+            .into_body()
+            .into_data_stream();
+
+        // The stream is open after its first event: the slot stays taken
+        tx.send(Ok("data: {}\n\n".to_string())).await.unwrap();
+        assert_eq!(body.next().await.unwrap().unwrap(), "data: {}\n\n");
+        assert!(limiter.acquire(Provider::OpenAI).await.is_err());
+
+        // Finished (or disconnected) streams give it back
+        drop(tx);
+        assert!(body.next().await.is_none());
+        drop(body);
+        assert!(limiter.acquire(Provider::OpenAI).await.is_ok());
+    }
+
+    #[test]
+    fn test_string_stop_accepted_and_forwarded_as_list() {
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Hello" }],
+            "stop": "END"
+        }))
+        .unwrap();
+        assert_eq!(body.stop, Some(vec!["END".to_string()]));
+        assert_eq!(serde_json::to_value(&body).unwrap()["stop"], serde_json::json!(["END"]));
+    }
+
+    #[test]
+    fn test_service_tier_passes_through_to_openai_only() {
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Hello" }],
+            "service_tier": "flex"
+        }))
+        .unwrap();
+        assert_eq!(serde_json::to_value(&body).unwrap()["service_tier"], "flex");
+
+        let mut gemini = body.clone();
+        gemini.model = "gemini-1.5-flash".to_string();
+        assert!(upstream_request_body(Provider::Google, &gemini).unwrap().get("service_tier").is_none());
+
+        // Unset tier is omitted entirely
+        let mut default = body;
+        default.service_tier = None;
+        assert!(serde_json::to_value(&default).unwrap().get("service_tier").is_none());
+    }
+
+    #[test]
+    fn test_deprecated_model_carries_warning_header() {
+        let deprecated = Provider::Google.catalog_model("gemini-pro");
+        let response = warn_if_deprecated(StatusCode::OK.into_response(), deprecated);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::WARNING],
+            "299 - \"Model gemini-pro is deprecated; use gemini-1.5-flash instead\""
+        );
+
+        let current = Provider::OpenAI.catalog_model("gpt-4o");
+        let response = warn_if_deprecated(StatusCode::OK.into_response(), current);
+        assert!(response.headers().get(header::WARNING).is_none());
+
+        let response = warn_if_deprecated(StatusCode::OK.into_response(), None);
+        assert!(response.headers().get(header::WARNING).is_none());
+    }
 
-    Sse::new(stream)
-        .keep_alive(axum::response::sse::KeepAlive::default())
-        .into_response()
-}
+    fn message(role: &str, content: &str) -> Message {
+        Message { role: role.to_string(), content: Some(content.into()), tool_calls: None, tool_call_id: None, name: None }
+    }
 
-/// Forward Qwen streaming response with transformation
-/// Requirements: 4.1-4.5
-async fn forward_qwen_stream(response: reqwest::Response, model: String) -> Response {
-    let stream = stream! {
-        let mut byte_stream = response.bytes_stream();
-        let mut buffer = String::new();
-
-        while let Some(chunk_result) = byte_stream.next().await {
-            match chunk_result {
-                Ok(bytes) => {
-                    buffer.push_str(&String::from_utf8_lossy(&bytes));
-                    
-                    // Process complete lines
-                    while let Some(pos) = buffer.find("\n\n") {
-                        let line = buffer[..pos].to_string();
-                        buffer = buffer[pos + 2..].to_string();
-                        
-                        if let Some(data) = StreamHandler::parse_sse_line(&line) {
-                            if let Ok(qwen_chunk) = serde_json::from_str::<QwenStreamChunk>(&data) {
-                                if let Some(chunk) = StreamHandler::transform_qwen_chunk(&qwen_chunk, &model) {
-                                    let sse_data = serde_json::to_string(&chunk).unwrap_or_default();
-                                    yield Ok::<_, Infallible>(Event::default().data(sse_data));
-                                }
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    tracing::error!("Qwen stream error: {}", e);
-                    break;
-                }
-            }
-        }
-        
-        yield Ok::<_, Infallible>(Event::default().data("[DONE]"));
-    };
+    #[test]
+    fn test_system_prompt_folded_into_first_user_message() {
+        let mut messages = vec![
+            message("system", "You are terse."),
+            message("user", "Hi"),
+            message("assistant", "Hello"),
+            message("user", "Bye"),
+        ];
+
+        assert!(fold_system_prompt(&mut messages));
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].role, "user");
+        assert_eq!(messages[0].text(), "You are terse.\n\nHi");
+        assert_eq!(messages[2].text(), "Bye");
+    }
 
-    Sse::new(stream)
-        .keep_alive(axum::response::sse::KeepAlive::default())
-        .into_response()
-}
+    #[test]
+    fn test_system_prompt_fold_edge_cases() {
+        // Several system messages are merged in order
+        let mut messages = vec![message("system", "A"), message("system", "B"), message("user", "Q")];
+        assert!(fold_system_prompt(&mut messages));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].text(), "A\n\nB\n\nQ");
+
+        // No user message: the system prompt becomes one
+        let mut messages = vec![message("system", "Only instructions")];
+        assert!(fold_system_prompt(&mut messages));
+        assert_eq!(messages[0].role, "user");
+        assert_eq!(messages[0].text(), "Only instructions");
+
+        // Nothing to fold leaves the conversation untouched
+        let mut messages = vec![message("user", "Q")];
+        assert!(!fold_system_prompt(&mut messages));
+        assert_eq!(messages[0].text(), "Q");
+
+        // Multimodal user message: the prompt becomes a leading text part
+        let image: MessageContent = serde_json::from_value(serde_json::json!([
+            { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } }
+        ]))
+        .unwrap();
+        let mut messages = vec![message("system", "Describe"), Message { content: Some(image), ..message("user", "") }];
+        assert!(fold_system_prompt(&mut messages));
+        assert_eq!(
+            serde_json::to_value(&messages[0].content).unwrap(),
+            serde_json::json!([
+                { "type": "text", "text": "Describe" },
+                { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } }
+            ])
+        );
+    }
 
-/// Forward response from upstream provider
-async fn forward_response(response: reqwest::Response) -> Response {
-    let status_code = response.status().as_u16();
-    let content_type = response
-        .headers()
-        .get("content-type")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string());
+    #[tokio::test]
+    async fn test_credentials_errors_map_to_status() {
+        let response = credentials_error(Provider::Google, ApiKeyError::NotFound);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let json = error_json(response).await;
+        assert_eq!(json["error"]["code"], "GOOGLE_KEY_NOT_CONFIGURED");
+        assert_eq!(json["error"]["message"], "Google AI API key not configured");
+
+        let decryption = crate::utils::encryption::EncryptionError::DecryptionFailed;
+        let response = credentials_error(Provider::OpenAI, ApiKeyError::DecryptionFailed(uuid::Uuid::nil(), decryption));
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let json = error_json(response).await;
+        assert_eq!(json["error"]["code"], "KEY_DECRYPTION_FAILED");
+        assert_eq!(json["error"]["type"], "server_error");
+
+        let response = credentials_error(Provider::Qwen, ApiKeyError::DatabaseError(sqlx::Error::PoolTimedOut));
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error_json(response).await["error"]["code"], "KEY_LOOKUP_FAILED");
+    }
 
-    match response.bytes().await {
-        Ok(bytes) => {
-            let axum_status = StatusCode::from_u16(status_code).unwrap_or(StatusCode::OK);
-            let mut builder = Response::builder().status(axum_status);
+    async fn error_json(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
 
-            if let Some(ct) = content_type {
-                builder = builder.header("Content-Type", ct);
-            }
+    fn streaming_request() -> ChatCompletionRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [{ "role": "user", "content": "Hello" }],
+            "stream": true,
+            "stream_options": { "include_usage": true }
+        }))
+        .unwrap()
+    }
 
-            builder.body(Body::from(bytes)).unwrap_or_else(|_| {
-                Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::empty())
-                    .unwrap()
-            })
-        }
-        Err(e) => {
-            tracing::error!("Failed to read upstream response: {}", e);
-            proxy_error(
-                StatusCode::BAD_GATEWAY,
-                "Failed to read response from provider",
-                "upstream_error",
-                "RESPONSE_READ_ERROR",
-            )
-        }
+    #[test]
+    fn test_streaming_allowed_plan_keeps_stream() {
+        let access = ModelAccess::for_plan(crate::models::PlanTier::Pro);
+        let mut body = streaming_request();
+
+        let downgraded = apply_streaming_access(&access, DisallowedStreaming::Reject, &mut body).unwrap();
+        assert!(!downgraded);
+        assert!(body.stream);
+        assert!(body.stream_options.is_some());
     }
-}
 
-/// Forward response with specific status
-async fn forward_response_with_status(response: reqwest::Response, _status: reqwest::StatusCode) -> Response {
-    forward_response(response).await
-}
+    #[tokio::test]
+    async fn test_streaming_disallowed_plan_rejected() {
+        let access = ModelAccess {
+            streaming: false,
+            ..ModelAccess::for_plan(crate::models::PlanTier::Free)
+        };
+        let mut body = streaming_request();
+
+        let e = apply_streaming_access(&access, DisallowedStreaming::Reject, &mut body).unwrap_err();
+        assert!(body.stream);
+        let response = streaming_not_in_plan(&e);
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], "STREAMING_NOT_IN_PLAN");
+        assert_eq!(json["error"]["type"], "permission_error");
+
+        // Non-streaming requests on the same plan are unaffected
+        let mut body = streaming_request();
+        body.stream = false;
+        assert_eq!(apply_streaming_access(&access, DisallowedStreaming::Reject, &mut body).ok(), Some(false));
+    }
 
-/// Helper function to create proxy error responses
-fn proxy_error(status: StatusCode, message: &str, error_type: &str, code: &str) -> Response {
-    let body = Json(ProxyErrorResponse {
-        error: ProxyError {
-            message: message.to_string(),
-            r#type: error_type.to_string(),
-            code: code.to_string(),
-        },
-    });
+    #[test]
+    fn test_streaming_disallowed_plan_downgraded() {
+        let access = ModelAccess {
+            streaming: false,
+            ..ModelAccess::for_plan(crate::models::PlanTier::Free)
+        };
+        let mut body = streaming_request();
 
-    (status, body).into_response()
-}
+        let downgraded = apply_streaming_access(&access, DisallowedStreaming::Downgrade, &mut body).unwrap();
+        assert!(downgraded);
+        assert!(!body.stream);
+        assert!(body.stream_options.is_none());
 
+        let response = mark_stream_downgraded(StatusCode::OK.into_response(), downgraded);
+        assert_eq!(response.headers().get(STREAM_HEADER).unwrap(), "downgraded");
+        let response = mark_stream_downgraded(StatusCode::OK.into_response(), false);
+        assert!(response.headers().get(STREAM_HEADER).is_none());
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::services::transformers::Provider;
+    #[test]
+    fn test_logprobs_unavailable_header() {
+        let response = mark_logprobs_unavailable(StatusCode::OK.into_response(), Provider::Google, true);
+        assert_eq!(response.headers()[LOGPROBS_HEADER], "unavailable");
 
-    // ============================================================
-    // Unit Tests for Multi-Provider Proxy (Tasks 1-4)
-    // **Validates: Requirements 1.1, 2.1, 3.1, 5.1, 5.2**
-    // ============================================================
+        let response = mark_logprobs_unavailable(StatusCode::OK.into_response(), Provider::OpenAI, true);
+        assert!(response.headers().get(LOGPROBS_HEADER).is_none());
+
+        let response = mark_logprobs_unavailable(StatusCode::OK.into_response(), Provider::Google, false);
+        assert!(response.headers().get(LOGPROBS_HEADER).is_none());
+    }
 
     #[test]
-    fn test_provider_routing_openai() {
-        assert_eq!(Provider::from_model("gpt-4"), Some(Provider::OpenAI));
-        assert_eq!(Provider::from_model("gpt-4-turbo"), Some(Provider::OpenAI));
-        assert_eq!(Provider::from_model("gpt-3.5-turbo"), Some(Provider::OpenAI));
-        assert_eq!(Provider::from_model("o1-preview"), Some(Provider::OpenAI));
+    fn test_mirror_mode_flag_gates_mirroring() {
+        let config = MirrorConfig::from_lookup(|key| match key {
+            "MIRROR_MODEL" => Some("gpt-4o-mini".to_string()),
+            "MIRROR_SAMPLE_RATE" => Some("1.0".to_string()),
+            _ => None,
+        });
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Hello" }]
+        }))
+        .unwrap();
+        let flags = FeatureFlags::default();
+
+        let (primary, mirror_body) = mirror_request(&flags, config.clone(), &body, 0.5).unwrap();
+        assert_eq!(primary, "gpt-4o");
+        assert_eq!(mirror_body.model, "gpt-4o-mini");
+
+        flags.set(FeatureFlag::MirrorMode, false);
+        assert!(mirror_request(&flags, config, &body, 0.5).is_none());
     }
 
     #[test]
-    fn test_provider_routing_anthropic() {
-        assert_eq!(Provider::from_model("claude-3-opus"), Some(Provider::Anthropic));
-        assert_eq!(Provider::from_model("claude-3-sonnet"), Some(Provider::Anthropic));
-        assert_eq!(Provider::from_model("claude-3-haiku"), Some(Provider::Anthropic));
+    fn test_unmetered_key_usage_marked_internal() {
+        let mut api_key_user = ApiKeyUser {
+            key_id: uuid::Uuid::new_v4(),
+            user_id: uuid::Uuid::new_v4(),
+            plan: crate::models::PlanTier::Free,
+            unmetered: true,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Summarize this" }],
+            "metadata": { "feature": "summarize" }
+        }))
+        .unwrap();
+        let log = usage_log_for(&api_key_user, Provider::OpenAI, &request, Some("req_abc"));
+        assert!(log.is_internal);
+        assert_eq!(log.provider_request_id.as_deref(), Some("req_abc"));
+        assert_eq!(log.proxy_key_id, Some(api_key_user.key_id));
+        assert_eq!(log.metadata.unwrap()["feature"], "summarize");
+        // Metadata is kept for analytics, not forwarded to the provider
+        assert!(serde_json::to_value(&request).unwrap().get("metadata").is_none());
+
+        api_key_user.unmetered = false;
+        assert!(!usage_log_for(&api_key_user, Provider::OpenAI, &request, None).is_internal);
+    }
+
+    fn legacy_functions_request() -> ChatCompletionRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "claude-3-5-sonnet-20241022",
+            "messages": [{ "role": "user", "content": "Weather in Jakarta?" }],
+            "functions": [{
+                "name": "get_weather",
+                "description": "Current weather for a city",
+                "parameters": { "type": "object", "properties": { "city": { "type": "string" } } }
+            }],
+            "function_call": { "name": "get_weather" }
+        }))
+        .unwrap()
     }
 
     #[test]
-    fn test_provider_routing_google() {
-        assert_eq!(Provider::from_model("gemini-pro"), Some(Provider::Google));
-        assert_eq!(Provider::from_model("gemini-1.5-pro"), Some(Provider::Google));
-        assert_eq!(Provider::from_model("gemini-1.5-flash"), Some(Provider::Google));
+    fn test_legacy_functions_converted_to_tools() {
+        let mut request = legacy_functions_request();
+        request.convert_legacy_functions();
+
+        let tools = request.tools.as_ref().unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].r#type, "function");
+        assert_eq!(tools[0].function.name, "get_weather");
+        assert_eq!(tools[0].function.description.as_deref(), Some("Current weather for a city"));
+        assert_eq!(
+            request.tool_choice,
+            Some(serde_json::json!({ "type": "function", "function": { "name": "get_weather" } }))
+        );
+
+        // Providers other than OpenAI only see the tools
+        let transformed: crate::services::transformers::ChatCompletionRequest = request.into();
+        assert_eq!(transformed.tools.unwrap()[0].function.name, "get_weather");
+        assert_eq!(
+            crate::services::transformers::ToolChoice::from_value(&transformed.tool_choice.unwrap()),
+            crate::services::transformers::ToolChoice::Function("get_weather".to_string())
+        );
     }
 
     #[test]
-    fn test_provider_routing_qwen() {
-        assert_eq!(Provider::from_model("qwen-turbo"), Some(Provider::Qwen));
-        assert_eq!(Provider::from_model("qwen-plus"), Some(Provider::Qwen));
-        assert_eq!(Provider::from_model("qwen-max"), Some(Provider::Qwen));
+    fn test_legacy_function_call_modes_kept() {
+        let mut request = legacy_functions_request();
+        request.function_call = Some(serde_json::json!("none"));
+        request.convert_legacy_functions();
+        assert_eq!(request.tool_choice, Some(serde_json::json!("none")));
+
+        let mut request = legacy_functions_request();
+        request.function_call = None;
+        request.convert_legacy_functions();
+        assert!(request.tools.is_some());
+        assert!(request.tool_choice.is_none());
     }
 
     #[test]
-    fn test_provider_routing_unknown() {
-        assert_eq!(Provider::from_model("llama-2"), None);
-        assert_eq!(Provider::from_model("mistral-7b"), None);
-        assert_eq!(Provider::from_model("unknown"), None);
+    fn test_explicit_tools_win_over_legacy_functions() {
+        let mut request = legacy_functions_request();
+        request.tools = Some(vec![Tool::from(FunctionDefinition {
+            name: "lookup".to_string(),
+            description: None,
+            parameters: None,
+        })]);
+        request.convert_legacy_functions();
+
+        assert!(!request.legacy_functions);
+        assert_eq!(request.tools.unwrap()[0].function.name, "lookup");
     }
 
     #[test]
-    fn test_chat_completion_request_serialization() {
-        let request = ChatCompletionRequest {
-            model: "gpt-4".to_string(),
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: "Hello".to_string(),
-            }],
+    fn test_openai_receives_legacy_fields_client_sent() {
+        let mut request = legacy_functions_request();
+        request.convert_legacy_functions();
+        request.restore_legacy_functions();
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("tools").is_none());
+        assert!(json.get("tool_choice").is_none());
+        assert!(json.get("legacy_functions").is_none());
+        assert_eq!(json["functions"][0]["name"], "get_weather");
+        assert_eq!(json["function_call"]["name"], "get_weather");
+    }
+
+    #[test]
+    fn test_openai_scope_headers_present_when_configured() {
+        let credentials = ProviderCredentials {
+            key_id: None,
+            api_key: "sk-test".to_string(),
+            openai_organization: Some("org-123".to_string()),
+            openai_project: Some("proj_456".to_string()),
+            anthropic_beta: None,
+        };
+
+        let headers = openai_scope_headers(&credentials);
+        assert_eq!(
+            headers,
+            vec![
+                ("OpenAI-Organization", "org-123".to_string()),
+                ("OpenAI-Project", "proj_456".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_openai_scope_headers_absent_otherwise() {
+        let credentials = ProviderCredentials {
+            key_id: None,
+            api_key: "sk-test".to_string(),
+            openai_organization: None,
+            openai_project: Some(String::new()),
+            anthropic_beta: None,
+        };
+
+        assert!(openai_scope_headers(&credentials).is_empty());
+    }
+
+    #[test]
+    fn test_o1_request_never_serializes_temperature() {
+        let mut request = ChatCompletionRequest {
+            model: "o1-preview".to_string(),
+            messages: vec![message("user", "Hello")],
             temperature: Some(0.7),
-            max_tokens: Some(100),
+            max_tokens: None,
             stream: false,
             top_p: None,
             frequency_penalty: None,
             presence_penalty: None,
             stop: None,
             user: None,
+            response_format: None,
+            n: None,
+            stream_options: None,
+            logprobs: None,
+            top_logprobs: None,
+            service_tier: None,
+            max_retries: None,
+            include_reasoning: false,
+            metadata: None,
+            tools: None,
+            tool_choice: None,
+            function_call: None,
+            functions: None,
+            legacy_functions: false,
         };
 
-        let json = serde_json::to_string(&request).unwrap();
-        assert!(json.contains("gpt-4"));
-        assert!(json.contains("Hello"));
+        request.temperature = ModelMetadata::for_model(&request.model).resolve_temperature(request.temperature);
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("temperature").is_none());
+
+        request.model = "gpt-4".to_string();
+        request.temperature = ModelMetadata::for_model(&request.model).resolve_temperature(Some(0.7));
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("temperature").is_some());
     }
 
     #[test]
-    fn test_message_conversion() {
-        let msg = Message {
-            role: "user".to_string(),
-            content: "Test".to_string(),
-        };
+    fn test_json_schema_response_format_passes_through_to_openai() {
+        let response_format = serde_json::json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": "weather",
+                "strict": true,
+                "schema": {
+                    "type": "object",
+                    "properties": { "city": { "type": "string" } },
+                    "required": ["city"],
+                    "additionalProperties": false
+                }
+            }
+        });
+        let body = serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Weather?" }],
+            "response_format": response_format,
+        });
 
-        let transformer_msg: crate::services::transformers::Message = msg.into();
-        assert_eq!(transformer_msg.role, "user");
-        assert_eq!(transformer_msg.content, "Test");
+        let request: ChatCompletionRequest = serde_json::from_value(body).unwrap();
+        let forwarded = serde_json::to_value(&request).unwrap();
+
+        assert_eq!(forwarded["response_format"], response_format);
     }
 
     #[test]
-    fn test_proxy_error_struct() {
-        let error = ProxyError {
-            message: "Test message".to_string(),
-            r#type: "test_type".to_string(),
-            code: "TEST_CODE".to_string(),
+    fn test_unsupported_response_format_rejected() {
+        let schema = ResponseFormat::JsonSchema {
+            json_schema: crate::services::transformers::JsonSchemaFormat {
+                name: "weather".to_string(),
+                description: None,
+                schema: None,
+                strict: None,
+            },
         };
 
-        assert_eq!(error.message, "Test message");
-        assert_eq!(error.r#type, "test_type");
-        assert_eq!(error.code, "TEST_CODE");
+        assert!(schema.check_supported(Provider::OpenAI, true).is_ok());
+        assert!(schema.check_supported(Provider::Google, false).is_ok());
+        assert!(schema.check_supported(Provider::Anthropic, false).is_ok());
+        assert!(schema.check_supported(Provider::Anthropic, true).is_err());
+        assert!(schema.check_supported(Provider::Qwen, false).is_err());
+        assert!(ResponseFormat::JsonObject.check_supported(Provider::Anthropic, false).is_err());
+        assert!(ResponseFormat::JsonObject.check_supported(Provider::Qwen, false).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_html_body_with_200_returns_upstream_non_json() {
+        let html = format!(
+            "<!DOCTYPE html><html><body><h1>Down for maintenance</h1>{}</body></html>",
+            "x".repeat(500)
+        );
+
+        let response = non_json_error("Google AI", Some("text/html; charset=utf-8"), html.as_bytes())
+            .expect("HTML body should be rejected");
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let message = json["error"]["message"].as_str().unwrap();
+
+        assert_eq!(json["error"]["code"], "UPSTREAM_NON_JSON");
+        assert!(message.contains("text/html"));
+        assert!(message.contains("Down for maintenance"));
+        assert!(message.len() < html.len());
+    }
+
+    #[test]
+    fn test_json_content_type_is_accepted() {
+        assert!(non_json_error("OpenAI", Some("application/json"), b"{}").is_none());
+        assert!(non_json_error("OpenAI", Some("application/json; charset=utf-8"), b"{}").is_none());
+        assert!(non_json_error("OpenAI", None, b"{}").is_some());
+    }
+
+    fn completion_json() -> serde_json::Value {
+        serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Hi" },
+                "finish_reason": "stop"
+            }],
+            "usage": { "prompt_tokens": 1000000, "completion_tokens": 1000000, "total_tokens": 2000000 }
+        })
+    }
+
+    async fn buffered(response: Response) -> BufferedResponse {
+        BufferedResponse::read(response, "test").await.ok().unwrap()
+    }
+
+    #[test]
+    fn test_cost_requested_header() {
+        let mut headers = HeaderMap::new();
+        assert!(!cost_requested(&headers));
+
+        headers.insert(INCLUDE_COST_HEADER, "true".parse().unwrap());
+        assert!(cost_requested(&headers));
+
+        headers.insert(INCLUDE_COST_HEADER, "false".parse().unwrap());
+        assert!(!cost_requested(&headers));
+    }
+
+    #[tokio::test]
+    async fn test_cost_field_only_when_requested() {
+        async fn respond(requested: bool) -> serde_json::Value {
+            let mut response = buffered(Json(completion_json()).into_response()).await;
+            attach_cost_if_requested(&mut response, Provider::OpenAI, requested);
+            let body = axum::body::to_bytes(response.into_response().into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice(&body).unwrap()
+        }
+
+        let expected = UsageLogger::calculate_cost(Provider::OpenAI, "gpt-4o", 1_000_000, 1_000_000);
+        let with_cost = respond(true).await;
+        assert_eq!(with_cost["x_webrana_cost_idr"], expected);
+        assert_eq!(with_cost["choices"][0]["message"]["content"], "Hi");
+
+        let without_cost = respond(false).await;
+        assert!(without_cost.get("x_webrana_cost_idr").is_none());
+        assert_eq!(without_cost, completion_json());
+    }
+
+    #[tokio::test]
+    async fn test_cost_field_uses_returned_service_tier() {
+        let mut completion = completion_json();
+        completion["service_tier"] = "flex".into();
+        let mut response = buffered(Json(completion).into_response()).await;
+        attach_cost_if_requested(&mut response, Provider::OpenAI, true);
+        let body = axum::body::to_bytes(response.into_response().into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let standard = UsageLogger::calculate_cost(Provider::OpenAI, "gpt-4o", 1_000_000, 1_000_000);
+        assert_eq!(json["x_webrana_cost_idr"], standard / 2);
+        assert_eq!(json["service_tier"], "flex");
+    }
+
+    #[tokio::test]
+    async fn test_context_headers_for_known_model() {
+        let mut response = buffered(Json(completion_json()).into_response()).await;
+        attach_context_headers(&mut response);
+        let response = response.into_response();
+        assert_eq!(response.headers()[CONTEXT_USED_HEADER], "2000000");
+        assert_eq!(response.headers()[CONTEXT_LIMIT_HEADER], "128000");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), completion_json());
+
+        // Unknown context limit: only the used count
+        let mut unknown = completion_json();
+        unknown["model"] = "qwen-72b-chat".into();
+        let mut response = buffered(Json(unknown).into_response()).await;
+        attach_context_headers(&mut response);
+        assert_eq!(response.parts.headers[CONTEXT_USED_HEADER], "2000000");
+        assert!(response.parts.headers.get(CONTEXT_LIMIT_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_usage_receipt_header_decodes_to_usage() {
+        let mut response = buffered(Json(completion_json()).into_response()).await;
+        attach_usage_receipt(&mut response, Provider::OpenAI, 850);
+        let response = response.into_response();
+        let receipt = UsageReceipt::decode(response.headers()[USAGE_RECEIPT_HEADER].to_str().unwrap()).unwrap();
+
+        assert_eq!(
+            receipt,
+            UsageReceipt {
+                prompt_tokens: 1_000_000,
+                completion_tokens: 1_000_000,
+                total_tokens: 2_000_000,
+                cost_idr: UsageLogger::calculate_cost(Provider::OpenAI, "gpt-4o", 1_000_000, 1_000_000),
+                provider: "openai".to_string(),
+                latency_ms: 850,
+            }
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), completion_json());
+
+        // Error responses carry no receipt
+        let error = proxy_error(StatusCode::BAD_GATEWAY, "boom", "upstream_error", "UPSTREAM");
+        let mut response = buffered(error).await;
+        attach_usage_receipt(&mut response, Provider::OpenAI, 850);
+        assert!(response.parts.headers.get(USAGE_RECEIPT_HEADER).is_none());
     }
 
     // Property Test 5: Model Routing Correctness
@@ -820,4 +3062,703 @@ mod tests {
             assert_eq!(Provider::from_model(model), Some(Provider::Qwen));
         }
     }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_provider_key_header_used_instead_of_stored_key(pool: sqlx::PgPool) {
+        use crate::models::api_key::CreateApiKey;
+        use crate::test_support::{app_state, insert_user};
+
+        let state = app_state(pool.clone());
+        let service = ApiKeyServiceImpl::with_encryption(EncryptionUtils::from_key(&[7u8; 32]).unwrap());
+        let user_id = insert_user(&pool, "byok@example.com", crate::models::PlanTier::Pro).await;
+        service
+            .store_provider_key(
+                &pool,
+                user_id,
+                CreateApiKey {
+                    provider: AiProvider::Openai,
+                    key: "sk-stored-key".to_string(),
+                    name: "default".to_string(),
+                    openai_organization: None,
+                    openai_project: None,
+                    weight: None,
+                    anthropic_beta: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-provider-key", "sk-request-key".parse().unwrap());
+        let mut api_key_user = ApiKeyUser {
+            key_id: uuid::Uuid::new_v4(),
+            user_id,
+            plan: crate::models::PlanTier::Pro,
+            unmetered: false,
+            allow_provider_key: true,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            provider_key: ProviderKeyOverride::from_headers(&headers, Provider::OpenAI, true).unwrap(),
+            output_spend_limit_idr: None,
+        };
+
+        let credentials = provider_credentials(&state, &service, &api_key_user, Provider::OpenAI, None)
+            .await
+            .unwrap();
+        assert_eq!(credentials.api_key, "sk-request-key");
+
+        api_key_user.provider_key = None;
+        let credentials = provider_credentials(&state, &service, &api_key_user, Provider::OpenAI, None)
+            .await
+            .unwrap();
+        assert_eq!(credentials.api_key, "sk-stored-key");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_transcript_stored_only_for_opted_in_keys(pool: sqlx::PgPool) {
+        use crate::test_support::{app_state, insert_user};
+
+        let state = app_state(pool.clone());
+        let encryption = EncryptionUtils::from_key(&[9u8; 32]).unwrap();
+        let user_id = insert_user(&pool, "audit@example.com", crate::models::PlanTier::Pro).await;
+        let key_id: uuid::Uuid = sqlx::query_scalar(
+            "INSERT INTO proxy_api_keys (user_id, key_hash, key_prefix, name) VALUES ($1, 'hash', 'wbr_test', 'audit') RETURNING id",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [{ "role": "user", "content": "Audit me" }]
+        }))
+        .unwrap();
+        let content_logging = TranscriptConfig::from_lookup(|key| (key == "LOG_REQUEST_CONTENT").then(|| "true".to_string()));
+        let mut api_key_user = ApiKeyUser {
+            key_id,
+            user_id,
+            plan: crate::models::PlanTier::Pro,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+
+        // Keys don't store transcripts by default, nor without content logging
+        assert!(transcript_for(&content_logging, &api_key_user, Provider::OpenAI, &body).is_none());
+        api_key_user.store_transcripts = true;
+        assert!(transcript_for(&TranscriptConfig::default(), &api_key_user, Provider::OpenAI, &body).is_none());
+
+        let transcript = transcript_for(&content_logging, &api_key_user, Provider::OpenAI, &body).unwrap();
+        record_transcript(&state, &encryption, transcript, StatusCode::OK, Some(&completion_json()));
+
+        // Stored in the background
+        let mut stored = Vec::new();
+        for _ in 0..50 {
+            stored = transcripts::list_for_user(&pool, user_id).await.unwrap();
+            if !stored.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(stored.len(), 1);
+        let transcript = transcripts::get(&pool, &encryption, stored[0].id, Some(user_id)).await.unwrap().unwrap();
+        assert_eq!(transcript.messages[0]["content"], "Audit me");
+        assert_eq!(transcript.response, Some(completion_json()));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_request_id_surfaced_to_client() {
+        let app = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::get(|| async {
+                ([("x-request-id", "req_upstream_42")], Json(completion_json()))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let upstream = reqwest::get(format!("http://{}/v1/chat/completions", addr)).await.unwrap();
+        let api_key_user = ApiKeyUser {
+            key_id: uuid::Uuid::new_v4(),
+            user_id: uuid::Uuid::new_v4(),
+            plan: crate::models::PlanTier::Free,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "trace-7".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+
+        let upstream_id = log_upstream_request_id(Provider::OpenAI, &api_key_user, &upstream);
+        let response = with_upstream_request_id(forward_response(upstream).await, upstream_id);
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(UPSTREAM_REQUEST_ID_HEADER).unwrap(),
+            "req_upstream_42"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["id"], "chatcmpl-1");
+    }
+
+    #[tokio::test]
+    async fn test_qwen_body_request_id_surfaced_to_client() {
+        let qwen_resp: crate::services::transformers::qwen::QwenResponse = serde_json::from_value(serde_json::json!({
+            "output": {
+                "choices": [{ "finish_reason": "stop", "message": { "role": "assistant", "content": "Halo" } }]
+            },
+            "usage": { "input_tokens": 3, "output_tokens": 1 },
+            "request_id": "d3b5c3a1-6f0e-9c8e-8f3a-2b7e4c1d0a99"
+        }))
+        .unwrap();
+
+        let response = qwen_completion_response(qwen_resp, "qwen-turbo");
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(UPSTREAM_REQUEST_ID_HEADER).unwrap(),
+            "d3b5c3a1-6f0e-9c8e-8f3a-2b7e4c1d0a99"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["choices"][0]["message"]["content"], "Halo");
+        // The id travels in the header only, never in the OpenAI-format body
+        assert!(json.get("provider_request_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_gzip_upstream_response_forwarded_decoded() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(completion_json().to_string().as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let app = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::get(move || async move {
+                (
+                    [
+                        (header::CONTENT_TYPE, "application/json"),
+                        (header::CONTENT_ENCODING, "gzip"),
+                    ],
+                    gzipped,
+                )
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = crate::services::upstream_client::UpstreamClientConfig::from_lookup(|_| None).build();
+        let upstream = client
+            .get(format!("http://{}/v1/chat/completions", addr))
+            .send()
+            .await
+            .unwrap();
+        let response = forward_response(upstream).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).expect("client receives valid JSON");
+        assert_eq!(json, completion_json());
+    }
+
+    #[tokio::test]
+    async fn test_identical_request_flood_puts_key_on_cooldown() {
+        use crate::services::abuse_detector::{AbuseDetector, AbuseDetectorConfig};
+
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://postgres@127.0.0.1:1/webrana")
+            .unwrap();
+        let state = Arc::new(AppState {
+            abuse_detector: AbuseDetector::new(Some(AbuseDetectorConfig {
+                max_identical: 2,
+                window: std::time::Duration::from_secs(60),
+                cooldown: std::time::Duration::from_secs(120),
+            })),
+            ..Arc::unwrap_or_clone(crate::test_support::app_state(pool))
+        });
+        let api_key_user = ApiKeyUser {
+            key_id: uuid::Uuid::new_v4(),
+            user_id: uuid::Uuid::new_v4(),
+            plan: crate::models::PlanTier::Pro,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+        // An unroutable model fails fast without touching the database
+        let send = || {
+            let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+                "model": "no-such-model",
+                "messages": [{ "role": "user", "content": "x".repeat(10_000) }]
+            }))
+            .unwrap();
+            chat_completions(
+                Extension(state.clone()),
+                Extension(api_key_user.clone()),
+                None,
+                HeaderMap::new(),
+                Json(body),
+            )
+        };
+
+        for _ in 0..2 {
+            assert_eq!(send().await.into_response().status(), StatusCode::BAD_REQUEST);
+        }
+        let response = send().await.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "120");
+        assert_eq!(error_json(response).await["error"]["code"], "IDENTICAL_REQUEST_COOLDOWN");
+    }
+
+    #[tokio::test]
+    async fn test_prefixed_model_checked_as_bare_model() {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://postgres@127.0.0.1:1/webrana")
+            .unwrap();
+        let api_key_user = ApiKeyUser {
+            key_id: uuid::Uuid::new_v4(),
+            user_id: uuid::Uuid::new_v4(),
+            plan: crate::models::PlanTier::Free,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "anthropic/claude-3-opus-20240229",
+            "messages": [{ "role": "user", "content": "Hello" }]
+        }))
+        .unwrap();
+
+        // Routed to Anthropic and checked against the plan without the prefix
+        let response = chat_completions(
+            Extension(crate::test_support::app_state(pool)),
+            Extension(api_key_user),
+            None,
+            HeaderMap::new(),
+            Json(body),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            error_json(response).await["error"]["message"],
+            "Model claude-3-opus-20240229 is not available on the free plan"
+        );
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_request_over_remaining_spend_is_pre_rejected(pool: sqlx::PgPool) {
+        use crate::test_support::{app_state, insert_user};
+
+        let state = app_state(pool.clone());
+        let user_id = insert_user(&pool, "spend@example.com", crate::models::PlanTier::Pro).await;
+        sqlx::query("UPDATE users SET monthly_spend_limit_idr = 100 WHERE id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let api_key_user = ApiKeyUser {
+            key_id: uuid::Uuid::new_v4(),
+            user_id,
+            plan: crate::models::PlanTier::Pro,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [{ "role": "user", "content": "x".repeat(200_000) }],
+            "max_tokens": 16000
+        }))
+        .unwrap();
+
+        let response = chat_completions(
+            Extension(state),
+            Extension(api_key_user),
+            None,
+            HeaderMap::new(),
+            Json(body),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], "INSUFFICIENT_QUOTA");
+        assert_eq!(json["error"]["type"], "insufficient_quota");
+    }
+
+    #[test]
+    fn test_model_list_only_has_given_providers() {
+        let list = serde_json::to_value(model_list(&[Provider::Qwen, Provider::OpenAI])).unwrap();
+        assert_eq!(list["object"], "list");
+
+        let data = list["data"].as_array().unwrap();
+        assert_eq!(data.len(), Provider::OpenAI.base_models().len() + Provider::Qwen.base_models().len());
+        assert_eq!(data[0]["id"], "gpt-4o");
+        assert_eq!(data[0]["object"], "model");
+        assert_eq!(data[0]["owned_by"], "OpenAI");
+        assert!(data.iter().any(|model| model["id"] == "qwen-turbo" && model["owned_by"] == "Qwen"));
+        assert!(data.iter().all(|model| model["owned_by"] == "OpenAI" || model["owned_by"] == "Qwen"));
+
+        assert!(model_list(&[]).data.is_empty());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_models_listed_for_configured_providers(pool: sqlx::PgPool) {
+        use crate::test_support::{app_state, insert_user};
+
+        let user_id = insert_user(&pool, "models@example.com", crate::models::PlanTier::Pro).await;
+        let keys = [(AiProvider::Anthropic, true), (AiProvider::Google, false)];
+        for (provider, is_active) in keys {
+            sqlx::query(
+                "INSERT INTO api_keys (user_id, provider, key_name, encrypted_key, iv, auth_tag, is_active) VALUES ($1, $2, 'default', '\\x00', '\\x00', '\\x00', $3)",
+            )
+            .bind(user_id)
+            .bind(provider)
+            .bind(is_active)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let api_key_user = ApiKeyUser {
+            key_id: uuid::Uuid::new_v4(),
+            user_id,
+            plan: crate::models::PlanTier::Pro,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+
+        let response = list_models(Extension(app_state(pool)), Extension(api_key_user)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let list: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let data = list["data"].as_array().unwrap();
+        assert_eq!(data.len(), Provider::Anthropic.base_models().len());
+        assert!(data.iter().all(|model| model["owned_by"] == "Anthropic"));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_completion_usage_logged_for_key(pool: sqlx::PgPool) {
+        use crate::test_support::{app_state, insert_user};
+
+        let state = app_state(pool.clone());
+        let user_id = insert_user(&pool, "usage@example.com", crate::models::PlanTier::Pro).await;
+        let key_id: uuid::Uuid = sqlx::query_scalar(
+            "INSERT INTO proxy_api_keys (user_id, key_hash, key_prefix, name) VALUES ($1, 'hash', 'wbr_test', 'usage') RETURNING id",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let api_key_user = ApiKeyUser {
+            key_id,
+            user_id,
+            plan: crate::models::PlanTier::Pro,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Hello" }]
+        }))
+        .unwrap();
+
+        let response = with_upstream_request_id(Json(completion_json()).into_response(), Some("req_abc".to_string()));
+        let response = log_completion_usage(
+            &state,
+            &api_key_user,
+            Provider::OpenAI,
+            &body,
+            std::time::Instant::now(),
+            response,
+            UsageLogging::Log,
+        )
+        .await;
+
+        // The client still gets the completion untouched
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&bytes).unwrap(), completion_json());
+
+        state.tasks.shutdown(std::time::Duration::from_secs(5)).await;
+        let (proxy_key_id, total_tokens, cost, provider_request_id): (Option<uuid::Uuid>, i32, i64, Option<String>) =
+            sqlx::query_as(
+                "SELECT proxy_key_id, total_tokens, estimated_cost_idr, provider_request_id FROM proxy_requests WHERE user_id = $1",
+            )
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(proxy_key_id, Some(key_id));
+        assert_eq!(total_tokens, 2_000_000);
+        assert_eq!(cost, UsageLogger::calculate_cost(Provider::OpenAI, "gpt-4o", 1_000_000, 1_000_000));
+        assert_eq!(provider_request_id.as_deref(), Some("req_abc"));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_mirrored_request_logs_one_usage_row(pool: sqlx::PgPool) {
+        use crate::test_support::{app_state, insert_user};
+
+        let state = app_state(pool.clone());
+        let user_id = insert_user(&pool, "mirror@example.com", crate::models::PlanTier::Pro).await;
+        let key_id: uuid::Uuid = sqlx::query_scalar(
+            "INSERT INTO proxy_api_keys (user_id, key_hash, key_prefix, name) VALUES ($1, 'hash', 'wbr_test', 'mirror') RETURNING id",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let api_key_user = ApiKeyUser {
+            key_id,
+            user_id,
+            plan: crate::models::PlanTier::Pro,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Hello" }]
+        }))
+        .unwrap();
+        let mut mirror_body = body.clone();
+        mirror_body.model = "gpt-4o-mini".to_string();
+
+        // The primary completion is the user's; its mirror is not
+        for (body, logging) in [(&body, UsageLogging::Log), (&mirror_body, UsageLogging::Skip)] {
+            let response = log_completion_usage(
+                &state,
+                &api_key_user,
+                Provider::OpenAI,
+                body,
+                std::time::Instant::now(),
+                Json(completion_json()).into_response(),
+                logging,
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        state.tasks.shutdown(std::time::Duration::from_secs(5)).await;
+        let models: Vec<String> = sqlx::query_scalar("SELECT model FROM proxy_requests WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(models, ["gpt-4o"]);
+    }
+
+    #[test]
+    fn test_completion_tokens_estimated_without_usage() {
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "qwen-turbo",
+            "messages": [{ "role": "user", "content": "Hello there" }]
+        }))
+        .unwrap();
+        let mut completion = completion_json();
+        completion.as_object_mut().unwrap().remove("usage");
+        completion["choices"][0]["message"]["content"] = serde_json::json!("General Kenobi");
+
+        let messages: Vec<crate::services::transformers::Message> = body.messages.iter().cloned().map(Into::into).collect();
+        assert_eq!(
+            estimate_completion_tokens(&body, &completion),
+            (TokenCounter::count_message_tokens(&messages), TokenCounter::estimate_tokens("General Kenobi"))
+        );
+    }
+
+    #[test]
+    fn test_unparsed_response_usage_from_headers() {
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-3-haiku-20240307",
+            "messages": [{ "role": "user", "content": "Hello there" }]
+        }))
+        .unwrap();
+        let raw = br#"{"content": "truncated"#;
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-usage-input-tokens", "120".parse().unwrap());
+        headers.insert("x-usage-output-tokens", "45".parse().unwrap());
+        let PartialUsage(usage) = unparsed_response_usage(Provider::Anthropic, &body, &headers, raw);
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (120, 45, 165));
+
+        // Without usage headers the tokens are estimated
+        let PartialUsage(usage) = unparsed_response_usage(Provider::Anthropic, &body, &Default::default(), raw);
+        assert_eq!(usage.prompt_tokens, estimated_prompt_tokens(&body));
+        assert_eq!(usage.completion_tokens, TokenCounter::estimate_tokens(r#"{"content": "truncated"#));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_usage_logged_when_body_parse_fails(pool: sqlx::PgPool) {
+        use crate::test_support::{app_state, insert_user};
+
+        let state = app_state(pool.clone());
+        let user_id = insert_user(&pool, "partial@example.com", crate::models::PlanTier::Pro).await;
+        let key_id: uuid::Uuid = sqlx::query_scalar(
+            "INSERT INTO proxy_api_keys (user_id, key_hash, key_prefix, name) VALUES ($1, 'hash', 'wbr_test', 'partial') RETURNING id",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let api_key_user = ApiKeyUser {
+            key_id,
+            user_id,
+            plan: crate::models::PlanTier::Pro,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+        let body: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "qwen-turbo",
+            "messages": [{ "role": "user", "content": "Hello" }]
+        }))
+        .unwrap();
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-usage-prompt-tokens", "300".parse().unwrap());
+        headers.insert("x-usage-completion-tokens", "700".parse().unwrap());
+        let usage = unparsed_response_usage(Provider::Qwen, &body, &headers, b"{\"output\": ");
+        let response = parse_error("Qwen", "QWEN_PARSE_ERROR", usage);
+        let response = log_completion_usage(
+            &state,
+            &api_key_user,
+            Provider::Qwen,
+            &body,
+            std::time::Instant::now(),
+            response,
+            UsageLogging::Log,
+        )
+        .await;
+
+        // The client still gets the parse error
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+        state.tasks.shutdown(std::time::Duration::from_secs(5)).await;
+        let (status_code, prompt_tokens, completion_tokens, cost): (i32, i32, i32, i64) = sqlx::query_as(
+            "SELECT status_code::int, prompt_tokens, completion_tokens, estimated_cost_idr FROM proxy_requests WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(status_code, 502);
+        assert_eq!((prompt_tokens, completion_tokens), (300, 700));
+        assert_eq!(cost, UsageLogger::calculate_cost(Provider::Qwen, "qwen-turbo", 300, 700));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_forwarded_upstream_400_is_recorded(pool: sqlx::PgPool) {
+        use crate::test_support::{app_state, insert_user};
+
+        let app = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::get(|| async {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({
+                        "error": {
+                            "message": "Incorrect API key provided: sk-proj-secret123",
+                            "type": "invalid_request_error",
+                            "code": "invalid_api_key"
+                        }
+                    })),
+                )
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let state = app_state(pool.clone());
+        let user_id = insert_user(&pool, "errors@example.com", crate::models::PlanTier::Pro).await;
+        let key_id: uuid::Uuid = sqlx::query_scalar(
+            "INSERT INTO proxy_api_keys (user_id, key_hash, key_prefix, name) VALUES ($1, 'hash', 'wbr_test', 'errors') RETURNING id",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let api_key_user = ApiKeyUser {
+            key_id,
+            user_id,
+            plan: crate::models::PlanTier::Pro,
+            unmetered: false,
+            allow_provider_key: false,
+            provider_key: None,
+            request_id: "req-1".to_string(),
+            store_transcripts: false,
+            output_spend_limit_idr: None,
+        };
+
+        let upstream = reqwest::get(format!("http://{}/v1/chat/completions", addr)).await.unwrap();
+        let response = forward_upstream_error(&state, &api_key_user, Provider::OpenAI, "gpt-4o-mini", upstream).await;
+
+        // The client still sees the upstream error unchanged
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], "invalid_api_key");
+
+        // Recording happens in the background
+        let mut errors = Vec::new();
+        for _ in 0..50 {
+            errors = provider_errors::list_recent(&pool, user_id, 10).await.unwrap();
+            if !errors.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+        assert_eq!(error.proxy_key_id, Some(key_id));
+        assert_eq!(error.provider, "openai");
+        assert_eq!(error.model, "gpt-4o-mini");
+        assert_eq!(error.status_code, 400);
+        assert_eq!(error.error_code.as_deref(), Some("invalid_api_key"));
+        assert_eq!(error.message, "Incorrect API key provided: [redacted]");
+    }
 }
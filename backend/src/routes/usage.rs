@@ -1,21 +1,33 @@
 use axum::{
-    extract::{Query, State},
+    extract::{Extension, Path, Query, State},
     http::StatusCode,
-    response::{IntoResponse, Response},
+    response::{
+        sse::{Event, KeepAlive},
+        IntoResponse, Response, Sse,
+    },
     routing::get,
     Json, Router,
 };
 use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::middleware::auth::AuthUser;
 use crate::services::usage_analytics::{
-    DateRange, DailyUsage, ModelUsage, ProviderUsage, UsageAnalyticsService, UsageStats,
+    DateRange, DailyUsage, MetadataUsage, ModelUsage, ProviderUsage, UsageAnalyticsService, UsageStats,
 };
 
 // Re-export for main.rs
 pub use crate::services::usage_analytics::UsageAnalyticsService as _;
+use crate::services::transcripts::{self, StoredTranscript, TranscriptSummary};
+use crate::services::usage_events::{self, UsageEvent, REQUEST_COMPLETED_EVENT};
+use crate::services::usage_retention::{self, PurgeResult};
+use crate::utils::encryption::EncryptionUtils;
+use crate::services::usage_webhook::{self, WebhookError, USAGE_THRESHOLDS};
+use crate::AppState;
 
 /// Query parameters for usage endpoints
 #[derive(Debug, Deserialize)]
@@ -42,6 +54,17 @@ impl UsageQuery {
     }
 }
 
+/// Query parameters for usage grouped by a metadata key
+#[derive(Debug, Deserialize)]
+pub struct MetadataUsageQuery {
+    /// Metadata key to group by
+    pub key: String,
+    /// Only requests where the key has this value
+    pub value: Option<String>,
+    #[serde(flatten)]
+    pub range: UsageQuery,
+}
+
 /// Combined usage response
 #[derive(Debug, Serialize)]
 pub struct UsageResponse {
@@ -51,15 +74,39 @@ pub struct UsageResponse {
     pub daily: Vec<DailyUsage>,
 }
 
+/// Request to set the usage threshold webhook
+#[derive(Debug, Deserialize)]
+pub struct SetUsageWebhookRequest {
+    pub url: String,
+}
+
+/// Usage threshold webhook configuration
+#[derive(Debug, Serialize)]
+pub struct UsageWebhookResponse {
+    pub url: String,
+    /// Signing secret, only returned when the webhook is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub thresholds: [u8; 3],
+}
+
 /// Create usage routes
 pub fn usage_routes() -> Router<PgPool> {
     Router::new()
-        .route("/", get(get_usage))
+        .route("/", get(get_usage).delete(purge_usage))
         .route("/stats", get(get_usage_stats))
         .route("/by-provider", get(get_usage_by_provider))
         .route("/by-model", get(get_usage_by_model))
+        .route("/by-metadata", get(get_usage_by_metadata))
         .route("/daily", get(get_daily_usage))
         .route("/export", get(export_csv))
+        .route(
+            "/webhook",
+            get(get_usage_webhook).put(set_usage_webhook).delete(delete_usage_webhook),
+        )
+        .route("/transcripts", get(get_transcripts))
+        .route("/transcripts/:id", get(get_transcript))
+        .route("/stream", get(stream_usage))
 }
 
 
@@ -154,6 +201,26 @@ async fn get_usage_by_model(
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
+/// Get the caller's usage grouped by a request metadata key
+/// GET /usage/by-metadata?key=feature
+async fn get_usage_by_metadata(
+    State(pool): State<PgPool>,
+    Extension(auth_user): Extension<AuthUser>,
+    Query(query): Query<MetadataUsageQuery>,
+) -> Result<Json<Vec<MetadataUsage>>, StatusCode> {
+    let service = UsageAnalyticsService::new(pool);
+    let range = query.range.to_date_range();
+
+    service
+        .get_usage_by_metadata(auth_user.user_id, &range, &query.key, query.value.as_deref())
+        .await
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!(user_id = %auth_user.user_id, "Failed to group usage by metadata: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
 /// Get daily usage
 /// GET /usage/daily
 async fn get_daily_usage(
@@ -196,3 +263,234 @@ async fn export_csv(
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
+
+/// Purge the caller's request history, keeping monthly billing aggregates
+/// DELETE /usage
+async fn purge_usage(
+    State(pool): State<PgPool>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<PurgeResult>, StatusCode> {
+    usage_retention::purge_user(&pool, auth_user.user_id)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!(user_id = %auth_user.user_id, "Failed to purge usage: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Get the caller's usage threshold webhook
+/// GET /usage/webhook
+async fn get_usage_webhook(
+    State(pool): State<PgPool>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<UsageWebhookResponse>, StatusCode> {
+    match usage_webhook::get_webhook(&pool, auth_user.user_id).await {
+        Ok(Some(webhook)) => Ok(Json(UsageWebhookResponse {
+            url: webhook.url,
+            secret: None,
+            thresholds: USAGE_THRESHOLDS,
+        })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!(user_id = %auth_user.user_id, "Failed to load usage webhook: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Set the caller's usage threshold webhook; returns a new signing secret
+/// PUT /usage/webhook
+async fn set_usage_webhook(
+    State(pool): State<PgPool>,
+    Extension(auth_user): Extension<AuthUser>,
+    Json(request): Json<SetUsageWebhookRequest>,
+) -> Result<Json<UsageWebhookResponse>, StatusCode> {
+    match usage_webhook::set_webhook(&pool, auth_user.user_id, request.url.trim()).await {
+        Ok(webhook) => Ok(Json(UsageWebhookResponse {
+            url: webhook.url,
+            secret: Some(webhook.secret),
+            thresholds: USAGE_THRESHOLDS,
+        })),
+        Err(WebhookError::InvalidUrl(_)) => Err(StatusCode::BAD_REQUEST),
+        Err(e) => {
+            tracing::error!(user_id = %auth_user.user_id, "Failed to set usage webhook: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Remove the caller's usage threshold webhook
+/// DELETE /usage/webhook
+async fn delete_usage_webhook(
+    State(pool): State<PgPool>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> StatusCode {
+    match usage_webhook::delete_webhook(&pool, auth_user.user_id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            tracing::error!(user_id = %auth_user.user_id, "Failed to delete usage webhook: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// List the caller's audit transcripts
+/// GET /usage/transcripts
+async fn get_transcripts(
+    State(pool): State<PgPool>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Result<Json<Vec<TranscriptSummary>>, StatusCode> {
+    transcripts::list_for_user(&pool, auth_user.user_id)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!(user_id = %auth_user.user_id, "Failed to list transcripts: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Get one of the caller's audit transcripts, decrypted
+/// GET /usage/transcripts/:id
+async fn get_transcript(
+    State(pool): State<PgPool>,
+    Extension(auth_user): Extension<AuthUser>,
+    Path(transcript_id): Path<Uuid>,
+) -> Result<Json<StoredTranscript>, StatusCode> {
+    let encryption = EncryptionUtils::from_env().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    match transcripts::get(&pool, &encryption, transcript_id, Some(auth_user.user_id)).await {
+        Ok(Some(transcript)) => Ok(Json(transcript)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!(user_id = %auth_user.user_id, "Failed to load transcript: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Live feed of the caller's usage as server-sent `request_completed` events.
+/// The Redis subscription is dropped with the stream when the client
+/// disconnects.
+/// GET /usage/stream
+async fn stream_usage(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(auth_user): Extension<AuthUser>,
+) -> Response {
+    match usage_events::subscribe(&state.redis, auth_user.user_id).await {
+        Ok(events) => usage_event_stream(events).into_response(),
+        Err(e) => {
+            tracing::error!(user_id = %auth_user.user_id, "Failed to subscribe to usage events: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        }
+    }
+}
+
+fn usage_event_stream<S>(events: S) -> Sse<impl Stream<Item = Result<Event, axum::Error>>>
+where
+    S: Stream<Item = UsageEvent> + Send + 'static,
+{
+    let events = events.map(|event| Event::default().event(REQUEST_COMPLETED_EVENT).json_data(event));
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::transformers::Provider;
+    use crate::services::usage_logger::{UsageLog, UsageLogger};
+    use sqlx::postgres::PgPoolOptions;
+
+    fn auth_user() -> AuthUser {
+        AuthUser {
+            user_id: Uuid::new_v4(),
+            email: "dash@example.com".to_string(),
+            plan: "pro".to_string(),
+        }
+    }
+
+    /// Pool that fails fast, so usage logs fall through to Redis immediately
+    fn unreachable_pool() -> PgPool {
+        PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(100))
+            .connect_lazy("postgres://postgres@127.0.0.1:1/webrana")
+            .unwrap()
+    }
+
+    async fn serve(state: Arc<AppState>, user: AuthUser) -> String {
+        let app = Router::new()
+            .route("/stream", get(stream_usage))
+            .layer(Extension(user))
+            .layer(Extension(state));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/stream", addr)
+    }
+
+    #[tokio::test]
+    async fn test_stream_unavailable_without_redis() {
+        let state = crate::test_support::app_state(unreachable_pool());
+        let url = serve(state, auth_user()).await;
+
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    // Redis-backed tests; run with REDIS_URL set and `--ignored`
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_URL"]
+    async fn test_logged_usage_delivered_over_sse() {
+        let redis = redis::Client::open(std::env::var("REDIS_URL").expect("REDIS_URL")).unwrap();
+        let state = Arc::new(AppState {
+            redis: redis.clone(),
+            ..Arc::unwrap_or_clone(crate::test_support::app_state(unreachable_pool()))
+        });
+        let user = auth_user();
+        let user_id = user.user_id;
+        let url = serve(state.clone(), user).await;
+
+        // Headers arrive once the subscription is active
+        let mut response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+        UsageLogger::log_async(
+            &state.tasks,
+            unreachable_pool(),
+            redis,
+            UsageLog {
+                user_id,
+                proxy_key_id: None,
+                provider: Provider::OpenAI,
+                model: "gpt-4o-mini".to_string(),
+                prompt_tokens: 40,
+                completion_tokens: 60,
+                total_tokens: 100,
+                latency_ms: 300,
+                estimated_cost_idr: 7,
+                status_code: 200,
+                error_message: None,
+                is_internal: false,
+                metadata: None,
+                provider_request_id: None,
+            },
+        );
+
+        let mut received = String::new();
+        while !received.contains("}\n\n") {
+            let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), response.chunk())
+                .await
+                .unwrap()
+                .unwrap()
+                .expect("stream ended");
+            received.push_str(&String::from_utf8_lossy(&chunk));
+        }
+        assert!(received.contains("event: request_completed"));
+        assert!(received.contains("\"model\":\"gpt-4o-mini\""));
+        assert!(received.contains("\"total_tokens\":100"));
+        assert!(received.contains("\"estimated_cost_idr\":7"));
+    }
+}
@@ -0,0 +1,231 @@
+//! Detection of identical requests replayed in a loop.
+//!
+//! Each chat completion body is hashed; when one proxy key submits the same
+//! fingerprint more than the configured number of times within the window,
+//! the key is put on a temporary cooldown and a security event is logged.
+//! Counts are kept in memory per instance. Off unless configured.
+//!
+//! Configuration:
+//! - `ABUSE_MAX_IDENTICAL_REQUESTS`: identical requests allowed per window (enables detection)
+//! - `ABUSE_WINDOW_SECS`: window for counting repeats (default `60`)
+//! - `ABUSE_COOLDOWN_SECS`: how long a flagged key is refused (default `300`)
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Default window for counting repeats
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Default cooldown for a flagged key
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Tracked fingerprints before stale entries are swept
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Abuse detection thresholds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbuseDetectorConfig {
+    pub max_identical: u32,
+    pub window: Duration,
+    pub cooldown: Duration,
+}
+
+impl AbuseDetectorConfig {
+    /// Load thresholds from environment variables (`None` when disabled)
+    pub fn from_env() -> Option<Self> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load thresholds using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Option<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let read = |key: &str| lookup(key).and_then(|v| v.trim().parse::<u64>().ok()).filter(|v| *v > 0);
+
+        let max_identical = read("ABUSE_MAX_IDENTICAL_REQUESTS")?;
+        Some(Self {
+            max_identical: u32::try_from(max_identical).unwrap_or(u32::MAX),
+            window: read("ABUSE_WINDOW_SECS").map(Duration::from_secs).unwrap_or(DEFAULT_WINDOW),
+            cooldown: read("ABUSE_COOLDOWN_SECS").map(Duration::from_secs).unwrap_or(DEFAULT_COOLDOWN),
+        })
+    }
+}
+
+/// Key is cooling down after submitting too many identical requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Too many identical requests from this key; retry in {retry_after_secs}s")]
+pub struct KeyCoolingDown {
+    pub retry_after_secs: u64,
+}
+
+#[derive(Debug, Default)]
+struct Tracker {
+    /// Recent submission times per key and request fingerprint
+    seen: HashMap<(Uuid, [u8; 32]), VecDeque<Instant>>,
+    /// Keys refused until the given instant
+    cooldowns: HashMap<Uuid, Instant>,
+}
+
+/// Per-key identical request detector shared by all requests
+#[derive(Debug, Clone, Default)]
+pub struct AbuseDetector {
+    config: Option<AbuseDetectorConfig>,
+    tracker: Arc<Mutex<Tracker>>,
+}
+
+impl AbuseDetector {
+    /// Create a detector; `None` disables detection
+    pub fn new(config: Option<AbuseDetectorConfig>) -> Self {
+        Self {
+            config,
+            tracker: Arc::default(),
+        }
+    }
+
+    /// Load the detector from environment variables
+    pub fn from_env() -> Self {
+        Self::new(AbuseDetectorConfig::from_env())
+    }
+
+    /// Record a request body from a key, refusing it while the key cools down
+    pub fn check(&self, key_id: Uuid, body: &[u8]) -> Result<(), KeyCoolingDown> {
+        self.check_at(key_id, body, Instant::now())
+    }
+
+    /// `check` at a given instant
+    pub fn check_at(&self, key_id: Uuid, body: &[u8], now: Instant) -> Result<(), KeyCoolingDown> {
+        let Some(config) = self.config else {
+            return Ok(());
+        };
+        let mut tracker = self.tracker.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(until) = tracker.cooldowns.get(&key_id).copied() {
+            if now < until {
+                return Err(KeyCoolingDown {
+                    retry_after_secs: until.duration_since(now).as_secs().max(1),
+                });
+            }
+            tracker.cooldowns.remove(&key_id);
+        }
+
+        if tracker.seen.len() > SWEEP_THRESHOLD {
+            tracker.seen.retain(|_, times| {
+                times.back().is_some_and(|last| now.duration_since(*last) < config.window)
+            });
+        }
+
+        let fingerprint: [u8; 32] = Sha256::digest(body).into();
+        let times = tracker.seen.entry((key_id, fingerprint)).or_default();
+        while times.front().is_some_and(|first| now.duration_since(*first) >= config.window) {
+            times.pop_front();
+        }
+        times.push_back(now);
+
+        let repeats = times.len();
+        if repeats <= config.max_identical as usize {
+            return Ok(());
+        }
+
+        tracker.seen.remove(&(key_id, fingerprint));
+        tracker.cooldowns.insert(key_id, now + config.cooldown);
+        tracing::warn!(
+            target: "security",
+            key_id = %key_id,
+            fingerprint = %hex_prefix(&fingerprint),
+            repeats,
+            window_secs = config.window.as_secs(),
+            cooldown_secs = config.cooldown.as_secs(),
+            "Identical request flood detected, key cooling down"
+        );
+        Err(KeyCoolingDown {
+            retry_after_secs: config.cooldown.as_secs().max(1),
+        })
+    }
+}
+
+/// First bytes of a fingerprint as hex, enough to correlate log lines
+fn hex_prefix(fingerprint: &[u8; 32]) -> String {
+    fingerprint[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector(max_identical: u32) -> AbuseDetector {
+        AbuseDetector::new(Some(AbuseDetectorConfig {
+            max_identical,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(300),
+        }))
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        assert_eq!(AbuseDetectorConfig::from_lookup(|_| None), None);
+
+        let detector = AbuseDetector::default();
+        let key = Uuid::new_v4();
+        for _ in 0..1000 {
+            assert!(detector.check(key, b"same").is_ok());
+        }
+    }
+
+    #[test]
+    fn test_config_from_lookup() {
+        let config = AbuseDetectorConfig::from_lookup(|key| match key {
+            "ABUSE_MAX_IDENTICAL_REQUESTS" => Some("20".to_string()),
+            "ABUSE_COOLDOWN_SECS" => Some("30".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(config.max_identical, 20);
+        assert_eq!(config.window, DEFAULT_WINDOW);
+        assert_eq!(config.cooldown, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_rapid_identical_requests_trigger_cooldown() {
+        let detector = detector(3);
+        let key = Uuid::new_v4();
+        let start = Instant::now();
+
+        for i in 0..3 {
+            assert!(detector.check_at(key, b"huge prompt", start + Duration::from_millis(i)).is_ok());
+        }
+        let err = detector.check_at(key, b"huge prompt", start + Duration::from_millis(3)).unwrap_err();
+        assert_eq!(err.retry_after_secs, 300);
+
+        // The whole key is refused during the cooldown, even for new requests
+        let err = detector.check_at(key, b"different", start + Duration::from_secs(100)).unwrap_err();
+        assert_eq!(err.retry_after_secs, 200);
+
+        // Other keys are unaffected
+        assert!(detector.check_at(Uuid::new_v4(), b"huge prompt", start).is_ok());
+
+        // Cooldown over
+        assert!(detector.check_at(key, b"huge prompt", start + Duration::from_secs(301)).is_ok());
+    }
+
+    #[test]
+    fn test_repeats_outside_window_or_varied_requests_allowed() {
+        let detector = detector(2);
+        let key = Uuid::new_v4();
+        let start = Instant::now();
+
+        // Same request, spaced beyond the window
+        for i in 0..5 {
+            assert!(detector.check_at(key, b"poll", start + Duration::from_secs(61 * i)).is_ok());
+        }
+
+        // Many different requests in quick succession
+        for i in 0..50u32 {
+            assert!(detector.check_at(key, &i.to_be_bytes(), start).is_ok());
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Anthropic API version and beta feature headers.
+//!
+//! The `anthropic-version` sent upstream is configurable so newer API
+//! features can be enabled without a code change. Optional `anthropic-beta`
+//! flags come from configuration and from individual provider keys, and are
+//! only sent when they are on the allowlist.
+//!
+//! Configuration:
+//! - `ANTHROPIC_VERSION`: API version date, `YYYY-MM-DD` (default `2023-06-01`)
+//! - `ANTHROPIC_BETA`: comma-separated beta flags sent on every request (default none)
+
+/// Default `anthropic-version` header
+pub const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Beta flags that may be forwarded in `anthropic-beta`
+pub const ANTHROPIC_BETA_ALLOWLIST: &[&str] = &[
+    "prompt-caching-2024-07-31",
+    "max-tokens-3-5-sonnet-2024-07-15",
+    "message-batches-2024-09-24",
+    "pdfs-2024-09-25",
+    "computer-use-2024-10-22",
+    "token-counting-2024-11-01",
+];
+
+/// Anthropic header configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnthropicHeaderConfig {
+    pub version: String,
+    pub beta: Vec<String>,
+}
+
+impl Default for AnthropicHeaderConfig {
+    fn default() -> Self {
+        Self {
+            version: DEFAULT_ANTHROPIC_VERSION.to_string(),
+            beta: Vec::new(),
+        }
+    }
+}
+
+impl AnthropicHeaderConfig {
+    /// Load configuration from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load configuration using a custom variable lookup.
+    /// Invalid values are logged and ignored.
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let defaults = Self::default();
+
+        let version = match lookup("ANTHROPIC_VERSION").map(|v| v.trim().to_string()) {
+            Some(version) if is_valid_version(&version) => version,
+            Some(version) => {
+                tracing::warn!(%version, "Ignoring invalid ANTHROPIC_VERSION");
+                defaults.version
+            }
+            None => defaults.version,
+        };
+
+        let beta = match lookup("ANTHROPIC_BETA").map(|v| parse_beta_flags(&v)) {
+            Some(Ok(flags)) => flags,
+            Some(Err(e)) => {
+                tracing::warn!("Ignoring ANTHROPIC_BETA: {}", e);
+                defaults.beta
+            }
+            None => defaults.beta,
+        };
+
+        Self { version, beta }
+    }
+
+    /// Headers for an upstream request, merging configured and per-key beta flags
+    pub fn headers(&self, key_beta: Option<&str>) -> Vec<(&'static str, String)> {
+        let mut flags = self.beta.clone();
+        // Stored values were validated on write; drop anything no longer allowed
+        for flag in key_beta.map(split_flags).into_iter().flatten() {
+            if is_allowed_beta(flag) && !flags.iter().any(|f| f == flag) {
+                flags.push(flag.to_string());
+            }
+        }
+
+        let mut headers = vec![("anthropic-version", self.version.clone())];
+        if !flags.is_empty() {
+            headers.push(("anthropic-beta", flags.join(",")));
+        }
+        headers
+    }
+}
+
+/// Whether a beta flag is on the allowlist
+pub fn is_allowed_beta(flag: &str) -> bool {
+    ANTHROPIC_BETA_ALLOWLIST.contains(&flag)
+}
+
+/// Parse a comma-separated beta flag list, rejecting flags not on the allowlist
+pub fn parse_beta_flags(value: &str) -> Result<Vec<String>, String> {
+    let mut flags: Vec<String> = Vec::new();
+    for flag in split_flags(value) {
+        if !is_allowed_beta(flag) {
+            return Err(format!("unsupported anthropic-beta flag '{}'", flag));
+        }
+        if !flags.iter().any(|f| f == flag) {
+            flags.push(flag.to_string());
+        }
+    }
+    Ok(flags)
+}
+
+fn split_flags(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|flag| !flag.is_empty())
+}
+
+/// `YYYY-MM-DD`
+fn is_valid_version(version: &str) -> bool {
+    chrono::NaiveDate::parse_from_str(version, "%Y-%m-%d").is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let config = AnthropicHeaderConfig::from_lookup(|_| None);
+        assert_eq!(config, AnthropicHeaderConfig::default());
+        assert_eq!(
+            config.headers(None),
+            vec![("anthropic-version", DEFAULT_ANTHROPIC_VERSION.to_string())]
+        );
+    }
+
+    #[test]
+    fn test_configured_version_and_beta_are_sent() {
+        let config = AnthropicHeaderConfig::from_lookup(|key| match key {
+            "ANTHROPIC_VERSION" => Some("2024-10-22".to_string()),
+            "ANTHROPIC_BETA" => Some("prompt-caching-2024-07-31, pdfs-2024-09-25".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(
+            config.headers(None),
+            vec![
+                ("anthropic-version", "2024-10-22".to_string()),
+                ("anthropic-beta", "prompt-caching-2024-07-31,pdfs-2024-09-25".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_key_beta_merged_with_config() {
+        let config = AnthropicHeaderConfig {
+            beta: vec!["prompt-caching-2024-07-31".to_string()],
+            ..Default::default()
+        };
+
+        let headers = config.headers(Some("computer-use-2024-10-22,prompt-caching-2024-07-31,unknown-beta"));
+        assert_eq!(
+            headers[1],
+            ("anthropic-beta", "prompt-caching-2024-07-31,computer-use-2024-10-22".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_config_falls_back_to_defaults() {
+        let config = AnthropicHeaderConfig::from_lookup(|key| match key {
+            "ANTHROPIC_VERSION" => Some("latest".to_string()),
+            "ANTHROPIC_BETA" => Some("prompt-caching-2024-07-31,made-up-beta".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(config, AnthropicHeaderConfig::default());
+    }
+
+    #[test]
+    fn test_parse_beta_flags() {
+        assert_eq!(parse_beta_flags(""), Ok(vec![]));
+        assert_eq!(
+            parse_beta_flags("pdfs-2024-09-25,pdfs-2024-09-25"),
+            Ok(vec!["pdfs-2024-09-25".to_string()])
+        );
+        assert!(parse_beta_flags("pdfs-2024-09-25,tools-2099-01-01").is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Retry handling for Anthropic `overloaded_error` responses.
+//!
+//! Anthropic signals capacity problems with HTTP `529` and an error body of
+//! type `overloaded_error`. These are transient, so they are retried in the
+//! same loop as other transient upstream failures (see `upstream_retry`). If
+//! every attempt is overloaded, callers return a normalized `503 overloaded`
+//! with `Retry-After` instead of the raw upstream error.
+//!
+//! Configuration:
+//! - `UPSTREAM_MAX_RETRIES`: retries after the first attempt (default `2`).
+//!   Clients may lower this per non-streaming request with `max_retries`,
+//!   never raise it.
+
+use reqwest::StatusCode;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::services::upstream_retry::send_with_retry;
+
+/// Status Anthropic uses for `overloaded_error`
+pub const OVERLOADED_STATUS: u16 = 529;
+
+/// `Retry-After` sent when the upstream didn't provide one
+pub const DEFAULT_RETRY_AFTER_SECS: u64 = 30;
+
+/// Backoff policy for overloaded responses
+#[derive(Debug, Clone, Copy)]
+pub struct OverloadRetryPolicy {
+    pub attempts: u32,
+    /// Delay before the second attempt; doubled for each further attempt
+    pub base_delay: Duration,
+}
+
+impl Default for OverloadRetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            base_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl OverloadRetryPolicy {
+    /// Load the server retry budget from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load the server retry budget using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let default = Self::default();
+        let attempts = lookup("UPSTREAM_MAX_RETRIES")
+            .and_then(|v| v.trim().parse::<u32>().ok())
+            .map(|retries| retries.saturating_add(1))
+            .unwrap_or(default.attempts);
+        Self { attempts, ..default }
+    }
+
+    /// Apply a client's `max_retries` hint, bounded by this policy
+    pub fn with_max_retries(self, max_retries: Option<u32>) -> Self {
+        match max_retries {
+            Some(retries) => Self {
+                attempts: retries.saturating_add(1).min(self.attempts),
+                ..self
+            },
+            None => self,
+        }
+    }
+}
+
+/// Result of sending with overload retries
+#[derive(Debug)]
+pub enum OverloadOutcome<R> {
+    /// A response that was not overloaded (success or any other error)
+    Response(R),
+    /// Still overloaded after every attempt
+    Overloaded { retry_after_secs: u64 },
+}
+
+/// Whether an upstream status is Anthropic's `overloaded_error`
+pub fn is_overloaded(status: StatusCode) -> bool {
+    status.as_u16() == OVERLOADED_STATUS
+}
+
+/// Upstream `Retry-After` in seconds, or the default
+pub fn retry_after_secs(response: &reqwest::Response) -> u64 {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_RETRY_AFTER_SECS)
+}
+
+/// Send via `send` with transient failures (including `529`) retried.
+/// A response that is still overloaded after the last attempt is reported as
+/// `Overloaded`; anything else is returned as is.
+pub async fn send_with_overload_retry<F, Fut>(
+    policy: OverloadRetryPolicy,
+    send: F,
+) -> Result<OverloadOutcome<reqwest::Response>, reqwest::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<reqwest::Response, reqwest::Error>>,
+{
+    let response = send_with_retry(policy, send).await?;
+    if !is_overloaded(response.status()) {
+        return Ok(OverloadOutcome::Response(response));
+    }
+
+    tracing::warn!(attempts = policy.attempts, "Anthropic still overloaded, giving up");
+    Ok(OverloadOutcome::Overloaded {
+        retry_after_secs: retry_after_secs(&response),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::StatusCode as AxumStatus, response::IntoResponse, routing::post, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const OVERLOADED_BODY: &str =
+        r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+
+    /// Mock Anthropic that is overloaded for the first `overloaded` calls
+    async fn start_mock(overloaded: usize) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = |State((calls, overloaded)): State<(Arc<AtomicUsize>, usize)>| async move {
+            if calls.fetch_add(1, Ordering::SeqCst) < overloaded {
+                (
+                    AxumStatus::from_u16(OVERLOADED_STATUS).unwrap(),
+                    [("retry-after", "7")],
+                    OVERLOADED_BODY,
+                )
+                    .into_response()
+            } else {
+                (AxumStatus::OK, r#"{"id":"msg_1"}"#).into_response()
+            }
+        };
+        let app = Router::new()
+            .route("/v1/messages", post(handler))
+            .with_state((calls.clone(), overloaded));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (format!("http://{}/v1/messages", addr), calls)
+    }
+
+    fn fast_policy() -> OverloadRetryPolicy {
+        OverloadRetryPolicy {
+            attempts: 3,
+            base_delay: Duration::from_millis(5),
+        }
+    }
+
+    #[test]
+    fn test_is_overloaded() {
+        assert!(is_overloaded(StatusCode::from_u16(529).unwrap()));
+        assert!(!is_overloaded(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_overloaded(StatusCode::OK));
+    }
+
+    #[tokio::test]
+    async fn test_overloaded_then_success_is_retried() {
+        let (url, calls) = start_mock(1).await;
+        let client = reqwest::Client::new();
+
+        let outcome = send_with_overload_retry(fast_policy(), || client.post(&url).send())
+            .await
+            .unwrap();
+
+        let OverloadOutcome::Response(response) = outcome else {
+            panic!("expected a response after retry");
+        };
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), r#"{"id":"msg_1"}"#);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_persistent_overload_reports_retry_after() {
+        let (url, calls) = start_mock(usize::MAX).await;
+        let client = reqwest::Client::new();
+
+        let outcome = send_with_overload_retry(fast_policy(), || client.post(&url).send())
+            .await
+            .unwrap();
+
+        assert!(matches!(outcome, OverloadOutcome::Overloaded { retry_after_secs: 7 }));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_server_retry_budget_from_lookup() {
+        assert_eq!(OverloadRetryPolicy::from_lookup(|_| None).attempts, 3);
+        let policy = OverloadRetryPolicy::from_lookup(|key| match key {
+            "UPSTREAM_MAX_RETRIES" => Some("4".to_string()),
+            _ => None,
+        });
+        assert_eq!(policy.attempts, 5);
+    }
+
+    #[test]
+    fn test_max_retries_hint_bounded_by_server() {
+        let server = fast_policy();
+        assert_eq!(server.with_max_retries(None).attempts, 3);
+        assert_eq!(server.with_max_retries(Some(0)).attempts, 1);
+        assert_eq!(server.with_max_retries(Some(1)).attempts, 2);
+        assert_eq!(server.with_max_retries(Some(50)).attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn test_zero_max_retries_disables_retries() {
+        let (url, calls) = start_mock(1).await;
+        let client = reqwest::Client::new();
+
+        let policy = fast_policy().with_max_retries(Some(0));
+        let outcome = send_with_overload_retry(policy, || client.post(&url).send())
+            .await
+            .unwrap();
+
+        assert!(matches!(outcome, OverloadOutcome::Overloaded { retry_after_secs: 7 }));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_retries_honored_up_to_server_cap() {
+        // One retry allowed: two calls, still overloaded
+        let (url, calls) = start_mock(usize::MAX).await;
+        let client = reqwest::Client::new();
+        let policy = fast_policy().with_max_retries(Some(1));
+        let outcome = send_with_overload_retry(policy, || client.post(&url).send())
+            .await
+            .unwrap();
+        assert!(matches!(outcome, OverloadOutcome::Overloaded { .. }));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // More than the server allows: capped at the server's attempts
+        let (url, calls) = start_mock(usize::MAX).await;
+        let policy = fast_policy().with_max_retries(Some(10));
+        send_with_overload_retry(policy, || client.post(&url).send())
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
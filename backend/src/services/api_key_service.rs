@@ -1,19 +1,35 @@
 //! API Key service for provider key encryption and proxy key management.
 //!
 //! Requirements: 3.1, 3.2, 3.4, 3.6 - Provider API key storage with AES-256-GCM encryption
+//!
+//! Provider key limits default per plan tier and can be overridden via
+//! environment (a number, or `unlimited`):
+//! - `MAX_PROVIDER_KEYS` / `MAX_PROVIDER_KEYS_<PLAN>`
+//! - `MAX_PROVIDERS` / `MAX_PROVIDERS_<PLAN>`
+//!
+//! Plan-specific variables take precedence over the global ones.
 
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use serde::Serialize;
+use sqlx::{PgExecutor, PgPool};
+use std::collections::HashSet;
+use std::future::Future;
 use uuid::Uuid;
 
-use crate::models::api_key::{AiProvider, ApiKey, ApiKeyInfo, CreateApiKey};
+use crate::models::api_key::{AiProvider, ApiKey, ApiKeyInfo, CreateApiKey, KeyHealth, DEFAULT_KEY_WEIGHT};
+use crate::models::user::PlanTier;
 use crate::utils::encryption::{EncryptedData, EncryptionError, EncryptionUtils};
 
 /// API Key service error
 #[derive(Debug)]
 pub enum ApiKeyError {
     InvalidKeyFormat(String),
+    InvalidOptions(String),
+    PlanLimitReached(String),
     EncryptionError(EncryptionError),
+    /// A stored key exists but can't be decrypted (wrong or rotated
+    /// `ENCRYPTION_KEY`, corrupted row); a server fault, not a user error
+    DecryptionFailed(Uuid, EncryptionError),
     DatabaseError(sqlx::Error),
     NotFound,
     Unauthorized,
@@ -23,7 +39,10 @@ impl std::fmt::Display for ApiKeyError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ApiKeyError::InvalidKeyFormat(msg) => write!(f, "Invalid key format: {}", msg),
+            ApiKeyError::InvalidOptions(msg) => write!(f, "Invalid key options: {}", msg),
+            ApiKeyError::PlanLimitReached(msg) => write!(f, "Plan limit reached: {}", msg),
             ApiKeyError::EncryptionError(e) => write!(f, "Encryption error: {}", e),
+            ApiKeyError::DecryptionFailed(id, e) => write!(f, "Failed to decrypt stored API key {}: {}", id, e),
             ApiKeyError::DatabaseError(e) => write!(f, "Database error: {}", e),
             ApiKeyError::NotFound => write!(f, "API key not found"),
             ApiKeyError::Unauthorized => write!(f, "Unauthorized access to API key"),
@@ -55,6 +74,221 @@ pub struct StoredApiKey {
     pub created_at: DateTime<Utc>,
 }
 
+/// Provider key limits for a plan; `None` means unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProviderKeyLimits {
+    pub max_keys: Option<u32>,
+    pub max_providers: Option<u32>,
+}
+
+impl ProviderKeyLimits {
+    /// Default limits for a plan tier
+    pub fn for_plan(plan: PlanTier) -> Self {
+        Self {
+            max_keys: plan.api_key_limit(),
+            max_providers: plan.provider_limit(),
+        }
+    }
+
+    /// Load limits for a plan tier from environment variables
+    pub fn from_env(plan: PlanTier) -> Self {
+        Self::from_lookup(plan, |key| std::env::var(key).ok())
+    }
+
+    /// Load limits for a plan tier using a custom variable lookup
+    pub fn from_lookup<F>(plan: PlanTier, lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let suffix = plan.as_str().to_uppercase();
+        let read = |name: &str, default: Option<u32>| -> Option<u32> {
+            let value = lookup(&format!("{}_{}", name, suffix)).or_else(|| lookup(name));
+            match value.as_deref().map(str::trim) {
+                Some(v) if v.eq_ignore_ascii_case("unlimited") => None,
+                Some(v) => v.parse().ok().or(default),
+                None => default,
+            }
+        };
+
+        let defaults = Self::for_plan(plan);
+        Self {
+            max_keys: read("MAX_PROVIDER_KEYS", defaults.max_keys),
+            max_providers: read("MAX_PROVIDERS", defaults.max_providers),
+        }
+    }
+}
+
+/// Active provider keys held by a user, for plan limit checks
+#[derive(Debug, Clone, Default)]
+pub struct ProviderKeyUsage {
+    pub key_count: u32,
+    pub providers: HashSet<AiProvider>,
+}
+
+impl ProviderKeyUsage {
+    /// Check whether another key for `provider` fits within the plan limits
+    pub fn check(&self, limits: ProviderKeyLimits, provider: AiProvider) -> Result<(), ApiKeyError> {
+        if let Some(limit) = limits.max_keys {
+            if self.key_count >= limit {
+                return Err(ApiKeyError::PlanLimitReached(format!(
+                    "Provider key limit reached for your plan (max: {})",
+                    limit
+                )));
+            }
+        }
+
+        if let Some(limit) = limits.max_providers {
+            if !self.providers.contains(&provider) && self.providers.len() as u32 >= limit {
+                return Err(ApiKeyError::PlanLimitReached(format!(
+                    "Provider limit reached for your plan (max: {})",
+                    limit
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record a newly stored key
+    pub fn record(&mut self, provider: AiProvider) {
+        self.key_count += 1;
+        self.providers.insert(provider);
+    }
+}
+
+/// Per-item result of a bulk provider key import
+#[derive(Debug, Serialize)]
+pub struct BulkImportResult {
+    pub index: usize,
+    pub name: String,
+    pub provider: AiProvider,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub masked_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+impl BulkImportResult {
+    fn failure(index: usize, name: String, provider: AiProvider, error: &ApiKeyError) -> Self {
+        let (message, code) = match error {
+            ApiKeyError::InvalidKeyFormat(msg) => (msg.clone(), "INVALID_KEY_FORMAT"),
+            ApiKeyError::InvalidOptions(msg) => (msg.clone(), "INVALID_KEY_OPTIONS"),
+            ApiKeyError::PlanLimitReached(msg) => (msg.clone(), "PLAN_LIMIT_REACHED"),
+            _ => ("Failed to store API key".to_string(), "STORAGE_ERROR"),
+        };
+
+        Self {
+            index,
+            name,
+            provider,
+            success: false,
+            id: None,
+            masked_key: None,
+            error: Some(message),
+            code: Some(code.to_string()),
+        }
+    }
+}
+
+/// Import keys one by one, reporting per-item results without aborting the batch
+pub async fn run_bulk_import<F, Fut>(
+    limits: ProviderKeyLimits,
+    mut usage: ProviderKeyUsage,
+    items: Vec<CreateApiKey>,
+    mut store: F,
+) -> Vec<BulkImportResult>
+where
+    F: FnMut(CreateApiKey) -> Fut,
+    Fut: Future<Output = Result<StoredApiKey, ApiKeyError>>,
+{
+    let mut results = Vec::with_capacity(items.len());
+
+    for (index, input) in items.into_iter().enumerate() {
+        let precheck = if !input.provider.validate_key_format(&input.key) {
+            Err(ApiKeyError::InvalidKeyFormat(format!(
+                "Invalid {} API key format",
+                format!("{:?}", input.provider).to_lowercase()
+            )))
+        } else {
+            input
+                .validate_options()
+                .map_err(ApiKeyError::InvalidOptions)
+                .and_then(|_| usage.check(limits, input.provider))
+        };
+
+        if let Err(e) = precheck {
+            results.push(BulkImportResult::failure(index, input.name, input.provider, &e));
+            continue;
+        }
+
+        let name = input.name.clone();
+        let provider = input.provider;
+        match store(input).await {
+            Ok(stored) => {
+                usage.record(provider);
+                results.push(BulkImportResult {
+                    index,
+                    name,
+                    provider,
+                    success: true,
+                    id: Some(stored.id),
+                    masked_key: Some(stored.masked_key),
+                    error: None,
+                    code: None,
+                });
+            }
+            Err(e) => {
+                if !matches!(e, ApiKeyError::InvalidKeyFormat(_) | ApiKeyError::InvalidOptions(_)) {
+                    tracing::error!("Failed to import provider key #{}: {}", index, e);
+                }
+                results.push(BulkImportResult::failure(index, name, provider, &e));
+            }
+        }
+    }
+
+    results
+}
+
+/// Pick an index with probability proportional to its weight, given a
+/// uniform roll in `[0, 1)`. Items with weight `<= 0` are never picked.
+pub fn pick_weighted<T, F>(items: &[T], weight: F, roll: f64) -> Option<usize>
+where
+    F: Fn(&T) -> i32,
+{
+    let total: i64 = items.iter().map(|item| weight(item).max(0) as i64).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut target = (roll.clamp(0.0, 1.0) * total as f64) as i64;
+    for (index, item) in items.iter().enumerate() {
+        let w = weight(item).max(0) as i64;
+        if target < w {
+            return Some(index);
+        }
+        target -= w;
+    }
+
+    // roll == 1.0: fall back to the last eligible item
+    items.iter().rposition(|item| weight(item) > 0)
+}
+
+/// Decrypted provider credentials for proxy use
+#[derive(Debug, Clone)]
+pub struct ProviderCredentials {
+    /// Stored key the credentials came from; `None` for a key sent with the request
+    pub key_id: Option<Uuid>,
+    pub api_key: String,
+    pub openai_organization: Option<String>,
+    pub openai_project: Option<String>,
+    pub anthropic_beta: Option<String>,
+}
+
 /// API Key service implementation
 pub struct ApiKeyServiceImpl {
     encryption: EncryptionUtils,
@@ -67,11 +301,22 @@ impl ApiKeyServiceImpl {
         Ok(Self { encryption })
     }
 
+    /// Create API key service with the given encryption (for testing)
+    #[cfg(test)]
+    pub fn with_encryption(encryption: EncryptionUtils) -> Self {
+        Self { encryption }
+    }
+
+    /// Encryption used for stored provider keys
+    pub fn encryption(&self) -> &EncryptionUtils {
+        &self.encryption
+    }
+
     /// Store a provider API key (encrypted)
     /// Requirements: 3.1, 3.2, 3.6
-    pub async fn store_provider_key(
+    pub async fn store_provider_key<'e>(
         &self,
-        pool: &PgPool,
+        executor: impl PgExecutor<'e>,
         user_id: Uuid,
         input: CreateApiKey,
     ) -> Result<StoredApiKey, ApiKeyError> {
@@ -83,6 +328,8 @@ impl ApiKeyServiceImpl {
             )));
         }
 
+        input.validate_options().map_err(ApiKeyError::InvalidOptions)?;
+
         // Encrypt the API key (Requirements 3.1, 3.2)
         let encrypted = self.encryption.encrypt(&input.key)?;
 
@@ -92,8 +339,8 @@ impl ApiKeyServiceImpl {
 
         sqlx::query(
             r#"
-            INSERT INTO api_keys (id, user_id, provider, key_name, encrypted_key, iv, auth_tag, is_active, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, true, $8, $8)
+            INSERT INTO api_keys (id, user_id, provider, key_name, encrypted_key, iv, auth_tag, is_active, created_at, updated_at, openai_organization, openai_project, weight, anthropic_beta)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, true, $8, $8, $9, $10, $11, $12)
             "#,
         )
         .bind(id)
@@ -104,7 +351,11 @@ impl ApiKeyServiceImpl {
         .bind(&encrypted.iv.to_vec())
         .bind(&encrypted.auth_tag.to_vec())
         .bind(now)
-        .execute(pool)
+        .bind(&input.openai_organization)
+        .bind(&input.openai_project)
+        .bind(input.weight.unwrap_or(DEFAULT_KEY_WEIGHT))
+        .bind(&input.anthropic_beta)
+        .execute(executor)
         .await?;
 
         Ok(StoredApiKey {
@@ -116,6 +367,68 @@ impl ApiKeyServiceImpl {
         })
     }
 
+    /// Store a provider API key if the user's current plan has room for it.
+    /// The user row is locked while counting, so concurrent requests can't
+    /// both take the last free slot.
+    pub async fn add_provider_key(
+        &self,
+        pool: &PgPool,
+        user_id: Uuid,
+        input: CreateApiKey,
+    ) -> Result<StoredApiKey, ApiKeyError> {
+        let mut tx = pool.begin().await?;
+        let plan: PlanTier = sqlx::query_scalar("SELECT plan_tier FROM users WHERE id = $1 FOR UPDATE")
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        Self::get_provider_key_usage(&mut *tx, user_id)
+            .await?
+            .check(ProviderKeyLimits::from_env(plan), input.provider)?;
+
+        let stored = self.store_provider_key(&mut *tx, user_id, input).await?;
+        tx.commit().await?;
+        Ok(stored)
+    }
+
+    /// Import multiple provider keys, respecting the user's current plan limits
+    pub async fn import_provider_keys(
+        &self,
+        pool: &PgPool,
+        user_id: Uuid,
+        items: Vec<CreateApiKey>,
+    ) -> Result<Vec<BulkImportResult>, ApiKeyError> {
+        let plan: PlanTier = sqlx::query_scalar("SELECT plan_tier FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?;
+        let usage = Self::get_provider_key_usage(pool, user_id).await?;
+
+        Ok(run_bulk_import(ProviderKeyLimits::from_env(plan), usage, items, |input| {
+            self.store_provider_key(pool, user_id, input)
+        })
+        .await)
+    }
+
+    /// Get active provider key count and distinct providers for a user
+    pub async fn get_provider_key_usage<'e>(
+        executor: impl PgExecutor<'e>,
+        user_id: Uuid,
+    ) -> Result<ProviderKeyUsage, ApiKeyError> {
+        let providers: Vec<(AiProvider,)> = sqlx::query_as(
+            "SELECT provider FROM api_keys WHERE user_id = $1 AND is_active = true",
+        )
+        .bind(user_id)
+        .fetch_all(executor)
+        .await?;
+
+        let mut usage = ProviderKeyUsage::default();
+        for (provider,) in providers {
+            usage.record(provider);
+        }
+
+        Ok(usage)
+    }
 
     /// List provider API keys for a user (masked)
     /// Requirement: 3.4
@@ -126,7 +439,7 @@ impl ApiKeyServiceImpl {
     ) -> Result<Vec<ApiKeyInfo>, ApiKeyError> {
         let keys: Vec<ApiKey> = sqlx::query_as(
             r#"
-            SELECT id, user_id, provider, key_name, encrypted_key, iv, auth_tag, is_active, last_used_at, created_at, updated_at
+            SELECT id, user_id, provider, key_name, encrypted_key, iv, auth_tag, is_active, last_used_at, created_at, updated_at, openai_organization, openai_project, weight, anthropic_beta, validation_status, last_validated_at
             FROM api_keys
             WHERE user_id = $1 AND is_active = true
             ORDER BY created_at DESC
@@ -154,7 +467,13 @@ impl ApiKeyServiceImpl {
                 provider: key.provider,
                 name: key.key_name,
                 masked_key,
+                openai_organization: key.openai_organization,
+                openai_project: key.openai_project,
+                weight: key.weight,
+                anthropic_beta: key.anthropic_beta,
                 is_active: key.is_active,
+                status: KeyHealth::from_status(key.validation_status.as_deref()),
+                last_validated_at: key.last_validated_at,
                 last_used_at: key.last_used_at,
                 created_at: key.created_at,
             });
@@ -189,35 +508,65 @@ impl ApiKeyServiceImpl {
         Ok(())
     }
 
-    /// Get decrypted provider API key for proxy use
-    /// Requirement: 4.1, 4.2
-    pub async fn get_decrypted_key(
+    /// Record whether the provider accepted a stored key
+    pub async fn record_key_health(pool: &PgPool, key_id: Uuid, health: KeyHealth) -> Result<(), ApiKeyError> {
+        sqlx::query("UPDATE api_keys SET validation_status = $2, last_validated_at = NOW() WHERE id = $1")
+            .bind(key_id)
+            .bind(health.as_str())
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Providers the user has at least one usable (active, weighted) key for
+    pub async fn configured_providers(pool: &PgPool, user_id: Uuid) -> Result<Vec<AiProvider>, ApiKeyError> {
+        let providers = sqlx::query_scalar(
+            "SELECT DISTINCT provider FROM api_keys WHERE user_id = $1 AND is_active = true AND weight > 0",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(providers)
+    }
+
+    /// Get decrypted provider API key with provider-specific options.
+    /// Picks among the user's active keys in proportion to their weights;
+    /// when `key_name` is set, only the active key with that name is used.
+    pub async fn get_decrypted_credentials(
         &self,
         pool: &PgPool,
         user_id: Uuid,
         provider: AiProvider,
-    ) -> Result<String, ApiKeyError> {
-        let key: Option<ApiKey> = sqlx::query_as(
+        key_name: Option<&str>,
+    ) -> Result<ProviderCredentials, ApiKeyError> {
+        let mut keys: Vec<ApiKey> = sqlx::query_as(
             r#"
-            SELECT id, user_id, provider, key_name, encrypted_key, iv, auth_tag, is_active, last_used_at, created_at, updated_at
+            SELECT id, user_id, provider, key_name, encrypted_key, iv, auth_tag, is_active, last_used_at, created_at, updated_at, openai_organization, openai_project, weight, anthropic_beta, validation_status, last_validated_at
             FROM api_keys
-            WHERE user_id = $1 AND provider = $2 AND is_active = true
+            WHERE user_id = $1 AND provider = $2 AND is_active = true AND weight > 0
+              AND ($3::text IS NULL OR key_name = $3)
             ORDER BY created_at DESC
-            LIMIT 1
             "#,
         )
         .bind(user_id)
         .bind(provider)
-        .fetch_optional(pool)
+        .bind(key_name)
+        .fetch_all(pool)
         .await?;
 
-        let key = key.ok_or(ApiKeyError::NotFound)?;
+        let index = pick_weighted(&keys, |key| key.weight, rand::random::<f64>())
+            .ok_or(ApiKeyError::NotFound)?;
+        let key = keys.swap_remove(index);
 
         let encrypted = EncryptedData {
             ciphertext: key.encrypted_key,
             iv: key.iv.try_into().unwrap_or([0u8; 12]),
             auth_tag: key.auth_tag.try_into().unwrap_or([0u8; 16]),
         };
+        let api_key = self
+            .encryption
+            .decrypt(&encrypted)
+            .map_err(|e| ApiKeyError::DecryptionFailed(key.id, e))?;
 
         // Update last_used_at
         sqlx::query("UPDATE api_keys SET last_used_at = NOW() WHERE id = $1")
@@ -225,13 +574,48 @@ impl ApiKeyServiceImpl {
             .execute(pool)
             .await?;
 
-        Ok(self.encryption.decrypt(&encrypted)?)
+        Ok(ProviderCredentials {
+            key_id: Some(key.id),
+            api_key,
+            openai_organization: key.openai_organization,
+            openai_project: key.openai_project,
+            anthropic_beta: key.anthropic_beta,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::env_lookup;
+
+    #[test]
+    fn test_weighted_selection_matches_weights() {
+        let weights = [70, 30, 0];
+        let mut counts = [0u32; 3];
+        let trials = 20_000;
+
+        for _ in 0..trials {
+            let index = pick_weighted(&weights, |w| *w, rand::random::<f64>()).unwrap();
+            counts[index] += 1;
+        }
+
+        let share = |count: u32| count as f64 / trials as f64;
+        assert!((share(counts[0]) - 0.7).abs() < 0.03, "got {:?}", counts);
+        assert!((share(counts[1]) - 0.3).abs() < 0.03, "got {:?}", counts);
+        // Zero weight is never selected
+        assert_eq!(counts[2], 0);
+    }
+
+    #[test]
+    fn test_weighted_selection_edges() {
+        assert_eq!(pick_weighted(&[0, 0], |w| *w, 0.5), None);
+        assert_eq!(pick_weighted::<i32, _>(&[], |w| *w, 0.5), None);
+        assert_eq!(pick_weighted(&[0, 5], |w| *w, 0.0), Some(1));
+        assert_eq!(pick_weighted(&[5, 0], |w| *w, 1.0), Some(0));
+        assert_eq!(pick_weighted(&[1, 1], |w| *w, 0.49), Some(0));
+        assert_eq!(pick_weighted(&[1, 1], |w| *w, 0.5), Some(1));
+    }
 
     // Property Test 4: Sensitive Data Masking
     // Validates: Requirements 3.4, 6.4
@@ -265,4 +649,262 @@ mod tests {
         // Should not contain the middle part
         assert!(!masked.contains("verylongapikey"));
     }
+
+    // ============================================================
+    // Unit Tests for Bulk Provider Key Import
+    // ============================================================
+
+    fn import_item(provider: AiProvider, key: &str, name: &str) -> CreateApiKey {
+        CreateApiKey {
+            provider,
+            key: key.to_string(),
+            name: name.to_string(),
+            openai_organization: None,
+            openai_project: None,
+            weight: None,
+            anthropic_beta: None,
+        }
+    }
+
+    async fn mock_store(input: CreateApiKey) -> Result<StoredApiKey, ApiKeyError> {
+        Ok(StoredApiKey {
+            id: Uuid::new_v4(),
+            provider: input.provider,
+            masked_key: ApiKeyInfo::mask_key(&input.key),
+            name: input.name,
+            created_at: Utc::now(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_bulk_import_partial_success() {
+        let items = vec![
+            import_item(AiProvider::Openai, "sk-valid-openai-key-123", "openai"),
+            import_item(AiProvider::Anthropic, "not-an-anthropic-key", "bad"),
+            import_item(AiProvider::Google, "AIza-valid-google-key", "google"),
+        ];
+
+        let results =
+            run_bulk_import(ProviderKeyLimits::for_plan(PlanTier::Pro), ProviderKeyUsage::default(), items, mock_store).await;
+
+        assert_eq!(results.len(), 3);
+        assert!(results[0].success);
+        assert!(results[0].masked_key.is_some());
+        assert!(!results[1].success);
+        assert_eq!(results[1].code.as_deref(), Some("INVALID_KEY_FORMAT"));
+        assert!(results[2].success);
+        assert_eq!(results[2].index, 2);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_import_storage_failure_does_not_abort() {
+        let items = vec![
+            import_item(AiProvider::Openai, "sk-first-openai-key-1", "first"),
+            import_item(AiProvider::Openai, "sk-second-openai-key-2", "second"),
+        ];
+
+        let mut calls = 0;
+        let results = run_bulk_import(ProviderKeyLimits::for_plan(PlanTier::Pro), ProviderKeyUsage::default(), items, |input| {
+            calls += 1;
+            let fail = calls == 1;
+            async move {
+                if fail {
+                    Err(ApiKeyError::DatabaseError(sqlx::Error::PoolTimedOut))
+                } else {
+                    mock_store(input).await
+                }
+            }
+        })
+        .await;
+
+        assert!(!results[0].success);
+        assert_eq!(results[0].code.as_deref(), Some("STORAGE_ERROR"));
+        assert!(results[1].success);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_import_enforces_key_limit() {
+        // Free plan allows a single provider key
+        let items = vec![
+            import_item(AiProvider::Openai, "sk-first-openai-key-1", "first"),
+            import_item(AiProvider::Openai, "sk-second-openai-key-2", "second"),
+        ];
+
+        let results =
+            run_bulk_import(ProviderKeyLimits::for_plan(PlanTier::Free), ProviderKeyUsage::default(), items, mock_store).await;
+
+        assert!(results[0].success);
+        assert!(!results[1].success);
+        assert_eq!(results[1].code.as_deref(), Some("PLAN_LIMIT_REACHED"));
+    }
+
+    #[tokio::test]
+    async fn test_bulk_import_enforces_provider_limit_with_existing_keys() {
+        // Starter plan allows 2 distinct providers; user already has OpenAI + Anthropic
+        let mut usage = ProviderKeyUsage::default();
+        usage.record(AiProvider::Openai);
+        usage.record(AiProvider::Anthropic);
+
+        let items = vec![
+            import_item(AiProvider::Google, "AIza-valid-google-key", "google"),
+            import_item(AiProvider::Openai, "sk-another-openai-key", "openai-2"),
+        ];
+
+        let results = run_bulk_import(ProviderKeyLimits::for_plan(PlanTier::Starter), usage, items, mock_store).await;
+
+        assert!(!results[0].success);
+        assert_eq!(results[0].code.as_deref(), Some("PLAN_LIMIT_REACHED"));
+        assert!(results[1].success);
+    }
+
+    #[test]
+    fn test_provider_key_limits_from_env() {
+        assert_eq!(
+            ProviderKeyLimits::from_lookup(PlanTier::Free, env_lookup(&[])),
+            ProviderKeyLimits::for_plan(PlanTier::Free)
+        );
+
+        let limits = ProviderKeyLimits::from_lookup(
+            PlanTier::Starter,
+            env_lookup(&[
+                ("MAX_PROVIDER_KEYS", "3"),
+                ("MAX_PROVIDER_KEYS_STARTER", "8"),
+                ("MAX_PROVIDERS_STARTER", "unlimited"),
+            ]),
+        );
+        assert_eq!(limits, ProviderKeyLimits { max_keys: Some(8), max_providers: None });
+    }
+
+    // Database-backed tests (see test_support for how to run them)
+
+    fn test_service() -> ApiKeyServiceImpl {
+        ApiKeyServiceImpl::with_encryption(EncryptionUtils::from_key(&[3u8; 32]).unwrap())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_free_plan_cannot_add_second_provider_key(pool: PgPool) {
+        let service = test_service();
+        let user_id = crate::test_support::insert_user(&pool, "free@example.com", PlanTier::Free).await;
+
+        let first = import_item(AiProvider::Openai, "sk-first-openai-key-1", "first");
+        service.add_provider_key(&pool, user_id, first).await.unwrap();
+
+        let second = import_item(AiProvider::Openai, "sk-second-openai-key-2", "second");
+        let err = service.add_provider_key(&pool, user_id, second).await.unwrap_err();
+        assert!(matches!(err, ApiKeyError::PlanLimitReached(_)));
+        assert_eq!(ApiKeyServiceImpl::get_provider_key_usage(&pool, user_id).await.unwrap().key_count, 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_concurrent_adds_cannot_exceed_plan_limit(pool: PgPool) {
+        let service = test_service();
+        let user_id = crate::test_support::insert_user(&pool, "race@example.com", PlanTier::Free).await;
+
+        let (first, second) = tokio::join!(
+            service.add_provider_key(&pool, user_id, import_item(AiProvider::Openai, "sk-first-openai-key-1", "first")),
+            service.add_provider_key(&pool, user_id, import_item(AiProvider::Openai, "sk-second-openai-key-2", "second")),
+        );
+
+        assert_eq!(first.is_ok() as u8 + second.is_ok() as u8, 1);
+        assert_eq!(ApiKeyServiceImpl::get_provider_key_usage(&pool, user_id).await.unwrap().key_count, 1);
+
+        // The stored plan counts, so an upgrade takes effect without a new token
+        sqlx::query("UPDATE users SET plan_tier = 'starter' WHERE id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        service
+            .add_provider_key(&pool, user_id, import_item(AiProvider::Openai, "sk-third-openai-key-3", "third"))
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_higher_plan_can_add_more_provider_keys(pool: PgPool) {
+        let service = test_service();
+        let user_id = crate::test_support::insert_user(&pool, "starter@example.com", PlanTier::Starter).await;
+
+        for (provider, key, name) in [
+            (AiProvider::Openai, "sk-first-openai-key-1", "first"),
+            (AiProvider::Openai, "sk-second-openai-key-2", "second"),
+            (AiProvider::Anthropic, "sk-ant-anthropic-key", "anthropic"),
+        ] {
+            service
+                .add_provider_key(&pool, user_id, import_item(provider, key, name))
+                .await
+                .unwrap();
+        }
+
+        // Starter allows two distinct providers
+        let google = import_item(AiProvider::Google, "AIza-valid-google-key", "google");
+        let err = service.add_provider_key(&pool, user_id, google).await.unwrap_err();
+        assert!(matches!(err, ApiKeyError::PlanLimitReached(_)));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_listing_masks_keys_and_reports_health(pool: PgPool) {
+        let service = test_service();
+        let user_id = crate::test_support::insert_user(&pool, "list@example.com", PlanTier::Pro).await;
+        let plaintext = "sk-proj-listing-secret-key-9876";
+
+        let stored = service
+            .add_provider_key(&pool, user_id, import_item(AiProvider::Openai, plaintext, "main"))
+            .await
+            .unwrap();
+
+        let keys = service.list_provider_keys(&pool, user_id).await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].provider, AiProvider::Openai);
+        assert_eq!(keys[0].status, KeyHealth::Unverified);
+        assert_eq!(keys[0].last_validated_at, None);
+
+        let listing = serde_json::to_string(&keys).unwrap();
+        assert!(!listing.contains(plaintext));
+        assert!(!listing.contains("listing-secret"));
+        assert!(listing.contains("\"masked_key\":\"sk-...y-9876\""));
+        assert!(listing.contains("\"status\":\"unverified\""));
+        assert!(listing.contains("\"created_at\""));
+
+        ApiKeyServiceImpl::record_key_health(&pool, stored.id, KeyHealth::Invalid).await.unwrap();
+        let keys = service.list_provider_keys(&pool, user_id).await.unwrap();
+        assert_eq!(keys[0].status, KeyHealth::Invalid);
+        assert!(keys[0].last_validated_at.is_some());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_missing_and_undecryptable_keys_are_distinct(pool: PgPool) {
+        let service = test_service();
+        let user_id = crate::test_support::insert_user(&pool, "decrypt@example.com", PlanTier::Pro).await;
+
+        let err = service
+            .get_decrypted_credentials(&pool, user_id, AiProvider::Openai, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ApiKeyError::NotFound));
+
+        let stored = service
+            .add_provider_key(&pool, user_id, import_item(AiProvider::Openai, "sk-proj-rotated-key-1234", "main"))
+            .await
+            .unwrap();
+
+        // Same row read with a different encryption key
+        let rotated = ApiKeyServiceImpl::with_encryption(EncryptionUtils::from_key(&[9u8; 32]).unwrap());
+        let err = rotated
+            .get_decrypted_credentials(&pool, user_id, AiProvider::Openai, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ApiKeyError::DecryptionFailed(id, _) if id == stored.id));
+
+        let credentials = service
+            .get_decrypted_credentials(&pool, user_id, AiProvider::Openai, None)
+            .await
+            .unwrap();
+        assert_eq!(credentials.api_key, "sk-proj-rotated-key-1234");
+    }
 }
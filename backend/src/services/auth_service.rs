@@ -1,12 +1,13 @@
 //! Authentication service for user registration, login, and JWT management.
 
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::models::{User, PlanTier, CreateUser, UserResponse};
+use crate::services::email_service::{self, EmailQueue, EmailRequest};
 use crate::utils::password::{hash_password, verify_password};
 
 /// JWT Claims structure
@@ -52,6 +53,7 @@ pub enum AuthError {
     InvalidCredentials,
     InvalidToken,
     TokenExpired,
+    TokenRevoked,
     DatabaseError(String),
     HashingError,
 }
@@ -65,6 +67,7 @@ impl std::fmt::Display for AuthError {
             AuthError::InvalidCredentials => write!(f, "Invalid email or password"),
             AuthError::InvalidToken => write!(f, "Invalid token"),
             AuthError::TokenExpired => write!(f, "Token has expired"),
+            AuthError::TokenRevoked => write!(f, "Token has been revoked"),
             AuthError::DatabaseError(e) => write!(f, "Database error: {}", e),
             AuthError::HashingError => write!(f, "Password hashing failed"),
         }
@@ -75,11 +78,19 @@ impl std::fmt::Display for AuthError {
 pub struct AuthService {
     db: PgPool,
     jwt_secret: String,
+    /// Background queue for welcome emails; none means no email is sent
+    email_queue: Option<EmailQueue>,
 }
 
 impl AuthService {
     pub fn new(db: PgPool, jwt_secret: String) -> Self {
-        Self { db, jwt_secret }
+        Self { db, jwt_secret, email_queue: None }
+    }
+
+    /// Queue a welcome email for each new user
+    pub fn with_email_queue(mut self, queue: EmailQueue) -> Self {
+        self.email_queue = Some(queue);
+        self
     }
 
     /// Register a new user
@@ -111,20 +122,31 @@ impl AuthService {
         let password_hash = hash_password(&input.password)
             .map_err(|_| AuthError::HashingError)?;
 
+        let locale = input
+            .locale
+            .as_deref()
+            .and_then(email_service::supported_locale)
+            .unwrap_or_else(email_service::default_locale_from_env);
+
         // Insert user with default Free plan
         let user = sqlx::query_as::<_, User>(
             r#"
-            INSERT INTO users (email, password_hash, plan_tier)
-            VALUES ($1, $2, 'free')
+            INSERT INTO users (email, password_hash, plan_tier, locale)
+            VALUES ($1, $2, 'free', $3)
             RETURNING id, email, password_hash, plan_tier, is_active, email_verified_at, created_at, updated_at
             "#
         )
         .bind(&input.email)
         .bind(&password_hash)
+        .bind(locale)
         .fetch_one(&self.db)
         .await
         .map_err(|e| AuthError::DatabaseError(e.to_string()))?;
 
+        if let Some(queue) = &self.email_queue {
+            queue.enqueue(EmailRequest::welcome(&user.email, None, locale));
+        }
+
         // Generate tokens
         let tokens = self.generate_tokens(&user)?;
 
@@ -185,6 +207,14 @@ impl AuthService {
         .map_err(|e| AuthError::DatabaseError(e.to_string()))?
         .ok_or(AuthError::InvalidToken)?;
 
+        // Reject refresh tokens issued before the user's sessions were revoked
+        let cutoff = get_tokens_valid_after(&self.db, user_id)
+            .await
+            .map_err(|e| AuthError::DatabaseError(e.to_string()))?;
+        if issued_before_cutoff(claims.iat, cutoff) {
+            return Err(AuthError::TokenRevoked);
+        }
+
         // Generate new tokens
         self.generate_tokens(&user)
     }
@@ -277,6 +307,34 @@ pub async fn get_user_by_id(db: &PgPool, user_id: Uuid) -> Result<Option<User>,
     .await
 }
 
+/// Whether a token issued at `iat` predates the session cutoff. Compared at
+/// second precision (the resolution of `iat`), so tokens issued in the same
+/// second as a revocation are rejected too.
+pub fn issued_before_cutoff(iat: i64, tokens_valid_after: Option<DateTime<Utc>>) -> bool {
+    tokens_valid_after.is_some_and(|cutoff| iat <= cutoff.timestamp())
+}
+
+/// Get a user's session cutoff, if their sessions have ever been revoked
+pub async fn get_tokens_valid_after(db: &PgPool, user_id: Uuid) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let cutoff: Option<(Option<DateTime<Utc>>,)> =
+        sqlx::query_as("SELECT tokens_valid_after FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?;
+
+    Ok(cutoff.and_then(|(c,)| c))
+}
+
+/// Revoke every session for a user by moving their token cutoff to now.
+/// Returns the new cutoff, or `None` when the user doesn't exist.
+pub async fn revoke_all_sessions(db: &PgPool, user_id: Uuid) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar(
+        "UPDATE users SET tokens_valid_after = NOW(), updated_at = NOW() WHERE id = $1 RETURNING tokens_valid_after",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await
+}
 
 #[cfg(test)]
 mod tests {
@@ -635,4 +693,126 @@ mod tests {
         
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_issued_before_cutoff() {
+        let cutoff = Utc::now();
+        assert!(!issued_before_cutoff(cutoff.timestamp() - 60, None));
+        assert!(issued_before_cutoff(cutoff.timestamp() - 60, Some(cutoff)));
+        assert!(issued_before_cutoff(cutoff.timestamp(), Some(cutoff)));
+        assert!(!issued_before_cutoff(cutoff.timestamp() + 1, Some(cutoff)));
+    }
+
+    // Database-backed tests (see test_support for how to run them)
+
+    fn db_service(pool: PgPool) -> AuthService {
+        AuthService::new(pool, "test-secret".to_string())
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_register_then_login(pool: PgPool) {
+        let service = db_service(pool);
+        let registered = service
+            .register(CreateUser {
+                email: "budi@example.com".to_string(),
+                password: "correct horse battery".to_string(),
+                locale: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(registered.user.plan_tier, PlanTier::Free);
+
+        let logged_in = service.login("budi@example.com", "correct horse battery").await.unwrap();
+        assert_eq!(logged_in.user.id, registered.user.id);
+
+        assert!(matches!(
+            service.login("budi@example.com", "wrong password").await,
+            Err(AuthError::InvalidCredentials)
+        ));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_register_stores_locale_for_welcome_email(pool: PgPool) {
+        use crate::services::email_service::{email_queue, locale_from_accept_language, EmailTemplate};
+
+        let (queue, mut receiver) = email_queue();
+        let service = db_service(pool.clone()).with_email_queue(queue);
+        let registered = service
+            .register(CreateUser {
+                email: "ayu@example.com".to_string(),
+                password: "password123".to_string(),
+                locale: Some(locale_from_accept_language(Some("en"), "id").to_string()),
+            })
+            .await
+            .unwrap();
+
+        let locale: String = sqlx::query_scalar("SELECT locale FROM users WHERE id = $1")
+            .bind(registered.user.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(locale, "en");
+
+        let welcome = receiver.try_recv().expect("welcome email queued");
+        assert_eq!(welcome.template, EmailTemplate::Welcome);
+        assert_eq!(welcome.to, "ayu@example.com");
+        assert_eq!(welcome.language, "en");
+
+        // Without a detected locale the Indonesian default is stored
+        let registered = service
+            .register(CreateUser {
+                email: "rudi@example.com".to_string(),
+                password: "password123".to_string(),
+                locale: None,
+            })
+            .await
+            .unwrap();
+        let locale: String = sqlx::query_scalar("SELECT locale FROM users WHERE id = $1")
+            .bind(registered.user.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(locale, "id");
+        assert_eq!(receiver.try_recv().unwrap().language, "id");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_register_rejects_duplicate_email(pool: PgPool) {
+        let service = db_service(pool);
+        let input = || CreateUser {
+            email: "siti@example.com".to_string(),
+            password: "password123".to_string(),
+            locale: None,
+        };
+
+        service.register(input()).await.unwrap();
+        assert!(matches!(service.register(input()).await, Err(AuthError::EmailAlreadyExists)));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_revoke_all_sessions_rejects_old_refresh_tokens(pool: PgPool) {
+        let service = db_service(pool.clone());
+        let registered = service
+            .register(CreateUser {
+                email: "dewi@example.com".to_string(),
+                password: "password123".to_string(),
+                locale: None,
+            })
+            .await
+            .unwrap();
+        assert!(get_tokens_valid_after(&pool, registered.user.id).await.unwrap().is_none());
+
+        let cutoff = revoke_all_sessions(&pool, registered.user.id).await.unwrap().unwrap();
+        assert_eq!(get_tokens_valid_after(&pool, registered.user.id).await.unwrap(), Some(cutoff));
+        assert!(matches!(
+            service.refresh_token(&registered.tokens.refresh_token).await,
+            Err(AuthError::TokenRevoked)
+        ));
+
+        assert!(revoke_all_sessions(&pool, Uuid::new_v4()).await.unwrap().is_none());
+    }
 }
@@ -4,13 +4,14 @@
 //!
 //! These tests verify correctness properties for:
 //! - Usage aggregation (Property 1)
-//! - Payment amount calculation with PPN (Property 2)
 //! - Subscription lifecycle integrity (Property 3)
-//! - Proration calculation (Property 4)
 //! - Rate limiting enforcement (Property 5)
 //! - Webhook signature verification (Property 6)
 //! - Invoice number uniqueness (Property 7)
 //! - CSV export completeness (Property 8)
+//!
+//! Payment amount (Property 2) and proration (Property 4) are tested against
+//! the real implementations in `services::pricing`.
 
 #[cfg(test)]
 mod property_tests {
@@ -18,6 +19,8 @@ mod property_tests {
     use chrono::{DateTime, Duration, Utc};
     use sha2::{Digest, Sha512};
 
+    use crate::services::pricing::PlanTier;
+
     // ============================================================
     // Property Test 1: Usage Aggregation Correctness
     // **Feature: week3-billing-analytics, Property 1: Usage Aggregation Correctness**
@@ -132,83 +135,6 @@ mod property_tests {
     }
 
 
-    // ============================================================
-    // Property Test 2: Payment Amount Calculation
-    // **Feature: week3-billing-analytics, Property 2: Payment Amount Calculation**
-    // **Validates: Requirements 2.1, 4.2**
-    // ============================================================
-
-    const PPN_RATE: f64 = 0.11;
-
-    /// Calculate total amount with PPN (11% VAT)
-    fn calculate_total_with_ppn(base_price: i64) -> (i64, i64, i64) {
-        let ppn = (base_price as f64 * PPN_RATE).round() as i64;
-        let total = base_price + ppn;
-        (base_price, ppn, total)
-    }
-
-    /// Plan tier pricing
-    fn plan_price(tier: &str) -> i64 {
-        match tier {
-            "free" => 0,
-            "starter" => 49_000,
-            "pro" => 99_000,
-            "team" => 299_000,
-            _ => 0,
-        }
-    }
-
-    proptest! {
-        #![proptest_config(ProptestConfig::with_cases(100))]
-
-        /// Property: Total equals base price + PPN (11%)
-        /// Requirements: 2.1, 4.2 - Payment amount calculation
-        #[test]
-        fn prop_total_equals_base_plus_ppn(base_price in 1000i64..1000000i64) {
-            let (subtotal, ppn, total) = calculate_total_with_ppn(base_price);
-            
-            prop_assert_eq!(subtotal, base_price, "Subtotal should equal base price");
-            prop_assert_eq!(total, subtotal + ppn, "Total should equal subtotal + PPN");
-        }
-
-        /// Property: PPN is exactly 11% of base price (rounded)
-        /// Requirements: 2.1 - 11% PPN calculation
-        #[test]
-        fn prop_ppn_is_eleven_percent(base_price in 1000i64..1000000i64) {
-            let (_, ppn, _) = calculate_total_with_ppn(base_price);
-            let expected_ppn = (base_price as f64 * 0.11).round() as i64;
-            
-            prop_assert_eq!(ppn, expected_ppn, "PPN should be 11% of base price");
-        }
-
-        /// Property: All plan tiers have correct pricing with PPN
-        /// Requirements: 2.1 - Plan tier pricing
-        #[test]
-        fn prop_plan_tier_pricing(tier in prop_oneof![
-            Just("starter"),
-            Just("pro"),
-            Just("team"),
-        ]) {
-            let base = plan_price(&tier);
-            let (subtotal, ppn, total) = calculate_total_with_ppn(base);
-            
-            // Verify expected totals
-            let expected_totals = [
-                ("starter", 49_000, 5_390, 54_390),
-                ("pro", 99_000, 10_890, 109_890),
-                ("team", 299_000, 32_890, 331_890),
-            ];
-            
-            for (t, exp_sub, exp_ppn, exp_total) in expected_totals {
-                if tier == t {
-                    prop_assert_eq!(subtotal, exp_sub, "Subtotal mismatch for {}", tier);
-                    prop_assert_eq!(ppn, exp_ppn, "PPN mismatch for {}", tier);
-                    prop_assert_eq!(total, exp_total, "Total mismatch for {}", tier);
-                }
-            }
-        }
-    }
-
     // ============================================================
     // Property Test 3: Subscription Lifecycle Integrity
     // **Feature: week3-billing-analytics, Property 3: Subscription Lifecycle Integrity**
@@ -310,81 +236,6 @@ mod property_tests {
     }
 
 
-    // ============================================================
-    // Property Test 4: Proration Calculation
-    // **Feature: week3-billing-analytics, Property 4: Proration Calculation**
-    // **Validates: Requirements 3.4**
-    // ============================================================
-
-    /// Calculate prorated amount for mid-cycle upgrade
-    fn calculate_proration(old_price: i64, new_price: i64, remaining_days: i64) -> i64 {
-        if new_price <= old_price || remaining_days <= 0 {
-            return 0;
-        }
-        let price_diff = new_price - old_price;
-        ((price_diff as f64 * remaining_days as f64) / 30.0).round() as i64
-    }
-
-    proptest! {
-        #![proptest_config(ProptestConfig::with_cases(100))]
-
-        /// Property: Proration equals (new_price - old_price) * (remaining_days / 30)
-        /// Requirements: 3.4 - Proration calculation
-        #[test]
-        fn prop_proration_formula_correct(
-            old_price in 0i64..100000i64,
-            new_price in 0i64..500000i64,
-            remaining_days in 1i64..30i64
-        ) {
-            let prorated = calculate_proration(old_price, new_price, remaining_days);
-            
-            if new_price > old_price {
-                let expected = ((new_price - old_price) as f64 * remaining_days as f64 / 30.0).round() as i64;
-                prop_assert_eq!(
-                    prorated,
-                    expected,
-                    "Proration should follow formula: (new - old) * remaining / 30"
-                );
-            } else {
-                prop_assert_eq!(
-                    prorated,
-                    0,
-                    "Downgrade or same plan should have 0 proration"
-                );
-            }
-        }
-
-        /// Property: Proration is 0 when downgrading
-        /// Requirements: 3.4 - No proration for downgrades
-        #[test]
-        fn prop_no_proration_for_downgrade(
-            old_price in 50000i64..500000i64,
-            remaining_days in 1i64..30i64
-        ) {
-            let new_price = old_price / 2; // Downgrade
-            let prorated = calculate_proration(old_price, new_price, remaining_days);
-            
-            prop_assert_eq!(prorated, 0, "Downgrade should have 0 proration");
-        }
-
-        /// Property: Full month upgrade equals full price difference
-        /// Requirements: 3.4 - Full month proration
-        #[test]
-        fn prop_full_month_proration(
-            old_price in 0i64..100000i64,
-            new_price in 100001i64..500000i64
-        ) {
-            let prorated = calculate_proration(old_price, new_price, 30);
-            let expected = new_price - old_price;
-            
-            prop_assert_eq!(
-                prorated,
-                expected,
-                "30 days remaining should equal full price difference"
-            );
-        }
-    }
-
     // ============================================================
     // Property Test 5: Rate Limiting Enforcement
     // **Feature: week3-billing-analytics, Property 5: Rate Limiting Enforcement**
@@ -393,13 +244,7 @@ mod property_tests {
 
     /// Plan tier request limits
     fn plan_request_limit(tier: &str) -> i64 {
-        match tier {
-            "free" => 1_000,
-            "starter" => 10_000,
-            "pro" => 50_000,
-            "team" => 200_000,
-            _ => 1_000,
-        }
+        PlanTier::parse(tier).unwrap_or(PlanTier::Free).request_limit()
     }
 
     /// Check if request should be allowed
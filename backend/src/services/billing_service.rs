@@ -5,52 +5,12 @@ use sha2::{Digest, Sha512};
 use sqlx::{PgPool, Row};
 use uuid::Uuid;
 
-/// Plan tier pricing in IDR (before PPN)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-pub enum PlanTier {
-    Free,
-    Starter,
-    Pro,
-    Team,
-}
-
-impl PlanTier {
-    /// Get base price in IDR (before PPN)
-    pub fn price_idr(&self) -> i64 {
-        match self {
-            PlanTier::Free => 0,
-            PlanTier::Starter => 49_000,
-            PlanTier::Pro => 99_000,
-            PlanTier::Team => 299_000,
-        }
-    }
-
-    /// Get monthly request limit
-    pub fn request_limit(&self) -> i64 {
-        match self {
-            PlanTier::Free => 1_000,
-            PlanTier::Starter => 10_000,
-            PlanTier::Pro => 50_000,
-            PlanTier::Team => 200_000,
-        }
-    }
-
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            PlanTier::Free => "free",
-            PlanTier::Starter => "starter",
-            PlanTier::Pro => "pro",
-            PlanTier::Team => "team",
-        }
-    }
-}
-
-impl std::fmt::Display for PlanTier {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.as_str())
-    }
-}
+use crate::services::email_service::{EmailData, EmailQueue, EmailRequest, EmailTemplate};
+use crate::services::invoice_service::format_rupiah;
+use crate::services::pricing::{self, calculate_total_with_ppn_at, effective_ppn_rate, format_ppn_rate, split_ppn_inclusive_at};
+use crate::utils::db_retry::retry_db;
 
+pub use crate::services::pricing::{PlanTier, ProrationPreview};
 
 /// Subscription status
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
@@ -58,6 +18,8 @@ impl std::fmt::Display for PlanTier {
 pub enum SubscriptionStatus {
     PendingPayment,
     Active,
+    /// Period ended; still on the paid tier until the grace period passes
+    PastDue,
     Expired,
     Cancelled,
 }
@@ -92,6 +54,8 @@ pub struct MidtransWebhook {
     pub order_id: String,
     pub status_code: String,
     pub gross_amount: String,
+    /// Empty when Midtrans sends the signature in a header instead
+    #[serde(default)]
     pub signature_key: String,
     pub transaction_status: String,
     pub transaction_id: String,
@@ -113,17 +77,137 @@ pub enum BillingError {
     InvalidPlanTier,
 }
 
-/// PPN (VAT) rate in Indonesia: 11%
-const PPN_RATE: f64 = 0.11;
+/// Default time after a subscription period ends before the user is downgraded
+pub const DEFAULT_GRACE_PERIOD_DAYS: i64 = 2;
+
+/// Grace period after subscription expiry from `SUBSCRIPTION_GRACE_DAYS`
+pub fn grace_period_from_env() -> Duration {
+    grace_period_from_lookup(|key| std::env::var(key).ok())
+}
 
-/// Calculate total amount with PPN
-/// Property 2: Payment Amount Calculation
-pub fn calculate_total_with_ppn(base_price: i64) -> (i64, i64, i64) {
-    let ppn = (base_price as f64 * PPN_RATE).round() as i64;
-    let total = base_price + ppn;
-    (base_price, ppn, total)
+/// Grace period after subscription expiry using a custom variable lookup
+pub fn grace_period_from_lookup<F>(lookup: F) -> Duration
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let days = lookup("SUBSCRIPTION_GRACE_DAYS")
+        .and_then(|v| v.trim().parse::<i64>().ok())
+        .filter(|days| *days >= 0)
+        .unwrap_or(DEFAULT_GRACE_PERIOD_DAYS);
+    Duration::days(days)
 }
 
+/// Midtrans environment (sandbox or production)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidtransEnvironment {
+    Sandbox,
+    Production,
+}
+
+impl MidtransEnvironment {
+    /// Environment a key was issued for, from its prefix (`SB-Mid-` for
+    /// sandbox, `Mid-` for production); `None` for unrecognized formats
+    pub fn of_key(key: &str) -> Option<Self> {
+        let key = key.trim();
+        if key.starts_with("SB-Mid-") {
+            Some(MidtransEnvironment::Sandbox)
+        } else if key.starts_with("Mid-") {
+            Some(MidtransEnvironment::Production)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for MidtransEnvironment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MidtransEnvironment::Sandbox => write!(f, "sandbox"),
+            MidtransEnvironment::Production => write!(f, "production"),
+        }
+    }
+}
+
+/// Midtrans environment from `MIDTRANS_IS_PRODUCTION` (default sandbox)
+pub fn midtrans_environment_from_env() -> MidtransEnvironment {
+    midtrans_environment_from_lookup(|key| std::env::var(key).ok())
+}
+
+/// Midtrans environment using a custom variable lookup
+pub fn midtrans_environment_from_lookup<F>(lookup: F) -> MidtransEnvironment
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match lookup("MIDTRANS_IS_PRODUCTION") {
+        Some(v) if matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes") => {
+            MidtransEnvironment::Production
+        }
+        _ => MidtransEnvironment::Sandbox,
+    }
+}
+
+/// A Midtrans key issued for a different environment than the one configured
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{variable} is a {key_environment} key but Midtrans is configured for {environment}")]
+pub struct MidtransKeyMismatch {
+    pub variable: &'static str,
+    pub key_environment: MidtransEnvironment,
+    pub environment: MidtransEnvironment,
+}
+
+/// Keys whose prefix shows they belong to another environment, so test keys
+/// never meet production (or real keys the sandbox). Keys in an
+/// unrecognized format are not reported.
+pub fn midtrans_key_mismatches(
+    environment: MidtransEnvironment,
+    server_key: &str,
+    client_key: &str,
+) -> Vec<MidtransKeyMismatch> {
+    [("MIDTRANS_SERVER_KEY", server_key), ("MIDTRANS_CLIENT_KEY", client_key)]
+        .into_iter()
+        .filter_map(|(variable, key)| {
+            let key_environment = MidtransEnvironment::of_key(key)?;
+            (key_environment != environment).then_some(MidtransKeyMismatch {
+                variable,
+                key_environment,
+                environment,
+            })
+        })
+        .collect()
+}
+
+/// What upgrading to `new_plan` costs: prorated against an active
+/// subscription, or a full new period without one
+fn quote_upgrade(
+    current_sub: Option<&Subscription>,
+    new_plan: PlanTier,
+    now: DateTime<Utc>,
+    ppn_rate: f64,
+) -> Result<ProrationPreview, BillingError> {
+    match current_sub {
+        Some(sub) => {
+            let current_plan = PlanTier::parse(&sub.plan_tier).ok_or(BillingError::InvalidPlanTier)?;
+            pricing::calculate_proration(current_plan, new_plan, sub.current_period_end, now, ppn_rate)
+                .ok_or(BillingError::InvalidPlanTier)
+        }
+        None => Ok(pricing::full_period_quote(new_plan, ppn_rate)),
+    }
+}
+
+/// Midtrans line items for a charge; the PPN line is left out when no PPN
+/// is due, since item prices must sum to the gross amount
+fn snap_item_details(item: serde_json::Value, ppn: i64, ppn_rate: f64) -> Vec<serde_json::Value> {
+    let mut items = vec![item];
+    if ppn > 0 {
+        items.push(serde_json::json!({
+            "id": "ppn",
+            "price": ppn,
+            "quantity": 1,
+            "name": format!("PPN {}", format_ppn_rate(ppn_rate))
+        }));
+    }
+    items
+}
 
 /// Billing Service for Midtrans integration
 /// Requirements: 2.1, 2.3, 2.4, 2.5, 2.6, 3.1
@@ -133,6 +217,16 @@ pub struct BillingService {
     server_key: String,
     client_key: String,
     is_sandbox: bool,
+    /// Midtrans Core API base URL, used for refunds
+    core_api_url: String,
+    /// Header that may carry the webhook signature (`MIDTRANS_SIGNATURE_HEADER`)
+    signature_header: Option<String>,
+    /// Time after a period ends before downgrading (`SUBSCRIPTION_GRACE_DAYS`)
+    grace_period: Duration,
+    /// PPN rate for customers who aren't tax-exempt (`PPN_RATE`)
+    ppn_rate: f64,
+    /// Background queue for payment receipts; none means no email is sent
+    email_queue: Option<EmailQueue>,
 }
 
 impl BillingService {
@@ -143,14 +237,70 @@ impl BillingService {
             server_key,
             client_key,
             is_sandbox,
+            core_api_url: if is_sandbox {
+                "https://api.sandbox.midtrans.com".to_string()
+            } else {
+                "https://api.midtrans.com".to_string()
+            },
+            signature_header: std::env::var("MIDTRANS_SIGNATURE_HEADER")
+                .ok()
+                .map(|name| name.trim().to_ascii_lowercase())
+                .filter(|name| !name.is_empty()),
+            grace_period: grace_period_from_env(),
+            ppn_rate: pricing::ppn_rate_from_env(),
+            email_queue: None,
         }
     }
 
+    /// Charge PPN at a different rate
+    pub fn with_ppn_rate(mut self, ppn_rate: f64) -> Self {
+        self.ppn_rate = ppn_rate;
+        self
+    }
+
+    /// Email payment receipts through this queue after activation
+    pub fn with_email_queue(mut self, queue: EmailQueue) -> Self {
+        self.email_queue = Some(queue);
+        self
+    }
+
+    /// Send Core API calls (refunds) to a different base URL
+    pub fn with_core_api_url(mut self, url: impl Into<String>) -> Self {
+        self.core_api_url = url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Use a different grace period before downgrading expired subscriptions
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Also accept webhook signatures sent in this header
+    pub fn with_signature_header(mut self, header: Option<&str>) -> Self {
+        self.signature_header = header.map(str::to_ascii_lowercase);
+        self
+    }
+
+    /// Header that may carry the webhook signature, if configured
+    pub fn signature_header(&self) -> Option<&str> {
+        self.signature_header.as_deref()
+    }
+
     /// Get reference to the database pool
     pub fn pool(&self) -> &PgPool {
         &self.pool
     }
 
+    /// Whether the user is exempt from PPN
+    async fn is_tax_exempt(&self, user_id: Uuid) -> Result<bool, BillingError> {
+        let exempt: Option<bool> = sqlx::query_scalar("SELECT tax_exempt FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(exempt.unwrap_or(false))
+    }
+
     fn snap_url(&self) -> &str {
         if self.is_sandbox {
             "https://app.sandbox.midtrans.com/snap/v1/transactions"
@@ -171,7 +321,9 @@ impl BillingService {
             return Err(BillingError::InvalidPlanTier);
         }
 
-        let (subtotal, ppn, total) = calculate_total_with_ppn(plan.price_idr());
+        let tax_exempt = self.is_tax_exempt(user_id).await?;
+        let ppn_rate = effective_ppn_rate(self.ppn_rate, tax_exempt);
+        let (subtotal, ppn, total) = calculate_total_with_ppn_at(plan.price_idr(), ppn_rate);
         let order_id = format!("WEB-{}-{}", Utc::now().format("%Y%m%d%H%M%S"), &user_id.to_string()[..8]);
 
         // Create pending subscription in database
@@ -181,8 +333,8 @@ impl BillingService {
         
         sqlx::query(
             r#"
-            INSERT INTO subscriptions (id, user_id, plan_tier, price_idr, status, midtrans_order_id, current_period_start, current_period_end, created_at, updated_at)
-            VALUES ($1, $2, $3::plan_tier, $4, 'pending', $5, $6, $7, NOW(), NOW())
+            INSERT INTO subscriptions (id, user_id, plan_tier, price_idr, status, midtrans_order_id, current_period_start, current_period_end, tax_exempt, created_at, updated_at)
+            VALUES ($1, $2, $3::plan_tier, $4, 'pending', $5, $6, $7, $8, NOW(), NOW())
             "#,
         )
         .bind(subscription_id)
@@ -192,6 +344,7 @@ impl BillingService {
         .bind(&order_id)
         .bind(now)
         .bind(period_end)
+        .bind(tax_exempt)
         .execute(&self.pool)
         .await?;
 
@@ -201,17 +354,12 @@ impl BillingService {
                 "order_id": order_id,
                 "gross_amount": total
             },
-            "item_details": [{
+            "item_details": snap_item_details(serde_json::json!({
                 "id": plan.as_str(),
                 "price": subtotal,
                 "quantity": 1,
                 "name": format!("Webrana {} Plan", plan.as_str().to_uppercase())
-            }, {
-                "id": "ppn",
-                "price": ppn,
-                "quantity": 1,
-                "name": "PPN 11%"
-            }],
+            }), ppn, ppn_rate),
             "customer_details": {
                 "email": user_email
             },
@@ -265,23 +413,38 @@ impl BillingService {
     /// Requirements: 2.4, 2.5
     /// Property 6: Webhook Signature Verification
     pub fn verify_signature(&self, webhook: &MidtransWebhook) -> bool {
+        self.expected_signature(webhook) == webhook.signature_key
+    }
+
+    /// Verify a webhook signed in either the body (`signature_key`) or the
+    /// configured signature header
+    pub fn verify_webhook(&self, webhook: &MidtransWebhook, header_signature: Option<&str>) -> bool {
+        let expected = self.expected_signature(webhook);
+        let header_valid = self.signature_header.is_some()
+            && header_signature.is_some_and(|signature| signature.trim() == expected);
+        expected == webhook.signature_key || header_valid
+    }
+
+    fn expected_signature(&self, webhook: &MidtransWebhook) -> String {
         let signature_input = format!(
             "{}{}{}{}",
             webhook.order_id, webhook.status_code, webhook.gross_amount, self.server_key
         );
-        
+
         let mut hasher = Sha512::new();
         hasher.update(signature_input.as_bytes());
-        let computed = format!("{:x}", hasher.finalize());
-        
-        computed == webhook.signature_key
+        format!("{:x}", hasher.finalize())
     }
 
     /// Handle Midtrans webhook notification
     /// Requirements: 2.4, 2.6, 3.1
-    pub async fn handle_webhook(&self, webhook: MidtransWebhook) -> Result<(), BillingError> {
+    pub async fn handle_webhook(
+        &self,
+        webhook: MidtransWebhook,
+        header_signature: Option<&str>,
+    ) -> Result<(), BillingError> {
         // Verify signature first
-        if !self.verify_signature(&webhook) {
+        if !self.verify_webhook(&webhook, header_signature) {
             tracing::warn!(
                 order_id = %webhook.order_id,
                 "Invalid webhook signature - potential security threat"
@@ -326,7 +489,7 @@ impl BillingService {
         // Get subscription and user info
         let row = sqlx::query(
             r#"
-            SELECT s.id, s.user_id, s.plan_tier::text as plan_tier, s.price_idr
+            SELECT s.id, s.user_id, s.plan_tier::text as plan_tier, s.price_idr, s.tax_exempt
             FROM subscriptions s
             WHERE s.midtrans_order_id = $1 AND s.status = 'pending'
             "#,
@@ -340,31 +503,47 @@ impl BillingService {
         let user_id: Uuid = row.get("user_id");
         let plan_tier: String = row.get("plan_tier");
         let price_idr: i64 = row.get("price_idr");
+        let ppn_rate = effective_ppn_rate(self.ppn_rate, row.get("tax_exempt"));
 
         // Update subscription to active
-        sqlx::query(
-            r#"
-            UPDATE subscriptions
-            SET status = 'active', midtrans_transaction_id = $1, current_period_start = $2, current_period_end = $3, updated_at = NOW()
-            WHERE id = $4
-            "#,
-        )
-        .bind(transaction_id)
-        .bind(now)
-        .bind(end_date)
-        .bind(subscription_id)
-        .execute(&self.pool)
+        retry_db(|| {
+            sqlx::query(
+                r#"
+                UPDATE subscriptions
+                SET status = 'active', midtrans_transaction_id = $1, current_period_start = $2, current_period_end = $3, updated_at = NOW()
+                WHERE id = $4
+                "#,
+            )
+            .bind(transaction_id)
+            .bind(now)
+            .bind(end_date)
+            .bind(subscription_id)
+            .execute(&self.pool)
+        })
         .await?;
 
-        // Update user plan tier
-        sqlx::query("UPDATE users SET plan_tier = $1::plan_tier, updated_at = NOW() WHERE id = $2")
-            .bind(&plan_tier)
+        // A renewal paid during the grace period replaces the past-due subscription
+        retry_db(|| {
+            sqlx::query(
+                "UPDATE subscriptions SET status = 'expired', updated_at = NOW() WHERE user_id = $1 AND status = 'past_due'",
+            )
             .bind(user_id)
             .execute(&self.pool)
-            .await?;
+        })
+        .await?;
+
+        // Update user plan tier
+        retry_db(|| {
+            sqlx::query("UPDATE users SET plan_tier = $1::plan_tier, updated_at = NOW() WHERE id = $2")
+                .bind(&plan_tier)
+                .bind(user_id)
+                .execute(&self.pool)
+        })
+        .await?;
 
         // Generate invoice
-        self.generate_invoice(user_id, subscription_id, price_idr, transaction_id, payment_type)
+        let invoice_number = self
+            .generate_invoice(user_id, subscription_id, price_idr, ppn_rate, transaction_id, payment_type)
             .await?;
 
         tracing::info!(
@@ -374,8 +553,47 @@ impl BillingService {
             "Subscription activated"
         );
 
+        self.queue_payment_receipt(user_id, &plan_tier, price_idr, &invoice_number)
+            .await;
+
         Ok(())
     }
+
+    /// Queue the payment-success email; failures are logged, never returned,
+    /// so the webhook still succeeds
+    async fn queue_payment_receipt(&self, user_id: Uuid, plan_tier: &str, total_idr: i64, invoice_number: &str) {
+        let Some(queue) = &self.email_queue else {
+            return;
+        };
+
+        let (email, locale): (String, String) = match sqlx::query_as("SELECT email, locale FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(recipient) => recipient,
+            Err(e) => {
+                tracing::error!(user_id = %user_id, error = %e, "Failed to look up email for payment receipt");
+                return;
+            }
+        };
+
+        let queued = queue.enqueue(EmailRequest {
+            to: email,
+            to_name: None,
+            template: EmailTemplate::PaymentSuccess,
+            data: EmailData {
+                plan_name: Some(plan_tier.to_string()),
+                amount: Some(format_rupiah(total_idr)),
+                invoice_number: Some(invoice_number.to_string()),
+                ..Default::default()
+            },
+            language: locale,
+        });
+        if queued {
+            tracing::info!(user_id = %user_id, invoice_number = %invoice_number, "Payment receipt email queued");
+        }
+    }
     
     /// Generate invoice after payment
     /// Requirements: 4.2, 4.3
@@ -384,12 +602,12 @@ impl BillingService {
         user_id: Uuid,
         subscription_id: Uuid,
         total_idr: i64,
+        ppn_rate: f64,
         transaction_id: &str,
         payment_type: &str,
-    ) -> Result<Uuid, BillingError> {
+    ) -> Result<String, BillingError> {
         let now = Utc::now();
-        let ppn = (total_idr as f64 * PPN_RATE / (1.0 + PPN_RATE)).round() as i64;
-        let subtotal = total_idr - ppn;
+        let (subtotal, ppn) = split_ppn_inclusive_at(total_idr, ppn_rate);
         
         // Generate invoice number: WEB-YYYY-MM-XXX
         let invoice_number = format!(
@@ -399,27 +617,29 @@ impl BillingService {
         );
         
         let invoice_id = Uuid::new_v4();
-        sqlx::query(
-            r#"
-            INSERT INTO invoices (id, user_id, subscription_id, invoice_number, subtotal_idr, ppn_idr, total_idr, payment_method, midtrans_transaction_id, status, paid_at, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, 'paid', $10, NOW())
-            "#,
-        )
-        .bind(invoice_id)
-        .bind(user_id)
-        .bind(subscription_id)
-        .bind(&invoice_number)
-        .bind(subtotal)
-        .bind(ppn)
-        .bind(total_idr)
-        .bind(payment_type)
-        .bind(transaction_id)
-        .bind(now)
-        .execute(&self.pool)
+        retry_db(|| {
+            sqlx::query(
+                r#"
+                INSERT INTO invoices (id, user_id, subscription_id, invoice_number, subtotal_idr, ppn_idr, total_idr, payment_method, midtrans_transaction_id, status, paid_at, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, 'paid', $10, NOW())
+                "#,
+            )
+            .bind(invoice_id)
+            .bind(user_id)
+            .bind(subscription_id)
+            .bind(&invoice_number)
+            .bind(subtotal)
+            .bind(ppn)
+            .bind(total_idr)
+            .bind(payment_type)
+            .bind(transaction_id)
+            .bind(now)
+            .execute(&self.pool)
+        })
         .await?;
         
-        tracing::info!(invoice_number = %invoice_number, "Invoice generated");
-        Ok(invoice_id)
+        tracing::info!(invoice_id = %invoice_id, invoice_number = %invoice_number, "Invoice generated");
+        Ok(invoice_number)
     }
 
     /// Cancel pending subscription
@@ -480,6 +700,161 @@ impl BillingService {
         Ok(())
     }
 
+    /// Cancel a subscription immediately and refund its payment through
+    /// Midtrans, for chargebacks and support. The user is downgraded to Free
+    /// and a credit note reversing the paid invoice is recorded.
+    pub async fn refund_subscription(
+        &self,
+        subscription_id: Uuid,
+        reason: &str,
+    ) -> Result<RefundResult, BillingError> {
+        let row = sqlx::query(
+            r#"
+            SELECT user_id, plan_tier::text as plan_tier, price_idr, tax_exempt, midtrans_order_id
+            FROM subscriptions
+            WHERE id = $1 AND status IN ('active', 'past_due')
+            "#,
+        )
+        .bind(subscription_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(BillingError::SubscriptionNotFound)?;
+
+        let user_id: Uuid = row.get("user_id");
+        let plan_tier: String = row.get("plan_tier");
+        let price_idr: i64 = row.get("price_idr");
+        let order_id: String = row
+            .get::<Option<String>, _>("midtrans_order_id")
+            .ok_or_else(|| BillingError::MidtransApi("Subscription has no Midtrans order to refund".to_string()))?;
+
+        // Paid invoice being reversed; its split is what the credit note mirrors
+        let invoice = sqlx::query(
+            "SELECT id, subtotal_idr, ppn_idr, total_idr FROM invoices WHERE subscription_id = $1 AND kind = 'invoice' ORDER BY created_at DESC LIMIT 1",
+        )
+        .bind(subscription_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let (invoice_id, subtotal, ppn, total) = match invoice {
+            Some(r) => (Some(r.get::<Uuid, _>("id")), r.get("subtotal_idr"), r.get("ppn_idr"), r.get("total_idr")),
+            None => {
+                let ppn_rate = effective_ppn_rate(self.ppn_rate, row.get("tax_exempt"));
+                let (subtotal, ppn) = split_ppn_inclusive_at(price_idr, ppn_rate);
+                (None, subtotal, ppn, price_idr)
+            }
+        };
+
+        self.request_midtrans_refund(&order_id, total, reason).await?;
+
+        // Midtrans has refunded; from here on only local state changes
+        retry_db(|| {
+            sqlx::query(
+                r#"
+                UPDATE subscriptions
+                SET status = 'cancelled', cancelled_at = NOW(), refunded_at = NOW(), cancel_at_period_end = false, updated_at = NOW()
+                WHERE id = $1
+                "#,
+            )
+            .bind(subscription_id)
+            .execute(&self.pool)
+        })
+        .await?;
+
+        retry_db(|| {
+            sqlx::query("UPDATE users SET plan_tier = 'free'::plan_tier, updated_at = NOW() WHERE id = $1")
+                .bind(user_id)
+                .execute(&self.pool)
+        })
+        .await?;
+
+        let now = Utc::now();
+        let credit_note_number = format!("CN-{}-{:03}", now.format("%Y-%m"), now.timestamp_millis() % 1000);
+        retry_db(|| {
+            sqlx::query(
+                r#"
+                INSERT INTO invoices (user_id, subscription_id, invoice_number, subtotal_idr, ppn_idr, total_idr, payment_method, status, paid_at, kind, credited_invoice_id, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, 'midtrans_refund', 'refunded', $7, 'credit_note', $8, NOW())
+                "#,
+            )
+            .bind(user_id)
+            .bind(subscription_id)
+            .bind(&credit_note_number)
+            .bind(-subtotal)
+            .bind(-ppn)
+            .bind(-total)
+            .bind(now)
+            .bind(invoice_id)
+            .execute(&self.pool)
+        })
+        .await?;
+
+        tracing::info!(
+            order_id = %order_id,
+            user_id = %user_id,
+            plan = %plan_tier,
+            refund_amount = total,
+            credit_note = %credit_note_number,
+            "Subscription refunded and cancelled"
+        );
+
+        Ok(RefundResult {
+            subscription_id,
+            refund_amount: total,
+            credit_note_number,
+        })
+    }
+
+    /// Ask Midtrans to refund `amount` of a settled order
+    async fn request_midtrans_refund(&self, order_id: &str, amount: i64, reason: &str) -> Result<(), BillingError> {
+        let auth = base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            format!("{}:", self.server_key),
+        );
+
+        let response = self
+            .http_client
+            .post(format!("{}/v2/{}/refund", self.core_api_url, order_id))
+            .header("Authorization", format!("Basic {}", auth))
+            .header("Content-Type", "application/json")
+            .json(&serde_json::json!({
+                // Lets Midtrans de-duplicate a retried refund
+                "refund_key": format!("{}-refund", order_id),
+                "amount": amount,
+                "reason": reason
+            }))
+            .send()
+            .await
+            .map_err(|e| BillingError::MidtransApi(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(BillingError::MidtransApi(error_text));
+        }
+
+        // The Core API reports failures in the body with an HTTP 200
+        let body: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| BillingError::MidtransApi(e.to_string()))?;
+        if body["status_code"].as_str() != Some("200") {
+            let message = body["status_message"].as_str().unwrap_or("Refund rejected");
+            return Err(BillingError::MidtransApi(message.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Preview the charge for upgrading, without creating an order.
+    /// Uses the same quote as `upgrade_subscription`.
+    pub async fn preview_upgrade(
+        &self,
+        user_id: Uuid,
+        new_plan: PlanTier,
+    ) -> Result<ProrationPreview, BillingError> {
+        let current_sub = self.get_subscription(user_id).await?;
+        let ppn_rate = effective_ppn_rate(self.ppn_rate, self.is_tax_exempt(user_id).await?);
+        quote_upgrade(current_sub.as_ref(), new_plan, Utc::now(), ppn_rate)
+    }
+
     /// Upgrade subscription to a higher tier with proration
     /// Requirements: 3.4 - Calculate prorated amount for remaining days
     pub async fn upgrade_subscription(
@@ -490,7 +865,11 @@ impl BillingService {
     ) -> Result<UpgradeResult, BillingError> {
         // Get current active subscription
         let current_sub = self.get_subscription(user_id).await?;
-        
+        let now = Utc::now();
+        let tax_exempt = self.is_tax_exempt(user_id).await?;
+        let ppn_rate = effective_ppn_rate(self.ppn_rate, tax_exempt);
+        let quote = quote_upgrade(current_sub.as_ref(), new_plan, now, ppn_rate)?;
+
         let current_sub = match current_sub {
             Some(sub) => sub,
             None => {
@@ -498,35 +877,19 @@ impl BillingService {
                 let snap_token = self.create_subscription(user_id, new_plan, user_email).await?;
                 return Ok(UpgradeResult {
                     prorated_amount: 0,
-                    new_total: calculate_total_with_ppn(new_plan.price_idr()).2,
+                    new_total: quote.total,
                     snap_token: Some(snap_token),
-                    remaining_days: 30,
+                    remaining_days: quote.remaining_days as i32,
                 });
             }
         };
 
-        // Parse current plan tier
-        let current_plan = match current_sub.plan_tier.as_str() {
-            "free" => PlanTier::Free,
-            "starter" => PlanTier::Starter,
-            "pro" => PlanTier::Pro,
-            "team" => PlanTier::Team,
-            _ => return Err(BillingError::InvalidPlanTier),
-        };
-
-        // Can't downgrade or stay same
-        if new_plan.price_idr() <= current_plan.price_idr() {
-            return Err(BillingError::InvalidPlanTier);
-        }
-
-        // Calculate remaining days
-        let now = Utc::now();
-        let remaining_days = (current_sub.current_period_end - now).num_days().max(0);
-
-        // Calculate prorated amount: (new_price - old_price) * (remaining_days / 30)
-        let price_diff = new_plan.price_idr() - current_plan.price_idr();
-        let prorated_base = ((price_diff as f64 * remaining_days as f64) / 30.0).round() as i64;
-        let (_, ppn, prorated_total) = calculate_total_with_ppn(prorated_base);
+        let ProrationPreview {
+            prorated_base,
+            ppn,
+            total: prorated_total,
+            remaining_days,
+        } = quote;
 
         // Create order for prorated amount
         let order_id = format!("WEB-UPG-{}-{}", Utc::now().format("%Y%m%d%H%M%S"), &user_id.to_string()[..8]);
@@ -537,8 +900,8 @@ impl BillingService {
         
         sqlx::query(
             r#"
-            INSERT INTO subscriptions (id, user_id, plan_tier, price_idr, status, midtrans_order_id, current_period_start, current_period_end, is_upgrade, previous_subscription_id, created_at, updated_at)
-            VALUES ($1, $2, $3::plan_tier, $4, 'pending', $5, $6, $7, true, $8, NOW(), NOW())
+            INSERT INTO subscriptions (id, user_id, plan_tier, price_idr, status, midtrans_order_id, current_period_start, current_period_end, is_upgrade, previous_subscription_id, tax_exempt, created_at, updated_at)
+            VALUES ($1, $2, $3::plan_tier, $4, 'pending', $5, $6, $7, true, $8, $9, NOW(), NOW())
             "#,
         )
         .bind(subscription_id)
@@ -549,6 +912,7 @@ impl BillingService {
         .bind(now)
         .bind(period_end)
         .bind(Uuid::parse_str(&current_sub.id.to_string()).ok())
+        .bind(tax_exempt)
         .execute(&self.pool)
         .await?;
 
@@ -558,17 +922,12 @@ impl BillingService {
                 "order_id": order_id,
                 "gross_amount": prorated_total
             },
-            "item_details": [{
+            "item_details": snap_item_details(serde_json::json!({
                 "id": format!("upgrade-{}", new_plan.as_str()),
                 "price": prorated_base,
                 "quantity": 1,
                 "name": format!("Upgrade to {} Plan (Prorated {} days)", new_plan.as_str().to_uppercase(), remaining_days)
-            }, {
-                "id": "ppn",
-                "price": ppn,
-                "quantity": 1,
-                "name": "PPN 11%"
-            }],
+            }), ppn, ppn_rate),
             "customer_details": {
                 "email": user_email
             },
@@ -613,7 +972,7 @@ impl BillingService {
 
         tracing::info!(
             user_id = %user_id,
-            from_plan = %current_plan.as_str(),
+            from_plan = %current_sub.plan_tier,
             to_plan = %new_plan.as_str(),
             prorated_amount = prorated_total,
             remaining_days = remaining_days,
@@ -636,18 +995,39 @@ impl BillingService {
     /// Requirements: 3.3 - Downgrade to Free tier on expiration
     /// This should be called by a scheduled task (cron job) daily
     pub async fn check_expired_subscriptions(&self) -> Result<ExpiredSubscriptionsResult, BillingError> {
-        let now = Utc::now();
-        
-        // Find all active subscriptions that have expired
+        self.check_expired_subscriptions_at(Utc::now()).await
+    }
+
+    /// Mark subscriptions whose period ended before `now` as past due, and
+    /// downgrade those still past due once the grace period has passed
+    pub async fn check_expired_subscriptions_at(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<ExpiredSubscriptionsResult, BillingError> {
+        // Ended subscriptions keep their tier during the grace period
+        let past_due_count = sqlx::query(
+            r#"
+            UPDATE subscriptions
+            SET status = 'past_due', updated_at = NOW()
+            WHERE status = 'active'
+              AND current_period_end < $1
+            "#,
+        )
+        .bind(now)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        // Find all past due subscriptions whose grace period has passed
         let expired_rows = sqlx::query(
             r#"
             SELECT s.id, s.user_id, s.plan_tier::text as plan_tier
             FROM subscriptions s
-            WHERE s.status = 'active'
+            WHERE s.status = 'past_due'
               AND s.current_period_end < $1
             "#,
         )
-        .bind(now)
+        .bind(now - self.grace_period)
         .fetch_all(&self.pool)
         .await?;
 
@@ -678,7 +1058,7 @@ impl BillingService {
             tracing::info!(
                 user_id = %user_id,
                 old_plan = %plan_tier,
-                "Subscription expired, downgraded to Free tier"
+                "Subscription grace period ended, downgraded to Free tier"
             );
 
             expired_count += 1;
@@ -686,6 +1066,7 @@ impl BillingService {
         }
 
         Ok(ExpiredSubscriptionsResult {
+            past_due_count: past_due_count as i32,
             expired_count,
             downgraded_users,
         })
@@ -731,10 +1112,20 @@ impl BillingService {
 /// Result of expired subscriptions check
 #[derive(Debug, Serialize)]
 pub struct ExpiredSubscriptionsResult {
+    /// Subscriptions that ended and entered the grace period
+    pub past_due_count: i32,
     pub expired_count: i32,
     pub downgraded_users: Vec<Uuid>,
 }
 
+/// Result of an immediate refund and cancellation
+#[derive(Debug, Serialize)]
+pub struct RefundResult {
+    pub subscription_id: Uuid,
+    pub refund_amount: i64,
+    pub credit_note_number: String,
+}
+
 /// Result of subscription upgrade
 #[derive(Debug, Serialize)]
 pub struct UpgradeResult {
@@ -755,4 +1146,514 @@ pub struct ExpiringSubscription {
     pub expires_at: DateTime<Utc>,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::pricing::calculate_total_with_ppn;
+    use crate::test_support::insert_user;
+
+    #[test]
+    fn test_midtrans_key_environment_from_prefix() {
+        assert_eq!(MidtransEnvironment::of_key("SB-Mid-server-abc"), Some(MidtransEnvironment::Sandbox));
+        assert_eq!(MidtransEnvironment::of_key("Mid-client-abc"), Some(MidtransEnvironment::Production));
+        assert_eq!(MidtransEnvironment::of_key("your-midtrans-server-key"), None);
+
+        assert_eq!(midtrans_environment_from_lookup(|_| None), MidtransEnvironment::Sandbox);
+        assert_eq!(
+            midtrans_environment_from_lookup(|_| Some("true".to_string())),
+            MidtransEnvironment::Production
+        );
+    }
 
+    #[test]
+    fn test_midtrans_key_mismatch_detected() {
+        // Sandbox keys in production
+        let mismatches = midtrans_key_mismatches(MidtransEnvironment::Production, "SB-Mid-server-abc", "SB-Mid-client-abc");
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0].variable, "MIDTRANS_SERVER_KEY");
+        assert_eq!(
+            mismatches[0].to_string(),
+            "MIDTRANS_SERVER_KEY is a sandbox key but Midtrans is configured for production"
+        );
+
+        // A production key in the sandbox
+        let mismatches = midtrans_key_mismatches(MidtransEnvironment::Sandbox, "SB-Mid-server-abc", "Mid-client-abc");
+        assert_eq!(
+            mismatches,
+            vec![MidtransKeyMismatch {
+                variable: "MIDTRANS_CLIENT_KEY",
+                key_environment: MidtransEnvironment::Production,
+                environment: MidtransEnvironment::Sandbox,
+            }]
+        );
+
+        // Matching or unrecognized keys pass
+        assert!(midtrans_key_mismatches(MidtransEnvironment::Production, "Mid-server-abc", "Mid-client-abc").is_empty());
+        assert!(midtrans_key_mismatches(MidtransEnvironment::Production, "server-key", "client-key").is_empty());
+    }
+
+    fn subscription(plan: &str, period_end: DateTime<Utc>) -> Subscription {
+        let now = Utc::now();
+        Subscription {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            plan_tier: plan.to_string(),
+            price_idr: 0,
+            status: "active".to_string(),
+            current_period_start: now,
+            current_period_end: period_end,
+            midtrans_order_id: None,
+            midtrans_transaction_id: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn billing_service(signature_header: Option<&str>) -> BillingService {
+        let pool = PgPool::connect_lazy("postgres://localhost/webrana_test").unwrap();
+        BillingService::new(pool, "server-key".to_string(), "client-key".to_string(), true)
+            .with_signature_header(signature_header)
+    }
+
+    fn webhook(signature_key: &str) -> MidtransWebhook {
+        MidtransWebhook {
+            order_id: "WBR-20241211-abc".to_string(),
+            status_code: "200".to_string(),
+            gross_amount: "55500.00".to_string(),
+            signature_key: signature_key.to_string(),
+            transaction_status: "settlement".to_string(),
+            transaction_id: "txn-1".to_string(),
+            payment_type: "qris".to_string(),
+        }
+    }
+
+    fn signature(webhook: &MidtransWebhook) -> String {
+        let mut hasher = Sha512::new();
+        hasher.update(format!("{}{}{}server-key", webhook.order_id, webhook.status_code, webhook.gross_amount));
+        format!("{:x}", hasher.finalize())
+    }
+
+    #[tokio::test]
+    async fn test_body_signature_verified() {
+        let service = billing_service(None);
+        let valid = signature(&webhook(""));
+
+        assert!(service.verify_webhook(&webhook(&valid), None));
+        assert!(!service.verify_webhook(&webhook("bogus"), None));
+        // Header signatures are ignored unless a header is configured
+        assert!(!service.verify_webhook(&webhook(""), Some(&valid)));
+    }
+
+    #[tokio::test]
+    async fn test_header_signature_verified_when_configured() {
+        let service = billing_service(Some("X-Midtrans-Signature"));
+        assert_eq!(service.signature_header(), Some("x-midtrans-signature"));
+        let valid = signature(&webhook(""));
+
+        assert!(service.verify_webhook(&webhook(""), Some(&valid)));
+        assert!(!service.verify_webhook(&webhook(""), Some("bogus")));
+        assert!(!service.verify_webhook(&webhook("bogus"), None));
+        // The body field still works alongside the header
+        assert!(service.verify_webhook(&webhook(&valid), Some("bogus")));
+    }
+
+    #[test]
+    fn test_webhook_without_body_signature_parses() {
+        let webhook: MidtransWebhook = serde_json::from_value(serde_json::json!({
+            "order_id": "WBR-1",
+            "status_code": "200",
+            "gross_amount": "55500.00",
+            "transaction_status": "settlement",
+            "transaction_id": "txn-1",
+            "payment_type": "qris"
+        }))
+        .unwrap();
+        assert!(webhook.signature_key.is_empty());
+        assert_eq!(
+            (webhook.transaction_status.as_str(), webhook.transaction_id.as_str(), webhook.payment_type.as_str()),
+            ("settlement", "txn-1", "qris")
+        );
+    }
+
+    #[test]
+    fn test_quote_without_subscription_is_full_period() {
+        let quote = quote_upgrade(None, PlanTier::Pro, Utc::now(), pricing::PPN_RATE).unwrap();
+        let (base, ppn, total) = calculate_total_with_ppn(PlanTier::Pro.price_idr());
+        assert_eq!((quote.prorated_base, quote.ppn, quote.total), (base, ppn, total));
+        assert_eq!(quote.remaining_days, 30);
+    }
+
+    #[test]
+    fn test_quote_uses_subscription_plan_and_period() {
+        let now = Utc::now();
+        let period_end = now + Duration::days(20) + Duration::hours(1);
+        let sub = subscription("starter", period_end);
+
+        let quote = quote_upgrade(Some(&sub), PlanTier::Team, now, pricing::PPN_RATE).unwrap();
+        assert_eq!(
+            quote,
+            pricing::calculate_proration(PlanTier::Starter, PlanTier::Team, period_end, now, pricing::PPN_RATE).unwrap()
+        );
+        assert!(quote_upgrade(Some(&subscription("legacy", period_end)), PlanTier::Team, now, pricing::PPN_RATE).is_err());
+    }
+
+    #[test]
+    fn test_snap_items_label_configured_rate_and_skip_exempt_ppn() {
+        let plan = serde_json::json!({"id": "pro", "price": 99_000, "quantity": 1});
+
+        let items = snap_item_details(plan.clone(), 11_880, 0.12);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1]["name"], "PPN 12%");
+        assert_eq!(items[1]["price"], 11_880);
+
+        assert_eq!(snap_item_details(plan, 0, 0.0).len(), 1);
+    }
+
+    // Database-backed tests (see test_support for how to run them)
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_preview_upgrade_reads_active_subscription(pool: PgPool) {
+        let user_id = insert_user(&pool, "preview@example.com", crate::models::PlanTier::Starter).await;
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (user_id, plan_tier, price_idr, status, current_period_start, current_period_end)
+            VALUES ($1, 'starter', 54390, 'active', NOW(), NOW() + INTERVAL '15 days 1 hour')
+            "#,
+        )
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let service = BillingService::new(pool.clone(), "server-key".to_string(), "client-key".to_string(), true);
+        let preview = service.preview_upgrade(user_id, PlanTier::Pro).await.unwrap();
+
+        assert_eq!(preview.remaining_days, 15);
+        assert_eq!(preview.total, 27_750);
+        // Nothing is written
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM subscriptions WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_activate_subscription_upgrades_plan_and_invoices(pool: PgPool) {
+        let user_id = insert_user(&pool, "andi@example.com", crate::models::PlanTier::Free).await;
+        let (_, _, total) = calculate_total_with_ppn(PlanTier::Pro.price_idr());
+
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (user_id, plan_tier, price_idr, status, midtrans_order_id, current_period_start, current_period_end)
+            VALUES ($1, 'pro', $2, 'pending', 'WEB-TEST-1', NOW(), NOW() + INTERVAL '30 days')
+            "#,
+        )
+        .bind(user_id)
+        .bind(total)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let service = BillingService::new(pool.clone(), "server-key".to_string(), "client-key".to_string(), true);
+        service.activate_subscription("WEB-TEST-1", "txn-1", "qris").await.unwrap();
+
+        let status: String = sqlx::query_scalar(
+            "SELECT status::text FROM subscriptions WHERE midtrans_order_id = 'WEB-TEST-1'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(status, "active");
+
+        let plan: String = sqlx::query_scalar("SELECT plan_tier::text FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(plan, "pro");
+
+        let invoice_total: i64 = sqlx::query_scalar("SELECT total_idr FROM invoices WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(invoice_total, total);
+
+        // A second notification for the same order finds nothing pending
+        assert!(matches!(
+            service.activate_subscription("WEB-TEST-1", "txn-1", "qris").await,
+            Err(BillingError::SubscriptionNotFound)
+        ));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_tax_exempt_customer_invoiced_without_ppn(pool: PgPool) {
+        let user_id = insert_user(&pool, "pt-maju@example.com", crate::models::PlanTier::Free).await;
+        sqlx::query("UPDATE users SET tax_exempt = true WHERE id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let service = BillingService::new(pool.clone(), "server-key".to_string(), "client-key".to_string(), true)
+            .with_ppn_rate(0.12);
+        assert!(service.is_tax_exempt(user_id).await.unwrap());
+        assert_eq!(service.preview_upgrade(user_id, PlanTier::Team).await.unwrap().ppn, 0);
+
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (user_id, plan_tier, price_idr, status, midtrans_order_id, current_period_start, current_period_end, tax_exempt)
+            VALUES ($1, 'team', 299000, 'pending', 'WEB-TEST-EXEMPT', NOW(), NOW() + INTERVAL '30 days', true)
+            "#,
+        )
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        service.activate_subscription("WEB-TEST-EXEMPT", "txn-4", "bank_transfer").await.unwrap();
+
+        let (subtotal, ppn): (i64, i64) = sqlx::query_as("SELECT subtotal_idr, ppn_idr FROM invoices WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!((subtotal, ppn), (299_000, 0));
+
+        // Other customers pay the configured rate
+        let other = insert_user(&pool, "retail@example.com", crate::models::PlanTier::Free).await;
+        assert_eq!(service.preview_upgrade(other, PlanTier::Pro).await.unwrap().ppn, 11_880);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_activation_queues_payment_success_email(pool: PgPool) {
+        let user_id = insert_user(&pool, "sari@example.com", crate::models::PlanTier::Free).await;
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (user_id, plan_tier, price_idr, status, midtrans_order_id, current_period_start, current_period_end)
+            VALUES ($1, 'starter', 55500, 'pending', 'WEB-TEST-2', NOW(), NOW() + INTERVAL '30 days')
+            "#,
+        )
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let (queue, mut receiver) = crate::services::email_service::email_queue();
+        let service = BillingService::new(pool.clone(), "server-key".to_string(), "client-key".to_string(), true)
+            .with_email_queue(queue);
+        service.activate_subscription("WEB-TEST-2", "txn-2", "qris").await.unwrap();
+
+        let email = receiver.try_recv().expect("payment receipt queued");
+        assert_eq!(email.to, "sari@example.com");
+        assert_eq!(email.template, EmailTemplate::PaymentSuccess);
+        assert_eq!(email.data.plan_name.as_deref(), Some("starter"));
+        assert_eq!(email.data.amount.as_deref(), Some("Rp 55.500"));
+
+        let invoice_number: String = sqlx::query_scalar("SELECT invoice_number FROM invoices WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(email.data.invoice_number, Some(invoice_number));
+
+        // Activation still succeeds when the email worker is gone
+        drop(receiver);
+        let other = insert_user(&pool, "budi@example.com", crate::models::PlanTier::Free).await;
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (user_id, plan_tier, price_idr, status, midtrans_order_id, current_period_start, current_period_end)
+            VALUES ($1, 'starter', 55500, 'pending', 'WEB-TEST-3', NOW(), NOW() + INTERVAL '30 days')
+            "#,
+        )
+        .bind(other)
+        .execute(&pool)
+        .await
+        .unwrap();
+        service.activate_subscription("WEB-TEST-3", "txn-3", "qris").await.unwrap();
+    }
+
+    /// Refund requests received by the mock Midtrans Core API
+    type RefundRequests = std::sync::Arc<std::sync::Mutex<Vec<(String, serde_json::Value)>>>;
+
+    /// Start a mock Core API answering refunds with `status_code`
+    async fn mock_midtrans(status_code: &'static str) -> (String, RefundRequests) {
+        use axum::{extract::{Path, State}, routing::post, Json, Router};
+
+        let requests = RefundRequests::default();
+        let app = Router::new()
+            .route(
+                "/v2/:order_id/refund",
+                post(
+                    move |State(requests): State<RefundRequests>, Path(order_id): Path<String>, Json(body): Json<serde_json::Value>| async move {
+                        requests.lock().unwrap().push((order_id, body));
+                        Json(serde_json::json!({"status_code": status_code, "status_message": "Refund request is processed"}))
+                    },
+                ),
+            )
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (format!("http://{}", addr), requests)
+    }
+
+    /// A Pro user with a paid, active subscription for order `WEB-TEST-REFUND`
+    async fn paid_subscription(pool: &PgPool, service: &BillingService) -> (Uuid, Uuid) {
+        let user_id = insert_user(pool, "refund@example.com", crate::models::PlanTier::Free).await;
+        let subscription_id: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO subscriptions (user_id, plan_tier, price_idr, status, midtrans_order_id, current_period_start, current_period_end)
+            VALUES ($1, 'pro', 111000, 'pending', 'WEB-TEST-REFUND', NOW(), NOW() + INTERVAL '30 days')
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        service.activate_subscription("WEB-TEST-REFUND", "txn-refund", "qris").await.unwrap();
+        (user_id, subscription_id)
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_refund_cancels_downgrades_and_issues_credit_note(pool: PgPool) {
+        let (midtrans_url, requests) = mock_midtrans("200").await;
+        let service = BillingService::new(pool.clone(), "server-key".to_string(), "client-key".to_string(), true)
+            .with_core_api_url(midtrans_url);
+        let (user_id, subscription_id) = paid_subscription(&pool, &service).await;
+
+        let result = service.refund_subscription(subscription_id, "Chargeback").await.unwrap();
+        assert_eq!(result.refund_amount, 111_000);
+        assert!(result.credit_note_number.starts_with("CN-"));
+
+        let requests = requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, "WEB-TEST-REFUND");
+        assert_eq!(requests[0].1["amount"], 111_000);
+        assert_eq!(requests[0].1["reason"], "Chargeback");
+
+        assert_eq!(subscription_status(&pool, user_id).await, ("cancelled".to_string(), "free".to_string()));
+
+        let (subtotal, ppn, total, status, credited): (i64, i64, i64, String, Option<Uuid>) = sqlx::query_as(
+            "SELECT subtotal_idr, ppn_idr, total_idr, status, credited_invoice_id FROM invoices WHERE user_id = $1 AND kind = 'credit_note'",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let (paid_id, paid_subtotal, paid_ppn): (Uuid, i64, i64) = sqlx::query_as(
+            "SELECT id, subtotal_idr, ppn_idr FROM invoices WHERE user_id = $1 AND kind = 'invoice'",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((subtotal, ppn, total), (-paid_subtotal, -paid_ppn, -111_000));
+        assert_eq!(status, "refunded");
+        assert_eq!(credited, Some(paid_id));
+
+        // Already cancelled: nothing left to refund
+        assert!(matches!(
+            service.refund_subscription(subscription_id, "Chargeback").await,
+            Err(BillingError::SubscriptionNotFound)
+        ));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_rejected_refund_leaves_subscription_active(pool: PgPool) {
+        let (midtrans_url, requests) = mock_midtrans("412").await;
+        let service = BillingService::new(pool.clone(), "server-key".to_string(), "client-key".to_string(), true)
+            .with_core_api_url(midtrans_url);
+        let (user_id, subscription_id) = paid_subscription(&pool, &service).await;
+
+        assert!(matches!(
+            service.refund_subscription(subscription_id, "Support request").await,
+            Err(BillingError::MidtransApi(_))
+        ));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(subscription_status(&pool, user_id).await, ("active".to_string(), "pro".to_string()));
+
+        let credit_notes: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM invoices WHERE kind = 'credit_note'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(credit_notes, 0);
+    }
+
+    #[test]
+    fn test_grace_period_from_lookup() {
+        assert_eq!(grace_period_from_lookup(|_| None), Duration::days(DEFAULT_GRACE_PERIOD_DAYS));
+        assert_eq!(
+            grace_period_from_lookup(|key| (key == "SUBSCRIPTION_GRACE_DAYS").then(|| "5".to_string())),
+            Duration::days(5)
+        );
+        assert_eq!(
+            grace_period_from_lookup(|key| (key == "SUBSCRIPTION_GRACE_DAYS").then(|| "0".to_string())),
+            Duration::zero()
+        );
+    }
+
+    async fn subscription_status(pool: &PgPool, user_id: Uuid) -> (String, String) {
+        let status: String = sqlx::query_scalar("SELECT status::text FROM subscriptions WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        let plan: String = sqlx::query_scalar("SELECT plan_tier::text FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        (status, plan)
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_expired_subscription_keeps_tier_during_grace(pool: PgPool) {
+        let user_id = insert_user(&pool, "grace@example.com", crate::models::PlanTier::Pro).await;
+        let period_end = Utc::now() - Duration::hours(1);
+        sqlx::query(
+            r#"
+            INSERT INTO subscriptions (user_id, plan_tier, price_idr, status, current_period_start, current_period_end)
+            VALUES ($1, 'pro', 111000, 'active', $2, $3)
+            "#,
+        )
+        .bind(user_id)
+        .bind(period_end - Duration::days(30))
+        .bind(period_end)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let service = BillingService::new(pool.clone(), "server-key".to_string(), "client-key".to_string(), true)
+            .with_grace_period(Duration::days(2));
+
+        // Within grace: past due, still on Pro
+        let result = service.check_expired_subscriptions_at(Utc::now()).await.unwrap();
+        assert_eq!((result.past_due_count, result.expired_count), (1, 0));
+        assert_eq!(subscription_status(&pool, user_id).await, ("past_due".to_string(), "pro".to_string()));
+
+        let result = service
+            .check_expired_subscriptions_at(period_end + Duration::days(2) - Duration::minutes(1))
+            .await
+            .unwrap();
+        assert_eq!(result.expired_count, 0);
+
+        // After grace: expired and downgraded
+        let result = service
+            .check_expired_subscriptions_at(period_end + Duration::days(2) + Duration::minutes(1))
+            .await
+            .unwrap();
+        assert_eq!(result.downgraded_users, vec![user_id]);
+        assert_eq!(subscription_status(&pool, user_id).await, ("expired".to_string(), "free".to_string()));
+    }
+}
@@ -0,0 +1,139 @@
+//! Control character sanitization for chat message content.
+//!
+//! Some providers reject requests whose messages contain null bytes or other
+//! control characters. Disallowed characters are stripped (default) or the
+//! request is rejected. Newlines, carriage returns and tabs are kept, as is
+//! all other unicode.
+//!
+//! Configuration:
+//! - `CONTROL_CHAR_MODE`: `strip` (default) or `reject`
+
+/// What to do with disallowed control characters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SanitizeMode {
+    #[default]
+    Strip,
+    Reject,
+}
+
+/// A message contained a disallowed control character (reject mode)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Message {message_index} contains a disallowed control character (U+{:04X})", *.character as u32)]
+pub struct ControlCharError {
+    pub message_index: usize,
+    pub character: char,
+}
+
+/// Control characters providers may refuse; common whitespace is allowed
+pub fn is_disallowed(c: char) -> bool {
+    c.is_control() && !matches!(c, '\n' | '\r' | '\t')
+}
+
+/// Message content sanitizer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContentSanitizer {
+    pub mode: SanitizeMode,
+}
+
+impl ContentSanitizer {
+    /// Load sanitizer configuration from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load sanitizer configuration using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mode = match lookup("CONTROL_CHAR_MODE").map(|v| v.trim().to_lowercase()).as_deref() {
+            Some("reject") => SanitizeMode::Reject,
+            _ => SanitizeMode::Strip,
+        };
+        Self { mode }
+    }
+
+    /// Sanitize each message's content in place.
+    /// Returns the number of characters stripped.
+    pub fn sanitize<'a>(
+        &self,
+        contents: impl IntoIterator<Item = &'a mut String>,
+    ) -> Result<usize, ControlCharError> {
+        let mut stripped = 0;
+        for (message_index, content) in contents.into_iter().enumerate() {
+            let Some(character) = content.chars().find(|c| is_disallowed(*c)) else {
+                continue;
+            };
+
+            match self.mode {
+                SanitizeMode::Reject => {
+                    return Err(ControlCharError {
+                        message_index,
+                        character,
+                    })
+                }
+                SanitizeMode::Strip => {
+                    let before = content.chars().count();
+                    content.retain(|c| !is_disallowed(c));
+                    stripped += before - content.chars().count();
+                }
+            }
+        }
+        Ok(stripped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(content: &str) -> Vec<String> {
+        vec!["Be brief.".to_string(), content.to_string()]
+    }
+
+    fn sanitizer(mode: &str) -> ContentSanitizer {
+        ContentSanitizer::from_lookup(|key| (key == "CONTROL_CHAR_MODE").then(|| mode.to_string()))
+    }
+
+    #[test]
+    fn test_mode_from_env() {
+        assert_eq!(ContentSanitizer::from_lookup(|_| None).mode, SanitizeMode::Strip);
+        assert_eq!(sanitizer("REJECT").mode, SanitizeMode::Reject);
+        assert_eq!(sanitizer("bogus").mode, SanitizeMode::Strip);
+    }
+
+    #[test]
+    fn test_null_byte_stripped() {
+        let mut messages = messages("Halo\0 dunia\u{7}!");
+
+        assert_eq!(sanitizer("strip").sanitize(&mut messages), Ok(2));
+        assert_eq!(messages[1], "Halo dunia!");
+        assert_eq!(messages[0], "Be brief.");
+    }
+
+    #[test]
+    fn test_null_byte_rejected() {
+        let mut messages = messages("Halo\0 dunia");
+
+        let err = sanitizer("reject").sanitize(&mut messages).unwrap_err();
+        assert_eq!(
+            err,
+            ControlCharError {
+                message_index: 1,
+                character: '\0',
+            }
+        );
+        assert!(err.to_string().contains("U+0000"));
+        assert_eq!(messages[1], "Halo\0 dunia");
+    }
+
+    #[test]
+    fn test_whitespace_and_unicode_preserved() {
+        let content = "Baris satu\nbaris\tdua\r\n日本語 émoji 🚀 \u{200D}";
+        for mode in ["strip", "reject"] {
+            let mut messages = messages(content);
+            assert_eq!(sanitizer(mode).sanitize(&mut messages), Ok(0));
+            assert_eq!(messages[1], content);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Sampled request/response capture for debugging transformation bugs.
+//!
+//! For a tiny sampled share of chat completions, stores the client request,
+//! the transformed upstream request, and the response in `debug_captures`.
+//! Captures are off by default, never happen unless content logging is
+//! enabled, and are deleted after a TTL.
+//!
+//! Configuration:
+//! - `LOG_REQUEST_CONTENT`: master switch for storing message content (default `false`)
+//! - `DEBUG_CAPTURE_RATE`: share of requests captured, `0.0`-`1.0` (default `0.0`)
+//! - `DEBUG_CAPTURE_TTL_HOURS`: how long captures are kept (default `24`)
+
+use serde::Serialize;
+use sqlx::{PgPool, Row};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::services::task_manager::TaskManager;
+use crate::services::transformers::Provider;
+
+/// Default capture retention
+pub const DEFAULT_CAPTURE_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Interval between expired capture cleanups
+pub const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Debug capture configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugCaptureConfig {
+    pub content_logging: bool,
+    pub sample_rate: f64,
+    pub ttl: Duration,
+}
+
+impl Default for DebugCaptureConfig {
+    fn default() -> Self {
+        Self {
+            content_logging: false,
+            sample_rate: 0.0,
+            ttl: DEFAULT_CAPTURE_TTL,
+        }
+    }
+}
+
+impl DebugCaptureConfig {
+    /// Load configuration from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load configuration using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let defaults = Self::default();
+        Self {
+            content_logging: lookup("LOG_REQUEST_CONTENT")
+                .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(defaults.content_logging),
+            sample_rate: lookup("DEBUG_CAPTURE_RATE")
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .map(|rate| rate.clamp(0.0, 1.0))
+                .unwrap_or(defaults.sample_rate),
+            ttl: lookup("DEBUG_CAPTURE_TTL_HOURS")
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(|hours| Duration::from_secs(hours * 3600))
+                .unwrap_or(defaults.ttl),
+        }
+    }
+
+    /// Decide whether to capture a request, given a uniform roll in `[0, 1)`
+    pub fn should_capture(&self, roll: f64) -> bool {
+        self.content_logging && roll < self.sample_rate
+    }
+}
+
+/// A captured request/response pair
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugCapture {
+    pub user_id: Uuid,
+    pub provider: Provider,
+    pub model: String,
+    pub request_body: serde_json::Value,
+    pub upstream_body: Option<serde_json::Value>,
+    pub response_body: Option<serde_json::Value>,
+    pub status_code: u16,
+}
+
+/// Store a capture
+pub async fn save(pool: &PgPool, capture: &DebugCapture) -> Result<Uuid, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        INSERT INTO debug_captures (user_id, provider, model, request_body, upstream_body, response_body, status_code)
+        VALUES ($1, $2, $3, $4::jsonb, $5::jsonb, $6::jsonb, $7)
+        RETURNING id
+        "#,
+    )
+    .bind(capture.user_id)
+    .bind(capture.provider.name().to_lowercase())
+    .bind(&capture.model)
+    .bind(capture.request_body.to_string())
+    .bind(capture.upstream_body.as_ref().map(|v| v.to_string()))
+    .bind(capture.response_body.as_ref().map(|v| v.to_string()))
+    .bind(capture.status_code as i32)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.get("id"))
+}
+
+/// Store a capture in the background without blocking the response
+pub fn save_async(tasks: &TaskManager, pool: PgPool, capture: DebugCapture) {
+    tasks.spawn(async move {
+        if let Err(e) = save(&pool, &capture).await {
+            tracing::warn!(user_id = %capture.user_id, "Failed to store debug capture: {}", e);
+        }
+    });
+}
+
+/// Delete captures older than the TTL
+pub async fn cleanup_expired(pool: &PgPool, ttl: Duration) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "DELETE FROM debug_captures WHERE created_at < NOW() - make_interval(secs => $1)",
+    )
+    .bind(ttl.as_secs() as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Spawn background task that periodically deletes expired captures
+pub fn spawn_cleanup_worker(pool: PgPool, ttl: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match cleanup_expired(&pool, ttl).await {
+                Ok(deleted) if deleted > 0 => {
+                    tracing::info!(deleted, "Deleted expired debug captures");
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Failed to clean up debug captures: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(content_logging: bool, rate: f64) -> DebugCaptureConfig {
+        DebugCaptureConfig {
+            content_logging,
+            sample_rate: rate,
+            ttl: DEFAULT_CAPTURE_TTL,
+        }
+    }
+
+    const ROLLS: [f64; 5] = [0.0, 0.001, 0.5, 0.9, 0.999_999];
+
+    #[test]
+    fn test_full_sampling_captures_every_request() {
+        let config = config(true, 1.0);
+        assert!(ROLLS.iter().all(|&roll| config.should_capture(roll)));
+    }
+
+    #[test]
+    fn test_zero_sampling_never_captures() {
+        let config = config(true, 0.0);
+        assert!(ROLLS.iter().all(|&roll| !config.should_capture(roll)));
+    }
+
+    #[test]
+    fn test_no_capture_without_content_logging() {
+        let config = config(false, 1.0);
+        assert!(ROLLS.iter().all(|&roll| !config.should_capture(roll)));
+    }
+
+    #[test]
+    fn test_off_by_default() {
+        let config = DebugCaptureConfig::from_lookup(|_| None);
+        assert_eq!(config, DebugCaptureConfig::default());
+        assert!(!config.should_capture(0.0));
+    }
+
+    #[test]
+    fn test_from_lookup() {
+        let config = DebugCaptureConfig::from_lookup(|key| match key {
+            "LOG_REQUEST_CONTENT" => Some("true".to_string()),
+            "DEBUG_CAPTURE_RATE" => Some("0.01".to_string()),
+            "DEBUG_CAPTURE_TTL_HOURS" => Some("6".to_string()),
+            _ => None,
+        });
+
+        assert!(config.content_logging);
+        assert_eq!(config.sample_rate, 0.01);
+        assert_eq!(config.ttl, Duration::from_secs(6 * 3600));
+    }
+}
@@ -7,7 +7,9 @@ use chrono::{DateTime, Utc};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
 /// Email template types
@@ -19,6 +21,7 @@ pub enum EmailTemplate {
     QuotaWarning,
     QuotaExceeded,
     SubscriptionExpiring,
+    PaymentOverdue,
     OnboardingReminder,
 }
 
@@ -31,11 +34,66 @@ impl EmailTemplate {
             EmailTemplate::QuotaWarning => "quota_warning",
             EmailTemplate::QuotaExceeded => "quota_exceeded",
             EmailTemplate::SubscriptionExpiring => "subscription_expiring",
+            EmailTemplate::PaymentOverdue => "payment_overdue",
             EmailTemplate::OnboardingReminder => "onboarding_reminder",
         }
     }
 }
 
+/// Languages email templates are written in
+pub const SUPPORTED_LOCALES: &[&str] = &["id", "en"];
+
+/// Email language when no preference is known
+pub const DEFAULT_LOCALE: &str = "id";
+
+/// Fallback email language from `DEFAULT_EMAIL_LOCALE`
+pub fn default_locale_from_env() -> &'static str {
+    default_locale_from_lookup(|key| std::env::var(key).ok())
+}
+
+/// Fallback email language using a custom variable lookup.
+/// Unsupported values are logged and ignored.
+pub fn default_locale_from_lookup<F>(lookup: F) -> &'static str
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match lookup("DEFAULT_EMAIL_LOCALE") {
+        Some(value) => supported_locale(value.trim()).unwrap_or_else(|| {
+            tracing::warn!(locale = %value, "Ignoring unsupported DEFAULT_EMAIL_LOCALE");
+            DEFAULT_LOCALE
+        }),
+        None => DEFAULT_LOCALE,
+    }
+}
+
+/// The supported locale for a language tag (`en-US` is `en`; the legacy
+/// `in` is `id`), if any
+pub fn supported_locale(tag: &str) -> Option<&'static str> {
+    let primary = tag.split(['-', '_']).next()?.trim().to_ascii_lowercase();
+    let primary = if primary == "in" { "id".to_string() } else { primary };
+    SUPPORTED_LOCALES.iter().copied().find(|locale| *locale == primary)
+}
+
+/// Best-effort email language from an `Accept-Language` header: the
+/// supported language with the highest quality, `default` when none is
+pub fn locale_from_accept_language(header: Option<&str>, default: &'static str) -> &'static str {
+    let mut best: Option<(&'static str, f32)> = None;
+    for entry in header.unwrap_or_default().split(',') {
+        let mut params = entry.split(';');
+        let Some(locale) = params.next().and_then(supported_locale) else {
+            continue;
+        };
+        let quality = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+            .unwrap_or(1.0);
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((locale, quality));
+        }
+    }
+    best.map_or(default, |(locale, _)| locale)
+}
+
 /// Email send request
 #[derive(Debug, Clone)]
 pub struct EmailRequest {
@@ -46,6 +104,22 @@ pub struct EmailRequest {
     pub language: String, // "id" or "en"
 }
 
+impl EmailRequest {
+    /// Welcome email for a new user
+    pub fn welcome(email: &str, name: Option<String>, language: &str) -> Self {
+        Self {
+            to: email.to_string(),
+            to_name: name.clone(),
+            template: EmailTemplate::Welcome,
+            data: EmailData {
+                user_name: name,
+                ..Default::default()
+            },
+            language: language.to_string(),
+        }
+    }
+}
+
 /// Email template data
 #[derive(Debug, Clone, Serialize)]
 pub struct EmailData {
@@ -477,6 +551,47 @@ impl EmailService {
                 }
             }
 
+            EmailTemplate::PaymentOverdue => {
+                let days = request.data.days_remaining.unwrap_or(0);
+                let plan = request.data.plan_name.clone().unwrap_or_default();
+
+                if is_indonesian {
+                    (
+                        "Pembayaran Langganan Tertunda ⚠️".to_string(),
+                        format!(
+                            r#"<!DOCTYPE html>
+<html><head><meta charset="UTF-8"></head>
+<body style="font-family: Arial, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px;">
+<h1 style="color: #F59E0B;">Langganan Telah Berakhir ⚠️</h1>
+<p>Halo {},</p>
+<p>Masa langganan <strong>{}</strong> Anda telah berakhir. Akun Anda akan diturunkan ke paket Free dalam <strong>{} hari</strong> jika pembayaran belum diterima.</p>
+<p>Jika pembayaran Anda sedang diproses, abaikan email ini.</p>
+<a href="https://webrana.id/dashboard/billing" style="display: inline-block; background: #3B82F6; color: white; padding: 12px 24px; text-decoration: none; border-radius: 6px; margin-top: 20px;">Perpanjang Sekarang</a>
+<p style="margin-top: 20px;">Salam,<br>Tim Webrana</p>
+</body></html>"#,
+                            name, plan, days
+                        ),
+                    )
+                } else {
+                    (
+                        "Subscription Payment Overdue ⚠️".to_string(),
+                        format!(
+                            r#"<!DOCTYPE html>
+<html><head><meta charset="UTF-8"></head>
+<body style="font-family: Arial, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px;">
+<h1 style="color: #F59E0B;">Subscription Has Ended ⚠️</h1>
+<p>Hello {},</p>
+<p>Your <strong>{}</strong> subscription period has ended. Your account will be downgraded to the Free plan in <strong>{} days</strong> unless payment is received.</p>
+<p>If your payment is already being processed, you can ignore this email.</p>
+<a href="https://webrana.id/dashboard/billing" style="display: inline-block; background: #3B82F6; color: white; padding: 12px 24px; text-decoration: none; border-radius: 6px; margin-top: 20px;">Renew Now</a>
+<p style="margin-top: 20px;">Best regards,<br>The Webrana Team</p>
+</body></html>"#,
+                            name, plan, days
+                        ),
+                    )
+                }
+            }
+
             EmailTemplate::OnboardingReminder => {
                 if is_indonesian {
                     (
@@ -535,17 +650,7 @@ impl EmailService {
     /// Send welcome email
     /// Requirements: 7.2
     pub async fn send_welcome(&self, email: &str, name: Option<String>, language: &str) -> Result<(), EmailError> {
-        self.send_email(EmailRequest {
-            to: email.to_string(),
-            to_name: name.clone(),
-            template: EmailTemplate::Welcome,
-            data: EmailData {
-                user_name: name,
-                ..Default::default()
-            },
-            language: language.to_string(),
-        })
-        .await
+        self.send_email(EmailRequest::welcome(email, name, language)).await
     }
 
     /// Send quota warning email
@@ -596,6 +701,30 @@ impl EmailService {
         .await
     }
 
+    /// Send reminder during the grace period after a subscription ends
+    pub async fn send_payment_overdue(
+        &self,
+        email: &str,
+        name: Option<String>,
+        plan_name: &str,
+        days_until_downgrade: i32,
+        language: &str,
+    ) -> Result<(), EmailError> {
+        self.send_email(EmailRequest {
+            to: email.to_string(),
+            to_name: name.clone(),
+            template: EmailTemplate::PaymentOverdue,
+            data: EmailData {
+                user_name: name,
+                plan_name: Some(plan_name.to_string()),
+                days_remaining: Some(days_until_downgrade),
+                ..Default::default()
+            },
+            language: language.to_string(),
+        })
+        .await
+    }
+
     /// Send onboarding reminder email for users who haven't added API key
     /// Requirements: 5.5
     pub async fn send_onboarding_reminder(
@@ -617,3 +746,82 @@ impl EmailService {
         .await
     }
 }
+
+/// Most queued emails a worker takes at once
+const EMAIL_BATCH_SIZE: usize = 50;
+
+/// Handle for queueing emails to be sent in the background, so callers such
+/// as webhook handlers don't wait on the email API
+#[derive(Clone)]
+pub struct EmailQueue {
+    sender: mpsc::UnboundedSender<EmailRequest>,
+}
+
+/// Receiving end of an [`EmailQueue`], drained by [`spawn_email_worker`]
+pub type EmailReceiver = mpsc::UnboundedReceiver<EmailRequest>;
+
+/// Create an email queue and its receiver
+pub fn email_queue() -> (EmailQueue, EmailReceiver) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    (EmailQueue { sender }, receiver)
+}
+
+impl EmailQueue {
+    /// Queue an email; returns false when the worker has stopped
+    pub fn enqueue(&self, request: EmailRequest) -> bool {
+        let template = request.template.as_str();
+        match self.sender.send(request) {
+            Ok(()) => {
+                tracing::debug!(template, "Email queued");
+                true
+            }
+            Err(_) => {
+                tracing::error!(template, "Email worker stopped; email dropped");
+                false
+            }
+        }
+    }
+}
+
+/// Send queued emails in batches until every queue handle is dropped.
+/// Failures are logged by `send_email` and never reach the enqueuer.
+pub fn spawn_email_worker(service: Arc<EmailService>, mut receiver: EmailReceiver) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut batch = Vec::with_capacity(EMAIL_BATCH_SIZE);
+        while receiver.recv_many(&mut batch, EMAIL_BATCH_SIZE).await > 0 {
+            tracing::debug!(count = batch.len(), "Sending queued emails");
+            for request in batch.drain(..) {
+                let (to, template) = (request.to.clone(), request.template.as_str());
+                if let Err(e) = service.send_email(request).await {
+                    tracing::error!(to = %to, template, error = %e, "Queued email failed");
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_from_accept_language() {
+        assert_eq!(locale_from_accept_language(Some("en"), "id"), "en");
+        assert_eq!(locale_from_accept_language(Some("en-US,en;q=0.9"), "id"), "en");
+        assert_eq!(locale_from_accept_language(Some("id-ID,id;q=0.9,en-US;q=0.8"), "en"), "id");
+        assert_eq!(locale_from_accept_language(Some("fr-FR, en;q=0.5, id;q=0.7"), "id"), "id");
+        assert_eq!(locale_from_accept_language(Some("in"), "en"), "id");
+
+        // Nothing supported, or only refused languages: the default
+        assert_eq!(locale_from_accept_language(Some("fr, de;q=0.8"), "id"), "id");
+        assert_eq!(locale_from_accept_language(Some("en;q=0"), "id"), "id");
+        assert_eq!(locale_from_accept_language(None, "id"), "id");
+    }
+
+    #[test]
+    fn test_default_locale_from_lookup() {
+        assert_eq!(default_locale_from_lookup(|_| None), "id");
+        assert_eq!(default_locale_from_lookup(|_| Some("en".to_string())), "en");
+        assert_eq!(default_locale_from_lookup(|_| Some("fr".to_string())), "id");
+    }
+}
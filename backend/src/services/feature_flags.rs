@@ -0,0 +1,222 @@
+//! Database-backed feature flags.
+//!
+//! Flags in `feature_flags` switch optional proxy features on or off without
+//! a redeploy. A flag with no row is enabled, so a feature's own configuration
+//! (e.g. `MIRROR_MODEL`) still decides whether it runs; a flag can only
+//! turn a configured feature off. The table is cached in memory and refreshed
+//! periodically, like model routing overrides.
+//!
+//! Configuration:
+//! - `FEATURE_FLAGS_REFRESH_SECS`: cache refresh interval (default `60`)
+
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Default interval between flag cache refreshes
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Features that can be toggled at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeatureFlag {
+    /// Shadow mirroring of sampled requests
+    MirrorMode,
+    /// Retrying safety-blocked completions on the fallback model
+    SafetyFallback,
+    /// Sampled request/response debug captures
+    DebugCapture,
+}
+
+impl FeatureFlag {
+    pub const ALL: [FeatureFlag; 3] = [
+        FeatureFlag::MirrorMode,
+        FeatureFlag::SafetyFallback,
+        FeatureFlag::DebugCapture,
+    ];
+
+    /// Name as stored in `feature_flags.name`
+    pub fn name(&self) -> &'static str {
+        match self {
+            FeatureFlag::MirrorMode => "mirror_mode",
+            FeatureFlag::SafetyFallback => "safety_fallback",
+            FeatureFlag::DebugCapture => "debug_capture",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|flag| flag.name() == name)
+    }
+}
+
+/// Current state of a flag
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeatureFlagState {
+    pub name: &'static str,
+    pub enabled: bool,
+}
+
+/// In-memory flag table shared by all requests
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags {
+    overrides: Arc<RwLock<HashMap<FeatureFlag, bool>>>,
+}
+
+impl FeatureFlags {
+    /// Whether a feature is enabled (features without a stored flag are)
+    pub fn is_enabled(&self, flag: FeatureFlag) -> bool {
+        self.overrides
+            .read()
+            .ok()
+            .and_then(|overrides| overrides.get(&flag).copied())
+            .unwrap_or(true)
+    }
+
+    /// Keep `value` only while `flag` is enabled
+    pub fn gate<T>(&self, flag: FeatureFlag, value: Option<T>) -> Option<T> {
+        value.filter(|_| self.is_enabled(flag))
+    }
+
+    /// Set a flag in the cache
+    pub fn set(&self, flag: FeatureFlag, enabled: bool) {
+        if let Ok(mut current) = self.overrides.write() {
+            current.insert(flag, enabled);
+        }
+    }
+
+    /// Replace the cached flags
+    pub fn replace(&self, overrides: HashMap<FeatureFlag, bool>) {
+        if let Ok(mut current) = self.overrides.write() {
+            *current = overrides;
+        }
+    }
+
+    /// State of every known flag
+    pub fn snapshot(&self) -> Vec<FeatureFlagState> {
+        FeatureFlag::ALL
+            .into_iter()
+            .map(|flag| FeatureFlagState {
+                name: flag.name(),
+                enabled: self.is_enabled(flag),
+            })
+            .collect()
+    }
+
+    /// Reload flags from the database, returning how many are stored
+    pub async fn refresh(&self, pool: &PgPool) -> Result<usize, sqlx::Error> {
+        let flags = load_flags(pool).await?;
+        let count = flags.len();
+        self.replace(flags);
+        Ok(count)
+    }
+
+    /// Spawn background task that periodically reloads flags
+    pub fn spawn_refresh_worker(&self, pool: PgPool, interval: Duration) {
+        let flags = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = flags.refresh(&pool).await {
+                    tracing::error!("Failed to refresh feature flags: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// Read the refresh interval from `FEATURE_FLAGS_REFRESH_SECS`
+pub fn refresh_interval_from_env() -> Duration {
+    std::env::var("FEATURE_FLAGS_REFRESH_SECS")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_REFRESH_INTERVAL)
+}
+
+/// Load stored flags, ignoring names this build doesn't know
+pub async fn load_flags(pool: &PgPool) -> Result<HashMap<FeatureFlag, bool>, sqlx::Error> {
+    let rows = sqlx::query("SELECT name, enabled FROM feature_flags")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let name: String = row.get("name");
+            Some((FeatureFlag::from_name(&name)?, row.get("enabled")))
+        })
+        .collect())
+}
+
+/// Store a flag
+pub async fn set_flag(pool: &PgPool, flag: FeatureFlag, enabled: bool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO feature_flags (name, enabled)
+        VALUES ($1, $2)
+        ON CONFLICT (name) DO UPDATE SET enabled = EXCLUDED.enabled
+        "#,
+    )
+    .bind(flag.name())
+    .bind(enabled)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_flags_default_to_enabled() {
+        let flags = FeatureFlags::default();
+        for flag in FeatureFlag::ALL {
+            assert!(flags.is_enabled(flag));
+        }
+    }
+
+    #[test]
+    fn test_set_and_replace() {
+        let flags = FeatureFlags::default();
+        flags.set(FeatureFlag::MirrorMode, false);
+        assert!(!flags.is_enabled(FeatureFlag::MirrorMode));
+        assert_eq!(flags.gate(FeatureFlag::MirrorMode, Some(1)), None);
+        assert_eq!(flags.gate(FeatureFlag::SafetyFallback, Some(1)), Some(1));
+
+        flags.replace(HashMap::new());
+        assert!(flags.is_enabled(FeatureFlag::MirrorMode));
+    }
+
+    #[test]
+    fn test_flag_names_round_trip() {
+        for flag in FeatureFlag::ALL {
+            assert_eq!(FeatureFlag::from_name(flag.name()), Some(flag));
+            assert_eq!(serde_json::to_value(flag).unwrap(), flag.name());
+        }
+        assert_eq!(FeatureFlag::from_name("caching"), None);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_refresh_loads_stored_flags(pool: PgPool) {
+        set_flag(&pool, FeatureFlag::DebugCapture, false).await.unwrap();
+        sqlx::query("INSERT INTO feature_flags (name, enabled) VALUES ('retired_flag', false)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let flags = FeatureFlags::default();
+        assert_eq!(flags.refresh(&pool).await.unwrap(), 1);
+        assert!(!flags.is_enabled(FeatureFlag::DebugCapture));
+
+        set_flag(&pool, FeatureFlag::DebugCapture, true).await.unwrap();
+        flags.refresh(&pool).await.unwrap();
+        assert!(flags.is_enabled(FeatureFlag::DebugCapture));
+    }
+}
@@ -8,6 +8,8 @@ use serde::{Deserialize, Serialize};
 use sqlx::{PgPool, Row};
 use uuid::Uuid;
 
+use crate::services::pricing::format_ppn_rate;
+
 /// Invoice entity
 #[derive(Debug, Serialize, Clone)]
 pub struct Invoice {
@@ -110,7 +112,7 @@ impl InvoiceService {
                 total: invoice.subtotal_idr,
             },
             InvoiceLineItem {
-                description: "PPN (11%)".to_string(),
+                description: ppn_label(invoice.subtotal_idr, invoice.ppn_idr),
                 quantity: 1,
                 unit_price: invoice.ppn_idr,
                 total: invoice.ppn_idr,
@@ -268,7 +270,7 @@ impl InvoiceService {
             <span>{subtotal_formatted}</span>
         </div>
         <div class="totals-row">
-            <span>PPN (11%)</span>
+            <span>{ppn_label}</span>
             <span>{ppn_formatted}</span>
         </div>
         <div class="totals-row total">
@@ -298,6 +300,7 @@ impl InvoiceService {
             customer_email = invoice.user_email,
             plan_tier = invoice.plan_tier.to_uppercase(),
             subtotal_formatted = format_rupiah(invoice.invoice.subtotal_idr),
+            ppn_label = ppn_label(invoice.invoice.subtotal_idr, invoice.invoice.ppn_idr),
             ppn_formatted = format_rupiah(invoice.invoice.ppn_idr),
             total_formatted = format_rupiah(invoice.invoice.total_idr),
             payment_method = invoice.invoice.payment_method.clone().unwrap_or_else(|| "-".to_string()),
@@ -306,8 +309,15 @@ impl InvoiceService {
     }
 }
 
+/// PPN line label with the rate actually charged, e.g. `PPN (11%)`.
+/// Derived from the stored amounts so old invoices keep their rate.
+fn ppn_label(subtotal: i64, ppn: i64) -> String {
+    let rate = if subtotal > 0 { ppn as f64 / subtotal as f64 } else { 0.0 };
+    format!("PPN ({})", format_ppn_rate(rate))
+}
+
 /// Format number as Indonesian Rupiah
-fn format_rupiah(amount: i64) -> String {
+pub(crate) fn format_rupiah(amount: i64) -> String {
     let formatted = amount
         .to_string()
         .chars()
@@ -0,0 +1,117 @@
+//! Build and schema version reporting for deploy verification.
+//!
+//! Compares the migrations embedded in this binary with those recorded in
+//! sqlx's `_sqlx_migrations` table. Any embedded migration that hasn't been
+//! applied successfully makes the instance unhealthy.
+
+use serde::Serialize;
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+
+/// Migrations compiled into this binary
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Version of the running binary
+pub const BUILD_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Build and migration versions of a running instance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionReport {
+    pub status: &'static str,
+    pub build_version: &'static str,
+    /// Latest successfully applied migration
+    pub migration_version: Option<i64>,
+    /// Latest migration embedded in this binary
+    pub expected_migration_version: Option<i64>,
+    pub pending_migrations: Vec<i64>,
+}
+
+impl VersionReport {
+    /// Build a report from embedded and applied migration versions
+    pub fn new(expected: &[i64], applied: &[i64]) -> Self {
+        let pending_migrations: Vec<i64> = expected
+            .iter()
+            .filter(|version| !applied.contains(version))
+            .copied()
+            .collect();
+
+        Self {
+            status: if pending_migrations.is_empty() { "ok" } else { "unhealthy" },
+            build_version: BUILD_VERSION,
+            migration_version: applied.iter().max().copied(),
+            expected_migration_version: expected.iter().max().copied(),
+            pending_migrations,
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.pending_migrations.is_empty()
+    }
+}
+
+/// Versions of migrations embedded in this binary
+pub fn expected_versions() -> Vec<i64> {
+    MIGRATOR.iter().map(|m| m.version).collect()
+}
+
+/// Versions recorded as successfully applied
+pub async fn applied_versions(db: &PgPool) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+        .fetch_all(db)
+        .await
+}
+
+/// Compare the database's applied migrations with this binary's
+pub async fn version_report(db: &PgPool) -> Result<VersionReport, sqlx::Error> {
+    let applied = applied_versions(db).await?;
+    Ok(VersionReport::new(&expected_versions(), &applied))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_report_serialization() {
+        let report = VersionReport::new(&[20241211011, 20241211012], &[20241211011, 20241211012]);
+
+        assert!(report.is_healthy());
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "status": "ok",
+                "build_version": BUILD_VERSION,
+                "migration_version": 20241211012i64,
+                "expected_migration_version": 20241211012i64,
+                "pending_migrations": [],
+            })
+        );
+    }
+
+    #[test]
+    fn test_pending_migration_is_unhealthy() {
+        let report = VersionReport::new(&[20241211011, 20241211012], &[20241211011]);
+
+        assert!(!report.is_healthy());
+        assert_eq!(report.status, "unhealthy");
+        assert_eq!(report.migration_version, Some(20241211011));
+        assert_eq!(report.pending_migrations, vec![20241211012]);
+    }
+
+    #[test]
+    fn test_embedded_migrations_are_listed() {
+        let expected = expected_versions();
+        assert!(expected.contains(&20241209001));
+        assert!(expected.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    // Database-backed tests (see test_support for how to run them)
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_migrated_database_is_healthy(pool: PgPool) {
+        let report = version_report(&pool).await.unwrap();
+        assert!(report.is_healthy());
+        assert_eq!(report.migration_version, report.expected_migration_version);
+    }
+}
@@ -1,17 +1,51 @@
+pub mod abuse_detector;
 pub mod analytics_service;
+pub mod anthropic_headers;
+pub mod anthropic_overload;
 pub mod auth_service;
 pub mod api_key_service;
 pub mod billing_service;
+pub mod content_sanitizer;
+pub mod debug_capture;
 pub mod email_service;
+pub mod feature_flags;
 pub mod invoice_service;
+pub mod migration_status;
+pub mod model_access;
+pub mod model_routing;
 pub mod onboarding_service;
+pub mod pricing;
+pub mod provider_headers;
+pub mod provider_key_override;
+pub mod provider_errors;
+pub mod provider_limiter;
+pub mod provider_regions;
+pub mod proxy_config;
 pub mod proxy_key_service;
 pub mod proxy_service;
 pub mod rate_limiter;
+pub mod request_guard;
+pub mod request_id;
+pub mod request_metadata;
+pub mod request_timing;
+pub mod safety_fallback;
 pub mod scheduler_service;
+pub mod session_revocation;
+pub mod shadow_mirror;
+pub mod spend_guard;
+pub mod stream_coalesce;
 pub mod stream_handler;
+pub mod task_manager;
+pub mod transcripts;
 pub mod transformers;
+pub mod upstream_client;
+pub mod upstream_retry;
+pub mod usage_dlq;
+pub mod usage_events;
 pub mod usage_logger;
+pub mod usage_receipt;
+pub mod usage_retention;
+pub mod usage_webhook;
 pub mod usage_analytics;
 
 #[cfg(test)]
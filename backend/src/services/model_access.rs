@@ -0,0 +1,255 @@
+//! Plan-based model access.
+//!
+//! Each plan tier may use a set of providers and models. By default Free is
+//! limited to small, fast models, Starter adds the mid-tier models, and Pro
+//! and Team can use everything. Overrides via environment:
+//! - `PLAN_MODELS_<PLAN>`: comma-separated model ids; a trailing `*` matches
+//!   by prefix and `*` alone allows every model
+//! - `PLAN_PROVIDERS_<PLAN>`: comma-separated providers
+//!   (`openai`, `anthropic`, `google`, `qwen`), or `*` for all
+//! - `PLAN_STREAMING_<PLAN>`: `false` to withhold `stream: true` from a plan
+//!   (every plan may stream by default)
+//! - `DISALLOWED_STREAM_MODE`: `reject` (default) answers such requests with an
+//!   error, `downgrade` serves them as non-streaming completions
+
+use crate::models::PlanTier;
+use crate::services::transformers::Provider;
+
+const FREE_MODELS: &[&str] = &[
+    "gpt-4o-mini",
+    "gpt-3.5-turbo",
+    "claude-3-haiku-*",
+    "claude-3-5-haiku-*",
+    "gemini-1.5-flash*",
+    "qwen-turbo",
+];
+
+const STARTER_MODELS: &[&str] = &["gpt-4o", "claude-3-5-sonnet-*", "gemini-1.5-pro", "qwen-plus"];
+
+/// A model the caller's plan doesn't include
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Model {model} is not available on the {plan} plan")]
+pub struct ModelNotInPlan {
+    pub model: String,
+    pub plan: &'static str,
+}
+
+/// A streaming request on a plan without streaming
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Streaming is not available on the {plan} plan; retry with stream=false")]
+pub struct StreamingNotInPlan {
+    pub plan: &'static str,
+}
+
+/// What to do with a streaming request the plan doesn't allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisallowedStreaming {
+    /// Fail the request with `StreamingNotInPlan`
+    #[default]
+    Reject,
+    /// Serve it as a non-streaming completion
+    Downgrade,
+}
+
+impl DisallowedStreaming {
+    /// Load the mode from `DISALLOWED_STREAM_MODE`
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load the mode using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        match lookup("DISALLOWED_STREAM_MODE").map(|v| v.trim().to_lowercase()).as_deref() {
+            Some("downgrade") => Self::Downgrade,
+            _ => Self::Reject,
+        }
+    }
+}
+
+/// Providers and models a plan tier may use; `None` means unrestricted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelAccess {
+    pub plan: PlanTier,
+    pub providers: Option<Vec<Provider>>,
+    pub models: Option<Vec<String>>,
+    /// Whether the plan may request `stream: true`
+    pub streaming: bool,
+}
+
+impl ModelAccess {
+    /// Default access for a plan tier
+    pub fn for_plan(plan: PlanTier) -> Self {
+        let models = match plan {
+            PlanTier::Free => Some(FREE_MODELS.to_vec()),
+            PlanTier::Starter => Some([FREE_MODELS, STARTER_MODELS].concat()),
+            PlanTier::Pro | PlanTier::Team => None,
+        };
+        Self {
+            plan,
+            providers: None,
+            models: models.map(|models| models.into_iter().map(str::to_string).collect()),
+            streaming: true,
+        }
+    }
+
+    /// Load access for a plan tier from environment variables
+    pub fn from_env(plan: PlanTier) -> Self {
+        Self::from_lookup(plan, |key| std::env::var(key).ok())
+    }
+
+    /// Load access for a plan tier using a custom variable lookup
+    pub fn from_lookup<F>(plan: PlanTier, lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let suffix = plan.as_str().to_uppercase();
+        let read = |name: &str| -> Option<Option<Vec<String>>> {
+            let value = lookup(&format!("{}_{}", name, suffix))?;
+            let entries: Vec<String> = value
+                .split(',')
+                .map(|entry| entry.trim().to_lowercase())
+                .filter(|entry| !entry.is_empty())
+                .collect();
+            Some((!entries.iter().any(|entry| entry == "*")).then_some(entries))
+        };
+
+        let defaults = Self::for_plan(plan);
+        Self {
+            plan,
+            providers: read("PLAN_PROVIDERS")
+                .map(|names| names.map(|names| names.iter().filter_map(|name| parse_provider(name)).collect()))
+                .unwrap_or(defaults.providers),
+            models: read("PLAN_MODELS").unwrap_or(defaults.models),
+            streaming: lookup(&format!("PLAN_STREAMING_{}", suffix))
+                .and_then(|v| v.trim().parse::<bool>().ok())
+                .unwrap_or(defaults.streaming),
+        }
+    }
+
+    /// Check that the plan includes `model` on `provider`
+    pub fn check(&self, provider: Provider, model: &str) -> Result<(), ModelNotInPlan> {
+        let provider_allowed = self
+            .providers
+            .as_ref()
+            .is_none_or(|providers| providers.contains(&provider));
+        let model_allowed = self
+            .models
+            .as_ref()
+            .is_none_or(|models| models.iter().any(|pattern| matches_model(pattern, model)));
+
+        if provider_allowed && model_allowed {
+            Ok(())
+        } else {
+            Err(ModelNotInPlan {
+                model: model.to_string(),
+                plan: self.plan.as_str(),
+            })
+        }
+    }
+}
+
+impl ModelAccess {
+    /// Check that the plan allows this request's streaming mode
+    pub fn check_streaming(&self, stream: bool) -> Result<(), StreamingNotInPlan> {
+        if stream && !self.streaming {
+            Err(StreamingNotInPlan { plan: self.plan.as_str() })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn parse_provider(name: &str) -> Option<Provider> {
+    Provider::ALL
+        .into_iter()
+        .find(|provider| provider.name().eq_ignore_ascii_case(name))
+}
+
+fn matches_model(pattern: &str, model: &str) -> bool {
+    let model = model.to_lowercase();
+    match pattern.strip_suffix('*') {
+        Some(prefix) => model.starts_with(prefix),
+        None => model == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::env_lookup;
+
+    #[test]
+    fn test_free_plan_denied_premium_model() {
+        let access = ModelAccess::for_plan(PlanTier::Free);
+
+        let err = access.check(Provider::OpenAI, "gpt-4").unwrap_err();
+        assert_eq!(err.to_string(), "Model gpt-4 is not available on the free plan");
+        assert!(access.check(Provider::Anthropic, "claude-3-opus-20240229").is_err());
+        assert!(access.check(Provider::OpenAI, "gpt-4o-mini").is_ok());
+        assert!(access.check(Provider::Anthropic, "claude-3-haiku-20240307").is_ok());
+    }
+
+    #[test]
+    fn test_pro_plan_allowed_premium_model() {
+        let access = ModelAccess::for_plan(PlanTier::Pro);
+
+        assert!(access.check(Provider::OpenAI, "gpt-4").is_ok());
+        assert!(access.check(Provider::Anthropic, "claude-3-opus-20240229").is_ok());
+    }
+
+    #[test]
+    fn test_starter_adds_mid_tier_models() {
+        let access = ModelAccess::for_plan(PlanTier::Starter);
+
+        assert!(access.check(Provider::OpenAI, "gpt-4o").is_ok());
+        assert!(access.check(Provider::OpenAI, "gpt-4o-mini").is_ok());
+        assert!(access.check(Provider::OpenAI, "o1-preview").is_err());
+    }
+
+    #[test]
+    fn test_matrix_configurable_per_tier() {
+        let access = ModelAccess::from_lookup(
+            PlanTier::Free,
+            env_lookup(&[("PLAN_MODELS_FREE", "gpt-4, qwen-*"), ("PLAN_PROVIDERS_FREE", "openai,qwen")]),
+        );
+        assert!(access.check(Provider::OpenAI, "gpt-4").is_ok());
+        assert!(access.check(Provider::Qwen, "qwen-max").is_ok());
+        assert!(access.check(Provider::OpenAI, "gpt-4o-mini").is_err());
+
+        // A provider outside the plan is denied even if the model matches
+        let access = ModelAccess::from_lookup(
+            PlanTier::Pro,
+            env_lookup(&[("PLAN_PROVIDERS_PRO", "openai")]),
+        );
+        assert!(access.check(Provider::Anthropic, "claude-3-opus-20240229").is_err());
+        assert!(access.check(Provider::OpenAI, "o1-preview").is_ok());
+
+        let open = ModelAccess::from_lookup(PlanTier::Free, env_lookup(&[("PLAN_MODELS_FREE", "*")]));
+        assert!(open.check(Provider::OpenAI, "gpt-4").is_ok());
+    }
+
+    #[test]
+    fn test_streaming_allowed_by_default() {
+        for plan in [PlanTier::Free, PlanTier::Starter, PlanTier::Pro, PlanTier::Team] {
+            let access = ModelAccess::from_lookup(plan, env_lookup(&[]));
+            assert!(access.check_streaming(true).is_ok());
+        }
+        assert_eq!(DisallowedStreaming::from_lookup(env_lookup(&[])), DisallowedStreaming::Reject);
+    }
+
+    #[test]
+    fn test_streaming_withheld_per_plan() {
+        let vars = env_lookup(&[("PLAN_STREAMING_FREE", "false"), ("DISALLOWED_STREAM_MODE", "Downgrade")]);
+        let free = ModelAccess::from_lookup(PlanTier::Free, &vars);
+        let err = free.check_streaming(true).unwrap_err();
+        assert_eq!(err.to_string(), "Streaming is not available on the free plan; retry with stream=false");
+        assert!(free.check_streaming(false).is_ok());
+
+        let pro = ModelAccess::from_lookup(PlanTier::Pro, &vars);
+        assert!(pro.check_streaming(true).is_ok());
+        assert_eq!(DisallowedStreaming::from_lookup(&vars), DisallowedStreaming::Downgrade);
+    }
+}
@@ -0,0 +1,234 @@
+//! Admin-managed model routing overrides.
+//!
+//! Overrides in `model_routes` map a model name to a provider (and optionally
+//! a named upstream key) and take precedence over prefix routing in
+//! `Provider::from_model`. The table is cached in memory and refreshed
+//! periodically, so admin changes apply without a restart.
+//!
+//! Configuration:
+//! - `MODEL_ROUTES_REFRESH_SECS`: cache refresh interval (default `60`)
+
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::services::transformers::Provider;
+
+/// Default interval between override cache refreshes
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Resolved upstream for a model
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelRoute {
+    pub provider: Provider,
+    /// Name of the user's provider key to use (most recent active key when unset)
+    pub key_name: Option<String>,
+}
+
+impl ModelRoute {
+    /// Route using the default key for a provider
+    pub fn provider(provider: Provider) -> Self {
+        Self {
+            provider,
+            key_name: None,
+        }
+    }
+}
+
+/// Override entry as stored in `model_routes`
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelRouteOverride {
+    pub model: String,
+    #[serde(flatten)]
+    pub route: ModelRoute,
+    pub updated_at: String,
+}
+
+/// In-memory routing table shared by all requests
+#[derive(Debug, Clone, Default)]
+pub struct ModelRouter {
+    overrides: Arc<RwLock<HashMap<String, ModelRoute>>>,
+}
+
+impl ModelRouter {
+    /// Resolve a model to its upstream, preferring overrides over prefix routing
+    pub fn resolve(&self, model: &str) -> Option<ModelRoute> {
+        let overridden = self
+            .overrides
+            .read()
+            .ok()
+            .and_then(|overrides| overrides.get(model).cloned());
+
+        overridden.or_else(|| Provider::from_model(model).map(ModelRoute::provider))
+    }
+
+    /// Replace the cached overrides
+    pub fn replace(&self, overrides: HashMap<String, ModelRoute>) {
+        if let Ok(mut current) = self.overrides.write() {
+            *current = overrides;
+        }
+    }
+
+    /// Reload overrides from the database, returning how many are active
+    pub async fn refresh(&self, pool: &PgPool) -> Result<usize, sqlx::Error> {
+        let overrides: HashMap<String, ModelRoute> = list_overrides(pool)
+            .await?
+            .into_iter()
+            .map(|entry| (entry.model, entry.route))
+            .collect();
+
+        let count = overrides.len();
+        self.replace(overrides);
+        Ok(count)
+    }
+
+    /// Spawn background task that periodically reloads overrides
+    pub fn spawn_refresh_worker(&self, pool: PgPool, interval: Duration) {
+        let router = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = router.refresh(&pool).await {
+                    tracing::error!("Failed to refresh model routes: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// Read the refresh interval from `MODEL_ROUTES_REFRESH_SECS`
+pub fn refresh_interval_from_env() -> Duration {
+    std::env::var("MODEL_ROUTES_REFRESH_SECS")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_REFRESH_INTERVAL)
+}
+
+fn parse_provider(value: &str) -> Option<Provider> {
+    match value {
+        "openai" => Some(Provider::OpenAI),
+        "anthropic" => Some(Provider::Anthropic),
+        "google" => Some(Provider::Google),
+        "qwen" => Some(Provider::Qwen),
+        _ => None,
+    }
+}
+
+/// List all overrides
+pub async fn list_overrides(pool: &PgPool) -> Result<Vec<ModelRouteOverride>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT model, provider::text AS provider, key_name, updated_at FROM model_routes ORDER BY model",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let provider: String = row.get("provider");
+            let provider = parse_provider(&provider)?;
+            Some(ModelRouteOverride {
+                model: row.get("model"),
+                route: ModelRoute {
+                    provider,
+                    key_name: row.get("key_name"),
+                },
+                updated_at: row
+                    .get::<chrono::DateTime<chrono::Utc>, _>("updated_at")
+                    .to_rfc3339(),
+            })
+        })
+        .collect())
+}
+
+/// Create or replace the override for a model
+pub async fn upsert_override(pool: &PgPool, model: &str, route: &ModelRoute) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO model_routes (model, provider, key_name)
+        VALUES ($1, $2::ai_provider, $3)
+        ON CONFLICT (model) DO UPDATE SET provider = EXCLUDED.provider, key_name = EXCLUDED.key_name
+        "#,
+    )
+    .bind(model)
+    .bind(route.provider.name().to_lowercase())
+    .bind(&route.key_name)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Remove the override for a model, returning whether one existed
+pub async fn delete_override(pool: &PgPool, model: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM model_routes WHERE model = $1")
+        .bind(model)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_override_takes_precedence_over_prefix() {
+        let router = ModelRouter::default();
+        assert_eq!(router.resolve("gpt-4o"), Some(ModelRoute::provider(Provider::OpenAI)));
+
+        let azure = ModelRoute {
+            provider: Provider::OpenAI,
+            key_name: Some("azure-east".to_string()),
+        };
+        router.replace(HashMap::from([
+            ("gpt-4o".to_string(), azure.clone()),
+            ("claude-3-haiku-20240307".to_string(), ModelRoute::provider(Provider::Google)),
+        ]));
+
+        assert_eq!(router.resolve("gpt-4o"), Some(azure));
+        assert_eq!(
+            router.resolve("claude-3-haiku-20240307"),
+            Some(ModelRoute::provider(Provider::Google))
+        );
+        // Models without an override still use prefix routing
+        assert_eq!(router.resolve("gpt-4"), Some(ModelRoute::provider(Provider::OpenAI)));
+    }
+
+    #[test]
+    fn test_override_routes_unknown_model() {
+        let router = ModelRouter::default();
+        assert_eq!(router.resolve("my-finetune"), None);
+
+        router.replace(HashMap::from([(
+            "my-finetune".to_string(),
+            ModelRoute::provider(Provider::Qwen),
+        )]));
+        assert_eq!(router.resolve("my-finetune"), Some(ModelRoute::provider(Provider::Qwen)));
+    }
+
+    #[test]
+    fn test_clones_share_overrides() {
+        let router = ModelRouter::default();
+        let worker_copy = router.clone();
+        worker_copy.replace(HashMap::from([(
+            "gemini-pro".to_string(),
+            ModelRoute::provider(Provider::Anthropic),
+        )]));
+
+        assert_eq!(router.resolve("gemini-pro"), Some(ModelRoute::provider(Provider::Anthropic)));
+    }
+
+    #[test]
+    fn test_parse_provider() {
+        assert_eq!(parse_provider("openai"), Some(Provider::OpenAI));
+        assert_eq!(parse_provider("qwen"), Some(Provider::Qwen));
+        assert_eq!(parse_provider("azure"), None);
+    }
+}
@@ -78,6 +78,8 @@ pub struct InactiveUser {
     pub user_id: Uuid,
     pub email: String,
     pub name: Option<String>,
+    /// Email language (`id`/`en`)
+    pub locale: String,
     pub account_created_at: DateTime<Utc>,
     pub hours_since_signup: i64,
 }
@@ -217,7 +219,7 @@ impl OnboardingService {
         let rows = sqlx::query(
             r#"
             SELECT 
-                o.user_id, u.email, u.name, o.account_created_at
+                o.user_id, u.email, u.name, u.locale, o.account_created_at
             FROM onboarding_progress o
             JOIN users u ON u.id = o.user_id
             WHERE o.api_key_added_at IS NULL
@@ -239,6 +241,7 @@ impl OnboardingService {
                     user_id: r.get("user_id"),
                     email: r.get("email"),
                     name: r.get("name"),
+                    locale: r.get("locale"),
                     account_created_at: created_at,
                     hours_since_signup: (now - created_at).num_hours(),
                 }
@@ -0,0 +1,406 @@
+//! Plan pricing, PPN, and proration math.
+//!
+//! Pure functions with no database or HTTP access, shared by `BillingService`
+//! and the upgrade preview endpoint so quotes and charges can't drift apart.
+//!
+//! Configuration:
+//! - `PPN_RATE`: PPN as a fraction, e.g. `0.12` (default `0.11`)
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Default PPN (VAT) rate in Indonesia: 11%
+pub const PPN_RATE: f64 = 0.11;
+
+/// Days in a billing period, used for proration
+pub const BILLING_PERIOD_DAYS: i64 = 30;
+
+/// Plan tier pricing in IDR (before PPN)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlanTier {
+    Free,
+    Starter,
+    Pro,
+    Team,
+}
+
+impl PlanTier {
+    /// Get base price in IDR (before PPN)
+    pub fn price_idr(&self) -> i64 {
+        match self {
+            PlanTier::Free => 0,
+            PlanTier::Starter => 49_000,
+            PlanTier::Pro => 99_000,
+            PlanTier::Team => 299_000,
+        }
+    }
+
+    /// Get monthly request limit
+    pub fn request_limit(&self) -> i64 {
+        match self {
+            PlanTier::Free => 1_000,
+            PlanTier::Starter => 10_000,
+            PlanTier::Pro => 50_000,
+            PlanTier::Team => 200_000,
+        }
+    }
+
+    /// Parse a stored or requested plan name
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "free" => Some(PlanTier::Free),
+            "starter" => Some(PlanTier::Starter),
+            "pro" => Some(PlanTier::Pro),
+            "team" => Some(PlanTier::Team),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanTier::Free => "free",
+            PlanTier::Starter => "starter",
+            PlanTier::Pro => "pro",
+            PlanTier::Team => "team",
+        }
+    }
+}
+
+impl std::fmt::Display for PlanTier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Load the PPN rate from environment variables
+pub fn ppn_rate_from_env() -> f64 {
+    ppn_rate_from_lookup(|key| std::env::var(key).ok())
+}
+
+/// Load the PPN rate using a custom variable lookup; invalid values fall
+/// back to the default
+pub fn ppn_rate_from_lookup<F>(lookup: F) -> f64
+where
+    F: Fn(&str) -> Option<String>,
+{
+    lookup("PPN_RATE")
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|rate| (0.0..1.0).contains(rate))
+        .unwrap_or(PPN_RATE)
+}
+
+/// PPN rate charged to a customer: zero when they are tax-exempt
+pub fn effective_ppn_rate(rate: f64, tax_exempt: bool) -> f64 {
+    if tax_exempt {
+        0.0
+    } else {
+        rate
+    }
+}
+
+/// Rate for display, e.g. `11%` or `11.5%`
+pub fn format_ppn_rate(rate: f64) -> String {
+    let percent = (rate * 1000.0).round() / 10.0;
+    format!("{}%", percent)
+}
+
+/// Calculate total amount with the default PPN rate
+/// Property 2: Payment Amount Calculation
+pub fn calculate_total_with_ppn(base_price: i64) -> (i64, i64, i64) {
+    calculate_total_with_ppn_at(base_price, PPN_RATE)
+}
+
+/// Calculate (subtotal, ppn, total) at a given PPN rate
+pub fn calculate_total_with_ppn_at(base_price: i64, rate: f64) -> (i64, i64, i64) {
+    let ppn = (base_price as f64 * rate).round() as i64;
+    let total = base_price + ppn;
+    (base_price, ppn, total)
+}
+
+/// Split an amount including default-rate PPN into (subtotal, ppn)
+pub fn split_ppn_inclusive(total: i64) -> (i64, i64) {
+    split_ppn_inclusive_at(total, PPN_RATE)
+}
+
+/// Split an amount including PPN at a given rate into (subtotal, ppn)
+pub fn split_ppn_inclusive_at(total: i64, rate: f64) -> (i64, i64) {
+    let ppn = (total as f64 * rate / (1.0 + rate)).round() as i64;
+    (total - ppn, ppn)
+}
+
+/// Prorated price difference: (new_price - old_price) * (remaining_days / 30).
+/// Zero for downgrades, same-price changes, and elapsed periods.
+/// Property 4: Proration Calculation
+pub fn prorated_amount(old_price: i64, new_price: i64, remaining_days: i64) -> i64 {
+    if new_price <= old_price || remaining_days <= 0 {
+        return 0;
+    }
+    let price_diff = new_price - old_price;
+    ((price_diff as f64 * remaining_days as f64) / BILLING_PERIOD_DAYS as f64).round() as i64
+}
+
+/// Charge for an upgrade, before any order is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ProrationPreview {
+    pub prorated_base: i64,
+    pub ppn: i64,
+    pub total: i64,
+    pub remaining_days: i64,
+}
+
+impl ProrationPreview {
+    fn from_base(prorated_base: i64, remaining_days: i64, ppn_rate: f64) -> Self {
+        let (_, ppn, total) = calculate_total_with_ppn_at(prorated_base, ppn_rate);
+        Self {
+            prorated_base,
+            ppn,
+            total,
+            remaining_days,
+        }
+    }
+}
+
+/// Prorated upgrade charge for the rest of the current period, plus PPN at
+/// `ppn_rate`. `None` for downgrades and same-plan changes.
+pub fn calculate_proration(
+    current_plan: PlanTier,
+    new_plan: PlanTier,
+    period_end: DateTime<Utc>,
+    now: DateTime<Utc>,
+    ppn_rate: f64,
+) -> Option<ProrationPreview> {
+    if new_plan.price_idr() <= current_plan.price_idr() {
+        return None;
+    }
+
+    let remaining_days = (period_end - now).num_days().max(0);
+    let prorated_base = prorated_amount(current_plan.price_idr(), new_plan.price_idr(), remaining_days);
+    Some(ProrationPreview::from_base(prorated_base, remaining_days, ppn_rate))
+}
+
+/// Charge for a full new period on `plan`, plus PPN at `ppn_rate`
+pub fn full_period_quote(plan: PlanTier, ppn_rate: f64) -> ProrationPreview {
+    ProrationPreview::from_base(plan.price_idr(), BILLING_PERIOD_DAYS, ppn_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use proptest::prelude::*;
+
+    const PAID_TIERS: [PlanTier; 3] = [PlanTier::Starter, PlanTier::Pro, PlanTier::Team];
+
+    #[test]
+    fn test_plan_names_round_trip() {
+        for tier in [PlanTier::Free, PlanTier::Starter, PlanTier::Pro, PlanTier::Team] {
+            assert_eq!(PlanTier::parse(tier.as_str()), Some(tier));
+        }
+        assert_eq!(PlanTier::parse("PRO"), Some(PlanTier::Pro));
+        assert_eq!(PlanTier::parse("enterprise"), None);
+    }
+
+    #[test]
+    fn test_proration_matches_upgrade_formula() {
+        let now = Utc::now();
+        let period_end = now + Duration::days(15) + Duration::hours(1);
+
+        let preview = calculate_proration(PlanTier::Starter, PlanTier::Pro, period_end, now, PPN_RATE).unwrap();
+
+        // (99_000 - 49_000) * 15 / 30 = 25_000, PPN 11% = 2_750
+        assert_eq!(
+            preview,
+            ProrationPreview {
+                prorated_base: 25_000,
+                ppn: 2_750,
+                total: 27_750,
+                remaining_days: 15,
+            }
+        );
+    }
+
+    #[test]
+    fn test_proration_rejects_downgrade_and_same_plan() {
+        let now = Utc::now();
+        let period_end = now + Duration::days(10);
+        assert!(calculate_proration(PlanTier::Pro, PlanTier::Starter, period_end, now, PPN_RATE).is_none());
+        assert!(calculate_proration(PlanTier::Pro, PlanTier::Pro, period_end, now, PPN_RATE).is_none());
+    }
+
+    #[test]
+    fn test_expired_period_prorates_to_zero() {
+        let now = Utc::now();
+        let preview = calculate_proration(PlanTier::Starter, PlanTier::Team, now - Duration::days(2), now, PPN_RATE).unwrap();
+        assert_eq!(preview.remaining_days, 0);
+        assert_eq!(preview.total, 0);
+    }
+
+    #[test]
+    fn test_full_period_quote_is_plan_price_with_ppn() {
+        let quote = full_period_quote(PlanTier::Pro, PPN_RATE);
+        assert_eq!((quote.prorated_base, quote.ppn, quote.total), (99_000, 10_890, 109_890));
+        assert_eq!(quote.remaining_days, BILLING_PERIOD_DAYS);
+    }
+
+    #[test]
+    fn test_configured_ppn_rate() {
+        let rate = ppn_rate_from_lookup(|key| (key == "PPN_RATE").then(|| "0.12".to_string()));
+        assert_eq!(rate, 0.12);
+        assert_eq!(calculate_total_with_ppn_at(PlanTier::Pro.price_idr(), rate), (99_000, 11_880, 110_880));
+        assert_eq!(split_ppn_inclusive_at(110_880, rate), (99_000, 11_880));
+        assert_eq!(full_period_quote(PlanTier::Pro, rate).total, 110_880);
+        assert_eq!(format_ppn_rate(rate), "12%");
+
+        assert_eq!(ppn_rate_from_lookup(|_| None), PPN_RATE);
+        assert_eq!(ppn_rate_from_lookup(|_| Some("11".to_string())), PPN_RATE);
+        assert_eq!(format_ppn_rate(0.115), "11.5%");
+    }
+
+    #[test]
+    fn test_tax_exempt_customer_pays_no_ppn() {
+        let rate = effective_ppn_rate(PPN_RATE, true);
+        assert_eq!(calculate_total_with_ppn_at(PlanTier::Team.price_idr(), rate), (299_000, 0, 299_000));
+        assert_eq!(split_ppn_inclusive_at(299_000, rate), (299_000, 0));
+        assert_eq!(full_period_quote(PlanTier::Team, rate).ppn, 0);
+        assert_eq!(effective_ppn_rate(PPN_RATE, false), PPN_RATE);
+    }
+
+    #[test]
+    fn test_split_ppn_inclusive_reverses_plan_totals() {
+        for tier in PAID_TIERS {
+            let (base, ppn, total) = calculate_total_with_ppn(tier.price_idr());
+            assert_eq!(split_ppn_inclusive(total), (base, ppn));
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(100))]
+
+        /// Property: Total equals base price + PPN (11%)
+        /// Requirements: 2.1, 4.2 - Payment amount calculation
+        #[test]
+        fn prop_total_equals_base_plus_ppn(base_price in 1000i64..1000000i64) {
+            let (subtotal, ppn, total) = calculate_total_with_ppn(base_price);
+
+            prop_assert_eq!(subtotal, base_price, "Subtotal should equal base price");
+            prop_assert_eq!(total, subtotal + ppn, "Total should equal subtotal + PPN");
+        }
+
+        /// Property: PPN is exactly 11% of base price (rounded)
+        /// Requirements: 2.1 - 11% PPN calculation
+        #[test]
+        fn prop_ppn_is_eleven_percent(base_price in 1000i64..1000000i64) {
+            let (_, ppn, _) = calculate_total_with_ppn(base_price);
+            let expected_ppn = (base_price as f64 * 0.11).round() as i64;
+
+            prop_assert_eq!(ppn, expected_ppn, "PPN should be 11% of base price");
+        }
+
+        /// Property: Splitting a PPN-inclusive total gives back subtotal + PPN
+        /// Requirements: 4.2 - Invoice PPN breakdown
+        #[test]
+        fn prop_split_ppn_inclusive_sums_to_total(total in 0i64..10000000i64) {
+            let (subtotal, ppn) = split_ppn_inclusive(total);
+
+            prop_assert_eq!(subtotal + ppn, total);
+            prop_assert!(ppn >= 0 && ppn <= total);
+        }
+
+        /// Property: All plan tiers have correct pricing with PPN
+        /// Requirements: 2.1 - Plan tier pricing
+        #[test]
+        fn prop_plan_tier_pricing(tier in prop::sample::select(PAID_TIERS.to_vec())) {
+            let (subtotal, ppn, total) = calculate_total_with_ppn(tier.price_idr());
+
+            let expected = match tier {
+                PlanTier::Starter => (49_000, 5_390, 54_390),
+                PlanTier::Pro => (99_000, 10_890, 109_890),
+                PlanTier::Team => (299_000, 32_890, 331_890),
+                PlanTier::Free => unreachable!(),
+            };
+            prop_assert_eq!((subtotal, ppn, total), expected, "Pricing mismatch for {}", tier);
+        }
+
+        /// Property: Proration equals (new_price - old_price) * (remaining_days / 30)
+        /// Requirements: 3.4 - Proration calculation
+        #[test]
+        fn prop_proration_formula_correct(
+            old_price in 0i64..100000i64,
+            new_price in 0i64..500000i64,
+            remaining_days in 1i64..30i64
+        ) {
+            let prorated = prorated_amount(old_price, new_price, remaining_days);
+
+            if new_price > old_price {
+                let expected = ((new_price - old_price) as f64 * remaining_days as f64 / 30.0).round() as i64;
+                prop_assert_eq!(
+                    prorated,
+                    expected,
+                    "Proration should follow formula: (new - old) * remaining / 30"
+                );
+            } else {
+                prop_assert_eq!(
+                    prorated,
+                    0,
+                    "Downgrade or same plan should have 0 proration"
+                );
+            }
+        }
+
+        /// Property: Proration is 0 when downgrading
+        /// Requirements: 3.4 - No proration for downgrades
+        #[test]
+        fn prop_no_proration_for_downgrade(
+            old_price in 50000i64..500000i64,
+            remaining_days in 1i64..30i64
+        ) {
+            let new_price = old_price / 2; // Downgrade
+            let prorated = prorated_amount(old_price, new_price, remaining_days);
+
+            prop_assert_eq!(prorated, 0, "Downgrade should have 0 proration");
+        }
+
+        /// Property: Full month upgrade equals full price difference
+        /// Requirements: 3.4 - Full month proration
+        #[test]
+        fn prop_full_month_proration(
+            old_price in 0i64..100000i64,
+            new_price in 100001i64..500000i64
+        ) {
+            let prorated = prorated_amount(old_price, new_price, 30);
+            let expected = new_price - old_price;
+
+            prop_assert_eq!(
+                prorated,
+                expected,
+                "30 days remaining should equal full price difference"
+            );
+        }
+
+        /// Property: Plan upgrades never cost more than the full price difference
+        /// Requirements: 3.4 - Proration calculation
+        #[test]
+        fn prop_plan_upgrade_bounded_by_price_difference(
+            from in prop::sample::select(PAID_TIERS.to_vec()),
+            to in prop::sample::select(PAID_TIERS.to_vec()),
+            remaining_hours in 0i64..(31 * 24)
+        ) {
+            let now = Utc::now();
+            let period_end = now + Duration::hours(remaining_hours);
+
+            match calculate_proration(from, to, period_end, now, PPN_RATE) {
+                Some(preview) => {
+                    prop_assert!(to.price_idr() > from.price_idr());
+                    prop_assert_eq!(preview.remaining_days, remaining_hours / 24);
+                    prop_assert_eq!(
+                        preview.prorated_base,
+                        prorated_amount(from.price_idr(), to.price_idr(), preview.remaining_days)
+                    );
+                    prop_assert!(preview.prorated_base <= to.price_idr() - from.price_idr());
+                    prop_assert_eq!(preview.total, preview.prorated_base + preview.ppn);
+                }
+                None => prop_assert!(to.price_idr() <= from.price_idr()),
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Per-user history of upstream provider errors.
+//!
+//! Non-success responses from providers are recorded in `provider_errors`
+//! (provider, status, the provider's error code and message) so support can
+//! see a user's recent failures without searching logs. Messages are
+//! truncated and anything that looks like a credential is redacted before
+//! storage. Only the newest `MAX_ERRORS_PER_USER` rows are kept per user.
+
+use serde::Serialize;
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+use crate::services::task_manager::TaskManager;
+use crate::services::transformers::Provider;
+
+/// Rows kept per user; older errors are pruned on insert
+pub const MAX_ERRORS_PER_USER: i64 = 200;
+
+/// Longest stored message, in characters
+pub const MAX_MESSAGE_CHARS: usize = 500;
+
+/// Token prefixes of provider and proxy credentials
+const SECRET_PREFIXES: &[&str] = &["sk-", "AIza", "wbr_", "Bearer"];
+
+/// Upstream error to record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderErrorRecord {
+    pub user_id: Uuid,
+    pub proxy_key_id: Option<Uuid>,
+    pub provider: Provider,
+    pub model: String,
+    pub status_code: i32,
+    /// Provider's error code or type, when the body has one
+    pub error_code: Option<String>,
+    pub message: String,
+}
+
+impl ProviderErrorRecord {
+    /// Build a record from an upstream error body, pulling the code and
+    /// message out of the provider's error shape when it is JSON
+    pub fn from_upstream(
+        user_id: Uuid,
+        proxy_key_id: Option<Uuid>,
+        provider: Provider,
+        model: &str,
+        status_code: u16,
+        body: &[u8],
+    ) -> Self {
+        let (error_code, message) = match serde_json::from_slice::<serde_json::Value>(body) {
+            Ok(json) => parse_error_body(&json),
+            Err(_) => (None, String::from_utf8_lossy(body).into_owned()),
+        };
+
+        Self {
+            user_id,
+            proxy_key_id,
+            provider,
+            model: model.to_string(),
+            status_code: status_code as i32,
+            error_code: error_code.map(|code| redact(&code)),
+            message: redact(&message),
+        }
+    }
+}
+
+/// Error code and message from OpenAI, Anthropic, Google or DashScope bodies
+fn parse_error_body(json: &serde_json::Value) -> (Option<String>, String) {
+    let error = &json["error"];
+    let code = [&error["code"], &error["type"], &error["status"], &json["code"]]
+        .into_iter()
+        .find_map(|value| match value {
+            serde_json::Value::String(code) => Some(code.clone()),
+            serde_json::Value::Number(code) => Some(code.to_string()),
+            _ => None,
+        });
+    let message = error["message"]
+        .as_str()
+        .or_else(|| json["message"].as_str())
+        .map(str::to_string)
+        .unwrap_or_else(|| json.to_string());
+    (code, message)
+}
+
+/// Truncate a message and replace credential-looking tokens with `[redacted]`
+pub fn redact(text: &str) -> String {
+    let truncated: String = text.chars().take(MAX_MESSAGE_CHARS).collect();
+    let mut redacted = String::with_capacity(truncated.len());
+    let mut token = String::new();
+    let mut redact_next = false;
+
+    let flush = |token: &mut String, redacted: &mut String, redact_next: &mut bool| {
+        if token.is_empty() {
+            return;
+        }
+        if *redact_next || SECRET_PREFIXES.iter().any(|prefix| token.starts_with(prefix)) {
+            redacted.push_str("[redacted]");
+            // `Bearer <token>`: the credential is the next token
+            *redact_next = token == "Bearer";
+        } else {
+            redacted.push_str(token);
+        }
+        token.clear();
+    };
+
+    for c in truncated.chars() {
+        if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+            token.push(c);
+        } else {
+            flush(&mut token, &mut redacted, &mut redact_next);
+            redacted.push(c);
+        }
+    }
+    flush(&mut token, &mut redacted, &mut redact_next);
+
+    // `?key=...` in a Google URL
+    match redacted.find("key=") {
+        Some(start) => {
+            let value_start = start + "key=".len();
+            let value_end = redacted[value_start..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+                .map(|offset| value_start + offset)
+                .unwrap_or(redacted.len());
+            format!("{}[redacted]{}", &redacted[..value_start], &redacted[value_end..])
+        }
+        None => redacted,
+    }
+}
+
+/// Recorded error as listed for support
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderErrorEntry {
+    pub id: Uuid,
+    pub proxy_key_id: Option<Uuid>,
+    pub provider: String,
+    pub model: String,
+    pub status_code: i32,
+    pub error_code: Option<String>,
+    pub message: String,
+    pub created_at: String,
+}
+
+/// Insert an error and prune the user's history beyond the cap
+pub async fn record(pool: &PgPool, error: &ProviderErrorRecord) -> Result<Uuid, sqlx::Error> {
+    let id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO provider_errors (user_id, proxy_key_id, provider, model, status_code, error_code, message)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+        "#,
+    )
+    .bind(error.user_id)
+    .bind(error.proxy_key_id)
+    .bind(error.provider.name().to_lowercase())
+    .bind(&error.model)
+    .bind(error.status_code)
+    .bind(&error.error_code)
+    .bind(&error.message)
+    .fetch_one(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM provider_errors
+        WHERE user_id = $1 AND id NOT IN (
+            SELECT id FROM provider_errors WHERE user_id = $1 ORDER BY created_at DESC, id LIMIT $2
+        )
+        "#,
+    )
+    .bind(error.user_id)
+    .bind(MAX_ERRORS_PER_USER)
+    .execute(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// Record an error in the background so the response isn't delayed
+pub fn record_async(tasks: &TaskManager, pool: PgPool, error: ProviderErrorRecord) {
+    tasks.spawn(async move {
+        if let Err(e) = record(&pool, &error).await {
+            tracing::warn!(user_id = %error.user_id, "Failed to record provider error: {}", e);
+        }
+    });
+}
+
+/// A user's most recent provider errors, newest first
+pub async fn list_recent(pool: &PgPool, user_id: Uuid, limit: i64) -> Result<Vec<ProviderErrorEntry>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, proxy_key_id, provider, model, status_code, error_code, message, created_at
+        FROM provider_errors
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| ProviderErrorEntry {
+            id: row.get("id"),
+            proxy_key_id: row.get("proxy_key_id"),
+            provider: row.get("provider"),
+            model: row.get("model"),
+            status_code: row.get("status_code"),
+            error_code: row.get("error_code"),
+            message: row.get("message"),
+            created_at: row
+                .get::<chrono::DateTime<chrono::Utc>, _>("created_at")
+                .to_rfc3339(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_for(provider: Provider, status: u16, body: serde_json::Value) -> ProviderErrorRecord {
+        ProviderErrorRecord::from_upstream(Uuid::nil(), None, provider, "model", status, body.to_string().as_bytes())
+    }
+
+    #[test]
+    fn test_error_shapes_per_provider() {
+        let openai = record_for(Provider::OpenAI, 400, serde_json::json!({
+            "error": { "message": "Invalid 'messages'", "type": "invalid_request_error", "code": "invalid_value" }
+        }));
+        assert_eq!(openai.error_code.as_deref(), Some("invalid_value"));
+        assert_eq!(openai.message, "Invalid 'messages'");
+        assert_eq!(openai.status_code, 400);
+
+        let anthropic = record_for(Provider::Anthropic, 529, serde_json::json!({
+            "type": "error", "error": { "type": "overloaded_error", "message": "Overloaded" }
+        }));
+        assert_eq!(anthropic.error_code.as_deref(), Some("overloaded_error"));
+        assert_eq!(anthropic.message, "Overloaded");
+
+        let google = record_for(Provider::Google, 400, serde_json::json!({
+            "error": { "code": 400, "message": "API key not valid", "status": "INVALID_ARGUMENT" }
+        }));
+        assert_eq!(google.error_code.as_deref(), Some("400"));
+        assert_eq!(google.message, "API key not valid");
+
+        let qwen = record_for(Provider::Qwen, 401, serde_json::json!({
+            "code": "InvalidApiKey", "message": "Invalid API-key provided.", "request_id": "abc"
+        }));
+        assert_eq!(qwen.error_code.as_deref(), Some("InvalidApiKey"));
+        assert_eq!(qwen.message, "Invalid API-key provided.");
+    }
+
+    #[test]
+    fn test_non_json_body_kept_as_text() {
+        let error = ProviderErrorRecord::from_upstream(Uuid::nil(), None, Provider::Qwen, "qwen-turbo", 502, b"<html>Bad Gateway</html>");
+        assert_eq!(error.error_code, None);
+        assert_eq!(error.message, "<html>Bad Gateway</html>");
+    }
+
+    #[test]
+    fn test_credentials_redacted_and_message_truncated() {
+        assert_eq!(
+            redact("Incorrect API key provided: sk-proj-abc123. See docs."),
+            "Incorrect API key provided: [redacted] See docs."
+        );
+        assert_eq!(redact("Authorization: Bearer wbr_live_123 rejected"), "Authorization: [redacted] [redacted] rejected");
+        assert_eq!(redact("key AIzaSyA-123_x is invalid"), "key [redacted] is invalid");
+        assert_eq!(
+            redact("POST /v1beta/models/gemini-pro:generateContent?key=secret123&alt=sse"),
+            "POST /v1beta/models/gemini-pro:generateContent?key=[redacted]&alt=sse"
+        );
+
+        let long = "x".repeat(MAX_MESSAGE_CHARS * 2);
+        assert_eq!(redact(&long).chars().count(), MAX_MESSAGE_CHARS);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_history_capped_per_user(pool: PgPool) {
+        let user_id = crate::test_support::insert_user(&pool, "cap@example.com", crate::models::PlanTier::Free).await;
+        let error = ProviderErrorRecord {
+            user_id,
+            ..record_for(Provider::OpenAI, 500, serde_json::json!({ "error": { "message": "boom" } }))
+        };
+        for _ in 0..MAX_ERRORS_PER_USER + 5 {
+            record(&pool, &error).await.unwrap();
+        }
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM provider_errors WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, MAX_ERRORS_PER_USER);
+        assert_eq!(list_recent(&pool, user_id, 10).await.unwrap().len(), 10);
+    }
+}
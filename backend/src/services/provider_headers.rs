@@ -0,0 +1,208 @@
+//! Static headers added to every upstream request for a provider.
+//!
+//! Some deployments need extra headers beyond auth and API version, such as a
+//! data-residency header or an account-level feature flag. They are read once
+//! at startup; an invalid entry stops the server from starting instead of
+//! being silently dropped. Headers the proxy sets itself (auth, content type,
+//! Anthropic version and beta flags) can't be overridden here.
+//!
+//! Configuration:
+//! - `PROVIDER_HEADERS_<PROVIDER>`: `;`-separated `Name: value` pairs
+//!   (e.g. `PROVIDER_HEADERS_QWEN=X-DashScope-DataInspection: enable`; none when unset)
+
+use reqwest::header::{HeaderName, HeaderValue};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::services::transformers::Provider;
+
+/// Headers the proxy sets itself, which configuration may not replace
+pub const RESERVED_HEADERS: &[&str] = &[
+    "authorization",
+    "x-api-key",
+    "x-goog-api-key",
+    "content-type",
+    "content-length",
+    "host",
+    "anthropic-version",
+    "anthropic-beta",
+];
+
+/// An invalid `PROVIDER_HEADERS_<PROVIDER>` entry
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ProviderHeadersError {
+    #[error("{variable}: expected `Name: value`, got '{entry}'")]
+    Malformed { variable: String, entry: String },
+    #[error("{variable}: invalid header name '{name}'")]
+    InvalidName { variable: String, name: String },
+    #[error("{variable}: invalid value for header '{name}'")]
+    InvalidValue { variable: String, name: String },
+    #[error("{variable}: header '{name}' is set by the proxy and can't be configured")]
+    Reserved { variable: String, name: String },
+}
+
+/// Configured static headers per provider
+#[derive(Debug, Clone, Default)]
+pub struct ProviderHeaders {
+    headers: Arc<HashMap<Provider, Vec<(HeaderName, HeaderValue)>>>,
+}
+
+impl ProviderHeaders {
+    /// Load headers from environment variables
+    pub fn from_env() -> Result<Self, ProviderHeadersError> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load headers using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Result<Self, ProviderHeadersError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut headers = HashMap::new();
+        for provider in Provider::ALL {
+            let variable = format!("PROVIDER_HEADERS_{}", provider.name().to_uppercase());
+            let Some(value) = lookup(&variable) else {
+                continue;
+            };
+            let parsed = parse_headers(&variable, &value)?;
+            if !parsed.is_empty() {
+                headers.insert(provider, parsed);
+            }
+        }
+        Ok(Self { headers: Arc::new(headers) })
+    }
+
+    /// Configured headers for a provider
+    pub fn for_provider(&self, provider: Provider) -> &[(HeaderName, HeaderValue)] {
+        self.headers.get(&provider).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Add a provider's configured headers to an upstream request
+    pub fn apply(&self, provider: Provider, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        for (name, value) in self.for_provider(provider) {
+            request = request.header(name.clone(), value.clone());
+        }
+        request
+    }
+
+    /// Number of headers configured across all providers
+    pub fn len(&self) -> usize {
+        self.headers.values().map(Vec::len).sum()
+    }
+
+    /// Whether no provider has headers configured
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn parse_headers(variable: &str, value: &str) -> Result<Vec<(HeaderName, HeaderValue)>, ProviderHeadersError> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, header_value) = entry.split_once(':').ok_or_else(|| ProviderHeadersError::Malformed {
+                variable: variable.to_string(),
+                entry: entry.to_string(),
+            })?;
+            let name = name.trim();
+            let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| ProviderHeadersError::InvalidName {
+                variable: variable.to_string(),
+                name: name.to_string(),
+            })?;
+            if RESERVED_HEADERS.contains(&header_name.as_str()) {
+                return Err(ProviderHeadersError::Reserved {
+                    variable: variable.to_string(),
+                    name: name.to_string(),
+                });
+            }
+            let header_value =
+                HeaderValue::from_str(header_value.trim()).map_err(|_| ProviderHeadersError::InvalidValue {
+                    variable: variable.to_string(),
+                    name: name.to_string(),
+                })?;
+            Ok((header_name, header_value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::env_lookup;
+    use axum::{http::HeaderMap, routing::post, Json, Router};
+
+    #[test]
+    fn test_headers_parsed_per_provider() {
+        let headers = ProviderHeaders::from_lookup(env_lookup(&[(
+            "PROVIDER_HEADERS_GOOGLE",
+            "X-Goog-User-Project: billing-123; X-Residency : eu ;",
+        )]))
+        .unwrap();
+
+        let google = headers.for_provider(Provider::Google);
+        assert_eq!(google.len(), 2);
+        assert_eq!(google[0].0, "x-goog-user-project");
+        assert_eq!(google[0].1, "billing-123");
+        assert_eq!(google[1].0, "x-residency");
+        assert_eq!(google[1].1, "eu");
+        assert!(headers.for_provider(Provider::OpenAI).is_empty());
+        assert_eq!(headers.len(), 2);
+
+        assert!(ProviderHeaders::from_lookup(|_| None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_headers_rejected() {
+        let error = |value: &'static str| ProviderHeaders::from_lookup(env_lookup(&[("PROVIDER_HEADERS_OPENAI", value)])).unwrap_err();
+
+        assert!(matches!(error("X-Residency"), ProviderHeadersError::Malformed { .. }));
+        assert!(matches!(error("Bad Name: value"), ProviderHeadersError::InvalidName { .. }));
+        assert!(matches!(error("X-Residency: eu\nX-Other: 1"), ProviderHeadersError::InvalidValue { .. }));
+        assert!(matches!(error("Authorization: Bearer sk-other"), ProviderHeadersError::Reserved { .. }));
+        assert_eq!(
+            error("x-api-key: secret").to_string(),
+            "PROVIDER_HEADERS_OPENAI: header 'x-api-key' is set by the proxy and can't be configured"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_configured_headers_sent_upstream() {
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(|headers: HeaderMap| async move {
+                let residency = headers.get("x-residency").and_then(|v| v.to_str().ok()).map(str::to_string);
+                Json(serde_json::json!({ "residency": residency }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let headers = ProviderHeaders::from_lookup(env_lookup(&[("PROVIDER_HEADERS_OPENAI", "X-Residency: eu")])).unwrap();
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/v1/chat/completions", addr);
+
+        let received: serde_json::Value = headers
+            .apply(Provider::OpenAI, client.post(&url))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(received["residency"], "eu");
+
+        // Other providers' requests don't get them
+        let received: serde_json::Value = headers
+            .apply(Provider::Qwen, client.post(&url))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(received["residency"].is_null());
+    }
+}
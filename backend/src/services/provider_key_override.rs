@@ -0,0 +1,135 @@
+//! Per-request provider keys ("bring your own key").
+//!
+//! Proxy keys created with `allow_provider_key_override` may send their own
+//! provider key in `X-Provider-Key`. It is used instead of the stored key for
+//! the provider the request is routed to, and is never logged or persisted.
+//! Requests routed elsewhere (e.g. a mirror on another provider) keep using
+//! stored keys.
+
+use axum::http::HeaderMap;
+
+use crate::models::api_key::AiProvider;
+use crate::services::api_key_service::ProviderCredentials;
+use crate::services::transformers::Provider;
+
+/// Header carrying a per-request provider key
+pub const PROVIDER_KEY_HEADER: &str = "x-provider-key";
+
+/// Why a request's provider key was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ProviderKeyOverrideError {
+    #[error("This API key is not allowed to supply its own provider key")]
+    NotAllowed,
+    #[error("X-Provider-Key is not a valid {} API key", .0.name())]
+    InvalidFormat(Provider),
+}
+
+/// A provider key supplied with a single request
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProviderKeyOverride {
+    provider: Provider,
+    api_key: String,
+}
+
+// Keep the key out of logs and debug output
+impl std::fmt::Debug for ProviderKeyOverride {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProviderKeyOverride")
+            .field("provider", &self.provider)
+            .field("api_key", &"[redacted]")
+            .finish()
+    }
+}
+
+impl ProviderKeyOverride {
+    /// Read the override for a request routed to `provider`.
+    /// `Ok(None)` when the header is absent or blank.
+    pub fn from_headers(
+        headers: &HeaderMap,
+        provider: Provider,
+        allowed: bool,
+    ) -> Result<Option<Self>, ProviderKeyOverrideError> {
+        let Some(api_key) = headers
+            .get(PROVIDER_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+        else {
+            return Ok(None);
+        };
+
+        if !allowed {
+            return Err(ProviderKeyOverrideError::NotAllowed);
+        }
+        if !AiProvider::from(provider).validate_key_format(api_key) {
+            return Err(ProviderKeyOverrideError::InvalidFormat(provider));
+        }
+
+        Ok(Some(Self {
+            provider,
+            api_key: api_key.to_string(),
+        }))
+    }
+
+    /// Credentials to use when forwarding to `provider`, if this override applies
+    pub fn credentials_for(&self, provider: Provider) -> Option<ProviderCredentials> {
+        (self.provider == provider).then(|| ProviderCredentials {
+            key_id: None,
+            api_key: self.api_key.clone(),
+            openai_organization: None,
+            openai_project: None,
+            anthropic_beta: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(PROVIDER_KEY_HEADER, key.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_header_key_used_for_routed_provider_only() {
+        let key = ProviderKeyOverride::from_headers(&headers("sk-user-own-key"), Provider::OpenAI, true)
+            .unwrap()
+            .unwrap();
+
+        let credentials = key.credentials_for(Provider::OpenAI).unwrap();
+        assert_eq!(credentials.api_key, "sk-user-own-key");
+        assert!(credentials.openai_organization.is_none());
+        assert!(key.credentials_for(Provider::Anthropic).is_none());
+    }
+
+    #[test]
+    fn test_header_forbidden_without_permission() {
+        assert_eq!(
+            ProviderKeyOverride::from_headers(&headers("sk-user-own-key"), Provider::OpenAI, false),
+            Err(ProviderKeyOverrideError::NotAllowed)
+        );
+        // No header is fine either way
+        assert_eq!(ProviderKeyOverride::from_headers(&HeaderMap::new(), Provider::OpenAI, false), Ok(None));
+    }
+
+    #[test]
+    fn test_header_key_format_checked_for_provider() {
+        assert_eq!(
+            ProviderKeyOverride::from_headers(&headers("sk-openai-key"), Provider::Anthropic, true),
+            Err(ProviderKeyOverrideError::InvalidFormat(Provider::Anthropic))
+        );
+    }
+
+    #[test]
+    fn test_debug_output_redacts_key() {
+        let key = ProviderKeyOverride::from_headers(&headers("sk-ant-secret"), Provider::Anthropic, true)
+            .unwrap()
+            .unwrap();
+        let debug = format!("{:?}", key);
+        assert!(!debug.contains("sk-ant-secret"));
+        assert!(debug.contains("[redacted]"));
+    }
+}
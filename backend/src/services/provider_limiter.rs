@@ -0,0 +1,168 @@
+//! Global per-provider concurrency shaping.
+//!
+//! Caps in-flight upstream requests per provider across all users so we stay
+//! under provider org limits. Requests beyond the cap queue briefly and are
+//! rejected once the queue timeout elapses.
+//!
+//! Configuration:
+//! - `PROVIDER_MAX_CONCURRENCY` / `PROVIDER_MAX_CONCURRENCY_<PROVIDER>`
+//! - `PROVIDER_QUEUE_TIMEOUT_MS`
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::services::transformers::Provider;
+
+/// Default max concurrent upstream requests per provider
+pub const DEFAULT_MAX_CONCURRENCY: usize = 64;
+
+/// Default time a request may wait for a slot
+pub const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(2);
+
+const PROVIDERS: [Provider; 4] = [
+    Provider::OpenAI,
+    Provider::Anthropic,
+    Provider::Google,
+    Provider::Qwen,
+];
+
+/// Provider has no free upstream slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProviderSaturated(pub Provider);
+
+impl std::fmt::Display for ProviderSaturated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is at capacity, please retry shortly", self.0.name())
+    }
+}
+
+impl std::error::Error for ProviderSaturated {}
+
+/// Per-provider upstream concurrency limiter shared by all requests
+#[derive(Clone)]
+pub struct ProviderLimiter {
+    semaphores: HashMap<Provider, Arc<Semaphore>>,
+    queue_timeout: Duration,
+}
+
+impl ProviderLimiter {
+    /// Create a limiter with the same limit for every provider
+    pub fn new(max_concurrency: usize, queue_timeout: Duration) -> Self {
+        Self::from_limits(|_| max_concurrency, queue_timeout)
+    }
+
+    /// Load limits from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load limits using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let read = |key: &str| lookup(key).and_then(|v| v.trim().parse::<u64>().ok());
+
+        let global = read("PROVIDER_MAX_CONCURRENCY")
+            .map(|v| v as usize)
+            .unwrap_or(DEFAULT_MAX_CONCURRENCY);
+        let queue_timeout = read("PROVIDER_QUEUE_TIMEOUT_MS")
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_QUEUE_TIMEOUT);
+
+        Self::from_limits(
+            |provider| {
+                let key = format!("PROVIDER_MAX_CONCURRENCY_{}", provider.name().to_uppercase());
+                read(&key).map(|v| v as usize).unwrap_or(global)
+            },
+            queue_timeout,
+        )
+    }
+
+    fn from_limits<F>(limit: F, queue_timeout: Duration) -> Self
+    where
+        F: Fn(Provider) -> usize,
+    {
+        let semaphores = PROVIDERS
+            .iter()
+            .map(|&provider| (provider, Arc::new(Semaphore::new(limit(provider).max(1)))))
+            .collect();
+
+        Self {
+            semaphores,
+            queue_timeout,
+        }
+    }
+
+    /// Wait for an upstream slot, giving up after the queue timeout.
+    /// The slot is released when the returned permit is dropped.
+    pub async fn acquire(&self, provider: Provider) -> Result<OwnedSemaphorePermit, ProviderSaturated> {
+        let semaphore = self.semaphores[&provider].clone();
+
+        match tokio::time::timeout(self.queue_timeout, semaphore.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            // Timed out, or semaphore closed (never happens)
+            _ => {
+                tracing::warn!(provider = provider.name(), "Provider concurrency limit reached");
+                Err(ProviderSaturated(provider))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_calls_beyond_limit_are_throttled() {
+        let limiter = ProviderLimiter::new(2, Duration::from_millis(20));
+
+        let first = limiter.acquire(Provider::OpenAI).await.unwrap();
+        let _second = limiter.acquire(Provider::OpenAI).await.unwrap();
+
+        assert_eq!(
+            limiter.acquire(Provider::OpenAI).await.unwrap_err(),
+            ProviderSaturated(Provider::OpenAI)
+        );
+
+        // Other providers have their own budget
+        assert!(limiter.acquire(Provider::Anthropic).await.is_ok());
+
+        // Releasing a slot lets the next call through
+        drop(first);
+        assert!(limiter.acquire(Provider::OpenAI).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queued_call_proceeds_when_slot_frees() {
+        let limiter = ProviderLimiter::new(1, Duration::from_millis(500));
+        let held = limiter.acquire(Provider::Google).await.unwrap();
+
+        let waiter = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move { limiter.acquire(Provider::Google).await.is_ok() })
+        };
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(held);
+
+        assert!(waiter.await.unwrap());
+    }
+
+    #[test]
+    fn test_per_provider_override() {
+        let limiter = ProviderLimiter::from_lookup(|key| match key {
+            "PROVIDER_MAX_CONCURRENCY" => Some("10".to_string()),
+            "PROVIDER_MAX_CONCURRENCY_QWEN" => Some("3".to_string()),
+            "PROVIDER_QUEUE_TIMEOUT_MS" => Some("250".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(limiter.semaphores[&Provider::Qwen].available_permits(), 3);
+        assert_eq!(limiter.semaphores[&Provider::OpenAI].available_permits(), 10);
+        assert_eq!(limiter.queue_timeout, Duration::from_millis(250));
+    }
+}
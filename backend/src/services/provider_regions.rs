@@ -0,0 +1,254 @@
+//! Regional upstream endpoints with latency-aware selection.
+//!
+//! Providers with several regional deployments list them in a static table;
+//! the first entry is the default region. Deployments opt into extra regions
+//! per provider, a background probe measures each enabled region's latency,
+//! and requests go to the fastest reachable region. Until a probe has
+//! succeeded (or when every region fails) requests use the default region.
+//!
+//! Configuration:
+//! - `PROVIDER_REGIONS_<PROVIDER>`: comma-separated regions to enable
+//!   (e.g. `PROVIDER_REGIONS_QWEN=beijing,singapore`; default region only when unset)
+//! - `PROVIDER_PROBE_INTERVAL_SECS`: interval between latency probes (default `60`)
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::services::transformers::Provider;
+
+/// Default interval between latency probes
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Probes slower than this count as unreachable
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A regional deployment of a provider's API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub name: &'static str,
+    pub base_url: &'static str,
+}
+
+const OPENAI_REGIONS: &[Region] = &[Region { name: "global", base_url: "https://api.openai.com" }];
+
+const ANTHROPIC_REGIONS: &[Region] = &[Region { name: "global", base_url: "https://api.anthropic.com" }];
+
+const GOOGLE_REGIONS: &[Region] = &[Region {
+    name: "global",
+    base_url: "https://generativelanguage.googleapis.com",
+}];
+
+const QWEN_REGIONS: &[Region] = &[
+    Region { name: "beijing", base_url: "https://dashscope.aliyuncs.com" },
+    Region { name: "singapore", base_url: "https://dashscope-intl.aliyuncs.com" },
+];
+
+/// Known regions for a provider, default region first
+pub fn regions(provider: Provider) -> &'static [Region] {
+    match provider {
+        Provider::OpenAI => OPENAI_REGIONS,
+        Provider::Anthropic => ANTHROPIC_REGIONS,
+        Provider::Google => GOOGLE_REGIONS,
+        Provider::Qwen => QWEN_REGIONS,
+    }
+}
+
+/// Picks an upstream region per provider from probed latencies
+#[derive(Debug, Clone, Default)]
+pub struct RegionSelector {
+    /// Regions enabled beyond the default, per provider
+    enabled: Arc<HashMap<Provider, Vec<Region>>>,
+    /// Last successful probe latency per region
+    latencies: Arc<RwLock<HashMap<(Provider, &'static str), Duration>>>,
+}
+
+impl RegionSelector {
+    /// Load enabled regions from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load enabled regions using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let enabled = Provider::ALL
+            .into_iter()
+            .filter_map(|provider| {
+                let key = format!("PROVIDER_REGIONS_{}", provider.name().to_uppercase());
+                let value = lookup(&key)?;
+                let known = regions(provider);
+                let selected: Vec<Region> = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .filter_map(|name| {
+                        let region = known.iter().find(|region| region.name == name).copied();
+                        if region.is_none() {
+                            tracing::warn!(provider = provider.name(), region = name, "Ignoring unknown region");
+                        }
+                        region
+                    })
+                    .collect();
+                (!selected.is_empty()).then_some((provider, selected))
+            })
+            .collect();
+
+        Self {
+            enabled: Arc::new(enabled),
+            latencies: Arc::default(),
+        }
+    }
+
+    /// Regions requests for a provider may be sent to
+    pub fn enabled_regions(&self, provider: Provider) -> &[Region] {
+        match self.enabled.get(&provider) {
+            Some(enabled) => enabled,
+            None => &regions(provider)[..1],
+        }
+    }
+
+    /// Record a probe result (`None` when the region was unreachable)
+    pub fn record_latency(&self, provider: Provider, region: &'static str, latency: Option<Duration>) {
+        if let Ok(mut latencies) = self.latencies.write() {
+            match latency {
+                Some(latency) => latencies.insert((provider, region), latency),
+                None => latencies.remove(&(provider, region)),
+            };
+        }
+    }
+
+    /// Fastest probed region, falling back to the first enabled one
+    pub fn select(&self, provider: Provider) -> Region {
+        let enabled = self.enabled_regions(provider);
+        let fastest = self.latencies.read().ok().and_then(|latencies| {
+            enabled
+                .iter()
+                .filter_map(|region| latencies.get(&(provider, region.name)).map(|latency| (*latency, *region)))
+                .min_by_key(|(latency, _)| *latency)
+                .map(|(_, region)| region)
+        });
+
+        fastest.unwrap_or(enabled[0])
+    }
+
+    /// Base URL for a provider's next upstream request
+    pub fn base_url(&self, provider: Provider) -> &'static str {
+        self.select(provider).base_url
+    }
+
+    /// Whether any provider has more than one region to choose from
+    pub fn has_choices(&self) -> bool {
+        self.enabled.values().any(|regions| regions.len() > 1)
+    }
+
+    /// Measure every enabled region of providers with a choice of regions.
+    /// Any HTTP response counts as reachable; only connection failures and
+    /// timeouts mark a region as down.
+    pub async fn probe(&self, client: &reqwest::Client) {
+        for (provider, regions) in self.enabled.iter().filter(|(_, regions)| regions.len() > 1) {
+            for region in regions {
+                let started = Instant::now();
+                let result = client.head(region.base_url).timeout(PROBE_TIMEOUT).send().await;
+                let latency = match result {
+                    Ok(_) => Some(started.elapsed()),
+                    Err(e) => {
+                        tracing::warn!(provider = provider.name(), region = region.name, "Region probe failed: {}", e);
+                        None
+                    }
+                };
+                self.record_latency(*provider, region.name, latency);
+            }
+        }
+    }
+
+    /// Spawn background task that periodically probes region latency
+    pub fn spawn_probe_worker(&self, client: reqwest::Client, interval: Duration) {
+        let selector = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                selector.probe(&client).await;
+            }
+        });
+    }
+}
+
+/// Read the probe interval from `PROVIDER_PROBE_INTERVAL_SECS`
+pub fn probe_interval_from_env() -> Duration {
+    std::env::var("PROVIDER_PROBE_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_PROBE_INTERVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qwen_both_regions() -> RegionSelector {
+        RegionSelector::from_lookup(|key| match key {
+            "PROVIDER_REGIONS_QWEN" => Some("beijing, singapore".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_default_region_without_config() {
+        let selector = RegionSelector::default();
+        for provider in Provider::ALL {
+            assert_eq!(selector.select(provider), regions(provider)[0]);
+        }
+        assert_eq!(selector.base_url(Provider::Qwen), "https://dashscope.aliyuncs.com");
+        assert!(!selector.has_choices());
+    }
+
+    #[test]
+    fn test_selection_prefers_lower_latency_region() {
+        let selector = qwen_both_regions();
+        assert!(selector.has_choices());
+        // No probe yet: default region
+        assert_eq!(selector.select(Provider::Qwen).name, "beijing");
+
+        selector.record_latency(Provider::Qwen, "beijing", Some(Duration::from_millis(180)));
+        selector.record_latency(Provider::Qwen, "singapore", Some(Duration::from_millis(35)));
+        assert_eq!(selector.base_url(Provider::Qwen), "https://dashscope-intl.aliyuncs.com");
+
+        selector.record_latency(Provider::Qwen, "singapore", Some(Duration::from_millis(400)));
+        assert_eq!(selector.select(Provider::Qwen).name, "beijing");
+    }
+
+    #[test]
+    fn test_unreachable_region_is_skipped() {
+        let selector = qwen_both_regions();
+        selector.record_latency(Provider::Qwen, "beijing", Some(Duration::from_millis(180)));
+        selector.record_latency(Provider::Qwen, "singapore", Some(Duration::from_millis(35)));
+
+        selector.record_latency(Provider::Qwen, "singapore", None);
+        assert_eq!(selector.select(Provider::Qwen).name, "beijing");
+
+        // Every region down: back to the first enabled region
+        selector.record_latency(Provider::Qwen, "beijing", None);
+        assert_eq!(selector.select(Provider::Qwen).name, "beijing");
+    }
+
+    #[test]
+    fn test_config_ignores_unknown_regions_and_keeps_order() {
+        let selector = RegionSelector::from_lookup(|key| match key {
+            "PROVIDER_REGIONS_QWEN" => Some("singapore,mars".to_string()),
+            "PROVIDER_REGIONS_OPENAI" => Some("mars".to_string()),
+            _ => None,
+        });
+
+        // Only the configured regions are used; the first is the fallback
+        assert_eq!(selector.enabled_regions(Provider::Qwen), &QWEN_REGIONS[1..]);
+        assert_eq!(selector.select(Provider::Qwen).name, "singapore");
+        // Nothing valid configured: the provider's default region
+        assert_eq!(selector.enabled_regions(Provider::OpenAI), OPENAI_REGIONS);
+    }
+}
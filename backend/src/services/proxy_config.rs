@@ -0,0 +1,125 @@
+//! Proxy request settings, read from the environment once at startup.
+//!
+//! Each setting is documented (with its variables) in its own module; this
+//! only gathers them so `chat_completions` doesn't re-read the environment
+//! on every request.
+
+use crate::models::PlanTier;
+use crate::services::{
+    anthropic_headers::AnthropicHeaderConfig,
+    anthropic_overload::OverloadRetryPolicy,
+    content_sanitizer::ContentSanitizer,
+    debug_capture::DebugCaptureConfig,
+    model_access::{DisallowedStreaming, ModelAccess},
+    request_guard::ConversationLimits,
+    request_id::RequestIdConfig,
+    request_timing::SlowRequestConfig,
+    safety_fallback::SafetyFallbackConfig,
+    shadow_mirror::MirrorConfig,
+    stream_coalesce::StreamCoalesceConfig,
+    transcripts::TranscriptConfig,
+    usage_receipt::UsageReceiptConfig,
+};
+
+/// A setting with one value per plan tier
+#[derive(Debug, Clone, PartialEq)]
+pub struct PerPlan<T> {
+    free: T,
+    starter: T,
+    pro: T,
+    team: T,
+}
+
+impl<T> PerPlan<T> {
+    fn from_fn(value: impl Fn(PlanTier) -> T) -> Self {
+        Self {
+            free: value(PlanTier::Free),
+            starter: value(PlanTier::Starter),
+            pro: value(PlanTier::Pro),
+            team: value(PlanTier::Team),
+        }
+    }
+
+    pub fn get(&self, plan: PlanTier) -> &T {
+        match plan {
+            PlanTier::Free => &self.free,
+            PlanTier::Starter => &self.starter,
+            PlanTier::Pro => &self.pro,
+            PlanTier::Team => &self.team,
+        }
+    }
+}
+
+/// Settings for proxied completion requests
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    pub model_access: PerPlan<ModelAccess>,
+    pub disallowed_streaming: DisallowedStreaming,
+    pub conversation_limits: PerPlan<ConversationLimits>,
+    pub content_sanitizer: ContentSanitizer,
+    pub mirror: Option<MirrorConfig>,
+    pub safety_fallback: Option<SafetyFallbackConfig>,
+    pub debug_capture: DebugCaptureConfig,
+    pub transcripts: TranscriptConfig,
+    pub slow_request: Option<SlowRequestConfig>,
+    pub usage_receipt: UsageReceiptConfig,
+    pub upstream_retry: OverloadRetryPolicy,
+    pub anthropic_headers: AnthropicHeaderConfig,
+    pub request_id: RequestIdConfig,
+    pub stream_coalesce: Option<StreamCoalesceConfig>,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self::from_lookup(|_| None)
+    }
+}
+
+impl ProxyConfig {
+    /// Load settings from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load settings using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        Self {
+            model_access: PerPlan::from_fn(|plan| ModelAccess::from_lookup(plan, &lookup)),
+            disallowed_streaming: DisallowedStreaming::from_lookup(&lookup),
+            conversation_limits: PerPlan::from_fn(|plan| ConversationLimits::from_lookup(plan, &lookup)),
+            content_sanitizer: ContentSanitizer::from_lookup(&lookup),
+            mirror: MirrorConfig::from_lookup(&lookup),
+            safety_fallback: SafetyFallbackConfig::from_lookup(&lookup),
+            debug_capture: DebugCaptureConfig::from_lookup(&lookup),
+            transcripts: TranscriptConfig::from_lookup(&lookup),
+            slow_request: SlowRequestConfig::from_lookup(&lookup),
+            usage_receipt: UsageReceiptConfig::from_lookup(&lookup),
+            upstream_retry: OverloadRetryPolicy::from_lookup(&lookup),
+            anthropic_headers: AnthropicHeaderConfig::from_lookup(&lookup),
+            request_id: RequestIdConfig::from_lookup(&lookup),
+            stream_coalesce: StreamCoalesceConfig::from_lookup(&lookup),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::env_lookup;
+
+    #[test]
+    fn test_per_plan_settings_loaded_for_each_tier() {
+        let config = ProxyConfig::from_lookup(env_lookup(&[
+            ("MAX_MESSAGES_PER_REQUEST", "40"),
+            ("MAX_MESSAGES_PER_REQUEST_TEAM", "900"),
+        ]));
+
+        assert_eq!(config.conversation_limits.get(PlanTier::Free).max_messages, 40);
+        assert_eq!(config.conversation_limits.get(PlanTier::Pro).max_messages, 40);
+        assert_eq!(config.conversation_limits.get(PlanTier::Team).max_messages, 900);
+        assert_eq!(config.model_access.get(PlanTier::Pro), &ModelAccess::for_plan(PlanTier::Pro));
+    }
+}
@@ -52,6 +52,27 @@ impl From<sqlx::Error> for ProxyKeyError {
     }
 }
 
+/// A validated proxy key
+#[derive(Debug, Clone, Copy)]
+pub struct ValidatedProxyKey {
+    pub key_id: Uuid,
+    pub user_id: Uuid,
+    pub daily_request_limit: Option<i64>,
+    pub unmetered: bool,
+    pub allow_provider_key_override: bool,
+    pub store_transcripts: bool,
+    /// Owner's plan, read with the key so plan limits need no extra query
+    pub plan: PlanTier,
+}
+
+/// Active key joined with its owner's plan
+#[derive(sqlx::FromRow)]
+struct ActiveProxyKey {
+    #[sqlx(flatten)]
+    key: ProxyApiKey,
+    plan_tier: PlanTier,
+}
+
 /// Proxy key service implementation
 pub struct ProxyKeyService;
 
@@ -96,8 +117,8 @@ impl ProxyKeyService {
 
         sqlx::query(
             r#"
-            INSERT INTO proxy_api_keys (id, user_id, key_hash, key_prefix, name, is_active, request_count, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, true, 0, $6, $6)
+            INSERT INTO proxy_api_keys (id, user_id, key_hash, key_prefix, name, is_active, request_count, daily_request_limit, allow_provider_key_override, store_transcripts, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, true, 0, $6, $7, $8, $9, $9)
             "#,
         )
         .bind(id)
@@ -105,6 +126,9 @@ impl ProxyKeyService {
         .bind(&key_hash)
         .bind(&key_prefix)
         .bind(&input.name)
+        .bind(input.daily_request_limit)
+        .bind(input.allow_provider_key_override)
+        .bind(input.store_transcripts)
         .bind(now)
         .execute(pool)
         .await?;
@@ -128,7 +152,7 @@ impl ProxyKeyService {
     ) -> Result<Vec<ProxyApiKeyInfo>, ProxyKeyError> {
         let keys: Vec<ProxyApiKey> = sqlx::query_as(
             r#"
-            SELECT id, user_id, key_hash, key_prefix, name, is_active, last_used_at, request_count, created_at, updated_at
+            SELECT id, user_id, key_hash, key_prefix, name, is_active, last_used_at, request_count, daily_request_limit, is_unmetered, allow_provider_key_override, store_transcripts, created_at, updated_at
             FROM proxy_api_keys
             WHERE user_id = $1
             ORDER BY created_at DESC
@@ -167,29 +191,52 @@ impl ProxyKeyService {
         Ok(())
     }
 
-    /// Validate a proxy API key and return user_id if valid
+    /// Mark a proxy key as unmetered (internal) or metered.
+    /// Admin-only: callers must not expose this to key owners.
+    pub async fn set_unmetered(
+        pool: &PgPool,
+        key_id: Uuid,
+        unmetered: bool,
+    ) -> Result<(), ProxyKeyError> {
+        let result = sqlx::query(
+            "UPDATE proxy_api_keys SET is_unmetered = $2, updated_at = NOW() WHERE id = $1",
+        )
+        .bind(key_id)
+        .bind(unmetered)
+        .execute(pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(ProxyKeyError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Validate a proxy API key and return its owner and limits if valid
     /// Requirement: 7.1, 7.2
     pub async fn validate_key(
         pool: &PgPool,
         key: &str,
-    ) -> Result<(Uuid, Uuid), ProxyKeyError> {
+    ) -> Result<ValidatedProxyKey, ProxyKeyError> {
         // Key must start with prefix
         if !key.starts_with(PROXY_KEY_PREFIX) {
             return Err(ProxyKeyError::NotFound);
         }
 
-        // Get all active keys and check against hash
-        let keys: Vec<ProxyApiKey> = sqlx::query_as(
+        // Get all active keys, with their owners' plans, and check against hash
+        let keys: Vec<ActiveProxyKey> = sqlx::query_as(
             r#"
-            SELECT id, user_id, key_hash, key_prefix, name, is_active, last_used_at, request_count, created_at, updated_at
-            FROM proxy_api_keys
-            WHERE is_active = true
+            SELECT k.id, k.user_id, k.key_hash, k.key_prefix, k.name, k.is_active, k.last_used_at, k.request_count, k.daily_request_limit, k.is_unmetered, k.allow_provider_key_override, k.store_transcripts, k.created_at, k.updated_at, u.plan_tier
+            FROM proxy_api_keys k
+            JOIN users u ON u.id = k.user_id
+            WHERE k.is_active = true
             "#,
         )
         .fetch_all(pool)
         .await?;
 
-        for proxy_key in keys {
+        for ActiveProxyKey { key: proxy_key, plan_tier } in keys {
             if verify_password(key, &proxy_key.key_hash).unwrap_or(false) {
                 // Update last_used_at and increment request_count
                 sqlx::query(
@@ -199,7 +246,15 @@ impl ProxyKeyService {
                 .execute(pool)
                 .await?;
 
-                return Ok((proxy_key.id, proxy_key.user_id));
+                return Ok(ValidatedProxyKey {
+                    key_id: proxy_key.id,
+                    user_id: proxy_key.user_id,
+                    daily_request_limit: proxy_key.daily_request_limit.map(i64::from),
+                    unmetered: proxy_key.is_unmetered,
+                    allow_provider_key_override: proxy_key.allow_provider_key_override,
+                    store_transcripts: proxy_key.store_transcripts,
+                    plan: plan_tier,
+                });
             }
         }
 
@@ -269,4 +324,30 @@ mod tests {
             prop_assert_ne!(key1, key2);
         }
     }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_validated_key_carries_owner_plan(pool: PgPool) {
+        let user_id = crate::test_support::insert_user(&pool, "plan@example.com", PlanTier::Pro).await;
+        let key = generate_proxy_key_string();
+        sqlx::query("INSERT INTO proxy_api_keys (user_id, key_hash, key_prefix, name) VALUES ($1, $2, 'wbr_test', 'ci')")
+            .bind(user_id)
+            .bind(hash_password(&key).unwrap())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let validated = ProxyKeyService::validate_key(&pool, &key).await.unwrap();
+        assert_eq!(validated.user_id, user_id);
+        assert_eq!(validated.plan, PlanTier::Pro);
+
+        // A plan change applies to the very next request
+        sqlx::query("UPDATE users SET plan_tier = 'team' WHERE id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let validated = ProxyKeyService::validate_key(&pool, &key).await.unwrap();
+        assert_eq!(validated.plan, PlanTier::Team);
+    }
 }
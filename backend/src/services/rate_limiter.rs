@@ -1,11 +1,32 @@
 use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
 use redis::AsyncCommands;
 use serde::Serialize;
+use std::sync::OnceLock;
 use uuid::Uuid;
 
 use crate::services::billing_service::PlanTier;
 
-/// Rate limit check result
+/// Quota window a limit applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaWindow {
+    Minute,
+    Daily,
+    Monthly,
+}
+
+impl QuotaWindow {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaWindow::Minute => "minute",
+            QuotaWindow::Daily => "daily",
+            QuotaWindow::Monthly => "monthly",
+        }
+    }
+}
+
+/// Rate limit check result.
+/// `window` is the limit that was hit, or the tightest one when allowed.
 #[derive(Debug, Serialize)]
 pub struct RateLimitResult {
     pub allowed: bool,
@@ -13,6 +34,26 @@ pub struct RateLimitResult {
     pub limit: i64,
     pub reset_at: DateTime<Utc>,
     pub retry_after_secs: Option<i64>,
+    pub window: QuotaWindow,
+    /// Monthly requests used, including this one when allowed
+    pub monthly_used: i64,
+}
+
+/// Limits applied to a request
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaLimits {
+    /// Per-user monthly limit from the plan
+    pub monthly: i64,
+    /// Optional per-key daily limit
+    pub daily: Option<i64>,
+}
+
+/// Counter values before the current request
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuotaUsage {
+    pub monthly_used: i64,
+    pub daily_used: i64,
+    pub minute_used: i64,
 }
 
 /// Rate limit usage info
@@ -36,6 +77,61 @@ pub enum RateLimitError {
 /// Per-minute burst limit
 const BURST_LIMIT: i64 = 60;
 
+/// Atomically check every counter against its limit and increment them all
+/// only if none is exhausted, so concurrent requests can't overshoot.
+///
+/// KEYS: monthly, minute, and optionally the key's daily counter.
+/// ARGV: monthly limit, daily limit, burst limit, monthly TTL, daily TTL.
+/// Returns the counter values before this request: monthly, daily, minute.
+const QUOTA_SCRIPT: &str = r#"
+local monthly = tonumber(redis.call('GET', KEYS[1]) or '0')
+local minute = tonumber(redis.call('GET', KEYS[2]) or '0')
+local daily = 0
+if #KEYS > 2 then
+    daily = tonumber(redis.call('GET', KEYS[3]) or '0')
+end
+
+local allowed = monthly < tonumber(ARGV[1]) and minute < tonumber(ARGV[3])
+if #KEYS > 2 then
+    allowed = allowed and daily < tonumber(ARGV[2])
+end
+
+if allowed then
+    redis.call('INCR', KEYS[1])
+    redis.call('EXPIRE', KEYS[1], ARGV[4])
+    redis.call('INCR', KEYS[2])
+    redis.call('EXPIRE', KEYS[2], 60)
+    if #KEYS > 2 then
+        redis.call('INCR', KEYS[3])
+        redis.call('EXPIRE', KEYS[3], ARGV[5])
+    end
+end
+
+return {monthly, daily, minute}
+"#;
+
+fn quota_script() -> &'static redis::Script {
+    static SCRIPT: OnceLock<redis::Script> = OnceLock::new();
+    SCRIPT.get_or_init(|| redis::Script::new(QUOTA_SCRIPT))
+}
+
+/// Give back a request's slot in each counter, never going below zero.
+///
+/// KEYS: the counters to decrement.
+const RELEASE_SCRIPT: &str = r#"
+for _, key in ipairs(KEYS) do
+    if tonumber(redis.call('GET', key) or '0') > 0 then
+        redis.call('DECR', key)
+    end
+end
+return 0
+"#;
+
+fn release_script() -> &'static redis::Script {
+    static SCRIPT: OnceLock<redis::Script> = OnceLock::new();
+    SCRIPT.get_or_init(|| redis::Script::new(RELEASE_SCRIPT))
+}
+
 /// Rate Limiter Service using Redis
 /// Requirements: 5.1, 5.2, 5.5
 pub struct RateLimiter {
@@ -48,6 +144,11 @@ impl RateLimiter {
         Ok(Self { redis })
     }
 
+    /// Create a rate limiter sharing an existing Redis client
+    pub fn from_client(redis: redis::Client) -> Self {
+        Self { redis }
+    }
+
     /// Get monthly key for user
     fn monthly_key(user_id: Uuid) -> String {
         let now = Utc::now();
@@ -61,6 +162,12 @@ impl RateLimiter {
     }
 
 
+    /// Day-bucketed key for a proxy key's daily counter
+    fn daily_key(key_id: Uuid) -> String {
+        let now = Utc::now();
+        format!("rate:key:{}:day:{}", key_id, now.format("%Y%m%d"))
+    }
+
     /// Check rate limit and increment counter if allowed
     /// Requirements: 5.1, 5.5
     /// Property 5: Rate Limiting Enforcement
@@ -68,59 +175,137 @@ impl RateLimiter {
         &self,
         user_id: Uuid,
         plan: PlanTier,
+    ) -> Result<RateLimitResult, RateLimitError> {
+        let limits = QuotaLimits {
+            monthly: plan.request_limit(),
+            daily: None,
+        };
+        self.check_and_increment_limits(user_id, None, limits).await
+    }
+
+    /// Check the user's monthly/burst limits and, when a proxy key is given,
+    /// its daily limit; increment all counters if allowed
+    pub async fn check_and_increment_limits(
+        &self,
+        user_id: Uuid,
+        key_id: Option<Uuid>,
+        limits: QuotaLimits,
     ) -> Result<RateLimitResult, RateLimitError> {
         let mut conn = self.redis.get_multiplexed_async_connection().await?;
-        
-        let monthly_limit = plan.request_limit();
+        let now = Utc::now();
+
         let monthly_key = Self::monthly_key(user_id);
         let minute_key = Self::minute_key(user_id);
+        let daily_key = key_id
+            .filter(|_| limits.daily.is_some())
+            .map(Self::daily_key);
 
-        // Check monthly limit
-        let monthly_used: i64 = conn.get(&monthly_key).await.unwrap_or(0);
-        
-        if monthly_used >= monthly_limit {
-            let reset_at = Self::next_month_start();
-            return Ok(RateLimitResult {
-                allowed: false,
-                remaining: 0,
-                limit: monthly_limit,
-                reset_at,
-                retry_after_secs: Some((reset_at - Utc::now()).num_seconds()),
-            });
+        // Check and increment in one step so the limit can't be overshot
+        let mut invocation = quota_script().prepare_invoke();
+        invocation.key(&monthly_key).key(&minute_key);
+        if let Some(key) = &daily_key {
+            invocation.key(key);
         }
+        invocation
+            .arg(limits.monthly)
+            .arg(limits.daily.unwrap_or(i64::MAX))
+            .arg(BURST_LIMIT)
+            .arg(Self::seconds_until_month_end())
+            .arg(Self::seconds_until_day_end(now));
+        let (monthly_used, daily_used, minute_used): (i64, i64, i64) =
+            invocation.invoke_async(&mut conn).await?;
 
-        // Check per-minute burst limit
-        let minute_used: i64 = conn.get(&minute_key).await.unwrap_or(0);
-        
-        if minute_used >= BURST_LIMIT {
-            let reset_at = Utc::now() + Duration::seconds(60 - (Utc::now().timestamp() % 60));
-            return Ok(RateLimitResult {
-                allowed: false,
-                remaining: monthly_limit - monthly_used,
-                limit: monthly_limit,
-                reset_at,
-                retry_after_secs: Some(60 - (Utc::now().timestamp() % 60)),
-            });
+        // Same decision as the script, built into a full result
+        let usage = QuotaUsage {
+            monthly_used,
+            daily_used,
+            minute_used,
+        };
+        Ok(Self::evaluate(usage, limits, now))
+    }
+
+    /// Undo `check_and_increment_limits` for a request that didn't succeed.
+    /// The per-minute burst counter keeps the attempt.
+    pub async fn release_limits(
+        &self,
+        user_id: Uuid,
+        key_id: Option<Uuid>,
+        limits: QuotaLimits,
+    ) -> Result<(), RateLimitError> {
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+
+        let mut invocation = release_script().prepare_invoke();
+        invocation.key(Self::monthly_key(user_id));
+        if let Some(key_id) = key_id.filter(|_| limits.daily.is_some()) {
+            invocation.key(Self::daily_key(key_id));
+        }
+        let _: i64 = invocation.invoke_async(&mut conn).await?;
+        Ok(())
+    }
+
+    /// Decide whether a request fits within its limits.
+    /// Monthly is checked first, then daily, then the per-minute burst.
+    pub fn evaluate(usage: QuotaUsage, limits: QuotaLimits, now: DateTime<Utc>) -> RateLimitResult {
+        let month_reset = Self::next_month_start();
+        let day_reset = Self::next_day_start(now);
+
+        if usage.monthly_used >= limits.monthly {
+            return Self::denied(QuotaWindow::Monthly, limits.monthly, month_reset, now, usage.monthly_used);
         }
 
-        // Increment both counters
-        let _: () = redis::pipe()
-            .atomic()
-            .incr(&monthly_key, 1)
-            .expire(&monthly_key, Self::seconds_until_month_end())
-            .incr(&minute_key, 1)
-            .expire(&minute_key, 60)
-            .query_async(&mut conn)
-            .await?;
-
-        let reset_at = Self::next_month_start();
-        Ok(RateLimitResult {
-            allowed: true,
-            remaining: monthly_limit - monthly_used - 1,
-            limit: monthly_limit,
+        if let Some(daily_limit) = limits.daily {
+            if usage.daily_used >= daily_limit {
+                return Self::denied(QuotaWindow::Daily, daily_limit, day_reset, now, usage.monthly_used);
+            }
+        }
+
+        if usage.minute_used >= BURST_LIMIT {
+            let minute_reset = now + Duration::seconds(60 - (now.timestamp() % 60));
+            return RateLimitResult {
+                remaining: limits.monthly - usage.monthly_used,
+                ..Self::denied(QuotaWindow::Minute, BURST_LIMIT, minute_reset, now, usage.monthly_used)
+            };
+        }
+
+        let monthly_remaining = limits.monthly - usage.monthly_used - 1;
+        match limits.daily {
+            Some(daily_limit) if daily_limit - usage.daily_used - 1 < monthly_remaining => RateLimitResult {
+                allowed: true,
+                remaining: daily_limit - usage.daily_used - 1,
+                limit: daily_limit,
+                reset_at: day_reset,
+                retry_after_secs: None,
+                window: QuotaWindow::Daily,
+                monthly_used: usage.monthly_used + 1,
+            },
+            _ => RateLimitResult {
+                allowed: true,
+                remaining: monthly_remaining,
+                limit: limits.monthly,
+                reset_at: month_reset,
+                retry_after_secs: None,
+                window: QuotaWindow::Monthly,
+                monthly_used: usage.monthly_used + 1,
+            },
+        }
+    }
+
+    fn denied(
+        window: QuotaWindow,
+        limit: i64,
+        reset_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+        monthly_used: i64,
+    ) -> RateLimitResult {
+        RateLimitResult {
+            allowed: false,
+            remaining: 0,
+            limit,
             reset_at,
-            retry_after_secs: None,
-        })
+            retry_after_secs: Some((reset_at - now).num_seconds().max(1)),
+            window,
+            monthly_used,
+        }
     }
 
     /// Get current usage without incrementing
@@ -169,6 +354,19 @@ impl RateLimiter {
             .unwrap_or(30 * 24 * 60 * 60) // Default to 30 days
     }
 
+    /// Calculate seconds until the end of the current UTC day
+    fn seconds_until_day_end(now: DateTime<Utc>) -> i64 {
+        (Self::next_day_start(now) - now).num_seconds().max(1)
+    }
+
+    /// Get start of next UTC day
+    fn next_day_start(now: DateTime<Utc>) -> DateTime<Utc> {
+        (now.date_naive() + Duration::days(1))
+            .and_hms_opt(0, 0, 0)
+            .map(|midnight| midnight.and_utc())
+            .unwrap_or(now + Duration::days(1))
+    }
+
     /// Get start of next month
     fn next_month_start() -> DateTime<Utc> {
         let now = Utc::now();
@@ -190,3 +388,134 @@ impl RateLimiter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 12, 11, 15, 30, 0).unwrap()
+    }
+
+    fn limits(daily: Option<i64>) -> QuotaLimits {
+        QuotaLimits { monthly: 10_000, daily }
+    }
+
+    #[test]
+    fn test_daily_limit_hit_with_monthly_headroom() {
+        let usage = QuotaUsage {
+            monthly_used: 250,
+            daily_used: 100,
+            minute_used: 0,
+        };
+
+        let result = RateLimiter::evaluate(usage, limits(Some(100)), now());
+
+        assert!(!result.allowed);
+        assert_eq!(result.window, QuotaWindow::Daily);
+        assert_eq!(result.limit, 100);
+        assert_eq!(result.remaining, 0);
+        // Resets at the next UTC midnight
+        assert_eq!(result.reset_at, Utc.with_ymd_and_hms(2024, 12, 12, 0, 0, 0).unwrap());
+        assert_eq!(result.retry_after_secs, Some(8 * 3600 + 30 * 60));
+    }
+
+    #[test]
+    fn test_under_daily_limit_is_allowed() {
+        let usage = QuotaUsage {
+            monthly_used: 250,
+            daily_used: 99,
+            minute_used: 0,
+        };
+
+        let result = RateLimiter::evaluate(usage, limits(Some(100)), now());
+
+        assert!(result.allowed);
+        assert_eq!(result.window, QuotaWindow::Daily);
+        assert_eq!(result.remaining, 0);
+    }
+
+    #[test]
+    fn test_monthly_limit_takes_precedence() {
+        let usage = QuotaUsage {
+            monthly_used: 10_000,
+            daily_used: 100,
+            minute_used: 0,
+        };
+
+        let result = RateLimiter::evaluate(usage, limits(Some(100)), now());
+
+        assert!(!result.allowed);
+        assert_eq!(result.window, QuotaWindow::Monthly);
+    }
+
+    #[test]
+    fn test_no_daily_limit_ignores_daily_usage() {
+        let usage = QuotaUsage {
+            monthly_used: 10,
+            daily_used: 5_000,
+            minute_used: 0,
+        };
+
+        let result = RateLimiter::evaluate(usage, limits(None), now());
+
+        assert!(result.allowed);
+        assert_eq!(result.window, QuotaWindow::Monthly);
+        assert_eq!(result.remaining, 10_000 - 11);
+    }
+
+    #[test]
+    fn test_burst_limit() {
+        let usage = QuotaUsage {
+            monthly_used: 10,
+            daily_used: 10,
+            minute_used: BURST_LIMIT,
+        };
+
+        let result = RateLimiter::evaluate(usage, limits(Some(100)), now());
+
+        assert!(!result.allowed);
+        assert_eq!(result.window, QuotaWindow::Minute);
+        assert_eq!(result.retry_after_secs, Some(60));
+    }
+
+    // Redis-backed tests; run with REDIS_URL set and `--ignored`
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_URL"]
+    async fn test_concurrent_requests_never_exceed_limit() {
+        let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL");
+        let client = redis::Client::open(redis_url).unwrap();
+        let limiter = std::sync::Arc::new(RateLimiter::from_client(client.clone()));
+        let user_id = Uuid::new_v4();
+        let key_id = Uuid::new_v4();
+        let limits = QuotaLimits { monthly: 10_000, daily: Some(20) };
+
+        let tasks: Vec<_> = (0..50)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    limiter
+                        .check_and_increment_limits(user_id, Some(key_id), limits)
+                        .await
+                        .unwrap()
+                        .allowed
+                })
+            })
+            .collect();
+
+        let mut allowed = 0;
+        for task in tasks {
+            if task.await.unwrap() {
+                allowed += 1;
+            }
+        }
+        assert_eq!(allowed, 20);
+
+        // Rejected requests don't consume quota
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let monthly_used: i64 = conn.get(RateLimiter::monthly_key(user_id)).await.unwrap();
+        assert_eq!(monthly_used, 20);
+    }
+}
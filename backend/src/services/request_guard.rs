@@ -0,0 +1,178 @@
+//! Request guards applied before forwarding to AI providers.
+//!
+//! Caps conversation length per request to keep cost and latency bounded.
+//! Limits default per plan tier and can be overridden via environment:
+//! - `MAX_MESSAGES_PER_REQUEST` / `MAX_MESSAGES_PER_REQUEST_<PLAN>`
+//! - `MAX_CONVERSATION_CHARS` / `MAX_CONVERSATION_CHARS_<PLAN>`
+//!
+//! Plan-specific variables take precedence over the global ones.
+
+use crate::models::PlanTier;
+
+/// Conversation length limits for a single request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConversationLimits {
+    pub max_messages: usize,
+    pub max_total_chars: Option<usize>,
+}
+
+/// Conversation limit violation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversationLimitError {
+    TooManyMessages { count: usize, limit: usize },
+    TooManyChars { count: usize, limit: usize },
+}
+
+impl std::fmt::Display for ConversationLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConversationLimitError::TooManyMessages { count, limit } => write!(
+                f,
+                "Too many messages: {} exceeds the limit of {} per request",
+                count, limit
+            ),
+            ConversationLimitError::TooManyChars { count, limit } => write!(
+                f,
+                "Conversation too long: {} characters exceeds the limit of {} per request",
+                count, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConversationLimitError {}
+
+impl ConversationLimits {
+    /// Default limits for a plan tier (no character cap)
+    pub fn for_plan(plan: PlanTier) -> Self {
+        Self {
+            max_messages: plan.max_messages_per_request(),
+            max_total_chars: None,
+        }
+    }
+
+    /// Load limits for a plan tier from environment variables
+    pub fn from_env(plan: PlanTier) -> Self {
+        Self::from_lookup(plan, |key| std::env::var(key).ok())
+    }
+
+    /// Load limits for a plan tier using a custom variable lookup
+    pub fn from_lookup<F>(plan: PlanTier, lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let suffix = plan.as_str().to_uppercase();
+        let read = |name: &str| -> Option<usize> {
+            lookup(&format!("{}_{}", name, suffix))
+                .or_else(|| lookup(name))
+                .and_then(|v| v.trim().parse().ok())
+        };
+
+        let defaults = Self::for_plan(plan);
+        Self {
+            max_messages: read("MAX_MESSAGES_PER_REQUEST").unwrap_or(defaults.max_messages),
+            max_total_chars: read("MAX_CONVERSATION_CHARS").or(defaults.max_total_chars),
+        }
+    }
+
+    /// Check a conversation's message count and total characters against the limits
+    pub fn check(
+        &self,
+        message_count: usize,
+        total_chars: usize,
+    ) -> Result<(), ConversationLimitError> {
+        if message_count > self.max_messages {
+            return Err(ConversationLimitError::TooManyMessages {
+                count: message_count,
+                limit: self.max_messages,
+            });
+        }
+
+        if let Some(limit) = self.max_total_chars {
+            if total_chars > limit {
+                return Err(ConversationLimitError::TooManyChars {
+                    count: total_chars,
+                    limit,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::env_lookup;
+
+    #[test]
+    fn test_message_cap_boundary() {
+        let limits = ConversationLimits {
+            max_messages: 3,
+            max_total_chars: None,
+        };
+
+        assert!(limits.check(3, 6).is_ok());
+        assert_eq!(
+            limits.check(4, 8),
+            Err(ConversationLimitError::TooManyMessages { count: 4, limit: 3 })
+        );
+    }
+
+    #[test]
+    fn test_char_cap_boundary() {
+        let limits = ConversationLimits {
+            max_messages: 10,
+            max_total_chars: Some(10),
+        };
+
+        assert!(limits.check(2, 10).is_ok());
+        assert_eq!(
+            limits.check(2, 12),
+            Err(ConversationLimitError::TooManyChars { count: 12, limit: 10 })
+        );
+    }
+
+    #[test]
+    fn test_plan_defaults() {
+        assert_eq!(ConversationLimits::for_plan(PlanTier::Free).max_messages, 50);
+        assert_eq!(ConversationLimits::for_plan(PlanTier::Team).max_messages, 500);
+        assert!(ConversationLimits::for_plan(PlanTier::Pro).max_total_chars.is_none());
+    }
+
+    #[test]
+    fn test_global_override() {
+        let limits = ConversationLimits::from_lookup(
+            PlanTier::Starter,
+            env_lookup(&[("MAX_MESSAGES_PER_REQUEST", "20"), ("MAX_CONVERSATION_CHARS", "5000")]),
+        );
+
+        assert_eq!(limits.max_messages, 20);
+        assert_eq!(limits.max_total_chars, Some(5000));
+    }
+
+    #[test]
+    fn test_plan_override_takes_precedence() {
+        let vars = [
+            ("MAX_MESSAGES_PER_REQUEST", "20"),
+            ("MAX_MESSAGES_PER_REQUEST_PRO", "400"),
+        ];
+
+        let pro = ConversationLimits::from_lookup(PlanTier::Pro, env_lookup(&vars));
+        let free = ConversationLimits::from_lookup(PlanTier::Free, env_lookup(&vars));
+
+        assert_eq!(pro.max_messages, 400);
+        assert_eq!(free.max_messages, 20);
+    }
+
+    #[test]
+    fn test_invalid_override_falls_back_to_plan_default() {
+        let limits = ConversationLimits::from_lookup(
+            PlanTier::Free,
+            env_lookup(&[("MAX_MESSAGES_PER_REQUEST", "lots")]),
+        );
+
+        assert_eq!(limits.max_messages, 50);
+    }
+}
@@ -0,0 +1,128 @@
+//! Request ids for tracing a completion across the proxy and providers.
+//!
+//! Each proxied request gets an id (the client's `X-Request-Id` when it is
+//! usable, otherwise a new UUID) that is returned in `X-Request-Id` and sent
+//! upstream in a provider-appropriate correlation header. The id the provider
+//! assigns is logged and returned in `X-Upstream-Request-Id`.
+//!
+//! Configuration:
+//! - `FORWARD_REQUEST_ID`: send our id to providers (default `true`)
+
+use axum::http::{HeaderMap, HeaderValue};
+
+use crate::services::transformers::Provider;
+
+/// Header carrying our request id, inbound and on responses
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Response header carrying the provider's request id
+pub const UPSTREAM_REQUEST_ID_HEADER: &str = "x-upstream-request-id";
+
+/// Longest client-supplied request id we accept
+pub const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Response headers providers use for their request id, in lookup order
+const UPSTREAM_ID_HEADERS: [&str; 3] = ["x-request-id", "request-id", "x-dashscope-request-id"];
+
+/// Request id forwarding configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestIdConfig {
+    pub forward: bool,
+}
+
+impl RequestIdConfig {
+    /// Load request id configuration from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load request id configuration using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let forward = lookup("FORWARD_REQUEST_ID")
+            .map(|v| !matches!(v.trim().to_ascii_lowercase().as_str(), "0" | "false" | "no" | "off"))
+            .unwrap_or(true);
+        Self { forward }
+    }
+
+    /// Correlation header to send to `provider`, if forwarding is enabled
+    pub fn outbound_header(&self, provider: Provider) -> Option<&'static str> {
+        self.forward.then_some(match provider {
+            // OpenAI's own x-request-id is response-only; this one is echoed back
+            Provider::OpenAI => "x-client-request-id",
+            Provider::Anthropic | Provider::Google | Provider::Qwen => REQUEST_ID_HEADER,
+        })
+    }
+}
+
+/// The client's request id when it is short printable ASCII, otherwise a new one
+pub fn request_id_from_headers(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .filter(|id| id.chars().all(|c| c.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// The request id a provider returned with its response
+pub fn upstream_request_id(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    UPSTREAM_ID_HEADERS
+        .iter()
+        .find_map(|name| headers.get(*name))
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+}
+
+/// Set a header on a response when the value is a valid header value
+pub fn insert_header(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_request_id_reused_when_valid() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, "trace-1234".parse().unwrap());
+        assert_eq!(request_id_from_headers(&headers), "trace-1234");
+
+        headers.insert(REQUEST_ID_HEADER, "has space".parse().unwrap());
+        let generated = request_id_from_headers(&headers);
+        assert!(uuid::Uuid::parse_str(&generated).is_ok());
+
+        headers.insert(REQUEST_ID_HEADER, "x".repeat(MAX_REQUEST_ID_LEN + 1).parse().unwrap());
+        assert_ne!(request_id_from_headers(&headers).len(), MAX_REQUEST_ID_LEN + 1);
+    }
+
+    #[test]
+    fn test_outbound_header_per_provider() {
+        let config = RequestIdConfig::from_lookup(|_| None);
+        assert_eq!(config.outbound_header(Provider::OpenAI), Some("x-client-request-id"));
+        assert_eq!(config.outbound_header(Provider::Anthropic), Some("x-request-id"));
+
+        let disabled = RequestIdConfig::from_lookup(|key| (key == "FORWARD_REQUEST_ID").then(|| "false".to_string()));
+        assert_eq!(disabled.outbound_header(Provider::Qwen), None);
+    }
+
+    #[test]
+    fn test_upstream_request_id_header_names() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(upstream_request_id(&headers), None);
+
+        headers.insert("request-id", "req_011CV".parse().unwrap());
+        assert_eq!(upstream_request_id(&headers).as_deref(), Some("req_011CV"));
+
+        headers.insert("x-request-id", "req_abc".parse().unwrap());
+        assert_eq!(upstream_request_id(&headers).as_deref(), Some("req_abc"));
+    }
+}
@@ -0,0 +1,84 @@
+//! Client-supplied request metadata.
+//!
+//! Requests may carry a `metadata` object of string key/value pairs (as in
+//! OpenAI's API) to tag usage for analytics, e.g. `{"feature": "summarize"}`.
+//! It is stored with the usage log and never sent upstream. Limits match
+//! OpenAI's: at most 16 pairs, keys up to 64 characters, values up to 512.
+
+use std::collections::HashMap;
+
+/// Request metadata: string keys to string values
+pub type RequestMetadata = HashMap<String, String>;
+
+/// Most key/value pairs a request may carry
+pub const MAX_METADATA_PAIRS: usize = 16;
+
+/// Longest metadata key, in characters
+pub const MAX_METADATA_KEY_CHARS: usize = 64;
+
+/// Longest metadata value, in characters
+pub const MAX_METADATA_VALUE_CHARS: usize = 512;
+
+/// Metadata outside the size limits
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum MetadataError {
+    #[error("metadata has {0} keys; at most {MAX_METADATA_PAIRS} are allowed")]
+    TooManyPairs(usize),
+
+    #[error("metadata keys must be 1 to {MAX_METADATA_KEY_CHARS} characters: {0:?}")]
+    InvalidKey(String),
+
+    #[error("metadata value for {0:?} exceeds {MAX_METADATA_VALUE_CHARS} characters")]
+    ValueTooLong(String),
+}
+
+/// Check metadata against the size limits
+pub fn validate(metadata: &RequestMetadata) -> Result<(), MetadataError> {
+    if metadata.len() > MAX_METADATA_PAIRS {
+        return Err(MetadataError::TooManyPairs(metadata.len()));
+    }
+    for (key, value) in metadata {
+        let key_chars = key.chars().count();
+        if key_chars == 0 || key_chars > MAX_METADATA_KEY_CHARS {
+            return Err(MetadataError::InvalidKey(key.clone()));
+        }
+        if value.chars().count() > MAX_METADATA_VALUE_CHARS {
+            return Err(MetadataError::ValueTooLong(key.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(pairs: &[(&str, &str)]) -> RequestMetadata {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_metadata_within_limits() {
+        assert!(validate(&metadata(&[("feature", "summarize"), ("team", "search")])).is_ok());
+        assert!(validate(&RequestMetadata::new()).is_ok());
+
+        let full: RequestMetadata = (0..MAX_METADATA_PAIRS).map(|i| (format!("k{}", i), "v".to_string())).collect();
+        assert!(validate(&full).is_ok());
+    }
+
+    #[test]
+    fn test_metadata_limits_enforced() {
+        let too_many: RequestMetadata = (0..=MAX_METADATA_PAIRS).map(|i| (format!("k{}", i), "v".to_string())).collect();
+        assert_eq!(validate(&too_many), Err(MetadataError::TooManyPairs(17)));
+
+        let long_key = "k".repeat(MAX_METADATA_KEY_CHARS + 1);
+        assert_eq!(validate(&metadata(&[(&long_key, "v")])), Err(MetadataError::InvalidKey(long_key)));
+        assert_eq!(validate(&metadata(&[("", "v")])), Err(MetadataError::InvalidKey(String::new())));
+
+        let long_value = "v".repeat(MAX_METADATA_VALUE_CHARS + 1);
+        assert_eq!(
+            validate(&metadata(&[("feature", &long_value)])),
+            Err(MetadataError::ValueTooLong("feature".to_string()))
+        );
+    }
+}
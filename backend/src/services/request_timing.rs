@@ -0,0 +1,234 @@
+//! Per-phase latency breakdown for proxied requests.
+//!
+//! Phases:
+//! - `auth`: API key validation, plan lookup, guards and provider key lookup
+//! - `upstream`: waiting for the provider to respond (until headers arrive)
+//! - `transform`: reading and transforming the provider response body
+//!
+//! Requests slower than `SLOW_REQUEST_MS` (off when unset or `0`) are logged
+//! as warnings with their model, provider and phase breakdown, never content.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::services::transformers::Provider;
+
+/// Instant the request entered the proxy (set by `api_key_auth`)
+#[derive(Debug, Clone, Copy)]
+pub struct RequestStart(pub Instant);
+
+/// Latency above which a request is logged as slow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowRequestConfig {
+    pub threshold: Duration,
+}
+
+impl SlowRequestConfig {
+    /// Load the slow request threshold from environment variables
+    pub fn from_env() -> Option<Self> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load the slow request threshold using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Option<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let ms = lookup("SLOW_REQUEST_MS")
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|&ms| ms > 0)?;
+        Some(Self {
+            threshold: Duration::from_millis(ms),
+        })
+    }
+}
+
+/// Request phase being timed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Auth,
+    Upstream,
+    Transform,
+}
+
+/// Accumulated timings for a single request
+#[derive(Debug, Clone)]
+pub struct RequestTimings {
+    start: Instant,
+    auth: Duration,
+    upstream: Duration,
+    transform: Duration,
+}
+
+impl RequestTimings {
+    pub fn new(start: Instant) -> Self {
+        Self {
+            start,
+            auth: Duration::ZERO,
+            upstream: Duration::ZERO,
+            transform: Duration::ZERO,
+        }
+    }
+
+    /// Instant the request started
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    /// Add elapsed time to a phase
+    pub fn record(&mut self, phase: Phase, elapsed: Duration) {
+        match phase {
+            Phase::Auth => self.auth += elapsed,
+            Phase::Upstream => self.upstream += elapsed,
+            Phase::Transform => self.transform += elapsed,
+        }
+    }
+
+    /// Attribute all time since the request started that isn't yet
+    /// accounted for to a phase
+    pub fn record_since_start(&mut self, phase: Phase) {
+        let unaccounted = self.total().saturating_sub(self.phases_sum());
+        self.record(phase, unaccounted);
+    }
+
+    /// Run a future and record its duration under a phase
+    pub async fn measure<F, T>(&mut self, phase: Phase, fut: F) -> T
+    where
+        F: Future<Output = T>,
+    {
+        let started = Instant::now();
+        let output = fut.await;
+        self.record(phase, started.elapsed());
+        output
+    }
+
+    /// Time recorded for a phase
+    pub fn get(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::Auth => self.auth,
+            Phase::Upstream => self.upstream,
+            Phase::Transform => self.transform,
+        }
+    }
+
+    /// Sum of all recorded phases
+    pub fn phases_sum(&self) -> Duration {
+        self.auth + self.upstream + self.transform
+    }
+
+    /// Wall-clock time since the request started
+    pub fn total(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Record the breakdown into the current tracing span and emit a debug event
+    pub fn record_to_span(&self, span: &tracing::Span) {
+        let auth_ms = self.get(Phase::Auth).as_millis() as u64;
+        let upstream_ms = self.get(Phase::Upstream).as_millis() as u64;
+        let transform_ms = self.get(Phase::Transform).as_millis() as u64;
+        let total_ms = self.total().as_millis() as u64;
+
+        span.record("auth_ms", auth_ms);
+        span.record("upstream_ms", upstream_ms);
+        span.record("transform_ms", transform_ms);
+        span.record("total_ms", total_ms);
+
+        tracing::debug!(
+            auth_ms,
+            upstream_ms,
+            transform_ms,
+            total_ms,
+            "Request timing breakdown"
+        );
+    }
+
+    /// Warn with the phase breakdown if the request took longer than the
+    /// threshold, returning whether it was logged
+    pub fn warn_if_slow(&self, config: SlowRequestConfig, provider: Provider, model: &str) -> bool {
+        let total = self.total();
+        if total <= config.threshold {
+            return false;
+        }
+
+        tracing::warn!(
+            model,
+            provider = provider.name(),
+            auth_ms = self.get(Phase::Auth).as_millis() as u64,
+            upstream_ms = self.get(Phase::Upstream).as_millis() as u64,
+            transform_ms = self.get(Phase::Transform).as_millis() as u64,
+            total_ms = total.as_millis() as u64,
+            threshold_ms = config.threshold.as_millis() as u64,
+            "Slow request"
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_accumulates_per_phase() {
+        let mut timings = RequestTimings::new(Instant::now());
+        timings.record(Phase::Upstream, Duration::from_millis(10));
+        timings.record(Phase::Upstream, Duration::from_millis(5));
+        timings.record(Phase::Transform, Duration::from_millis(2));
+
+        assert_eq!(timings.get(Phase::Upstream), Duration::from_millis(15));
+        assert_eq!(timings.get(Phase::Transform), Duration::from_millis(2));
+        assert_eq!(timings.get(Phase::Auth), Duration::ZERO);
+        assert_eq!(timings.phases_sum(), Duration::from_millis(17));
+    }
+
+    #[test]
+    fn test_slow_request_config() {
+        let config = |value: &str| {
+            let value = value.to_string();
+            SlowRequestConfig::from_lookup(move |key| (key == "SLOW_REQUEST_MS").then(|| value.clone()))
+        };
+        assert_eq!(config("2500"), Some(SlowRequestConfig { threshold: Duration::from_millis(2500) }));
+        assert_eq!(config("0"), None);
+        assert_eq!(config("soon"), None);
+        assert_eq!(SlowRequestConfig::from_lookup(|_| None), None);
+    }
+
+    #[test]
+    fn test_simulated_slow_request_is_logged() {
+        let config = SlowRequestConfig { threshold: Duration::from_millis(1000) };
+
+        // Inject a start time in the past instead of waiting
+        let started = Instant::now() - Duration::from_millis(1500);
+        let mut timings = RequestTimings::new(started);
+        timings.record(Phase::Auth, Duration::from_millis(100));
+        timings.record(Phase::Upstream, Duration::from_millis(1350));
+        assert!(timings.warn_if_slow(config, Provider::OpenAI, "gpt-4o"));
+
+        let fast = RequestTimings::new(Instant::now());
+        assert!(!fast.warn_if_slow(config, Provider::OpenAI, "gpt-4o"));
+    }
+
+    #[tokio::test]
+    async fn test_phases_sum_roughly_equals_total() {
+        let mut timings = RequestTimings::new(Instant::now());
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        timings.record_since_start(Phase::Auth);
+
+        timings
+            .measure(Phase::Upstream, tokio::time::sleep(Duration::from_millis(30)))
+            .await;
+        timings
+            .measure(Phase::Transform, tokio::time::sleep(Duration::from_millis(10)))
+            .await;
+
+        assert!(timings.get(Phase::Auth) >= Duration::from_millis(20));
+        assert!(timings.get(Phase::Upstream) >= Duration::from_millis(30));
+        assert!(timings.get(Phase::Transform) >= Duration::from_millis(10));
+
+        let total = timings.total();
+        let sum = timings.phases_sum();
+        assert!(sum <= total);
+        assert!(total - sum < Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,168 @@
+//! Fallback for safety-blocked completions.
+//!
+//! When a non-streaming completion comes back empty with
+//! `finish_reason: "content_filter"` (e.g. a Gemini safety block), the request
+//! is retried once on a configured fallback model. The fallback's answer is
+//! returned as-is, even if it is blocked too, so a genuine block never loops.
+//!
+//! Configuration:
+//! - `SAFETY_FALLBACK_MODEL`: model to retry blocked requests on (off when unset)
+
+use std::future::Future;
+
+use crate::services::transformers::ChatCompletionResponse;
+
+/// Fallback model for safety-blocked responses
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafetyFallbackConfig {
+    pub model: String,
+}
+
+impl SafetyFallbackConfig {
+    /// Load fallback configuration from environment variables
+    pub fn from_env() -> Option<Self> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load fallback configuration using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Option<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let model = lookup("SAFETY_FALLBACK_MODEL").filter(|m| !m.trim().is_empty())?;
+        Some(Self {
+            model: model.trim().to_string(),
+        })
+    }
+
+    /// Whether a request is eligible for fallback.
+    /// Streaming responses can't be inspected before they reach the client.
+    pub fn applies_to(&self, model: &str, stream: bool) -> bool {
+        !stream && model != self.model
+    }
+}
+
+/// Whether an OpenAI-format completion body was blocked: every choice is
+/// empty and stopped by the content filter
+pub fn is_safety_blocked(body: &[u8]) -> bool {
+    let Ok(response) = serde_json::from_slice::<ChatCompletionResponse>(body) else {
+        return false;
+    };
+
+    !response.choices.is_empty()
+        && response.choices.iter().all(|choice| {
+            choice.finish_reason.as_deref() == Some("content_filter")
+                && choice.message.content.text().trim().is_empty()
+        })
+}
+
+/// Which attempt produced the final response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServedBy {
+    Primary,
+    Fallback,
+}
+
+/// Retry a blocked primary response once via `fallback`. The fallback result
+/// is final; if the fallback fails (`None`), the primary response is kept.
+pub async fn retry_once_if_blocked<T, F, Fut>(primary: T, body: impl AsRef<[u8]>, fallback: F) -> (T, ServedBy)
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    if !is_safety_blocked(body.as_ref()) {
+        return (primary, ServedBy::Primary);
+    }
+
+    match fallback().await {
+        Some(response) => (response, ServedBy::Fallback),
+        None => (primary, ServedBy::Primary),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn completion(content: &str, finish_reason: &str) -> Vec<u8> {
+        serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gemini-pro",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": content },
+                "finish_reason": finish_reason
+            }],
+            "usage": { "prompt_tokens": 8, "completion_tokens": 0, "total_tokens": 8 }
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_is_safety_blocked() {
+        assert!(is_safety_blocked(&completion("", "content_filter")));
+        assert!(!is_safety_blocked(&completion("Halo!", "stop")));
+        // Partial content is still an answer
+        assert!(!is_safety_blocked(&completion("Sebagian", "content_filter")));
+        assert!(!is_safety_blocked(b"not json"));
+    }
+
+    #[tokio::test]
+    async fn test_safety_block_triggers_one_fallback_attempt() {
+        let attempts = AtomicUsize::new(0);
+        let blocked = completion("", "content_filter");
+
+        let (response, served_by) = retry_once_if_blocked(blocked.clone(), &blocked, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            // The fallback is blocked too; it is still final
+            Some(completion("", "content_filter"))
+        })
+        .await;
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(served_by, ServedBy::Fallback);
+        assert!(is_safety_blocked(&response));
+    }
+
+    #[tokio::test]
+    async fn test_unblocked_response_skips_fallback() {
+        let attempts = AtomicUsize::new(0);
+        let answer = completion("Halo!", "stop");
+
+        let (_, served_by) = retry_once_if_blocked(answer.clone(), &answer, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Some(completion("other", "stop"))
+        })
+        .await;
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 0);
+        assert_eq!(served_by, ServedBy::Primary);
+    }
+
+    #[tokio::test]
+    async fn test_failed_fallback_keeps_primary() {
+        let blocked = completion("", "content_filter");
+        let (response, served_by) =
+            retry_once_if_blocked(blocked.clone(), &blocked, || async { None::<Vec<u8>> }).await;
+
+        assert_eq!(served_by, ServedBy::Primary);
+        assert_eq!(response, blocked);
+    }
+
+    #[test]
+    fn test_from_lookup() {
+        assert_eq!(SafetyFallbackConfig::from_lookup(|_| None), None);
+        let config = SafetyFallbackConfig::from_lookup(|key| {
+            (key == "SAFETY_FALLBACK_MODEL").then(|| " gpt-4o-mini ".to_string())
+        })
+        .unwrap();
+        assert_eq!(config.model, "gpt-4o-mini");
+        assert!(config.applies_to("gemini-pro", false));
+        assert!(!config.applies_to("gemini-pro", true));
+        assert!(!config.applies_to("gpt-4o-mini", false));
+    }
+}
@@ -1,13 +1,18 @@
 //! Scheduler Service for background jobs
 //!
 //! Handles scheduled tasks like inactive user reminders, subscription expiry checks, etc.
+//! Each job processes its batch with up to `SCHEDULER_CONCURRENCY` items in
+//! flight (default 4).
 //! Requirements: 5.5 - Send reminder email to users without API key after 24h
 
 use chrono::{Duration, Utc};
+use futures::{stream, StreamExt};
 use sqlx::PgPool;
+use std::future::Future;
 use std::sync::Arc;
 use tokio::time::{interval, Duration as TokioDuration};
 
+use super::billing_service::grace_period_from_env;
 use super::email_service::EmailService;
 use super::onboarding_service::OnboardingService;
 
@@ -20,12 +25,47 @@ pub enum SchedulerError {
     Email(String),
 }
 
+/// Default number of items a job processes at once
+pub const DEFAULT_SCHEDULER_CONCURRENCY: usize = 4;
+
+/// Per-job concurrency from `SCHEDULER_CONCURRENCY`
+pub fn concurrency_from_env() -> usize {
+    concurrency_from_lookup(|key| std::env::var(key).ok())
+}
+
+/// Per-job concurrency using a custom variable lookup
+pub fn concurrency_from_lookup<F>(lookup: F) -> usize
+where
+    F: Fn(&str) -> Option<String>,
+{
+    lookup("SCHEDULER_CONCURRENCY")
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_SCHEDULER_CONCURRENCY)
+}
+
+/// Run `task` on every item with at most `concurrency` in flight; returns
+/// how many items the task reported as handled
+pub async fn process_concurrently<T, F, Fut>(items: Vec<T>, concurrency: usize, task: F) -> u32
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    stream::iter(items)
+        .map(task)
+        .buffer_unordered(concurrency.max(1))
+        .fold(0, |handled, ok| async move { handled + u32::from(ok) })
+        .await
+}
+
 /// Scheduler Service for running background jobs
 /// Requirements: 5.5
 pub struct SchedulerService {
     pool: PgPool,
     email_service: Arc<EmailService>,
     onboarding_service: OnboardingService,
+    /// Items each job processes at once (`SCHEDULER_CONCURRENCY`)
+    concurrency: usize,
 }
 
 impl SchedulerService {
@@ -35,6 +75,7 @@ impl SchedulerService {
             pool,
             email_service,
             onboarding_service,
+            concurrency: concurrency_from_env(),
         }
     }
 
@@ -80,15 +121,13 @@ impl SchedulerService {
             .await
             .map_err(|e| SchedulerError::Database(sqlx::Error::Protocol(e.to_string())))?;
 
-        let mut sent_count = 0;
-
-        for user in inactive_users {
+        let sent_count = process_concurrently(inactive_users, self.concurrency, |user| async move {
             // Send reminder email
             let result = self.email_service
                 .send_onboarding_reminder(
                     &user.email,
                     user.name.clone(),
-                    "id", // Default to Indonesian
+                    &user.locale,
                 )
                 .await;
 
@@ -102,13 +141,13 @@ impl SchedulerService {
                             "Failed to mark reminder as sent"
                         );
                     }
-                    sent_count += 1;
                     tracing::info!(
                         user_id = %user.user_id,
                         email = %user.email,
                         hours_since_signup = user.hours_since_signup,
                         "Sent onboarding reminder email"
                     );
+                    true
                 }
                 Err(e) => {
                     tracing::error!(
@@ -116,9 +155,11 @@ impl SchedulerService {
                         error = %e,
                         "Failed to send onboarding reminder"
                     );
+                    false
                 }
             }
-        }
+        })
+        .await;
 
         tracing::info!(sent_count = sent_count, "Inactive user reminder job completed");
         Ok(sent_count)
@@ -135,6 +176,9 @@ impl SchedulerService {
             if let Err(e) = self.check_expiring_subscriptions().await {
                 tracing::error!(error = %e, "Failed to check expiring subscriptions");
             }
+            if let Err(e) = self.send_grace_period_reminders().await {
+                tracing::error!(error = %e, "Failed to send grace period reminders");
+            }
         }
     }
 
@@ -147,7 +191,7 @@ impl SchedulerService {
             r#"
             SELECT 
                 s.id, s.user_id, s.plan_tier::text as plan_tier, s.current_period_end,
-                u.email, u.name
+                u.email, u.name, u.locale
             FROM subscriptions s
             JOIN users u ON u.id = s.user_id
             WHERE s.status = 'active'
@@ -167,14 +211,13 @@ impl SchedulerService {
         .fetch_all(&self.pool)
         .await?;
 
-        let mut sent_count = 0;
-
-        for row in rows {
+        let sent_count = process_concurrently(rows, self.concurrency, |row| async move {
             use sqlx::Row;
             let email: String = row.get("email");
             let name: Option<String> = row.get("name");
             let plan_tier: String = row.get("plan_tier");
             let period_end: chrono::DateTime<Utc> = row.get("current_period_end");
+            let locale: String = row.get("locale");
             
             let days_remaining = (period_end - Utc::now()).num_days() as i32;
 
@@ -184,19 +227,19 @@ impl SchedulerService {
                     name,
                     &plan_tier,
                     days_remaining,
-                    "id",
+                    &locale,
                 )
                 .await;
 
             match result {
                 Ok(_) => {
-                    sent_count += 1;
                     tracing::info!(
                         email = %email,
                         plan = %plan_tier,
                         days_remaining = days_remaining,
                         "Sent subscription expiring reminder"
                     );
+                    true
                 }
                 Err(e) => {
                     tracing::error!(
@@ -204,14 +247,80 @@ impl SchedulerService {
                         error = %e,
                         "Failed to send subscription expiring reminder"
                     );
+                    false
                 }
             }
-        }
+        })
+        .await;
 
         tracing::info!(sent_count = sent_count, "Subscription expiry check completed");
         Ok(sent_count)
     }
 
+    /// Remind users whose subscription is past due that they will be
+    /// downgraded when the grace period ends (at most once a day)
+    pub async fn send_grace_period_reminders(&self) -> Result<u32, SchedulerError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT s.plan_tier::text as plan_tier, s.current_period_end, u.email, u.name, u.locale
+            FROM subscriptions s
+            JOIN users u ON u.id = s.user_id
+            WHERE s.status = 'past_due'
+              AND NOT EXISTS (
+                SELECT 1 FROM email_logs el
+                WHERE el.recipient = u.email
+                  AND el.template = 'payment_overdue'
+                  AND el.sent_at > NOW() - INTERVAL '1 day'
+              )
+            ORDER BY s.current_period_end ASC
+            LIMIT 100
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let grace_period = grace_period_from_env();
+        let sent_count = process_concurrently(rows, self.concurrency, |row| async move {
+            use sqlx::Row;
+            let email: String = row.get("email");
+            let name: Option<String> = row.get("name");
+            let plan_tier: String = row.get("plan_tier");
+            let period_end: chrono::DateTime<Utc> = row.get("current_period_end");
+            let locale: String = row.get("locale");
+
+            // Round up so the last partial day reads as 1
+            let hours_left = (period_end + grace_period - Utc::now()).num_hours().max(0);
+            let days_until_downgrade = ((hours_left + 23) / 24) as i32;
+
+            match self.email_service
+                .send_payment_overdue(&email, name, &plan_tier, days_until_downgrade, &locale)
+                .await
+            {
+                Ok(_) => {
+                    tracing::info!(
+                        email = %email,
+                        plan = %plan_tier,
+                        days_until_downgrade = days_until_downgrade,
+                        "Sent grace period reminder"
+                    );
+                    true
+                }
+                Err(e) => {
+                    tracing::error!(
+                        email = %email,
+                        error = %e,
+                        "Failed to send grace period reminder"
+                    );
+                    false
+                }
+            }
+        })
+        .await;
+
+        tracing::info!(sent_count = sent_count, "Grace period reminder job completed");
+        Ok(sent_count)
+    }
+
     /// Manual trigger for inactive user check (for testing/admin)
     pub async fn trigger_inactive_user_check(&self) -> Result<u32, SchedulerError> {
         self.send_inactive_user_reminders().await
@@ -235,4 +344,43 @@ mod tests {
         let email_error = SchedulerError::Email("test error".to_string());
         assert!(email_error.to_string().contains("Email error"));
     }
+
+    #[test]
+    fn test_concurrency_from_lookup() {
+        assert_eq!(concurrency_from_lookup(|_| None), DEFAULT_SCHEDULER_CONCURRENCY);
+        assert_eq!(concurrency_from_lookup(|_| Some("16".to_string())), 16);
+        assert_eq!(concurrency_from_lookup(|_| Some("0".to_string())), DEFAULT_SCHEDULER_CONCURRENCY);
+    }
+
+    #[tokio::test]
+    async fn test_batch_processed_with_bounded_concurrency_exactly_once() {
+        use std::collections::HashMap;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Mutex;
+
+        let in_flight = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let handled = Mutex::new(HashMap::<u32, u32>::new());
+
+        let items: Vec<u32> = (0..50).collect();
+        let count = process_concurrently(items, 4, |item| {
+            let (in_flight, peak, handled) = (&in_flight, &peak, &handled);
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(TokioDuration::from_millis(2)).await;
+                *handled.lock().unwrap().entry(item).or_default() += 1;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                // Odd items fail and aren't counted
+                item % 2 == 0
+            }
+        })
+        .await;
+
+        assert_eq!(count, 25);
+        assert_eq!(peak.load(Ordering::SeqCst), 4);
+        let handled = handled.into_inner().unwrap();
+        assert_eq!(handled.len(), 50);
+        assert!(handled.values().all(|times| *times == 1));
+    }
 }
@@ -0,0 +1,184 @@
+//! Session revocation cutoffs, cached in Redis.
+//!
+//! `jwt_auth` checks every access token against the user's
+//! `tokens_valid_after`. The cutoff is cached per user so authenticated
+//! requests don't each hit the database; revoking sessions deletes the
+//! cached cutoff. Users who never revoked are cached too. If Redis is
+//! unavailable the cutoff is read from the database, and if the cached
+//! cutoff couldn't be deleted it is ignored until it would have expired.
+
+use chrono::{DateTime, TimeZone, Utc};
+use redis::AsyncCommands;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::services::auth_service;
+
+/// How long a cached cutoff is trusted before re-reading the database
+pub const CUTOFF_CACHE_TTL_SECS: u64 = 300;
+
+/// Cached value for users whose sessions were never revoked
+const NO_CUTOFF: &str = "none";
+
+fn cache_key(user_id: Uuid) -> String {
+    format!("session_cutoff:{}", user_id)
+}
+
+fn encode_cutoff(cutoff: Option<DateTime<Utc>>) -> String {
+    cutoff
+        .map(|c| c.timestamp().to_string())
+        .unwrap_or_else(|| NO_CUTOFF.to_string())
+}
+
+/// `None` when the cached value is unreadable (treated as a cache miss)
+fn decode_cutoff(value: &str) -> Option<Option<DateTime<Utc>>> {
+    if value == NO_CUTOFF {
+        return Some(None);
+    }
+    let secs = value.parse::<i64>().ok()?;
+    Utc.timestamp_opt(secs, 0).single().map(Some)
+}
+
+/// Read a cached cutoff; `None` on a miss or when Redis is unavailable
+async fn cached_cutoff(redis: &redis::Client, user_id: Uuid) -> Option<Option<DateTime<Utc>>> {
+    let mut conn = redis.get_multiplexed_async_connection().await.ok()?;
+    let value: Option<String> = conn.get(cache_key(user_id)).await.ok()?;
+    decode_cutoff(&value?)
+}
+
+async fn store_cutoff(redis: &redis::Client, user_id: Uuid, cutoff: Option<DateTime<Utc>>) {
+    let result: Result<(), redis::RedisError> = async {
+        let mut conn = redis.get_multiplexed_async_connection().await?;
+        conn.set_ex(cache_key(user_id), encode_cutoff(cutoff), CUTOFF_CACHE_TTL_SECS)
+            .await
+    }
+    .await;
+
+    if let Err(e) = result {
+        tracing::debug!("Failed to cache session cutoff for user {}: {}", user_id, e);
+    }
+}
+
+/// Users whose cached cutoff couldn't be deleted after a revocation, with
+/// when that happened
+fn stale_cache_users() -> &'static Mutex<HashMap<Uuid, Instant>> {
+    static USERS: OnceLock<Mutex<HashMap<Uuid, Instant>>> = OnceLock::new();
+    USERS.get_or_init(Default::default)
+}
+
+/// Whether the user's cached cutoff can be trusted: not when it may predate
+/// a revocation, until it would have expired
+fn cache_trusted(user_id: Uuid) -> bool {
+    let mut users = stale_cache_users().lock().unwrap_or_else(PoisonError::into_inner);
+    users.retain(|_, since| since.elapsed() < Duration::from_secs(CUTOFF_CACHE_TTL_SECS));
+    !users.contains_key(&user_id)
+}
+
+/// Delete a cached cutoff; if that fails, stop reading it
+async fn invalidate_cutoff(redis: &redis::Client, user_id: Uuid) {
+    let result: Result<(), redis::RedisError> = async {
+        let mut conn = redis.get_multiplexed_async_connection().await?;
+        conn.del(cache_key(user_id)).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to clear cached session cutoff for user {}: {}", user_id, e);
+        stale_cache_users()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(user_id, Instant::now());
+    }
+}
+
+/// A user's session cutoff, from the cache when possible
+pub async fn tokens_valid_after(
+    db: &PgPool,
+    redis: &redis::Client,
+    user_id: Uuid,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    if !cache_trusted(user_id) {
+        return auth_service::get_tokens_valid_after(db, user_id).await;
+    }
+    if let Some(cutoff) = cached_cutoff(redis, user_id).await {
+        return Ok(cutoff);
+    }
+
+    let cutoff = auth_service::get_tokens_valid_after(db, user_id).await?;
+    store_cutoff(redis, user_id, cutoff).await;
+    Ok(cutoff)
+}
+
+/// Revoke every session for a user and clear the cached cutoff.
+/// Returns the new cutoff, or `None` when the user doesn't exist.
+pub async fn revoke_all_sessions(
+    db: &PgPool,
+    redis: &redis::Client,
+    user_id: Uuid,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let cutoff = auth_service::revoke_all_sessions(db, user_id).await?;
+    if cutoff.is_some() {
+        invalidate_cutoff(redis, user_id).await;
+    }
+    Ok(cutoff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PlanTier;
+    use crate::test_support::{app_state, insert_user};
+
+    #[test]
+    fn test_cutoff_cache_encoding_round_trips() {
+        let cutoff = Utc.timestamp_opt(1_733_900_000, 0).unwrap();
+        assert_eq!(decode_cutoff(&encode_cutoff(Some(cutoff))), Some(Some(cutoff)));
+        assert_eq!(decode_cutoff(&encode_cutoff(None)), Some(None));
+        assert_eq!(decode_cutoff("garbage"), None);
+    }
+
+    // Database-backed tests (see test_support for how to run them)
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_revoke_sets_cutoff_without_redis(pool: PgPool) {
+        let state = app_state(pool.clone());
+        let user_id = insert_user(&pool, "agus@example.com", PlanTier::Pro).await;
+        let issued = Utc::now().timestamp() - 30;
+
+        let before = tokens_valid_after(&state.db, &state.redis, user_id).await.unwrap();
+        assert!(!auth_service::issued_before_cutoff(issued, before));
+
+        let cutoff = revoke_all_sessions(&state.db, &state.redis, user_id).await.unwrap();
+        assert!(cutoff.is_some());
+        // The cached cutoff couldn't be cleared, so it isn't read
+        assert!(!cache_trusted(user_id));
+
+        let after = tokens_valid_after(&state.db, &state.redis, user_id).await.unwrap();
+        assert_eq!(after, cutoff);
+        assert!(auth_service::issued_before_cutoff(issued, after));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL and REDIS_URL"]
+    async fn test_stale_cache_ignored_when_revoke_cannot_clear_it(pool: PgPool) {
+        let state = app_state(pool.clone());
+        let redis = redis::Client::open(std::env::var("REDIS_URL").expect("REDIS_URL")).unwrap();
+        let user_id = insert_user(&pool, "dewi@example.com", PlanTier::Pro).await;
+
+        // Cache "never revoked" through the working Redis
+        assert_eq!(tokens_valid_after(&pool, &redis, user_id).await.unwrap(), None);
+        assert_eq!(cached_cutoff(&redis, user_id).await, Some(None));
+
+        // Revoking can't reach Redis to clear it
+        let cutoff = revoke_all_sessions(&pool, &state.redis, user_id).await.unwrap();
+        assert!(cutoff.is_some());
+        assert_eq!(cached_cutoff(&redis, user_id).await, Some(None));
+
+        // The stale entry is not trusted
+        assert_eq!(tokens_valid_after(&pool, &redis, user_id).await.unwrap(), cutoff);
+    }
+}
@@ -0,0 +1,217 @@
+//! Shadow/mirror mode for evaluating a secondary model on live traffic.
+//!
+//! For a sampled share of non-streaming chat completions, the same request is
+//! replayed against a mirror model after the primary response has been
+//! produced. Both results (tokens, latency, content hash) are logged for
+//! offline comparison; the client response is never delayed or altered.
+//!
+//! Configuration:
+//! - `MIRROR_MODEL`: model to mirror to (mirroring is off when unset)
+//! - `MIRROR_SAMPLE_RATE`: share of requests mirrored, `0.0`-`1.0` (default `0.0`)
+
+use sha2::{Digest, Sha256};
+use std::future::Future;
+
+use crate::services::task_manager::TaskManager;
+use crate::services::transformers::ChatCompletionResponse;
+
+/// Mirror model and sampling rate
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirrorConfig {
+    pub model: String,
+    pub sample_rate: f64,
+}
+
+/// Outcome of one completion, used to compare primary and mirror
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorResult {
+    pub model: String,
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub latency_ms: u64,
+    pub content_hash: String,
+}
+
+impl MirrorConfig {
+    /// Load mirror configuration from environment variables
+    pub fn from_env() -> Option<Self> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load mirror configuration using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Option<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let model = lookup("MIRROR_MODEL").filter(|m| !m.trim().is_empty())?;
+        let sample_rate = lookup("MIRROR_SAMPLE_RATE")
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .unwrap_or(0.0)
+            .clamp(0.0, 1.0);
+
+        Some(Self {
+            model: model.trim().to_string(),
+            sample_rate,
+        })
+    }
+
+    /// Decide whether a request is sampled, given a uniform roll in `[0, 1)`.
+    /// Streaming requests and requests already targeting the mirror model are skipped.
+    pub fn should_mirror(&self, model: &str, stream: bool, roll: f64) -> bool {
+        !stream && model != self.model && roll < self.sample_rate
+    }
+}
+
+impl MirrorResult {
+    /// Summarize an OpenAI-format completion body
+    pub fn from_response_body(model: &str, body: &[u8], latency_ms: u64) -> Option<Self> {
+        let response: ChatCompletionResponse = serde_json::from_slice(body).ok()?;
+
+        let mut hasher = Sha256::new();
+        for choice in &response.choices {
+            hasher.update(choice.message.content.text().as_bytes());
+        }
+
+        Some(Self {
+            model: model.to_string(),
+            prompt_tokens: response.usage.prompt_tokens,
+            completion_tokens: response.usage.completion_tokens,
+            latency_ms,
+            content_hash: format!("{:x}", hasher.finalize()),
+        })
+    }
+}
+
+/// Run the mirror request in the background and log the comparison
+pub fn spawn_mirror<Fut>(tasks: &TaskManager, primary: MirrorResult, mirror: Fut)
+where
+    Fut: Future<Output = Option<MirrorResult>> + Send + 'static,
+{
+    tasks.spawn(async move {
+        match mirror.await {
+            Some(result) => log_comparison(&primary, &result),
+            None => tracing::warn!(
+                primary_model = %primary.model,
+                "Mirror request failed, no comparison logged"
+            ),
+        }
+    });
+}
+
+/// Log primary vs mirror results for offline comparison
+fn log_comparison(primary: &MirrorResult, mirror: &MirrorResult) {
+    tracing::info!(
+        target: "shadow_mirror",
+        primary_model = %primary.model,
+        primary_prompt_tokens = primary.prompt_tokens,
+        primary_completion_tokens = primary.completion_tokens,
+        primary_latency_ms = primary.latency_ms,
+        primary_content_hash = %primary.content_hash,
+        mirror_model = %mirror.model,
+        mirror_prompt_tokens = mirror.prompt_tokens,
+        mirror_completion_tokens = mirror.completion_tokens,
+        mirror_latency_ms = mirror.latency_ms,
+        mirror_content_hash = %mirror.content_hash,
+        same_content = primary.content_hash == mirror.content_hash,
+        "Shadow mirror comparison"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config(rate: f64) -> MirrorConfig {
+        MirrorConfig {
+            model: "claude-3-haiku-20240307".to_string(),
+            sample_rate: rate,
+        }
+    }
+
+    fn result(model: &str) -> MirrorResult {
+        MirrorResult {
+            model: model.to_string(),
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            latency_ms: 100,
+            content_hash: "abc".to_string(),
+        }
+    }
+
+    /// Mirror only when sampled, mirroring through a channel so the test can observe it
+    fn mirror_if_sampled(tasks: &TaskManager, config: &MirrorConfig, roll: f64) -> tokio::sync::oneshot::Receiver<String> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        if config.should_mirror("gpt-4o", false, roll) {
+            let model = config.model.clone();
+            spawn_mirror(tasks, result("gpt-4o"), async move {
+                let _ = tx.send(model.clone());
+                Some(result(&model))
+            });
+        }
+        rx
+    }
+
+    #[tokio::test]
+    async fn test_mirror_fires_on_sampled_requests() {
+        let tasks = TaskManager::default();
+        let rx = mirror_if_sampled(&tasks, &config(0.25), 0.1);
+        let fired = tokio::time::timeout(Duration::from_secs(1), rx).await;
+        assert_eq!(fired.unwrap().unwrap(), "claude-3-haiku-20240307");
+    }
+
+    #[tokio::test]
+    async fn test_mirror_skips_unsampled_requests() {
+        let tasks = TaskManager::default();
+        let rx = mirror_if_sampled(&tasks, &config(0.25), 0.9);
+        // Sender dropped without firing
+        assert!(rx.await.is_err());
+    }
+
+    #[test]
+    fn test_should_mirror_rules() {
+        assert!(!config(1.0).should_mirror("gpt-4o", true, 0.0));
+        assert!(!config(1.0).should_mirror("claude-3-haiku-20240307", false, 0.0));
+        assert!(!config(0.0).should_mirror("gpt-4o", false, 0.0));
+        assert!(config(1.0).should_mirror("gpt-4o", false, 0.99));
+    }
+
+    #[test]
+    fn test_config_from_lookup() {
+        assert!(MirrorConfig::from_lookup(|_| None).is_none());
+
+        let config = MirrorConfig::from_lookup(|key| match key {
+            "MIRROR_MODEL" => Some("gemini-1.5-flash".to_string()),
+            "MIRROR_SAMPLE_RATE" => Some("2.5".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(config.model, "gemini-1.5-flash");
+        assert_eq!(config.sample_rate, 1.0);
+    }
+
+    #[test]
+    fn test_result_from_response_body() {
+        let body = serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Hello" },
+                "finish_reason": "stop"
+            }],
+            "usage": { "prompt_tokens": 7, "completion_tokens": 2, "total_tokens": 9 }
+        });
+
+        let result =
+            MirrorResult::from_response_body("gpt-4o", body.to_string().as_bytes(), 42).unwrap();
+        assert_eq!(result.prompt_tokens, 7);
+        assert_eq!(result.completion_tokens, 2);
+        assert_eq!(result.latency_ms, 42);
+        assert_eq!(result.content_hash.len(), 64);
+
+        assert!(MirrorResult::from_response_body("gpt-4o", b"not json", 0).is_none());
+    }
+}
@@ -0,0 +1,307 @@
+//! Pre-authorization of a request's cost against the user's monthly spend limit.
+//!
+//! Users may have a `monthly_spend_limit_idr` (no limit when unset). Before a
+//! request is forwarded, its worst-case cost is estimated from the prompt and
+//! the output token budget (`max_tokens`, else the model's output cap), and
+//! the request is rejected if that can't fit in what is left of the month.
+//! Nothing is reserved: the spent amount is always summed from logged actual
+//! costs, so the budget reconciles itself once the request's usage is logged.
+//!
+//! Streams are also cut off mid-response once the streamed output's cost
+//! would cross the spend left after the prompt (e.g. when concurrent requests
+//! share the budget): the client gets a `QUOTA_EXCEEDED` error event and the
+//! stream ends.
+
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+use crate::services::stream_handler::StreamHandler;
+use crate::services::transformers::{Message, ModelMetadata, Provider};
+use crate::services::usage_logger::{TokenCounter, UsageLogger};
+
+/// Error code of the SSE event sent when a stream hits the spend limit
+pub const QUOTA_EXCEEDED_CODE: &str = "QUOTA_EXCEEDED";
+
+/// Output budget assumed when neither the request nor the model sets one
+pub const DEFAULT_COMPLETION_ESTIMATE: u32 = 4096;
+
+/// Worst-case token usage and cost of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpendEstimate {
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    /// Cost of the prompt alone
+    pub prompt_cost_idr: i64,
+    pub cost_idr: i64,
+}
+
+/// Estimate a request's cost assuming it uses its whole output budget
+pub fn estimate(provider: Provider, model: &str, messages: &[Message], max_tokens: Option<u32>) -> SpendEstimate {
+    let prompt_tokens = TokenCounter::count_message_tokens(messages);
+    let completion_tokens = max_tokens
+        .or(ModelMetadata::for_model(model).max_output_tokens)
+        .unwrap_or(DEFAULT_COMPLETION_ESTIMATE)
+        .min(i32::MAX as u32) as i32;
+
+    SpendEstimate {
+        prompt_tokens,
+        completion_tokens,
+        prompt_cost_idr: UsageLogger::calculate_cost(provider, model, prompt_tokens, 0),
+        cost_idr: UsageLogger::calculate_cost(provider, model, prompt_tokens, completion_tokens),
+    }
+}
+
+/// Request can't fit in the remaining monthly spend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsufficientQuota {
+    pub estimate_idr: i64,
+    pub remaining_idr: i64,
+}
+
+impl std::fmt::Display for InsufficientQuota {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Estimated cost of Rp {} exceeds the remaining monthly spend of Rp {}; lower max_tokens or raise your spend limit",
+            self.estimate_idr, self.remaining_idr
+        )
+    }
+}
+
+impl std::error::Error for InsufficientQuota {}
+
+/// A user's monthly spend limit and what has been spent so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpendBudget {
+    pub limit_idr: Option<i64>,
+    pub spent_idr: i64,
+}
+
+impl SpendBudget {
+    /// Spend left this month (`None` when unlimited)
+    pub fn remaining_idr(&self) -> Option<i64> {
+        self.limit_idr.map(|limit| (limit - self.spent_idr).max(0))
+    }
+
+    /// Check that a request's estimated cost fits in the remaining spend
+    pub fn check(&self, estimate: &SpendEstimate) -> Result<(), InsufficientQuota> {
+        match self.remaining_idr() {
+            Some(remaining) if estimate.cost_idr > remaining => Err(InsufficientQuota {
+                estimate_idr: estimate.cost_idr,
+                remaining_idr: remaining,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Spend left for the request's output once its prompt is paid for
+    pub fn output_limit_idr(&self, estimate: &SpendEstimate) -> Option<i64> {
+        self.remaining_idr()
+            .map(|remaining| (remaining - estimate.prompt_cost_idr).max(0))
+    }
+}
+
+/// Pass chunk payloads through until the streamed output would cost more
+/// than `output_limit_idr`, then send a `QUOTA_EXCEEDED` error payload in
+/// place of the chunk that crossed the limit and end the stream.
+/// Without a limit the payloads pass through unchanged.
+pub fn limit_stream<S>(
+    payloads: S,
+    provider: Provider,
+    model: String,
+    output_limit_idr: Option<i64>,
+) -> impl Stream<Item = String>
+where
+    S: Stream<Item = String>,
+{
+    stream! {
+        futures::pin_mut!(payloads);
+        let mut completion_tokens = 0;
+
+        while let Some(data) = payloads.next().await {
+            if let Some(limit) = output_limit_idr {
+                completion_tokens += StreamHandler::streamed_tokens(&data);
+                if UsageLogger::calculate_cost(provider, &model, 0, completion_tokens) > limit {
+                    tracing::warn!(model = %model, completion_tokens, "Stream cut off at the monthly spend limit");
+                    yield StreamHandler::error_payload(
+                        "Monthly spend limit reached during the response",
+                        "insufficient_quota",
+                        QUOTA_EXCEEDED_CODE,
+                    );
+                    break;
+                }
+            }
+            yield data;
+        }
+    }
+}
+
+/// Load a user's spend limit and logged spend for the current month
+pub async fn load_budget(pool: &PgPool, user_id: Uuid) -> Result<SpendBudget, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            u.monthly_spend_limit_idr,
+            COALESCE((
+                SELECT SUM(estimated_cost_idr)
+                FROM proxy_requests
+                WHERE user_id = u.id AND created_at >= date_trunc('month', NOW())
+            ), 0)::bigint AS spent_idr
+        FROM users u
+        WHERE u.id = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(SpendBudget {
+        limit_idr: row.get("monthly_spend_limit_idr"),
+        spent_idr: row.get("spent_idr"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PlanTier;
+    use crate::test_support::{insert_proxy_request, insert_user, ProxyRequestFixture};
+
+    fn estimate_of(cost_idr: i64) -> SpendEstimate {
+        SpendEstimate { prompt_tokens: 100, completion_tokens: 100, prompt_cost_idr: cost_idr / 2, cost_idr }
+    }
+
+    fn content_chunk(text: &str) -> String {
+        serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "choices": [{ "index": 0, "delta": { "content": text }, "finish_reason": null }]
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_estimate_uses_output_budget() {
+        let messages = vec![Message { role: "user".to_string(), content: "x".repeat(4000).into(), tool_calls: None, tool_call_id: None, name: None }];
+
+        let explicit = estimate(Provider::OpenAI, "gpt-4o", &messages, Some(100));
+        assert_eq!(explicit.completion_tokens, 100);
+        assert_eq!(explicit.prompt_tokens, TokenCounter::count_message_tokens(&messages));
+
+        // Unset max_tokens assumes the model's full output cap
+        let capped = estimate(Provider::OpenAI, "gpt-4o", &messages, None);
+        assert_eq!(capped.completion_tokens, 16_384);
+        assert!(capped.cost_idr > explicit.cost_idr);
+
+        let unknown = estimate(Provider::OpenAI, "gpt-5-unreleased", &messages, None);
+        assert_eq!(unknown.completion_tokens, DEFAULT_COMPLETION_ESTIMATE as i32);
+    }
+
+    #[test]
+    fn test_request_at_remaining_spend_boundary() {
+        let budget = SpendBudget { limit_idr: Some(10_000), spent_idr: 9_000 };
+        assert_eq!(budget.remaining_idr(), Some(1_000));
+
+        // Exactly fits
+        assert!(budget.check(&estimate_of(1_000)).is_ok());
+        // One rupiah over is pre-rejected
+        assert_eq!(
+            budget.check(&estimate_of(1_001)),
+            Err(InsufficientQuota { estimate_idr: 1_001, remaining_idr: 1_000 })
+        );
+    }
+
+    #[test]
+    fn test_overspent_and_unlimited_budgets() {
+        let overspent = SpendBudget { limit_idr: Some(10_000), spent_idr: 12_000 };
+        assert_eq!(overspent.remaining_idr(), Some(0));
+        assert!(overspent.check(&estimate_of(0)).is_ok());
+        assert!(overspent.check(&estimate_of(1)).is_err());
+
+        let unlimited = SpendBudget { limit_idr: None, spent_idr: 1_000_000 };
+        assert!(unlimited.check(&estimate_of(i64::MAX)).is_ok());
+    }
+
+    #[test]
+    fn test_output_limit_excludes_prompt_cost() {
+        let budget = SpendBudget { limit_idr: Some(10_000), spent_idr: 9_000 };
+        assert_eq!(budget.output_limit_idr(&estimate_of(600)), Some(700));
+        assert_eq!(budget.output_limit_idr(&estimate_of(4_000)), Some(0));
+        assert_eq!(SpendBudget { limit_idr: None, spent_idr: 0 }.output_limit_idr(&estimate_of(600)), None);
+    }
+
+    #[tokio::test]
+    async fn test_stream_cut_off_when_quota_trips_mid_stream() {
+        // Each chunk streams 1000 tokens; the limit covers 2500
+        let chunk = content_chunk(&"x".repeat(4000));
+        let limit = UsageLogger::calculate_cost(Provider::OpenAI, "gpt-4o", 0, 2500);
+        let upstream = futures::stream::iter(vec![chunk.clone(); 5]);
+
+        let payloads: Vec<String> =
+            limit_stream(upstream, Provider::OpenAI, "gpt-4o".to_string(), Some(limit)).collect().await;
+
+        assert_eq!(payloads.len(), 3);
+        assert_eq!(payloads[..2], [chunk.clone(), chunk.clone()]);
+        let error: serde_json::Value = serde_json::from_str(&payloads[2]).unwrap();
+        assert_eq!(error["error"]["code"], QUOTA_EXCEEDED_CODE);
+        assert_eq!(error["error"]["type"], "insufficient_quota");
+    }
+
+    #[tokio::test]
+    async fn test_stream_without_limit_passes_through() {
+        let chunks = vec![content_chunk(&"x".repeat(4000)); 5];
+        let upstream = futures::stream::iter(chunks.clone());
+
+        let payloads: Vec<String> = limit_stream(upstream, Provider::OpenAI, "gpt-4o".to_string(), None).collect().await;
+        assert_eq!(payloads, chunks);
+    }
+
+    #[test]
+    fn test_large_request_pre_rejected() {
+        let messages = vec![Message { role: "user".to_string(), content: "x".repeat(400_000).into(), tool_calls: None, tool_call_id: None, name: None }];
+        let large = estimate(Provider::OpenAI, "gpt-4o", &messages, Some(16_384));
+        let budget = SpendBudget { limit_idr: Some(large.cost_idr), spent_idr: 1 };
+
+        let err = budget.check(&large).unwrap_err();
+        assert_eq!(err.remaining_idr, large.cost_idr - 1);
+        assert!(err.to_string().contains("exceeds the remaining monthly spend"));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_budget_sums_logged_spend(pool: PgPool) {
+        let user_id = insert_user(&pool, "budget@example.com", PlanTier::Pro).await;
+        assert_eq!(
+            load_budget(&pool, user_id).await.unwrap(),
+            SpendBudget { limit_idr: None, spent_idr: 0 }
+        );
+
+        sqlx::query("UPDATE users SET monthly_spend_limit_idr = 5000 WHERE id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        for cost_idr in [1200, 800] {
+            insert_proxy_request(
+                &pool,
+                user_id,
+                ProxyRequestFixture {
+                    provider: "openai",
+                    model: "gpt-4o",
+                    prompt_tokens: 10,
+                    completion_tokens: 10,
+                    cost_idr,
+                    status_code: 200,
+                },
+            )
+            .await;
+        }
+
+        let budget = load_budget(&pool, user_id).await.unwrap();
+        assert_eq!(budget, SpendBudget { limit_idr: Some(5000), spent_idr: 2000 });
+        assert!(budget.check(&estimate_of(3000)).is_ok());
+        assert!(budget.check(&estimate_of(3001)).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! Optional coalescing of streamed content deltas.
+//!
+//! Token-by-token streams produce many tiny SSE events. When enabled, plain
+//! content deltas arriving within a short window are merged into one chunk
+//! before being sent. Any other chunk (role, finish reason, usage, tool calls)
+//! flushes the pending content first and is passed through unchanged, so
+//! ordering is preserved. `[DONE]` is appended by the caller after the stream.
+//!
+//! Configuration:
+//! - `STREAM_COALESCE_MS`: buffering window in milliseconds (off when unset or `0`)
+
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Upper bound for the buffering window, to keep streams interactive
+pub const MAX_COALESCE_MS: u64 = 500;
+
+/// Coalescing window for streamed deltas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamCoalesceConfig {
+    pub window: Duration,
+}
+
+impl StreamCoalesceConfig {
+    /// Load coalescing configuration from environment variables
+    pub fn from_env() -> Option<Self> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load coalescing configuration using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Option<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let ms = lookup("STREAM_COALESCE_MS")
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|&ms| ms > 0)?;
+        Some(Self {
+            window: Duration::from_millis(ms.min(MAX_COALESCE_MS)),
+        })
+    }
+}
+
+/// Content of a chunk that carries nothing but a single content delta
+fn content_delta(chunk: &Value) -> Option<&str> {
+    if chunk.get("usage").is_some_and(|usage| !usage.is_null()) {
+        return None;
+    }
+    let [choice] = chunk.get("choices")?.as_array()?.as_slice() else {
+        return None;
+    };
+    if choice.get("finish_reason").is_some_and(|reason| !reason.is_null()) {
+        return None;
+    }
+    let delta = choice.get("delta")?.as_object()?;
+    if delta.len() != 1 {
+        return None;
+    }
+    delta.get("content")?.as_str()
+}
+
+/// Merge runs of content-only chunk payloads arriving within `window`.
+/// Payloads that aren't JSON content deltas are never merged.
+pub fn coalesce<S>(payloads: S, window: Duration) -> impl Stream<Item = String>
+where
+    S: Stream<Item = String>,
+{
+    stream! {
+        futures::pin_mut!(payloads);
+        // First pending chunk (content accumulated into it) and its flush deadline
+        let mut pending: Option<(Value, String, Instant)> = None;
+
+        loop {
+            let deadline = pending.as_ref().map(|(_, _, deadline)| *deadline);
+            let next = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, payloads.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        if let Some((chunk, content, _)) = pending.take() {
+                            yield merged(chunk, content);
+                        }
+                        continue;
+                    }
+                },
+                None => payloads.next().await,
+            };
+
+            let Some(payload) = next else { break };
+
+            let parsed = serde_json::from_str::<Value>(&payload).ok();
+            match parsed.as_ref().and_then(content_delta) {
+                Some(content) => match &mut pending {
+                    Some((_, buffered, _)) => buffered.push_str(content),
+                    None => {
+                        let content = content.to_string();
+                        pending = Some((parsed.unwrap_or_default(), content, Instant::now() + window));
+                    }
+                },
+                None => {
+                    if let Some((chunk, content, _)) = pending.take() {
+                        yield merged(chunk, content);
+                    }
+                    yield payload;
+                }
+            }
+        }
+
+        if let Some((chunk, content, _)) = pending.take() {
+            yield merged(chunk, content);
+        }
+    }
+}
+
+/// Serialize the first buffered chunk with the combined content
+fn merged(mut chunk: Value, content: String) -> String {
+    if let Some(delta) = chunk.pointer_mut("/choices/0/delta") {
+        delta["content"] = Value::String(content);
+    }
+    chunk.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta(content: &str) -> String {
+        serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{ "index": 0, "delta": { "content": content } }]
+        })
+        .to_string()
+    }
+
+    fn finish() -> String {
+        serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }]
+        })
+        .to_string()
+    }
+
+    fn content_of(payload: &str) -> String {
+        let chunk: Value = serde_json::from_str(payload).unwrap();
+        chunk["choices"][0]["delta"]["content"].as_str().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_coalescing_reduces_events_and_keeps_content() {
+        let words = ["Halo", ",", " apa", " kabar", "?"];
+        let mut input: Vec<String> = words.iter().map(|w| delta(w)).collect();
+        input.push(finish());
+
+        let output: Vec<String> =
+            coalesce(futures::stream::iter(input.clone()), Duration::from_millis(50))
+                .collect()
+                .await;
+
+        assert_eq!(output.len(), 2);
+        let text: String = output.iter().map(|p| content_of(p)).collect();
+        assert_eq!(text, words.concat());
+        // The finish chunk is passed through last, unchanged
+        assert_eq!(output.last(), input.last());
+    }
+
+    #[tokio::test]
+    async fn test_non_delta_chunks_flush_and_keep_order() {
+        let role = serde_json::json!({
+            "choices": [{ "index": 0, "delta": { "role": "assistant" } }]
+        })
+        .to_string();
+        let input = vec![
+            role.clone(),
+            delta("a"),
+            delta("b"),
+            "not json".to_string(),
+            delta("c"),
+            finish(),
+        ];
+
+        let output: Vec<String> =
+            coalesce(futures::stream::iter(input), Duration::from_millis(50))
+                .collect()
+                .await;
+
+        assert_eq!(output.len(), 5);
+        assert_eq!(output[0], role);
+        assert_eq!(content_of(&output[1]), "ab");
+        assert_eq!(output[2], "not json");
+        assert_eq!(content_of(&output[3]), "c");
+        assert_eq!(output[4], finish());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pending_content_flushes_after_window() {
+        let input = futures::stream::iter(vec![delta("a"), delta("b")]).chain(
+            futures::stream::once(async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                delta("c")
+            }),
+        );
+
+        let output: Vec<String> = coalesce(input, Duration::from_millis(20)).collect().await;
+
+        assert_eq!(output.len(), 2);
+        assert_eq!(content_of(&output[0]), "ab");
+        assert_eq!(content_of(&output[1]), "c");
+    }
+
+    #[test]
+    fn test_from_lookup() {
+        assert_eq!(StreamCoalesceConfig::from_lookup(|_| None), None);
+        assert_eq!(
+            StreamCoalesceConfig::from_lookup(|_| Some("0".to_string())),
+            None
+        );
+        let config = StreamCoalesceConfig::from_lookup(|key| {
+            (key == "STREAM_COALESCE_MS").then(|| "25".to_string())
+        })
+        .unwrap();
+        assert_eq!(config.window, Duration::from_millis(25));
+        let capped = StreamCoalesceConfig::from_lookup(|_| Some("10000".to_string())).unwrap();
+        assert_eq!(capped.window, Duration::from_millis(MAX_COALESCE_MS));
+    }
+}
@@ -9,8 +9,15 @@ use async_stream::stream;
 use futures::Stream;
 use serde::{Deserialize, Serialize};
 use std::pin::Pin;
+use tokio_util::sync::CancellationToken;
 
-use crate::services::transformers::Provider;
+use crate::services::transformers::google::UsageMetadata;
+use crate::services::transformers::qwen::QwenUsage;
+use crate::services::transformers::Usage;
+use crate::services::usage_logger::TokenCounter;
+
+/// Error code of the SSE event sent when a stream is ended by shutdown
+pub const SHUTDOWN_CODE: &str = "SERVER_SHUTTING_DOWN";
 
 /// OpenAI-compatible streaming chunk format
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +43,32 @@ pub struct StreamDelta {
     pub role: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+    /// Reasoning tokens; only sent when the client sets `include_reasoning`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+}
+
+/// Incremental tool call in OpenAI's streaming shape: the first delta for a
+/// call carries `id`, `type`, and the function name; later deltas carry only
+/// the next piece of the `arguments` string.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallDelta {
+    pub index: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<String>,
+    pub function: FunctionCallDelta,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCallDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub arguments: String,
 }
 
 /// Anthropic streaming event types
@@ -65,6 +98,14 @@ pub enum AnthropicStreamEvent {
 pub struct AnthropicMessageStart {
     pub id: String,
     pub model: String,
+    #[serde(default)]
+    pub usage: Option<AnthropicUsageStart>,
+}
+
+/// Usage reported in `message_start` (prompt tokens)
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnthropicUsageStart {
+    pub input_tokens: i32,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -72,6 +113,12 @@ pub struct AnthropicContentBlock {
     pub r#type: String,
     #[serde(default)]
     pub text: String,
+    /// Tool call id (`tool_use` blocks)
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Tool name (`tool_use` blocks)
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -79,6 +126,12 @@ pub struct AnthropicDelta {
     pub r#type: String,
     #[serde(default)]
     pub text: String,
+    /// Next piece of the tool input JSON (`input_json_delta`)
+    #[serde(default)]
+    pub partial_json: String,
+    /// Next piece of extended thinking (`thinking_delta`)
+    #[serde(default)]
+    pub thinking: String,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -101,6 +154,9 @@ pub struct AnthropicError {
 #[derive(Debug, Clone, Deserialize)]
 pub struct GoogleStreamChunk {
     pub candidates: Option<Vec<GoogleCandidate>>,
+    /// Token counts so far; the final chunk carries the totals
+    #[serde(rename = "usageMetadata", default)]
+    pub usage_metadata: Option<UsageMetadata>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -125,6 +181,9 @@ pub struct GooglePart {
 pub struct QwenStreamChunk {
     pub output: QwenStreamOutput,
     pub request_id: String,
+    /// Token counts so far; the final chunk carries the totals
+    #[serde(default)]
+    pub usage: Option<QwenUsage>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -133,6 +192,93 @@ pub struct QwenStreamOutput {
     pub finish_reason: Option<String>,
 }
 
+/// OpenAI stream chunk carrying usage
+/// (final chunk when `stream_options.include_usage` is set, `null` otherwise)
+#[derive(Debug, Clone, Deserialize)]
+pub struct OpenAIUsageChunk {
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+/// Sets the assistant role on the first chunk of a stream only, as OpenAI
+/// does; every later delta carries content (or tool calls) without a role.
+#[derive(Debug, Default)]
+pub struct FirstChunkRole {
+    sent: bool,
+}
+
+impl FirstChunkRole {
+    pub fn apply(&mut self, chunk: &mut StreamChunk) {
+        for choice in &mut chunk.choices {
+            choice.delta.role = (!self.sent).then(|| "assistant".to_string());
+        }
+        self.sent = true;
+    }
+}
+
+/// Token usage of one stream, reported once through its callback when the
+/// stream finishes or is dropped early (e.g. the client disconnected).
+/// Provider-reported counts win; otherwise the prompt falls back to the
+/// caller's estimate and the completion to the streamed text.
+pub struct StreamUsage<F: FnOnce(Usage)> {
+    estimated_prompt_tokens: i32,
+    streamed_tokens: i32,
+    prompt_tokens: Option<i32>,
+    completion_tokens: Option<i32>,
+    on_usage: Option<F>,
+}
+
+impl<F: FnOnce(Usage)> StreamUsage<F> {
+    pub fn new(estimated_prompt_tokens: i32, on_usage: F) -> Self {
+        Self {
+            estimated_prompt_tokens,
+            streamed_tokens: 0,
+            prompt_tokens: None,
+            completion_tokens: None,
+            on_usage: Some(on_usage),
+        }
+    }
+
+    /// Count the text of an OpenAI chunk payload sent to the client
+    pub fn add_payload(&mut self, data: &str) {
+        self.streamed_tokens += StreamHandler::streamed_tokens(data);
+    }
+
+    /// Prompt tokens reported by the provider
+    pub fn report_prompt_tokens(&mut self, tokens: i32) {
+        self.prompt_tokens = Some(tokens);
+    }
+
+    /// Completion tokens reported by the provider (cumulative)
+    pub fn report_completion_tokens(&mut self, tokens: i32) {
+        self.completion_tokens = Some(tokens);
+    }
+
+    /// Usage as currently known
+    pub fn usage(&self) -> Usage {
+        let prompt_tokens = self.prompt_tokens.unwrap_or(self.estimated_prompt_tokens);
+        let completion_tokens = self.completion_tokens.unwrap_or(self.streamed_tokens);
+        Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+
+    /// Report the usage; only the first call (or drop) reports
+    pub fn finish(&mut self) {
+        if let Some(callback) = self.on_usage.take() {
+            callback(self.usage());
+        }
+    }
+}
+
+impl<F: FnOnce(Usage)> Drop for StreamUsage<F> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
 /// Stream handler for transforming provider SSE to OpenAI format
 pub struct StreamHandler;
 
@@ -150,35 +296,37 @@ impl StreamHandler {
         }
     }
 
-    /// Transform Anthropic stream event to OpenAI chunk
+    /// Transform Anthropic stream event to OpenAI chunk.
+    /// Anthropic's content block index is not a choice index: there is only
+    /// ever choice `0`. Deltas carry no role; streams set it with `FirstChunkRole`.
     pub fn transform_anthropic_chunk(
         event: &AnthropicStreamEvent,
         message_id: &str,
         model: &str,
     ) -> Option<StreamChunk> {
         match event {
-            AnthropicStreamEvent::ContentBlockStart { index, content_block } => {
-                // First chunk with role
+            AnthropicStreamEvent::ContentBlockStart { content_block, .. } => {
+                if content_block.text.is_empty() {
+                    return None;
+                }
                 Some(StreamChunk {
                     id: format!("chatcmpl-{}", message_id),
                     object: "chat.completion.chunk".to_string(),
                     created: chrono::Utc::now().timestamp(),
                     model: model.to_string(),
                     choices: vec![StreamChoice {
-                        index: *index,
+                        index: 0,
                         delta: StreamDelta {
-                            role: Some("assistant".to_string()),
-                            content: if content_block.text.is_empty() {
-                                None
-                            } else {
-                                Some(content_block.text.clone())
-                            },
+                            role: None,
+                            content: Some(content_block.text.clone()),
+                            tool_calls: None,
+                            reasoning_content: None,
                         },
                         finish_reason: None,
                     }],
                 })
             }
-            AnthropicStreamEvent::ContentBlockDelta { index, delta } => {
+            AnthropicStreamEvent::ContentBlockDelta { delta, .. } => {
                 if delta.text.is_empty() {
                     return None;
                 }
@@ -188,10 +336,12 @@ impl StreamHandler {
                     created: chrono::Utc::now().timestamp(),
                     model: model.to_string(),
                     choices: vec![StreamChoice {
-                        index: *index,
+                        index: 0,
                         delta: StreamDelta {
                             role: None,
                             content: Some(delta.text.clone()),
+                            tool_calls: None,
+                            reasoning_content: None,
                         },
                         finish_reason: None,
                     }],
@@ -200,11 +350,71 @@ impl StreamHandler {
             AnthropicStreamEvent::MessageDelta { delta, .. } => {
                 let finish_reason = delta.stop_reason.as_ref().map(|r| {
                     match r.as_str() {
-                        "end_turn" => "stop".to_string(),
+                        "end_turn" | "stop_sequence" => "stop".to_string(),
+                        "tool_use" => "tool_calls".to_string(),
                         "max_tokens" => "length".to_string(),
                         other => other.to_string(),
                     }
                 });
+                Some(Self::anthropic_terminal_chunk(message_id, model, finish_reason))
+            }
+            _ => None,
+        }
+    }
+
+    /// Translate a `tool_use` block start or `input_json_delta` into an
+    /// OpenAI tool call delta. `tool_blocks` records the content block index of
+    /// each tool call seen so far; its position is the OpenAI tool call index.
+    pub fn anthropic_tool_call_delta(
+        event: &AnthropicStreamEvent,
+        tool_blocks: &mut Vec<i32>,
+    ) -> Option<ToolCallDelta> {
+        match event {
+            AnthropicStreamEvent::ContentBlockStart { index, content_block }
+                if content_block.r#type == "tool_use" =>
+            {
+                tool_blocks.push(*index);
+                Some(ToolCallDelta {
+                    index: (tool_blocks.len() - 1) as i32,
+                    id: content_block.id.clone(),
+                    r#type: Some("function".to_string()),
+                    function: FunctionCallDelta {
+                        name: content_block.name.clone(),
+                        arguments: String::new(),
+                    },
+                })
+            }
+            AnthropicStreamEvent::ContentBlockDelta { index, delta }
+                if delta.r#type == "input_json_delta" =>
+            {
+                if delta.partial_json.is_empty() {
+                    return None;
+                }
+                let tool_index = tool_blocks.iter().position(|block| block == index)?;
+                Some(ToolCallDelta {
+                    index: tool_index as i32,
+                    id: None,
+                    r#type: None,
+                    function: FunctionCallDelta {
+                        name: None,
+                        arguments: delta.partial_json.clone(),
+                    },
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Translate a `thinking_delta` into a `reasoning_content` chunk
+    pub fn anthropic_reasoning_chunk(
+        event: &AnthropicStreamEvent,
+        message_id: &str,
+        model: &str,
+    ) -> Option<StreamChunk> {
+        match event {
+            AnthropicStreamEvent::ContentBlockDelta { delta, .. }
+                if delta.r#type == "thinking_delta" && !delta.thinking.is_empty() =>
+            {
                 Some(StreamChunk {
                     id: format!("chatcmpl-{}", message_id),
                     object: "chat.completion.chunk".to_string(),
@@ -215,8 +425,10 @@ impl StreamHandler {
                         delta: StreamDelta {
                             role: None,
                             content: None,
+                            tool_calls: None,
+                            reasoning_content: Some(delta.thinking.clone()),
                         },
-                        finish_reason,
+                        finish_reason: None,
                     }],
                 })
             }
@@ -224,6 +436,46 @@ impl StreamHandler {
         }
     }
 
+    /// Chunk carrying a single tool call delta
+    fn anthropic_tool_call_chunk(message_id: &str, model: &str, tool_call: ToolCallDelta) -> StreamChunk {
+        StreamChunk {
+            id: format!("chatcmpl-{}", message_id),
+            object: "chat.completion.chunk".to_string(),
+            created: chrono::Utc::now().timestamp(),
+            model: model.to_string(),
+            choices: vec![StreamChoice {
+                index: 0,
+                delta: StreamDelta {
+                    role: None,
+                    content: None,
+                    tool_calls: Some(vec![tool_call]),
+                    reasoning_content: None,
+                },
+                finish_reason: None,
+            }],
+        }
+    }
+
+    /// Empty-delta chunk carrying the final finish_reason
+    fn anthropic_terminal_chunk(message_id: &str, model: &str, finish_reason: Option<String>) -> StreamChunk {
+        StreamChunk {
+            id: format!("chatcmpl-{}", message_id),
+            object: "chat.completion.chunk".to_string(),
+            created: chrono::Utc::now().timestamp(),
+            model: model.to_string(),
+            choices: vec![StreamChoice {
+                index: 0,
+                delta: StreamDelta {
+                    role: None,
+                    content: None,
+                    tool_calls: None,
+                    reasoning_content: None,
+                },
+                finish_reason,
+            }],
+        }
+    }
+
     /// Transform Google stream chunk to OpenAI format
     pub fn transform_google_chunk(chunk: &GoogleStreamChunk, model: &str) -> Option<StreamChunk> {
         let candidates = chunk.candidates.as_ref()?;
@@ -250,8 +502,10 @@ impl StreamHandler {
             choices: vec![StreamChoice {
                 index: 0,
                 delta: StreamDelta {
-                    role: if content.is_some() { Some("assistant".to_string()) } else { None },
+                    role: None,
                     content,
+                    tool_calls: None,
+                    reasoning_content: None,
                 },
                 finish_reason,
             }],
@@ -276,19 +530,385 @@ impl StreamHandler {
             choices: vec![StreamChoice {
                 index: 0,
                 delta: StreamDelta {
-                    role: Some("assistant".to_string()),
+                    role: None,
                     content: chunk.output.text.clone(),
+                    tool_calls: None,
+                    reasoning_content: None,
                 },
                 finish_reason,
             }],
         })
     }
 
+    /// Estimated tokens of the text (content, reasoning and tool call
+    /// arguments) in an OpenAI chunk payload
+    pub fn streamed_tokens(data: &str) -> i32 {
+        let Ok(chunk) = serde_json::from_str::<serde_json::Value>(data) else {
+            return 0;
+        };
+        let deltas = chunk["choices"].as_array().into_iter().flatten().map(|choice| &choice["delta"]);
+        deltas
+            .flat_map(|delta| {
+                let arguments = delta["tool_calls"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|call| call["function"]["arguments"].as_str());
+                [delta["content"].as_str(), delta["reasoning_content"].as_str()]
+                    .into_iter()
+                    .flatten()
+                    .chain(arguments)
+                    .collect::<Vec<_>>()
+            })
+            .map(TokenCounter::estimate_tokens)
+            .sum()
+    }
+
+    /// Extract usage from an OpenAI stream chunk, if present
+    pub fn extract_openai_usage(data: &str) -> Option<Usage> {
+        serde_json::from_str::<OpenAIUsageChunk>(data)
+            .ok()
+            .and_then(|chunk| chunk.usage)
+    }
+
+    /// Drop `delta.reasoning_content` from an OpenAI-format chunk payload;
+    /// payloads without reasoning are returned unchanged
+    pub fn without_reasoning(data: &str) -> String {
+        if !data.contains("reasoning_content") {
+            return data.to_string();
+        }
+        let Ok(mut chunk) = serde_json::from_str::<serde_json::Value>(data) else {
+            return data.to_string();
+        };
+        if let Some(choices) = chunk["choices"].as_array_mut() {
+            for choice in choices {
+                if let Some(delta) = choice["delta"].as_object_mut() {
+                    delta.remove("reasoning_content");
+                }
+            }
+        }
+        chunk.to_string()
+    }
+
+    /// Passthrough OpenAI SSE, yielding each `data:` payload unchanged
+    /// (apart from reasoning deltas, unless `include_reasoning` is set).
+    /// Usage comes from the final chunk when the client set
+    /// `stream_options.include_usage`, otherwise from the streamed text.
+    /// Requirements: 4.1-4.3
+    pub fn openai_passthrough<S, B, E, F>(
+        byte_stream: S,
+        include_reasoning: bool,
+        usage: StreamUsage<F>,
+    ) -> impl Stream<Item = String>
+    where
+        S: Stream<Item = Result<B, E>>,
+        B: AsRef<[u8]>,
+        E: std::fmt::Display,
+        F: FnOnce(Usage),
+    {
+        stream! {
+            futures::pin_mut!(byte_stream);
+            let mut buffer = String::new();
+            let mut usage = usage;
+
+            while let Some(chunk_result) = futures::StreamExt::next(&mut byte_stream).await {
+                match chunk_result {
+                    Ok(bytes) => {
+                        buffer.push_str(&String::from_utf8_lossy(bytes.as_ref()));
+
+                        // Process complete lines
+                        while let Some(pos) = buffer.find("\n\n") {
+                            let line = buffer[..pos].to_string();
+                            buffer = buffer[pos + 2..].to_string();
+
+                            if let Some(data) = line.strip_prefix("data: ") {
+                                match Self::extract_openai_usage(data) {
+                                    Some(reported) => {
+                                        usage.report_prompt_tokens(reported.prompt_tokens);
+                                        usage.report_completion_tokens(reported.completion_tokens);
+                                    }
+                                    None => usage.add_payload(data),
+                                }
+                                if data == "[DONE]" {
+                                    usage.finish();
+                                }
+                                if include_reasoning {
+                                    yield data.to_string();
+                                } else {
+                                    yield Self::without_reasoning(data);
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Stream error: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            usage.finish();
+        }
+    }
+
+    /// Transform Anthropic SSE to OpenAI chunk payloads.
+    ///
+    /// Prompt tokens come from `message_start`, output tokens from
+    /// `message_delta`; usage is reported once on `message_stop` (or when the
+    /// stream ends early, estimated from the streamed text if Anthropic
+    /// reported no output tokens yet). A terminal chunk with `finish_reason` is always
+    /// emitted, even if `message_delta` carried no stop reason. Extended
+    /// thinking is sent as `reasoning_content` only with `include_reasoning`.
+    /// Requirements: 4.1-4.5
+    pub fn anthropic_stream<S, B, E, F>(
+        byte_stream: S,
+        model: String,
+        include_reasoning: bool,
+        usage: StreamUsage<F>,
+    ) -> impl Stream<Item = String>
+    where
+        S: Stream<Item = Result<B, E>>,
+        B: AsRef<[u8]>,
+        E: std::fmt::Display,
+        F: FnOnce(Usage),
+    {
+        stream! {
+            futures::pin_mut!(byte_stream);
+            let mut buffer = String::new();
+            let mut message_id = String::new();
+            let mut usage = usage;
+            let mut finished = false;
+            let mut tool_blocks = Vec::new();
+            let mut role = FirstChunkRole::default();
+
+            while let Some(chunk_result) = futures::StreamExt::next(&mut byte_stream).await {
+                match chunk_result {
+                    Ok(bytes) => {
+                        buffer.push_str(&String::from_utf8_lossy(bytes.as_ref()));
+
+                        // Process complete SSE events
+                        while let Some(pos) = buffer.find("\n\n") {
+                            let event_block = buffer[..pos].to_string();
+                            buffer = buffer[pos + 2..].to_string();
+
+                            let Some(data) = event_block.lines().find_map(|line| line.strip_prefix("data: ")) else {
+                                continue;
+                            };
+                            let Ok(event) = serde_json::from_str::<AnthropicStreamEvent>(data) else {
+                                continue;
+                            };
+
+                            match &event {
+                                AnthropicStreamEvent::MessageStart { message } => {
+                                    message_id = message.id.clone();
+                                    if let Some(reported) = &message.usage {
+                                        usage.report_prompt_tokens(reported.input_tokens);
+                                    }
+                                }
+                                AnthropicStreamEvent::MessageDelta { delta, usage: reported } => {
+                                    if let Some(reported) = reported {
+                                        usage.report_completion_tokens(reported.output_tokens);
+                                    }
+                                    finished = finished || delta.stop_reason.is_some();
+                                }
+                                AnthropicStreamEvent::MessageStop => {
+                                    if !finished {
+                                        finished = true;
+                                        let mut chunk = Self::anthropic_terminal_chunk(&message_id, &model, Some("stop".to_string()));
+                                        role.apply(&mut chunk);
+                                        yield serde_json::to_string(&chunk).unwrap_or_default();
+                                    }
+                                    usage.finish();
+                                }
+                                _ => {}
+                            }
+
+                            let chunk = match Self::anthropic_tool_call_delta(&event, &mut tool_blocks) {
+                                Some(tool_call) => Some(Self::anthropic_tool_call_chunk(&message_id, &model, tool_call)),
+                                None if include_reasoning => Self::anthropic_reasoning_chunk(&event, &message_id, &model)
+                                    .or_else(|| Self::transform_anthropic_chunk(&event, &message_id, &model)),
+                                None => Self::transform_anthropic_chunk(&event, &message_id, &model),
+                            };
+                            if let Some(mut chunk) = chunk {
+                                role.apply(&mut chunk);
+                                let data = serde_json::to_string(&chunk).unwrap_or_default();
+                                usage.add_payload(&data);
+                                yield data;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Anthropic stream error: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            // Stream ended without message_stop: still bill what was produced
+            usage.finish();
+        }
+    }
+
+    /// Transform Google SSE to OpenAI chunk payloads, reporting usage from
+    /// the last `usageMetadata` when the stream ends
+    /// Requirements: 4.1-4.5
+    pub fn google_stream<S, B, E, F>(byte_stream: S, model: String, usage: StreamUsage<F>) -> impl Stream<Item = String>
+    where
+        S: Stream<Item = Result<B, E>>,
+        B: AsRef<[u8]>,
+        E: std::fmt::Display,
+        F: FnOnce(Usage),
+    {
+        stream! {
+            futures::pin_mut!(byte_stream);
+            let mut buffer = String::new();
+            let mut usage = usage;
+            let mut role = FirstChunkRole::default();
+
+            while let Some(chunk_result) = futures::StreamExt::next(&mut byte_stream).await {
+                match chunk_result {
+                    Ok(bytes) => {
+                        buffer.push_str(&String::from_utf8_lossy(bytes.as_ref()));
+
+                        // Process complete lines
+                        while let Some(pos) = buffer.find('\n') {
+                            let line = buffer[..pos].to_string();
+                            buffer = buffer[pos + 1..].to_string();
+
+                            let Some(data) = Self::parse_sse_line(&line) else {
+                                continue;
+                            };
+                            let Ok(google_chunk) = serde_json::from_str::<GoogleStreamChunk>(&data) else {
+                                continue;
+                            };
+                            if let Some(metadata) = &google_chunk.usage_metadata {
+                                if let Some(tokens) = metadata.prompt_token_count {
+                                    usage.report_prompt_tokens(tokens);
+                                }
+                                if let Some(tokens) = metadata.candidates_token_count {
+                                    usage.report_completion_tokens(tokens);
+                                }
+                            }
+                            if let Some(mut chunk) = Self::transform_google_chunk(&google_chunk, &model) {
+                                role.apply(&mut chunk);
+                                let data = serde_json::to_string(&chunk).unwrap_or_default();
+                                usage.add_payload(&data);
+                                yield data;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Google stream error: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            usage.finish();
+        }
+    }
+
+    /// Transform Qwen SSE to OpenAI chunk payloads, reporting usage from the
+    /// final chunk when the stream ends
+    /// Requirements: 4.1-4.5
+    pub fn qwen_stream<S, B, E, F>(byte_stream: S, model: String, usage: StreamUsage<F>) -> impl Stream<Item = String>
+    where
+        S: Stream<Item = Result<B, E>>,
+        B: AsRef<[u8]>,
+        E: std::fmt::Display,
+        F: FnOnce(Usage),
+    {
+        stream! {
+            futures::pin_mut!(byte_stream);
+            let mut buffer = String::new();
+            let mut usage = usage;
+            let mut role = FirstChunkRole::default();
+
+            while let Some(chunk_result) = futures::StreamExt::next(&mut byte_stream).await {
+                match chunk_result {
+                    Ok(bytes) => {
+                        buffer.push_str(&String::from_utf8_lossy(bytes.as_ref()));
+
+                        // Process complete SSE events
+                        while let Some(pos) = buffer.find("\n\n") {
+                            let line = buffer[..pos].to_string();
+                            buffer = buffer[pos + 2..].to_string();
+
+                            let Some(data) = line.lines().find_map(Self::parse_sse_line) else {
+                                continue;
+                            };
+                            let Ok(qwen_chunk) = serde_json::from_str::<QwenStreamChunk>(&data) else {
+                                continue;
+                            };
+                            if let Some(reported) = &qwen_chunk.usage {
+                                usage.report_prompt_tokens(reported.input_tokens);
+                                usage.report_completion_tokens(reported.output_tokens);
+                            }
+                            if let Some(mut chunk) = Self::transform_qwen_chunk(&qwen_chunk, &model) {
+                                role.apply(&mut chunk);
+                                let data = serde_json::to_string(&chunk).unwrap_or_default();
+                                usage.add_payload(&data);
+                                yield data;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Qwen stream error: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            usage.finish();
+        }
+    }
+
     /// Format chunk as SSE data line
     pub fn format_sse_chunk(chunk: &StreamChunk) -> String {
         format!("data: {}\n\n", serde_json::to_string(chunk).unwrap_or_default())
     }
 
+    /// OpenAI-shaped error payload for a stream that must end early
+    pub fn error_payload(message: &str, error_type: &str, code: &str) -> String {
+        serde_json::json!({
+            "error": { "message": message, "type": error_type, "code": code }
+        })
+        .to_string()
+    }
+
+    /// Pass payloads through until `shutdown` is cancelled, then send a
+    /// `SERVER_SHUTTING_DOWN` error payload and end the stream, dropping
+    /// the upstream instead of polling it further
+    pub fn until_shutdown<S>(payloads: S, shutdown: CancellationToken) -> impl Stream<Item = String>
+    where
+        S: Stream<Item = String>,
+    {
+        stream! {
+            futures::pin_mut!(payloads);
+
+            loop {
+                let next = tokio::select! {
+                    biased;
+                    _ = shutdown.cancelled() => None,
+                    next = futures::StreamExt::next(&mut payloads) => Some(next),
+                };
+                match next {
+                    Some(Some(data)) => yield data,
+                    Some(None) => break,
+                    None => {
+                        tracing::info!("Ending stream for server shutdown");
+                        yield Self::error_payload(
+                            "Server is shutting down, please retry the request",
+                            "server_error",
+                            SHUTDOWN_CODE,
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
     /// Format SSE done message
     pub fn format_sse_done() -> String {
         "data: [DONE]\n\n".to_string()
@@ -329,6 +949,8 @@ mod tests {
                 delta: StreamDelta {
                     role: None,
                     content: Some("Hello".to_string()),
+                    tool_calls: None,
+                    reasoning_content: None,
                 },
                 finish_reason: None,
             }],
@@ -356,6 +978,7 @@ mod tests {
                 }),
                 finish_reason: None,
             }]),
+            usage_metadata: None,
         };
 
         let result = StreamHandler::transform_google_chunk(&chunk, "gemini-pro");
@@ -373,6 +996,7 @@ mod tests {
                 finish_reason: None,
             },
             request_id: "req-123".to_string(),
+            usage: None,
         };
 
         let result = StreamHandler::transform_qwen_chunk(&chunk, "qwen-turbo");
@@ -381,4 +1005,420 @@ mod tests {
         assert!(stream_chunk.id.contains("req-123"));
         assert_eq!(stream_chunk.choices[0].delta.content, Some("Test response".to_string()));
     }
+
+    async fn collect_anthropic(sse: String, include_reasoning: bool) -> (Vec<StreamChunk>, Option<Usage>) {
+        use futures::StreamExt;
+
+        let bytes = futures::stream::iter(vec![Ok::<_, std::convert::Infallible>(sse.into_bytes())]);
+        let captured = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let sink = captured.clone();
+
+        let chunks: Vec<StreamChunk> = StreamHandler::anthropic_stream(bytes, "claude-3-haiku-20240307".to_string(), include_reasoning, StreamUsage::new(0, move |usage| {
+            *sink.lock().unwrap() = Some(usage);
+        }))
+        .map(|data| serde_json::from_str(&data).unwrap())
+        .collect()
+        .await;
+
+        let usage = captured.lock().unwrap().clone();
+        (chunks, usage)
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_thinking_streamed_only_with_include_reasoning() {
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg_3","model":"claude-3-7-sonnet-20250219","usage":{"input_tokens":10,"output_tokens":1}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"thinking","thinking":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"Let me add these."}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"text_delta","text":"4"}}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":9}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let sse: String = events.iter().map(|data| format!("data: {}\n\n", data)).collect();
+        let reasoning = |chunks: &[StreamChunk]| -> Vec<String> {
+            chunks
+                .iter()
+                .filter_map(|chunk| chunk.choices[0].delta.reasoning_content.clone())
+                .collect()
+        };
+        let content = |chunks: &[StreamChunk]| -> String {
+            chunks.iter().filter_map(|chunk| chunk.choices[0].delta.content.clone()).collect()
+        };
+
+        let (excluded, _) = collect_anthropic(sse.clone(), false).await;
+        assert!(excluded.iter().all(|chunk| chunk.choices[0].index == 0));
+        assert!(reasoning(&excluded).is_empty());
+        assert_eq!(content(&excluded), "4");
+
+        let (included, usage) = collect_anthropic(sse, true).await;
+        assert!(included.iter().all(|chunk| chunk.choices[0].index == 0));
+        assert_eq!(reasoning(&included), vec!["Let me add these."]);
+        assert_eq!(content(&included), "4");
+        assert_eq!(usage.unwrap().completion_tokens, 9);
+    }
+
+    #[tokio::test]
+    async fn test_openai_passthrough_strips_reasoning_unless_included() {
+        use futures::StreamExt;
+
+        let reasoning = r#"{"id":"chatcmpl-2","choices":[{"index":0,"delta":{"reasoning_content":"Thinking..."}}]}"#;
+        let content = r#"{"id":"chatcmpl-2","choices":[{"index":0,"delta":{"content":"Done"}}]}"#;
+        let sse = format!("data: {}\n\ndata: {}\n\n", reasoning, content);
+        let passthrough = |include_reasoning| {
+            let bytes = futures::stream::iter(vec![Ok::<_, std::io::Error>(sse.clone().into_bytes())]);
+            StreamHandler::openai_passthrough(bytes, include_reasoning, StreamUsage::new(0, |_| {})).collect::<Vec<String>>()
+        };
+
+        let excluded = passthrough(false).await;
+        let first: serde_json::Value = serde_json::from_str(&excluded[0]).unwrap();
+        assert_eq!(first["choices"][0]["delta"], serde_json::json!({}));
+        assert_eq!(excluded[1], content);
+
+        assert_eq!(passthrough(true).await, vec![reasoning.to_string(), content.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_stream_terminal_chunk_and_usage() {
+        let events = [
+            ("message_start", r#"{"type":"message_start","message":{"id":"msg_1","model":"claude-3-haiku-20240307","usage":{"input_tokens":25,"output_tokens":1}}}"#),
+            ("content_block_start", r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#),
+            ("content_block_delta", r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}"#),
+            ("content_block_stop", r#"{"type":"content_block_stop","index":0}"#),
+            ("message_delta", r#"{"type":"message_delta","delta":{"stop_reason":"max_tokens"},"usage":{"output_tokens":15}}"#),
+            ("message_stop", r#"{"type":"message_stop"}"#),
+        ];
+        let sse: String = events
+            .iter()
+            .map(|(event, data)| format!("event: {}\ndata: {}\n\n", event, data))
+            .collect();
+
+        let (chunks, usage) = collect_anthropic(sse, false).await;
+
+        let last = chunks.last().expect("terminal chunk");
+        assert_eq!(last.id, "chatcmpl-msg_1");
+        assert_eq!(last.choices[0].finish_reason.as_deref(), Some("length"));
+        assert_eq!(chunks.iter().filter(|c| c.choices[0].finish_reason.is_some()).count(), 1);
+
+        let usage = usage.expect("usage logged");
+        assert_eq!(usage.prompt_tokens, 25);
+        assert_eq!(usage.completion_tokens, 15);
+        assert_eq!(usage.total_tokens, 40);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_stream_message_stop_without_stop_reason() {
+        let sse = concat!(
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_2\",\"model\":\"claude\",\"usage\":{\"input_tokens\":3}}}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        )
+        .to_string();
+
+        let (chunks, usage) = collect_anthropic(sse, false).await;
+
+        assert_eq!(chunks.last().unwrap().choices[0].finish_reason.as_deref(), Some("stop"));
+        assert_eq!(usage.unwrap().prompt_tokens, 3);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_stream_tool_call_deltas_reassemble() {
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg_3","model":"claude","usage":{"input_tokens":10}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Checking."}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"get_weather","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":""}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"city\": \"Ja"}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"karta\"}"}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+            r#"{"type":"content_block_start","index":2,"content_block":{"type":"tool_use","id":"toolu_2","name":"get_time","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":2,"delta":{"type":"input_json_delta","partial_json":"{}"}}"#,
+            r#"{"type":"content_block_stop","index":2}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":40}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let sse: String = events.iter().map(|data| format!("data: {}\n\n", data)).collect();
+
+        let (chunks, _) = collect_anthropic(sse, false).await;
+
+        // Reassemble the way an OpenAI client does: by tool call index
+        let mut calls: Vec<(String, String, String)> = Vec::new();
+        for delta in chunks.iter().filter_map(|c| c.choices[0].delta.tool_calls.as_ref()).flatten() {
+            let index = delta.index as usize;
+            if let Some(id) = &delta.id {
+                assert_eq!(index, calls.len(), "new calls are numbered in order");
+                assert_eq!(delta.r#type.as_deref(), Some("function"));
+                calls.push((id.clone(), delta.function.name.clone().unwrap(), String::new()));
+            } else {
+                assert!(delta.function.name.is_none(), "later deltas only carry arguments");
+            }
+            calls[index].2.push_str(&delta.function.arguments);
+        }
+
+        assert_eq!(
+            calls,
+            vec![
+                ("toolu_1".to_string(), "get_weather".to_string(), r#"{"city": "Jakarta"}"#.to_string()),
+                ("toolu_2".to_string(), "get_time".to_string(), "{}".to_string()),
+            ]
+        );
+        let args: serde_json::Value = serde_json::from_str(&calls[0].2).unwrap();
+        assert_eq!(args["city"], "Jakarta");
+
+        // Text content is still streamed, and the stop reason maps to tool_calls
+        assert!(chunks.iter().any(|c| c.choices[0].delta.content.as_deref() == Some("Checking.")));
+        assert_eq!(chunks.last().unwrap().choices[0].finish_reason.as_deref(), Some("tool_calls"));
+
+        // Wire shape matches OpenAI: opening delta has id/type/name, later ones only arguments
+        let opening = serde_json::to_value(&chunks.iter().find(|c| c.choices[0].delta.tool_calls.is_some()).unwrap().choices[0].delta).unwrap();
+        assert_eq!(
+            opening["tool_calls"][0],
+            serde_json::json!({ "index": 0, "id": "toolu_1", "type": "function", "function": { "name": "get_weather", "arguments": "" } })
+        );
+    }
+
+    fn roles(chunks: &[StreamChunk]) -> Vec<Option<&str>> {
+        chunks.iter().map(|c| c.choices[0].delta.role.as_deref()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_stream_role_only_on_first_chunk() {
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg_4","model":"claude","usage":{"input_tokens":5}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Halo"}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":", apa kabar?"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"lookup","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{}"}}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":9}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let sse: String = events.iter().map(|data| format!("data: {}\n\n", data)).collect();
+
+        let (chunks, _) = collect_anthropic(sse, false).await;
+
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(roles(&chunks)[0], Some("assistant"));
+        assert!(roles(&chunks)[1..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_google_and_qwen_role_only_on_first_chunk() {
+        let google = |text: &str, finish: Option<&str>| GoogleStreamChunk {
+            candidates: Some(vec![GoogleCandidate {
+                content: Some(GoogleContent {
+                    parts: Some(vec![GooglePart { text: Some(text.to_string()) }]),
+                }),
+                finish_reason: finish.map(str::to_string),
+            }]),
+            usage_metadata: None,
+        };
+        let qwen = |text: &str| QwenStreamChunk {
+            output: QwenStreamOutput { text: Some(text.to_string()), finish_reason: None },
+            request_id: "req-1".to_string(),
+            usage: None,
+        };
+
+        let mut role = FirstChunkRole::default();
+        let google_chunks: Vec<StreamChunk> = [google("Satu", None), google(" dua", None), google(" tiga", Some("STOP"))]
+            .iter()
+            .map(|chunk| {
+                let mut chunk = StreamHandler::transform_google_chunk(chunk, "gemini-pro").unwrap();
+                role.apply(&mut chunk);
+                chunk
+            })
+            .collect();
+        assert_eq!(roles(&google_chunks), vec![Some("assistant"), None, None]);
+        assert_eq!(google_chunks[2].choices[0].delta.content.as_deref(), Some(" tiga"));
+
+        let mut role = FirstChunkRole::default();
+        let qwen_chunks: Vec<StreamChunk> = ["Satu", " dua", " tiga"]
+            .iter()
+            .map(|text| {
+                let mut chunk = StreamHandler::transform_qwen_chunk(&qwen(text), "qwen-turbo").unwrap();
+                role.apply(&mut chunk);
+                chunk
+            })
+            .collect();
+        assert_eq!(roles(&qwen_chunks), vec![Some("assistant"), None, None]);
+
+        // Later deltas serialize without a role key at all
+        let later = serde_json::to_value(&qwen_chunks[1].choices[0].delta).unwrap();
+        assert_eq!(later, serde_json::json!({ "content": " dua" }));
+    }
+
+    #[test]
+    fn test_extract_openai_usage() {
+        let data = r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","choices":[],"usage":{"prompt_tokens":12,"completion_tokens":30,"total_tokens":42}}"#;
+        let usage = StreamHandler::extract_openai_usage(data).unwrap();
+        assert_eq!(usage.prompt_tokens, 12);
+        assert_eq!(usage.completion_tokens, 30);
+        assert_eq!(usage.total_tokens, 42);
+
+        let content = r#"{"id":"chatcmpl-1","choices":[{"index":0,"delta":{"content":"Hi"}}],"usage":null}"#;
+        assert!(StreamHandler::extract_openai_usage(content).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_openai_passthrough_captures_usage_chunk() {
+        use futures::StreamExt;
+        use std::sync::{Arc, Mutex};
+
+        let content = r#"{"id":"chatcmpl-1","choices":[{"index":0,"delta":{"content":"Hello"}}],"usage":null}"#;
+        let usage_chunk = r#"{"id":"chatcmpl-1","choices":[],"usage":{"prompt_tokens":5,"completion_tokens":7,"total_tokens":12}}"#;
+        let sse = format!("data: {}\n\ndata: {}\n\ndata: [DONE]\n\n", content, usage_chunk);
+
+        // Split mid-event to exercise buffering across network chunks
+        let (first, second) = sse.split_at(40);
+        let bytes = futures::stream::iter(vec![
+            Ok::<_, std::io::Error>(first.as_bytes().to_vec()),
+            Ok(second.as_bytes().to_vec()),
+        ]);
+
+        let captured = Arc::new(Mutex::new(None));
+        let sink = captured.clone();
+        let payloads: Vec<String> = StreamHandler::openai_passthrough(bytes, false, StreamUsage::new(0, move |usage| {
+            *sink.lock().unwrap() = Some(usage);
+        }))
+        .collect()
+        .await;
+
+        // Chunks are forwarded unchanged, including the usage chunk
+        assert_eq!(payloads, vec![content.to_string(), usage_chunk.to_string(), "[DONE]".to_string()]);
+
+        let usage = captured.lock().unwrap().clone().expect("usage captured");
+        assert_eq!(usage.prompt_tokens, 5);
+        assert_eq!(usage.completion_tokens, 7);
+        assert_eq!(usage.total_tokens, 12);
+    }
+
+    type CapturedUsage = std::sync::Arc<std::sync::Mutex<Option<Usage>>>;
+
+    /// A usage callback and the usage it received, if any
+    fn usage_sink() -> (StreamUsage<impl FnOnce(Usage)>, CapturedUsage) {
+        let captured = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let sink = captured.clone();
+        let usage = StreamUsage::new(20, move |usage| {
+            let mut slot = sink.lock().unwrap();
+            assert!(slot.is_none(), "usage reported twice");
+            *slot = Some(usage);
+        });
+        (usage, captured)
+    }
+
+    #[tokio::test]
+    async fn test_openai_passthrough_estimates_usage_without_usage_chunk() {
+        use futures::StreamExt;
+
+        let content = r#"{"id":"chatcmpl-1","choices":[{"index":0,"delta":{"content":"Hello there"}}]}"#;
+        let sse = format!("data: {}\n\ndata: [DONE]\n\n", content);
+        let bytes = futures::stream::iter(vec![Ok::<_, std::io::Error>(sse.into_bytes())]);
+        let (usage, captured) = usage_sink();
+
+        StreamHandler::openai_passthrough(bytes, false, usage).collect::<Vec<_>>().await;
+
+        let usage = captured.lock().unwrap().clone().expect("usage logged");
+        assert_eq!(usage.prompt_tokens, 20);
+        assert_eq!(usage.completion_tokens, TokenCounter::estimate_tokens("Hello there"));
+    }
+
+    #[tokio::test]
+    async fn test_google_stream_reports_usage_metadata() {
+        use futures::StreamExt;
+
+        let events = [
+            r#"{"candidates":[{"content":{"parts":[{"text":"Halo"}]}}],"usageMetadata":{"promptTokenCount":8}}"#,
+            r#"{"candidates":[{"content":{"parts":[{"text":" dunia"}]},"finishReason":"STOP"}],"usageMetadata":{"promptTokenCount":8,"candidatesTokenCount":3,"totalTokenCount":11}}"#,
+        ];
+        let sse: String = events.iter().map(|data| format!("data: {}\r\n\r\n", data)).collect();
+        let bytes = futures::stream::iter(vec![Ok::<_, std::io::Error>(sse.into_bytes())]);
+        let (usage, captured) = usage_sink();
+
+        let chunks: Vec<String> = StreamHandler::google_stream(bytes, "gemini-1.5-flash".to_string(), usage).collect().await;
+
+        assert_eq!(chunks.len(), 2);
+        let usage = captured.lock().unwrap().clone().expect("usage logged");
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (8, 3, 11));
+    }
+
+    #[tokio::test]
+    async fn test_qwen_stream_reports_final_usage() {
+        use futures::StreamExt;
+
+        let events = [
+            r#"{"output":{"text":"Halo","finish_reason":"null"},"usage":{"input_tokens":12,"output_tokens":1},"request_id":"req-1"}"#,
+            r#"{"output":{"text":" dunia","finish_reason":"stop"},"usage":{"input_tokens":12,"output_tokens":2,"total_tokens":14},"request_id":"req-1"}"#,
+        ];
+        let sse: String = events
+            .iter()
+            .enumerate()
+            .map(|(id, data)| format!("id:{}\nevent:result\ndata: {}\n\n", id + 1, data))
+            .collect();
+        let bytes = futures::stream::iter(vec![Ok::<_, std::io::Error>(sse.into_bytes())]);
+        let (usage, captured) = usage_sink();
+
+        let chunks: Vec<String> = StreamHandler::qwen_stream(bytes, "qwen-turbo".to_string(), usage).collect().await;
+
+        assert_eq!(chunks.len(), 2);
+        let usage = captured.lock().unwrap().clone().expect("usage logged");
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (12, 2));
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_logs_usage_so_far() {
+        use futures::StreamExt;
+
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg_4","model":"claude-3-haiku-20240307","usage":{"input_tokens":30,"output_tokens":1}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Once upon a time"}}"#,
+        ];
+        let sse: String = events.iter().map(|data| format!("data: {}\n\n", data)).collect();
+        // The upstream keeps the stream open; the client goes away first
+        let bytes = futures::stream::iter(vec![Ok::<_, std::io::Error>(sse.into_bytes())])
+            .chain(futures::stream::pending());
+        let (usage, captured) = usage_sink();
+
+        let mut stream = Box::pin(StreamHandler::anthropic_stream(bytes, "claude-3-haiku-20240307".to_string(), false, usage));
+        loop {
+            let chunk: StreamChunk = serde_json::from_str(&stream.next().await.unwrap()).unwrap();
+            if chunk.choices[0].delta.content.as_deref() == Some("Once upon a time") {
+                break;
+            }
+        }
+        assert!(captured.lock().unwrap().is_none());
+
+        drop(stream);
+
+        let usage = captured.lock().unwrap().clone().expect("usage logged on disconnect");
+        assert_eq!(usage.prompt_tokens, 30);
+        assert_eq!(usage.completion_tokens, TokenCounter::estimate_tokens("Once upon a time"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_ends_in_progress_stream() {
+        use futures::StreamExt;
+
+        let data = r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{"content":"Hello"}}]}"#;
+        // The upstream never finishes on its own
+        let bytes = futures::stream::iter(vec![Ok::<_, std::io::Error>(format!("data: {}\n\n", data).into_bytes())])
+            .chain(futures::stream::pending());
+        let (usage, captured) = usage_sink();
+        let shutdown = CancellationToken::new();
+
+        let payloads = StreamHandler::openai_passthrough(bytes, false, usage);
+        let mut stream = Box::pin(StreamHandler::until_shutdown(payloads, shutdown.clone()));
+        assert_eq!(stream.next().await.unwrap(), data);
+
+        shutdown.cancel();
+
+        let error: serde_json::Value = serde_json::from_str(&stream.next().await.unwrap()).unwrap();
+        assert_eq!(error["error"]["code"], SHUTDOWN_CODE);
+        assert!(stream.next().await.is_none());
+        // The upstream was dropped, so its usage is already logged
+        assert_eq!(captured.lock().unwrap().clone().unwrap().completion_tokens, TokenCounter::estimate_tokens("Hello"));
+    }
 }
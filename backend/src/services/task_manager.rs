@@ -0,0 +1,111 @@
+//! Tracking for fire-and-forget background tasks.
+//!
+//! Work spawned off the request path (usage logs, error records, transcripts,
+//! webhooks) registers with the `TaskManager` in `AppState` so graceful
+//! shutdown can wait for it instead of dropping it mid-write. Long-running
+//! workers (refresh loops, cleanup jobs) never finish and are not tracked.
+//!
+//! Configuration:
+//! - `SHUTDOWN_TIMEOUT_SECS`: how long shutdown waits for outstanding tasks (default `10`)
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::task::JoinSet;
+
+/// Default time shutdown waits for outstanding tasks
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Background tasks that graceful shutdown waits for
+#[derive(Debug, Clone, Default)]
+pub struct TaskManager {
+    tasks: Arc<Mutex<JoinSet<()>>>,
+}
+
+impl TaskManager {
+    /// Spawn a tracked background task
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        // Drop finished tasks so the set only holds outstanding work
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(task);
+    }
+
+    /// Number of tasks that haven't finished yet
+    pub fn outstanding(&self) -> usize {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        while tasks.try_join_next().is_some() {}
+        tasks.len()
+    }
+
+    /// Wait up to `timeout` for outstanding tasks; any still running after
+    /// that are aborted. Returns how many were aborted.
+    pub async fn shutdown(&self, timeout: Duration) -> usize {
+        let mut tasks = std::mem::take(&mut *self.tasks.lock().unwrap_or_else(|e| e.into_inner()));
+        let pending = tasks.len();
+        if pending > 0 {
+            tracing::info!(pending, "Waiting for background tasks to finish");
+        }
+
+        let drained = tokio::time::timeout(timeout, async {
+            while let Some(result) = tasks.join_next().await {
+                if let Err(e) = result {
+                    tracing::warn!("Background task failed: {}", e);
+                }
+            }
+        })
+        .await;
+
+        if drained.is_ok() {
+            return 0;
+        }
+        let aborted = tasks.len();
+        tracing::warn!(aborted, "Background tasks still running at shutdown timeout, aborting");
+        tasks.shutdown().await;
+        aborted
+    }
+}
+
+/// Read the shutdown wait from `SHUTDOWN_TIMEOUT_SECS`
+pub fn shutdown_timeout_from_env() -> Duration {
+    std::env::var("SHUTDOWN_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[tokio::test]
+    async fn test_shutdown_awaits_outstanding_tasks() {
+        let tasks = TaskManager::default();
+        let finished = Arc::new(AtomicBool::new(false));
+        let flag = finished.clone();
+        tasks.spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            flag.store(true, Ordering::SeqCst);
+        });
+        assert_eq!(tasks.outstanding(), 1);
+
+        assert_eq!(tasks.shutdown(Duration::from_secs(5)).await, 0);
+        assert!(finished.load(Ordering::SeqCst));
+        assert_eq!(tasks.outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_aborts_tasks_past_timeout() {
+        let tasks = TaskManager::default();
+        tasks.spawn(std::future::pending());
+        tasks.spawn(async {});
+
+        assert_eq!(tasks.shutdown(Duration::from_millis(20)).await, 1);
+    }
+}
@@ -0,0 +1,392 @@
+//! Opt-in conversation transcripts for audit.
+//!
+//! Proxy keys created with `store_transcripts` keep an audit trail of each
+//! chat completion: the request messages and the response, encrypted with the
+//! master key in `conversation_transcripts`. Nothing is stored unless both the
+//! key opted in and content logging is enabled. Transcripts are readable only
+//! by the owning user and admins, and are deleted after the retention window.
+//!
+//! Configuration:
+//! - `LOG_REQUEST_CONTENT`: master switch for storing message content (default `false`)
+//! - `TRANSCRIPT_RETENTION_DAYS`: how long transcripts are kept (default `30`)
+
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::services::task_manager::TaskManager;
+use crate::services::transformers::Provider;
+use crate::utils::encryption::{EncryptedData, EncryptionError, EncryptionUtils};
+
+/// Default transcript retention
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// Interval between expired transcript cleanups
+pub const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Transcript errors
+#[derive(Debug, thiserror::Error)]
+pub enum TranscriptError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Encryption error: {0}")]
+    Encryption(#[from] EncryptionError),
+    #[error("Stored transcript is malformed")]
+    Malformed,
+}
+
+/// Transcript storage configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptConfig {
+    pub content_logging: bool,
+    pub retention: Duration,
+}
+
+impl Default for TranscriptConfig {
+    fn default() -> Self {
+        Self {
+            content_logging: false,
+            retention: DEFAULT_RETENTION,
+        }
+    }
+}
+
+impl TranscriptConfig {
+    /// Load configuration from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load configuration using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let defaults = Self::default();
+        Self {
+            content_logging: lookup("LOG_REQUEST_CONTENT")
+                .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(defaults.content_logging),
+            retention: lookup("TRANSCRIPT_RETENTION_DAYS")
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(|days| Duration::from_secs(days * 24 * 3600))
+                .unwrap_or(defaults.retention),
+        }
+    }
+
+    /// Decide whether to store a transcript for a key
+    pub fn should_store(&self, key_opted_in: bool) -> bool {
+        self.content_logging && key_opted_in
+    }
+}
+
+/// A completion to record
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    pub user_id: Uuid,
+    pub proxy_key_id: Uuid,
+    pub provider: Provider,
+    pub model: String,
+    pub messages: serde_json::Value,
+    pub response: Option<serde_json::Value>,
+    pub status_code: u16,
+}
+
+/// The encrypted part of a transcript
+#[derive(Debug, Serialize, Deserialize)]
+struct TranscriptContent {
+    messages: serde_json::Value,
+    response: Option<serde_json::Value>,
+}
+
+/// Transcript list item (content omitted)
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptSummary {
+    pub id: Uuid,
+    pub proxy_key_id: Option<Uuid>,
+    pub provider: String,
+    pub model: String,
+    pub status_code: i32,
+    pub created_at: String,
+}
+
+/// A decrypted transcript
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredTranscript {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub proxy_key_id: Option<Uuid>,
+    pub provider: String,
+    pub model: String,
+    pub status_code: i32,
+    pub messages: serde_json::Value,
+    pub response: Option<serde_json::Value>,
+    pub created_at: String,
+}
+
+/// Encrypt a transcript's messages and response
+fn encrypt(encryption: &EncryptionUtils, transcript: &Transcript) -> Result<EncryptedData, TranscriptError> {
+    let content = serde_json::to_string(&TranscriptContent {
+        messages: transcript.messages.clone(),
+        response: transcript.response.clone(),
+    })
+    .map_err(|_| TranscriptError::Malformed)?;
+    Ok(encryption.encrypt(&content)?)
+}
+
+async fn insert(pool: &PgPool, transcript: &Transcript, encrypted: &EncryptedData) -> Result<Uuid, sqlx::Error> {
+    let row = sqlx::query(
+        r#"
+        INSERT INTO conversation_transcripts
+            (user_id, proxy_key_id, provider, model, status_code, content_ciphertext, content_iv, content_auth_tag)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id
+        "#,
+    )
+    .bind(transcript.user_id)
+    .bind(transcript.proxy_key_id)
+    .bind(transcript.provider.name().to_lowercase())
+    .bind(&transcript.model)
+    .bind(transcript.status_code as i32)
+    .bind(&encrypted.ciphertext)
+    .bind(encrypted.iv.to_vec())
+    .bind(encrypted.auth_tag.to_vec())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.get("id"))
+}
+
+/// Encrypt and store a transcript
+pub async fn save(
+    pool: &PgPool,
+    encryption: &EncryptionUtils,
+    transcript: &Transcript,
+) -> Result<Uuid, TranscriptError> {
+    let encrypted = encrypt(encryption, transcript)?;
+    Ok(insert(pool, transcript, &encrypted).await?)
+}
+
+/// Store a transcript in the background without blocking the response
+pub fn save_async(tasks: &TaskManager, pool: PgPool, encryption: EncryptionUtils, transcript: Transcript) {
+    tasks.spawn(async move {
+        if let Err(e) = save(&pool, &encryption, &transcript).await {
+            tracing::warn!(user_id = %transcript.user_id, "Failed to store transcript: {}", e);
+        }
+    });
+}
+
+/// List a user's transcripts, newest first
+pub async fn list_for_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<TranscriptSummary>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, proxy_key_id, provider, model, status_code, created_at
+        FROM conversation_transcripts
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        LIMIT 100
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| TranscriptSummary {
+            id: row.get("id"),
+            proxy_key_id: row.get("proxy_key_id"),
+            provider: row.get("provider"),
+            model: row.get("model"),
+            status_code: row.get("status_code"),
+            created_at: row
+                .get::<chrono::DateTime<chrono::Utc>, _>("created_at")
+                .to_rfc3339(),
+        })
+        .collect())
+}
+
+/// Load and decrypt a transcript. With `owner` set, transcripts belonging
+/// to other users are not found; admins pass `None`.
+pub async fn get(
+    pool: &PgPool,
+    encryption: &EncryptionUtils,
+    id: Uuid,
+    owner: Option<Uuid>,
+) -> Result<Option<StoredTranscript>, TranscriptError> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, user_id, proxy_key_id, provider, model, status_code, created_at,
+            content_ciphertext, content_iv, content_auth_tag
+        FROM conversation_transcripts
+        WHERE id = $1 AND ($2::uuid IS NULL OR user_id = $2)
+        "#,
+    )
+    .bind(id)
+    .bind(owner)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let encrypted = EncryptedData {
+        ciphertext: row.get("content_ciphertext"),
+        iv: row
+            .get::<Vec<u8>, _>("content_iv")
+            .try_into()
+            .map_err(|_| TranscriptError::Malformed)?,
+        auth_tag: row
+            .get::<Vec<u8>, _>("content_auth_tag")
+            .try_into()
+            .map_err(|_| TranscriptError::Malformed)?,
+    };
+    let content: TranscriptContent =
+        serde_json::from_str(&encryption.decrypt(&encrypted)?).map_err(|_| TranscriptError::Malformed)?;
+
+    Ok(Some(StoredTranscript {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        proxy_key_id: row.get("proxy_key_id"),
+        provider: row.get("provider"),
+        model: row.get("model"),
+        status_code: row.get("status_code"),
+        messages: content.messages,
+        response: content.response,
+        created_at: row
+            .get::<chrono::DateTime<chrono::Utc>, _>("created_at")
+            .to_rfc3339(),
+    }))
+}
+
+/// Delete transcripts older than the retention window
+pub async fn cleanup_expired(pool: &PgPool, retention: Duration) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "DELETE FROM conversation_transcripts WHERE created_at < NOW() - make_interval(secs => $1)",
+    )
+    .bind(retention.as_secs() as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Spawn background task that periodically deletes expired transcripts
+pub fn spawn_cleanup_worker(pool: PgPool, retention: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match cleanup_expired(&pool, retention).await {
+                Ok(deleted) if deleted > 0 => {
+                    tracing::info!(deleted, "Deleted expired conversation transcripts");
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Failed to clean up conversation transcripts: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_off_by_default() {
+        let config = TranscriptConfig::from_lookup(|_| None);
+        assert_eq!(config, TranscriptConfig::default());
+        assert!(!config.should_store(true));
+        assert!(!config.should_store(false));
+    }
+
+    #[test]
+    fn test_requires_content_logging_and_key_opt_in() {
+        let config = TranscriptConfig::from_lookup(|key| match key {
+            "LOG_REQUEST_CONTENT" => Some("true".to_string()),
+            "TRANSCRIPT_RETENTION_DAYS" => Some("7".to_string()),
+            _ => None,
+        });
+
+        assert!(config.should_store(true));
+        assert!(!config.should_store(false));
+        assert_eq!(config.retention, Duration::from_secs(7 * 24 * 3600));
+    }
+
+    // Database-backed tests (see test_support for how to run them)
+
+    use crate::models::PlanTier;
+    use crate::test_support::insert_user;
+
+    fn transcript(user_id: Uuid, proxy_key_id: Uuid) -> Transcript {
+        Transcript {
+            user_id,
+            proxy_key_id,
+            provider: Provider::OpenAI,
+            model: "gpt-4o-mini".to_string(),
+            messages: serde_json::json!([{"role": "user", "content": "Rahasia dagang kami"}]),
+            response: Some(serde_json::json!({"choices": [{"message": {"content": "Baik"}}]})),
+            status_code: 200,
+        }
+    }
+
+    async fn insert_proxy_key(pool: &PgPool, user_id: Uuid) -> Uuid {
+        sqlx::query_scalar(
+            r#"
+            INSERT INTO proxy_api_keys (user_id, key_hash, key_prefix, name)
+            VALUES ($1, 'hash', 'wbr_test', 'audit')
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_stored_encrypted_and_owner_only(pool: PgPool) {
+        let encryption = EncryptionUtils::from_key(&[5u8; 32]).unwrap();
+        let owner = insert_user(&pool, "audit@example.com", PlanTier::Pro).await;
+        let other = insert_user(&pool, "other@example.com", PlanTier::Pro).await;
+        let key_id = insert_proxy_key(&pool, owner).await;
+
+        let id = save(&pool, &encryption, &transcript(owner, key_id)).await.unwrap();
+
+        // Content is not stored in plaintext
+        let ciphertext: Vec<u8> =
+            sqlx::query_scalar("SELECT content_ciphertext FROM conversation_transcripts WHERE id = $1")
+                .bind(id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert!(!String::from_utf8_lossy(&ciphertext).contains("Rahasia"));
+
+        let stored = get(&pool, &encryption, id, Some(owner)).await.unwrap().unwrap();
+        assert_eq!(stored.messages[0]["content"], "Rahasia dagang kami");
+        assert_eq!(stored.proxy_key_id, Some(key_id));
+        assert_eq!(list_for_user(&pool, owner).await.unwrap().len(), 1);
+
+        // Other users can't see it; admins can
+        assert!(get(&pool, &encryption, id, Some(other)).await.unwrap().is_none());
+        assert!(list_for_user(&pool, other).await.unwrap().is_empty());
+        assert!(get(&pool, &encryption, id, None).await.unwrap().is_some());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_keys_default_to_not_storing(pool: PgPool) {
+        let user_id = insert_user(&pool, "default@example.com", PlanTier::Pro).await;
+        let key_id = insert_proxy_key(&pool, user_id).await;
+
+        let opted_in: bool = sqlx::query_scalar("SELECT store_transcripts FROM proxy_api_keys WHERE id = $1")
+            .bind(key_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(!opted_in);
+    }
+}
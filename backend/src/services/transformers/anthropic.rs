@@ -5,10 +5,13 @@
 //! Transforms between OpenAI-compatible format and Anthropic Messages API format.
 
 use serde::{Deserialize, Serialize};
-use chrono::Utc;
 use uuid::Uuid;
 
-use super::{ChatCompletionRequest, ChatCompletionResponse, Choice, Message, Usage};
+use super::{
+    created_timestamp, reconcile_usage, response_id, ChatCompletionRequest, ChatCompletionResponse,
+    Choice, ContentPart, Message, MessageContent, ModelMetadata, Provider, ResponseFormat, Tool, ToolCall,
+    ToolChoice,
+};
 
 /// Anthropic Messages API request format
 /// https://docs.anthropic.com/en/api/messages
@@ -27,12 +30,127 @@ pub struct AnthropicRequest {
     pub stop_sequences: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<AnthropicTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<AnthropicToolChoice>,
+}
+
+/// Tool definition (client tools, and the forced tool that emulates
+/// `response_format: json_schema`)
+#[derive(Debug, Clone, Serialize)]
+pub struct AnthropicTool {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub input_schema: serde_json::Value,
+}
+
+/// How Claude may use tools (`auto`, `any`, or `tool` with a name)
+#[derive(Debug, Clone, Serialize)]
+pub struct AnthropicToolChoice {
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl AnthropicTool {
+    fn from_tool(tool: &Tool) -> Self {
+        Self {
+            name: tool.function.name.clone(),
+            description: tool.function.description.clone(),
+            input_schema: tool
+                .function
+                .parameters
+                .clone()
+                .unwrap_or_else(|| serde_json::json!({ "type": "object" })),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnthropicMessage {
     pub role: String,
-    pub content: String,
+    pub content: AnthropicMessageContent,
+}
+
+/// Message content: a bare string, or content blocks when it has images
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AnthropicMessageContent {
+    Text(String),
+    Blocks(Vec<AnthropicContentBlock>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicContentBlock {
+    Text { text: String },
+    Image { source: AnthropicImageSource },
+    /// Tool call from an earlier assistant turn
+    ToolUse { id: String, name: String, input: serde_json::Value },
+    /// Result of a tool call, sent in a user turn
+    ToolResult { tool_use_id: String, content: String },
+}
+
+impl AnthropicContentBlock {
+    fn tool_use(call: &ToolCall) -> Self {
+        AnthropicContentBlock::ToolUse {
+            id: call.id.clone(),
+            name: call.function.name.clone(),
+            // Arguments that aren't valid JSON are sent as no arguments
+            input: serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| serde_json::json!({})),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+impl From<&MessageContent> for AnthropicMessageContent {
+    fn from(content: &MessageContent) -> Self {
+        match content {
+            MessageContent::Text(text) => AnthropicMessageContent::Text(text.clone()),
+            MessageContent::Parts(parts) => AnthropicMessageContent::Blocks(
+                parts
+                    .iter()
+                    .map(|part| match part {
+                        ContentPart::Text { text } => AnthropicContentBlock::Text { text: text.clone() },
+                        ContentPart::ImageUrl { image_url } => AnthropicContentBlock::Image {
+                            source: match image_url.data() {
+                                Some((media_type, data)) => AnthropicImageSource::Base64 {
+                                    media_type: media_type.to_string(),
+                                    data: data.to_string(),
+                                },
+                                None => AnthropicImageSource::Url { url: image_url.url.clone() },
+                            },
+                        },
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl AnthropicMessageContent {
+    /// Content as blocks; empty text has none
+    fn into_blocks(self) -> Vec<AnthropicContentBlock> {
+        match self {
+            AnthropicMessageContent::Text(text) if text.is_empty() => Vec::new(),
+            AnthropicMessageContent::Text(text) => vec![AnthropicContentBlock::Text { text }],
+            AnthropicMessageContent::Blocks(blocks) => blocks,
+        }
+    }
+}
+
+impl PartialEq<&str> for AnthropicMessageContent {
+    fn eq(&self, other: &&str) -> bool {
+        matches!(self, AnthropicMessageContent::Text(text) if text == other)
+    }
 }
 
 /// Anthropic Messages API response format
@@ -41,6 +159,7 @@ pub struct AnthropicResponse {
     pub id: String,
     pub r#type: String,
     pub role: String,
+    #[serde(default)]
     pub content: Vec<AnthropicContent>,
     pub model: String,
     pub stop_reason: Option<String>,
@@ -51,7 +170,17 @@ pub struct AnthropicResponse {
 #[derive(Debug, Clone, Deserialize)]
 pub struct AnthropicContent {
     pub r#type: String,
+    #[serde(default)]
     pub text: String,
+    /// Tool call id (`tool_use` blocks)
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Called tool's name (`tool_use` blocks)
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Tool call arguments (`tool_use` blocks)
+    #[serde(default)]
+    pub input: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -73,73 +202,190 @@ impl AnthropicTransformer {
         let mut messages: Vec<AnthropicMessage> = Vec::new();
 
         for msg in &request.messages {
-            if msg.role == "system" {
+            match msg.role.as_str() {
                 // Anthropic requires system as separate parameter
-                system_message = Some(msg.content.clone());
-            } else {
-                messages.push(AnthropicMessage {
-                    role: msg.role.clone(),
-                    content: msg.content.clone(),
-                });
+                "system" => system_message = Some(msg.content.text().into_owned()),
+                // Tool results go back in a user turn; consecutive results share one
+                "tool" => {
+                    let result = AnthropicContentBlock::ToolResult {
+                        tool_use_id: msg.tool_call_id.clone().unwrap_or_default(),
+                        content: msg.content.text().into_owned(),
+                    };
+                    match messages.last_mut() {
+                        Some(AnthropicMessage { role, content: AnthropicMessageContent::Blocks(blocks) })
+                            if role == "user"
+                                && blocks.iter().all(|b| matches!(b, AnthropicContentBlock::ToolResult { .. })) =>
+                        {
+                            blocks.push(result)
+                        }
+                        _ => messages.push(AnthropicMessage {
+                            role: "user".to_string(),
+                            content: AnthropicMessageContent::Blocks(vec![result]),
+                        }),
+                    }
+                }
+                _ => {
+                    let mut content = AnthropicMessageContent::from(&msg.content);
+                    if let Some(tool_calls) = msg.tool_calls.as_ref().filter(|calls| !calls.is_empty()) {
+                        let mut blocks = content.into_blocks();
+                        blocks.extend(tool_calls.iter().map(AnthropicContentBlock::tool_use));
+                        content = AnthropicMessageContent::Blocks(blocks);
+                    }
+                    messages.push(AnthropicMessage { role: msg.role.clone(), content });
+                }
             }
         }
 
         // Requirement 1.3: max_tokens is required for Anthropic
-        // Default to 4096 if not specified
-        let max_tokens = request.max_tokens.unwrap_or(4096);
+        // Default to 4096 if not specified, and never exceed the model's cap
+        let metadata = ModelMetadata::for_model(&request.model);
+        let max_tokens = metadata
+            .resolve_max_tokens(&request.model, Some(request.max_tokens.unwrap_or(4096)))
+            .unwrap_or(4096);
+        let (temperature, top_p) = Provider::Anthropic.sampling_policy().apply(
+            &request.model,
+            metadata.resolve_temperature(request.temperature),
+            request.top_p,
+        );
+
+        // Client tools; `tool_choice: "none"` withholds them entirely
+        let choice = request.tool_choice.as_ref().map(ToolChoice::from_value);
+        let mut tools: Vec<AnthropicTool> = match choice {
+            Some(ToolChoice::None) => Vec::new(),
+            _ => request.tools.iter().flatten().map(AnthropicTool::from_tool).collect(),
+        };
+        let mut tool_choice = match choice {
+            _ if tools.is_empty() => None,
+            Some(ToolChoice::Required) => Some(AnthropicToolChoice { r#type: "any".to_string(), name: None }),
+            Some(ToolChoice::Function(name)) => Some(AnthropicToolChoice { r#type: "tool".to_string(), name: Some(name) }),
+            Some(ToolChoice::Auto) | Some(ToolChoice::None) | None => None,
+        };
+
+        // Emulate structured outputs with a forced tool call whose input is the schema
+        if let Some(ResponseFormat::JsonSchema { json_schema }) = &request.response_format {
+            tools.push(AnthropicTool {
+                name: json_schema.name.clone(),
+                description: json_schema.description.clone(),
+                input_schema: json_schema
+                    .schema
+                    .clone()
+                    .unwrap_or_else(|| serde_json::json!({ "type": "object" })),
+            });
+            tool_choice = Some(AnthropicToolChoice {
+                r#type: "tool".to_string(),
+                name: Some(json_schema.name.clone()),
+            });
+        }
+        let tools = if tools.is_empty() { None } else { Some(tools) };
 
         AnthropicRequest {
             model: request.model.clone(),
             max_tokens,
             system: system_message,
             messages,
-            temperature: request.temperature,
-            top_p: request.top_p,
+            temperature,
+            top_p,
             stop_sequences: request.stop.clone(),
             stream: if request.stream { Some(true) } else { None },
+            tools,
+            tool_choice,
         }
     }
 
-    /// Transform Anthropic response to OpenAI-compatible format
+    /// Transform Anthropic response to OpenAI-compatible format.
+    /// `tool_use` blocks become `tool_calls` on the message.
     /// Requirement: 1.4
     pub fn transform_response(response: AnthropicResponse) -> ChatCompletionResponse {
-        // Combine all content blocks into single message
-        let content = response
+        let tool_calls: Vec<ToolCall> = response
+            .content
+            .iter()
+            .filter(|c| c.r#type == "tool_use")
+            .map(|c| {
+                ToolCall::new(
+                    c.id.clone().unwrap_or_default(),
+                    c.name.clone().unwrap_or_default(),
+                    c.input.as_ref().unwrap_or(&serde_json::Value::Null),
+                )
+            })
+            .collect();
+        let content = Self::text_content(&response);
+        let finish_reason = Self::map_stop_reason(response.stop_reason.as_deref(), "tool_calls");
+        let tool_calls = if tool_calls.is_empty() { None } else { Some(tool_calls) };
+
+        Self::into_completion(response, content, tool_calls, finish_reason)
+    }
+
+    /// Transform a response to a `response_format: json_schema` request,
+    /// where the forced tool call carries the structured output as its input
+    pub fn transform_structured_response(response: AnthropicResponse) -> ChatCompletionResponse {
+        let tool_output = response
+            .content
+            .iter()
+            .find(|c| c.r#type == "tool_use")
+            .and_then(|c| c.input.as_ref())
+            .map(|input| input.to_string());
+
+        let content = tool_output.unwrap_or_else(|| Self::text_content(&response));
+        let finish_reason = Self::map_stop_reason(response.stop_reason.as_deref(), "stop");
+
+        Self::into_completion(response, content, None, finish_reason)
+    }
+
+    /// Combine all text blocks into a single message
+    fn text_content(response: &AnthropicResponse) -> String {
+        response
             .content
             .iter()
             .filter(|c| c.r#type == "text")
             .map(|c| c.text.clone())
             .collect::<Vec<_>>()
-            .join("");
-
-        // Map Anthropic stop_reason to OpenAI finish_reason
-        let finish_reason = response.stop_reason.map(|reason| {
-            match reason.as_str() {
-                "end_turn" => "stop".to_string(),
-                "max_tokens" => "length".to_string(),
-                "stop_sequence" => "stop".to_string(),
-                other => other.to_string(),
-            }
-        });
+            .join("")
+    }
+
+    /// Map Anthropic stop_reason to OpenAI finish_reason
+    fn map_stop_reason(reason: Option<&str>, tool_use: &str) -> Option<String> {
+        reason.map(|reason| match reason {
+            "end_turn" => "stop".to_string(),
+            "max_tokens" => "length".to_string(),
+            "stop_sequence" => "stop".to_string(),
+            "tool_use" => tool_use.to_string(),
+            other => other.to_string(),
+        })
+    }
+
+    fn into_completion(
+        response: AnthropicResponse,
+        content: String,
+        tool_calls: Option<Vec<ToolCall>>,
+        finish_reason: Option<String>,
+    ) -> ChatCompletionResponse {
 
         ChatCompletionResponse {
-            id: format!("chatcmpl-{}", response.id),
+            id: response_id(),
             object: "chat.completion".to_string(),
-            created: Utc::now().timestamp(),
+            created: created_timestamp(),
             model: response.model,
             choices: vec![Choice {
                 index: 0,
                 message: Message {
                     role: "assistant".to_string(),
-                    content,
+                    content: MessageContent::Text(content),
+                    tool_calls,
+                    tool_call_id: None,
+                    name: None,
                 },
                 finish_reason,
+                logprobs: None,
             }],
-            usage: Usage {
-                prompt_tokens: response.usage.input_tokens,
-                completion_tokens: response.usage.output_tokens,
-                total_tokens: response.usage.input_tokens + response.usage.output_tokens,
-            },
+            usage: reconcile_usage(
+                Provider::Anthropic,
+                response.usage.input_tokens,
+                response.usage.output_tokens,
+                None,
+            ),
+            system_fingerprint: None,
+            service_tier: None,
+            provider_request_id: Some(response.id),
         }
     }
 
@@ -179,6 +425,7 @@ impl AnthropicTransformer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::services::transformers::JsonSchemaFormat;
 
     // ============================================================
     // Unit Tests for Anthropic Transformer (Task 1.1, 1.2)
@@ -192,7 +439,10 @@ mod tests {
             messages: vec![
                 Message {
                     role: "user".to_string(),
-                    content: "Hello, Claude!".to_string(),
+                    content: "Hello, Claude!".into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
                 },
             ],
             temperature: Some(0.7),
@@ -203,6 +453,10 @@ mod tests {
             presence_penalty: None,
             stop: None,
             user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
         };
 
         let anthropic_req = AnthropicTransformer::transform_request(&request);
@@ -222,11 +476,17 @@ mod tests {
             messages: vec![
                 Message {
                     role: "system".to_string(),
-                    content: "You are a helpful assistant.".to_string(),
+                    content: "You are a helpful assistant.".into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
                 },
                 Message {
                     role: "user".to_string(),
-                    content: "Hello!".to_string(),
+                    content: "Hello!".into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
                 },
             ],
             temperature: None,
@@ -237,6 +497,10 @@ mod tests {
             presence_penalty: None,
             stop: None,
             user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
         };
 
         let anthropic_req = AnthropicTransformer::transform_request(&request);
@@ -255,7 +519,10 @@ mod tests {
             model: "claude-3-sonnet-20240229".to_string(),
             messages: vec![Message {
                 role: "user".to_string(),
-                content: "Test".to_string(),
+                content: "Test".into(),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
             }],
             temperature: None,
             max_tokens: None, // Not specified
@@ -265,6 +532,10 @@ mod tests {
             presence_penalty: None,
             stop: None,
             user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
         };
 
         let anthropic_req = AnthropicTransformer::transform_request(&request);
@@ -282,6 +553,9 @@ mod tests {
             content: vec![AnthropicContent {
                 r#type: "text".to_string(),
                 text: "Hello! How can I help you today?".to_string(),
+                id: None,
+                name: None,
+                input: None,
             }],
             model: "claude-3-sonnet-20240229".to_string(),
             stop_reason: Some("end_turn".to_string()),
@@ -304,6 +578,7 @@ mod tests {
         assert_eq!(response.usage.prompt_tokens, 10);
         assert_eq!(response.usage.completion_tokens, 20);
         assert_eq!(response.usage.total_tokens, 30);
+        assert!(response.system_fingerprint.is_none());
     }
 
     #[test]
@@ -315,6 +590,9 @@ mod tests {
             content: vec![AnthropicContent {
                 r#type: "text".to_string(),
                 text: "Truncated response...".to_string(),
+                id: None,
+                name: None,
+                input: None,
             }],
             model: "claude-3-opus-20240229".to_string(),
             stop_reason: Some("max_tokens".to_string()),
@@ -331,6 +609,224 @@ mod tests {
         assert_eq!(response.choices[0].finish_reason, Some("length".to_string()));
     }
 
+    #[test]
+    fn test_transform_request_json_schema_uses_forced_tool() {
+        let request = ChatCompletionRequest {
+            model: "claude-3-5-sonnet-20241022".to_string(),
+            messages: vec![Message { role: "user".to_string(), content: "Weather?".into(), tool_calls: None, tool_call_id: None, name: None }],
+            temperature: None,
+            max_tokens: None,
+            stream: false,
+            top_p: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            stop: None,
+            user: None,
+            response_format: Some(ResponseFormat::JsonSchema {
+                json_schema: JsonSchemaFormat {
+                    name: "weather".to_string(),
+                    description: None,
+                    schema: Some(serde_json::json!({
+                        "type": "object",
+                        "properties": { "city": { "type": "string" } }
+                    })),
+                    strict: None,
+                },
+            }),
+            n: None,
+            tools: None,
+            tool_choice: None,
+        };
+
+        let json = serde_json::to_value(AnthropicTransformer::transform_request(&request)).unwrap();
+
+        assert_eq!(json["tools"][0]["name"], "weather");
+        assert_eq!(json["tools"][0]["input_schema"]["properties"]["city"]["type"], "string");
+        assert_eq!(json["tool_choice"], serde_json::json!({ "type": "tool", "name": "weather" }));
+    }
+
+    #[test]
+    fn test_tool_call_round_trip() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-3-5-sonnet-20241022",
+            "messages": [{ "role": "user", "content": "Weather in Jakarta?" }],
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "description": "Current weather for a city",
+                    "parameters": { "type": "object", "properties": { "city": { "type": "string" } } }
+                }
+            }],
+            "tool_choice": { "type": "function", "function": { "name": "get_weather" } }
+        }))
+        .unwrap();
+
+        let json = serde_json::to_value(AnthropicTransformer::transform_request(&request)).unwrap();
+        assert_eq!(json["tools"][0]["name"], "get_weather");
+        assert_eq!(json["tools"][0]["description"], "Current weather for a city");
+        assert_eq!(json["tools"][0]["input_schema"]["properties"]["city"]["type"], "string");
+        assert_eq!(json["tool_choice"], serde_json::json!({ "type": "tool", "name": "get_weather" }));
+
+        let anthropic_response: AnthropicResponse = serde_json::from_value(serde_json::json!({
+            "id": "msg_tool",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                { "type": "text", "text": "Checking." },
+                { "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": { "city": "Jakarta" } }
+            ],
+            "model": "claude-3-5-sonnet-20241022",
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": { "input_tokens": 30, "output_tokens": 12 }
+        }))
+        .unwrap();
+
+        let response = AnthropicTransformer::transform_response(anthropic_response);
+        let message = &response.choices[0].message;
+        let tool_calls = message.tool_calls.as_ref().unwrap();
+        assert_eq!(message.content, "Checking.");
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id, "toolu_1");
+        assert_eq!(tool_calls[0].r#type, "function");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&tool_calls[0].function.arguments).unwrap(),
+            serde_json::json!({ "city": "Jakarta" })
+        );
+        assert_eq!(response.choices[0].finish_reason, Some("tool_calls".to_string()));
+
+        // The next turn carries the call and its result back, with the
+        // assistant's content null as clients send it
+        let follow_up: crate::routes::proxy::ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-3-5-sonnet-20241022",
+            "messages": [
+                { "role": "user", "content": "Weather in Jakarta?" },
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "get_weather", "arguments": "{\"city\": \"Jakarta\"}" }
+                    }]
+                },
+                { "role": "tool", "tool_call_id": "call_1", "content": "{\"temp_c\": 31}" }
+            ]
+        }))
+        .unwrap();
+        let follow_up: ChatCompletionRequest = follow_up.into();
+        let json = serde_json::to_value(AnthropicTransformer::transform_request(&follow_up)).unwrap();
+        assert_eq!(json["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(json["messages"][1]["role"], "assistant");
+        assert_eq!(
+            json["messages"][1]["content"],
+            serde_json::json!([{ "type": "tool_use", "id": "call_1", "name": "get_weather", "input": { "city": "Jakarta" } }])
+        );
+        assert_eq!(json["messages"][2]["role"], "user");
+        assert_eq!(
+            json["messages"][2]["content"],
+            serde_json::json!([{ "type": "tool_result", "tool_use_id": "call_1", "content": "{\"temp_c\": 31}" }])
+        );
+    }
+
+    #[test]
+    fn test_tool_choice_mapping() {
+        let request_with = |tool_choice: serde_json::Value| -> serde_json::Value {
+            let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+                "model": "claude-3-5-sonnet-20241022",
+                "messages": [{ "role": "user", "content": "Hi" }],
+                "tools": [{ "type": "function", "function": { "name": "lookup" } }],
+                "tool_choice": tool_choice
+            }))
+            .unwrap();
+            serde_json::to_value(AnthropicTransformer::transform_request(&request)).unwrap()
+        };
+
+        let auto = request_with(serde_json::json!("auto"));
+        assert_eq!(auto["tools"][0]["input_schema"], serde_json::json!({ "type": "object" }));
+        assert!(auto.get("tool_choice").is_none());
+        assert_eq!(request_with(serde_json::json!("required"))["tool_choice"], serde_json::json!({ "type": "any" }));
+
+        let none = request_with(serde_json::json!("none"));
+        assert!(none.get("tools").is_none());
+        assert!(none.get("tool_choice").is_none());
+    }
+
+    #[test]
+    fn test_image_parts_become_image_blocks() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-3-5-sonnet-20241022",
+            "messages": [{
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "Compare these" },
+                    { "type": "image_url", "image_url": { "url": "data:image/jpeg;base64,/9j/4AAQ" } },
+                    { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } }
+                ]
+            }]
+        }))
+        .unwrap();
+
+        let json = serde_json::to_value(AnthropicTransformer::transform_request(&request)).unwrap();
+        assert_eq!(
+            json["messages"][0]["content"],
+            serde_json::json!([
+                { "type": "text", "text": "Compare these" },
+                { "type": "image", "source": { "type": "base64", "media_type": "image/jpeg", "data": "/9j/4AAQ" } },
+                { "type": "image", "source": { "type": "url", "url": "https://example.com/cat.png" } }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_structured_response_tool_use_becomes_json_content() {
+        let anthropic_response: AnthropicResponse = serde_json::from_value(serde_json::json!({
+            "id": "msg_789",
+            "type": "message",
+            "role": "assistant",
+            "content": [{
+                "type": "tool_use",
+                "id": "toolu_1",
+                "name": "weather",
+                "input": { "city": "Jakarta" }
+            }],
+            "model": "claude-3-5-sonnet-20241022",
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": { "input_tokens": 12, "output_tokens": 8 }
+        }))
+        .unwrap();
+
+        let response = AnthropicTransformer::transform_structured_response(anthropic_response);
+
+        assert_eq!(response.choices[0].message.content, r#"{"city":"Jakarta"}"#);
+        assert!(response.choices[0].message.tool_calls.is_none());
+        assert_eq!(response.choices[0].finish_reason, Some("stop".to_string()));
+    }
+
+    #[test]
+    fn test_transform_response_empty_content() {
+        let anthropic_response: AnthropicResponse = serde_json::from_value(serde_json::json!({
+            "id": "msg_empty",
+            "type": "message",
+            "role": "assistant",
+            "content": [],
+            "model": "claude-3-haiku-20240307",
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": { "input_tokens": 5, "output_tokens": 0 }
+        }))
+        .unwrap();
+
+        let response = AnthropicTransformer::transform_response(anthropic_response);
+
+        assert_eq!(response.choices.len(), 1);
+        assert_eq!(response.choices[0].message.content, "");
+        assert_eq!(response.choices[0].finish_reason, Some("stop".to_string()));
+    }
+
     #[test]
     fn test_is_anthropic_model() {
         assert!(AnthropicTransformer::is_anthropic_model("claude-3-opus-20240229"));
@@ -359,10 +855,10 @@ mod tests {
     fn prop_request_preserves_messages() {
         // For any request, transformation should preserve message content
         let messages = vec![
-            Message { role: "system".to_string(), content: "System prompt".to_string() },
-            Message { role: "user".to_string(), content: "User message".to_string() },
-            Message { role: "assistant".to_string(), content: "Assistant reply".to_string() },
-            Message { role: "user".to_string(), content: "Follow up".to_string() },
+            Message { role: "system".to_string(), content: "System prompt".into(), tool_calls: None, tool_call_id: None, name: None },
+            Message { role: "user".to_string(), content: "User message".into(), tool_calls: None, tool_call_id: None, name: None },
+            Message { role: "assistant".to_string(), content: "Assistant reply".into(), tool_calls: None, tool_call_id: None, name: None },
+            Message { role: "user".to_string(), content: "Follow up".into(), tool_calls: None, tool_call_id: None, name: None },
         ];
 
         let request = ChatCompletionRequest {
@@ -376,6 +872,10 @@ mod tests {
             presence_penalty: None,
             stop: Some(vec!["STOP".to_string()]),
             user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
         };
 
         let anthropic_req = AnthropicTransformer::transform_request(&request);
@@ -389,9 +889,9 @@ mod tests {
         assert_eq!(anthropic_req.messages[1].content, "Assistant reply");
         assert_eq!(anthropic_req.messages[2].content, "Follow up");
 
-        // Parameters should be preserved
+        // Parameters should be preserved (top_p is dropped alongside temperature)
         assert_eq!(anthropic_req.temperature, Some(0.5));
-        assert_eq!(anthropic_req.top_p, Some(0.9));
+        assert_eq!(anthropic_req.top_p, None);
         assert_eq!(anthropic_req.stop_sequences, Some(vec!["STOP".to_string()]));
     }
 }
@@ -5,9 +5,13 @@
 //! Transforms between OpenAI-compatible format and Google Generative AI API format.
 
 use serde::{Deserialize, Serialize};
-use chrono::Utc;
+use std::collections::HashMap;
 
-use super::{ChatCompletionRequest, ChatCompletionResponse, Choice, Message, Usage};
+use super::{
+    created_timestamp, reconcile_usage, response_id, ChatCompletionRequest, ChatCompletionResponse,
+    Choice, ContentPart, Message, MessageContent, ModelMetadata, Provider, ResponseFormat, ToolCall,
+    ToolChoice,
+};
 
 /// Google Generative AI API request format
 /// https://ai.google.dev/api/rest/v1beta/models/generateContent
@@ -18,17 +22,136 @@ pub struct GoogleRequest {
     pub generation_config: Option<GenerationConfig>,
     #[serde(rename = "systemInstruction", skip_serializing_if = "Option::is_none")]
     pub system_instruction: Option<GoogleContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<GoogleTool>>,
+    #[serde(rename = "toolConfig", skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Functions Gemini may call
+#[derive(Debug, Clone, Serialize)]
+pub struct GoogleTool {
+    #[serde(rename = "functionDeclarations")]
+    pub function_declarations: Vec<FunctionDeclaration>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolConfig {
+    #[serde(rename = "functionCallingConfig")]
+    pub function_calling_config: FunctionCallingConfig,
+}
+
+/// Function calling mode (`AUTO`, `ANY` or `NONE`)
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionCallingConfig {
+    pub mode: String,
+    #[serde(rename = "allowedFunctionNames", skip_serializing_if = "Option::is_none")]
+    pub allowed_function_names: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GoogleContent {
+    #[serde(default)]
     pub role: String,
+    #[serde(default)]
     pub parts: Vec<Part>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Part {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub text: String,
+    /// Function call made by the model
+    #[serde(rename = "functionCall", default, skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<GoogleFunctionCall>,
+    /// Result of a function call, sent back to the model
+    #[serde(rename = "functionResponse", default, skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<GoogleFunctionResponse>,
+    /// Base64 image sent inline
+    #[serde(rename = "inlineData", default, skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<InlineData>,
+    /// Image referenced by URI
+    #[serde(rename = "fileData", default, skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<FileData>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InlineData {
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileData {
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+    #[serde(rename = "fileUri")]
+    pub file_uri: String,
+}
+
+impl Part {
+    /// Parts for a message: one text part, or one part per text/image part
+    fn from_content(content: &MessageContent) -> Vec<Part> {
+        match content {
+            MessageContent::Text(text) => vec![Part { text: text.clone(), ..Default::default() }],
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text { text } => Part { text: text.clone(), ..Default::default() },
+                    ContentPart::ImageUrl { image_url } => match image_url.data() {
+                        Some((mime_type, data)) => Part {
+                            inline_data: Some(InlineData {
+                                mime_type: mime_type.to_string(),
+                                data: data.to_string(),
+                            }),
+                            ..Default::default()
+                        },
+                        None => Part {
+                            file_data: Some(FileData {
+                                mime_type: image_url.guess_media_type().to_string(),
+                                file_uri: image_url.url.clone(),
+                            }),
+                            ..Default::default()
+                        },
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoogleFunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoogleFunctionResponse {
+    pub name: String,
+    /// Must be an object: a JSON object result as is, anything else as `{"content": ...}`
+    pub response: serde_json::Value,
+}
+
+impl GoogleFunctionResponse {
+    fn from_result(name: String, result: &str) -> Self {
+        let response = match serde_json::from_str::<serde_json::Value>(result) {
+            Ok(object @ serde_json::Value::Object(_)) => object,
+            _ => serde_json::json!({ "content": result }),
+        };
+        Self { name, response }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -41,18 +164,42 @@ pub struct GenerationConfig {
     pub max_output_tokens: Option<u32>,
     #[serde(rename = "stopSequences", skip_serializing_if = "Option::is_none")]
     pub stop_sequences: Option<Vec<String>>,
+    #[serde(rename = "responseMimeType", skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    #[serde(rename = "responseSchema", skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<serde_json::Value>,
+    #[serde(rename = "candidateCount", skip_serializing_if = "Option::is_none")]
+    pub candidate_count: Option<u32>,
 }
 
+/// JSON Schema keywords Gemini's `responseSchema` (OpenAPI subset) rejects
+const UNSUPPORTED_SCHEMA_KEYS: &[&str] = &["$schema", "$id", "additionalProperties", "strict"];
+
 /// Google Generative AI API response format
 #[derive(Debug, Clone, Deserialize)]
 pub struct GoogleResponse {
+    /// Omitted entirely when the prompt is blocked
+    #[serde(default)]
     pub candidates: Vec<Candidate>,
     #[serde(rename = "usageMetadata")]
     pub usage_metadata: Option<UsageMetadata>,
+    #[serde(rename = "promptFeedback", default)]
+    pub prompt_feedback: Option<PromptFeedback>,
+    #[serde(rename = "responseId", default)]
+    pub response_id: Option<String>,
+}
+
+/// Why a prompt was blocked (no candidates returned)
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptFeedback {
+    #[serde(rename = "blockReason")]
+    pub block_reason: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Candidate {
+    /// Missing when the candidate was blocked for safety
+    #[serde(default)]
     pub content: GoogleContent,
     #[serde(rename = "finishReason")]
     pub finish_reason: Option<String>,
@@ -80,54 +227,185 @@ impl GoogleTransformer {
         let mut contents: Vec<GoogleContent> = Vec::new();
         let mut system_instruction: Option<GoogleContent> = None;
 
+        // Function responses are matched to calls by name, not id
+        let call_names: HashMap<&str, &str> = request
+            .messages
+            .iter()
+            .flat_map(|msg| msg.tool_calls.iter().flatten())
+            .map(|call| (call.id.as_str(), call.function.name.as_str()))
+            .collect();
+
         for msg in &request.messages {
-            if msg.role == "system" {
+            match msg.role.as_str() {
                 // Google uses systemInstruction for system prompts
-                system_instruction = Some(GoogleContent {
-                    role: "user".to_string(), // System instruction uses user role
-                    parts: vec![Part { text: msg.content.clone() }],
-                });
-            } else {
-                // Map OpenAI roles to Google roles
-                let role = match msg.role.as_str() {
-                    "assistant" => "model",
-                    _ => &msg.role,
-                };
-
-                contents.push(GoogleContent {
-                    role: role.to_string(),
-                    parts: vec![Part { text: msg.content.clone() }],
-                });
+                "system" => {
+                    system_instruction = Some(GoogleContent {
+                        role: "user".to_string(), // System instruction uses user role
+                        parts: Part::from_content(&msg.content),
+                    });
+                }
+                // Tool results go back in a user turn; consecutive results share one
+                "tool" => {
+                    let name = msg
+                        .name
+                        .clone()
+                        .or_else(|| msg.tool_call_id.as_deref().and_then(|id| call_names.get(id)).map(|name| name.to_string()))
+                        .unwrap_or_default();
+                    let part = Part {
+                        function_response: Some(GoogleFunctionResponse::from_result(name, &msg.content.text())),
+                        ..Default::default()
+                    };
+                    match contents.last_mut() {
+                        Some(last) if last.role == "user" && last.parts.iter().all(|p| p.function_response.is_some()) => {
+                            last.parts.push(part)
+                        }
+                        _ => contents.push(GoogleContent { role: "user".to_string(), parts: vec![part] }),
+                    }
+                }
+                _ => {
+                    // Map OpenAI roles to Google roles
+                    let role = match msg.role.as_str() {
+                        "assistant" => "model",
+                        _ => &msg.role,
+                    };
+
+                    let mut parts = Part::from_content(&msg.content);
+                    if let Some(tool_calls) = msg.tool_calls.as_ref().filter(|calls| !calls.is_empty()) {
+                        parts.retain(|part| !part.text.is_empty() || part.inline_data.is_some() || part.file_data.is_some());
+                        parts.extend(tool_calls.iter().map(|call| Part {
+                            function_call: Some(GoogleFunctionCall {
+                                name: call.function.name.clone(),
+                                args: serde_json::from_str(&call.function.arguments)
+                                    .unwrap_or_else(|_| serde_json::json!({})),
+                            }),
+                            ..Default::default()
+                        }));
+                    }
+
+                    contents.push(GoogleContent { role: role.to_string(), parts });
+                }
             }
         }
 
+        // Map response_format to JSON mode / responseSchema
+        let (response_mime_type, response_schema) = match &request.response_format {
+            Some(ResponseFormat::JsonObject) => (Some("application/json".to_string()), None),
+            Some(ResponseFormat::JsonSchema { json_schema }) => (
+                Some("application/json".to_string()),
+                json_schema.schema.as_ref().map(Self::transform_schema),
+            ),
+            Some(ResponseFormat::Text) | None => (None, None),
+        };
+
         // Build generation config if any parameters are set
-        let generation_config = if request.temperature.is_some()
-            || request.top_p.is_some()
-            || request.max_tokens.is_some()
+        let metadata = ModelMetadata::for_model(&request.model);
+        let (temperature, top_p) = Provider::Google.sampling_policy().apply(
+            &request.model,
+            metadata.resolve_temperature(request.temperature),
+            request.top_p,
+        );
+        let max_output_tokens = metadata.resolve_max_tokens(&request.model, request.max_tokens);
+        let candidate_count = request.n.filter(|n| *n > 1);
+        let generation_config = if temperature.is_some()
+            || top_p.is_some()
+            || max_output_tokens.is_some()
             || request.stop.is_some()
+            || response_mime_type.is_some()
+            || candidate_count.is_some()
         {
             Some(GenerationConfig {
-                temperature: request.temperature,
-                top_p: request.top_p,
-                max_output_tokens: request.max_tokens,
+                temperature,
+                top_p,
+                max_output_tokens,
                 stop_sequences: request.stop.clone(),
+                response_mime_type,
+                response_schema,
+                candidate_count,
             })
         } else {
             None
         };
 
+        let (tools, tool_config) = Self::transform_tools(request);
+
         GoogleRequest {
             contents,
             generation_config,
             system_instruction,
+            tools,
+            tool_config,
+        }
+    }
+
+    /// Map OpenAI `tools` to `functionDeclarations` and `tool_choice` to `toolConfig`
+    fn transform_tools(request: &ChatCompletionRequest) -> (Option<Vec<GoogleTool>>, Option<ToolConfig>) {
+        let Some(tools) = request.tools.as_ref().filter(|tools| !tools.is_empty()) else {
+            return (None, None);
+        };
+
+        let function_declarations = tools
+            .iter()
+            .map(|tool| FunctionDeclaration {
+                name: tool.function.name.clone(),
+                description: tool.function.description.clone(),
+                parameters: tool.function.parameters.as_ref().map(Self::transform_schema),
+            })
+            .collect();
+
+        let (mode, allowed_function_names) = match request.tool_choice.as_ref().map(ToolChoice::from_value) {
+            Some(ToolChoice::Auto) | None => return (Some(vec![GoogleTool { function_declarations }]), None),
+            Some(ToolChoice::None) => ("NONE", None),
+            Some(ToolChoice::Required) => ("ANY", None),
+            Some(ToolChoice::Function(name)) => ("ANY", Some(vec![name])),
+        };
+
+        (
+            Some(vec![GoogleTool { function_declarations }]),
+            Some(ToolConfig {
+                function_calling_config: FunctionCallingConfig {
+                    mode: mode.to_string(),
+                    allowed_function_names,
+                },
+            }),
+        )
+    }
+
+    /// Convert a JSON Schema into Gemini's `responseSchema` by dropping
+    /// keywords it doesn't accept
+    pub fn transform_schema(schema: &serde_json::Value) -> serde_json::Value {
+        match schema {
+            serde_json::Value::Object(map) => serde_json::Value::Object(
+                map.iter()
+                    .filter(|(key, _)| !UNSUPPORTED_SCHEMA_KEYS.contains(&key.as_str()))
+                    .map(|(key, value)| {
+                        let value = match value {
+                            // Property names are user-defined, only transform their schemas
+                            serde_json::Value::Object(props) if key == "properties" => {
+                                serde_json::Value::Object(
+                                    props
+                                        .iter()
+                                        .map(|(name, prop)| (name.clone(), Self::transform_schema(prop)))
+                                        .collect(),
+                                )
+                            }
+                            _ => Self::transform_schema(value),
+                        };
+                        (key.clone(), value)
+                    })
+                    .collect(),
+            ),
+            serde_json::Value::Array(items) => {
+                serde_json::Value::Array(items.iter().map(Self::transform_schema).collect())
+            }
+            other => other.clone(),
         }
     }
 
-    /// Transform Google response to OpenAI-compatible format
+    /// Transform Google response to OpenAI-compatible format.
+    /// Each candidate becomes a choice, ordered by index; usage covers all candidates.
     /// Requirement: 2.4
     pub fn transform_response(response: GoogleResponse, model: &str) -> ChatCompletionResponse {
-        let choices: Vec<Choice> = response
+        let mut choices: Vec<Choice> = response
             .candidates
             .iter()
             .enumerate()
@@ -140,9 +418,25 @@ impl GoogleTransformer {
                     .collect::<Vec<_>>()
                     .join("");
 
+                // Gemini doesn't id its function calls, so generate OpenAI-style ids
+                let tool_calls: Vec<ToolCall> = candidate
+                    .content
+                    .parts
+                    .iter()
+                    .filter_map(|p| p.function_call.as_ref())
+                    .map(|call| {
+                        ToolCall::new(
+                            format!("call_{}", uuid::Uuid::new_v4().simple()),
+                            call.name.clone(),
+                            &call.args,
+                        )
+                    })
+                    .collect();
+
                 // Map Google finish reasons to OpenAI format
                 let finish_reason = candidate.finish_reason.as_ref().map(|reason| {
                     match reason.as_str() {
+                        "STOP" if !tool_calls.is_empty() => "tool_calls".to_string(),
                         "STOP" => "stop".to_string(),
                         "MAX_TOKENS" => "length".to_string(),
                         "SAFETY" => "content_filter".to_string(),
@@ -155,39 +449,64 @@ impl GoogleTransformer {
                     index: candidate.index.unwrap_or(i as i32),
                     message: Message {
                         role: "assistant".to_string(),
-                        content,
+                        content: MessageContent::Text(content),
+                        tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+                        tool_call_id: None,
+                        name: None,
                     },
                     finish_reason,
+                    logprobs: None,
                 }
             })
             .collect();
+        choices.sort_by_key(|choice| choice.index);
+
+        // Blocked prompts return no candidates: answer with one empty, filtered choice
+        let choices = if choices.is_empty() {
+            if let Some(reason) = response.prompt_feedback.as_ref().and_then(|f| f.block_reason.as_ref()) {
+                tracing::warn!(block_reason = %reason, "Google returned no candidates");
+            }
+            vec![Choice {
+                index: 0,
+                message: Message {
+                    role: "assistant".to_string(),
+                    content: MessageContent::default(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
+                },
+                finish_reason: Some("content_filter".to_string()),
+                logprobs: None,
+            }]
+        } else {
+            choices
+        };
 
-        let usage = response.usage_metadata.map(|u| Usage {
-            prompt_tokens: u.prompt_token_count.unwrap_or(0),
-            completion_tokens: u.candidates_token_count.unwrap_or(0),
-            total_tokens: u.total_token_count.unwrap_or(0),
-        }).unwrap_or(Usage {
-            prompt_tokens: 0,
-            completion_tokens: 0,
-            total_tokens: 0,
-        });
+        let usage = response.usage_metadata.map(|u| {
+            reconcile_usage(
+                Provider::Google,
+                u.prompt_token_count.unwrap_or(0),
+                u.candidates_token_count.unwrap_or(0),
+                u.total_token_count,
+            )
+        }).unwrap_or_else(|| reconcile_usage(Provider::Google, 0, 0, None));
 
         ChatCompletionResponse {
-            id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
+            id: response_id(),
             object: "chat.completion".to_string(),
-            created: Utc::now().timestamp(),
+            created: created_timestamp(),
             model: model.to_string(),
             choices,
             usage,
+            system_fingerprint: None,
+            service_tier: None,
+            provider_request_id: response.response_id,
         }
     }
 
-    /// Get Google AI API URL for a model
-    pub fn api_url(model: &str, api_key: &str) -> String {
-        format!(
-            "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent?key={}",
-            model, api_key
-        )
+    /// Get Google AI API URL for a model on a regional base URL
+    pub fn api_url(base_url: &str, model: &str, api_key: &str) -> String {
+        format!("{}/v1beta/models/{}:generateContent?key={}", base_url, model, api_key)
     }
 
     /// Get required headers for Google AI API
@@ -216,6 +535,7 @@ impl GoogleTransformer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::services::transformers::JsonSchemaFormat;
 
     // ============================================================
     // Unit Tests for Google Transformer (Task 2.1, 2.2)
@@ -229,7 +549,10 @@ mod tests {
             messages: vec![
                 Message {
                     role: "user".to_string(),
-                    content: "Hello, Gemini!".to_string(),
+                    content: "Hello, Gemini!".into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
                 },
             ],
             temperature: Some(0.7),
@@ -240,6 +563,10 @@ mod tests {
             presence_penalty: None,
             stop: None,
             user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
         };
 
         let google_req = GoogleTransformer::transform_request(&request);
@@ -261,11 +588,17 @@ mod tests {
             messages: vec![
                 Message {
                     role: "system".to_string(),
-                    content: "You are a helpful assistant.".to_string(),
+                    content: "You are a helpful assistant.".into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
                 },
                 Message {
                     role: "user".to_string(),
-                    content: "Hello!".to_string(),
+                    content: "Hello!".into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
                 },
             ],
             temperature: None,
@@ -276,6 +609,10 @@ mod tests {
             presence_penalty: None,
             stop: None,
             user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
         };
 
         let google_req = GoogleTransformer::transform_request(&request);
@@ -297,9 +634,9 @@ mod tests {
         let request = ChatCompletionRequest {
             model: "gemini-pro".to_string(),
             messages: vec![
-                Message { role: "user".to_string(), content: "Hi".to_string() },
-                Message { role: "assistant".to_string(), content: "Hello!".to_string() },
-                Message { role: "user".to_string(), content: "How are you?".to_string() },
+                Message { role: "user".to_string(), content: "Hi".into(), tool_calls: None, tool_call_id: None, name: None },
+                Message { role: "assistant".to_string(), content: "Hello!".into(), tool_calls: None, tool_call_id: None, name: None },
+                Message { role: "user".to_string(), content: "How are you?".into(), tool_calls: None, tool_call_id: None, name: None },
             ],
             temperature: None,
             max_tokens: None,
@@ -309,6 +646,10 @@ mod tests {
             presence_penalty: None,
             stop: None,
             user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
         };
 
         let google_req = GoogleTransformer::transform_request(&request);
@@ -327,6 +668,7 @@ mod tests {
                     role: "model".to_string(),
                     parts: vec![Part {
                         text: "Hello! How can I help you?".to_string(),
+                        ..Default::default()
                     }],
                 },
                 finish_reason: Some("STOP".to_string()),
@@ -337,12 +679,15 @@ mod tests {
                 candidates_token_count: Some(15),
                 total_token_count: Some(25),
             }),
+            prompt_feedback: None,
+            response_id: Some("resp-abc".to_string()),
         };
 
         let response = GoogleTransformer::transform_response(google_response, "gemini-pro");
 
         assert_eq!(response.object, "chat.completion");
         assert_eq!(response.model, "gemini-pro");
+        assert_eq!(response.provider_request_id.as_deref(), Some("resp-abc"));
         assert_eq!(response.choices.len(), 1);
         assert_eq!(response.choices[0].message.role, "assistant");
         assert_eq!(response.choices[0].message.content, "Hello! How can I help you?");
@@ -352,6 +697,288 @@ mod tests {
         assert_eq!(response.usage.total_tokens, 25);
     }
 
+    #[test]
+    fn test_transform_request_json_schema() {
+        let request = ChatCompletionRequest {
+            model: "gemini-1.5-pro".to_string(),
+            messages: vec![Message { role: "user".to_string(), content: "Weather?".into(), tool_calls: None, tool_call_id: None, name: None }],
+            temperature: None,
+            max_tokens: None,
+            stream: false,
+            top_p: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            stop: None,
+            user: None,
+            response_format: Some(ResponseFormat::JsonSchema {
+                json_schema: JsonSchemaFormat {
+                    name: "weather".to_string(),
+                    description: None,
+                    schema: Some(serde_json::json!({
+                        "type": "object",
+                        "properties": {
+                            "city": { "type": "string" },
+                            "strict": { "type": "boolean" }
+                        },
+                        "required": ["city"],
+                        "additionalProperties": false
+                    })),
+                    strict: Some(true),
+                },
+            }),
+            n: None,
+            tools: None,
+            tool_choice: None,
+        };
+
+        let google_req = GoogleTransformer::transform_request(&request);
+        let config = google_req.generation_config.as_ref().unwrap();
+
+        assert_eq!(config.response_mime_type.as_deref(), Some("application/json"));
+        assert_eq!(
+            config.response_schema,
+            Some(serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "city": { "type": "string" },
+                    "strict": { "type": "boolean" }
+                },
+                "required": ["city"]
+            }))
+        );
+
+        let json = serde_json::to_value(&google_req).unwrap();
+        assert!(json["generationConfig"]["responseSchema"].is_object());
+        assert_eq!(json["generationConfig"]["responseMimeType"], "application/json");
+    }
+
+    #[test]
+    fn test_transform_request_json_object() {
+        let request = ChatCompletionRequest {
+            model: "gemini-pro".to_string(),
+            messages: vec![Message { role: "user".to_string(), content: "Hi".into(), tool_calls: None, tool_call_id: None, name: None }],
+            temperature: None,
+            max_tokens: None,
+            stream: false,
+            top_p: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            stop: None,
+            user: None,
+            response_format: Some(ResponseFormat::JsonObject),
+            n: None,
+            tools: None,
+            tool_choice: None,
+        };
+
+        let config = GoogleTransformer::transform_request(&request).generation_config.unwrap();
+
+        assert_eq!(config.response_mime_type.as_deref(), Some("application/json"));
+        assert!(config.response_schema.is_none());
+    }
+
+    #[test]
+    fn test_transform_response_multiple_candidates() {
+        let google_response: GoogleResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [
+                { "content": { "role": "model", "parts": [{ "text": "Kedua" }] }, "finishReason": "MAX_TOKENS", "index": 1 },
+                { "content": { "role": "model", "parts": [{ "text": "Pertama" }] }, "finishReason": "STOP", "index": 0 }
+            ],
+            "usageMetadata": { "promptTokenCount": 6, "candidatesTokenCount": 9, "totalTokenCount": 15 }
+        }))
+        .unwrap();
+
+        let response = GoogleTransformer::transform_response(google_response, "gemini-1.5-flash");
+
+        assert_eq!(response.choices.len(), 2);
+        assert_eq!(response.choices[0].index, 0);
+        assert_eq!(response.choices[0].message.content, "Pertama");
+        assert_eq!(response.choices[0].finish_reason.as_deref(), Some("stop"));
+        assert_eq!(response.choices[1].index, 1);
+        assert_eq!(response.choices[1].message.content, "Kedua");
+        assert_eq!(response.choices[1].finish_reason.as_deref(), Some("length"));
+        // Usage is reported once for all candidates
+        assert_eq!(response.usage.completion_tokens, 9);
+        assert_eq!(response.usage.total_tokens, 15);
+    }
+
+    #[test]
+    fn test_transform_request_maps_n_to_candidate_count() {
+        let mut request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-1.5-flash",
+            "messages": [{ "role": "user", "content": "Halo" }],
+            "n": 2
+        }))
+        .unwrap();
+
+        let config = GoogleTransformer::transform_request(&request).generation_config.unwrap();
+        assert_eq!(config.candidate_count, Some(2));
+
+        request.n = Some(1);
+        assert!(GoogleTransformer::transform_request(&request).generation_config.is_none());
+    }
+
+    #[test]
+    fn test_transform_response_without_candidates() {
+        let google_response: GoogleResponse = serde_json::from_value(serde_json::json!({
+            "promptFeedback": { "blockReason": "SAFETY" },
+            "usageMetadata": { "promptTokenCount": 8, "totalTokenCount": 8 }
+        }))
+        .unwrap();
+
+        let response = GoogleTransformer::transform_response(google_response, "gemini-pro");
+
+        assert_eq!(response.choices.len(), 1);
+        assert_eq!(response.choices[0].message.content, "");
+        assert_eq!(response.choices[0].finish_reason, Some("content_filter".to_string()));
+        assert_eq!(response.usage.prompt_tokens, 8);
+    }
+
+    #[test]
+    fn test_transform_response_safety_candidate_without_content() {
+        let google_response: GoogleResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{ "finishReason": "SAFETY", "index": 0 }]
+        }))
+        .unwrap();
+
+        let response = GoogleTransformer::transform_response(google_response, "gemini-pro");
+
+        assert_eq!(response.choices.len(), 1);
+        assert_eq!(response.choices[0].message.content, "");
+        assert_eq!(response.choices[0].finish_reason, Some("content_filter".to_string()));
+    }
+
+    #[test]
+    fn test_tool_call_round_trip() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-1.5-pro",
+            "messages": [{ "role": "user", "content": "Weather in Jakarta?" }],
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "description": "Current weather for a city",
+                    "parameters": {
+                        "type": "object",
+                        "properties": { "city": { "type": "string" } },
+                        "additionalProperties": false
+                    }
+                }
+            }],
+            "tool_choice": "required"
+        }))
+        .unwrap();
+
+        let json = serde_json::to_value(GoogleTransformer::transform_request(&request)).unwrap();
+        let declaration = &json["tools"][0]["functionDeclarations"][0];
+        assert_eq!(declaration["name"], "get_weather");
+        assert_eq!(declaration["description"], "Current weather for a city");
+        assert_eq!(declaration["parameters"]["properties"]["city"]["type"], "string");
+        assert!(declaration["parameters"].get("additionalProperties").is_none());
+        assert_eq!(json["toolConfig"]["functionCallingConfig"]["mode"], "ANY");
+
+        let google_response: GoogleResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [{ "functionCall": { "name": "get_weather", "args": { "city": "Jakarta" } } }]
+                },
+                "finishReason": "STOP",
+                "index": 0
+            }],
+            "usageMetadata": { "promptTokenCount": 20, "candidatesTokenCount": 5, "totalTokenCount": 25 }
+        }))
+        .unwrap();
+
+        let response = GoogleTransformer::transform_response(google_response, "gemini-1.5-pro");
+        let tool_calls = response.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert!(tool_calls[0].id.starts_with("call_"));
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&tool_calls[0].function.arguments).unwrap(),
+            serde_json::json!({ "city": "Jakarta" })
+        );
+        assert_eq!(response.choices[0].message.content, "");
+        assert_eq!(response.choices[0].finish_reason, Some("tool_calls".to_string()));
+
+        // The next turn carries the call and its result back, with the
+        // assistant's content null as clients send it
+        let follow_up: crate::routes::proxy::ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-1.5-pro",
+            "messages": [
+                { "role": "user", "content": "Weather in Jakarta?" },
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "get_weather", "arguments": "{\"city\": \"Jakarta\"}" }
+                    }]
+                },
+                { "role": "tool", "tool_call_id": "call_1", "content": "{\"temp_c\": 31}" }
+            ]
+        }))
+        .unwrap();
+        let follow_up: ChatCompletionRequest = follow_up.into();
+        let json = serde_json::to_value(GoogleTransformer::transform_request(&follow_up)).unwrap();
+        assert_eq!(json["contents"].as_array().unwrap().len(), 3);
+        assert_eq!(json["contents"][1]["role"], "model");
+        assert_eq!(
+            json["contents"][1]["parts"],
+            serde_json::json!([{ "functionCall": { "name": "get_weather", "args": { "city": "Jakarta" } } }])
+        );
+        assert_eq!(json["contents"][2]["role"], "user");
+        assert_eq!(
+            json["contents"][2]["parts"],
+            serde_json::json!([{ "functionResponse": { "name": "get_weather", "response": { "temp_c": 31 } } }])
+        );
+    }
+
+    #[test]
+    fn test_named_tool_choice_restricts_functions() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-1.5-pro",
+            "messages": [{ "role": "user", "content": "Hi" }],
+            "tools": [{ "type": "function", "function": { "name": "lookup" } }],
+            "tool_choice": { "type": "function", "function": { "name": "lookup" } }
+        }))
+        .unwrap();
+
+        let json = serde_json::to_value(GoogleTransformer::transform_request(&request)).unwrap();
+        assert_eq!(
+            json["toolConfig"]["functionCallingConfig"],
+            serde_json::json!({ "mode": "ANY", "allowedFunctionNames": ["lookup"] })
+        );
+    }
+
+    #[test]
+    fn test_image_parts_become_inline_and_file_data() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-1.5-pro",
+            "messages": [{
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "Compare these" },
+                    { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" } },
+                    { "type": "image_url", "image_url": { "url": "https://example.com/cat.webp" } }
+                ]
+            }]
+        }))
+        .unwrap();
+
+        let json = serde_json::to_value(GoogleTransformer::transform_request(&request)).unwrap();
+        assert_eq!(
+            json["contents"][0]["parts"],
+            serde_json::json!([
+                { "text": "Compare these" },
+                { "inlineData": { "mimeType": "image/png", "data": "iVBORw0KGgo=" } },
+                { "fileData": { "mimeType": "image/webp", "fileUri": "https://example.com/cat.webp" } }
+            ])
+        );
+    }
+
     #[test]
     fn test_is_google_model() {
         assert!(GoogleTransformer::is_google_model("gemini-pro"));
@@ -363,7 +990,7 @@ mod tests {
 
     #[test]
     fn test_api_url() {
-        let url = GoogleTransformer::api_url("gemini-pro", "test-api-key");
+        let url = GoogleTransformer::api_url("https://generativelanguage.googleapis.com", "gemini-pro", "test-api-key");
         assert!(url.contains("gemini-pro"));
         assert!(url.contains("key=test-api-key"));
         assert!(url.contains("generativelanguage.googleapis.com"));
@@ -29,16 +29,329 @@ pub struct ChatCompletionRequest {
     pub frequency_penalty: Option<f32>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub presence_penalty: Option<f32>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "deserialize_stop", skip_serializing_if = "Option::is_none")]
     pub stop: Option<Vec<String>>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// Number of choices to generate (Google maps this to `candidateCount`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// Functions the model may call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    /// OpenAI `tool_choice`: `"auto"`, `"none"`, `"required"` or a named function
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+}
+
+/// Function the model may call (OpenAI `tools` entry)
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Tool {
+    #[serde(default = "function_type")]
+    pub r#type: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON Schema of the arguments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+/// Function call made by the model (OpenAI `tool_calls` entry)
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(default = "function_type")]
+    pub r#type: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// Arguments as a JSON-encoded string
+    pub arguments: String,
+}
+
+impl ToolCall {
+    /// Build a call from a provider's decoded arguments
+    pub fn new(id: String, name: String, arguments: &serde_json::Value) -> Self {
+        Self {
+            id,
+            r#type: function_type(),
+            function: FunctionCall {
+                name,
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+}
+
+impl From<FunctionDefinition> for Tool {
+    fn from(function: FunctionDefinition) -> Self {
+        Self { r#type: function_type(), function }
+    }
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+/// Client `tool_choice`, normalized across its string and object forms
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolChoice {
+    Auto,
+    None,
+    Required,
+    Function(String),
+}
+
+impl ToolChoice {
+    /// Parse OpenAI `tool_choice`; unrecognized values fall back to `Auto`
+    pub fn from_value(value: &serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::String(choice) => match choice.as_str() {
+                "none" => ToolChoice::None,
+                "required" => ToolChoice::Required,
+                _ => ToolChoice::Auto,
+            },
+            _ => value["function"]["name"]
+                .as_str()
+                .map(|name| ToolChoice::Function(name.to_string()))
+                .unwrap_or(ToolChoice::Auto),
+        }
+    }
+}
+
+/// Deserialize OpenAI `stop`, which is either one sequence or a list of
+/// them, into a list
+pub fn deserialize_stop<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stop {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(Option::<Stop>::deserialize(deserializer)?.map(|stop| match stop {
+        Stop::One(sequence) => vec![sequence],
+        Stop::Many(sequences) => sequences,
+    }))
+}
+
+/// Requested output format (OpenAI `response_format`)
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+/// Structured output schema (OpenAI `response_format.json_schema`)
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl ResponseFormat {
+    /// Check whether a provider can honor this format.
+    /// Anthropic emulates `json_schema` with a forced tool call (non-streaming only);
+    /// Qwen only supports plain JSON mode.
+    pub fn check_supported(&self, provider: Provider, stream: bool) -> Result<(), String> {
+        match (self, provider) {
+            (ResponseFormat::Text, _) => Ok(()),
+            (_, Provider::OpenAI) | (_, Provider::Google) => Ok(()),
+            (ResponseFormat::JsonObject, Provider::Qwen) => Ok(()),
+            (ResponseFormat::JsonSchema { .. }, Provider::Anthropic) if !stream => Ok(()),
+            (ResponseFormat::JsonSchema { .. }, Provider::Anthropic) => Err(
+                "response_format json_schema is not supported with stream=true for Anthropic models".to_string(),
+            ),
+            (ResponseFormat::JsonObject, Provider::Anthropic) => Err(
+                "response_format json_object is not supported for Anthropic models; use json_schema".to_string(),
+            ),
+            (ResponseFormat::JsonSchema { .. }, Provider::Qwen) => Err(
+                "response_format json_schema is not supported for Qwen models; use json_object".to_string(),
+            ),
+        }
+    }
+}
+
+/// How a provider treats requests that set both `temperature` and `top_p`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplingPolicy {
+    /// Send both unchanged
+    Allow,
+    /// Send both but log that the provider advises against it
+    Warn,
+    /// Keep `temperature` and drop `top_p`
+    DropTopP,
+}
+
+impl SamplingPolicy {
+    /// Resolve the `(temperature, top_p)` pair to send upstream
+    pub fn apply(
+        self,
+        model: &str,
+        temperature: Option<f32>,
+        top_p: Option<f32>,
+    ) -> (Option<f32>, Option<f32>) {
+        let (Some(temp), Some(p)) = (temperature, top_p) else {
+            return (temperature, top_p);
+        };
+        match self {
+            SamplingPolicy::Allow => (temperature, top_p),
+            SamplingPolicy::Warn => {
+                tracing::warn!(model, temperature = temp, top_p = p, "Both temperature and top_p set");
+                (temperature, top_p)
+            }
+            SamplingPolicy::DropTopP => {
+                tracing::info!(model, temperature = temp, top_p = p, "Dropped top_p because temperature is set");
+                (temperature, None)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    pub content: MessageContent,
+    /// Function calls made by the assistant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Call a `role: "tool"` message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Called function's name on a tool result
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Message content: plain text, or a list of text and image parts
+/// (OpenAI vision format). Plain text serializes as a bare string.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+/// Image reference: an `http(s)` URL or a base64 `data:` URL
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ImageUrl {
+    /// Media type and base64 data of a `data:<type>;base64,<data>` URL
+    pub fn data(&self) -> Option<(&str, &str)> {
+        let (header, data) = self.url.strip_prefix("data:")?.split_once(',')?;
+        let media_type = header.strip_suffix(";base64")?;
+        Some((media_type, data))
+    }
+
+    /// Media type of a linked image, guessed from its extension
+    pub fn guess_media_type(&self) -> &'static str {
+        let path = self.url.split(['?', '#']).next().unwrap_or_default().to_ascii_lowercase();
+        match path.rsplit('.').next() {
+            Some("png") => "image/png",
+            Some("gif") => "image/gif",
+            Some("webp") => "image/webp",
+            Some("heic") => "image/heic",
+            _ => "image/jpeg",
+        }
+    }
+}
+
+impl MessageContent {
+    /// The message's text, with text parts joined
+    pub fn text(&self) -> std::borrow::Cow<'_, str> {
+        match self {
+            MessageContent::Text(text) => std::borrow::Cow::Borrowed(text),
+            MessageContent::Parts(parts) => std::borrow::Cow::Owned(
+                parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ContentPart::Text { text } => Some(text.as_str()),
+                        ContentPart::ImageUrl { .. } => None,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Every text segment, for in-place edits
+    pub fn texts_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        let (text, parts) = match self {
+            MessageContent::Text(text) => (Some(text), None),
+            MessageContent::Parts(parts) => (None, Some(parts)),
+        };
+        text.into_iter().chain(parts.into_iter().flatten().filter_map(|part| match part {
+            ContentPart::Text { text } => Some(text),
+            ContentPart::ImageUrl { .. } => None,
+        }))
+    }
+
+    /// Whether any part is an image
+    pub fn has_images(&self) -> bool {
+        matches!(self, MessageContent::Parts(parts) if parts.iter().any(|part| matches!(part, ContentPart::ImageUrl { .. })))
+    }
+}
+
+impl Default for MessageContent {
+    fn default() -> Self {
+        MessageContent::Text(String::new())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+impl PartialEq<&str> for MessageContent {
+    fn eq(&self, other: &&str) -> bool {
+        matches!(self, MessageContent::Text(text) if text == other)
+    }
+}
+
+impl PartialEq<String> for MessageContent {
+    fn eq(&self, other: &String) -> bool {
+        self == &other.as_str()
+    }
 }
 
 /// Unified chat completion response (OpenAI-compatible format)
@@ -50,6 +363,28 @@ pub struct ChatCompletionResponse {
     pub model: String,
     pub choices: Vec<Choice>,
     pub usage: Usage,
+    /// Backend configuration fingerprint; only OpenAI returns one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
+    /// Service tier that processed the request; only OpenAI returns one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
+    /// Upstream provider's id for the request (for tracing, never sent to clients)
+    #[serde(skip)]
+    pub provider_request_id: Option<String>,
+}
+
+/// Response id prefix shared by every provider
+pub const RESPONSE_ID_PREFIX: &str = "chatcmpl-";
+
+/// Generate a response id (`chatcmpl-` plus a random uuid)
+pub fn response_id() -> String {
+    format!("{}{}", RESPONSE_ID_PREFIX, uuid::Uuid::new_v4().simple())
+}
+
+/// Unix timestamp for a response's `created` field
+pub fn created_timestamp() -> i64 {
+    chrono::Utc::now().timestamp()
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -57,6 +392,9 @@ pub struct Choice {
     pub index: i32,
     pub message: Message,
     pub finish_reason: Option<String>,
+    /// Token log probabilities (only OpenAI provides them)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -66,8 +404,159 @@ pub struct Usage {
     pub total_tokens: i32,
 }
 
+/// Build usage from provider-reported counts, always setting
+/// `total_tokens = prompt_tokens + completion_tokens`.
+/// A reported total that disagrees is logged and replaced.
+pub fn reconcile_usage(
+    provider: Provider,
+    prompt_tokens: i32,
+    completion_tokens: i32,
+    reported_total: Option<i32>,
+) -> Usage {
+    let total_tokens = prompt_tokens + completion_tokens;
+    if let Some(reported) = reported_total.filter(|reported| *reported != total_tokens) {
+        tracing::warn!(
+            provider = provider.name(),
+            prompt_tokens,
+            completion_tokens,
+            reported_total = reported,
+            "Provider total_tokens disagrees with prompt + completion; correcting"
+        );
+    }
+
+    Usage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens,
+    }
+}
+
+/// Per-model request handling metadata
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelMetadata {
+    /// Whether the model accepts `temperature` (o1 models reject it)
+    pub supports_temperature: bool,
+    /// Temperature to send when the client doesn't set one
+    /// (`None` omits the field and lets the provider decide)
+    pub default_temperature: Option<f32>,
+    /// Largest output token count the provider accepts (`None` when unknown)
+    pub max_output_tokens: Option<u32>,
+    /// Context window in tokens, prompt plus completion (`None` when unknown)
+    pub context_window: Option<u32>,
+    /// Whether the model can stream reasoning tokens
+    pub supports_reasoning: bool,
+    /// Whether the model handles a `system` message; when it doesn't, the
+    /// system prompt is folded into the first user message
+    pub supports_system_prompt: bool,
+}
+
+impl ModelMetadata {
+    /// Look up metadata for a model name
+    pub fn for_model(model: &str) -> Self {
+        Self::from_lookup(model, |key| std::env::var(key).ok())
+    }
+
+    /// Look up metadata for a model name, reading overrides with a custom
+    /// variable lookup. `SYSTEM_PROMPT_UNSUPPORTED_MODELS` lists models
+    /// (trailing `*` matches by prefix) that don't support system prompts;
+    /// unset, every model keeps its system messages.
+    pub fn from_lookup<F>(model: &str, lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let is_o1 = model == "o1" || model.starts_with("o1-");
+        let system_prompt_unsupported = lookup("SYSTEM_PROMPT_UNSUPPORTED_MODELS").is_some_and(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => model.starts_with(prefix),
+                    None => model == pattern,
+                })
+        });
+        Self {
+            supports_temperature: !is_o1,
+            default_temperature: None,
+            max_output_tokens: Self::output_cap(model),
+            context_window: Self::context_limit(model),
+            supports_reasoning: ["o1", "o3", "claude-3-7-", "deepseek-r1", "deepseek-reasoner", "qwq"]
+                .iter()
+                .any(|prefix| model.starts_with(prefix)),
+            supports_system_prompt: !system_prompt_unsupported,
+        }
+    }
+
+    /// Known context windows, most specific prefix first
+    fn context_limit(model: &str) -> Option<u32> {
+        const LIMITS: &[(&str, u32)] = &[
+            ("o1", 128_000),
+            ("gpt-4o", 128_000),
+            ("gpt-4-turbo", 128_000),
+            ("gpt-4", 8_192),
+            ("gpt-3.5-turbo", 16_385),
+            ("claude-3-", 200_000),
+            ("claude-2.1", 200_000),
+            ("gemini-1.5-pro", 2_097_152),
+            ("gemini-1.5-flash", 1_048_576),
+            ("gemini-pro", 32_760),
+            ("gemini-1.0-pro", 32_760),
+            ("qwen-max-longcontext", 30_000),
+            ("qwen-max", 8_000),
+            ("qwen-plus", 32_000),
+            ("qwen-turbo", 8_000),
+        ];
+
+        LIMITS
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|(_, limit)| *limit)
+    }
+
+    /// Known output token caps, most specific prefix first
+    fn output_cap(model: &str) -> Option<u32> {
+        const CAPS: &[(&str, u32)] = &[
+            ("o1-mini", 65_536),
+            ("o1", 32_768),
+            ("gpt-4o", 16_384),
+            ("gpt-4-turbo", 4_096),
+            ("gpt-4", 8_192),
+            ("gpt-3.5-turbo", 4_096),
+            ("claude-3-5-", 8_192),
+            ("claude-3-", 4_096),
+            ("gemini-1.5-", 8_192),
+            ("gemini-pro", 2_048),
+            ("gemini-1.0-", 2_048),
+            ("qwen", 8_192),
+        ];
+
+        CAPS.iter()
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|(_, cap)| *cap)
+    }
+
+    /// Resolve the temperature to send upstream
+    pub fn resolve_temperature(&self, requested: Option<f32>) -> Option<f32> {
+        if !self.supports_temperature {
+            return None;
+        }
+        requested.or(self.default_temperature)
+    }
+
+    /// Clamp a requested output token limit to the model's cap
+    pub fn resolve_max_tokens(&self, model: &str, requested: Option<u32>) -> Option<u32> {
+        match (requested, self.max_output_tokens) {
+            (Some(requested), Some(cap)) if requested > cap => {
+                tracing::info!(model, requested, cap, "Clamped max_tokens to model output cap");
+                Some(cap)
+            }
+            _ => requested,
+        }
+    }
+}
+
 /// AI Provider enum for routing
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Provider {
     OpenAI,
@@ -76,10 +565,116 @@ pub enum Provider {
     Qwen,
 }
 
+/// Entry in a provider's model catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BaseModel {
+    pub id: &'static str,
+    /// Still served, but clients should move to a newer model
+    pub deprecated: bool,
+    /// Suggested model for clients of a deprecated one
+    pub replacement: Option<&'static str>,
+}
+
+impl BaseModel {
+    const fn active(id: &'static str) -> Self {
+        Self { id, deprecated: false, replacement: None }
+    }
+
+    const fn deprecated(id: &'static str, replacement: &'static str) -> Self {
+        Self { id, deprecated: true, replacement: Some(replacement) }
+    }
+
+    /// Human-readable deprecation notice, if this model is deprecated
+    pub fn deprecation_notice(&self) -> Option<String> {
+        if !self.deprecated {
+            return None;
+        }
+        Some(match self.replacement {
+            Some(replacement) => format!("Model {} is deprecated; use {} instead", self.id, replacement),
+            None => format!("Model {} is deprecated", self.id),
+        })
+    }
+}
+
+const OPENAI_MODELS: &[BaseModel] = &[
+    BaseModel::active("gpt-4o"),
+    BaseModel::active("gpt-4o-mini"),
+    BaseModel::active("gpt-4-turbo"),
+    BaseModel::active("gpt-4"),
+    BaseModel::active("gpt-3.5-turbo"),
+    BaseModel::active("o1-preview"),
+    BaseModel::active("o1-mini"),
+];
+
+const ANTHROPIC_MODELS: &[BaseModel] = &[
+    BaseModel::active("claude-3-5-sonnet-20241022"),
+    BaseModel::active("claude-3-5-sonnet-20240620"),
+    BaseModel::active("claude-3-5-haiku-20241022"),
+    BaseModel::active("claude-3-opus-20240229"),
+    BaseModel::deprecated("claude-3-sonnet-20240229", "claude-3-5-sonnet-20241022"),
+    BaseModel::active("claude-3-haiku-20240307"),
+    BaseModel::deprecated("claude-2.1", "claude-3-5-sonnet-20241022"),
+];
+
+const GOOGLE_MODELS: &[BaseModel] = &[
+    BaseModel::active("gemini-1.5-pro"),
+    BaseModel::active("gemini-1.5-flash"),
+    BaseModel::active("gemini-1.5-flash-8b"),
+    BaseModel::deprecated("gemini-1.0-pro", "gemini-1.5-flash"),
+    BaseModel::deprecated("gemini-pro", "gemini-1.5-flash"),
+];
+
+const QWEN_MODELS: &[BaseModel] = &[
+    BaseModel::active("qwen-turbo"),
+    BaseModel::active("qwen-plus"),
+    BaseModel::active("qwen-max"),
+    BaseModel::active("qwen-max-longcontext"),
+    BaseModel::active("qwen-7b-chat"),
+    BaseModel::active("qwen-14b-chat"),
+    BaseModel::active("qwen-72b-chat"),
+];
+
 impl Provider {
-    /// Determine provider from model name
+    /// Every supported provider
+    pub const ALL: [Provider; 4] = [
+        Provider::OpenAI,
+        Provider::Anthropic,
+        Provider::Google,
+        Provider::Qwen,
+    ];
+
+    /// Canonical models offered for this provider
+    pub fn base_models(&self) -> &'static [BaseModel] {
+        match self {
+            Provider::OpenAI => OPENAI_MODELS,
+            Provider::Anthropic => ANTHROPIC_MODELS,
+            Provider::Google => GOOGLE_MODELS,
+            Provider::Qwen => QWEN_MODELS,
+        }
+    }
+
+    /// Catalog entry for a model of this provider, if listed
+    pub fn catalog_model(&self, model: &str) -> Option<&'static BaseModel> {
+        self.base_models().iter().find(|entry| entry.id == model)
+    }
+
+    /// Split a `provider/model` name (e.g. `anthropic/claude-3-opus`) into
+    /// the named provider and the model name it knows
+    pub fn split_prefixed(model: &str) -> Option<(Self, &str)> {
+        let (prefix, name) = model.split_once('/')?;
+        let provider = Self::ALL
+            .into_iter()
+            .find(|provider| provider.name().eq_ignore_ascii_case(prefix))?;
+        (!name.is_empty()).then_some((provider, name))
+    }
+
+    /// Determine provider from model name, either `provider/model` or a
+    /// bare model name by its prefix
     /// Requirements: 1.1, 2.1, 3.1
     pub fn from_model(model: &str) -> Option<Self> {
+        if let Some((provider, _)) = Self::split_prefixed(model) {
+            return Some(provider);
+        }
         if model.starts_with("gpt-") || model.starts_with("o1-") {
             Some(Provider::OpenAI)
         } else if model.starts_with("claude-") {
@@ -93,6 +688,15 @@ impl Provider {
         }
     }
 
+    /// How this provider handles `temperature` and `top_p` set together
+    pub fn sampling_policy(&self) -> SamplingPolicy {
+        match self {
+            Provider::OpenAI => SamplingPolicy::Allow,
+            Provider::Google => SamplingPolicy::Warn,
+            Provider::Anthropic | Provider::Qwen => SamplingPolicy::DropTopP,
+        }
+    }
+
     /// Get provider name for display
     pub fn name(&self) -> &'static str {
         match self {
@@ -152,6 +756,24 @@ mod tests {
         assert_eq!(Provider::from_model("unknown-model"), None);
     }
 
+    #[test]
+    fn test_provider_from_prefixed_model() {
+        assert_eq!(Provider::from_model("anthropic/claude-3-opus"), Some(Provider::Anthropic));
+        assert_eq!(Provider::from_model("google/gemini-1.5-pro"), Some(Provider::Google));
+        assert_eq!(Provider::from_model("openai/gpt-4o"), Some(Provider::OpenAI));
+        assert_eq!(Provider::from_model("Qwen/qwen-max"), Some(Provider::Qwen));
+
+        assert_eq!(
+            Provider::split_prefixed("anthropic/claude-3-opus"),
+            Some((Provider::Anthropic, "claude-3-opus"))
+        );
+        assert_eq!(Provider::split_prefixed("claude-3-opus"), None);
+
+        // Unknown prefixes and empty model names aren't routed
+        assert_eq!(Provider::from_model("meta-llama/llama-3-70b"), None);
+        assert_eq!(Provider::from_model("anthropic/"), None);
+    }
+
     #[test]
     fn test_provider_name() {
         assert_eq!(Provider::OpenAI.name(), "OpenAI");
@@ -159,4 +781,339 @@ mod tests {
         assert_eq!(Provider::Google.name(), "Google");
         assert_eq!(Provider::Qwen.name(), "Qwen");
     }
+
+    // ============================================================
+    // Unit Tests for Model Metadata (temperature handling)
+    // ============================================================
+
+    #[test]
+    fn test_o1_never_sends_temperature() {
+        let metadata = ModelMetadata::for_model("o1-preview");
+        assert!(!metadata.supports_temperature);
+        assert_eq!(metadata.resolve_temperature(Some(0.7)), None);
+        assert_eq!(metadata.resolve_temperature(None), None);
+    }
+
+    #[test]
+    fn test_gpt4_passes_temperature_through() {
+        let metadata = ModelMetadata::for_model("gpt-4");
+        assert!(metadata.supports_temperature);
+        assert_eq!(metadata.resolve_temperature(Some(0.7)), Some(0.7));
+        assert_eq!(metadata.resolve_temperature(None), None);
+    }
+
+    #[test]
+    fn test_default_temperature_applied_when_unset() {
+        let metadata = ModelMetadata {
+            supports_temperature: true,
+            default_temperature: Some(1.0),
+            max_output_tokens: None,
+            context_window: None,
+            supports_reasoning: false,
+            supports_system_prompt: true,
+        };
+        assert_eq!(metadata.resolve_temperature(None), Some(1.0));
+        assert_eq!(metadata.resolve_temperature(Some(0.2)), Some(0.2));
+    }
+
+    #[test]
+    fn test_max_tokens_above_cap_is_clamped() {
+        let metadata = ModelMetadata::for_model("claude-3-haiku-20240307");
+        assert_eq!(metadata.resolve_max_tokens("claude-3-haiku-20240307", Some(1_000_000)), Some(4_096));
+
+        let metadata = ModelMetadata::for_model("gpt-4o-mini");
+        assert_eq!(metadata.resolve_max_tokens("gpt-4o-mini", Some(20_000)), Some(16_384));
+
+        let metadata = ModelMetadata::for_model("o1-mini");
+        assert_eq!(metadata.max_output_tokens, Some(65_536));
+    }
+
+    #[test]
+    fn test_system_prompt_support_configurable_per_model() {
+        let lookup = |key: &str| match key {
+            "SYSTEM_PROMPT_UNSUPPORTED_MODELS" => Some("qwen-turbo, gemini-1.0-*".to_string()),
+            _ => None,
+        };
+        assert!(!ModelMetadata::from_lookup("qwen-turbo", lookup).supports_system_prompt);
+        assert!(!ModelMetadata::from_lookup("gemini-1.0-pro-001", lookup).supports_system_prompt);
+        assert!(ModelMetadata::from_lookup("qwen-turbo-latest", lookup).supports_system_prompt);
+        assert!(ModelMetadata::from_lookup("gpt-4o", lookup).supports_system_prompt);
+
+        // Unconfigured: every model keeps its system prompt
+        assert!(ModelMetadata::from_lookup("qwen-turbo", |_| None).supports_system_prompt);
+    }
+
+    #[test]
+    fn test_reasoning_support_per_model() {
+        assert!(ModelMetadata::for_model("o1-mini").supports_reasoning);
+        assert!(ModelMetadata::for_model("claude-3-7-sonnet-20250219").supports_reasoning);
+        assert!(ModelMetadata::for_model("deepseek-r1").supports_reasoning);
+        assert!(!ModelMetadata::for_model("gpt-4o").supports_reasoning);
+        assert!(!ModelMetadata::for_model("claude-3-5-sonnet-20241022").supports_reasoning);
+    }
+
+    #[test]
+    fn test_context_window_known_per_model() {
+        assert_eq!(ModelMetadata::for_model("gpt-4o-2024-08-06").context_window, Some(128_000));
+        assert_eq!(ModelMetadata::for_model("gpt-4").context_window, Some(8_192));
+        assert_eq!(ModelMetadata::for_model("claude-3-5-sonnet-20241022").context_window, Some(200_000));
+        assert_eq!(ModelMetadata::for_model("qwen-max-longcontext").context_window, Some(30_000));
+        assert_eq!(ModelMetadata::for_model("qwen-72b-chat").context_window, None);
+    }
+
+    #[test]
+    fn test_max_tokens_within_cap_passes_through() {
+        let metadata = ModelMetadata::for_model("claude-3-5-sonnet-20241022");
+        assert_eq!(metadata.resolve_max_tokens("claude-3-5-sonnet-20241022", Some(8_192)), Some(8_192));
+        assert_eq!(metadata.resolve_max_tokens("claude-3-5-sonnet-20241022", Some(100)), Some(100));
+        assert_eq!(metadata.resolve_max_tokens("claude-3-5-sonnet-20241022", None), None);
+    }
+
+    #[test]
+    fn test_max_tokens_unknown_model_untouched() {
+        let metadata = ModelMetadata::for_model("my-finetune");
+        assert_eq!(metadata.max_output_tokens, None);
+        assert_eq!(metadata.resolve_max_tokens("my-finetune", Some(1_000_000)), Some(1_000_000));
+    }
+
+    #[test]
+    fn test_reconcile_usage_corrects_disagreeing_total() {
+        let usage = reconcile_usage(Provider::Google, 10, 15, Some(40));
+        assert_eq!(usage.prompt_tokens, 10);
+        assert_eq!(usage.completion_tokens, 15);
+        assert_eq!(usage.total_tokens, 25);
+    }
+
+    #[test]
+    fn test_reconcile_usage_keeps_matching_or_missing_total() {
+        assert_eq!(reconcile_usage(Provider::Qwen, 10, 15, Some(25)).total_tokens, 25);
+        assert_eq!(reconcile_usage(Provider::Qwen, 10, 15, None).total_tokens, 25);
+    }
+
+    #[test]
+    fn test_sampling_policy_per_provider() {
+        let both = (Some(0.7), Some(0.9));
+        let resolve = |provider: Provider| provider.sampling_policy().apply("model", both.0, both.1);
+
+        assert_eq!(resolve(Provider::OpenAI), both);
+        assert_eq!(resolve(Provider::Google), both);
+        assert_eq!(resolve(Provider::Anthropic), (Some(0.7), None));
+        assert_eq!(resolve(Provider::Qwen), (Some(0.7), None));
+    }
+
+    #[test]
+    fn test_sampling_policy_keeps_a_single_parameter() {
+        for provider in Provider::ALL {
+            let policy = provider.sampling_policy();
+            assert_eq!(policy.apply("model", None, Some(0.9)), (None, Some(0.9)));
+            assert_eq!(policy.apply("model", Some(0.7), None), (Some(0.7), None));
+            assert_eq!(policy.apply("model", None, None), (None, None));
+        }
+    }
+
+    #[test]
+    fn test_every_provider_has_a_catalog() {
+        for provider in Provider::ALL {
+            assert!(!provider.base_models().is_empty(), "{} has no models", provider.name());
+        }
+    }
+
+    #[test]
+    fn test_deprecated_models_suggest_a_listed_replacement() {
+        for provider in Provider::ALL {
+            for model in provider.base_models().iter().filter(|model| model.deprecated) {
+                let replacement = model.replacement.expect("deprecated model without replacement");
+                assert!(!provider.catalog_model(replacement).unwrap().deprecated, "{}", model.id);
+            }
+        }
+        assert_eq!(
+            Provider::Anthropic.catalog_model("claude-2.1").unwrap().deprecation_notice().as_deref(),
+            Some("Model claude-2.1 is deprecated; use claude-3-5-sonnet-20241022 instead")
+        );
+    }
+
+    #[test]
+    fn test_from_model_agrees_with_catalog() {
+        for provider in Provider::ALL {
+            for model in provider.base_models() {
+                assert_eq!(Provider::from_model(model.id), Some(provider), "{}", model.id);
+                assert_eq!(provider.catalog_model(model.id), Some(model));
+            }
+        }
+        assert!(Provider::Google.catalog_model("gemini-pro").unwrap().deprecated);
+        assert_eq!(Provider::OpenAI.catalog_model("gpt-4o").unwrap().deprecation_notice(), None);
+        assert_eq!(Provider::OpenAI.catalog_model("gpt-5-unreleased"), None);
+    }
+
+    // ============================================================
+    // Unit Tests for Message and Response Serialization
+    // ============================================================
+
+    #[test]
+    fn test_text_content_serializes_as_bare_string() {
+        let message: Message = serde_json::from_value(serde_json::json!({ "role": "user", "content": "Hello" })).unwrap();
+        assert_eq!(message.content, MessageContent::Text("Hello".to_string()));
+        assert!(!message.content.has_images());
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({ "role": "user", "content": "Hello" })
+        );
+    }
+
+    #[test]
+    fn test_mixed_content_serializes_as_array() {
+        let parts = serde_json::json!([
+            { "type": "text", "text": "What is in " },
+            { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=", "detail": "low" } },
+            { "type": "text", "text": "this image?" }
+        ]);
+        let message: Message = serde_json::from_value(serde_json::json!({ "role": "user", "content": parts })).unwrap();
+
+        assert!(message.content.has_images());
+        assert_eq!(message.content.text(), "What is in this image?");
+        assert_eq!(serde_json::to_value(&message.content).unwrap(), parts);
+
+        let MessageContent::Parts(parts) = &message.content else {
+            panic!("expected content parts");
+        };
+        let ContentPart::ImageUrl { image_url } = &parts[1] else {
+            panic!("expected an image part");
+        };
+        assert_eq!(image_url.data(), Some(("image/png", "iVBORw0KGgo=")));
+    }
+
+    #[test]
+    fn test_image_url_media_type() {
+        let image = |url: &str| ImageUrl { url: url.to_string(), detail: None };
+        assert_eq!(image("https://example.com/cat.PNG?size=large").guess_media_type(), "image/png");
+        assert_eq!(image("https://example.com/cat.webp").guess_media_type(), "image/webp");
+        assert_eq!(image("https://example.com/photo").guess_media_type(), "image/jpeg");
+        assert_eq!(image("https://example.com/photo").data(), None);
+    }
+
+    #[test]
+    fn test_openai_system_fingerprint_preserved() {
+        let body = serde_json::json!({
+            "id": "chatcmpl-abc",
+            "object": "chat.completion",
+            "created": 1733900000,
+            "model": "gpt-4o-mini",
+            "system_fingerprint": "fp_44709d6fcb",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Halo!" },
+                "finish_reason": "stop"
+            }],
+            "usage": { "prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7 }
+        });
+
+        let response: ChatCompletionResponse = serde_json::from_value(body).unwrap();
+        assert_eq!(response.system_fingerprint.as_deref(), Some("fp_44709d6fcb"));
+        assert_eq!(serde_json::to_value(&response).unwrap()["system_fingerprint"], "fp_44709d6fcb");
+
+        let without = ChatCompletionResponse {
+            system_fingerprint: None,
+            ..response
+        };
+        assert!(serde_json::to_value(&without).unwrap().get("system_fingerprint").is_none());
+    }
+
+    #[test]
+    fn test_choice_logprobs_round_trip() {
+        let body = serde_json::json!({
+            "index": 0,
+            "message": { "role": "assistant", "content": "Hi" },
+            "finish_reason": "stop",
+            "logprobs": { "content": [{ "token": "Hi", "logprob": -0.01, "top_logprobs": [] }] }
+        });
+        let choice: Choice = serde_json::from_value(body.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&choice).unwrap(), body);
+
+        // Providers without logprobs don't emit the field
+        let choice = Choice { logprobs: None, ..choice };
+        assert!(serde_json::to_value(&choice).unwrap().get("logprobs").is_none());
+    }
+
+    #[test]
+    fn test_response_id_format() {
+        let id = response_id();
+        assert!(id.starts_with(RESPONSE_ID_PREFIX));
+        assert_eq!(id.len(), RESPONSE_ID_PREFIX.len() + 32);
+        assert_ne!(id, response_id());
+    }
+
+    #[test]
+    fn test_all_transformers_produce_chatcmpl_ids() {
+        let anthropic: anthropic::AnthropicResponse = serde_json::from_value(serde_json::json!({
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "content": [{ "type": "text", "text": "Halo" }],
+            "model": "claude-3-haiku-20240307",
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 3, "output_tokens": 1 }
+        }))
+        .unwrap();
+        let google: google::GoogleResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": "Halo" }] }, "finishReason": "STOP" }]
+        }))
+        .unwrap();
+        let qwen: qwen::QwenResponse = serde_json::from_value(serde_json::json!({
+            "output": { "text": "Halo", "finish_reason": "stop" },
+            "usage": { "input_tokens": 3, "output_tokens": 1 },
+            "request_id": "req-1"
+        }))
+        .unwrap();
+
+        let responses = [
+            anthropic::AnthropicTransformer::transform_response(anthropic),
+            google::GoogleTransformer::transform_response(google, "gemini-pro"),
+            qwen::QwenTransformer::transform_response(qwen, "qwen-plus"),
+        ];
+
+        for response in &responses {
+            assert!(response.id.starts_with(RESPONSE_ID_PREFIX), "bad id: {}", response.id);
+        }
+        assert_eq!(responses[0].provider_request_id.as_deref(), Some("msg_01"));
+        assert_eq!(responses[2].provider_request_id.as_deref(), Some("req-1"));
+    }
+
+    fn request_with_stop(stop: serde_json::Value) -> ChatCompletionRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [{ "role": "user", "content": "Hello" }],
+            "stop": stop
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_stop_string_and_array_normalize_to_list() {
+        let string = request_with_stop(serde_json::json!("\n\n"));
+        let array = request_with_stop(serde_json::json!(["\n\n"]));
+        assert_eq!(string.stop, Some(vec!["\n\n".to_string()]));
+        assert_eq!(string.stop, array.stop);
+
+        let several = request_with_stop(serde_json::json!(["END", "STOP"]));
+        assert_eq!(several.stop, Some(vec!["END".to_string(), "STOP".to_string()]));
+        assert_eq!(request_with_stop(serde_json::Value::Null).stop, None);
+
+        let missing: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": []
+        }))
+        .unwrap();
+        assert_eq!(missing.stop, None);
+    }
+
+    #[test]
+    fn test_stop_rejects_other_types() {
+        let result = serde_json::from_value::<ChatCompletionRequest>(serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [],
+            "stop": 42
+        }));
+        assert!(result.is_err());
+    }
+
 }
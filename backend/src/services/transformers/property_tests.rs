@@ -15,7 +15,7 @@ mod property_tests {
     use proptest::prelude::*;
     use crate::services::transformers::{
         ChatCompletionRequest, ChatCompletionResponse, Message,
-        anthropic::{AnthropicTransformer, AnthropicResponse, AnthropicContent, AnthropicUsage},
+        anthropic::{AnthropicTransformer, AnthropicMessageContent, AnthropicResponse, AnthropicContent, AnthropicUsage},
         google::{GoogleTransformer, GoogleResponse, GoogleContent, Part, Candidate, UsageMetadata},
         qwen::{QwenTransformer, QwenResponse, QwenOutput, QwenChoice, QwenMessage, QwenUsage},
     };
@@ -41,7 +41,7 @@ mod property_tests {
 
     /// Generate a valid message
     fn message_strategy() -> impl Strategy<Value = Message> {
-        (role_strategy(), content_strategy()).prop_map(|(role, content)| Message { role, content })
+        (role_strategy(), content_strategy()).prop_map(|(role, content)| Message { role, content: content.into(), tool_calls: None, tool_call_id: None, name: None })
     }
 
     /// Generate a non-empty list of messages with at least one user message
@@ -109,6 +109,10 @@ mod property_tests {
                 presence_penalty: None,
                 stop,
                 user: None,
+                response_format: None,
+                n: None,
+                tools: None,
+                tool_choice: None,
             }
         })
     }
@@ -143,7 +147,7 @@ mod property_tests {
             // Each non-system message content should be preserved
             for (orig, transformed) in non_system_messages.iter().zip(anthropic_req.messages.iter()) {
                 prop_assert_eq!(
-                    &orig.content,
+                    &AnthropicMessageContent::from(&orig.content),
                     &transformed.content,
                     "Message content must be preserved"
                 );
@@ -168,7 +172,7 @@ mod property_tests {
                 Some(msg) => {
                     prop_assert_eq!(
                         anthropic_req.system,
-                        Some(msg.content.clone()),
+                        Some(msg.content.text().into_owned()),
                         "System message should be extracted to system field"
                     );
                 }
@@ -220,10 +224,11 @@ mod property_tests {
                 request.temperature,
                 "Temperature should be preserved"
             );
+            // Anthropic drops top_p when temperature is also set
             prop_assert_eq!(
                 anthropic_req.top_p,
-                request.top_p,
-                "top_p should be preserved"
+                request.top_p.filter(|_| request.temperature.is_none()),
+                "top_p should be preserved unless temperature is set"
             );
             prop_assert_eq!(
                 anthropic_req.stop_sequences,
@@ -396,19 +401,26 @@ mod property_tests {
                 request.temperature,
                 "Temperature should be preserved"
             );
+            // Qwen drops top_p when temperature is also set
             prop_assert_eq!(
                 params.top_p,
-                request.top_p,
-                "top_p should be preserved"
+                request.top_p.filter(|_| request.temperature.is_none()),
+                "top_p should be preserved unless temperature is set"
             );
             prop_assert_eq!(
                 params.max_tokens,
                 request.max_tokens,
                 "max_tokens should be preserved"
             );
+            // Qwen drops duplicate stop sequences
+            let mut expected_stop = request.stop.clone();
+            if let Some(stop) = expected_stop.as_mut() {
+                let mut seen = std::collections::HashSet::new();
+                stop.retain(|value| seen.insert(value.clone()));
+            }
             prop_assert_eq!(
                 params.stop,
-                request.stop,
+                expected_stop,
                 "Stop sequences should be preserved"
             );
         }
@@ -470,6 +482,9 @@ mod property_tests {
             content_strategy().prop_map(|text| AnthropicContent {
                 r#type: "text".to_string(),
                 text,
+                id: None,
+                name: None,
+                input: None,
             }),
             1..3,
         )
@@ -539,7 +554,7 @@ mod property_tests {
                 candidates: vec![Candidate {
                     content: GoogleContent {
                         role: "model".to_string(),
-                        parts: vec![Part { text }],
+                        parts: vec![Part { text, ..Default::default() }],
                     },
                     finish_reason,
                     index: Some(0),
@@ -549,6 +564,8 @@ mod property_tests {
                     candidates_token_count: Some(candidates_tokens),
                     total_token_count: Some(prompt_tokens + candidates_tokens),
                 }),
+                prompt_feedback: None,
+                response_id: None,
             };
             (response, model)
         })
@@ -582,6 +599,9 @@ mod property_tests {
                         message: QwenMessage {
                             role: "assistant".to_string(),
                             content: text,
+                            tool_calls: None,
+                            tool_call_id: None,
+                            name: None,
                         },
                     }]),
                 },
@@ -1243,6 +1263,8 @@ mod streaming_chunk_tests {
                     delta: StreamDelta {
                         role: if content.is_some() { Some("assistant".to_string()) } else { None },
                         content,
+                        tool_calls: None,
+                        reasoning_content: None,
                     },
                     finish_reason,
                 }],
@@ -1262,6 +1284,8 @@ mod streaming_chunk_tests {
                 delta: AnthropicDelta {
                     r#type: "text_delta".to_string(),
                     text,
+                    partial_json: String::new(),
+                    thinking: String::new(),
                 },
             };
             (event, msg_id, model)
@@ -1288,6 +1312,7 @@ mod streaming_chunk_tests {
                         other => other.to_uppercase(),
                     }),
                 }]),
+                usage_metadata: None,
             };
             (chunk, model)
         })
@@ -1307,6 +1332,7 @@ mod streaming_chunk_tests {
                     finish_reason,
                 },
                 request_id,
+                usage: None,
             };
             (chunk, model)
         })
@@ -1635,6 +1661,9 @@ mod usage_log_tests {
                     } else {
                         None
                     },
+                    is_internal: false,
+                    metadata: None,
+                    provider_request_id: None,
                 }
             })
         })
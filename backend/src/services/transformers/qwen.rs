@@ -5,9 +5,11 @@
 //! Transforms between OpenAI-compatible format and Alibaba DashScope API format.
 
 use serde::{Deserialize, Serialize};
-use chrono::Utc;
 
-use super::{ChatCompletionRequest, ChatCompletionResponse, Choice, Message, Usage};
+use super::{
+    created_timestamp, reconcile_usage, response_id, ChatCompletionRequest, ChatCompletionResponse,
+    Choice, Message, MessageContent, ModelMetadata, Provider, Tool, ToolCall,
+};
 
 /// Alibaba DashScope API request format
 /// https://help.aliyun.com/zh/dashscope/developer-reference/api-details
@@ -27,7 +29,17 @@ pub struct QwenInput {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QwenMessage {
     pub role: String,
+    #[serde(default)]
     pub content: String,
+    /// Function calls made by the assistant (OpenAI format)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Call a `role: "tool"` message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Called function's name on a tool result
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -46,6 +58,11 @@ pub struct QwenParameters {
     pub result_format: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub incremental_output: Option<bool>,
+    /// Functions the model may call (OpenAI format)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
 }
 
 /// Alibaba DashScope API response format
@@ -76,6 +93,9 @@ pub struct QwenUsage {
     pub total_tokens: Option<i32>,
 }
 
+/// Maximum stop sequences forwarded to DashScope
+pub const MAX_QWEN_STOP_SEQUENCES: usize = 4;
+
 /// Qwen transformer
 /// Requirements: 3.2, 3.3, 3.4
 pub struct QwenTransformer;
@@ -89,25 +109,38 @@ impl QwenTransformer {
             .iter()
             .map(|msg| QwenMessage {
                 role: msg.role.clone(),
-                content: msg.content.clone(),
+                content: msg.content.text().into_owned(),
+                tool_calls: msg.tool_calls.clone(),
+                tool_call_id: msg.tool_call_id.clone(),
+                name: msg.name.clone(),
             })
             .collect();
 
         // Build parameters if any are set
-        let parameters = if request.temperature.is_some()
-            || request.top_p.is_some()
-            || request.max_tokens.is_some()
-            || request.stop.is_some()
+        let metadata = ModelMetadata::for_model(&request.model);
+        let (temperature, top_p) = Provider::Qwen.sampling_policy().apply(
+            &request.model,
+            metadata.resolve_temperature(request.temperature),
+            request.top_p,
+        );
+        let max_tokens = metadata.resolve_max_tokens(&request.model, request.max_tokens);
+        let stop = Self::normalize_stop(request.stop.as_deref());
+        let parameters = if temperature.is_some()
+            || top_p.is_some()
+            || max_tokens.is_some()
+            || stop.is_some()
             || request.stream
         {
             Some(QwenParameters {
-                temperature: request.temperature,
-                top_p: request.top_p,
-                max_tokens: request.max_tokens,
-                stop: request.stop.clone(),
+                temperature,
+                top_p,
+                max_tokens,
+                stop,
                 enable_search: None,
                 result_format: Some("message".to_string()), // Use message format for consistency
                 incremental_output: if request.stream { Some(true) } else { None },
+                tools: request.tools.clone(),
+                tool_choice: request.tool_choice.clone(),
             })
         } else {
             Some(QwenParameters {
@@ -118,6 +151,8 @@ impl QwenTransformer {
                 enable_search: None,
                 result_format: Some("message".to_string()),
                 incremental_output: None,
+                tools: request.tools.clone(),
+                tool_choice: request.tool_choice.clone(),
             })
         };
 
@@ -128,47 +163,85 @@ impl QwenTransformer {
         }
     }
 
+    /// Normalize stop sequences to values DashScope accepts.
+    /// Drops empty values, duplicates, and special tokens (`<|im_end|>` etc.,
+    /// which Qwen already stops on and which error when sent as strings),
+    /// and caps the list at `MAX_QWEN_STOP_SEQUENCES`.
+    pub fn normalize_stop(stop: Option<&[String]>) -> Option<Vec<String>> {
+        let mut normalized: Vec<String> = Vec::new();
+
+        for value in stop? {
+            if value.is_empty() {
+                tracing::warn!("Dropping empty Qwen stop sequence");
+            } else if value.starts_with("<|") && value.ends_with("|>") {
+                tracing::warn!(stop = %value, "Dropping Qwen special token from stop sequences");
+            } else if normalized.contains(value) {
+                continue;
+            } else if normalized.len() >= MAX_QWEN_STOP_SEQUENCES {
+                tracing::warn!(stop = %value, "Dropping Qwen stop sequence beyond limit of {}", MAX_QWEN_STOP_SEQUENCES);
+            } else {
+                normalized.push(value.clone());
+            }
+        }
+
+        if normalized.is_empty() {
+            None
+        } else {
+            Some(normalized)
+        }
+    }
+
     /// Transform Qwen response to OpenAI-compatible format
     /// Requirement: 3.4
     pub fn transform_response(response: QwenResponse, model: &str) -> ChatCompletionResponse {
         // Handle both text format and message format responses
-        let (content, finish_reason) = if let Some(choices) = &response.output.choices {
+        let (content, tool_calls, finish_reason) = if let Some(choices) = &response.output.choices {
             // Message format (result_format: "message")
             if let Some(choice) = choices.first() {
                 (
                     choice.message.content.clone(),
+                    choice.message.tool_calls.clone(),
                     Some(Self::map_finish_reason(&choice.finish_reason)),
                 )
             } else {
-                (String::new(), None)
+                // Empty choices (e.g. moderation): one empty, filtered choice
+                (String::new(), None, Some("content_filter".to_string()))
             }
         } else {
             // Text format (default)
             (
                 response.output.text.unwrap_or_default(),
+                None,
                 response.output.finish_reason.map(|r| Self::map_finish_reason(&r)),
             )
         };
 
         ChatCompletionResponse {
-            id: format!("chatcmpl-{}", response.request_id),
+            id: response_id(),
             object: "chat.completion".to_string(),
-            created: Utc::now().timestamp(),
+            created: created_timestamp(),
             model: model.to_string(),
             choices: vec![Choice {
                 index: 0,
                 message: Message {
                     role: "assistant".to_string(),
-                    content,
+                    content: MessageContent::Text(content),
+                    tool_calls,
+                    tool_call_id: None,
+                    name: None,
                 },
                 finish_reason,
+                logprobs: None,
             }],
-            usage: Usage {
-                prompt_tokens: response.usage.input_tokens,
-                completion_tokens: response.usage.output_tokens,
-                total_tokens: response.usage.total_tokens
-                    .unwrap_or(response.usage.input_tokens + response.usage.output_tokens),
-            },
+            usage: reconcile_usage(
+                Provider::Qwen,
+                response.usage.input_tokens,
+                response.usage.output_tokens,
+                response.usage.total_tokens,
+            ),
+            system_fingerprint: None,
+            service_tier: None,
+            provider_request_id: Some(response.request_id),
         }
     }
 
@@ -195,17 +268,9 @@ impl QwenTransformer {
         ]
     }
 
-    /// Supported Qwen models
-    pub fn supported_models() -> &'static [&'static str] {
-        &[
-            "qwen-turbo",
-            "qwen-plus",
-            "qwen-max",
-            "qwen-max-longcontext",
-            "qwen-7b-chat",
-            "qwen-14b-chat",
-            "qwen-72b-chat",
-        ]
+    /// Supported Qwen models (from the provider catalog)
+    pub fn supported_models() -> impl Iterator<Item = &'static str> {
+        Provider::Qwen.base_models().iter().map(|model| model.id)
     }
 
     /// Check if model is a Qwen model
@@ -223,6 +288,34 @@ mod tests {
     // **Validates: Requirements 3.2, 3.3, 3.4**
     // ============================================================
 
+    #[test]
+    fn test_transform_request_drops_top_p_with_temperature() {
+        let request = ChatCompletionRequest {
+            model: "qwen-turbo".to_string(),
+            messages: vec![Message { role: "user".to_string(), content: "Hi".into(), tool_calls: None, tool_call_id: None, name: None }],
+            temperature: Some(0.7),
+            max_tokens: None,
+            stream: false,
+            top_p: Some(0.9),
+            frequency_penalty: None,
+            presence_penalty: None,
+            stop: None,
+            user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
+        };
+
+        let params = QwenTransformer::transform_request(&request).parameters.unwrap();
+        assert_eq!(params.temperature, Some(0.7));
+        assert_eq!(params.top_p, None);
+
+        let only_top_p = ChatCompletionRequest { temperature: None, ..request };
+        let params = QwenTransformer::transform_request(&only_top_p).parameters.unwrap();
+        assert_eq!(params.top_p, Some(0.9));
+    }
+
     #[test]
     fn test_transform_request_basic() {
         let request = ChatCompletionRequest {
@@ -230,7 +323,10 @@ mod tests {
             messages: vec![
                 Message {
                     role: "user".to_string(),
-                    content: "Hello, Qwen!".to_string(),
+                    content: "Hello, Qwen!".into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
                 },
             ],
             temperature: Some(0.7),
@@ -241,6 +337,10 @@ mod tests {
             presence_penalty: None,
             stop: None,
             user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
         };
 
         let qwen_req = QwenTransformer::transform_request(&request);
@@ -263,11 +363,17 @@ mod tests {
             messages: vec![
                 Message {
                     role: "system".to_string(),
-                    content: "You are a helpful assistant.".to_string(),
+                    content: "You are a helpful assistant.".into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
                 },
                 Message {
                     role: "user".to_string(),
-                    content: "Hello!".to_string(),
+                    content: "Hello!".into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    name: None,
                 },
             ],
             temperature: None,
@@ -278,6 +384,10 @@ mod tests {
             presence_penalty: None,
             stop: None,
             user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
         };
 
         let qwen_req = QwenTransformer::transform_request(&request);
@@ -294,7 +404,10 @@ mod tests {
             model: "qwen-turbo".to_string(),
             messages: vec![Message {
                 role: "user".to_string(),
-                content: "Test".to_string(),
+                content: "Test".into(),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
             }],
             temperature: None,
             max_tokens: None,
@@ -304,6 +417,10 @@ mod tests {
             presence_penalty: None,
             stop: None,
             user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
         };
 
         let qwen_req = QwenTransformer::transform_request(&request);
@@ -312,6 +429,57 @@ mod tests {
         assert_eq!(params.incremental_output, Some(true));
     }
 
+    #[test]
+    fn test_normalize_stop_keeps_valid_values() {
+        let stop = vec!["END".to_string(), "\n\n".to_string()];
+        assert_eq!(QwenTransformer::normalize_stop(Some(&stop)), Some(stop.clone()));
+        assert_eq!(QwenTransformer::normalize_stop(None), None);
+    }
+
+    #[test]
+    fn test_normalize_stop_drops_problematic_values() {
+        let stop: Vec<String> = ["", "<|im_end|>", "END", "END", "<|endoftext|>"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(QwenTransformer::normalize_stop(Some(&stop)), Some(vec!["END".to_string()]));
+
+        // Only unsupported values: omit stop entirely
+        let stop = vec!["<|im_end|>".to_string(), String::new()];
+        assert_eq!(QwenTransformer::normalize_stop(Some(&stop)), None);
+    }
+
+    #[test]
+    fn test_normalize_stop_caps_count() {
+        let stop: Vec<String> = (0..6).map(|i| format!("STOP{}", i)).collect();
+        let normalized = QwenTransformer::normalize_stop(Some(&stop)).unwrap();
+        assert_eq!(normalized.len(), MAX_QWEN_STOP_SEQUENCES);
+        assert_eq!(normalized[0], "STOP0");
+    }
+
+    #[test]
+    fn test_transform_request_omits_unsupported_stop() {
+        let request = ChatCompletionRequest {
+            model: "qwen-turbo".to_string(),
+            messages: vec![Message { role: "user".to_string(), content: "Hi".into(), tool_calls: None, tool_call_id: None, name: None }],
+            temperature: None,
+            max_tokens: None,
+            stream: false,
+            top_p: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            stop: Some(vec!["<|im_end|>".to_string()]),
+            user: None,
+            response_format: None,
+            n: None,
+            tools: None,
+            tool_choice: None,
+        };
+
+        let qwen_req = QwenTransformer::transform_request(&request);
+        assert!(qwen_req.parameters.unwrap().stop.is_none());
+    }
+
     #[test]
     fn test_transform_response_message_format() {
         let qwen_response = QwenResponse {
@@ -323,6 +491,9 @@ mod tests {
                     message: QwenMessage {
                         role: "assistant".to_string(),
                         content: "Hello! How can I help you?".to_string(),
+                        tool_calls: None,
+                        tool_call_id: None,
+                        name: None,
                     },
                 }]),
             },
@@ -338,7 +509,8 @@ mod tests {
 
         assert_eq!(response.object, "chat.completion");
         assert_eq!(response.model, "qwen-turbo");
-        assert!(response.id.contains("req-123"));
+        assert!(response.id.starts_with("chatcmpl-"));
+        assert_eq!(response.provider_request_id.as_deref(), Some("req-123"));
         assert_eq!(response.choices.len(), 1);
         assert_eq!(response.choices[0].message.role, "assistant");
         assert_eq!(response.choices[0].message.content, "Hello! How can I help you?");
@@ -370,6 +542,104 @@ mod tests {
         assert_eq!(response.usage.total_tokens, 15); // Calculated from input + output
     }
 
+    #[test]
+    fn test_transform_response_corrects_reported_total() {
+        let qwen_response = QwenResponse {
+            output: QwenOutput {
+                text: Some("Halo".to_string()),
+                finish_reason: Some("stop".to_string()),
+                choices: None,
+            },
+            usage: QwenUsage {
+                input_tokens: 5,
+                output_tokens: 10,
+                total_tokens: Some(99),
+            },
+            request_id: "req-789".to_string(),
+        };
+
+        let response = QwenTransformer::transform_response(qwen_response, "qwen-plus");
+
+        assert_eq!(response.usage.total_tokens, 15);
+    }
+
+    #[test]
+    fn test_tool_call_round_trip() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "qwen-plus",
+            "messages": [{ "role": "user", "content": "Weather in Jakarta?" }],
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "parameters": { "type": "object", "properties": { "city": { "type": "string" } } }
+                }
+            }],
+            "tool_choice": "auto"
+        }))
+        .unwrap();
+
+        let json = serde_json::to_value(QwenTransformer::transform_request(&request)).unwrap();
+        assert_eq!(json["parameters"]["tools"][0]["function"]["name"], "get_weather");
+        assert_eq!(json["parameters"]["tool_choice"], "auto");
+
+        let qwen_response: QwenResponse = serde_json::from_value(serde_json::json!({
+            "output": {
+                "choices": [{
+                    "finish_reason": "tool_calls",
+                    "message": {
+                        "role": "assistant",
+                        "content": "",
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "index": 0,
+                            "function": { "name": "get_weather", "arguments": "{\"city\": \"Jakarta\"}" }
+                        }]
+                    }
+                }]
+            },
+            "usage": { "input_tokens": 20, "output_tokens": 5 },
+            "request_id": "req-tool"
+        }))
+        .unwrap();
+
+        let response = QwenTransformer::transform_response(qwen_response, "qwen-plus");
+        let tool_calls = response.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls[0].id, "call_1");
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(tool_calls[0].function.arguments, r#"{"city": "Jakarta"}"#);
+        assert_eq!(response.choices[0].finish_reason, Some("tool_calls".to_string()));
+
+        // The next turn carries the call and its result back, with the
+        // assistant's content null as clients send it
+        let follow_up: crate::routes::proxy::ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "qwen-plus",
+            "messages": [
+                { "role": "user", "content": "Weather in Jakarta?" },
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "get_weather", "arguments": "{\"city\": \"Jakarta\"}" }
+                    }]
+                },
+                { "role": "tool", "tool_call_id": "call_1", "content": "{\"temp_c\": 31}" }
+            ]
+        }))
+        .unwrap();
+        let follow_up: ChatCompletionRequest = follow_up.into();
+        let json = serde_json::to_value(QwenTransformer::transform_request(&follow_up)).unwrap();
+        let messages = &json["input"]["messages"];
+        assert_eq!(messages[1]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(messages[1]["tool_calls"][0]["function"]["name"], "get_weather");
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(messages[2]["tool_call_id"], "call_1");
+        assert_eq!(messages[2]["content"], "{\"temp_c\": 31}");
+    }
+
     #[test]
     fn test_is_qwen_model() {
         assert!(QwenTransformer::is_qwen_model("qwen-turbo"));
@@ -0,0 +1,189 @@
+//! HTTP client for upstream provider requests.
+//!
+//! All provider calls share one `reqwest::Client` (and its connection pool),
+//! built once at startup and held in `AppState`. It identifies the proxy with
+//! a `User-Agent` so providers can attribute traffic instead of seeing
+//! reqwest's default. Gzip and brotli response bodies are decoded by the
+//! client, so callers always see plain bytes. Redirects are not followed:
+//! providers don't redirect API calls, and the client also delivers usage
+//! webhooks, which must not be bounced to internal addresses.
+//!
+//! Configuration:
+//! - `UPSTREAM_USER_AGENT`: overrides the default `Webrana-Proxy/<version>`
+//! - `UPSTREAM_POOL_MAX_IDLE_PER_HOST`: idle connections kept per provider host (default `32`)
+//! - `UPSTREAM_CONNECT_TIMEOUT_SECS`: connection timeout (default `10`)
+//! - `UPSTREAM_TIMEOUT_SECS`: limit on a whole upstream exchange, streamed
+//!   bodies included (default `600`)
+
+use reqwest::Client;
+use std::time::Duration;
+
+/// Default `User-Agent` sent to providers
+pub const DEFAULT_USER_AGENT: &str = concat!("Webrana-Proxy/", env!("CARGO_PKG_VERSION"));
+
+/// Default idle connections kept per provider host
+pub const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 32;
+
+/// Default connection timeout
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default limit on a whole upstream exchange; generous enough for long streams
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Upstream client configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamClientConfig {
+    pub user_agent: String,
+    pub pool_max_idle_per_host: usize,
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+}
+
+impl UpstreamClientConfig {
+    /// Load client configuration from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load client configuration using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let user_agent = lookup("UPSTREAM_USER_AGENT")
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+        let secs = |key: &str| {
+            lookup(key)
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+        };
+        Self {
+            user_agent,
+            pool_max_idle_per_host: lookup("UPSTREAM_POOL_MAX_IDLE_PER_HOST")
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(DEFAULT_POOL_MAX_IDLE_PER_HOST),
+            connect_timeout: secs("UPSTREAM_CONNECT_TIMEOUT_SECS").unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            timeout: secs("UPSTREAM_TIMEOUT_SECS").unwrap_or(DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// Build a client with this configuration
+    pub fn build(&self) -> Client {
+        Client::builder()
+            .user_agent(self.user_agent.as_str())
+            .gzip(true)
+            .brotli(true)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap_or_else(|e| {
+                tracing::error!(user_agent = %self.user_agent, "Invalid upstream client config: {}", e);
+                Client::new()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::env_lookup;
+    use axum::{http::{header, HeaderMap}, routing::get, Router};
+
+    #[test]
+    fn test_default_user_agent() {
+        let config = UpstreamClientConfig::from_lookup(env_lookup(&[]));
+        assert_eq!(config.user_agent, DEFAULT_USER_AGENT);
+        assert!(config.user_agent.starts_with("Webrana-Proxy/"));
+
+        let blank = UpstreamClientConfig::from_lookup(env_lookup(&[("UPSTREAM_USER_AGENT", "  ")]));
+        assert_eq!(blank.user_agent, DEFAULT_USER_AGENT);
+    }
+
+    #[test]
+    fn test_pool_and_timeouts_configurable() {
+        let defaults = UpstreamClientConfig::from_lookup(env_lookup(&[]));
+        assert_eq!(defaults.pool_max_idle_per_host, DEFAULT_POOL_MAX_IDLE_PER_HOST);
+        assert_eq!(defaults.connect_timeout, DEFAULT_CONNECT_TIMEOUT);
+        assert_eq!(defaults.timeout, DEFAULT_TIMEOUT);
+
+        let config = UpstreamClientConfig::from_lookup(|key| match key {
+            "UPSTREAM_POOL_MAX_IDLE_PER_HOST" => Some("8".to_string()),
+            "UPSTREAM_CONNECT_TIMEOUT_SECS" => Some("3".to_string()),
+            "UPSTREAM_TIMEOUT_SECS" => Some("0".to_string()),
+            _ => None,
+        });
+        assert_eq!(config.pool_max_idle_per_host, 8);
+        assert_eq!(config.connect_timeout, Duration::from_secs(3));
+        // Zero would fail every request; keep the default
+        assert_eq!(config.timeout, DEFAULT_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_redirects_not_followed() {
+        let app = Router::new()
+            .route("/", get(|| async { axum::response::Redirect::temporary("/internal") }))
+            .route("/internal", get(|| async { "internal" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = UpstreamClientConfig::from_lookup(env_lookup(&[])).build();
+        let response = client.get(format!("http://{}/", addr)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::TEMPORARY_REDIRECT);
+    }
+
+    #[tokio::test]
+    async fn test_slow_upstream_times_out() {
+        let app = Router::new().route(
+            "/",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "late"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let config = UpstreamClientConfig {
+            timeout: Duration::from_millis(100),
+            ..UpstreamClientConfig::from_lookup(env_lookup(&[]))
+        };
+        let err = config.build().get(format!("http://{}/", addr)).send().await.unwrap_err();
+        assert!(err.is_timeout());
+    }
+
+    #[tokio::test]
+    async fn test_requests_carry_configured_user_agent() {
+        let app = Router::new().route(
+            "/",
+            get(|headers: HeaderMap| async move {
+                headers
+                    .get(header::USER_AGENT)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string()
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = UpstreamClientConfig::from_lookup(env_lookup(&[("UPSTREAM_USER_AGENT", "Acme-Gateway/2.0")])).build();
+        let seen = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert_eq!(seen, "Acme-Gateway/2.0");
+    }
+}
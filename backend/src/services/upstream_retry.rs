@@ -0,0 +1,184 @@
+//! Retries for transient upstream failures.
+//!
+//! Connection failures and `429`/`500`/`502`/`503`/`529` responses are retried with
+//! jittered exponential backoff, waiting at least as long as the provider's
+//! `Retry-After`; only the final attempt reaches the client. A `Retry-After`
+//! longer than `MAX_RETRY_DELAY` is not waited out: that response is returned
+//! as is. Retry decisions use only the status and headers, before any of the
+//! body is read, so streaming requests are retried only before their first
+//! byte reaches the client.
+//!
+//! Anthropic overloads (`529`) are retried on plain backoff; their
+//! `Retry-After` is passed on to the client if every attempt is overloaded.
+//! The budget comes from `UPSTREAM_MAX_RETRIES`, lowered per non-streaming
+//! request by `max_retries`.
+
+use reqwest::StatusCode;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::services::anthropic_overload::{is_overloaded, OverloadRetryPolicy};
+
+/// Longest `Retry-After` the proxy waits out before retrying
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(20);
+
+/// Whether an upstream status is worth retrying
+pub fn is_transient(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 500 | 502 | 503) || is_overloaded(status)
+}
+
+/// Upstream `Retry-After` in seconds, if present
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// Backoff before retry number `retry` (1-based): `base` doubled for each
+/// earlier retry, scaled by `jitter` in `[0, 1)` to 50–100% of that
+pub fn backoff_delay(base: Duration, retry: u32, jitter: f64) -> Duration {
+    let exponential = base.saturating_mul(1 << retry.saturating_sub(1).min(16));
+    exponential.mul_f64(0.5 + jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+/// Send via `send`, retrying transient failures within `policy`'s attempts.
+/// Other responses and errors are returned immediately.
+pub async fn send_with_retry<F, Fut>(
+    policy: OverloadRetryPolicy,
+    mut send: F,
+) -> Result<reqwest::Response, reqwest::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<reqwest::Response, reqwest::Error>>,
+{
+    let attempts = policy.attempts.max(1);
+    let mut attempt = 1;
+
+    loop {
+        let result = send().await;
+        if attempt >= attempts {
+            return result;
+        }
+
+        let backoff = backoff_delay(policy.base_delay, attempt, rand::random());
+        let delay = match &result {
+            Ok(response) if is_overloaded(response.status()) => backoff,
+            Ok(response) if is_transient(response.status()) => match retry_after(response) {
+                Some(wait) if wait > MAX_RETRY_DELAY => return result,
+                Some(wait) => wait.max(backoff),
+                None => backoff,
+            },
+            Err(e) if e.is_connect() => backoff,
+            _ => return result,
+        };
+
+        match &result {
+            Ok(response) => tracing::warn!(attempt, status = %response.status(), ?delay, "Transient upstream failure, retrying"),
+            Err(_) => tracing::warn!(attempt, ?delay, "Upstream connection failed, retrying"),
+        }
+        drop(result);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::StatusCode as AxumStatus, response::IntoResponse, routing::post, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Mock provider answering `failure` for the first `failures` calls
+    async fn start_mock(failures: usize, failure: AxumStatus, retry_after: &'static str) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = move |State(calls): State<Arc<AtomicUsize>>| async move {
+            if calls.fetch_add(1, Ordering::SeqCst) < failures {
+                (failure, [("retry-after", retry_after)], r#"{"error":{"message":"Try again"}}"#).into_response()
+            } else {
+                (AxumStatus::OK, r#"{"id":"chatcmpl-1"}"#).into_response()
+            }
+        };
+        let app = Router::new()
+            .route("/v1/chat/completions", post(handler))
+            .with_state(calls.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (format!("http://{}/v1/chat/completions", addr), calls)
+    }
+
+    fn fast_policy() -> OverloadRetryPolicy {
+        OverloadRetryPolicy {
+            attempts: 3,
+            base_delay: Duration::from_millis(5),
+        }
+    }
+
+    #[test]
+    fn test_transient_statuses() {
+        for status in [429, 500, 502, 503, 529] {
+            assert!(is_transient(StatusCode::from_u16(status).unwrap()));
+        }
+        for status in [200, 400, 401, 404, 504] {
+            assert!(!is_transient(StatusCode::from_u16(status).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_with_jitter() {
+        let base = Duration::from_millis(100);
+        assert_eq!(backoff_delay(base, 1, 0.0), Duration::from_millis(50));
+        assert_eq!(backoff_delay(base, 1, 0.999_999).as_millis(), 99);
+        assert_eq!(backoff_delay(base, 3, 0.0), Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_fails_twice_then_succeeds() {
+        let (url, calls) = start_mock(2, AxumStatus::SERVICE_UNAVAILABLE, "0").await;
+        let client = reqwest::Client::new();
+
+        let response = send_with_retry(fast_policy(), || client.post(&url).send()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), r#"{"id":"chatcmpl-1"}"#);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_last_failure_forwarded_when_retries_run_out() {
+        let (url, calls) = start_mock(usize::MAX, AxumStatus::TOO_MANY_REQUESTS, "0").await;
+        let client = reqwest::Client::new();
+
+        let response = send_with_retry(fast_policy(), || client.post(&url).send()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_long_retry_after_not_waited_out() {
+        let (url, calls) = start_mock(1, AxumStatus::TOO_MANY_REQUESTS, "3600").await;
+        let client = reqwest::Client::new();
+
+        let response = send_with_retry(fast_policy(), || client.post(&url).send()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_not_retried() {
+        let (url, calls) = start_mock(1, AxumStatus::BAD_REQUEST, "0").await;
+        let client = reqwest::Client::new();
+
+        let response = send_with_retry(fast_policy(), || client.post(&url).send()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
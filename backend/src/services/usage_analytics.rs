@@ -33,6 +33,15 @@ pub struct ModelUsage {
     pub total_cost_idr: i64,
 }
 
+/// Usage grouped by the value of one request metadata key
+#[derive(Debug, Serialize)]
+pub struct MetadataUsage {
+    pub value: String,
+    pub request_count: i64,
+    pub total_tokens: i64,
+    pub total_cost_idr: i64,
+}
+
 /// Daily usage for time series charts
 #[derive(Debug, Serialize)]
 pub struct DailyUsage {
@@ -198,6 +207,52 @@ impl UsageAnalyticsService {
             .collect())
     }
 
+    /// Get usage grouped by the value of metadata `key`, optionally only
+    /// requests where it equals `value`. Requests without the key are left out.
+    pub async fn get_usage_by_metadata(
+        &self,
+        user_id: Uuid,
+        range: &DateRange,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<Vec<MetadataUsage>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                metadata->>$4 as value,
+                COUNT(*)::bigint as request_count,
+                COALESCE(SUM(total_tokens), 0)::bigint as total_tokens,
+                COALESCE(SUM(estimated_cost_idr), 0)::bigint as total_cost_idr
+            FROM proxy_requests
+            WHERE user_id = $1
+              AND created_at >= $2
+              AND created_at <= $3
+              AND status_code < 400
+              AND metadata ? $4
+              AND ($5::text IS NULL OR metadata->>$4 = $5)
+            GROUP BY metadata->>$4
+            ORDER BY request_count DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(range.start)
+        .bind(range.end)
+        .bind(key)
+        .bind(value)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| MetadataUsage {
+                value: r.get("value"),
+                request_count: r.get("request_count"),
+                total_tokens: r.get("total_tokens"),
+                total_cost_idr: r.get("total_cost_idr"),
+            })
+            .collect())
+    }
+
     /// Get daily usage for time series chart
     pub async fn get_daily_usage(
         &self,
@@ -292,3 +347,105 @@ impl UsageAnalyticsService {
         Ok(csv)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PlanTier;
+    use crate::test_support::{insert_proxy_request, insert_user, ProxyRequestFixture};
+
+    // Database-backed tests (see test_support for how to run them)
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_usage_stats_aggregate_successful_requests(pool: PgPool) {
+        let user_id = insert_user(&pool, "dewi@example.com", PlanTier::Starter).await;
+        let other_user = insert_user(&pool, "eko@example.com", PlanTier::Free).await;
+
+        let ok = |model, prompt_tokens, completion_tokens, cost_idr| ProxyRequestFixture {
+            provider: "openai",
+            model,
+            prompt_tokens,
+            completion_tokens,
+            cost_idr,
+            status_code: 200,
+        };
+        insert_proxy_request(&pool, user_id, ok("gpt-4o", 100, 50, 300)).await;
+        insert_proxy_request(&pool, user_id, ok("gpt-4o-mini", 10, 5, 20)).await;
+        insert_proxy_request(&pool, other_user, ok("gpt-4o", 999, 999, 9999)).await;
+        insert_proxy_request(
+            &pool,
+            user_id,
+            ProxyRequestFixture {
+                status_code: 500,
+                ..ok("gpt-4o", 1000, 0, 1000)
+            },
+        )
+        .await;
+
+        let service = UsageAnalyticsService::new(pool);
+        let stats = service.get_usage_stats(user_id, &DateRange::last_7_days()).await.unwrap();
+
+        // Failed requests and other users are excluded
+        assert_eq!(stats.total_requests, 2);
+        assert_eq!(stats.total_input_tokens, 110);
+        assert_eq!(stats.total_output_tokens, 55);
+        assert_eq!(stats.total_tokens, 165);
+        assert_eq!(stats.total_cost_idr, 320);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_usage_grouped_by_metadata_key(pool: PgPool) {
+        use crate::services::transformers::Provider;
+        use crate::services::usage_logger::{UsageLog, UsageLogger};
+
+        let user_id = insert_user(&pool, "tagged@example.com", PlanTier::Pro).await;
+        let log = |tags: &[(&str, &str)], total_tokens| UsageLog {
+            user_id,
+            proxy_key_id: None,
+            provider: Provider::OpenAI,
+            model: "gpt-4o".to_string(),
+            prompt_tokens: total_tokens,
+            completion_tokens: 0,
+            total_tokens,
+            latency_ms: 100,
+            estimated_cost_idr: 10,
+            status_code: 200,
+            error_message: None,
+            is_internal: false,
+            metadata: (!tags.is_empty()).then(|| tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+            provider_request_id: None,
+        };
+        let id = UsageLogger::log_request(&pool, log(&[("feature", "summarize"), ("team", "search")], 100)).await.unwrap();
+        UsageLogger::log_request(&pool, log(&[("feature", "summarize")], 50)).await.unwrap();
+        UsageLogger::log_request(&pool, log(&[("feature", "translate")], 30)).await.unwrap();
+        UsageLogger::log_request(&pool, log(&[], 999)).await.unwrap();
+
+        // Stored as JSONB on the usage log
+        let team: Option<String> = sqlx::query_scalar("SELECT metadata->>'team' FROM proxy_requests WHERE id = $1")
+            .bind(id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(team.as_deref(), Some("search"));
+
+        let service = UsageAnalyticsService::new(pool);
+        let range = DateRange::last_7_days();
+        let groups = service.get_usage_by_metadata(user_id, &range, "feature", None).await.unwrap();
+        let summary: Vec<_> = groups
+            .iter()
+            .map(|g| (g.value.as_str(), g.request_count, g.total_tokens))
+            .collect();
+        assert_eq!(summary, vec![("summarize", 2, 150), ("translate", 1, 30)]);
+
+        let filtered = service
+            .get_usage_by_metadata(user_id, &range, "feature", Some("translate"))
+            .await
+            .unwrap();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].total_tokens, 30);
+
+        assert!(service.get_usage_by_metadata(user_id, &range, "missing", None).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,485 @@
+//! Dead-letter queue for usage logs that failed to persist.
+//!
+//! When the database is briefly unavailable, failed `UsageLog` inserts are
+//! pushed onto a Redis list and replayed by a background task so billing
+//! data isn't dropped during transient outages.
+//!
+//! A replayed log is moved to a processing list and only removed once it is
+//! written, so a crash mid-flush doesn't lose it. Logs the database rejects
+//! for good (e.g. their API key was deleted) are moved to a dead list for
+//! inspection instead of blocking the queue.
+
+use redis::{AsyncCommands, Direction};
+use sqlx::PgPool;
+use std::time::Duration;
+
+use crate::services::usage_logger::{UsageLog, UsageLogger};
+use crate::utils::db_retry;
+
+/// Redis list holding usage logs awaiting retry
+pub const USAGE_DLQ_KEY: &str = "usage:dead_letter";
+
+/// Redis list holding usage logs being replayed
+pub const USAGE_DLQ_PROCESSING_KEY: &str = "usage:dead_letter:processing";
+
+/// Redis list holding usage logs that can never be persisted
+pub const USAGE_DLQ_DEAD_KEY: &str = "usage:dead_letter:dead";
+
+/// Interval between background flush attempts
+pub const DLQ_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum logs replayed per flush
+pub const DLQ_FLUSH_BATCH: usize = 500;
+
+/// Dead-letter queue error
+#[derive(Debug, thiserror::Error)]
+pub enum DeadLetterError {
+    #[error("Redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Destination for usage logs (the database in production)
+// Only used with concrete types inside this crate, so `Send` bounds on
+// the futures aren't needed
+#[allow(async_fn_in_trait)]
+pub trait UsageSink {
+    async fn write(&self, log: &UsageLog) -> Result<(), sqlx::Error>;
+}
+
+impl UsageSink for PgPool {
+    async fn write(&self, log: &UsageLog) -> Result<(), sqlx::Error> {
+        UsageLogger::log_request(self, log.clone()).await.map(|_| ())
+    }
+}
+
+/// A log taken from the queue. It stays in the processing list until it is
+/// acknowledged, released, or buried.
+#[derive(Debug, Clone)]
+pub struct ClaimedLog {
+    pub log: UsageLog,
+    /// Queued form, used to find the entry in the processing list
+    payload: String,
+}
+
+/// Durable queue for usage logs that failed to persist
+#[allow(async_fn_in_trait)]
+pub trait DeadLetterQueue {
+    /// Append a log to the back of the queue
+    async fn push(&self, log: &UsageLog) -> Result<(), DeadLetterError>;
+
+    /// Move the oldest log to the processing list
+    async fn claim(&self) -> Result<Option<ClaimedLog>, DeadLetterError>;
+
+    /// Remove a claimed log once it is persisted
+    async fn ack(&self, claimed: &ClaimedLog) -> Result<(), DeadLetterError>;
+
+    /// Return a claimed log to the front of the queue (retry failed)
+    async fn release(&self, claimed: &ClaimedLog) -> Result<(), DeadLetterError>;
+
+    /// Move a claimed log that can never be persisted to the dead list
+    async fn bury(&self, claimed: &ClaimedLog) -> Result<(), DeadLetterError>;
+
+    /// Return logs left in the processing list by an interrupted flush to
+    /// the front of the queue, keeping their order
+    async fn recover(&self) -> Result<usize, DeadLetterError>;
+}
+
+/// Redis-backed dead-letter queue
+#[derive(Clone)]
+pub struct RedisDeadLetterQueue {
+    redis: redis::Client,
+}
+
+impl RedisDeadLetterQueue {
+    pub fn new(redis: redis::Client) -> Self {
+        Self { redis }
+    }
+
+    /// Atomically move `payload` from the processing list to the front
+    /// (`RPUSH`) or back (`LPUSH`) of `destination`
+    async fn move_claimed(&self, payload: &str, destination: &str, front: bool) -> Result<(), DeadLetterError> {
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+        let mut pipe = redis::pipe();
+        pipe.atomic().lrem(USAGE_DLQ_PROCESSING_KEY, 1, payload).ignore();
+        if front {
+            pipe.rpush(destination, payload).ignore();
+        } else {
+            pipe.lpush(destination, payload).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut conn).await?;
+        Ok(())
+    }
+}
+
+impl DeadLetterQueue for RedisDeadLetterQueue {
+    async fn push(&self, log: &UsageLog) -> Result<(), DeadLetterError> {
+        let payload = serde_json::to_string(log)?;
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+        conn.lpush::<_, _, ()>(USAGE_DLQ_KEY, payload).await?;
+        Ok(())
+    }
+
+    async fn claim(&self) -> Result<Option<ClaimedLog>, DeadLetterError> {
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+        loop {
+            let payload: Option<String> = conn
+                .lmove(USAGE_DLQ_KEY, USAGE_DLQ_PROCESSING_KEY, Direction::Right, Direction::Left)
+                .await?;
+            let Some(payload) = payload else {
+                return Ok(None);
+            };
+            match serde_json::from_str(&payload) {
+                Ok(log) => return Ok(Some(ClaimedLog { log, payload })),
+                Err(e) => {
+                    tracing::error!("Undecodable usage log moved to the dead list: {}", e);
+                    self.move_claimed(&payload, USAGE_DLQ_DEAD_KEY, false).await?;
+                }
+            }
+        }
+    }
+
+    async fn ack(&self, claimed: &ClaimedLog) -> Result<(), DeadLetterError> {
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+        conn.lrem::<_, _, ()>(USAGE_DLQ_PROCESSING_KEY, 1, &claimed.payload).await?;
+        Ok(())
+    }
+
+    async fn release(&self, claimed: &ClaimedLog) -> Result<(), DeadLetterError> {
+        self.move_claimed(&claimed.payload, USAGE_DLQ_KEY, true).await
+    }
+
+    async fn bury(&self, claimed: &ClaimedLog) -> Result<(), DeadLetterError> {
+        self.move_claimed(&claimed.payload, USAGE_DLQ_DEAD_KEY, false).await
+    }
+
+    async fn recover(&self) -> Result<usize, DeadLetterError> {
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+        let mut recovered = 0;
+        // Newest claim first, so the oldest ends up at the front
+        while conn
+            .lmove::<_, _, Option<String>>(USAGE_DLQ_PROCESSING_KEY, USAGE_DLQ_KEY, Direction::Left, Direction::Right)
+            .await?
+            .is_some()
+        {
+            recovered += 1;
+        }
+        Ok(recovered)
+    }
+}
+
+/// Result of a dead-letter flush
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FlushResult {
+    pub persisted: usize,
+    /// Logs moved to the dead list because the database rejected them for good
+    pub dead: usize,
+    pub failed: bool,
+}
+
+/// Persist a usage log, falling back to the dead-letter queue on failure
+pub async fn log_or_enqueue<S, Q>(sink: &S, queue: &Q, log: UsageLog)
+where
+    S: UsageSink,
+    Q: DeadLetterQueue,
+{
+    if let Err(e) = sink.write(&log).await {
+        tracing::warn!(user_id = %log.user_id, "Failed to log usage, queueing for retry: {}", e);
+        if let Err(e) = queue.push(&log).await {
+            tracing::error!(user_id = %log.user_id, "Failed to queue usage log, dropping: {}", e);
+        }
+    }
+}
+
+/// Replay queued usage logs in order. Logs the database rejects for good are
+/// moved to the dead list; any other failure stops the flush.
+pub async fn flush<S, Q>(sink: &S, queue: &Q, max: usize) -> FlushResult
+where
+    S: UsageSink,
+    Q: DeadLetterQueue,
+{
+    let mut result = FlushResult::default();
+
+    while result.persisted + result.dead < max {
+        let claimed = match queue.claim().await {
+            Ok(Some(claimed)) => claimed,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::error!("Failed to read usage dead-letter queue: {}", e);
+                result.failed = true;
+                break;
+            }
+        };
+        let user_id = claimed.log.user_id;
+
+        match sink.write(&claimed.log).await {
+            Ok(()) => {
+                result.persisted += 1;
+                if let Err(e) = queue.ack(&claimed).await {
+                    tracing::error!(user_id = %user_id, "Failed to remove replayed usage log, it may be replayed again: {}", e);
+                    result.failed = true;
+                    break;
+                }
+            }
+            Err(e) if db_retry::is_permanent(&e) => {
+                tracing::error!(user_id = %user_id, "Usage log rejected by the database, moving to the dead list: {}", e);
+                if let Err(e) = queue.bury(&claimed).await {
+                    tracing::error!(user_id = %user_id, "Failed to move usage log to the dead list: {}", e);
+                    result.failed = true;
+                    break;
+                }
+                result.dead += 1;
+            }
+            Err(e) => {
+                tracing::warn!("Usage log retry failed, will retry later: {}", e);
+                if let Err(e) = queue.release(&claimed).await {
+                    tracing::error!(user_id = %user_id, "Failed to requeue usage log, it stays in the processing list: {}", e);
+                }
+                result.failed = true;
+                break;
+            }
+        }
+    }
+
+    result
+}
+
+/// Spawn background task that periodically replays the dead-letter queue.
+/// Logs claimed by a flush that was interrupted (e.g. by a crash) are
+/// returned to the queue first.
+pub fn spawn_retry_worker(pool: PgPool, queue: RedisDeadLetterQueue) {
+    tokio::spawn(async move {
+        match queue.recover().await {
+            Ok(0) => {}
+            Ok(recovered) => tracing::info!(recovered, "Requeued usage logs from an interrupted flush"),
+            Err(e) => tracing::error!("Failed to recover in-flight usage logs: {}", e),
+        }
+
+        let mut interval = tokio::time::interval(DLQ_RETRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            let result = flush(&pool, &queue, DLQ_FLUSH_BATCH).await;
+            if result.persisted > 0 {
+                tracing::info!(persisted = result.persisted, "Replayed queued usage logs");
+            }
+            if result.dead > 0 {
+                tracing::warn!(dead = result.dead, "Moved rejected usage logs to the dead list");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::transformers::Provider;
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    #[derive(Default)]
+    struct MockSink {
+        down: AtomicBool,
+        written: Mutex<Vec<UsageLog>>,
+    }
+
+    impl UsageSink for MockSink {
+        async fn write(&self, log: &UsageLog) -> Result<(), sqlx::Error> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(sqlx::Error::PoolTimedOut);
+            }
+            self.written.lock().unwrap().push(log.clone());
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryQueue {
+        items: Mutex<VecDeque<UsageLog>>,
+        processing: Mutex<Vec<ClaimedLog>>,
+        dead: Mutex<Vec<UsageLog>>,
+    }
+
+    impl MemoryQueue {
+        fn unclaim(&self, claimed: &ClaimedLog) {
+            self.processing.lock().unwrap().retain(|c| c.payload != claimed.payload);
+        }
+    }
+
+    impl DeadLetterQueue for MemoryQueue {
+        async fn push(&self, log: &UsageLog) -> Result<(), DeadLetterError> {
+            self.items.lock().unwrap().push_back(log.clone());
+            Ok(())
+        }
+
+        async fn claim(&self) -> Result<Option<ClaimedLog>, DeadLetterError> {
+            let Some(log) = self.items.lock().unwrap().pop_front() else {
+                return Ok(None);
+            };
+            let claimed = ClaimedLog {
+                payload: serde_json::to_string(&log)?,
+                log,
+            };
+            self.processing.lock().unwrap().push(claimed.clone());
+            Ok(Some(claimed))
+        }
+
+        async fn ack(&self, claimed: &ClaimedLog) -> Result<(), DeadLetterError> {
+            self.unclaim(claimed);
+            Ok(())
+        }
+
+        async fn release(&self, claimed: &ClaimedLog) -> Result<(), DeadLetterError> {
+            self.unclaim(claimed);
+            self.items.lock().unwrap().push_front(claimed.log.clone());
+            Ok(())
+        }
+
+        async fn bury(&self, claimed: &ClaimedLog) -> Result<(), DeadLetterError> {
+            self.unclaim(claimed);
+            self.dead.lock().unwrap().push(claimed.log.clone());
+            Ok(())
+        }
+
+        async fn recover(&self) -> Result<usize, DeadLetterError> {
+            let claimed: Vec<ClaimedLog> = self.processing.lock().unwrap().drain(..).collect();
+            let mut items = self.items.lock().unwrap();
+            for claimed in claimed.iter().rev() {
+                items.push_front(claimed.log.clone());
+            }
+            Ok(claimed.len())
+        }
+    }
+
+    fn sample_log(model: &str) -> UsageLog {
+        UsageLog {
+            user_id: Uuid::new_v4(),
+            proxy_key_id: None,
+            provider: Provider::OpenAI,
+            model: model.to_string(),
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            total_tokens: 15,
+            latency_ms: 120,
+            estimated_cost_idr: 3,
+            status_code: 200,
+            error_message: None,
+            is_internal: false,
+            metadata: None,
+            provider_request_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_successful_write_skips_queue() {
+        let sink = MockSink::default();
+        let queue = MemoryQueue::default();
+
+        log_or_enqueue(&sink, &queue, sample_log("gpt-4")).await;
+
+        assert_eq!(sink.written.lock().unwrap().len(), 1);
+        assert!(queue.items.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_db_failure_enqueues_and_flush_persists() {
+        let sink = MockSink::default();
+        let queue = MemoryQueue::default();
+        sink.down.store(true, Ordering::SeqCst);
+
+        log_or_enqueue(&sink, &queue, sample_log("gpt-4")).await;
+        log_or_enqueue(&sink, &queue, sample_log("gpt-4o")).await;
+
+        assert!(sink.written.lock().unwrap().is_empty());
+        assert_eq!(queue.items.lock().unwrap().len(), 2);
+
+        // Still down: flush stops and keeps the log queued
+        let result = flush(&sink, &queue, DLQ_FLUSH_BATCH).await;
+        assert_eq!(result, FlushResult { persisted: 0, dead: 0, failed: true });
+        assert_eq!(queue.items.lock().unwrap().len(), 2);
+        assert!(queue.processing.lock().unwrap().is_empty());
+
+        // Database recovers: flush persists logs in order
+        sink.down.store(false, Ordering::SeqCst);
+        let result = flush(&sink, &queue, DLQ_FLUSH_BATCH).await;
+        assert_eq!(result, FlushResult { persisted: 2, dead: 0, failed: false });
+        assert!(queue.items.lock().unwrap().is_empty());
+        assert!(queue.processing.lock().unwrap().is_empty());
+
+        let written = sink.written.lock().unwrap();
+        assert_eq!(written[0].model, "gpt-4");
+        assert_eq!(written[1].model, "gpt-4o");
+    }
+
+    #[tokio::test]
+    async fn test_flush_respects_batch_limit() {
+        let sink = MockSink::default();
+        let queue = MemoryQueue::default();
+        for _ in 0..3 {
+            queue.push(&sample_log("gpt-4")).await.unwrap();
+        }
+
+        let result = flush(&sink, &queue, 2).await;
+
+        assert_eq!(result.persisted, 2);
+        assert_eq!(queue.items.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_flush_is_recovered_in_order() {
+        let sink = MockSink::default();
+        let queue = MemoryQueue::default();
+        for model in ["gpt-4", "gpt-4o", "gpt-4o-mini"] {
+            queue.push(&sample_log(model)).await.unwrap();
+        }
+
+        // Claimed but never acknowledged, as when the process dies mid-write
+        queue.claim().await.unwrap().unwrap();
+        queue.claim().await.unwrap().unwrap();
+        assert_eq!(queue.items.lock().unwrap().len(), 1);
+
+        assert_eq!(queue.recover().await.unwrap(), 2);
+        let result = flush(&sink, &queue, DLQ_FLUSH_BATCH).await;
+
+        assert_eq!(result.persisted, 3);
+        let models: Vec<String> = sink.written.lock().unwrap().iter().map(|log| log.model.clone()).collect();
+        assert_eq!(models, ["gpt-4", "gpt-4o", "gpt-4o-mini"]);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_rejected_log_moved_to_dead_list(pool: PgPool) {
+        let user_id = crate::test_support::insert_user(&pool, "dlq@example.com", crate::models::PlanTier::Free).await;
+        let queue = MemoryQueue::default();
+        let log = |model: &str| UsageLog {
+            user_id,
+            ..sample_log(model)
+        };
+        queue.push(&log("gpt-4")).await.unwrap();
+        // Its API key was deleted while the log sat in the queue
+        queue
+            .push(&UsageLog {
+                proxy_key_id: Some(Uuid::new_v4()),
+                ..log("gpt-4o")
+            })
+            .await
+            .unwrap();
+        queue.push(&log("gpt-4o-mini")).await.unwrap();
+
+        let result = flush(&pool, &queue, DLQ_FLUSH_BATCH).await;
+
+        assert_eq!(result, FlushResult { persisted: 2, dead: 1, failed: false });
+        assert!(queue.items.lock().unwrap().is_empty());
+        assert!(queue.processing.lock().unwrap().is_empty());
+        assert_eq!(queue.dead.lock().unwrap()[0].model, "gpt-4o");
+        let logged: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM proxy_requests WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(logged, 2);
+    }
+}
@@ -0,0 +1,153 @@
+//! Live usage events for dashboards.
+//!
+//! Every usage log is published to a per-user Redis pub/sub channel as it is
+//! logged; `GET /usage/stream` relays that channel to the browser as
+//! server-sent events. Publishing is best effort: a Redis outage never
+//! affects proxying or usage persistence.
+
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::services::transformers::Provider;
+use crate::services::usage_logger::UsageLog;
+
+/// SSE event name for a completed proxy request
+pub const REQUEST_COMPLETED_EVENT: &str = "request_completed";
+
+/// Redis pub/sub channel carrying a user's usage events
+pub fn usage_channel(user_id: Uuid) -> String {
+    format!("usage:events:{}", user_id)
+}
+
+/// Usage event error
+#[derive(Debug, thiserror::Error)]
+pub enum UsageEventError {
+    #[error("Redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// A completed request, as shown on the live dashboard
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageEvent {
+    pub provider: Provider,
+    pub model: String,
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub total_tokens: i32,
+    pub estimated_cost_idr: i64,
+    pub latency_ms: i32,
+    pub status_code: i16,
+    pub completed_at: DateTime<Utc>,
+}
+
+impl UsageEvent {
+    pub fn from_log(log: &UsageLog, completed_at: DateTime<Utc>) -> Self {
+        Self {
+            provider: log.provider,
+            model: log.model.clone(),
+            prompt_tokens: log.prompt_tokens,
+            completion_tokens: log.completion_tokens,
+            total_tokens: log.total_tokens,
+            estimated_cost_idr: log.estimated_cost_idr,
+            latency_ms: log.latency_ms,
+            status_code: log.status_code,
+            completed_at,
+        }
+    }
+}
+
+/// Publish a usage log to its user's channel; returns the number of
+/// dashboards that received it
+pub async fn publish(redis: &redis::Client, log: &UsageLog) -> Result<usize, UsageEventError> {
+    let payload = serde_json::to_string(&UsageEvent::from_log(log, Utc::now()))?;
+    let mut conn = redis.get_multiplexed_async_connection().await?;
+    Ok(conn.publish(usage_channel(log.user_id), payload).await?)
+}
+
+/// Subscribe to a user's usage events. The subscription is active once this
+/// returns, and ends when the stream is dropped (its connection closes).
+pub async fn subscribe(
+    redis: &redis::Client,
+    user_id: Uuid,
+) -> Result<impl Stream<Item = UsageEvent>, UsageEventError> {
+    let mut pubsub = redis.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(usage_channel(user_id)).await?;
+
+    Ok(pubsub.into_on_message().filter_map(|msg| async move {
+        let payload: String = msg.get_payload().ok()?;
+        match serde_json::from_str(&payload) {
+            Ok(event) => Some(event),
+            Err(e) => {
+                tracing::warn!(channel = msg.get_channel_name(), "Ignoring malformed usage event: {}", e);
+                None
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_log(user_id: Uuid) -> UsageLog {
+        UsageLog {
+            user_id,
+            proxy_key_id: None,
+            provider: Provider::Anthropic,
+            model: "claude-3-5-sonnet-20241022".to_string(),
+            prompt_tokens: 120,
+            completion_tokens: 80,
+            total_tokens: 200,
+            latency_ms: 950,
+            estimated_cost_idr: 24,
+            status_code: 200,
+            error_message: Some("not sent to dashboards".to_string()),
+            is_internal: false,
+            metadata: None,
+            provider_request_id: None,
+        }
+    }
+
+    #[test]
+    fn test_event_carries_model_tokens_and_cost() {
+        let user_id = Uuid::new_v4();
+        let event = UsageEvent::from_log(&sample_log(user_id), Utc::now());
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["provider"], "anthropic");
+        assert_eq!(json["model"], "claude-3-5-sonnet-20241022");
+        assert_eq!(json["total_tokens"], 200);
+        assert_eq!(json["estimated_cost_idr"], 24);
+        assert!(json.get("error_message").is_none());
+        assert!(json.get("user_id").is_none());
+
+        assert_eq!(usage_channel(user_id), format!("usage:events:{}", user_id));
+    }
+
+    // Redis-backed tests; run with REDIS_URL set and `--ignored`
+
+    #[tokio::test]
+    #[ignore = "requires REDIS_URL"]
+    async fn test_published_log_reaches_only_its_user() {
+        let client = redis::Client::open(std::env::var("REDIS_URL").expect("REDIS_URL")).unwrap();
+        let user_id = Uuid::new_v4();
+        let events = subscribe(&client, user_id).await.unwrap();
+        futures::pin_mut!(events);
+
+        publish(&client, &sample_log(Uuid::new_v4())).await.unwrap();
+        assert_eq!(publish(&client, &sample_log(user_id)).await.unwrap(), 1);
+
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.model, "claude-3-5-sonnet-20241022");
+        assert_eq!(event.total_tokens, 200);
+    }
+}
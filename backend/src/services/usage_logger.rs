@@ -9,7 +9,11 @@ use serde::{Deserialize, Serialize};
 use sqlx::{PgPool, Row};
 use uuid::Uuid;
 
+use crate::services::request_metadata::RequestMetadata;
+use crate::services::task_manager::TaskManager;
 use crate::services::transformers::Provider;
+use crate::services::usage_dlq::{log_or_enqueue, RedisDeadLetterQueue};
+use crate::services::usage_events;
 
 /// Usage log entry for a proxy request
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,8 +29,23 @@ pub struct UsageLog {
     pub estimated_cost_idr: i64,
     pub status_code: i16,
     pub error_message: Option<String>,
+    /// Request made with an unmetered internal key
+    #[serde(default)]
+    pub is_internal: bool,
+    /// Client-supplied tags from the request's `metadata` field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<RequestMetadata>,
+    /// Provider's id for the request, quoted in support escalations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_request_id: Option<String>,
 }
 
+/// OpenAI service tier with discounted, slower processing
+pub const FLEX_SERVICE_TIER: &str = "flex";
+
+/// Flex processing price as a percentage of the standard price
+const FLEX_PRICE_PERCENT: i64 = 50;
+
 /// Provider pricing configuration (per 1M tokens in IDR)
 #[derive(Debug, Clone)]
 pub struct ProviderPricing {
@@ -119,7 +138,7 @@ impl TokenCounter {
     /// Count tokens from messages
     pub fn count_message_tokens(messages: &[crate::services::transformers::Message]) -> i32 {
         messages.iter()
-            .map(|m| Self::estimate_tokens(&m.content) + Self::estimate_tokens(&m.role) + 4)
+            .map(|m| Self::estimate_tokens(&m.content.text()) + Self::estimate_tokens(&m.role) + 4)
             .sum::<i32>() + 3 // Base overhead
     }
 }
@@ -144,9 +163,10 @@ impl UsageLogger {
             INSERT INTO proxy_requests (
                 user_id, proxy_key_id, provider, model,
                 prompt_tokens, completion_tokens, total_tokens,
-                latency_ms, estimated_cost_idr, status_code, error_message
+                latency_ms, estimated_cost_idr, status_code, error_message, is_internal, metadata,
+                provider_request_id
             )
-            VALUES ($1, $2, $3::ai_provider, $4, $5, $6, $7, $8, $9, $10, $11)
+            VALUES ($1, $2, $3::ai_provider, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13::jsonb, $14)
             RETURNING id
             "#,
         )
@@ -161,6 +181,9 @@ impl UsageLogger {
         .bind(log.estimated_cost_idr)
         .bind(log.status_code as i32)
         .bind(&log.error_message)
+        .bind(log.is_internal)
+        .bind(log.metadata.as_ref().and_then(|metadata| serde_json::to_string(metadata).ok()))
+        .bind(&log.provider_request_id)
         .fetch_one(pool)
         .await?;
 
@@ -169,6 +192,22 @@ impl UsageLogger {
         Ok(id)
     }
 
+    /// Calculate estimated cost in IDR for a request served on an OpenAI
+    /// service tier; `flex` is billed at half the standard token price
+    pub fn calculate_tier_cost(
+        provider: Provider,
+        model: &str,
+        prompt_tokens: i32,
+        completion_tokens: i32,
+        service_tier: Option<&str>,
+    ) -> i64 {
+        let cost = Self::calculate_cost(provider, model, prompt_tokens, completion_tokens);
+        match (provider, service_tier) {
+            (Provider::OpenAI, Some(FLEX_SERVICE_TIER)) => cost * FLEX_PRICE_PERCENT / 100,
+            _ => cost,
+        }
+    }
+
     /// Calculate estimated cost in IDR
     /// Requirements: 5.2
     pub fn calculate_cost(
@@ -186,11 +225,15 @@ impl UsageLogger {
     }
 
     /// Spawn async logging task to avoid blocking response
+    /// Failed writes are queued for retry in the dead-letter queue, and the
+    /// log is published to the user's live usage stream
     /// Requirements: 5.3
-    pub fn log_async(pool: PgPool, log: UsageLog) {
-        tokio::spawn(async move {
-            if let Err(e) = Self::log_request(&pool, log).await {
-                tracing::error!("Failed to log usage: {}", e);
+    pub fn log_async(tasks: &TaskManager, pool: PgPool, redis: redis::Client, log: UsageLog) {
+        tasks.spawn(async move {
+            let dlq = RedisDeadLetterQueue::new(redis.clone());
+            log_or_enqueue(&pool, &dlq, log.clone()).await;
+            if let Err(e) = usage_events::publish(&redis, &log).await {
+                tracing::debug!(user_id = %log.user_id, "Failed to publish usage event: {}", e);
             }
         });
     }
@@ -237,6 +280,27 @@ mod tests {
         assert!(cost < 100);
     }
 
+    #[test]
+    fn test_flex_service_tier_halves_openai_cost() {
+        let standard = UsageLogger::calculate_cost(Provider::OpenAI, "gpt-4o", 1_000_000, 1_000_000);
+        let flex = UsageLogger::calculate_tier_cost(Provider::OpenAI, "gpt-4o", 1_000_000, 1_000_000, Some("flex"));
+        assert_eq!(flex, standard / 2);
+
+        for tier in [None, Some("auto"), Some("default")] {
+            assert_eq!(
+                UsageLogger::calculate_tier_cost(Provider::OpenAI, "gpt-4o", 1_000_000, 1_000_000, tier),
+                standard
+            );
+        }
+
+        // Tiers are OpenAI-only
+        let claude = UsageLogger::calculate_cost(Provider::Anthropic, "claude-3-5-sonnet-20241022", 1000, 1000);
+        assert_eq!(
+            UsageLogger::calculate_tier_cost(Provider::Anthropic, "claude-3-5-sonnet-20241022", 1000, 1000, Some("flex")),
+            claude
+        );
+    }
+
     #[test]
     fn test_pricing_tiers() {
         // GPT-4 should be more expensive than GPT-3.5
@@ -252,11 +316,118 @@ mod tests {
         use crate::services::transformers::Message;
         
         let messages = vec![
-            Message { role: "user".to_string(), content: "Hello".to_string() },
-            Message { role: "assistant".to_string(), content: "Hi there!".to_string() },
+            Message { role: "user".to_string(), content: "Hello".into(), tool_calls: None, tool_call_id: None, name: None },
+            Message { role: "assistant".to_string(), content: "Hi there!".into(), tool_calls: None, tool_call_id: None, name: None },
         ];
         
         let count = TokenCounter::count_message_tokens(&messages);
         assert!(count > 0);
     }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_internal_usage_is_flagged(pool: PgPool) {
+        use crate::models::PlanTier;
+        use crate::test_support::insert_user;
+
+        let user_id = insert_user(&pool, "probe@example.com", PlanTier::Free).await;
+        let log = UsageLog {
+            user_id,
+            proxy_key_id: None,
+            provider: Provider::OpenAI,
+            model: "gpt-4o-mini".to_string(),
+            prompt_tokens: 5,
+            completion_tokens: 1,
+            total_tokens: 6,
+            latency_ms: 80,
+            estimated_cost_idr: 1,
+            status_code: 200,
+            error_message: None,
+            is_internal: true,
+            metadata: None,
+            provider_request_id: None,
+        };
+
+        let id = UsageLogger::log_request(&pool, log).await.unwrap();
+
+        let is_internal: bool = sqlx::query_scalar("SELECT is_internal FROM proxy_requests WHERE id = $1")
+            .bind(id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(is_internal);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_provider_request_id_stored(pool: PgPool) {
+        use crate::models::PlanTier;
+        use crate::test_support::insert_user;
+
+        let user_id = insert_user(&pool, "escalation@example.com", PlanTier::Free).await;
+        let log = UsageLog {
+            user_id,
+            proxy_key_id: None,
+            provider: Provider::Qwen,
+            model: "qwen-turbo".to_string(),
+            prompt_tokens: 3,
+            completion_tokens: 1,
+            total_tokens: 4,
+            latency_ms: 95,
+            estimated_cost_idr: 1,
+            status_code: 200,
+            error_message: None,
+            is_internal: false,
+            metadata: None,
+            provider_request_id: Some("d3b5c3a1-6f0e-9c8e-8f3a-2b7e4c1d0a99".to_string()),
+        };
+
+        let id = UsageLogger::log_request(&pool, log).await.unwrap();
+
+        let stored: Option<String> = sqlx::query_scalar("SELECT provider_request_id FROM proxy_requests WHERE id = $1")
+            .bind(id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored.as_deref(), Some("d3b5c3a1-6f0e-9c8e-8f3a-2b7e4c1d0a99"));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_spawned_log_is_awaited_on_shutdown(pool: PgPool) {
+        use crate::models::PlanTier;
+        use crate::test_support::insert_user;
+
+        let user_id = insert_user(&pool, "shutdown@example.com", PlanTier::Free).await;
+        let log = UsageLog {
+            user_id,
+            proxy_key_id: None,
+            provider: Provider::Anthropic,
+            model: "claude-3-haiku-20240307".to_string(),
+            prompt_tokens: 12,
+            completion_tokens: 3,
+            total_tokens: 15,
+            latency_ms: 120,
+            estimated_cost_idr: 2,
+            status_code: 200,
+            error_message: None,
+            is_internal: false,
+            metadata: None,
+            provider_request_id: None,
+        };
+
+        let tasks = TaskManager::default();
+        // Redis is unreachable; publishing fails open after the write
+        let redis = redis::Client::open("redis://127.0.0.1:1").unwrap();
+        UsageLogger::log_async(&tasks, pool.clone(), redis, log);
+
+        assert_eq!(tasks.shutdown(std::time::Duration::from_secs(10)).await, 0);
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM proxy_requests WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
 }
@@ -0,0 +1,125 @@
+//! Per-request usage receipt returned in a response header.
+//!
+//! Non-streaming completions can carry `X-Webrana-Usage`: base64-encoded
+//! JSON with the request's token counts, estimated cost, provider and
+//! latency, priced the same way as the usage log, so clients can account
+//! per request without calling the usage API.
+//!
+//! Configuration:
+//! - `USAGE_RECEIPT_HEADER`: add the receipt to non-streaming responses (default `false`)
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use crate::services::transformers::Provider;
+use crate::services::usage_logger::UsageLogger;
+
+/// Response header carrying the encoded receipt
+pub const USAGE_RECEIPT_HEADER: &str = "x-webrana-usage";
+
+/// Usage receipt configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UsageReceiptConfig {
+    pub enabled: bool,
+}
+
+impl UsageReceiptConfig {
+    /// Load receipt configuration from environment variables
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Load receipt configuration using a custom variable lookup
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let enabled = lookup("USAGE_RECEIPT_HEADER")
+            .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        Self { enabled }
+    }
+}
+
+/// Accounting for one completed request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageReceipt {
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub total_tokens: i32,
+    pub cost_idr: i64,
+    pub provider: String,
+    pub latency_ms: u64,
+}
+
+impl UsageReceipt {
+    /// Build a receipt from an OpenAI-format completion body; `None` when it
+    /// has no usage
+    pub fn from_completion(provider: Provider, completion: &serde_json::Value, latency_ms: u64) -> Option<Self> {
+        let usage = completion.get("usage").filter(|usage| usage.is_object())?;
+        let prompt_tokens = usage["prompt_tokens"].as_i64().unwrap_or(0) as i32;
+        let completion_tokens = usage["completion_tokens"].as_i64().unwrap_or(0) as i32;
+        let model = completion["model"].as_str().unwrap_or_default();
+
+        Some(Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            cost_idr: UsageLogger::calculate_tier_cost(
+                provider,
+                model,
+                prompt_tokens,
+                completion_tokens,
+                completion["service_tier"].as_str(),
+            ),
+            provider: provider.name().to_lowercase(),
+            latency_ms,
+        })
+    }
+
+    /// Header value: the receipt as base64-encoded JSON
+    pub fn encode(&self) -> String {
+        STANDARD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    /// Decode a header value produced by `encode`
+    #[cfg(test)]
+    pub fn decode(value: &str) -> Option<Self> {
+        let bytes = STANDARD.decode(value.trim()).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_by_default() {
+        assert!(!UsageReceiptConfig::from_lookup(|_| None).enabled);
+        assert!(UsageReceiptConfig::from_lookup(|_| Some("true".to_string())).enabled);
+    }
+
+    #[test]
+    fn test_receipt_round_trips_through_header_value() {
+        let completion = serde_json::json!({
+            "model": "claude-3-haiku-20240307",
+            "usage": { "prompt_tokens": 120, "completion_tokens": 30, "total_tokens": 150 }
+        });
+        let receipt = UsageReceipt::from_completion(Provider::Anthropic, &completion, 420).unwrap();
+
+        assert_eq!(receipt.total_tokens, 150);
+        assert_eq!(receipt.provider, "anthropic");
+        assert_eq!(
+            receipt.cost_idr,
+            UsageLogger::calculate_cost(Provider::Anthropic, "claude-3-haiku-20240307", 120, 30)
+        );
+        assert_eq!(UsageReceipt::decode(&receipt.encode()), Some(receipt));
+    }
+
+    #[test]
+    fn test_no_receipt_without_usage() {
+        let completion = serde_json::json!({ "model": "gpt-4o", "choices": [] });
+        assert_eq!(UsageReceipt::from_completion(Provider::OpenAI, &completion, 10), None);
+    }
+}
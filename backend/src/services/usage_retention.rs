@@ -0,0 +1,256 @@
+//! Usage data retention and user-initiated purge.
+//!
+//! Old `proxy_requests` rows are rolled up into `usage_monthly_aggregates`
+//! and then deleted, so per-request detail expires while monthly billing
+//! totals are kept. Users can also purge all of their own request history.
+//!
+//! Configuration:
+//! - `USAGE_RETENTION_DAYS`: days of per-request history kept (default `365`, `0` disables)
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Default per-request history retention
+pub const DEFAULT_RETENTION_DAYS: u32 = 365;
+
+/// Interval between retention runs
+pub const RETENTION_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+/// Outcome of a purge
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PurgeResult {
+    pub requests_deleted: u64,
+    pub captures_deleted: u64,
+}
+
+/// Read the retention window from `USAGE_RETENTION_DAYS`; `None` when disabled
+pub fn retention_days_from_env() -> Option<u32> {
+    retention_days_from_lookup(|key| std::env::var(key).ok())
+}
+
+/// Read the retention window using a custom variable lookup
+pub fn retention_days_from_lookup<F>(lookup: F) -> Option<u32>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let days = lookup("USAGE_RETENTION_DAYS")
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+
+    (days > 0).then_some(days)
+}
+
+/// Rows created before this instant are past retention
+pub fn retention_cutoff(now: DateTime<Utc>, days: u32) -> DateTime<Utc> {
+    now - ChronoDuration::days(days as i64)
+}
+
+/// Roll up and delete request rows, optionally for a single user and/or
+/// only before a cutoff. Successful requests are added to the monthly
+/// aggregates in the same transaction as the delete.
+async fn rollup_and_delete(
+    pool: &PgPool,
+    user_id: Option<Uuid>,
+    before: Option<DateTime<Utc>>,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO usage_monthly_aggregates
+            (user_id, month, provider, request_count, prompt_tokens, completion_tokens, total_tokens, estimated_cost_idr)
+        SELECT
+            user_id,
+            DATE_TRUNC('month', created_at AT TIME ZONE 'UTC')::date,
+            provider,
+            COUNT(*),
+            COALESCE(SUM(prompt_tokens), 0),
+            COALESCE(SUM(completion_tokens), 0),
+            COALESCE(SUM(total_tokens), 0),
+            COALESCE(SUM(estimated_cost_idr), 0)
+        FROM proxy_requests
+        WHERE ($1::uuid IS NULL OR user_id = $1)
+          AND ($2::timestamptz IS NULL OR created_at < $2)
+          AND status_code < 400
+        GROUP BY 1, 2, 3
+        ON CONFLICT (user_id, month, provider) DO UPDATE SET
+            request_count = usage_monthly_aggregates.request_count + EXCLUDED.request_count,
+            prompt_tokens = usage_monthly_aggregates.prompt_tokens + EXCLUDED.prompt_tokens,
+            completion_tokens = usage_monthly_aggregates.completion_tokens + EXCLUDED.completion_tokens,
+            total_tokens = usage_monthly_aggregates.total_tokens + EXCLUDED.total_tokens,
+            estimated_cost_idr = usage_monthly_aggregates.estimated_cost_idr + EXCLUDED.estimated_cost_idr,
+            updated_at = NOW()
+        "#,
+    )
+    .bind(user_id)
+    .bind(before)
+    .execute(&mut *tx)
+    .await?;
+
+    let deleted = sqlx::query(
+        r#"
+        DELETE FROM proxy_requests
+        WHERE ($1::uuid IS NULL OR user_id = $1)
+          AND ($2::timestamptz IS NULL OR created_at < $2)
+        "#,
+    )
+    .bind(user_id)
+    .bind(before)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+    Ok(deleted)
+}
+
+/// Purge request rows older than the cutoff for all users
+pub async fn purge_expired(pool: &PgPool, cutoff: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+    rollup_and_delete(pool, None, Some(cutoff)).await
+}
+
+/// Purge all of a user's request history and captured content,
+/// keeping monthly billing aggregates
+pub async fn purge_user(pool: &PgPool, user_id: Uuid) -> Result<PurgeResult, sqlx::Error> {
+    let requests_deleted = rollup_and_delete(pool, Some(user_id), None).await?;
+
+    let captures_deleted = sqlx::query("DELETE FROM debug_captures WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(PurgeResult {
+        requests_deleted,
+        captures_deleted,
+    })
+}
+
+/// Spawn background task that applies the retention window daily
+pub fn spawn_retention_worker(pool: PgPool, days: u32) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            let cutoff = retention_cutoff(Utc::now(), days);
+            match purge_expired(&pool, cutoff).await {
+                Ok(deleted) if deleted > 0 => {
+                    tracing::info!(deleted, %cutoff, "Purged usage rows past retention");
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Usage retention run failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PlanTier;
+    use crate::test_support::{insert_proxy_request, insert_user, ProxyRequestFixture};
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_retention_cutoff() {
+        let now = Utc.with_ymd_and_hms(2024, 12, 11, 10, 0, 0).unwrap();
+        assert_eq!(
+            retention_cutoff(now, 90),
+            Utc.with_ymd_and_hms(2024, 9, 12, 10, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_retention_days_from_lookup() {
+        assert_eq!(retention_days_from_lookup(|_| None), Some(DEFAULT_RETENTION_DAYS));
+        assert_eq!(retention_days_from_lookup(|_| Some("30".to_string())), Some(30));
+        assert_eq!(retention_days_from_lookup(|_| Some("0".to_string())), None);
+    }
+
+    // Database-backed tests (see test_support for how to run them)
+
+    fn request(cost_idr: i64, status_code: i32) -> ProxyRequestFixture<'static> {
+        ProxyRequestFixture {
+            provider: "openai",
+            model: "gpt-4o",
+            prompt_tokens: 100,
+            completion_tokens: 50,
+            cost_idr,
+            status_code,
+        }
+    }
+
+    async fn backdate_all(pool: &PgPool, user_id: Uuid, days: i32) {
+        sqlx::query("UPDATE proxy_requests SET created_at = NOW() - make_interval(days => $2) WHERE user_id = $1")
+            .bind(user_id)
+            .bind(days)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    async fn aggregate_cost(pool: &PgPool, user_id: Uuid) -> (i64, i64) {
+        sqlx::query_as(
+            "SELECT COALESCE(SUM(request_count), 0)::bigint, COALESCE(SUM(estimated_cost_idr), 0)::bigint FROM usage_monthly_aggregates WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn request_count(pool: &PgPool, user_id: Uuid) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM proxy_requests WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_retention_deletes_only_rows_past_cutoff(pool: PgPool) {
+        let old_user = insert_user(&pool, "lama@example.com", PlanTier::Free).await;
+        let new_user = insert_user(&pool, "baru@example.com", PlanTier::Free).await;
+
+        insert_proxy_request(&pool, old_user, request(300, 200)).await;
+        insert_proxy_request(&pool, old_user, request(999, 500)).await;
+        backdate_all(&pool, old_user, 100).await;
+        insert_proxy_request(&pool, new_user, request(200, 200)).await;
+
+        let deleted = purge_expired(&pool, retention_cutoff(Utc::now(), 90)).await.unwrap();
+
+        assert_eq!(deleted, 2);
+        assert_eq!(request_count(&pool, old_user).await, 0);
+        assert_eq!(request_count(&pool, new_user).await, 1);
+        // Only the successful request is billed
+        assert_eq!(aggregate_cost(&pool, old_user).await, (1, 300));
+        assert_eq!(aggregate_cost(&pool, new_user).await, (0, 0));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_user_purge_keeps_billing_aggregates(pool: PgPool) {
+        let user_id = insert_user(&pool, "rina@example.com", PlanTier::Pro).await;
+        let other_user = insert_user(&pool, "tono@example.com", PlanTier::Pro).await;
+
+        insert_proxy_request(&pool, user_id, request(300, 200)).await;
+        insert_proxy_request(&pool, user_id, request(200, 200)).await;
+        insert_proxy_request(&pool, other_user, request(100, 200)).await;
+
+        let result = purge_user(&pool, user_id).await.unwrap();
+        assert_eq!(result.requests_deleted, 2);
+
+        // A second purge in the same month adds to the aggregate rather than replacing it
+        insert_proxy_request(&pool, user_id, request(50, 200)).await;
+        purge_user(&pool, user_id).await.unwrap();
+
+        assert_eq!(request_count(&pool, user_id).await, 0);
+        assert_eq!(aggregate_cost(&pool, user_id).await, (3, 550));
+        // Other users are untouched
+        assert_eq!(request_count(&pool, other_user).await, 1);
+    }
+}
@@ -0,0 +1,591 @@
+//! Outbound usage threshold webhooks.
+//!
+//! Users can register a webhook URL that is notified when their monthly
+//! request usage crosses 50%, 80% and 100% of the plan limit. Payloads are
+//! signed with a per-user secret (HMAC-SHA256 over `"{timestamp}.{body}"`)
+//! and delivered with retries. Each threshold is sent at most once per user
+//! and month, tracked in `usage_webhook_deliveries`.
+//!
+//! Webhook URLs must resolve to public addresses, so users can't point the
+//! proxy at its own network or the cloud metadata service.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use reqwest::Client;
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::net::IpAddr;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::services::task_manager::TaskManager;
+
+/// Usage percentages that trigger a notification
+pub const USAGE_THRESHOLDS: [u8; 3] = [50, 80, 100];
+
+/// Event name sent in the payload
+pub const THRESHOLD_EVENT: &str = "usage.threshold_crossed";
+
+/// Header carrying `sha256=<hex>` of the signed payload
+pub const SIGNATURE_HEADER: &str = "X-Webrana-Signature";
+
+/// Header carrying the unix timestamp included in the signature
+pub const TIMESTAMP_HEADER: &str = "X-Webrana-Timestamp";
+
+/// Prefix of generated signing secrets
+pub const SECRET_PREFIX: &str = "whsec_";
+
+/// Per-attempt request timeout
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A user's webhook endpoint
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct UsageWebhook {
+    pub url: String,
+    pub secret: String,
+}
+
+/// Payload posted when a threshold is crossed
+#[derive(Debug, Clone, Serialize)]
+pub struct ThresholdEvent {
+    pub event: &'static str,
+    pub user_id: Uuid,
+    pub threshold_percent: u8,
+    pub used: i64,
+    pub limit: i64,
+    /// Billing month, e.g. `2024-12`
+    pub period: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Delivery retry policy
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    /// Delay before the second attempt; doubled for each further attempt
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            base_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Usage webhook errors
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("Invalid webhook URL: {0}")]
+    InvalidUrl(&'static str),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Delivery failed after {attempts} attempts: {last_error}")]
+    DeliveryFailed { attempts: u32, last_error: String },
+}
+
+/// Highest threshold crossed when usage moves from `used_before` to
+/// `used_after`, if any
+pub fn crossed_threshold(used_before: i64, used_after: i64, limit: i64) -> Option<u8> {
+    if limit <= 0 {
+        return None;
+    }
+
+    USAGE_THRESHOLDS
+        .iter()
+        .rev()
+        .copied()
+        .find(|&pct| {
+            let mark = i64::from(pct) * limit;
+            used_before * 100 < mark && used_after * 100 >= mark
+        })
+}
+
+/// Whether `ip` is reachable on the public internet. Loopback, private,
+/// link-local (which includes cloud metadata at 169.254.169.254), shared
+/// (100.64.0.0/10) and other special-purpose ranges are not.
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || first == 0
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_address(IpAddr::V4(mapped)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local (fc00::/7) and link-local (fe80::/10)
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Only HTTPS endpoints whose host resolves to public addresses are accepted
+pub async fn validate_webhook_url(url: &str) -> Result<(), WebhookError> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| WebhookError::InvalidUrl("not a valid URL"))?;
+    if parsed.scheme() != "https" {
+        return Err(WebhookError::InvalidUrl("must use https"));
+    }
+    let Some(host) = parsed.host_str() else {
+        return Err(WebhookError::InvalidUrl("missing host"));
+    };
+
+    // IPv6 literals keep their brackets in the URL
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<IpAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| WebhookError::InvalidUrl("host could not be resolved"))?
+        .map(|addr| addr.ip())
+        .collect();
+    if addresses.is_empty() || !addresses.into_iter().all(is_public_address) {
+        return Err(WebhookError::InvalidUrl("host must resolve to a public address"));
+    }
+    Ok(())
+}
+
+/// Signature header value for a payload: `sha256=<hex>`
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// First day of the billing month containing `now`
+fn period_start(now: DateTime<Utc>) -> NaiveDate {
+    NaiveDate::from_ymd_opt(now.year(), now.month(), 1).expect("first of month is valid")
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 24];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", SECRET_PREFIX, URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Get a user's webhook, if configured
+pub async fn get_webhook(pool: &PgPool, user_id: Uuid) -> Result<Option<UsageWebhook>, WebhookError> {
+    let webhook = sqlx::query_as("SELECT url, secret FROM usage_webhooks WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(webhook)
+}
+
+/// Set a user's webhook URL. A new signing secret is generated each time.
+pub async fn set_webhook(pool: &PgPool, user_id: Uuid, url: &str) -> Result<UsageWebhook, WebhookError> {
+    validate_webhook_url(url).await?;
+
+    let webhook = UsageWebhook {
+        url: url.to_string(),
+        secret: generate_secret(),
+    };
+    sqlx::query(
+        r#"
+        INSERT INTO usage_webhooks (user_id, url, secret)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id) DO UPDATE SET url = EXCLUDED.url, secret = EXCLUDED.secret
+        "#,
+    )
+    .bind(user_id)
+    .bind(&webhook.url)
+    .bind(&webhook.secret)
+    .execute(pool)
+    .await?;
+
+    Ok(webhook)
+}
+
+/// Remove a user's webhook; returns whether one existed
+pub async fn delete_webhook(pool: &PgPool, user_id: Uuid) -> Result<bool, WebhookError> {
+    let result = sqlx::query("DELETE FROM usage_webhooks WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record that a threshold is being notified for this period.
+/// Returns `false` if it was already sent (or is being sent).
+async fn claim_threshold(
+    pool: &PgPool,
+    user_id: Uuid,
+    period: NaiveDate,
+    threshold: u8,
+) -> Result<bool, WebhookError> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO usage_webhook_deliveries (user_id, period, threshold_percent)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(user_id)
+    .bind(period)
+    .bind(i16::from(threshold))
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Drop a claim whose delivery failed, so a later crossing can retry it
+async fn release_threshold(
+    pool: &PgPool,
+    user_id: Uuid,
+    period: NaiveDate,
+    threshold: u8,
+) -> Result<(), WebhookError> {
+    sqlx::query(
+        "DELETE FROM usage_webhook_deliveries WHERE user_id = $1 AND period = $2 AND threshold_percent = $3",
+    )
+    .bind(user_id)
+    .bind(period)
+    .bind(i16::from(threshold))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// POST a signed event, retrying on transport errors and non-2xx responses
+pub async fn deliver(
+    client: &Client,
+    webhook: &UsageWebhook,
+    event: &ThresholdEvent,
+    policy: RetryPolicy,
+) -> Result<(), WebhookError> {
+    let body = serde_json::to_vec(event).expect("threshold event serializes");
+    let mut delay = policy.base_delay;
+    let mut last_error = String::new();
+
+    for attempt in 1..=policy.attempts {
+        if attempt > 1 {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+
+        let timestamp = Utc::now().timestamp();
+        let result = client
+            .post(&webhook.url)
+            .timeout(DELIVERY_TIMEOUT)
+            .header("Content-Type", "application/json")
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign(&webhook.secret, timestamp, &body))
+            .body(body.clone())
+            .send()
+            .await;
+
+        match result {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => last_error = format!("HTTP {}", response.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+
+    Err(WebhookError::DeliveryFailed {
+        attempts: policy.attempts,
+        last_error,
+    })
+}
+
+/// Notify the user's webhook if the request that brought monthly usage to
+/// `used` crossed a threshold. Returns the threshold that was delivered.
+pub async fn notify_if_crossed(
+    pool: &PgPool,
+    client: &Client,
+    user_id: Uuid,
+    used: i64,
+    limit: i64,
+    policy: RetryPolicy,
+) -> Result<Option<u8>, WebhookError> {
+    let Some(threshold) = crossed_threshold(used - 1, used, limit) else {
+        return Ok(None);
+    };
+    let Some(webhook) = get_webhook(pool, user_id).await? else {
+        return Ok(None);
+    };
+
+    let now = Utc::now();
+    let period = period_start(now);
+    if !claim_threshold(pool, user_id, period, threshold).await? {
+        return Ok(None);
+    }
+
+    let event = ThresholdEvent {
+        event: THRESHOLD_EVENT,
+        user_id,
+        threshold_percent: threshold,
+        used,
+        limit,
+        period: now.format("%Y-%m").to_string(),
+        occurred_at: now,
+    };
+    if let Err(e) = deliver(client, &webhook, &event, policy).await {
+        release_threshold(pool, user_id, period, threshold).await?;
+        return Err(e);
+    }
+    Ok(Some(threshold))
+}
+
+/// Fire-and-forget threshold check from the rate limit path.
+/// Only spawns a task when this request actually crossed a threshold.
+pub fn spawn_threshold_check(
+    tasks: &TaskManager,
+    pool: PgPool,
+    client: Client,
+    user_id: Uuid,
+    used: i64,
+    limit: i64,
+) {
+    if crossed_threshold(used - 1, used, limit).is_none() {
+        return;
+    }
+
+    tasks.spawn(async move {
+        if let Err(e) =
+            notify_if_crossed(&pool, &client, user_id, used, limit, RetryPolicy::default()).await
+        {
+            tracing::warn!(user_id = %user_id, "Usage webhook notification failed: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Bytes, extract::State, http::HeaderMap, http::StatusCode, routing::post, Router};
+    use std::sync::{Arc, Mutex};
+
+    /// Requests received by the test receiver, and status codes to answer with
+    #[derive(Clone, Default)]
+    struct Receiver {
+        received: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+        statuses: Arc<Mutex<Vec<StatusCode>>>,
+    }
+
+    async fn receive(State(receiver): State<Receiver>, headers: HeaderMap, body: Bytes) -> StatusCode {
+        receiver.received.lock().unwrap().push((headers, body));
+        receiver.statuses.lock().unwrap().pop().unwrap_or(StatusCode::OK)
+    }
+
+    /// Start a local receiver; `statuses` are answered in order before 200s
+    async fn start_receiver(statuses: &[StatusCode]) -> (String, Receiver) {
+        let receiver = Receiver::default();
+        receiver.statuses.lock().unwrap().extend(statuses.iter().rev());
+
+        let app = Router::new().route("/hook", post(receive)).with_state(receiver.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (format!("http://{}/hook", addr), receiver)
+    }
+
+    fn fast_retry() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            base_delay: Duration::from_millis(5),
+        }
+    }
+
+    fn assert_signed(headers: &HeaderMap, body: &[u8], secret: &str) {
+        let timestamp: i64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+        let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+        assert_eq!(signature, sign(secret, timestamp, body));
+    }
+
+    #[test]
+    fn test_crossed_threshold() {
+        assert_eq!(crossed_threshold(49, 50, 100), Some(50));
+        assert_eq!(crossed_threshold(50, 51, 100), None);
+        assert_eq!(crossed_threshold(79, 80, 100), Some(80));
+        assert_eq!(crossed_threshold(99, 100, 100), Some(100));
+        assert_eq!(crossed_threshold(100, 101, 100), None);
+        // Non-round limits cross on the first request at or above the mark
+        assert_eq!(crossed_threshold(499, 500, 1000), Some(50));
+        assert_eq!(crossed_threshold(4, 5, 9), Some(50));
+        assert_eq!(crossed_threshold(3, 4, 9), None);
+        // A single step over several marks reports the highest
+        assert_eq!(crossed_threshold(0, 1, 1), Some(100));
+        assert_eq!(crossed_threshold(0, 1, 0), None);
+    }
+
+    #[test]
+    fn test_sign_is_deterministic_and_keyed() {
+        let body = br#"{"event":"usage.threshold_crossed"}"#;
+        let signature = sign("whsec_a", 1_700_000_000, body);
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), "sha256=".len() + 64);
+        assert_eq!(signature, sign("whsec_a", 1_700_000_000, body));
+        assert_ne!(signature, sign("whsec_b", 1_700_000_000, body));
+        assert_ne!(signature, sign("whsec_a", 1_700_000_001, body));
+    }
+
+    #[tokio::test]
+    async fn test_validate_webhook_url() {
+        assert!(validate_webhook_url("https://93.184.215.14/webrana").await.is_ok());
+        assert!(validate_webhook_url("https://[2606:2800:21f:cb07:6820:80da:af6b:8b2c]/webrana").await.is_ok());
+        assert!(validate_webhook_url("http://hooks.example.com/webrana").await.is_err());
+        assert!(validate_webhook_url("not a url").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_internal_webhook_hosts_rejected() {
+        for url in [
+            "https://localhost/hook",
+            "https://127.0.0.1/hook",
+            "https://10.0.0.5/hook",
+            "https://172.16.4.2/hook",
+            "https://192.168.1.10/hook",
+            "https://169.254.169.254/latest/meta-data",
+            "https://100.100.100.200/latest/meta-data",
+            "https://0.0.0.0/hook",
+            "https://[::1]/hook",
+            "https://[fd00:ec2::254]/hook",
+            "https://[fe80::1]/hook",
+            "https://[::ffff:127.0.0.1]/hook",
+        ] {
+            let err = validate_webhook_url(url).await.unwrap_err();
+            assert!(matches!(err, WebhookError::InvalidUrl(_)), "{} accepted", url);
+        }
+    }
+
+    #[test]
+    fn test_generated_secret() {
+        let secret = generate_secret();
+        assert!(secret.starts_with(SECRET_PREFIX));
+        assert_ne!(secret, generate_secret());
+    }
+
+    #[tokio::test]
+    async fn test_deliver_posts_signed_payload_after_retry() {
+        let (url, receiver) = start_receiver(&[StatusCode::INTERNAL_SERVER_ERROR]).await;
+        let webhook = UsageWebhook {
+            url,
+            secret: "whsec_test".to_string(),
+        };
+        let event = ThresholdEvent {
+            event: THRESHOLD_EVENT,
+            user_id: Uuid::new_v4(),
+            threshold_percent: 80,
+            used: 800,
+            limit: 1000,
+            period: "2024-12".to_string(),
+            occurred_at: Utc::now(),
+        };
+
+        deliver(&Client::new(), &webhook, &event, fast_retry()).await.unwrap();
+
+        let received = receiver.received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let (headers, body) = &received[1];
+        assert_signed(headers, body, "whsec_test");
+        let payload: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["event"], THRESHOLD_EVENT);
+        assert_eq!(payload["threshold_percent"], 80);
+        assert_eq!(payload["used"], 800);
+        assert_eq!(payload["limit"], 1000);
+        assert_eq!(payload["period"], "2024-12");
+    }
+
+    #[tokio::test]
+    async fn test_deliver_gives_up_after_attempts() {
+        let (url, receiver) = start_receiver(&[StatusCode::BAD_GATEWAY; 3]).await;
+        let webhook = UsageWebhook {
+            url,
+            secret: "whsec_test".to_string(),
+        };
+        let event = ThresholdEvent {
+            event: THRESHOLD_EVENT,
+            user_id: Uuid::new_v4(),
+            threshold_percent: 100,
+            used: 100,
+            limit: 100,
+            period: "2024-12".to_string(),
+            occurred_at: Utc::now(),
+        };
+
+        let err = deliver(&Client::new(), &webhook, &event, fast_retry()).await.unwrap_err();
+        assert!(matches!(err, WebhookError::DeliveryFailed { attempts: 3, .. }));
+        assert_eq!(receiver.received.lock().unwrap().len(), 3);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_threshold_notified_once_per_period(pool: PgPool) {
+        use crate::models::PlanTier;
+        use crate::test_support::insert_user;
+
+        let user_id = insert_user(&pool, "webhook@example.com", PlanTier::Starter).await;
+        let (url, receiver) = start_receiver(&[]).await;
+        // Local receiver is plain HTTP, so bypass set_webhook's https check
+        sqlx::query("INSERT INTO usage_webhooks (user_id, url, secret) VALUES ($1, $2, 'whsec_db')")
+            .bind(user_id)
+            .bind(&url)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let client = Client::new();
+
+        // Below the first mark: nothing sent
+        let sent = notify_if_crossed(&pool, &client, user_id, 49, 100, fast_retry()).await.unwrap();
+        assert_eq!(sent, None);
+
+        let sent = notify_if_crossed(&pool, &client, user_id, 50, 100, fast_retry()).await.unwrap();
+        assert_eq!(sent, Some(50));
+
+        // Same crossing again in the same period is deduped
+        let sent = notify_if_crossed(&pool, &client, user_id, 50, 100, fast_retry()).await.unwrap();
+        assert_eq!(sent, None);
+
+        let received = receiver.received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let (headers, body) = &received[0];
+        assert_signed(headers, body, "whsec_db");
+        let payload: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["user_id"], user_id.to_string());
+        assert_eq!(payload["threshold_percent"], 50);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_failed_delivery_is_not_recorded_as_sent(pool: PgPool) {
+        use crate::models::PlanTier;
+        use crate::test_support::insert_user;
+
+        let user_id = insert_user(&pool, "flaky-hook@example.com", PlanTier::Starter).await;
+        let (url, receiver) = start_receiver(&[StatusCode::BAD_GATEWAY; 3]).await;
+        sqlx::query("INSERT INTO usage_webhooks (user_id, url, secret) VALUES ($1, $2, 'whsec_db')")
+            .bind(user_id)
+            .bind(&url)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let client = Client::new();
+
+        let err = notify_if_crossed(&pool, &client, user_id, 80, 100, fast_retry()).await.unwrap_err();
+        assert!(matches!(err, WebhookError::DeliveryFailed { .. }));
+
+        // The threshold is still unsent, so the next crossing delivers it
+        let sent = notify_if_crossed(&pool, &client, user_id, 80, 100, fast_retry()).await.unwrap();
+        assert_eq!(sent, Some(80));
+        assert_eq!(receiver.received.lock().unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,98 @@
+//! Shared test fixtures: environment lookups for `from_lookup` configs, and
+//! fixtures for database-backed tests.
+//!
+//! DB tests use `#[sqlx::test]`, which creates a fresh database per test on
+//! the server at `DATABASE_URL` and applies `./migrations` before handing the
+//! test a `PgPool`. They are ignored by default; run them against a
+//! disposable Postgres (the role needs `CREATEDB`) with:
+//!
+//! ```sh
+//! DATABASE_URL=postgres://postgres@localhost:5432/webrana_test cargo test -- --ignored
+//! ```
+
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::models::PlanTier;
+use crate::services::{
+    abuse_detector::AbuseDetector, feature_flags::FeatureFlags, model_routing::ModelRouter,
+    provider_headers::ProviderHeaders, provider_limiter::ProviderLimiter, provider_regions::RegionSelector, proxy_config::ProxyConfig,
+    task_manager::TaskManager, upstream_client::UpstreamClientConfig,
+};
+use crate::AppState;
+
+/// Environment lookup over fixed variables, for `from_lookup` configs
+pub fn env_lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let map: HashMap<String, String> = vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    move |key| map.get(key).cloned()
+}
+
+/// App state around a test pool. Redis is never connected to, so anything
+/// that fails open on Redis errors behaves as if Redis were down.
+pub fn app_state(pool: PgPool) -> Arc<AppState> {
+    Arc::new(AppState {
+        db: pool,
+        redis: redis::Client::open("redis://127.0.0.1:1").expect("redis url"),
+        http_client: UpstreamClientConfig::from_lookup(|_| None).build(),
+        provider_limiter: ProviderLimiter::from_env(),
+        abuse_detector: AbuseDetector::default(),
+        model_router: ModelRouter::default(),
+        region_selector: RegionSelector::default(),
+        provider_headers: ProviderHeaders::default(),
+        feature_flags: FeatureFlags::default(),
+        proxy_config: ProxyConfig::default(),
+        email_queue: None,
+        billing_service: None,
+        tasks: TaskManager::default(),
+        shutdown: CancellationToken::new(),
+    })
+}
+
+/// Insert a user directly, bypassing registration
+pub async fn insert_user(pool: &PgPool, email: &str, plan: PlanTier) -> Uuid {
+    sqlx::query_scalar(
+        "INSERT INTO users (email, password_hash, plan_tier) VALUES ($1, 'not-a-hash', $2::plan_tier) RETURNING id",
+    )
+    .bind(email)
+    .bind(plan.as_str())
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+}
+
+/// Usage row for `insert_proxy_request`
+pub struct ProxyRequestFixture<'a> {
+    pub provider: &'a str,
+    pub model: &'a str,
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub cost_idr: i64,
+    pub status_code: i32,
+}
+
+/// Insert a logged proxy request for a user
+pub async fn insert_proxy_request(pool: &PgPool, user_id: Uuid, request: ProxyRequestFixture<'_>) {
+    sqlx::query(
+        r#"
+        INSERT INTO proxy_requests (user_id, provider, model, prompt_tokens, completion_tokens, total_tokens, latency_ms, estimated_cost_idr, status_code)
+        VALUES ($1, $2::ai_provider, $3, $4, $5, $6, 100, $7, $8)
+        "#,
+    )
+    .bind(user_id)
+    .bind(request.provider)
+    .bind(request.model)
+    .bind(request.prompt_tokens)
+    .bind(request.completion_tokens)
+    .bind(request.prompt_tokens + request.completion_tokens)
+    .bind(request.cost_idr)
+    .bind(request.status_code)
+    .execute(pool)
+    .await
+    .expect("insert proxy request");
+}
@@ -0,0 +1,35 @@
+//! OpenAI-style error responses, shared by the proxy routes and the
+//! middleware in front of them.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+/// Error response
+#[derive(Debug, Serialize)]
+pub struct ProxyErrorResponse {
+    pub error: ProxyError,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProxyError {
+    pub message: String,
+    pub r#type: String,
+    pub code: String,
+}
+
+/// Helper function to create proxy error responses
+pub fn proxy_error(status: StatusCode, message: &str, error_type: &str, code: &str) -> Response {
+    let body = Json(ProxyErrorResponse {
+        error: ProxyError {
+            message: message.to_string(),
+            r#type: error_type.to_string(),
+            code: code.to_string(),
+        },
+    });
+
+    (status, body).into_response()
+}
@@ -0,0 +1,168 @@
+//! Retry helper for transient database errors.
+//!
+//! Retries serialization failures, deadlocks, and dropped connections with
+//! exponential backoff. Non-transient errors are returned immediately.
+
+use std::future::Future;
+use std::time::Duration;
+
+/// Retry policy for database operations
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff delay before the given retry (1-based)
+    pub fn delay_for(&self, retry: u32) -> Duration {
+        self.base_delay * 2u32.saturating_pow(retry.saturating_sub(1))
+    }
+}
+
+/// Check whether a sqlx error is transient and safe to retry
+pub fn is_transient(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(db_err) => match db_err.code() {
+            // serialization_failure, deadlock_detected
+            Some(code) if code == "40001" || code == "40P01" => true,
+            // connection_exception class, admin_shutdown, cannot_connect_now
+            Some(code) => code.starts_with("08") || code == "57P01" || code == "57P03",
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Check whether a sqlx error fails the same way however often it is retried:
+/// data exceptions (class `22`) and integrity constraint violations (class `23`)
+pub fn is_permanent(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Database(db_err) => db_err
+            .code()
+            .is_some_and(|code| code.starts_with("22") || code.starts_with("23")),
+        _ => false,
+    }
+}
+
+/// Run a database operation, retrying transient errors with the default policy
+pub async fn retry_db<F, Fut, T>(op: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    retry_db_with(RetryPolicy::default(), op).await
+}
+
+/// Run a database operation, retrying transient errors with a custom policy
+pub async fn retry_db_with<F, Fut, T>(policy: RetryPolicy, mut op: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < policy.max_attempts && is_transient(&e) => {
+                let delay = policy.delay_for(attempt);
+                tracing::warn!(attempt, ?delay, "Transient database error, retrying: {}", e);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transient_error_succeeds_on_retry() {
+        let calls = AtomicU32::new(0);
+
+        let result = retry_db_with(fast_policy(), || async {
+            if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(sqlx::Error::PoolTimedOut)
+            } else {
+                Ok(42)
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_non_transient_error_is_not_retried() {
+        let calls = AtomicU32::new(0);
+
+        let result: Result<(), _> = retry_db_with(fast_policy(), || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(sqlx::Error::RowNotFound)
+        })
+        .await;
+
+        assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let calls = AtomicU32::new(0);
+
+        let result: Result<(), _> = retry_db_with(fast_policy(), || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(sqlx::Error::PoolTimedOut)
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(50),
+        };
+        assert_eq!(policy.delay_for(1), Duration::from_millis(50));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(3), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_is_transient_classification() {
+        assert!(is_transient(&sqlx::Error::PoolTimedOut));
+        assert!(is_transient(&sqlx::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "reset"
+        ))));
+        assert!(!is_transient(&sqlx::Error::RowNotFound));
+        assert!(!is_transient(&sqlx::Error::PoolClosed));
+        assert!(!is_permanent(&sqlx::Error::PoolTimedOut));
+        assert!(!is_permanent(&sqlx::Error::RowNotFound));
+    }
+}
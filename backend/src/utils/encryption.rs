@@ -6,9 +6,9 @@ use aes_gcm::{
     aead::{Aead, KeyInit, OsRng},
     Aes256Gcm, Nonce,
 };
-use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use rand::RngCore;
-use std::env;
+
+use crate::utils::key_provider::{key_provider_from_env, KeyProvider};
 
 /// Encrypted data structure for database storage
 #[derive(Debug, Clone)]
@@ -25,6 +25,7 @@ pub enum EncryptionError {
     EncryptionFailed,
     DecryptionFailed,
     MissingMasterKey,
+    KeyProviderUnavailable(String),
 }
 
 impl std::fmt::Display for EncryptionError {
@@ -34,6 +35,9 @@ impl std::fmt::Display for EncryptionError {
             EncryptionError::EncryptionFailed => write!(f, "Encryption failed"),
             EncryptionError::DecryptionFailed => write!(f, "Decryption failed"),
             EncryptionError::MissingMasterKey => write!(f, "Master encryption key not configured"),
+            EncryptionError::KeyProviderUnavailable(reason) => {
+                write!(f, "Master key provider unavailable: {}", reason)
+            }
         }
     }
 }
@@ -41,29 +45,24 @@ impl std::fmt::Display for EncryptionError {
 impl std::error::Error for EncryptionError {}
 
 /// Encryption utilities
+#[derive(Clone)]
 pub struct EncryptionUtils {
     cipher: Aes256Gcm,
 }
 
 impl EncryptionUtils {
-    /// Create new encryption utils from environment variable
+    /// Create new encryption utils with the key from the configured provider
     pub fn from_env() -> Result<Self, EncryptionError> {
-        let key_b64 = env::var("MASTER_ENCRYPTION_KEY")
-            .map_err(|_| EncryptionError::MissingMasterKey)?;
-        
-        let key_bytes = BASE64.decode(&key_b64)
-            .map_err(|_| EncryptionError::InvalidKey)?;
-        
-        if key_bytes.len() != 32 {
-            return Err(EncryptionError::InvalidKey);
-        }
-        
-        let key: [u8; 32] = key_bytes.try_into()
-            .map_err(|_| EncryptionError::InvalidKey)?;
-        
+        Self::from_provider(key_provider_from_env()?.as_ref())
+    }
+
+    /// Create encryption utils with the key from `provider`
+    pub fn from_provider(provider: &dyn KeyProvider) -> Result<Self, EncryptionError> {
+        let key = provider.master_key().inspect_err(|e| {
+            tracing::error!(provider = provider.name(), "Failed to load master encryption key: {}", e);
+        })?;
         let cipher = Aes256Gcm::new_from_slice(&key)
             .map_err(|_| EncryptionError::InvalidKey)?;
-        
         Ok(Self { cipher })
     }
 
@@ -0,0 +1,194 @@
+//! Master encryption key sources.
+//!
+//! The AES-256 key protecting provider API keys comes from a [`KeyProvider`]
+//! so it can live in a secrets manager instead of the environment.
+//!
+//! Configuration:
+//! - `MASTER_KEY_PROVIDER`: `env` (default), `vault` or `kms`
+//! - `MASTER_ENCRYPTION_KEY`: base64 key for the `env` provider
+//! - `VAULT_ADDR`, `VAULT_KEY_PATH`: secret location for the `vault` provider
+//! - `KMS_KEY_ID`: key id for the `kms` provider
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+
+use crate::utils::encryption::EncryptionError;
+
+/// Source of the master encryption key
+pub trait KeyProvider: Send + Sync {
+    /// Provider name for logs and errors
+    fn name(&self) -> &'static str;
+
+    /// Fetch the current 32-byte master key
+    fn master_key(&self) -> Result<[u8; 32], EncryptionError>;
+}
+
+/// Decode a base64 master key, requiring exactly 32 bytes
+pub fn decode_key(encoded: &str) -> Result<[u8; 32], EncryptionError> {
+    let bytes = BASE64
+        .decode(encoded.trim())
+        .map_err(|_| EncryptionError::InvalidKey)?;
+    bytes.try_into().map_err(|_| EncryptionError::InvalidKey)
+}
+
+/// Master key from `MASTER_ENCRYPTION_KEY` (the default)
+#[derive(Clone)]
+pub struct EnvKeyProvider {
+    encoded: Option<String>,
+}
+
+impl EnvKeyProvider {
+    pub fn new(encoded: Option<String>) -> Self {
+        Self { encoded }
+    }
+}
+
+impl KeyProvider for EnvKeyProvider {
+    fn name(&self) -> &'static str {
+        "env"
+    }
+
+    fn master_key(&self) -> Result<[u8; 32], EncryptionError> {
+        decode_key(self.encoded.as_deref().ok_or(EncryptionError::MissingMasterKey)?)
+    }
+}
+
+/// Master key stored as a HashiCorp Vault secret.
+/// Not implemented yet; configuring it fails at startup.
+#[derive(Debug, Clone)]
+pub struct VaultKeyProvider {
+    pub address: String,
+    pub path: String,
+}
+
+impl KeyProvider for VaultKeyProvider {
+    fn name(&self) -> &'static str {
+        "vault"
+    }
+
+    fn master_key(&self) -> Result<[u8; 32], EncryptionError> {
+        Err(EncryptionError::KeyProviderUnavailable(format!(
+            "Vault key provider is not implemented yet ({}/{})",
+            self.address, self.path
+        )))
+    }
+}
+
+/// Master key decrypted with a cloud KMS key.
+/// Not implemented yet; configuring it fails at startup.
+#[derive(Debug, Clone)]
+pub struct KmsKeyProvider {
+    pub key_id: String,
+}
+
+impl KeyProvider for KmsKeyProvider {
+    fn name(&self) -> &'static str {
+        "kms"
+    }
+
+    fn master_key(&self) -> Result<[u8; 32], EncryptionError> {
+        Err(EncryptionError::KeyProviderUnavailable(format!(
+            "KMS key provider is not implemented yet ({})",
+            self.key_id
+        )))
+    }
+}
+
+/// Select the key provider from environment variables
+pub fn key_provider_from_env() -> Result<Box<dyn KeyProvider>, EncryptionError> {
+    key_provider_from_lookup(|key| std::env::var(key).ok())
+}
+
+/// Select the key provider using a custom variable lookup
+pub fn key_provider_from_lookup<F>(lookup: F) -> Result<Box<dyn KeyProvider>, EncryptionError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let required = |name: &str| {
+        lookup(name)
+            .filter(|v| !v.trim().is_empty())
+            .ok_or_else(|| EncryptionError::KeyProviderUnavailable(format!("{} is not set", name)))
+    };
+
+    let kind = lookup("MASTER_KEY_PROVIDER").map(|v| v.trim().to_lowercase());
+    match kind.as_deref() {
+        None | Some("") | Some("env") => Ok(Box::new(EnvKeyProvider::new(lookup("MASTER_ENCRYPTION_KEY")))),
+        Some("vault") => Ok(Box::new(VaultKeyProvider {
+            address: required("VAULT_ADDR")?,
+            path: required("VAULT_KEY_PATH")?,
+        })),
+        Some("kms") => Ok(Box::new(KmsKeyProvider {
+            key_id: required("KMS_KEY_ID")?,
+        })),
+        Some(other) => Err(EncryptionError::KeyProviderUnavailable(format!(
+            "Unknown key provider: {}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::env_lookup;
+    use crate::utils::encryption::EncryptionUtils;
+
+    /// Key held in memory, as a secrets manager client would return it
+    struct InMemoryKeyProvider([u8; 32]);
+
+    impl KeyProvider for InMemoryKeyProvider {
+        fn name(&self) -> &'static str {
+            "memory"
+        }
+
+        fn master_key(&self) -> Result<[u8; 32], EncryptionError> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn test_encryption_from_in_memory_provider() {
+        let utils = EncryptionUtils::from_provider(&InMemoryKeyProvider([1u8; 32])).unwrap();
+        let encrypted = utils.encrypt("sk-test-key").unwrap();
+        assert_eq!(utils.decrypt(&encrypted).unwrap(), "sk-test-key");
+
+        // A rotated key can't read data encrypted under the old one
+        let rotated = EncryptionUtils::from_provider(&InMemoryKeyProvider([2u8; 32])).unwrap();
+        assert!(rotated.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_env_provider_is_default() {
+        let encoded = BASE64.encode([3u8; 32]);
+        let provider = key_provider_from_lookup(env_lookup(&[("MASTER_ENCRYPTION_KEY", &encoded)])).unwrap();
+
+        assert_eq!(provider.name(), "env");
+        assert_eq!(provider.master_key().unwrap(), [3u8; 32]);
+
+        let missing = key_provider_from_lookup(env_lookup(&[])).unwrap();
+        assert!(matches!(missing.master_key(), Err(EncryptionError::MissingMasterKey)));
+    }
+
+    #[test]
+    fn test_env_key_must_be_32_bytes() {
+        assert!(matches!(decode_key(&BASE64.encode([0u8; 16])), Err(EncryptionError::InvalidKey)));
+        assert!(matches!(decode_key("not base64!"), Err(EncryptionError::InvalidKey)));
+    }
+
+    #[test]
+    fn test_secrets_manager_providers_selected_by_config() {
+        let vault = key_provider_from_lookup(env_lookup(&[
+            ("MASTER_KEY_PROVIDER", "vault"),
+            ("VAULT_ADDR", "https://vault.internal:8200"),
+            ("VAULT_KEY_PATH", "secret/webrana/master-key"),
+        ]))
+        .unwrap();
+        assert_eq!(vault.name(), "vault");
+        assert!(matches!(vault.master_key(), Err(EncryptionError::KeyProviderUnavailable(_))));
+
+        let kms = key_provider_from_lookup(env_lookup(&[("MASTER_KEY_PROVIDER", "KMS"), ("KMS_KEY_ID", "alias/webrana")])).unwrap();
+        assert_eq!(kms.name(), "kms");
+
+        assert!(key_provider_from_lookup(env_lookup(&[("MASTER_KEY_PROVIDER", "vault")])).is_err());
+        assert!(key_provider_from_lookup(env_lookup(&[("MASTER_KEY_PROVIDER", "s3")])).is_err());
+    }
+}
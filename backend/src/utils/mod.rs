@@ -1,2 +1,5 @@
+pub mod api_error;
+pub mod db_retry;
 pub mod encryption;
+pub mod key_provider;
 pub mod password;